@@ -0,0 +1,58 @@
+//! A synthetic history of 100k Kraken trades, shared by the throughput
+//! benchmark and the test of its target, `tests/throughput.rs`.
+
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+use kraken2rfb::report::resolve::Resolved;
+use kraken2rfb::report::transactions::ExchangeInfo;
+use kraken2rfb::source::{Activity, NormalizedTrade, Side};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+pub const TRADES: usize = 100_000;
+
+/// Trades spread over a year, a few hundred a day, alternating buys and sells
+pub fn activity() -> Activity {
+    let start: DateTime<Utc> = "2024-01-01T00:00:00Z".parse().unwrap();
+    let step = TimeDelta::days(366) / TRADES as i32;
+    let trades = (0..TRADES)
+        .map(|i| NormalizedTrade {
+            base: "BTC".to_string(),
+            quote: "USD".to_string(),
+            side: if i % 2 == 0 { Side::Buy } else { Side::Sell },
+            volume: dec!(0.0125),
+            cost: dec!(812.34),
+            // 0.00002 BTC at the price, so the amounts net of the fee are
+            // exact and no warnings are printed
+            fee: dec!(1.299744),
+            credit_fee_usd: Decimal::ZERO,
+            price: dec!(64987.2),
+            time: start + step * i as i32,
+            ids: vec![format!("T{}", i)],
+        })
+        .collect();
+    Activity {
+        trades,
+        ..Activity::default()
+    }
+}
+
+/// The USD rate of every day of the trades, which start on the last day of
+/// 2023 in Brasília time
+pub fn rates() -> Resolved {
+    let mut resolved = Resolved::default();
+    let first_day = NaiveDate::from_ymd_opt(2023, 12, 31).unwrap();
+    for date in first_day.iter_days().take(367) {
+        resolved
+            .rates
+            .insert(("USD".to_string(), date), dec!(5.4321));
+    }
+    resolved
+}
+
+pub fn exchange() -> ExchangeInfo {
+    ExchangeInfo {
+        name: "Kraken".to_string(),
+        url: "https://www.kraken.com".to_string(),
+        country: "US".to_string(),
+    }
+}
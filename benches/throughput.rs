@@ -0,0 +1,86 @@
+//! Throughput of the hot paths on a synthetic history of 100k trades: pair
+//! parsing, decimal conversion, the conversion of the activity and the
+//! encoding of the records. Run with `cargo bench`. The documented target,
+//! to encode 100k records in under a second, is checked by
+//! `tests/throughput.rs`.
+
+mod synthetic;
+
+use criterion::{BatchSize, Criterion, criterion_group, criterion_main};
+use kraken2rfb::kraken_pairs::parse_pair;
+use kraken2rfb::report::layout::Layout;
+use kraken2rfb::report::{ProcessOptions, convert_activity, write_report};
+use kraken2rfb::to_decimal;
+use rust_decimal::Decimal;
+use serde_json::Value;
+use std::hint::black_box;
+
+use synthetic::TRADES;
+
+fn parse_pairs(c: &mut Criterion) {
+    let pairs = ["XXBTZUSD", "XETHZEUR", "SOLUSD", "DOTEUR"];
+    c.bench_function("parse 100k pairs", |b| {
+        b.iter(|| {
+            (0..TRADES)
+                .filter_map(|i| parse_pair(black_box(pairs[i % pairs.len()])))
+                .count()
+        })
+    });
+}
+
+fn convert_decimals(c: &mut Criterion) {
+    let numbers: Vec<Value> = (0..TRADES)
+        .map(|i| serde_json::from_str(&format!("{}.{:04}", i, i % 10000)).unwrap())
+        .collect();
+    c.bench_function("convert 100k decimals", |b| {
+        b.iter(|| {
+            numbers
+                .iter()
+                .map(|n| to_decimal(n).unwrap())
+                .sum::<Decimal>()
+        })
+    });
+}
+
+fn process_trades(c: &mut Criterion) {
+    let activity = synthetic::activity();
+    let resolved = synthetic::rates();
+    let exchange = synthetic::exchange();
+    let options = ProcessOptions::default();
+    c.bench_function("process 100k trades", |b| {
+        b.iter_batched(
+            || (activity.clone(), resolved.clone()),
+            |(activity, mut resolved)| {
+                convert_activity(activity, &exchange, &options, &mut resolved).unwrap()
+            },
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+fn encode_records(c: &mut Criterion) {
+    let transactions = convert_activity(
+        synthetic::activity(),
+        &synthetic::exchange(),
+        &ProcessOptions::default(),
+        &mut synthetic::rates(),
+    )
+    .unwrap()
+    .transactions;
+    let mut report = Vec::with_capacity(transactions.len() * 100);
+    c.bench_function("encode 100k records", |b| {
+        b.iter(|| {
+            report.clear();
+            write_report(&transactions, Layout::default(), &mut report).unwrap();
+            report.len()
+        })
+    });
+}
+
+criterion_group! {
+    name = throughput;
+    // Each run takes from milliseconds to a second, so fewer samples do
+    config = Criterion::default().sample_size(10);
+    targets = parse_pairs, convert_decimals, process_trades, encode_records
+}
+criterion_main!(throughput);
@@ -1,18 +1,90 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
 use std::fs::File;
 use std::io::Write;
 use std::path::Path;
+use std::process::Command;
+
+/// Set to download the current data from Kraken's public API instead of using
+/// the bundled copies in kraken_data/, e.g. for a release build
+const FETCH_ENV: &str = "KRAKEN2RFB_FETCH_DATA";
+
+/// Set to download from another server than Kraken's, as at run time
+const URL_ENV: &str = "KRAKEN2RFB_KRAKEN_URL";
+
+/// Downloads the response of a Kraken public endpoint with curl, checking
+/// that it has a non-empty result and no error
+fn download(endpoint: &str) -> Result<serde_json::Value, String> {
+    let base = env::var(URL_ENV).unwrap_or_else(|_| "https://api.kraken.com".to_string());
+    let url = format!("{}/0/public/{}", base.trim_end_matches('/'), endpoint);
+    let output = Command::new("curl")
+        .args([
+            "--silent",
+            "--show-error",
+            "--fail",
+            "--max-time",
+            "60",
+            &url,
+        ])
+        .output()
+        .map_err(|e| format!("failed to run curl: {}", e))?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout)
+        .map_err(|e| format!("invalid response from {}: {}", url, e))?;
+    let no_errors = json["error"]
+        .as_array()
+        .is_none_or(|errors| errors.is_empty());
+    match json["result"].as_object() {
+        Some(result) if no_errors && !result.is_empty() => Ok(json),
+        _ => Err(format!(
+            "unexpected response from {}: {}",
+            url, json["error"]
+        )),
+    }
+}
+
+/// Reads a bundled copy of the data
+fn read_bundled(file_name: &str) -> serde_json::Value {
+    let path = Path::new("kraken_data").join(file_name);
+    let content = std::fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("Failed to read {}: {}", path.display(), e));
+    serde_json::from_str(&content)
+        .unwrap_or_else(|e| panic!("Failed to parse {}: {}", path.display(), e))
+}
+
+/// The responses of Assets and AssetPairs: downloaded if FETCH_ENV is set,
+/// or else, or if either download fails, the bundled copies. Both come from
+/// the same place, since the pairs refer to the assets.
+fn kraken_data() -> (serde_json::Value, serde_json::Value) {
+    let fetch = env::var(FETCH_ENV)
+        .is_ok_and(|value| matches!(value.to_ascii_lowercase().as_str(), "1" | "true" | "yes"));
+    if fetch {
+        match download("Assets").and_then(|assets| Ok((assets, download("AssetPairs")?))) {
+            Ok(data) => {
+                println!("cargo:warning=using the Kraken data downloaded at build time");
+                return data;
+            }
+            Err(e) => println!(
+                "cargo:warning=failed to download the Kraken data, using the bundled copy: {}",
+                e
+            ),
+        }
+    }
+    (
+        read_bundled("assets.json"),
+        read_bundled("kraken_pairs.json"),
+    )
+}
 
 fn main() {
     println!("cargo:rerun-if-changed=kraken_data/assets.json");
     println!("cargo:rerun-if-changed=kraken_data/kraken_pairs.json");
+    println!("cargo:rerun-if-env-changed={}", FETCH_ENV);
+    println!("cargo:rerun-if-env-changed={}", URL_ENV);
 
-    // Read and parse assets.json
-    let assets_content =
-        std::fs::read_to_string("kraken_data/assets.json").expect("Failed to read assets.json");
-    let assets_json: serde_json::Value =
-        serde_json::from_str(&assets_content).expect("Failed to parse assets.json");
+    let (assets_json, pairs_json) = kraken_data();
 
     // Create a map from asset ID to altname
     let mut asset_altnames = HashMap::new();
@@ -27,12 +99,6 @@ fn main() {
         }
     }
 
-    // Read and parse kraken_pairs.json
-    let pairs_content = std::fs::read_to_string("kraken_data/kraken_pairs.json")
-        .expect("Failed to read kraken_pairs.json");
-    let pairs_json: serde_json::Value =
-        serde_json::from_str(&pairs_content).expect("Failed to parse kraken_pairs.json");
-
     // Generate asset name PHF map entries
     let mut asset_phf_entries = Vec::new();
     for (kraken_name, common_name) in &asset_altnames {
@@ -97,4 +163,44 @@ pub static KRAKEN_PAIRS: Map<&'static str, (&'static str, &'static str)> = phf::
     .unwrap();
 
     println!("Generated {} pair mappings", pair_phf_entries.len());
+
+    // Generate the index of pair altnames and wsnames, like "ETHEUR" and
+    // "ETH/EUR", to the pair IDs. Names that are themselves pair IDs are left
+    // out, and so are the ones shared by more than one pair.
+    let mut pair_names: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    if let Some(result) = pairs_json["result"].as_object() {
+        for (pair_name, pair_info) in result {
+            let names = [pair_info["altname"].as_str(), pair_info["wsname"].as_str()];
+            for name in names.into_iter().flatten() {
+                let ids = pair_names.entry(name.to_string()).or_default();
+                if name != pair_name && !result.contains_key(name) && !ids.contains(pair_name) {
+                    ids.push(pair_name.clone());
+                }
+            }
+        }
+    }
+    let altname_phf_entries: Vec<String> = pair_names
+        .iter()
+        .filter(|(_, ids)| ids.len() == 1)
+        .map(|(name, ids)| format!("    \"{}\" => \"{}\",", name, ids[0]))
+        .collect();
+
+    let dest_path = Path::new(&out_dir).join("kraken_pair_altnames_map.rs");
+    let mut f = File::create(&dest_path).unwrap();
+
+    write!(
+        f,
+        r#"
+pub static KRAKEN_PAIR_ALTNAMES: Map<&'static str, &'static str> = phf::phf_map! {{
+{}
+}};
+"#,
+        altname_phf_entries.join("\n")
+    )
+    .unwrap();
+
+    println!(
+        "Generated {} pair altname mappings",
+        altname_phf_entries.len()
+    );
 }
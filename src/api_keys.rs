@@ -0,0 +1,181 @@
+//! API keys of the exchanges, read from a key file or from the environment.
+//!
+//! Each exchange has its own key file, e.g. `kraken_keys.json` or
+//! `binance_keys.json`, with the `key`, the `secret` and, for the exchanges
+//! that need one, the `passphrase` of the API key.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::env;
+use crate::error::{Error, Result};
+use crate::i18n::t;
+use crate::paths::{self, profile_config_dir};
+use crate::redact;
+
+/// The keys of an API key
+#[derive(Serialize, Deserialize)]
+pub struct ApiKeys {
+    pub key: String,
+    pub secret: String,
+    /// Chosen by the user when creating the key, on KuCoin and OKX
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub passphrase: Option<String>,
+}
+
+/// Where the API keys of an exchange are looked for
+pub struct KeyLocation {
+    /// Name of the exchange, as shown to the user
+    pub exchange: &'static str,
+    /// Name of the key file, in the configuration directory
+    pub file_name: &'static str,
+    /// Prefix of the environment variables, after `KRAKEN2RFB_`: the key file
+    /// is in `<prefix>KEYFILE` and the keys in `<prefix>API_KEY`,
+    /// `<prefix>API_SECRET` and `<prefix>API_PASSPHRASE`
+    pub env_prefix: &'static str,
+    /// Whether the `keyfile` of the selected profile is this exchange's
+    pub profile_keyfile: bool,
+}
+
+impl KeyLocation {
+    fn env(&self, name: &str) -> String {
+        format!("{}{}", self.env_prefix, name)
+    }
+
+    /// Finds the key file, in order:
+    /// 1. the path given with `--keyfile`;
+    /// 2. the path in the `KRAKEN2RFB_<prefix>KEYFILE` environment variable;
+    /// 3. the [`KeyLocation::default_keyfile`], in the configuration directory;
+    /// 4. the key file in the current directory, unless a profile is selected.
+    ///
+    /// Explicitly given paths (1 and 2) are used even if they don't exist, so
+    /// the error message points to the file the user asked for. API keys given
+    /// in the environment are used instead of any file.
+    pub fn find_keyfile(&self, flag: Option<&Path>) -> Result<PathBuf> {
+        if let Some(path) = flag {
+            return Ok(path.to_path_buf());
+        }
+        if let Some(path) = env::var(&self.env("KEYFILE")) {
+            return Ok(PathBuf::from(path));
+        }
+        // The file isn't read, but is still shown by `doctor`
+        if self.env_keys().is_some() {
+            return Ok(self
+                .default_keyfile()
+                .unwrap_or_else(|| PathBuf::from(self.file_name)));
+        }
+
+        // The file in the current directory could be of anyone, so it isn't
+        // taken for a profile
+        let current_dir = paths::profile()
+            .is_none()
+            .then(|| PathBuf::from(self.file_name));
+        let candidates: Vec<PathBuf> = self
+            .default_keyfile()
+            .into_iter()
+            .chain(current_dir)
+            .collect();
+        candidates
+            .iter()
+            .find(|path| path.is_file())
+            .cloned()
+            .ok_or_else(|| {
+                Error::Config(t!(
+                    "{} key file not found, looked in: {}. Use --keyfile or {} to point to it",
+                    self.exchange,
+                    candidates.iter().map(|p| p.display()).join(", "),
+                    env::name(&self.env("KEYFILE"))
+                ))
+            })
+    }
+
+    /// Where `init` saves the API keys, the first path searched by
+    /// [`KeyLocation::find_keyfile`] that is not explicitly given. With a
+    /// profile selected, it is the `keyfile` of the profile in the
+    /// configuration, if it is of this exchange, or the key file in the
+    /// configuration directory of the profile (see [`paths::profile_config_dir`]).
+    pub fn default_keyfile(&self) -> Option<PathBuf> {
+        let configured = self
+            .profile_keyfile
+            .then(|| Config::load_file().ok()?.profile()?.keyfile.clone())
+            .flatten();
+        configured.or_else(|| profile_config_dir().map(|dir| dir.join(self.file_name)))
+    }
+
+    /// API keys given in the environment
+    fn env_keys(&self) -> Option<ApiKeys> {
+        Some(ApiKeys {
+            key: env::var(&self.env("API_KEY"))?,
+            secret: env::var(&self.env("API_SECRET"))?,
+            passphrase: env::var(&self.env("API_PASSPHRASE")),
+        })
+    }
+
+    /// Whether the API keys come from the environment instead of a key file
+    pub fn keys_in_env(&self) -> bool {
+        self.env_keys().is_some()
+    }
+
+    /// Loads the API keys from the environment or, if not there, from `path`.
+    /// The keys are registered as secrets, so they never show in the output.
+    pub fn load(&self, path: &Path) -> Result<ApiKeys> {
+        let keys = match self.env_keys() {
+            Some(keys) => keys,
+            None => {
+                let data = fs::read_to_string(path).map_err(|e| {
+                    Error::Config(t!("failed to read key file {}: {}", path.display(), e))
+                })?;
+                serde_json::from_str(&data).map_err(|e| {
+                    Error::Config(t!("invalid JSON in key file {}: {}", path.display(), e))
+                })?
+            }
+        };
+        redact::register_secret(&keys.key);
+        redact::register_secret(&keys.secret);
+        if let Some(passphrase) = &keys.passphrase {
+            redact::register_secret(passphrase);
+        }
+        Ok(keys)
+    }
+
+    /// Loads the API keys, like [`KeyLocation::load`], of an exchange that
+    /// also signs the requests with the passphrase of the key
+    pub fn load_with_passphrase(&self, path: &Path) -> Result<(ApiKeys, String)> {
+        let keys = self.load(path)?;
+        match keys.passphrase.clone() {
+            Some(passphrase) => Ok((keys, passphrase)),
+            None => Err(Error::Config(t!(
+                "the {} API key has no passphrase: add it to {} or set {}",
+                self.exchange,
+                path.display(),
+                env::name(&self.env("API_PASSPHRASE"))
+            ))),
+        }
+    }
+}
+
+/// Saves the API keys to `path`, readable only by the current user
+pub fn save(path: &Path, keys: &ApiKeys) -> Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let content = serde_json::to_string_pretty(keys)
+        .map_err(|e| Error::Validation(t!("failed to serialize {}: {}", name, e)))?;
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        // The mode is only used when creating the file, so fix existing ones
+        if path.exists() {
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        }
+    }
+    let mut file = options.open(path)?;
+    std::io::Write::write_all(&mut file, content.as_bytes())?;
+    Ok(())
+}
@@ -0,0 +1,204 @@
+//! Archive of the generated reports, for an audit trail: each run copies the
+//! report and what it was made from into a directory of its own under
+//! `archive/<period>/<time>` in the data directory, with a manifest of the
+//! tool version, the options and the files.
+//!
+//! What a run was made from is its checkpoint: the fetched activity, the BRL
+//! rates and the answers of the user, from which the conversion gives the
+//! same transactions again.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::i18n::t;
+use crate::paths;
+use crate::period::Period;
+use crate::report::interchange;
+use crate::report::manifest::ReportManifest;
+use crate::report::summary::RunSummary;
+use crate::report::transactions::Transaction;
+
+/// Name of the manifest in each archived run
+pub const MANIFEST_NAME: &str = "manifest.json";
+
+/// What an archived run is
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Version of kraken2rfb that generated the report
+    pub tool_version: String,
+    pub created_at: DateTime<Utc>,
+    pub period: Period,
+    /// Name of the exchange of the activity
+    pub exchange: String,
+    /// The options of the run that change the report, by name
+    pub options: BTreeMap<String, String>,
+    /// Names of the archived files, in the directory of the manifest
+    pub files: Vec<String>,
+}
+
+/// A run being archived
+pub struct Archive {
+    dir: PathBuf,
+    files: Vec<String>,
+}
+
+impl Archive {
+    /// Where the runs are archived by default: `archive` in the data
+    /// directory, of the selected profile if any
+    pub fn default_root() -> Option<PathBuf> {
+        paths::data_dir().map(|dir| dir.join("archive"))
+    }
+
+    /// Starts the archive of a run of `period` at `created_at`, in a new
+    /// directory under `root`
+    pub fn create(root: &Path, period: Period, created_at: DateTime<Utc>) -> Result<Self> {
+        let dir = root
+            .join(period.to_string())
+            .join(created_at.format("%Y%m%dT%H%M%SZ").to_string());
+        if dir.exists() {
+            return Err(Error::Config(t!(
+                "the archive {} already exists",
+                dir.display()
+            )));
+        }
+        fs::create_dir_all(&dir)?;
+        Ok(Archive {
+            dir,
+            files: Vec::new(),
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Copies a file into the archive, under `name`
+    pub fn copy(&mut self, path: &Path, name: &str) -> Result<()> {
+        fs::copy(path, self.dir.join(name))?;
+        self.files.push(name.to_string());
+        Ok(())
+    }
+
+    /// Writes a file into the archive
+    pub fn write(&mut self, name: &str, content: &[u8]) -> Result<()> {
+        fs::write(self.dir.join(name), content)?;
+        self.files.push(name.to_string());
+        Ok(())
+    }
+
+    /// Writes the manifest, with the archived files, and returns its path
+    pub fn finish(self, mut manifest: Manifest) -> Result<PathBuf> {
+        manifest.files = self.files;
+        let content = serde_json::to_string_pretty(&manifest)
+            .map_err(|e| Error::Validation(t!("failed to serialize {}: {}", MANIFEST_NAME, e)))?;
+        let path = self.dir.join(MANIFEST_NAME);
+        fs::write(&path, content + "\n")?;
+        Ok(path)
+    }
+}
+
+/// A report run to archive, see [`archive_run`]
+pub struct ArchivedRun<'a> {
+    pub period: Period,
+    /// Name of the exchange of the activity
+    pub exchange: &'a str,
+    pub report_file: &'a Path,
+    pub transactions: &'a [Transaction],
+    pub summary: &'a RunSummary,
+    /// The checkpoint the run was made from
+    pub checkpoint: &'a Path,
+    /// The options of the run that change the report, by name, like
+    /// [`crate::report::ProcessOptions::settings`]
+    pub options: BTreeMap<String, String>,
+}
+
+/// Copies the report, its manifest, its summary, its transactions and the
+/// checkpoint it was made from into a new directory of the archive under
+/// `root`, returning it
+pub fn archive_run(root: &Path, run: ArchivedRun) -> Result<PathBuf> {
+    let mut options = run.options;
+    if !run.summary.excluded_ids.is_empty() {
+        options.insert(
+            "exclude_ids".to_string(),
+            run.summary.excluded_ids.join(","),
+        );
+    }
+
+    let created_at = Utc::now();
+    let mut archive = Archive::create(root, run.period, created_at)?;
+    let report_name = run
+        .report_file
+        .file_name()
+        .map_or("report.txt".into(), |name| name.to_string_lossy());
+    archive.copy(run.report_file, &report_name)?;
+    archive.copy(
+        &ReportManifest::path_for(run.report_file),
+        &format!("{}.manifest.json", report_name),
+    )?;
+    let summary = serde_json::to_vec_pretty(run.summary)
+        .map_err(|e| Error::Validation(t!("failed to serialize {}: {}", "summary.json", e)))?;
+    archive.write("summary.json", &summary)?;
+    let mut document = Vec::new();
+    interchange::write(run.transactions, &mut document)?;
+    archive.write("transactions.json", &document)?;
+    archive.copy(run.checkpoint, "inputs.json")?;
+
+    let dir = archive.dir().to_path_buf();
+    archive.finish(Manifest {
+        tool_version: env!("CARGO_PKG_VERSION").to_string(),
+        created_at,
+        period: run.period,
+        exchange: run.exchange.to_string(),
+        options,
+        files: Vec::new(),
+    })?;
+    Ok(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_archive() {
+        let root =
+            std::env::temp_dir().join(format!("kraken2rfb-test-{}-archive", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let period = Period::month(2024, 3).unwrap();
+        let created_at = "2024-04-02T10:30:00Z".parse().unwrap();
+
+        let report = root.join("report.txt");
+        fs::create_dir_all(&root).unwrap();
+        fs::write(&report, "0510|01032024|V\r\n").unwrap();
+
+        let mut archive = Archive::create(&root, period, created_at).unwrap();
+        assert!(archive.dir().ends_with("2024-03/20240402T103000Z"));
+        archive.copy(&report, "report.txt").unwrap();
+        archive.write("summary.json", b"{}").unwrap();
+        let manifest = Manifest {
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            created_at,
+            period,
+            exchange: "Kraken".to_string(),
+            options: BTreeMap::from([("layout".to_string(), "2019".to_string())]),
+            files: Vec::new(),
+        };
+        let path = archive.finish(manifest.clone()).unwrap();
+
+        let saved: Manifest = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(saved.files, ["report.txt", "summary.json"]);
+        assert_eq!(saved.period, period);
+        assert_eq!(
+            fs::read_to_string(path.with_file_name("report.txt")).unwrap(),
+            "0510|01032024|V\r\n"
+        );
+        // A second run in the same second would overwrite the first
+        assert!(Archive::create(&root, period, created_at).is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
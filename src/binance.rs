@@ -0,0 +1,732 @@
+//! Binance API client: the signed endpoints with the spot trades, the
+//! deposits and withdrawals, the converts and the dust sweeps of the account.
+//!
+//! Binance only lists the trades of one symbol (e.g. "BTCUSDT") at a time, so
+//! the trades are fetched for the symbols in `KRAKEN2RFB_BINANCE_SYMBOLS`, or
+//! by default for every symbol between the assets the account holds or moved
+//! in the period and the usual quote assets.
+
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+use chrono::{NaiveDate, Utc};
+use hmac::{Hmac, Mac};
+use itertools::Itertools;
+use reqwest::blocking::Client;
+use rust_decimal::Decimal;
+use serde_json::Value;
+use sha2::Sha256;
+
+use crate::api_keys::{ApiKeys, KeyLocation};
+use crate::endpoints;
+use crate::env;
+use crate::error::{Error, Result};
+use crate::fields::{
+    self, date_time_field, decimal, decimal_field, int_field, millis_field, optional_str_field,
+    str_field,
+};
+use crate::http;
+use crate::i18n::t;
+use crate::report::transactions::ExchangeInfo;
+use crate::source::{
+    Activity, ExchangeSource, NormalizedDeposit, NormalizedTrade, NormalizedWithdrawal, Side,
+    day_millis, is_fiat, millis_range, millis_windows,
+};
+
+/// Where the Binance API keys are looked for: `binance_keys.json`, or the
+/// `KRAKEN2RFB_BINANCE_KEYFILE`, `KRAKEN2RFB_BINANCE_API_KEY` and
+/// `KRAKEN2RFB_BINANCE_API_SECRET` environment variables
+pub const KEYS: KeyLocation = KeyLocation {
+    exchange: "Binance",
+    file_name: "binance_keys.json",
+    env_prefix: "BINANCE_",
+    profile_keyfile: false,
+};
+
+/// Environment variable with the symbols to fetch the trades of, without the
+/// prefix
+const SYMBOLS_ENV: &str = "BINANCE_SYMBOLS";
+
+/// Milliseconds a signed request is valid for after its timestamp
+const RECV_WINDOW: u64 = 10_000;
+
+/// Longest time range of a request to the deposit, withdrawal and dust
+/// history, in days
+const HISTORY_WINDOW_DAYS: i64 = 90;
+
+/// Longest time range of a request to the convert history, in days
+const CONVERT_WINDOW_DAYS: i64 = 30;
+
+/// Most entries a page of a history has
+const PAGE_LIMIT: usize = 1000;
+
+/// Assets the symbols of the held assets are looked for against, besides
+/// each other
+const QUOTE_ASSETS: [&str; 10] = [
+    "USDT", "FDUSD", "USDC", "BUSD", "BTC", "ETH", "BNB", "BRL", "EUR", "TRY",
+];
+
+/// Raw Binance activity for a period. Each trade has its `baseAsset` and
+/// `quoteAsset` added and, if its commission was paid in a third asset, the
+/// price of that asset in the quote asset as `commissionPrice`.
+#[derive(Debug, Clone, Default)]
+pub struct BinanceActivity {
+    pub deposits: Vec<Value>,
+    pub withdrawals: Vec<Value>,
+    pub trades: Vec<Value>,
+    pub converts: Vec<Value>,
+    /// The details of the dust sweeps, one for each asset swept
+    pub dust: Vec<Value>,
+}
+
+/// Signature of the query of a signed request: the HMAC-SHA256 of the query
+/// with the API secret, in hexadecimal
+fn signature(query: &str, secret: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size");
+    mac.update(query.as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Error codes by which Binance rejects the API keys
+const AUTH_ERROR_CODES: [i64; 4] = [-1022, -2008, -2014, -2015];
+
+fn binance_error(endpoint: &str, body: &Value) -> Error {
+    let code = body["code"].as_i64();
+    Error::Exchange {
+        exchange: "Binance".to_string(),
+        endpoint: endpoint.to_string(),
+        message: match (code, body["msg"].as_str()) {
+            (Some(code), Some(msg)) => format!("{} ({})", msg, code),
+            _ => body.to_string(),
+        },
+        auth: code.is_some_and(|code| AUTH_ERROR_CODES.contains(&code)),
+    }
+}
+
+/// The signed API of an account
+struct Api {
+    client: &'static Client,
+    keys: ApiKeys,
+}
+
+impl Api {
+    fn new(keyfile: &Path) -> Result<Self> {
+        Ok(Api {
+            client: http::client()?,
+            keys: KEYS.load(keyfile)?,
+        })
+    }
+
+    fn get(&self, path: &str, query: String, signed: bool) -> Result<Value> {
+        let mut url = format!("{}{}?{}", endpoints::get().binance, path, query);
+        if signed {
+            url.push_str(&format!(
+                "&signature={}",
+                signature(&query, &self.keys.secret)
+            ));
+        }
+        let mut request = self.client.get(url);
+        if signed {
+            request = request.header("X-MBX-APIKEY", &self.keys.key);
+        }
+        let response = http::send(request)?;
+        let success = response.status().is_success();
+        let body: Value = response
+            .json()
+            .map_err(|e| Error::Network(t!("invalid JSON from Binance {}: {}", path, e)))?;
+        if !success || body["code"].as_i64().is_some_and(|code| code < 0) {
+            return Err(binance_error(path, &body));
+        }
+        Ok(body)
+    }
+
+    /// Calls a signed endpoint, e.g. "/api/v3/myTrades"
+    fn signed(&self, path: &str, params: &[(&str, String)]) -> Result<Value> {
+        let timestamp = Utc::now().timestamp_millis();
+        let mut query = serde_urlencoded::to_string(params).expect("the params are strings");
+        if !query.is_empty() {
+            query.push('&');
+        }
+        query.push_str(&format!(
+            "timestamp={}&recvWindow={}",
+            timestamp, RECV_WINDOW
+        ));
+        self.get(path, query, true)
+    }
+
+    /// Calls a public endpoint, e.g. "/api/v3/exchangeInfo"
+    fn public(&self, path: &str, params: &[(&str, String)]) -> Result<Value> {
+        let query = serde_urlencoded::to_string(params).expect("the params are strings");
+        self.get(path, query, false)
+    }
+
+    /// Pages through a history listed by offset, in windows of `days` days
+    fn history(&self, path: &str, start: i64, end: i64, days: i64) -> Result<Vec<Value>> {
+        let mut entries = Vec::new();
+        for (from, to) in millis_windows(start, end, days) {
+            for offset in (0..).step_by(PAGE_LIMIT) {
+                let page = self.signed(
+                    path,
+                    &[
+                        ("startTime", from.to_string()),
+                        ("endTime", to.to_string()),
+                        ("offset", offset.to_string()),
+                        ("limit", PAGE_LIMIT.to_string()),
+                    ],
+                )?;
+                let page = list(&page, path)?;
+                entries.extend(page.iter().cloned());
+                if page.len() < PAGE_LIMIT {
+                    break;
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// All the trades of a symbol from `start` to `end`, a page at a time
+    fn trades(&self, symbol: &str, start: i64, end: i64) -> Result<Vec<Value>> {
+        let path = "/api/v3/myTrades";
+        let mut trades = Vec::new();
+        let mut from = ("startTime", start.to_string());
+        loop {
+            let page = self.signed(
+                path,
+                &[
+                    ("symbol", symbol.to_string()),
+                    from.clone(),
+                    ("limit", PAGE_LIMIT.to_string()),
+                ],
+            )?;
+            let page = list(&page, path)?;
+            let Some(last) = page.last() else {
+                break;
+            };
+            let last_id = int_field(last, "id")?;
+            let past_end = int_field(last, "time")? > end;
+            trades.extend(
+                page.iter()
+                    .filter(|trade| int_field(trade, "time").is_ok_and(|t| t <= end))
+                    .cloned(),
+            );
+            if page.len() < PAGE_LIMIT || past_end {
+                break;
+            }
+            from = ("fromId", (last_id + 1).to_string());
+        }
+        Ok(trades)
+    }
+
+    /// The converts from `start` to `end`
+    fn converts(&self, start: i64, end: i64) -> Result<Vec<Value>> {
+        let path = "/sapi/v1/convert/tradeFlow";
+        let mut converts = Vec::new();
+        for (mut from, to) in millis_windows(start, end, CONVERT_WINDOW_DAYS) {
+            loop {
+                let page = self.signed(
+                    path,
+                    &[
+                        ("startTime", from.to_string()),
+                        ("endTime", to.to_string()),
+                        ("limit", PAGE_LIMIT.to_string()),
+                    ],
+                )?;
+                let entries = list(&page["list"], path)?;
+                converts.extend(
+                    entries
+                        .iter()
+                        .filter(|c| c["orderStatus"] == "SUCCESS")
+                        .cloned(),
+                );
+                // The rest of the window is asked for after the last one listed
+                match entries.last().map(|c| int_field(c, "createTime")) {
+                    Some(last) if page["moreData"] == true => from = last? + 1,
+                    _ => break,
+                }
+            }
+        }
+        Ok(converts)
+    }
+
+    /// The details of the dust sweeps from `start` to `end`, with the time of
+    /// their sweep
+    fn dust(&self, start: i64, end: i64) -> Result<Vec<Value>> {
+        let path = "/sapi/v1/asset/dribblet";
+        let mut details = Vec::new();
+        for (from, to) in millis_windows(start, end, HISTORY_WINDOW_DAYS) {
+            let page = self.signed(
+                path,
+                &[("startTime", from.to_string()), ("endTime", to.to_string())],
+            )?;
+            for sweep in list(&page["userAssetDribblets"], path)? {
+                for detail in list(&sweep["userAssetDribbletDetails"], path)? {
+                    let mut detail = detail.clone();
+                    if detail["operateTime"].is_null() {
+                        detail["operateTime"] = sweep["operateTime"].clone();
+                    }
+                    details.push(detail);
+                }
+            }
+        }
+        Ok(details)
+    }
+
+    /// The base and quote assets of every symbol, by symbol
+    fn symbols(&self) -> Result<HashMap<String, (String, String)>> {
+        let path = "/api/v3/exchangeInfo";
+        let info = self.public(path, &[])?;
+        list(&info["symbols"], path)?
+            .iter()
+            .map(|symbol| {
+                Ok((
+                    str_field(symbol, "symbol")?.to_string(),
+                    (
+                        str_field(symbol, "baseAsset")?.to_string(),
+                        str_field(symbol, "quoteAsset")?.to_string(),
+                    ),
+                ))
+            })
+            .collect()
+    }
+
+    /// The assets with a balance in the account
+    fn held_assets(&self) -> Result<Vec<String>> {
+        let path = "/api/v3/account";
+        let account = self.signed(path, &[("omitZeroBalances", "true".to_string())])?;
+        list(&account["balances"], path)?
+            .iter()
+            .map(|balance| Ok(str_field(balance, "asset")?.to_string()))
+            .collect()
+    }
+
+    /// Closing price on `date` of `asset` in `quote`, from the daily candle of
+    /// their symbol in either direction
+    fn price(
+        &self,
+        symbols: &HashMap<String, (String, String)>,
+        asset: &str,
+        quote: &str,
+        date: NaiveDate,
+    ) -> Result<Decimal> {
+        let candle = |symbol: String| -> Result<Option<Decimal>> {
+            if !symbols.contains_key(&symbol) {
+                return Ok(None);
+            }
+            let path = "/api/v3/klines";
+            let candles = self.public(
+                path,
+                &[
+                    ("symbol", symbol),
+                    ("interval", "1d".to_string()),
+                    ("startTime", day_millis(date).to_string()),
+                    ("limit", "1".to_string()),
+                ],
+            )?;
+            list(&candles, path)?
+                .first()
+                .map(|candle| decimal(&candle[4], "close"))
+                .transpose()
+        };
+        if let Some(price) = candle(format!("{}{}", asset, quote))? {
+            return Ok(price);
+        }
+        match candle(format!("{}{}", quote, asset))? {
+            Some(price) if !price.is_zero() => Ok(Decimal::ONE / price),
+            _ => Err(Error::Validation(t!(
+                "no {} market to value {} in {} on {}",
+                "Binance",
+                asset,
+                quote,
+                date
+            ))),
+        }
+    }
+}
+
+/// The symbols whose trades are fetched, see the module documentation
+fn trade_symbols(
+    symbols: &HashMap<String, (String, String)>,
+    assets: &BTreeSet<String>,
+) -> Vec<String> {
+    if let Some(listed) = env::var(SYMBOLS_ENV) {
+        return listed
+            .split(',')
+            .map(|symbol| symbol.trim().to_uppercase())
+            .filter(|symbol| !symbol.is_empty())
+            .collect();
+    }
+    let is_quote = |asset: &str| assets.contains(asset) || QUOTE_ASSETS.contains(&asset);
+    symbols
+        .iter()
+        .filter(|(_, (base, quote))| assets.contains(base) && is_quote(quote))
+        .map(|(symbol, _)| symbol.clone())
+        .sorted()
+        .collect()
+}
+
+pub fn fetch_binance_activity(
+    initial: NaiveDate,
+    final_: NaiveDate,
+    keyfile: &Path,
+) -> Result<BinanceActivity> {
+    let api = Api::new(keyfile)?;
+    let (start, end) = millis_range(initial, final_);
+
+    let deposits = api.history(
+        "/sapi/v1/capital/deposit/hisrec",
+        start,
+        end,
+        HISTORY_WINDOW_DAYS,
+    )?;
+    let withdrawals = api.history(
+        "/sapi/v1/capital/withdraw/history",
+        start,
+        end,
+        HISTORY_WINDOW_DAYS,
+    )?;
+    let converts = api.converts(start, end)?;
+    let dust = api.dust(start, end)?;
+
+    let symbols = api.symbols()?;
+    let mut assets: BTreeSet<String> = api.held_assets()?.into_iter().collect();
+    for entry in deposits.iter().chain(&withdrawals) {
+        assets.extend(entry["coin"].as_str().map(String::from));
+    }
+    for convert in &converts {
+        assets.extend(convert["fromAsset"].as_str().map(String::from));
+        assets.extend(convert["toAsset"].as_str().map(String::from));
+    }
+    for detail in &dust {
+        assets.extend(detail["fromAsset"].as_str().map(String::from));
+    }
+
+    let mut trades = Vec::new();
+    let mut prices = HashMap::new();
+    for symbol in trade_symbols(&symbols, &assets) {
+        let Some((base, quote)) = symbols.get(&symbol) else {
+            return Err(Error::UnknownPair(symbol));
+        };
+        for mut trade in api.trades(&symbol, start, end)? {
+            trade["baseAsset"] = Value::from(base.as_str());
+            trade["quoteAsset"] = Value::from(quote.as_str());
+            let commission_asset = str_field(&trade, "commissionAsset")?.to_string();
+            if commission_asset != *base
+                && commission_asset != *quote
+                && !decimal_field(&trade, "commission")?.is_zero()
+            {
+                let date = millis_field(&trade, "time")?.date_naive();
+                let key = (commission_asset.clone(), quote.clone(), date);
+                let price = match prices.get(&key) {
+                    Some(&price) => price,
+                    None => {
+                        let price = api.price(&symbols, &commission_asset, quote, date)?;
+                        *prices.entry(key).or_insert(price)
+                    }
+                };
+                trade["commissionPrice"] = Value::from(price.to_string());
+            }
+            trades.push(trade);
+        }
+    }
+
+    Ok(BinanceActivity {
+        deposits,
+        withdrawals,
+        trades,
+        converts,
+        dust,
+    })
+}
+
+/// The Binance account the API keys give access to
+pub struct BinanceSource {
+    /// Key file given by the user, or `None` to look for it only when fetching
+    keyfile: Option<PathBuf>,
+}
+
+impl BinanceSource {
+    pub fn new(keyfile: Option<PathBuf>) -> Self {
+        BinanceSource { keyfile }
+    }
+}
+
+impl ExchangeSource for BinanceSource {
+    fn exchange(&self) -> ExchangeInfo {
+        ExchangeInfo {
+            name: "Binance".to_string(),
+            url: "https://www.binance.com".to_string(),
+            country: "KY".to_string(),
+        }
+    }
+
+    fn fetch(&self, first_day: NaiveDate, last_day: NaiveDate) -> Result<Activity> {
+        let keyfile = KEYS.find_keyfile(self.keyfile.as_deref())?;
+        normalize(fetch_binance_activity(first_day, last_day, &keyfile)?)
+    }
+}
+
+/// Converts the raw Binance activity to the typed entries of [`Activity`].
+///
+/// The trades IDs are only unique within their symbol, so every ID but the
+/// transfers' is prefixed: `<symbol>:<id>` for a trade, `<symbol>:order:<id>`
+/// for its order, `convert:<id>` and `dust:<id>:<asset>`. The commission of a
+/// trade is valued in its quote asset; converts have no fee apart from their
+/// spread. A convert between crypto assets, or a dust sweep to BNB, is a buy of
+/// the asset received.
+fn normalize(activity: BinanceActivity) -> Result<Activity> {
+    let transfer_ids = |entry: &Value| -> Vec<String> {
+        ["id", "txId"]
+            .iter()
+            .filter_map(|field| entry[field].as_str())
+            .filter(|id| !id.is_empty())
+            .map(String::from)
+            .collect()
+    };
+    // Deposits are credited with status 1, or 6 while they can't be withdrawn
+    // yet; withdrawals are completed with status 6
+    let deposits = activity
+        .deposits
+        .iter()
+        .filter(|d| matches!(d["status"].as_i64(), Some(1 | 6)))
+        .map(|deposit| -> Result<NormalizedDeposit> {
+            Ok(NormalizedDeposit {
+                asset: str_field(deposit, "coin")?.to_string(),
+                amount: decimal_field(deposit, "amount")?,
+                fee: Decimal::ZERO,
+                time: millis_field(deposit, "insertTime")?,
+                ids: transfer_ids(deposit),
+                origin_exchange: None,
+                origin_wallet: None,
+                network: None,
+            })
+        });
+    let withdrawals = activity
+        .withdrawals
+        .iter()
+        .filter(|w| w["status"].as_i64() == Some(6))
+        .map(|withdrawal| -> Result<NormalizedWithdrawal> {
+            Ok(NormalizedWithdrawal {
+                asset: str_field(withdrawal, "coin")?.to_string(),
+                amount: decimal_field(withdrawal, "amount")?,
+                fee: decimal_field(withdrawal, "transactionFee")?,
+                time: date_time_field(withdrawal, "applyTime", "%Y-%m-%d %H:%M:%S")?,
+                ids: transfer_ids(withdrawal),
+                destination: optional_str_field(withdrawal, "address"),
+                network: None,
+            })
+        });
+    let trade = |trade: &Value| -> Result<NormalizedTrade> {
+        let symbol = str_field(trade, "symbol")?;
+        let (base, quote) = (
+            str_field(trade, "baseAsset")?,
+            str_field(trade, "quoteAsset")?,
+        );
+        let price = decimal_field(trade, "price")?;
+        let commission = decimal_field(trade, "commission")?;
+        let fee = match str_field(trade, "commissionAsset")? {
+            _ if commission.is_zero() => Decimal::ZERO,
+            asset if asset == quote => commission,
+            asset if asset == base => commission * price,
+            _ => commission * decimal_field(trade, "commissionPrice")?,
+        };
+        Ok(NormalizedTrade {
+            base: base.to_string(),
+            quote: quote.to_string(),
+            side: if trade["isBuyer"] == true {
+                Side::Buy
+            } else {
+                Side::Sell
+            },
+            volume: decimal_field(trade, "qty")?,
+            cost: decimal_field(trade, "quoteQty")?,
+            fee,
+            credit_fee_usd: Decimal::ZERO,
+            price,
+            time: millis_field(trade, "time")?,
+            ids: vec![
+                format!("{}:{}", symbol, int_field(trade, "id")?),
+                format!("{}:order:{}", symbol, int_field(trade, "orderId")?),
+            ],
+        })
+    };
+    let convert = |convert: &Value| -> Result<NormalizedTrade> {
+        let (from, to) = (
+            str_field(convert, "fromAsset")?,
+            str_field(convert, "toAsset")?,
+        );
+        let (from_amount, to_amount) = (
+            decimal_field(convert, "fromAmount")?,
+            decimal_field(convert, "toAmount")?,
+        );
+        // The crypto asset is the base of the trade
+        let (side, base, quote, volume, cost) = if is_fiat(to) && !is_fiat(from) {
+            (Side::Sell, from, to, from_amount, to_amount)
+        } else {
+            (Side::Buy, to, from, to_amount, from_amount)
+        };
+        Ok(NormalizedTrade {
+            base: base.to_string(),
+            quote: quote.to_string(),
+            side,
+            volume,
+            cost,
+            fee: Decimal::ZERO,
+            credit_fee_usd: Decimal::ZERO,
+            price: price(cost, volume, convert)?,
+            time: millis_field(convert, "createTime")?,
+            ids: vec![format!("convert:{}", int_field(convert, "orderId")?)],
+        })
+    };
+    let dust = |detail: &Value| -> Result<NormalizedTrade> {
+        let asset = str_field(detail, "fromAsset")?;
+        let fee = decimal_field(detail, "serviceChargeAmount")?;
+        let received = decimal_field(detail, "transferedAmount")?;
+        let swept = decimal_field(detail, "amount")?;
+        Ok(NormalizedTrade {
+            base: "BNB".to_string(),
+            quote: asset.to_string(),
+            side: Side::Buy,
+            volume: received,
+            cost: swept,
+            // The service charge is taken in BNB, so it is valued at the price
+            // of the sweep
+            fee: fee * price(swept, received + fee, detail)?,
+            credit_fee_usd: Decimal::ZERO,
+            price: price(swept, received, detail)?,
+            time: millis_field(detail, "operateTime")?,
+            ids: vec![format!("dust:{}:{}", int_field(detail, "transId")?, asset)],
+        })
+    };
+
+    let mut trades: Vec<NormalizedTrade> = activity.trades.iter().map(trade).try_collect()?;
+    trades.extend(
+        activity
+            .converts
+            .iter()
+            .map(convert)
+            .try_collect::<_, Vec<_>, _>()?,
+    );
+    trades.extend(
+        activity
+            .dust
+            .iter()
+            .map(dust)
+            .try_collect::<_, Vec<_>, _>()?,
+    );
+    trades.sort_by_key(|t| t.time);
+    let mut deposits: Vec<NormalizedDeposit> = deposits.try_collect()?;
+    deposits.sort_by_key(|d| d.time);
+    let mut withdrawals: Vec<NormalizedWithdrawal> = withdrawals.try_collect()?;
+    withdrawals.sort_by_key(|w| w.time);
+    Ok(Activity {
+        deposits,
+        withdrawals,
+        trades,
+        income: Vec::new(),
+        wallet_transfers: Vec::new(),
+    })
+}
+
+/// A JSON list in a Binance response
+fn list<'a>(value: &'a Value, endpoint: &str) -> Result<&'a Vec<Value>> {
+    fields::list(value, "Binance", endpoint)
+}
+
+/// Price of a trade of `volume` for `cost`
+fn price(cost: Decimal, volume: Decimal, entry: &Value) -> Result<Decimal> {
+    cost.checked_div(volume)
+        .ok_or_else(|| Error::Validation(t!("Binance entry with zero amount: {}", entry)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    use serde_json::json;
+
+    #[test]
+    fn test_signature() {
+        // The example of the Binance API documentation
+        let query = "symbol=LTCBTC&side=BUY&type=LIMIT&timeInForce=GTC&quantity=1&price=0.1&recvWindow=5000&timestamp=1499827319559";
+        let secret = "NhqPtmdSJYdKjVHjA7PZj4Mge3R5YNiP1e3UZjInClVN65XAbvqqM6A7H5fATj0j";
+        assert_eq!(
+            signature(query, secret),
+            "c8db56825ae71d6d79447849e617115f4a920fa2acdcab2b053c4b2838bd6b71"
+        );
+    }
+
+    #[test]
+    fn test_normalize() {
+        let activity = normalize(BinanceActivity {
+            deposits: vec![
+                json!({"id": "769800519366885376", "amount": "0.5", "coin": "BTC", "status": 1, "insertTime": 1709294400000_i64, "txId": "b34f"}),
+                json!({"id": "769800519366885377", "amount": "1", "coin": "BTC", "status": 0, "insertTime": 1709294400000_i64, "txId": ""}),
+            ],
+            withdrawals: vec![
+                json!({"id": "b6ae22b3aa844210a7041aee7589627c", "amount": "8.91", "transactionFee": "0.004", "coin": "USDT", "status": 6, "applyTime": "2024-03-02 11:12:02", "txId": "0xb5ef"}),
+            ],
+            trades: vec![
+                json!({"symbol": "BTCUSDT", "baseAsset": "BTC", "quoteAsset": "USDT", "id": 28457, "orderId": 100234, "price": "60000", "qty": "0.01", "quoteQty": "600", "commission": "0.00001", "commissionAsset": "BTC", "time": 1709380800700_i64, "isBuyer": true}),
+                json!({"symbol": "ETHBTC", "baseAsset": "ETH", "quoteAsset": "BTC", "id": 28457, "orderId": 5, "price": "0.05", "qty": "1", "quoteQty": "0.05", "commission": "0.01", "commissionAsset": "BNB", "commissionPrice": "0.008", "time": 1709380900000_i64, "isBuyer": false}),
+            ],
+            converts: vec![
+                json!({"orderId": 940708407462087195_i64, "orderStatus": "SUCCESS", "fromAsset": "BRL", "fromAmount": "1000", "toAsset": "USDT", "toAmount": "200", "createTime": 1709467200000_i64}),
+            ],
+            dust: vec![
+                json!({"transId": 45178372831_i64, "serviceChargeAmount": 0.00001, "amount": 0.5, "operateTime": 1709553600000_i64, "transferedAmount": 0.00049, "fromAsset": "DOGE"}),
+            ],
+        })
+        .unwrap();
+
+        // The pending deposit is left out
+        assert_eq!(activity.deposits.len(), 1);
+        assert_eq!(activity.deposits[0].ids, ["769800519366885376", "b34f"]);
+        let withdrawal = &activity.withdrawals[0];
+        assert_eq!(
+            (withdrawal.amount, withdrawal.fee),
+            (dec!(8.91), dec!(0.004))
+        );
+        assert_eq!(withdrawal.time.to_string(), "2024-03-02 11:12:02 UTC");
+
+        let [buy, sell, convert, dust] = activity.trades.as_slice() else {
+            panic!("expected 4 trades, got {:?}", activity.trades);
+        };
+        // A commission in the base asset is valued at the price of the trade
+        assert_eq!(buy.fee, dec!(0.6));
+        assert_eq!(buy.time.timestamp(), 1709380800);
+        assert_eq!(buy.ids, ["BTCUSDT:28457", "BTCUSDT:order:100234"]);
+        // One in BNB at the price of BNB in the quote asset
+        assert_eq!((sell.side, sell.fee), (Side::Sell, dec!(0.00008)));
+        assert_eq!(sell.ids[0], "ETHBTC:28457");
+        // Converting from BRL buys the asset
+        assert_eq!(
+            (convert.side, convert.base.as_str(), convert.quote.as_str()),
+            (Side::Buy, "USDT", "BRL")
+        );
+        assert_eq!((convert.volume, convert.price), (dec!(200), dec!(5)));
+        assert_eq!(dust.base, "BNB");
+        assert_eq!((dust.volume, dust.cost), (dec!(0.00049), dec!(0.5)));
+        assert_eq!(dust.fee, dec!(0.01));
+        assert_eq!(dust.ids, ["dust:45178372831:DOGE"]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = binance_error(
+            "/api/v3/account",
+            &json!({"code": -2015, "msg": "Invalid API-key, IP, or permissions for action."}),
+        );
+        assert!(error.is_auth_error());
+        assert!(error.to_string().contains("(-2015)"));
+        let error = binance_error(
+            "/api/v3/account",
+            &json!({"code": -1003, "msg": "Too many requests."}),
+        );
+        assert!(!error.is_auth_error());
+    }
+}
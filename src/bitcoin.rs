@@ -0,0 +1,558 @@
+//! Scan of a Bitcoin wallet of the user on an Esplora block explorer
+//! (mempool.space by default): the wallet is given by its extended public key
+//! (`xpub`, `ypub` or `zpub`) or an output descriptor of one (`pkh(...)`,
+//! `sh(wpkh(...))` or `wpkh(...)`), its addresses are derived until 20 in a
+//! row were never used, and the transactions between them and the outside are
+//! the [`ChainTransfer`]s that [`crate::transfers::match_wallet`] matches to
+//! the deposits and withdrawals of an exchange.
+//!
+//! Only the public keys are ever seen, and only the non-hardened derivation
+//! of BIP 32 is done from them, with the elliptic curve arithmetic of OpenSSL.
+
+use std::collections::{HashMap, HashSet};
+
+use hmac::{Hmac, Mac};
+use openssl::bn::{BigNum, BigNumContext};
+use openssl::ec::{EcGroup, EcPoint, PointConversionForm};
+use openssl::hash::{MessageDigest, hash};
+use openssl::nid::Nid;
+use reqwest::StatusCode;
+use reqwest::blocking::Client;
+use rust_decimal::Decimal;
+use serde_json::Value;
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::endpoints;
+use crate::error::{Error, Result};
+use crate::fields::{self, int_field, seconds_field, str_field};
+use crate::http;
+use crate::i18n::t;
+use crate::transfers::ChainTransfer;
+use crate::verify::ChainPayment;
+
+/// How many unused addresses in a row end the scan of a chain, as wallets do
+const GAP_LIMIT: u32 = 20;
+
+/// How many confirmed transactions Esplora gives per page
+const PAGE_SIZE: usize = 25;
+
+const BASE58: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+const BECH32: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// The kind of address of a wallet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Script {
+    /// Legacy addresses, starting with 1
+    Pkh,
+    /// Nested SegWit addresses, starting with 3
+    ShWpkh,
+    /// Native SegWit addresses, starting with bc1q
+    Wpkh,
+}
+
+/// Version bytes of the extended public keys of the main network, and the
+/// addresses the wallets derive from each
+const VERSIONS: [([u8; 4], Script); 3] = [
+    ([0x04, 0x88, 0xb2, 0x1e], Script::Pkh),
+    ([0x04, 0x9d, 0x7c, 0xaf], Script::ShWpkh),
+    ([0x04, 0xb2, 0x47, 0x46], Script::Wpkh),
+];
+
+/// A public key with its chain code, from which the child keys are derived
+#[derive(Debug, Clone, PartialEq)]
+struct ExtendedKey {
+    /// The compressed public key
+    key: Vec<u8>,
+    chain_code: Vec<u8>,
+}
+
+fn invalid_key(message: impl std::fmt::Display) -> Error {
+    Error::Config(t!("invalid extended public key: {}", message))
+}
+
+impl ExtendedKey {
+    /// Decodes a serialized extended public key, with the kind of address of
+    /// its version
+    fn decode(text: &str) -> Result<(Self, Script)> {
+        let bytes = base58check_decode(text).ok_or_else(|| invalid_key(text))?;
+        if bytes.len() != 78 {
+            return Err(invalid_key(text));
+        }
+        let script = VERSIONS
+            .iter()
+            .find(|(version, _)| bytes[..4] == *version)
+            .map(|&(_, script)| script)
+            .ok_or_else(|| invalid_key(text))?;
+        let key = ExtendedKey {
+            chain_code: bytes[13..45].to_vec(),
+            key: bytes[45..].to_vec(),
+        };
+        Ok((key, script))
+    }
+
+    /// The non-hardened child key at an index
+    fn child(&self, index: u32) -> Result<Self> {
+        let mut mac =
+            Hmac::<Sha512>::new_from_slice(&self.chain_code).expect("HMAC takes keys of any size");
+        mac.update(&self.key);
+        mac.update(&index.to_be_bytes());
+        let output = mac.finalize().into_bytes();
+        let (tweak, chain_code) = output.split_at(32);
+
+        let curve = |e: openssl::error::ErrorStack| invalid_key(e);
+        let group = EcGroup::from_curve_name(Nid::SECP256K1).map_err(curve)?;
+        let mut ctx = BigNumContext::new().map_err(curve)?;
+        let tweak = BigNum::from_slice(tweak).map_err(curve)?;
+        let mut order = BigNum::new().map_err(curve)?;
+        group.order(&mut order, &mut ctx).map_err(curve)?;
+        if tweak >= order {
+            return Err(invalid_key(t!("no child key at index {}", index)));
+        }
+        let parent = EcPoint::from_bytes(&group, &self.key, &mut ctx).map_err(curve)?;
+        let mut point = EcPoint::new(&group).map_err(curve)?;
+        point
+            .mul_generator2(&group, &tweak, &mut ctx)
+            .map_err(curve)?;
+        let mut child = EcPoint::new(&group).map_err(curve)?;
+        child
+            .add(&group, &point, &parent, &mut ctx)
+            .map_err(curve)?;
+        if child.is_infinity(&group) {
+            return Err(invalid_key(t!("no child key at index {}", index)));
+        }
+        Ok(ExtendedKey {
+            key: child
+                .to_bytes(&group, PointConversionForm::COMPRESSED, &mut ctx)
+                .map_err(curve)?,
+            chain_code: chain_code.to_vec(),
+        })
+    }
+}
+
+fn base58check_decode(text: &str) -> Option<Vec<u8>> {
+    // Base 256 digits, least significant first
+    let mut digits: Vec<u8> = Vec::new();
+    for c in text.bytes() {
+        let mut carry = BASE58.iter().position(|&b| b == c)? as u32;
+        for digit in &mut digits {
+            carry += *digit as u32 * 58;
+            *digit = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            digits.push(carry as u8);
+            carry >>= 8;
+        }
+    }
+    let zeros = text.bytes().take_while(|&c| c == b'1').count();
+    let mut bytes = vec![0; zeros];
+    bytes.extend(digits.iter().rev());
+    let (payload, checksum) = bytes.split_at(bytes.len().checked_sub(4)?);
+    (Sha256::digest(Sha256::digest(payload))[..4] == *checksum).then(|| payload.to_vec())
+}
+
+fn base58check_encode(payload: &[u8]) -> String {
+    let mut bytes = payload.to_vec();
+    bytes.extend_from_slice(&Sha256::digest(Sha256::digest(payload))[..4]);
+    // Base 58 digits, least significant first
+    let mut digits: Vec<u8> = Vec::new();
+    for &byte in &bytes {
+        let mut carry = byte as u32;
+        for digit in &mut digits {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+    let zeros = bytes.iter().take_while(|&&b| b == 0).count();
+    std::iter::repeat_n('1', zeros)
+        .chain(digits.iter().rev().map(|&d| BASE58[d as usize] as char))
+        .collect()
+}
+
+fn bech32_polymod(values: &[u8]) -> u32 {
+    const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+    let mut checksum = 1;
+    for &value in values {
+        let top = checksum >> 25;
+        checksum = ((checksum & 0x1ffffff) << 5) ^ value as u32;
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+    checksum
+}
+
+/// The native SegWit v0 address of a witness program, as in BIP 173
+fn segwit_address(program: &[u8]) -> String {
+    let hrp = "bc";
+    let mut data = vec![0];
+    let (mut accumulator, mut bits) = (0u32, 0);
+    for &byte in program {
+        accumulator = (accumulator << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            data.push(((accumulator >> bits) & 31) as u8);
+        }
+    }
+    if bits > 0 {
+        data.push(((accumulator << (5 - bits)) & 31) as u8);
+    }
+    let mut values: Vec<u8> = hrp.bytes().map(|c| c >> 5).collect();
+    values.push(0);
+    values.extend(hrp.bytes().map(|c| c & 31));
+    values.extend(&data);
+    values.extend([0; 6]);
+    let checksum = bech32_polymod(&values) ^ 1;
+    data.extend((0..6).map(|i| ((checksum >> (5 * (5 - i))) & 31) as u8));
+    let data: String = data.iter().map(|&d| BECH32[d as usize] as char).collect();
+    format!("{}1{}", hrp, data)
+}
+
+fn hash160(data: &[u8]) -> Result<Vec<u8>> {
+    let digest = hash(MessageDigest::ripemd160(), &Sha256::digest(data))
+        .map_err(|e| Error::Config(t!("RIPEMD-160 is not available: {}", e)))?;
+    Ok(digest.to_vec())
+}
+
+/// A wallet given by an extended public key: the kind of its addresses and
+/// the chains scanned, 0 for the receiving addresses and 1 for the change
+#[derive(Debug, Clone, PartialEq)]
+pub struct Descriptor {
+    key: ExtendedKey,
+    script: Script,
+    chains: Vec<u32>,
+}
+
+impl Descriptor {
+    /// Parses an extended public key, whose version tells the kind of its
+    /// addresses, or an output descriptor with one, like
+    /// `wpkh([d34db33f/84h/0h/0h]xpub.../0/*)`. Without the `/<chain>/*`
+    /// ending, both chains are scanned; `/<0;1>/*` is both too.
+    pub fn parse(text: &str) -> Result<Self> {
+        let unsupported = || Error::Config(t!("unsupported wallet descriptor: {}", text));
+        let descriptor = text.split('#').next().unwrap_or_default().trim();
+        let (script, inner) = [
+            ("sh(wpkh(", "))", Script::ShWpkh),
+            ("wpkh(", ")", Script::Wpkh),
+            ("pkh(", ")", Script::Pkh),
+        ]
+        .iter()
+        .find_map(|&(prefix, suffix, script)| {
+            Some((
+                Some(script),
+                descriptor.strip_prefix(prefix)?.strip_suffix(suffix)?,
+            ))
+        })
+        .unwrap_or((None, descriptor));
+        // The origin of the key, in brackets, only says where it came from
+        let inner = match inner.strip_prefix('[') {
+            Some(rest) => rest.split_once(']').ok_or_else(unsupported)?.1,
+            None => inner,
+        };
+        let mut path = inner.split('/');
+        let (key, version_script) = ExtendedKey::decode(path.next().unwrap_or_default())?;
+        let chains = match (path.next(), path.next(), path.next()) {
+            (None, _, _) | (Some("<0;1>"), Some("*"), None) => vec![0, 1],
+            (Some(chain), Some("*"), None) => vec![chain.parse().map_err(|_| unsupported())?],
+            _ => return Err(unsupported()),
+        };
+        Ok(Descriptor {
+            key,
+            script: script.unwrap_or(version_script),
+            chains,
+        })
+    }
+
+    /// The address at an index of a chain
+    fn address(&self, chain: u32, index: u32) -> Result<String> {
+        let key = self.key.child(chain)?.child(index)?;
+        let hash = hash160(&key.key)?;
+        Ok(match self.script {
+            Script::Pkh => base58check_encode(&[&[0x00], hash.as_slice()].concat()),
+            Script::ShWpkh => {
+                let redeem_script = [&[0x00, 0x14], hash.as_slice()].concat();
+                base58check_encode(&[&[0x05], hash160(&redeem_script)?.as_slice()].concat())
+            }
+            Script::Wpkh => segwit_address(&hash),
+        })
+    }
+}
+
+fn list<'a>(value: &'a Value, endpoint: &str) -> Result<&'a Vec<Value>> {
+    fields::list(value, "Esplora", endpoint)
+}
+
+fn esplora_error(path: &str, message: impl std::fmt::Display) -> Error {
+    Error::Network(t!("block explorer error on {}: {}", path, message))
+}
+
+/// The confirmed transactions of an address, newest first
+fn address_transactions(client: &Client, address: &str) -> Result<Vec<Value>> {
+    let mut transactions: Vec<Value> = Vec::new();
+    loop {
+        let mut path = format!("/address/{}/txs/chain", address);
+        if let Some(last) = transactions.last() {
+            path = format!("{}/{}", path, str_field(last, "txid")?);
+        }
+        let response = http::send(client.get(format!("{}{}", endpoints::get().esplora, path)))?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().unwrap_or_default();
+            return Err(esplora_error(&path, format!("{} {}", status, body.trim())));
+        }
+        let page: Value = response
+            .json()
+            .map_err(|e| esplora_error(&path, t!("invalid JSON: {}", e)))?;
+        let page = list(&page, &path)?;
+        transactions.extend(page.iter().cloned());
+        if page.len() < PAGE_SIZE {
+            return Ok(transactions);
+        }
+    }
+}
+
+/// What a transaction pays to each of its outputs, or `None` if the block
+/// explorer doesn't know it
+pub fn transaction_payments(txid: &str) -> Result<Option<Vec<ChainPayment>>> {
+    let path = format!("/tx/{}", txid);
+    let response =
+        http::send(http::client()?.get(format!("{}{}", endpoints::get().esplora, path)))?;
+    let status = response.status();
+    if status == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    if !status.is_success() {
+        let body = response.text().unwrap_or_default();
+        return Err(esplora_error(&path, format!("{} {}", status, body.trim())));
+    }
+    let transaction: Value = response
+        .json()
+        .map_err(|e| esplora_error(&path, t!("invalid JSON: {}", e)))?;
+    list(&transaction["vout"], &path)?
+        .iter()
+        .map(|output| {
+            Ok(ChainPayment {
+                address: output["scriptpubkey_address"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+                amount: Decimal::new(int_field(output, "value")?, 8),
+            })
+        })
+        .collect::<Result<_>>()
+        .map(Some)
+}
+
+/// Scans the addresses of a wallet, and returns its transfers to and from
+/// the outside, oldest first
+pub fn scan_wallet(descriptor: &Descriptor) -> Result<Vec<ChainTransfer>> {
+    let client = http::client()?;
+    let mut own = HashSet::new();
+    let mut transactions: HashMap<String, Value> = HashMap::new();
+    for &chain in &descriptor.chains {
+        let (mut index, mut unused) = (0, 0);
+        while unused < GAP_LIMIT {
+            let address = descriptor.address(chain, index)?;
+            let found = address_transactions(client, &address)?;
+            unused = if found.is_empty() { unused + 1 } else { 0 };
+            for transaction in found {
+                transactions.insert(str_field(&transaction, "txid")?.to_string(), transaction);
+            }
+            own.insert(address);
+            index += 1;
+        }
+    }
+    let mut transfers = transactions
+        .values()
+        .map(|transaction| wallet_transfers(transaction, &own))
+        .collect::<Result<Vec<_>>>()?
+        .concat();
+    transfers.sort_by(|a, b| (a.time, &a.txid).cmp(&(b.time, &b.txid)));
+    Ok(transfers)
+}
+
+/// The transfers of a transaction between the wallet of the `own` addresses
+/// and the outside: an output to the outside of a transaction the wallet
+/// funded is an outgoing transfer, from the first address of the wallet
+/// spent, and the outputs to the wallet of a transaction it didn't fund are
+/// an incoming transfer, to the first of them. A transaction between the
+/// addresses of the wallet is no transfer.
+fn wallet_transfers(transaction: &Value, own: &HashSet<String>) -> Result<Vec<ChainTransfer>> {
+    let txid = str_field(transaction, "txid")?;
+    let time = seconds_field(&transaction["status"], "block_time")?;
+    // (address, satoshis) of the inputs and outputs
+    let outputs = |array: &Value, prevout: bool| -> Result<Vec<(String, i64)>> {
+        list(array, txid)?
+            .iter()
+            .map(|entry| {
+                let output = if prevout { &entry["prevout"] } else { entry };
+                let address = output["scriptpubkey_address"].as_str().unwrap_or_default();
+                Ok((address.to_string(), int_field(output, "value")?))
+            })
+            .collect()
+    };
+    let inputs = outputs(&transaction["vin"], true)?;
+    let outputs = outputs(&transaction["vout"], false)?;
+    let transfer = |address: &str, satoshis: i64, fee: i64, outgoing| ChainTransfer {
+        asset: "BTC".to_string(),
+        amount: Decimal::new(satoshis, 8),
+        fee: Decimal::new(fee, 8),
+        time,
+        txid: txid.to_string(),
+        address: address.to_string(),
+        outgoing,
+        network: Some("Bitcoin".to_string()),
+    };
+
+    if let Some((from, _)) = inputs.iter().find(|(address, _)| own.contains(address)) {
+        // The fee is of the whole transaction, so it goes with the first
+        // output only
+        let fee = int_field(transaction, "fee")?;
+        return Ok(outputs
+            .iter()
+            .filter(|(address, _)| !own.contains(address))
+            .enumerate()
+            .map(|(i, (_, satoshis))| transfer(from, *satoshis, if i == 0 { fee } else { 0 }, true))
+            .collect());
+    }
+    let received: Vec<_> = outputs
+        .iter()
+        .filter(|(address, _)| own.contains(address))
+        .collect();
+    Ok(received
+        .first()
+        .map(|(to, _)| transfer(to, received.iter().map(|(_, s)| s).sum(), 0, false))
+        .into_iter()
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    use serde_json::json;
+
+    /// The master key of the second test vector of BIP 32
+    const XPUB: &str = "xpub661MyMwAqRbcFW31YEwpkMuc5THy2PSt5bDMsktWQcFF8syAmRUapSCGu8ED9W6oDMSgv6Zz8idoc4a6mr8BDzTJY47LJhkJ8UB7WEGuduB";
+
+    #[test]
+    fn test_derivation() {
+        let (key, script) = ExtendedKey::decode(XPUB).unwrap();
+        assert_eq!(script, Script::Pkh);
+        // m/0 of the test vector
+        let (child, _) = ExtendedKey::decode("xpub69H7F5d8KSRgmmdJg2KhpAK8SR3DjMwAdkxj3ZuxV27CprR9LgpeyGmXUbC6wb7ERfvrnKZjXoUmmDznezpbZb7ap6r1D3tgFxHmwMkQTPH").unwrap();
+        assert_eq!(key.child(0).unwrap(), child);
+
+        // The addresses of m/0/1 of each kind
+        let address = |descriptor: &str| {
+            let descriptor = Descriptor::parse(descriptor).unwrap();
+            descriptor.address(descriptor.chains[0], 1).unwrap()
+        };
+        assert_eq!(
+            address(&format!("{}/0/*", XPUB)),
+            "1NK8BtPi1AkfCwa4uuCb8fEtyYUg4NFC93"
+        );
+        assert_eq!(
+            address(&format!("sh(wpkh({}/0/*))", XPUB)),
+            "31kgwysj7CXKTAqKgsHKuCcRDBZEyXiWXD"
+        );
+        assert_eq!(
+            address(&format!("wpkh([d34db33f/84h/0h/0h]{}/0/*)#abcdefgh", XPUB)),
+            "bc1qa8yzpa070ejy9t8qgql39murz5vchd8zdwynde"
+        );
+        // The zpub of the same key, for native SegWit, scans both chains
+        let zpub = "zpub6jftahH18ngZx6RFCxX5AY6cRParudRsupFoSYgHAd11F5bdGjoi4ZWYwY9P9KQe2dgJR3m743LuNdoEDExCpTpWGjWBUXPGfvJQHKojT9s";
+        let descriptor = Descriptor::parse(zpub).unwrap();
+        assert_eq!(
+            (descriptor.script, descriptor.chains.as_slice()),
+            (Script::Wpkh, &[0, 1][..])
+        );
+
+        // The segwit address of the generator point, from BIP 173
+        let generator = hex("0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798");
+        assert_eq!(
+            segwit_address(&hash160(&generator).unwrap()),
+            "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4"
+        );
+
+        assert!(Descriptor::parse(&XPUB.replace('W', "X")).is_err());
+        assert!(Descriptor::parse(&format!("tr({})", XPUB)).is_err());
+        assert!(Descriptor::parse(&format!("{}/0/1/*", XPUB)).is_err());
+    }
+
+    fn hex(text: &str) -> Vec<u8> {
+        (0..text.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_wallet_transfers() {
+        let own: HashSet<String> = ["bc1qown", "bc1qchange"].map(String::from).into();
+        let output =
+            |address: &str, value: i64| json!({"scriptpubkey_address": address, "value": value});
+        let transaction = |vin: Vec<Value>, vout: Vec<Value>| {
+            json!({
+                "txid": "ab",
+                "fee": 10_000,
+                "status": {"confirmed": true, "block_time": 1709294400},
+                "vin": vin.into_iter().map(|prevout| json!({"prevout": prevout})).collect::<Vec<_>>(),
+                "vout": vout,
+            })
+        };
+
+        // Sent to an exchange, with the change back to the wallet
+        let sent = transaction(
+            vec![output("bc1qown", 60_000_000)],
+            vec![
+                output("bc1qexchange", 50_000_000),
+                output("bc1qchange", 9_990_000),
+            ],
+        );
+        let transfers = wallet_transfers(&sent, &own).unwrap();
+        assert_eq!(transfers.len(), 1);
+        assert_eq!(
+            (
+                transfers[0].amount,
+                transfers[0].fee,
+                transfers[0].address.as_str(),
+                transfers[0].outgoing
+            ),
+            (dec!(0.5), dec!(0.0001), "bc1qown", true)
+        );
+        assert_eq!(
+            transfers[0].time,
+            "2024-03-01T12:00:00Z"
+                .parse::<chrono::DateTime<chrono::Utc>>()
+                .unwrap()
+        );
+
+        let received = transaction(
+            vec![output("bc1qexchange", 80_000_000)],
+            vec![
+                output("bc1qown", 30_000_000),
+                output("bc1qother", 49_990_000),
+            ],
+        );
+        let transfers = wallet_transfers(&received, &own).unwrap();
+        assert_eq!(
+            (transfers[0].amount, transfers[0].outgoing),
+            (dec!(0.3), false)
+        );
+
+        let internal = transaction(
+            vec![output("bc1qown", 1000)],
+            vec![output("bc1qchange", 900)],
+        );
+        assert!(wallet_transfers(&internal, &own).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,429 @@
+//! Bitstamp API client: the user transactions of the account, with its market
+//! trades, deposits and withdrawals.
+//!
+//! A user transaction has the amount of each currency it moved in a field
+//! named after the currency, e.g. `"btc": "-0.5"`, and a trade also has its
+//! price in a field named after its market, e.g. `"btc_usd": "60000"`.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use chrono::{NaiveDate, Utc};
+use hmac::{Hmac, Mac};
+use itertools::Itertools;
+use reqwest::Url;
+use reqwest::blocking::Client;
+use rust_decimal::Decimal;
+use serde_json::Value;
+use sha2::Sha256;
+
+use crate::api_keys::{ApiKeys, KeyLocation};
+use crate::endpoints;
+use crate::error::{Error, Result};
+use crate::fields::{self, date_time_field, decimal, decimal_field, id_field};
+use crate::http;
+use crate::i18n::t;
+use crate::report::transactions::ExchangeInfo;
+use crate::source::{
+    Activity, ExchangeSource, NormalizedDeposit, NormalizedTrade, NormalizedWithdrawal, Side,
+    millis_range,
+};
+
+/// Where the Bitstamp API keys are looked for: `bitstamp_keys.json`, or the
+/// `KRAKEN2RFB_BITSTAMP_KEYFILE`, `KRAKEN2RFB_BITSTAMP_API_KEY` and
+/// `KRAKEN2RFB_BITSTAMP_API_SECRET` environment variables
+pub const KEYS: KeyLocation = KeyLocation {
+    exchange: "Bitstamp",
+    file_name: "bitstamp_keys.json",
+    env_prefix: "BITSTAMP_",
+    profile_keyfile: false,
+};
+
+const USER_TRANSACTIONS: &str = "/api/v2/user_transactions/";
+
+/// Most transactions a page has
+const PAGE_LIMIT: usize = 1000;
+
+/// Fields of a user transaction that aren't the amount of a currency
+const NON_CURRENCY_FIELDS: [&str; 5] = ["id", "datetime", "type", "fee", "order_id"];
+
+/// Error codes by which Bitstamp rejects the API keys or the signature
+const AUTH_ERROR_CODES: [&str; 7] = [
+    "API0001", "API0002", "API0003", "API0004", "API0005", "API0008", "API0011",
+];
+
+/// Raw Bitstamp activity for a period: the user transactions, in the order
+/// they happened
+#[derive(Debug, Clone, Default)]
+pub struct BitstampActivity {
+    pub transactions: Vec<Value>,
+}
+
+/// Signature of a request: the HMAC-SHA256, with the API secret, of the API
+/// key, the method, the host, the path, the content type, the nonce, the
+/// timestamp, the version of the authentication and the body, in hexadecimal
+fn signature(secret: &str, message: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size");
+    mac.update(message.as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// A nonce of the 36 characters Bitstamp asks for, never repeated by this
+/// process
+fn nonce() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = Utc::now().timestamp_nanos_opt().unwrap_or_default();
+    format!(
+        "{:020}{:016}",
+        nanos,
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+fn bitstamp_error(endpoint: &str, body: &Value) -> Error {
+    let code = body["code"].as_str();
+    let reason = match &body["reason"] {
+        Value::String(reason) => reason.clone(),
+        Value::Null => body.to_string(),
+        reason => reason.to_string(),
+    };
+    Error::Exchange {
+        exchange: "Bitstamp".to_string(),
+        endpoint: endpoint.to_string(),
+        message: match code {
+            Some(code) => format!("{} ({})", reason, code),
+            None => reason,
+        },
+        auth: code.is_some_and(|code| AUTH_ERROR_CODES.contains(&code)),
+    }
+}
+
+/// The private API of an account
+struct Api {
+    client: &'static Client,
+    keys: ApiKeys,
+}
+
+impl Api {
+    fn new(keyfile: &Path) -> Result<Self> {
+        Ok(Api {
+            client: http::client()?,
+            keys: KEYS.load(keyfile)?,
+        })
+    }
+
+    /// Calls a private endpoint, e.g. "/api/v2/user_transactions/"
+    fn post(&self, path: &str, params: &[(&str, String)]) -> Result<Value> {
+        let url = format!("{}{}", endpoints::get().bitstamp, path);
+        let host = Url::parse(&url)
+            .ok()
+            .and_then(|url| {
+                let host = url.host_str()?.to_string();
+                Some(match url.port() {
+                    Some(port) => format!("{}:{}", host, port),
+                    None => host,
+                })
+            })
+            .ok_or_else(|| Error::Config(t!("invalid URL: {}", url)))?;
+        let body = serde_urlencoded::to_string(params).expect("the params are strings");
+        let content_type = "application/x-www-form-urlencoded";
+        let nonce = nonce();
+        let timestamp = Utc::now().timestamp_millis().to_string();
+        let auth = format!("BITSTAMP {}", self.keys.key);
+        let message = format!(
+            "{}POST{}{}{}{}{}v2{}",
+            auth, host, path, content_type, nonce, timestamp, body
+        );
+        let request = self
+            .client
+            .post(url)
+            .header("X-Auth", auth)
+            .header("X-Auth-Signature", signature(&self.keys.secret, &message))
+            .header("X-Auth-Nonce", nonce)
+            .header("X-Auth-Timestamp", timestamp)
+            .header("X-Auth-Version", "v2")
+            .header("Content-Type", content_type)
+            .body(body);
+        let response = http::send(request)?;
+        let success = response.status().is_success();
+        let body: Value = response
+            .json()
+            .map_err(|e| Error::Network(t!("invalid JSON from Bitstamp {}: {}", path, e)))?;
+        if !success || body["status"] == "error" {
+            return Err(bitstamp_error(path, &body));
+        }
+        Ok(body)
+    }
+}
+
+pub fn fetch_bitstamp_activity(
+    initial: NaiveDate,
+    final_: NaiveDate,
+    keyfile: &Path,
+) -> Result<BitstampActivity> {
+    let api = Api::new(keyfile)?;
+    // Bitstamp takes the times in seconds
+    let (start, end) = millis_range(initial, final_);
+    let (start, end) = (start / 1000, end / 1000);
+
+    let mut transactions = Vec::new();
+    for offset in (0..).step_by(PAGE_LIMIT) {
+        let page = api.post(
+            USER_TRANSACTIONS,
+            &[
+                ("offset", offset.to_string()),
+                ("limit", PAGE_LIMIT.to_string()),
+                ("sort", "asc".to_string()),
+                ("since_timestamp", start.to_string()),
+                ("until_timestamp", end.to_string()),
+            ],
+        )?;
+        let page = fields::list(&page, "Bitstamp", USER_TRANSACTIONS)?;
+        transactions.extend(page.iter().cloned());
+        if page.len() < PAGE_LIMIT {
+            break;
+        }
+    }
+    Ok(BitstampActivity { transactions })
+}
+
+/// The Bitstamp account the API keys give access to
+pub struct BitstampSource {
+    /// Key file given by the user, or `None` to look for it only when fetching
+    keyfile: Option<PathBuf>,
+}
+
+impl BitstampSource {
+    pub fn new(keyfile: Option<PathBuf>) -> Self {
+        BitstampSource { keyfile }
+    }
+}
+
+impl ExchangeSource for BitstampSource {
+    fn exchange(&self) -> ExchangeInfo {
+        ExchangeInfo {
+            name: "Bitstamp".to_string(),
+            url: "https://www.bitstamp.net".to_string(),
+            country: "LU".to_string(),
+        }
+    }
+
+    fn fetch(&self, first_day: NaiveDate, last_day: NaiveDate) -> Result<Activity> {
+        let keyfile = KEYS.find_keyfile(self.keyfile.as_deref())?;
+        normalize(fetch_bitstamp_activity(first_day, last_day, &keyfile)?)
+    }
+}
+
+/// The currencies a user transaction moved, with the amounts
+fn amounts(transaction: &Value) -> Result<Vec<(String, Decimal)>> {
+    let Some(fields) = transaction.as_object() else {
+        return Err(Error::Validation(t!(
+            "unexpected {} response for {}",
+            "Bitstamp",
+            USER_TRANSACTIONS
+        )));
+    };
+    let mut amounts = Vec::new();
+    for (name, value) in fields {
+        if NON_CURRENCY_FIELDS.contains(&name.as_str()) || name.contains('_') {
+            continue;
+        }
+        let amount = decimal(value, name)?;
+        if !amount.is_zero() {
+            amounts.push((name.to_uppercase(), amount));
+        }
+    }
+    Ok(amounts)
+}
+
+/// Converts the raw Bitstamp activity to the typed entries of [`Activity`].
+///
+/// Only deposits (type 0), withdrawals (type 1) and market trades (type 2)
+/// are taken; transfers between the accounts of the user and the staking
+/// entries move nothing in or out of the exchange. The fee of a trade is in
+/// its quote currency, and that of a withdrawal in the currency withdrawn.
+fn normalize(activity: BitstampActivity) -> Result<Activity> {
+    let mut result = Activity::default();
+    for transaction in &activity.transactions {
+        let kind = match &transaction["type"] {
+            Value::String(kind) => kind.clone(),
+            kind => kind.to_string(),
+        };
+        let time = date_time_field(transaction, "datetime", "%Y-%m-%d %H:%M:%S%.f")?;
+        let fee = match &transaction["fee"] {
+            Value::Null => Decimal::ZERO,
+            _ => decimal_field(transaction, "fee")?,
+        };
+        let single_amount = || -> Result<(String, Decimal)> {
+            amounts(transaction)?
+                .into_iter()
+                .exactly_one()
+                .map_err(|_| Error::Validation(t!("unexpected Bitstamp entry: {}", transaction)))
+        };
+        match kind.as_str() {
+            "0" => {
+                let (asset, amount) = single_amount()?;
+                result.deposits.push(NormalizedDeposit {
+                    asset,
+                    amount: amount.abs(),
+                    fee: Decimal::ZERO,
+                    time,
+                    ids: vec![id_field(transaction, "id")?],
+                    origin_exchange: None,
+                    origin_wallet: None,
+                    network: None,
+                });
+            }
+            "1" => {
+                let (asset, amount) = single_amount()?;
+                result.withdrawals.push(NormalizedWithdrawal {
+                    asset,
+                    amount: amount.abs(),
+                    fee,
+                    time,
+                    ids: vec![id_field(transaction, "id")?],
+                    destination: None,
+                    network: None,
+                });
+            }
+            "2" => {
+                let amounts = amounts(transaction)?;
+                let amount = |asset: &str| {
+                    amounts
+                        .iter()
+                        .find(|(name, _)| name.eq_ignore_ascii_case(asset))
+                        .map(|&(_, amount)| amount)
+                };
+                // The market is the price field whose both currencies moved
+                let market = transaction
+                    .as_object()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|(name, price)| {
+                        let (base, quote) = name.split_once('_')?;
+                        Some((base, quote, amount(base)?, amount(quote)?, price))
+                    })
+                    .exactly_one();
+                let Ok((base, quote, volume, cost, price)) = market else {
+                    return Err(Error::Validation(t!(
+                        "unexpected Bitstamp entry: {}",
+                        transaction
+                    )));
+                };
+                let mut ids = vec![id_field(transaction, "id")?];
+                ids.extend(id_field(transaction, "order_id").ok());
+                result.trades.push(NormalizedTrade {
+                    base: base.to_uppercase(),
+                    quote: quote.to_uppercase(),
+                    side: if volume.is_sign_positive() {
+                        Side::Buy
+                    } else {
+                        Side::Sell
+                    },
+                    volume: volume.abs(),
+                    cost: cost.abs(),
+                    fee,
+                    credit_fee_usd: Decimal::ZERO,
+                    price: decimal(price, "price")?,
+                    time,
+                    ids,
+                });
+            }
+            _ => {}
+        }
+    }
+    result.deposits.sort_by_key(|d| d.time);
+    result.withdrawals.sort_by_key(|w| w.time);
+    result.trades.sort_by_key(|t| t.time);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    use serde_json::json;
+
+    #[test]
+    fn test_signature() {
+        let message = "BITSTAMP keyPOSTwww.bitstamp.net/api/v2/user_transactions/application/x-www-form-urlencodedf93c979d-b00d-43a9-9b9c-fd4cd9547fa61567755304968v2offset=0&limit=1000";
+        assert_eq!(
+            signature("secret", message),
+            "c8048f0ad6f4f7444ea5c12a8defd2e3a83d66ef50591d3634a941e42946c396"
+        );
+        assert_eq!(nonce().len(), 36);
+        assert_ne!(nonce(), nonce());
+    }
+
+    #[test]
+    fn test_normalize() {
+        let activity = normalize(BitstampActivity {
+            transactions: vec![
+                json!({"id": 1001, "datetime": "2024-03-01 12:00:00.123456", "type": "0", "fee": "0.00000", "btc": "0.5", "usd": 0.0}),
+                json!({"id": 1002, "datetime": "2024-03-02 12:00:00", "type": "2", "fee": "1.50", "order_id": 77, "btc": "-0.1", "usd": "6000.00", "btc_usd": "60000", "eur": 0.0, "eth_btc": 0.0}),
+                json!({"id": 1003, "datetime": "2024-03-03 12:00:00.000000", "type": "2", "fee": "0.00005", "order_id": 78, "eth": "2", "btc": "-0.1", "eth_btc": "0.05"}),
+                json!({"id": 1004, "datetime": "2024-03-04 12:00:00.000000", "type": "1", "fee": "0.0001", "btc": "-0.2"}),
+                json!({"id": 1005, "datetime": "2024-03-05 12:00:00.000000", "type": "27", "eth": "0.001"}),
+            ],
+        })
+        .unwrap();
+
+        let deposit = &activity.deposits[0];
+        assert_eq!((deposit.asset.as_str(), deposit.amount), ("BTC", dec!(0.5)));
+        assert_eq!(deposit.time.to_string(), "2024-03-01 12:00:00 UTC");
+        assert_eq!(deposit.ids, ["1001"]);
+        let withdrawal = &activity.withdrawals[0];
+        assert_eq!(
+            (withdrawal.amount, withdrawal.fee),
+            (dec!(0.2), dec!(0.0001))
+        );
+
+        let [sell, buy] = activity.trades.as_slice() else {
+            panic!("expected 2 trades, got {:?}", activity.trades);
+        };
+        assert_eq!(
+            (sell.side, sell.base.as_str(), sell.quote.as_str()),
+            (Side::Sell, "BTC", "USD")
+        );
+        assert_eq!(
+            (sell.volume, sell.cost, sell.fee),
+            (dec!(0.1), dec!(6000), dec!(1.5))
+        );
+        assert_eq!(sell.price, dec!(60000));
+        assert_eq!(sell.ids, ["1002", "77"]);
+        assert_eq!(
+            (buy.side, buy.base.as_str(), buy.quote.as_str()),
+            (Side::Buy, "ETH", "BTC")
+        );
+        assert_eq!((buy.volume, buy.cost), (dec!(2), dec!(0.1)));
+
+        // A trade with no market moving both its currencies
+        let error = normalize(BitstampActivity {
+            transactions: vec![
+                json!({"id": 1006, "datetime": "2024-03-02 12:00:00", "type": "2", "fee": "0", "btc": "-0.1", "usd": "6000"}),
+            ],
+        });
+        assert!(error.is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = bitstamp_error(
+            USER_TRANSACTIONS,
+            &json!({"status": "error", "reason": "Invalid signature", "code": "API0005"}),
+        );
+        assert!(error.is_auth_error());
+        assert!(error.to_string().contains("(API0005)"));
+        let error = bitstamp_error(
+            USER_TRANSACTIONS,
+            &json!({"status": "error", "reason": {"__all__": ["Invalid limit."]}}),
+        );
+        assert!(!error.is_auth_error());
+        assert!(error.to_string().contains("Invalid limit."));
+    }
+}
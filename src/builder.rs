@@ -0,0 +1,660 @@
+//! A single entry point to the report pipeline for library users: the sources,
+//! period, rates, policies and outputs are composed with a [`ReportBuilder`],
+//! and [`ReportBuilder::run`] fetches, converts and writes.
+//! [`ReportBuilder::stream`] does the same batch by batch, for histories too
+//! long to hold in memory at once.
+//!
+//! ```
+//! use chrono::NaiveDate;
+//! use kraken2rfb::builder::ReportBuilder;
+//! use kraken2rfb::error::Result;
+//! use kraken2rfb::period::Period;
+//! use kraken2rfb::report::Rounding;
+//! use kraken2rfb::report::resolve::Resolved;
+//! use kraken2rfb::report::transactions::ExchangeInfo;
+//! use kraken2rfb::source::{Activity, ExchangeSource, NormalizedWithdrawal};
+//! use rust_decimal_macros::dec;
+//!
+//! struct Recorded;
+//!
+//! impl ExchangeSource for Recorded {
+//!     fn exchange(&self) -> ExchangeInfo {
+//!         ExchangeInfo {
+//!             name: "Kraken".to_string(),
+//!             url: "https://www.kraken.com".to_string(),
+//!             country: "US".to_string(),
+//!         }
+//!     }
+//!
+//!     fn fetch(&self, _: NaiveDate, _: NaiveDate) -> Result<Activity> {
+//!         Ok(Activity {
+//!             withdrawals: vec![NormalizedWithdrawal {
+//!                 asset: "BTC".to_string(),
+//!                 amount: dec!(0.5),
+//!                 fee: dec!(0.0001),
+//!                 time: "2024-03-01T12:00:00Z".parse().unwrap(),
+//!                 ids: Vec::new(),
+//!                 destination: None,
+//!                 network: None,
+//!             }],
+//!             ..Activity::default()
+//!         })
+//!     }
+//! }
+//!
+//! let mut resolved = Resolved::default();
+//! let date = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+//! resolved.rates.insert(("BTC".to_string(), date), dec!(300000.25));
+//!
+//! let mut report = Vec::new();
+//! ReportBuilder::new(Period::month(2024, 3)?)
+//!     .source(Recorded)
+//!     .resolver(resolved)
+//!     .rounding(Rounding::HalfUp)
+//!     .output(&mut report)
+//!     .run()?;
+//! assert_eq!(
+//!     String::from_utf8(report).unwrap(),
+//!     "0510|01032024|V|30,00|BTC|0,5000000000|Kraken|https://www.kraken.com|US\r\n"
+//! );
+//! # Ok::<(), kraken2rfb::error::Error>(())
+//! ```
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use rust_decimal::Decimal;
+
+use crate::checked;
+use crate::error::{Error, Result};
+use crate::i18n::t;
+use crate::period::Period;
+use crate::report::layout::Layout;
+use crate::report::precision::{AmountResidual, Truncation};
+use crate::report::residuals::Residual;
+use crate::report::resolve::{Providers, Resolver};
+use crate::report::stream::RecordSpool;
+use crate::report::transactions::{ExchangeInfo, Transaction};
+use crate::report::{
+    FeeCredits, FeePolicy, ProcessOptions, ProcessedData, Rounding, convert_activity,
+    dedup_activity, write_report,
+};
+use crate::source::{Activity, ExchangeSource, fetch_days, month_ranges};
+use crate::transfers::match_transfers;
+
+enum Output<'a> {
+    File(PathBuf),
+    Writer(Box<dyn Write + 'a>),
+}
+
+/// What [`ReportBuilder::stream`] wrote and left out. The transactions
+/// themselves are only in the outputs.
+#[derive(Debug, Default)]
+pub struct StreamedReport {
+    /// How many records were written of each record type
+    pub record_counts: BTreeMap<&'static str, usize>,
+    /// How many operations were left out because of the minimum value
+    pub below_min_value: usize,
+    /// Their total value in BRL
+    pub below_min_value_total: Decimal,
+    /// IDs of the entries left out because of `ProcessOptions::exclude_ids`
+    pub excluded_ids: Vec<String>,
+    /// IDs of the deposits written as 0410 records
+    pub deposit_ids: Vec<String>,
+    /// What rounding to cents left out of the purchases and sales
+    pub residuals: Vec<Residual>,
+    /// What rounding the crypto amounts left out
+    pub amount_residuals: Vec<AmountResidual>,
+}
+
+impl StreamedReport {
+    /// Encodes the records of a converted batch, and adds what it left out
+    fn add(&mut self, spool: &mut RecordSpool, processed: ProcessedData) -> Result<()> {
+        for transaction in &processed.transactions {
+            spool.push(transaction)?;
+            if let Transaction::TransferToExchange(deposit) = transaction {
+                self.deposit_ids.extend(deposit.deposit_id.clone());
+            }
+        }
+        self.below_min_value += processed.below_min_value.len();
+        let what = t!("the operations below the minimum value");
+        self.below_min_value_total =
+            checked::sum(processed.below_min_value.iter().map(|s| s.brl_value), &what)
+                .and_then(|total| checked::add(self.below_min_value_total, total, &what))?;
+        self.excluded_ids.extend(processed.excluded_ids);
+        self.residuals.extend(processed.residuals);
+        self.amount_residuals.extend(processed.amount_residuals);
+        Ok(())
+    }
+}
+
+/// The report of a period, from one or more sources
+pub struct ReportBuilder<'a> {
+    period: Period,
+    sources: Vec<Box<dyn ExchangeSource + 'a>>,
+    resolver: Option<Box<dyn Resolver + 'a>>,
+    options: ProcessOptions,
+    outputs: Vec<Output<'a>>,
+}
+
+impl<'a> ReportBuilder<'a> {
+    pub fn new(period: Period) -> Self {
+        ReportBuilder {
+            period,
+            sources: Vec::new(),
+            resolver: None,
+            options: ProcessOptions::default(),
+            outputs: Vec::new(),
+        }
+    }
+
+    /// Adds a source of activity. The records of all sources end up together
+    /// in the report.
+    pub fn source(mut self, source: impl ExchangeSource + 'a) -> Self {
+        self.sources.push(Box::new(source));
+        self
+    }
+
+    /// Where the rates and the answers come from, instead of the built-in rate
+    /// providers, which answer no question
+    pub fn resolver(mut self, resolver: impl Resolver + 'a) -> Self {
+        self.resolver = Some(Box::new(resolver));
+        self
+    }
+
+    /// Replaces all the processing options, including the policies and the
+    /// layout set so far
+    pub fn options(mut self, options: ProcessOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn rounding(mut self, rounding: Rounding) -> Self {
+        self.options.rounding = rounding;
+        self
+    }
+
+    pub fn fees(mut self, fees: FeePolicy) -> Self {
+        self.options.fees = fees;
+        self
+    }
+
+    /// What the fees paid with fee credits, like Kraken's KFEE, are worth,
+    /// their value in US dollars by default
+    pub fn fee_credits(mut self, fee_credits: FeeCredits) -> Self {
+        self.options.fee_credits = fee_credits;
+        self
+    }
+
+    /// Leaves out the operations worth less than `min_value` BRL
+    pub fn min_value(mut self, min_value: Decimal) -> Self {
+        self.options.min_value = Some(min_value);
+        self
+    }
+
+    /// The layout the report is written in, the latest one by default
+    pub fn layout(mut self, layout: Layout) -> Self {
+        self.options.layout = layout;
+        self
+    }
+
+    /// What is done with the crypto amounts with more decimals than the
+    /// layout writes, a warning by default
+    pub fn truncation(mut self, truncation: Truncation) -> Self {
+        self.options.truncation = truncation;
+        self
+    }
+
+    /// Writes the report to a file, created when the report is complete
+    pub fn output_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.outputs.push(Output::File(path.into()));
+        self
+    }
+
+    /// Writes the report to `writer`
+    pub fn output(mut self, writer: impl Write + 'a) -> Self {
+        self.outputs.push(Output::Writer(Box::new(writer)));
+        self
+    }
+
+    /// Fetches the activity of every source, converts it and writes the report
+    /// to every output, returning the transactions and what was left out
+    pub fn run(mut self) -> Result<ProcessedData> {
+        let mut resolver = self.checked_resolver()?;
+
+        // The sources of the same exchange, e.g. its API and a file exported
+        // from it, are converted together, so what both have is reported once
+        let mut activities: Vec<(ExchangeInfo, Activity)> = Vec::new();
+        for source in &self.sources {
+            let activity = fetch_days(
+                source.as_ref(),
+                self.period.first_day(),
+                self.period.last_day(),
+                self.options.offset(),
+            )?;
+            let exchange = source.exchange();
+            match activities.iter_mut().find(|(e, _)| e.name == exchange.name) {
+                Some((_, merged)) => merged.extend(activity),
+                None => activities.push((exchange, activity)),
+            }
+        }
+
+        // A withdrawal from one exchange that arrives on another is a transfer
+        // between the user's own accounts, so its deposit isn't asked about.
+        // The repeated entries go first, or each copy could match apart.
+        for (_, activity) in &mut activities {
+            *activity = dedup_activity(std::mem::take(activity), &self.options)?;
+        }
+        let internal_transfers = match_transfers(&mut activities);
+
+        let mut report = ProcessedData {
+            internal_transfers,
+            ..ProcessedData::default()
+        };
+        for (exchange, activity) in activities {
+            let processed = convert_activity(activity, &exchange, &self.options, &mut *resolver)?;
+            report.transactions.extend(processed.transactions);
+            report.below_min_value.extend(processed.below_min_value);
+            report.excluded_ids.extend(processed.excluded_ids);
+            report.residuals.extend(processed.residuals);
+            report.amount_residuals.extend(processed.amount_residuals);
+            report.income.extend(processed.income);
+            report.wallet_transfers.extend(processed.wallet_transfers);
+        }
+        report.transactions.sort_by_key(|t| t.record_type().0);
+
+        for output in self.outputs {
+            match output {
+                Output::File(path) => write_report(
+                    &report.transactions,
+                    self.options.layout,
+                    &mut BufWriter::new(File::create(path)?),
+                )?,
+                Output::Writer(mut writer) => {
+                    write_report(&report.transactions, self.options.layout, &mut writer)?
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    /// Like [`ReportBuilder::run`], but fetches and converts the activity of
+    /// each source month by month, in the batches of
+    /// [`ExchangeSource::fetch_batches`], and keeps only the encoded records
+    /// until they are written, so the memory used doesn't grow with the
+    /// history.
+    ///
+    /// The entries are deduplicated across the sources and batches of the
+    /// same exchange by their IDs, against those of the same month and the
+    /// one before, and the residuals are settled within each batch. The
+    /// transfers between exchanges aren't matched, since that takes the
+    /// whole activity of each, so their deposits are asked about as any other.
+    pub fn stream(mut self) -> Result<StreamedReport> {
+        let mut resolver = self.checked_resolver()?;
+        let (first_day, last_day) = (self.period.first_day(), self.period.last_day());
+
+        let mut spool = RecordSpool::new(self.options.layout)?;
+        let mut report = StreamedReport::default();
+        // The sources of the same exchange are deduplicated together, as run
+        // merges them
+        let mut exchanges: Vec<(ExchangeInfo, Vec<&dyn ExchangeSource>)> = Vec::new();
+        for source in &self.sources {
+            let exchange = source.exchange();
+            match exchanges.iter_mut().find(|(e, _)| e.name == exchange.name) {
+                Some((_, sources)) => sources.push(source.as_ref()),
+                None => exchanges.push((exchange, vec![source.as_ref()])),
+            }
+        }
+        for (exchange, sources) in &exchanges {
+            // The IDs of the entries of the previous month and of this one, so
+            // those kept in memory don't grow with the history
+            let (mut previous, mut seen) = (HashSet::new(), HashSet::new());
+            for (first, last) in month_ranges(first_day, last_day) {
+                // A day more on each side of the period, as in fetch_days
+                let first = if first == first_day {
+                    first.pred_opt().unwrap_or(first)
+                } else {
+                    first
+                };
+                let last = if last == last_day {
+                    last.succ_opt().unwrap_or(last)
+                } else {
+                    last
+                };
+                for source in sources {
+                    for batch in source.fetch_batches(first, last) {
+                        let batch = batch?.within_days(first_day, last_day, self.options.offset());
+                        let activity =
+                            dedup_activity(batch, &self.options)?.unseen(&previous, &mut seen);
+                        let processed =
+                            convert_activity(activity, exchange, &self.options, &mut *resolver)?;
+                        report.add(&mut spool, processed)?;
+                    }
+                }
+                previous = std::mem::take(&mut seen);
+            }
+        }
+        report.record_counts = spool.counts().clone();
+
+        for output in self.outputs {
+            match output {
+                Output::File(path) => spool.write_to(&mut BufWriter::new(File::create(path)?))?,
+                Output::Writer(mut writer) => spool.write_to(&mut writer)?,
+            }
+        }
+        Ok(report)
+    }
+
+    fn checked_resolver(&mut self) -> Result<Box<dyn Resolver + 'a>> {
+        if self.sources.is_empty() {
+            return Err(Error::Config(t!("the report has no source of activity")));
+        }
+        Ok(self
+            .resolver
+            .take()
+            .unwrap_or_else(|| Box::new(Providers::default())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::resolve::Resolved;
+    use crate::source::day_ranges;
+    use crate::source::{NormalizedDeposit, NormalizedTrade, NormalizedWithdrawal, Side};
+    use chrono::NaiveDate;
+    use rust_decimal_macros::dec;
+
+    struct Fixed(&'static str, Activity);
+
+    impl ExchangeSource for Fixed {
+        fn exchange(&self) -> ExchangeInfo {
+            ExchangeInfo {
+                name: self.0.to_string(),
+                url: String::new(),
+                country: "US".to_string(),
+            }
+        }
+
+        fn fetch(&self, _: NaiveDate, _: NaiveDate) -> Result<Activity> {
+            Ok(self.1.clone())
+        }
+    }
+
+    fn purchase() -> Activity {
+        Activity {
+            trades: vec![NormalizedTrade {
+                base: "BTC".to_string(),
+                quote: "USD".to_string(),
+                side: Side::Buy,
+                volume: dec!(0.1),
+                cost: dec!(1000.05),
+                fee: dec!(2),
+                credit_fee_usd: Decimal::ZERO,
+                price: dec!(10000.5),
+                time: "2024-03-01T12:00:00Z".parse().unwrap(),
+                ids: Vec::new(),
+            }],
+            ..Activity::default()
+        }
+    }
+
+    fn run(fees: FeePolicy, rounding: Rounding) -> Vec<Transaction> {
+        let mut resolved = Resolved::default();
+        let date = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        resolved.rates.insert(("USD".to_string(), date), dec!(5));
+        ReportBuilder::new(Period::month(2024, 3).unwrap())
+            .source(Fixed("A", purchase()))
+            .source(Fixed("B", purchase()))
+            .resolver(resolved)
+            .fees(fees)
+            .rounding(rounding)
+            .run()
+            .unwrap()
+            .transactions
+    }
+
+    #[test]
+    fn test_policies() {
+        let transactions = run(FeePolicy::Net, Rounding::HalfEven);
+        let Transaction::Purchase(net) = &transactions[0] else {
+            panic!("expected a purchase");
+        };
+        assert_eq!(transactions.len(), 2);
+        // 998,05 USD at 5 BRL is 4990,25
+        assert_eq!(net.operation_value, dec!(4990.25));
+        assert_eq!(net.buyer_exchange.name, "A");
+
+        let transactions = run(FeePolicy::Gross, Rounding::HalfUp);
+        let Transaction::Purchase(gross) = &transactions[0] else {
+            panic!("expected a purchase");
+        };
+        assert_eq!(gross.operation_value, dec!(5000.25));
+        assert_eq!(gross.base.crypto_amount, dec!(0.1));
+
+        assert!(matches!(
+            ReportBuilder::new(Period::month(2024, 3).unwrap()).run(),
+            Err(Error::Config(_))
+        ));
+    }
+
+    #[test]
+    fn test_fee_credits() {
+        // 1 USD of the fee paid with fee credits, and no quote asset
+        let mut activity = purchase();
+        activity.trades[0].fee = Decimal::ZERO;
+        activity.trades[0].credit_fee_usd = dec!(1);
+        let fees = |fee_credits| {
+            let mut resolved = Resolved::default();
+            let date = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+            resolved.rates.insert(("USD".to_string(), date), dec!(5));
+            let transactions = ReportBuilder::new(Period::month(2024, 3).unwrap())
+                .source(Fixed("Kraken", activity.clone()))
+                .resolver(resolved)
+                .fee_credits(fee_credits)
+                .run()
+                .unwrap()
+                .transactions;
+            let Transaction::Purchase(purchase) = &transactions[0] else {
+                panic!("expected a purchase");
+            };
+            // The amounts traded are whole either way
+            assert_eq!(purchase.operation_value, dec!(5000.25));
+            assert_eq!(purchase.base.crypto_amount, dec!(0.1));
+            purchase.base.operation_fees
+        };
+        assert_eq!(fees(FeeCredits::Value), Some(dec!(5)));
+        assert_eq!(fees(FeeCredits::Zero), Some(dec!(0)));
+    }
+
+    #[test]
+    fn test_internal_transfer() {
+        let time = "2024-03-01T12:00:00Z".parse().unwrap();
+        let withdrawal = Activity {
+            withdrawals: vec![NormalizedWithdrawal {
+                asset: "BTC".to_string(),
+                amount: dec!(0.5),
+                fee: dec!(0.0005),
+                time,
+                ids: vec!["W1".to_string()],
+                destination: None,
+                network: None,
+            }],
+            ..Activity::default()
+        };
+        let deposit = Activity {
+            deposits: vec![NormalizedDeposit {
+                asset: "BTC".to_string(),
+                amount: dec!(0.4995),
+                fee: dec!(0),
+                time: time + chrono::TimeDelta::minutes(30),
+                ids: vec!["D1".to_string()],
+                origin_exchange: None,
+                origin_wallet: None,
+                network: None,
+            }],
+            ..Activity::default()
+        };
+        let mut resolved = Resolved::default();
+        let date = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        resolved
+            .rates
+            .insert(("BTC".to_string(), date), dec!(300000));
+        // Answers the questions about the deposit, which mustn't be asked
+        resolved.answers.insert(
+            t!(
+                "{}: origin wallet (empty if unknown):",
+                t!("Deposit of {} {} on {}", dec!(0.4995), "BTC", date)
+            ),
+            Some("bc1q".to_string()),
+        );
+
+        let mut report = Vec::new();
+        let processed = ReportBuilder::new(Period::month(2024, 3).unwrap())
+            .source(Fixed("Binance", withdrawal))
+            .source(Fixed("Kraken", deposit))
+            .resolver(resolved)
+            .output(&mut report)
+            .run()
+            .unwrap();
+        assert_eq!(processed.internal_transfers.len(), 1);
+        assert_eq!(processed.internal_transfers[0].to, "Kraken");
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "0410|01032024|IV|0,00|BTC|0,4995000000||Binance\r\n\
+             0510|01032024|V|150,00|BTC|0,5000000000|Binance||US\r\n"
+        );
+    }
+
+    #[test]
+    fn test_deposit_fee() {
+        let deposit = Activity {
+            deposits: vec![NormalizedDeposit {
+                asset: "BTC".to_string(),
+                amount: dec!(0.5),
+                fee: dec!(0.0001),
+                time: "2024-03-01T12:00:00Z".parse().unwrap(),
+                ids: vec!["D1".to_string()],
+                origin_exchange: None,
+                origin_wallet: Some("bc1q".to_string()),
+                network: None,
+            }],
+            ..Activity::default()
+        };
+        let mut resolved = Resolved::default();
+        let date = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        resolved
+            .rates
+            .insert(("BTC".to_string(), date), dec!(300000));
+        let mut report = Vec::new();
+        ReportBuilder::new(Period::month(2024, 3).unwrap())
+            .source(Fixed("Kraken", deposit))
+            .resolver(resolved)
+            .output(&mut report)
+            .run()
+            .unwrap();
+        // The fee is in BRL, as the withdrawal ones
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "0410|01032024|IV|30,00|BTC|0,5000000000|bc1q|\r\n"
+        );
+    }
+
+    #[test]
+    fn test_stream() {
+        let mut purchase = purchase();
+        purchase.trades[0].ids = vec!["T1".to_string()];
+        let mut withdrawal = Activity {
+            withdrawals: vec![NormalizedWithdrawal {
+                asset: "BTC".to_string(),
+                amount: dec!(0.1),
+                fee: dec!(0),
+                time: "2024-03-02T12:00:00Z".parse().unwrap(),
+                ids: vec!["W1".to_string()],
+                destination: None,
+                network: None,
+            }],
+            ..Activity::default()
+        };
+        withdrawal.extend(purchase.clone());
+        let mut resolved = Resolved::default();
+        for day in [1, 2] {
+            let date = NaiveDate::from_ymd_opt(2024, 3, day).unwrap();
+            resolved.rates.insert(("USD".to_string(), date), dec!(5));
+            resolved
+                .rates
+                .insert(("BTC".to_string(), date), dec!(300000));
+        }
+
+        let report = |stream: bool| {
+            let mut report = Vec::new();
+            // Fetched once for each month of the quarter, and the purchase is
+            // in both sources of the exchange
+            let builder = ReportBuilder::new(Period::quarter(2024, 1).unwrap())
+                .source(Fixed("A", withdrawal.clone()))
+                .source(Fixed("A", purchase.clone()))
+                .resolver(resolved.clone())
+                .output(&mut report);
+            let counts = if stream {
+                builder.stream().unwrap().record_counts
+            } else {
+                builder.run().unwrap();
+                BTreeMap::new()
+            };
+            (String::from_utf8(report).unwrap(), counts)
+        };
+        let (streamed, counts) = report(true);
+        assert_eq!(counts, BTreeMap::from([("0110", 1), ("0510", 1)]));
+        assert_eq!(streamed, report(false).0);
+    }
+
+    /// A source fetching each week apart, as Kraken does
+    struct Weekly(Activity);
+
+    impl ExchangeSource for Weekly {
+        fn exchange(&self) -> ExchangeInfo {
+            Fixed("A", Activity::default()).exchange()
+        }
+
+        fn fetch(&self, first_day: NaiveDate, last_day: NaiveDate) -> Result<Activity> {
+            Ok(self.0.clone().within(first_day, last_day))
+        }
+
+        fn fetch_batches<'s>(
+            &'s self,
+            first_day: NaiveDate,
+            last_day: NaiveDate,
+        ) -> Box<dyn Iterator<Item = Result<Activity>> + 's> {
+            Box::new(
+                day_ranges(first_day, last_day, 7).map(|(first, last)| self.fetch(first, last)),
+            )
+        }
+    }
+
+    #[test]
+    fn test_stream_weekly() {
+        let mut purchase = purchase();
+        purchase.trades[0].ids = vec!["T1".to_string()];
+        let mut later = purchase.clone();
+        later.trades[0].ids = vec!["T2".to_string()];
+        later.trades[0].time = "2024-03-20T12:00:00Z".parse().unwrap();
+        later.extend(purchase.clone());
+        let mut resolved = Resolved::default();
+        for day in [1, 20] {
+            let date = NaiveDate::from_ymd_opt(2024, 3, day).unwrap();
+            resolved.rates.insert(("USD".to_string(), date), dec!(5));
+        }
+
+        // The purchase of March 1 is in the first week of one source and in
+        // the month of the other
+        let report = ReportBuilder::new(Period::month(2024, 3).unwrap())
+            .source(Weekly(later))
+            .source(Fixed("A", purchase))
+            .resolver(resolved)
+            .stream()
+            .unwrap();
+        assert_eq!(report.record_counts, BTreeMap::from([("0110", 2)]));
+    }
+}
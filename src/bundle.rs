@@ -0,0 +1,152 @@
+//! Portable bundle of what the report of a period is made from, to generate
+//! it on a machine without network access: the activity fetched from the
+//! exchange, the BRL rates its conversion needs and the configuration file,
+//! in a single JSON file.
+//!
+//! `bundle export` writes it on a machine that can reach the exchange and the
+//! rate providers; `bundle import` saves it as the checkpoint of the report
+//! file on the other one, so the report resumes from it without fetching
+//! anything. The answers of the user aren't bundled, they are asked there.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::checkpoint::Checkpoint;
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::i18n::t;
+use crate::period::Period;
+use crate::source::Activity;
+
+/// Version of the bundle format, increased whenever it changes meaning, so
+/// the bundles of another version aren't read
+const VERSION: u32 = 1;
+
+/// A BRL rate of the bundle
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BundledRate {
+    /// Common symbol of the asset, e.g. "BTC"
+    pub asset: String,
+    pub date: NaiveDate,
+    /// BRL price of 1 unit
+    #[serde(with = "rust_decimal::serde::str")]
+    pub rate: Decimal,
+}
+
+/// What the report of a period is made from
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Bundle {
+    pub version: u32,
+    /// Version of kraken2rfb that wrote the bundle
+    pub tool_version: String,
+    pub created_at: DateTime<Utc>,
+    pub period: Period,
+    /// Name of the exchange of the activity, as given with `--exchange`
+    pub exchange: String,
+    pub activity: Activity,
+    pub rates: Vec<BundledRate>,
+    /// The configuration file of the machine that wrote the bundle
+    pub config: Config,
+}
+
+impl Bundle {
+    /// A bundle of the current version, written now
+    pub fn new(
+        period: Period,
+        exchange: &str,
+        activity: Activity,
+        rates: Vec<BundledRate>,
+        config: Config,
+    ) -> Self {
+        Bundle {
+            version: VERSION,
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            created_at: Utc::now(),
+            period,
+            exchange: exchange.to_string(),
+            activity,
+            rates,
+            config,
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let content = serde_json::to_string_pretty(self)
+            .map_err(|e| Error::Validation(t!("failed to serialize the bundle: {}", e)))?;
+        fs::write(path, content + "\n")?;
+        Ok(())
+    }
+
+    /// Reads a bundle, failing if it is of another version
+    pub fn read(path: &Path) -> Result<Self> {
+        let invalid = |e: serde_json::Error| {
+            Error::Config(t!("invalid bundle file {}: {}", path.display(), e))
+        };
+        let content = fs::read_to_string(path)?;
+        let version: serde_json::Value = serde_json::from_str(&content).map_err(invalid)?;
+        if version["version"].as_u64() != Some(VERSION.into()) {
+            return Err(Error::Config(t!(
+                "the bundle file {} is of another version of kraken2rfb",
+                path.display()
+            )));
+        }
+        serde_json::from_str(&content).map_err(invalid)
+    }
+
+    /// Saves the activity and the rates as a new checkpoint at `path`, which
+    /// the report of the period resumes from
+    pub fn save_checkpoint(&self, path: PathBuf) -> Result<Checkpoint> {
+        let mut checkpoint = Checkpoint::restart(path, self.period)?;
+        checkpoint.set_activity(self.activity.clone())?;
+        checkpoint.set_rates(
+            self.rates
+                .iter()
+                .map(|rate| (rate.asset.as_str(), rate.date, rate.rate)),
+        )?;
+        Ok(checkpoint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_bundle() {
+        let dir =
+            std::env::temp_dir().join(format!("kraken2rfb-test-{}-bundle", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let period = Period::month(2024, 3).unwrap();
+        let date = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        let bundle = Bundle::new(
+            period,
+            "kraken",
+            Activity::default(),
+            vec![BundledRate {
+                asset: "BTC".to_string(),
+                date,
+                rate: dec!(350000.12),
+            }],
+            Config::default(),
+        );
+        let path = dir.join("bundle.json");
+        bundle.write(&path).unwrap();
+        let read = Bundle::read(&path).unwrap();
+        assert_eq!(read, bundle);
+
+        let checkpoint = read
+            .save_checkpoint(dir.join("report.txt.checkpoint.json"))
+            .unwrap();
+        assert_eq!(checkpoint.rate("BTC", date), Some(dec!(350000.12)));
+        assert!(checkpoint.activity().is_some());
+
+        fs::write(&path, r#"{"version": 0}"#).unwrap();
+        assert!(matches!(Bundle::read(&path), Err(Error::Config(_))));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,512 @@
+//! Bybit API client, v5: the spot trades, deposits and withdrawals of a
+//! unified trading account.
+//!
+//! Every list of the v5 API is paged by a cursor and limited to a time range
+//! (7 days for the trades, 30 for the transfers), so a period is fetched in
+//! windows and each window page by page. Bybit counts the requests of each
+//! endpoint per second and tells how many are left in the response headers;
+//! when none are, the client waits for the limit to reset instead of being
+//! rejected.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use chrono::{NaiveDate, Utc};
+use hmac::{Hmac, Mac};
+use itertools::Itertools;
+use reqwest::blocking::Client;
+use reqwest::header::HeaderMap;
+use rust_decimal::Decimal;
+use serde_json::Value;
+use sha2::Sha256;
+
+use crate::api_keys::{ApiKeys, KeyLocation};
+use crate::endpoints;
+use crate::error::{Error, Result};
+use crate::fields::{
+    self, decimal_field, id_field, int_field, millis_field, optional_str_field, str_field,
+};
+use crate::http;
+use crate::i18n::t;
+use crate::report::transactions::ExchangeInfo;
+use crate::source::{
+    Activity, ExchangeSource, NormalizedDeposit, NormalizedTrade, NormalizedWithdrawal, Side,
+    millis_range, millis_windows,
+};
+
+/// Where the Bybit API keys are looked for: `bybit_keys.json`, or the
+/// `KRAKEN2RFB_BYBIT_KEYFILE`, `KRAKEN2RFB_BYBIT_API_KEY` and
+/// `KRAKEN2RFB_BYBIT_API_SECRET` environment variables
+pub const KEYS: KeyLocation = KeyLocation {
+    exchange: "Bybit",
+    file_name: "bybit_keys.json",
+    env_prefix: "BYBIT_",
+    profile_keyfile: false,
+};
+
+/// Milliseconds a signed request is valid for after its timestamp
+const RECV_WINDOW: u64 = 10_000;
+
+/// Longest time range of a request to the trade history, in days
+const TRADE_WINDOW_DAYS: i64 = 7;
+
+/// Longest time range of a request to the deposit and withdrawal history, in
+/// days
+const TRANSFER_WINDOW_DAYS: i64 = 30;
+
+/// Most trades a page has
+const TRADE_PAGE_LIMIT: usize = 100;
+
+/// Most deposits or withdrawals a page has
+const TRANSFER_PAGE_LIMIT: usize = 50;
+
+/// Return code of a request rejected for exceeding the rate limit
+const RATE_LIMIT_CODE: i64 = 10006;
+
+/// Return codes by which Bybit rejects the API keys
+const AUTH_ERROR_CODES: [i64; 6] = [10003, 10004, 10005, 10007, 10010, 33004];
+
+/// Raw Bybit activity for a period. Each trade has the `baseCoin` and
+/// `quoteCoin` of its symbol added.
+#[derive(Debug, Clone, Default)]
+pub struct BybitActivity {
+    pub deposits: Vec<Value>,
+    pub withdrawals: Vec<Value>,
+    pub trades: Vec<Value>,
+}
+
+/// Signature of a request: the HMAC-SHA256, with the API secret, of the
+/// timestamp, the API key, the receive window and the query, in hexadecimal
+fn signature(secret: &str, message: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size");
+    mac.update(message.as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn bybit_error(endpoint: &str, body: &Value) -> Error {
+    let code = body["retCode"].as_i64();
+    Error::Exchange {
+        exchange: "Bybit".to_string(),
+        endpoint: endpoint.to_string(),
+        message: match (code, body["retMsg"].as_str()) {
+            (Some(code), Some(msg)) => format!("{} ({})", msg, code),
+            _ => body.to_string(),
+        },
+        auth: code.is_some_and(|code| AUTH_ERROR_CODES.contains(&code)),
+    }
+}
+
+/// How long to wait for the rate limit to reset, by the headers of a response:
+/// `X-Bapi-Limit-Status` has how many requests are left, and
+/// `X-Bapi-Limit-Reset-Timestamp` when they are renewed, in UNIX
+/// milliseconds. `None` if there are requests left, or no such headers.
+fn rate_limit_wait(headers: &HeaderMap, now_millis: i64, exhausted: bool) -> Option<Duration> {
+    let header = |name: &str| -> Option<i64> { headers.get(name)?.to_str().ok()?.parse().ok() };
+    if !exhausted && header("X-Bapi-Limit-Status")? > 0 {
+        return None;
+    }
+    let reset = header("X-Bapi-Limit-Reset-Timestamp")?;
+    // A reset far ahead is a ban, better reported than waited for
+    let wait = (reset - now_millis).clamp(0, 60_000);
+    Some(Duration::from_millis(wait as u64))
+}
+
+/// The signed API of an account
+struct Api {
+    client: &'static Client,
+    keys: ApiKeys,
+}
+
+impl Api {
+    fn new(keyfile: &Path) -> Result<Self> {
+        Ok(Api {
+            client: http::client()?,
+            keys: KEYS.load(keyfile)?,
+        })
+    }
+
+    /// Calls an endpoint, e.g. "/v5/execution/list", and returns its `result`
+    fn get(&self, path: &str, params: &[(&str, String)], signed: bool) -> Result<Value> {
+        let query = serde_urlencoded::to_string(params).expect("the params are strings");
+        let mut attempt = 0;
+        loop {
+            let mut request =
+                self.client
+                    .get(format!("{}{}?{}", endpoints::get().bybit, path, query));
+            if signed {
+                let timestamp = Utc::now().timestamp_millis().to_string();
+                let message = format!("{}{}{}{}", timestamp, self.keys.key, RECV_WINDOW, query);
+                request = request
+                    .header("X-BAPI-API-KEY", &self.keys.key)
+                    .header("X-BAPI-TIMESTAMP", timestamp)
+                    .header("X-BAPI-RECV-WINDOW", RECV_WINDOW.to_string())
+                    .header("X-BAPI-SIGN", signature(&self.keys.secret, &message));
+            }
+            let response = http::send(request)?;
+            let success = response.status().is_success();
+            let headers = response.headers().clone();
+            let body: Value = response
+                .json()
+                .map_err(|e| Error::Network(t!("invalid JSON from Bybit {}: {}", path, e)))?;
+            let code = body["retCode"].as_i64();
+            let now = Utc::now().timestamp_millis();
+            if code == Some(RATE_LIMIT_CODE) && attempt < http::RATE_LIMIT_RETRIES {
+                let wait = rate_limit_wait(&headers, now, true);
+                thread::sleep(wait.unwrap_or(Duration::from_secs(2 << attempt)));
+                attempt += 1;
+                continue;
+            }
+            if !success || code != Some(0) {
+                return Err(bybit_error(path, &body));
+            }
+            if let Some(wait) = rate_limit_wait(&headers, now, false) {
+                thread::sleep(wait);
+            }
+            return Ok(body["result"].clone());
+        }
+    }
+
+    /// Pages through a list by its cursor, in windows of `days` days
+    fn paged(
+        &self,
+        path: &str,
+        params: &[(&str, String)],
+        (start, end): (i64, i64),
+        days: i64,
+        limit: usize,
+    ) -> Result<Vec<Value>> {
+        let mut entries = Vec::new();
+        for (from, to) in millis_windows(start, end, days) {
+            let mut cursor = String::new();
+            loop {
+                let mut page_params = params.to_vec();
+                page_params.extend([
+                    ("startTime", from.to_string()),
+                    ("endTime", to.to_string()),
+                    ("limit", limit.to_string()),
+                ]);
+                if !cursor.is_empty() {
+                    page_params.push(("cursor", cursor.clone()));
+                }
+                let result = self.get(path, &page_params, true)?;
+                // The transfer lists are in `rows`, the others in `list`
+                let page = match &result["rows"] {
+                    Value::Null => &result["list"],
+                    rows => rows,
+                };
+                let page = list(page, path)?;
+                entries.extend(page.iter().cloned());
+                match result["nextPageCursor"].as_str() {
+                    Some(next) if !next.is_empty() && !page.is_empty() => cursor = next.to_string(),
+                    _ => break,
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// The base and quote coins of every spot symbol, by symbol
+    fn symbols(&self) -> Result<HashMap<String, (String, String)>> {
+        let path = "/v5/market/instruments-info";
+        let info = self.get(path, &[("category", "spot".to_string())], false)?;
+        list(&info["list"], path)?
+            .iter()
+            .map(|symbol| {
+                Ok((
+                    str_field(symbol, "symbol")?.to_string(),
+                    (
+                        str_field(symbol, "baseCoin")?.to_string(),
+                        str_field(symbol, "quoteCoin")?.to_string(),
+                    ),
+                ))
+            })
+            .collect()
+    }
+}
+
+pub fn fetch_bybit_activity(
+    initial: NaiveDate,
+    final_: NaiveDate,
+    keyfile: &Path,
+) -> Result<BybitActivity> {
+    let api = Api::new(keyfile)?;
+    let range = millis_range(initial, final_);
+
+    let deposits = api.paged(
+        "/v5/asset/deposit/query-record",
+        &[],
+        range,
+        TRANSFER_WINDOW_DAYS,
+        TRANSFER_PAGE_LIMIT,
+    )?;
+    let withdrawals = api.paged(
+        "/v5/asset/withdraw/query-record",
+        &[],
+        range,
+        TRANSFER_WINDOW_DAYS,
+        TRANSFER_PAGE_LIMIT,
+    )?;
+    let executions = api.paged(
+        "/v5/execution/list",
+        &[("category", "spot".to_string())],
+        range,
+        TRADE_WINDOW_DAYS,
+        TRADE_PAGE_LIMIT,
+    )?;
+
+    let mut trades = Vec::new();
+    if !executions.is_empty() {
+        let symbols = api.symbols()?;
+        for mut trade in executions {
+            let symbol = str_field(&trade, "symbol")?;
+            let Some((base, quote)) = symbols.get(symbol) else {
+                return Err(Error::UnknownPair(symbol.to_string()));
+            };
+            trade["baseCoin"] = Value::from(base.as_str());
+            trade["quoteCoin"] = Value::from(quote.as_str());
+            trades.push(trade);
+        }
+    }
+
+    Ok(BybitActivity {
+        deposits,
+        withdrawals,
+        trades,
+    })
+}
+
+/// The Bybit account the API keys give access to
+pub struct BybitSource {
+    /// Key file given by the user, or `None` to look for it only when fetching
+    keyfile: Option<PathBuf>,
+}
+
+impl BybitSource {
+    pub fn new(keyfile: Option<PathBuf>) -> Self {
+        BybitSource { keyfile }
+    }
+}
+
+impl ExchangeSource for BybitSource {
+    fn exchange(&self) -> ExchangeInfo {
+        ExchangeInfo {
+            name: "Bybit".to_string(),
+            url: "https://www.bybit.com".to_string(),
+            country: "AE".to_string(),
+        }
+    }
+
+    fn fetch(&self, first_day: NaiveDate, last_day: NaiveDate) -> Result<Activity> {
+        let keyfile = KEYS.find_keyfile(self.keyfile.as_deref())?;
+        normalize(fetch_bybit_activity(first_day, last_day, &keyfile)?)
+    }
+}
+
+/// Converts the raw Bybit activity to the typed entries of [`Activity`].
+///
+/// The fee of a spot trade is charged in the coin received, the base coin of
+/// a buy or the quote coin of a sell, unless the trade says otherwise in its
+/// `feeCurrency`; a fee in the base coin is valued at the price of the trade.
+fn normalize(activity: BybitActivity) -> Result<Activity> {
+    let transfer_ids = |entry: &Value, own: &str| -> Vec<String> {
+        [own, "txID"]
+            .iter()
+            .filter_map(|field| id_field(entry, field).ok())
+            .collect()
+    };
+    // Deposits are credited with status 3; withdrawals are completed with
+    // status "success"
+    let deposits = activity
+        .deposits
+        .iter()
+        .filter(|d| int_field(d, "status").ok() == Some(3))
+        .map(|deposit| -> Result<NormalizedDeposit> {
+            Ok(NormalizedDeposit {
+                asset: str_field(deposit, "coin")?.to_string(),
+                amount: decimal_field(deposit, "amount")?,
+                fee: match &deposit["depositFee"] {
+                    Value::String(fee) if fee.is_empty() => Decimal::ZERO,
+                    Value::Null => Decimal::ZERO,
+                    _ => decimal_field(deposit, "depositFee")?,
+                },
+                time: millis_field(deposit, "successAt")?,
+                ids: transfer_ids(deposit, "id"),
+                origin_exchange: None,
+                origin_wallet: None,
+                network: None,
+            })
+        });
+    let withdrawals = activity
+        .withdrawals
+        .iter()
+        .filter(|w| w["status"] == "success")
+        .map(|withdrawal| -> Result<NormalizedWithdrawal> {
+            Ok(NormalizedWithdrawal {
+                asset: str_field(withdrawal, "coin")?.to_string(),
+                amount: decimal_field(withdrawal, "amount")?,
+                fee: decimal_field(withdrawal, "withdrawFee")?,
+                time: millis_field(withdrawal, "createTime")?,
+                ids: transfer_ids(withdrawal, "withdrawId"),
+                destination: optional_str_field(withdrawal, "toAddress"),
+                network: None,
+            })
+        });
+    let trade = |trade: &Value| -> Result<NormalizedTrade> {
+        let (base, quote) = (
+            str_field(trade, "baseCoin")?,
+            str_field(trade, "quoteCoin")?,
+        );
+        let side = match str_field(trade, "side")? {
+            "Buy" => Side::Buy,
+            "Sell" => Side::Sell,
+            _ => return Err(Error::Validation(t!("unexpected Bybit entry: {}", trade))),
+        };
+        let price = decimal_field(trade, "execPrice")?;
+        let fee = decimal_field(trade, "execFee")?.abs();
+        let fee_currency = match trade["feeCurrency"].as_str() {
+            Some(currency) if !currency.is_empty() => currency,
+            _ if side == Side::Buy => base,
+            _ => quote,
+        };
+        let fee = match fee_currency {
+            _ if fee.is_zero() => Decimal::ZERO,
+            currency if currency == quote => fee,
+            currency if currency == base => fee * price,
+            _ => return Err(Error::Validation(t!("unexpected Bybit entry: {}", trade))),
+        };
+        Ok(NormalizedTrade {
+            base: base.to_string(),
+            quote: quote.to_string(),
+            side,
+            volume: decimal_field(trade, "execQty")?,
+            cost: decimal_field(trade, "execValue")?,
+            fee,
+            credit_fee_usd: Decimal::ZERO,
+            price,
+            time: millis_field(trade, "execTime")?,
+            ids: vec![id_field(trade, "execId")?, id_field(trade, "orderId")?],
+        })
+    };
+
+    let mut trades: Vec<NormalizedTrade> = activity
+        .trades
+        .iter()
+        .filter(|t| t["execType"].as_str().is_none_or(|kind| kind == "Trade"))
+        .map(trade)
+        .try_collect()?;
+    trades.sort_by_key(|t| t.time);
+    let mut deposits: Vec<NormalizedDeposit> = deposits.try_collect()?;
+    deposits.sort_by_key(|d| d.time);
+    let mut withdrawals: Vec<NormalizedWithdrawal> = withdrawals.try_collect()?;
+    withdrawals.sort_by_key(|w| w.time);
+    Ok(Activity {
+        deposits,
+        withdrawals,
+        trades,
+        income: Vec::new(),
+        wallet_transfers: Vec::new(),
+    })
+}
+
+/// A JSON list in a Bybit response
+fn list<'a>(value: &'a Value, endpoint: &str) -> Result<&'a Vec<Value>> {
+    fields::list(value, "Bybit", endpoint)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    use serde_json::json;
+
+    #[test]
+    fn test_signature() {
+        assert_eq!(
+            signature("secret", "1709294400000key10000category=spot&limit=100"),
+            "0941d9a78eab3742fb13b569b1e6abbbfc538a2a92bb5575a9946390b7be81f6"
+        );
+    }
+
+    #[test]
+    fn test_rate_limit_wait() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(rate_limit_wait(&headers, 1000, false), None);
+        headers.insert("X-Bapi-Limit-Status", "3".parse().unwrap());
+        headers.insert("X-Bapi-Limit-Reset-Timestamp", "1400".parse().unwrap());
+        assert_eq!(rate_limit_wait(&headers, 1000, false), None);
+        assert_eq!(
+            rate_limit_wait(&headers, 1000, true),
+            Some(Duration::from_millis(400))
+        );
+        headers.insert("X-Bapi-Limit-Status", "0".parse().unwrap());
+        assert_eq!(
+            rate_limit_wait(&headers, 1000, false),
+            Some(Duration::from_millis(400))
+        );
+        // Already reset
+        assert_eq!(rate_limit_wait(&headers, 2000, false), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_normalize() {
+        let activity = normalize(BybitActivity {
+            deposits: vec![
+                json!({"id": "1001", "coin": "USDT", "amount": "100", "txID": "0xa1", "status": 3, "depositFee": "", "successAt": "1709294400000"}),
+                json!({"id": "1002", "coin": "USDT", "amount": "50", "txID": "0xa2", "status": 1, "depositFee": "", "successAt": "1709294400000"}),
+            ],
+            withdrawals: vec![
+                json!({"withdrawId": "2001", "coin": "BTC", "amount": "0.1", "txID": "", "status": "success", "withdrawFee": "0.0002", "createTime": "1709380800000"}),
+            ],
+            trades: vec![
+                json!({"symbol": "BTCUSDT", "baseCoin": "BTC", "quoteCoin": "USDT", "orderId": "o1", "execId": "e1", "side": "Buy", "execPrice": "60000", "execQty": "0.01", "execValue": "600", "execFee": "0.00001", "execType": "Trade", "execTime": "1709467200000"}),
+                json!({"symbol": "BTCUSDT", "baseCoin": "BTC", "quoteCoin": "USDT", "orderId": "o2", "execId": "e2", "side": "Sell", "execPrice": "61000", "execQty": "0.01", "execValue": "610", "execFee": "0.61", "feeCurrency": "USDT", "execType": "Trade", "execTime": "1709553600000"}),
+            ],
+        })
+        .unwrap();
+
+        // The deposit not yet credited is left out
+        assert_eq!(activity.deposits.len(), 1);
+        assert_eq!(activity.deposits[0].ids, ["1001", "0xa1"]);
+        assert_eq!(activity.deposits[0].fee, Decimal::ZERO);
+        let withdrawal = &activity.withdrawals[0];
+        assert_eq!(
+            (withdrawal.amount, withdrawal.fee),
+            (dec!(0.1), dec!(0.0002))
+        );
+        assert_eq!(withdrawal.ids, ["2001"]);
+        assert_eq!(withdrawal.time.timestamp(), 1709380800);
+
+        let [buy, sell] = activity.trades.as_slice() else {
+            panic!("expected 2 trades, got {:?}", activity.trades);
+        };
+        // The fee of a buy is in the base coin
+        assert_eq!((buy.side, buy.fee), (Side::Buy, dec!(0.6)));
+        assert_eq!(buy.ids, ["e1", "o1"]);
+        assert_eq!(
+            (sell.side, sell.fee, sell.cost),
+            (Side::Sell, dec!(0.61), dec!(610))
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = bybit_error(
+            "/v5/execution/list",
+            &json!({"retCode": 10003, "retMsg": "API key is invalid.", "result": {}}),
+        );
+        assert!(error.is_auth_error());
+        assert!(error.to_string().contains("(10003)"));
+        let error = bybit_error(
+            "/v5/execution/list",
+            &json!({"retCode": 10001, "retMsg": "params error", "result": {}}),
+        );
+        assert!(!error.is_auth_error());
+    }
+}
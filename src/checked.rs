@@ -0,0 +1,122 @@
+//! Arithmetic on amounts, rates and BRL values that fails instead of
+//! panicking or rounding away what the report writes. A [`Decimal`] holds 96
+//! bits, about 28 digits: a large amount times a rate with many decimals
+//! overflows it, or keeps fewer decimals than the amounts are written with.
+//! The errors name the entry being computed, given as `what`, e.g. "trade
+//! TZX2WP-XSEOP-CEWAIE".
+
+use rust_decimal::Decimal;
+
+use crate::error::{Error, Result};
+use crate::i18n::t;
+
+/// Decimals that must survive an operation when its operands have them: the
+/// most the report writes, those of the amounts of the sales
+const MIN_SCALE: u32 = 12;
+
+fn fail(expression: String, what: &str) -> Error {
+    Error::Validation(t!(
+        "{} can't be computed without losing precision, in {}",
+        expression,
+        what
+    ))
+}
+
+/// Fails if `result` was rounded to fewer decimals than the exact result has,
+/// up to [`MIN_SCALE`]. A zero, which has none, is always exact.
+fn exact(
+    result: Option<Decimal>,
+    exact_scale: u32,
+    expression: impl Fn() -> String,
+    what: &str,
+) -> Result<Decimal> {
+    match result {
+        Some(result) if result.is_zero() || result.scale() >= exact_scale.min(MIN_SCALE) => {
+            Ok(result)
+        }
+        _ => Err(fail(expression(), what)),
+    }
+}
+
+/// `a * b`, e.g. an amount by its BRL rate
+pub fn mul(a: Decimal, b: Decimal, what: &str) -> Result<Decimal> {
+    exact(
+        a.checked_mul(b),
+        a.scale() + b.scale(),
+        || format!("{} × {}", a, b),
+        what,
+    )
+}
+
+/// `a + b`
+pub fn add(a: Decimal, b: Decimal, what: &str) -> Result<Decimal> {
+    exact(
+        a.checked_add(b),
+        a.scale().max(b.scale()),
+        || format!("{} + {}", a, b),
+        what,
+    )
+}
+
+/// `a - b`
+pub fn sub(a: Decimal, b: Decimal, what: &str) -> Result<Decimal> {
+    exact(
+        a.checked_sub(b),
+        a.scale().max(b.scale()),
+        || format!("{} - {}", a, b),
+        what,
+    )
+}
+
+/// `a / b`, which is rounded to as many decimals as fit, like any division,
+/// but fails on overflow or on a zero `b`
+pub fn div(a: Decimal, b: Decimal, what: &str) -> Result<Decimal> {
+    a.checked_div(b)
+        .ok_or_else(|| fail(format!("{} / {}", a, b), what))
+}
+
+/// The sum of `values`
+pub fn sum(values: impl IntoIterator<Item = Decimal>, what: &str) -> Result<Decimal> {
+    values
+        .into_iter()
+        .try_fold(Decimal::ZERO, |total, value| add(total, value, what))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_checked() {
+        assert_eq!(
+            mul(dec!(0.5), dec!(350000.12), "x").unwrap(),
+            dec!(175000.060)
+        );
+        assert_eq!(
+            mul(dec!(0.123456789012), dec!(5.4321), "x").unwrap(),
+            dec!(0.6706296235920852)
+        );
+        // Overflows the 96 bits
+        assert!(matches!(
+            mul(Decimal::MAX, dec!(2), "trade T1"),
+            Err(Error::Validation(message)) if message.contains("trade T1")
+        ));
+        // Fits only by rounding away the decimals written in the report
+        assert!(
+            mul(
+                dec!(123456789012345678.123456789),
+                dec!(1000.123456789),
+                "x"
+            )
+            .is_err()
+        );
+        assert_eq!(mul(dec!(0), dec!(0.5), "x").unwrap(), dec!(0));
+        assert!(add(Decimal::MAX, dec!(1), "x").is_err());
+        assert_eq!(sub(dec!(5000), dec!(8.5), "x").unwrap(), dec!(4991.5));
+        assert!(div(dec!(1), Decimal::ZERO, "x").is_err());
+        assert_eq!(div(dec!(8), dec!(50000), "x").unwrap(), dec!(0.00016));
+        assert_eq!(sum([dec!(1.5), dec!(2.25)], "x").unwrap(), dec!(3.75));
+        assert!(sum([Decimal::MAX, Decimal::MAX], "x").is_err());
+    }
+}
@@ -0,0 +1,262 @@
+//! Saved progress of a report run, to resume it after an interruption.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::i18n::t;
+use crate::period::Period;
+use crate::source::Activity;
+
+/// Version of the checkpoint format, increased whenever the saved activity
+/// changes meaning, so checkpoints of older versions are not resumed
+const VERSION: u32 = 2;
+
+/// Only the version of a checkpoint, read before the rest, whose format
+/// depends on it
+#[derive(Deserialize)]
+struct CheckpointVersion {
+    #[serde(default)]
+    version: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CheckpointData {
+    /// Missing from the checkpoints saved before it was introduced
+    #[serde(default)]
+    version: u32,
+    #[serde(flatten)]
+    period: Period,
+    activity: Option<Activity>,
+    /// BRL rates already resolved, keyed by "<asset> <date>"
+    rates: BTreeMap<String, Decimal>,
+    /// Answers already given by the user, keyed by question
+    answers: BTreeMap<String, Option<String>>,
+}
+
+/// Progress of a report run, saved to disk after every step so that an
+/// interrupted run (e.g. by a price provider outage) can be resumed without
+/// fetching the same data, looking up the same rates or asking the same
+/// questions again. Since processing is deterministic given these inputs,
+/// the resumed run produces the same transactions.
+pub struct Checkpoint {
+    path: PathBuf,
+    data: CheckpointData,
+}
+
+impl Checkpoint {
+    /// Path of the checkpoint file of a report file
+    pub fn path_for(report_file: &Path) -> PathBuf {
+        let mut name = report_file.as_os_str().to_owned();
+        name.push(".checkpoint.json");
+        PathBuf::from(name)
+    }
+
+    /// Opens the checkpoint at `path`, resuming it if it exists and is for the
+    /// same period, or starting a new one otherwise.
+    pub fn open(path: PathBuf, period: Period) -> Result<Self> {
+        let fresh = CheckpointData {
+            version: VERSION,
+            period,
+            activity: None,
+            rates: BTreeMap::new(),
+            answers: BTreeMap::new(),
+        };
+
+        let data = match fs::read_to_string(&path) {
+            Ok(content) => {
+                let corrupted = |e: serde_json::Error| {
+                    Error::Config(t!(
+                        "corrupted checkpoint file {} ({}), delete it or use --restart",
+                        path.display(),
+                        e
+                    ))
+                };
+                let version: CheckpointVersion =
+                    serde_json::from_str(&content).map_err(corrupted)?;
+                if version.version != VERSION {
+                    println!(
+                        "{}",
+                        t!(
+                            "Ignoring checkpoint {} from another version",
+                            path.display()
+                        )
+                    );
+                    return Ok(Checkpoint { path, data: fresh });
+                }
+                let data: CheckpointData = serde_json::from_str(&content).map_err(corrupted)?;
+                if data.period == period {
+                    println!("{}", t!("Resuming from checkpoint {}", path.display()));
+                    data
+                } else {
+                    println!(
+                        "{}",
+                        t!(
+                            "Ignoring checkpoint {} from another period ({})",
+                            path.display(),
+                            data.period
+                        )
+                    );
+                    fresh
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => fresh,
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Checkpoint { path, data })
+    }
+
+    /// Discards any saved progress and starts over
+    pub fn restart(path: PathBuf, period: Period) -> Result<Self> {
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        Self::open(path, period)
+    }
+
+    fn save(&self) -> Result<()> {
+        let content = serde_json::to_string(&self.data)
+            .map_err(|e| Error::Validation(t!("failed to serialize checkpoint: {}", e)))?;
+        // Write to a temporary file first, so a crash never leaves a truncated checkpoint
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, content)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    /// Where the checkpoint is saved, after every step
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn activity(&self) -> Option<&Activity> {
+        self.data.activity.as_ref()
+    }
+
+    pub fn set_activity(&mut self, activity: Activity) -> Result<()> {
+        self.data.activity = Some(activity);
+        self.save()
+    }
+
+    fn rate_key(asset: &str, date: NaiveDate) -> String {
+        format!("{} {}", asset, date)
+    }
+
+    pub fn rate(&self, asset: &str, date: NaiveDate) -> Option<Decimal> {
+        self.data.rates.get(&Self::rate_key(asset, date)).copied()
+    }
+
+    pub fn set_rate(&mut self, asset: &str, date: NaiveDate, rate: Decimal) -> Result<()> {
+        self.data.rates.insert(Self::rate_key(asset, date), rate);
+        self.save()
+    }
+
+    /// Sets many rates at once, e.g. those of a [`crate::bundle::Bundle`]
+    pub fn set_rates<'a>(
+        &mut self,
+        rates: impl IntoIterator<Item = (&'a str, NaiveDate, Decimal)>,
+    ) -> Result<()> {
+        for (asset, date, rate) in rates {
+            self.data.rates.insert(Self::rate_key(asset, date), rate);
+        }
+        self.save()
+    }
+
+    /// The answer previously given to a question, if it was asked before
+    pub fn answer(&self, question: &str) -> Option<Option<String>> {
+        self.data.answers.get(question).cloned()
+    }
+
+    pub fn set_answer(&mut self, question: &str, answer: Option<String>) -> Result<()> {
+        self.data.answers.insert(question.to_string(), answer);
+        self.save()
+    }
+
+    /// Deletes the checkpoint file, once the run has completed
+    pub fn finish(self) -> Result<()> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::NormalizedDeposit;
+    use chrono::DateTime;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_resume() {
+        let path = std::env::temp_dir().join(format!(
+            "kraken2rfb-test-{}.checkpoint.json",
+            std::process::id()
+        ));
+        let date = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+
+        let mut checkpoint =
+            Checkpoint::restart(path.clone(), Period::month(2024, 3).unwrap()).unwrap();
+        assert!(checkpoint.activity().is_none());
+        checkpoint
+            .set_activity(Activity {
+                deposits: vec![NormalizedDeposit {
+                    asset: "BTC".to_string(),
+                    amount: dec!(0.5),
+                    fee: dec!(0),
+                    time: DateTime::from_timestamp(1709294400, 0).unwrap(),
+                    ids: vec!["FTQcuak-V6Za8qrWnhzTx67yYHz8Tg".to_string()],
+                    origin_exchange: None,
+                    origin_wallet: None,
+                    network: None,
+                }],
+                ..Activity::default()
+            })
+            .unwrap();
+        checkpoint.set_rate("BTC", date, dec!(350000.12)).unwrap();
+        checkpoint.set_answer("question?", None).unwrap();
+
+        // Same period resumes
+        let checkpoint = Checkpoint::open(path.clone(), Period::month(2024, 3).unwrap()).unwrap();
+        assert_eq!(checkpoint.activity().unwrap().deposits[0].amount, dec!(0.5));
+        assert_eq!(checkpoint.rate("BTC", date), Some(dec!(350000.12)));
+        assert_eq!(checkpoint.rate("ETH", date), None);
+        assert_eq!(checkpoint.answer("question?"), Some(None));
+        assert_eq!(checkpoint.answer("other?"), None);
+
+        // Another period starts over
+        let other = Checkpoint::open(path.clone(), Period::month(2024, 4).unwrap()).unwrap();
+        assert!(other.activity().is_none());
+
+        checkpoint.finish().unwrap();
+        assert!(!path.exists());
+
+        // A checkpoint without a version is from before the activity was normalized
+        fs::write(
+            &path,
+            r#"{"year": 2024, "month": 3, "activity": null, "rates": {}, "answers": {}}"#,
+        )
+        .unwrap();
+        let old = Checkpoint::open(path.clone(), Period::month(2024, 3).unwrap()).unwrap();
+        assert_eq!(old.data.version, VERSION);
+        old.finish().unwrap();
+
+        // The activity of an older version isn't even read
+        fs::write(
+            &path,
+            r#"{"version": 1, "year": 2024, "month": 3, "activity": {"deposits": [{"asset": "XXBT"}], "withdrawals": [], "trades": []}, "rates": {}, "answers": {}}"#,
+        )
+        .unwrap();
+        let old = Checkpoint::open(path.clone(), Period::month(2024, 3).unwrap()).unwrap();
+        assert!(old.activity().is_none());
+        old.finish().unwrap();
+    }
+}
@@ -0,0 +1,2249 @@
+use std::path::PathBuf;
+
+use chrono::{Datelike, FixedOffset, Local, NaiveDate};
+use itertools::Itertools;
+use rust_decimal::Decimal;
+
+use kraken2rfb::env;
+use kraken2rfb::error::{Error, Result};
+use kraken2rfb::i18n::t;
+use kraken2rfb::kraken_symbols::normalize_asset_or_keep;
+use kraken2rfb::period::Period;
+use kraken2rfb::report::FeeCredits;
+use kraken2rfb::report::layout::Layout;
+use kraken2rfb::report::precision::Truncation;
+use kraken2rfb::report::schema::Document;
+use kraken2rfb::report::transactions::RECORD_TYPES;
+use kraken2rfb::source::IncomeKind;
+use kraken2rfb::store::Query;
+
+/// Subcommand given on the command line
+#[derive(Debug)]
+pub enum Command {
+    /// Generate the monthly report (the default)
+    Report(Box<ReportOptions>),
+    /// Show the BRL rate the report would use for an asset on a date
+    Rates { asset: String, date: NaiveDate },
+    /// Fetch every rate the report of a period needs, to the HTTP cache
+    PrefetchRates(PeriodSource),
+    /// Write what the report of a period is made from to a bundle file, or
+    /// read it for the report of a machine without network access
+    Bundle(BundleCommand),
+    /// Show what the report makes of a single trade or ledger entry
+    Inspect(InspectOptions),
+    /// Inspect the compiled Kraken pair map
+    Pairs(PairsQuery),
+    /// Interactive setup of the configuration and API keys
+    Init,
+    /// Check that everything needed to generate a report is in place
+    Doctor { keyfile: Option<PathBuf> },
+    /// Estimate the tax of selling an asset today
+    SimulateSale(SaleSimulation),
+    /// Download fresh Kraken asset and pair metadata
+    UpdateData { dir: PathBuf },
+    /// Write the report of the transactions in an interchange document
+    Encode {
+        input: PathBuf,
+        report_file: String,
+        layout: Layout,
+        force: bool,
+    },
+    /// Print the JSON Schema of a document
+    Schema(Document),
+    /// Check a report file against the manifest written next to it
+    Verify { report_file: PathBuf },
+    /// Save the transactions of a file in the store, for the gains only
+    Import {
+        format: ImportFormat,
+        file: PathBuf,
+        /// Source to save the transactions under, with `--format json`
+        source: Option<String>,
+    },
+    /// List the stored transactions
+    Query(Query),
+    /// Write the stored transactions to a CSV file
+    Export { query: Query, file: PathBuf },
+    /// Show the assets held on a date, by the stored transactions
+    Holdings(HoldingsOptions),
+    /// Show the stored sales of each month of a year
+    Sales { year: i32 },
+    /// Show the stored income of a year, like referral rewards
+    Income {
+        year: i32,
+        /// Only the income of this kind
+        kind: Option<IncomeKind>,
+        /// CSV file to write the income of each asset in each month to
+        output: Option<PathBuf>,
+    },
+    /// Show the gains and tax of each month of a year, with the losses carried
+    Gains {
+        year: i32,
+        /// Losses carried from before the stored history, to save in the store
+        opening_loss: Option<Decimal>,
+    },
+    /// List the reported periods, or mark one as filed with the RFB
+    Filed {
+        period: Option<Period>,
+        /// Exchange of the report
+        exchange: ExchangeName,
+        /// Mapping file of the CSV files, with `--exchange csv`
+        mapping: Option<PathBuf>,
+        /// Wallet of the report, with the exchanges imported from a tracker
+        wallet: Option<String>,
+        /// Day the report was filed
+        date: NaiveDate,
+        /// Mark the period as not filed instead
+        undo: bool,
+    },
+    /// Serve report generation over HTTP
+    Serve {
+        listen: String,
+        keyfile: Option<PathBuf>,
+    },
+}
+
+/// A sale to simulate with `simulate-sale`
+#[derive(Debug)]
+pub struct SaleSimulation {
+    pub asset: String,
+    pub amount: Decimal,
+    /// Average acquisition cost of each unit, in BRL
+    pub cost_basis: Decimal,
+    /// Sale price of each unit in BRL, or today's rate if not given
+    pub price: Option<Decimal>,
+    /// BRL value of the other sales in the month, or what the store has of
+    /// this month if not given
+    pub month_sales: Option<Decimal>,
+}
+
+/// The activity of a period and where to take it from, as in the report, for
+/// `prefetch-rates` and `bundle export`
+#[derive(Debug)]
+pub struct PeriodSource {
+    pub period: Period,
+    pub exchange: ExchangeName,
+    /// Path of the API key file
+    pub keyfile: Option<PathBuf>,
+    /// File exported from the exchange, for the file importers
+    pub input: Option<PathBuf>,
+    /// Mapping file of the CSV files, with `--exchange csv`
+    pub mapping: Option<PathBuf>,
+    /// Wallet of the report, with the exchanges imported from a tracker
+    pub wallet: Option<String>,
+    /// Offset from UTC of the days of the report, Brasília time if `None`
+    pub utc_offset: Option<FixedOffset>,
+}
+
+/// What `bundle` does
+#[derive(Debug)]
+pub enum BundleCommand {
+    /// Fetch the activity and the rates of a period and write them, with the
+    /// configuration, to `file`
+    Export { source: PeriodSource, file: PathBuf },
+    /// Save what `file` has as the checkpoint of the report file
+    Import { file: PathBuf, report_file: String },
+}
+
+/// The entry to show with `inspect`, and where to find it
+#[derive(Debug)]
+pub struct InspectOptions {
+    /// Trade, order, ledger or reference ID of the entry
+    pub id: String,
+    /// Period the entry is in; without it, Kraken is asked when it took place
+    pub period: Option<Period>,
+    /// Bundle the entry is taken from, with its rates, instead of the exchange
+    pub bundle: Option<PathBuf>,
+    pub exchange: ExchangeName,
+    /// Path of the API key file
+    pub keyfile: Option<PathBuf>,
+    /// File exported from the exchange, for the file importers
+    pub input: Option<PathBuf>,
+    /// Mapping file of the CSV files, with `--exchange csv`
+    pub mapping: Option<PathBuf>,
+    /// Wallet of the report, with the exchanges imported from a tracker
+    pub wallet: Option<String>,
+    /// Offset from UTC of the days of the report, Brasília time if `None`
+    pub utc_offset: Option<FixedOffset>,
+}
+
+/// A snapshot of the holdings to show with `holdings`
+#[derive(Debug)]
+pub struct HoldingsOptions {
+    /// Day at whose end the holdings are taken
+    pub date: NaiveDate,
+    /// Which stored transactions count, by asset and source
+    pub query: Query,
+    /// Compare the holdings on Kraken with its balances on the date
+    pub reconcile: bool,
+    /// Path of the Kraken API key file
+    pub keyfile: Option<PathBuf>,
+}
+
+/// Address the HTTP server listens on by default, only reachable locally
+const DEFAULT_LISTEN: &str = "127.0.0.1:8080";
+
+/// What to show of the Kraken pair map
+#[derive(Debug, PartialEq)]
+pub enum PairsQuery {
+    /// Every pair, or only those with the given asset as base or quote
+    List { asset: Option<String> },
+    /// How a single pair resolves to base and quote assets
+    Lookup { pair: String },
+}
+
+/// Format of a file given to `import`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    /// The operation history exported from Mercado Bitcoin
+    MercadoBitcoin,
+    /// An interchange document, with the transactions valued in BRL
+    Json,
+}
+
+impl ImportFormat {
+    /// Parses the name of a format, as given on the command line
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "mercado-bitcoin" => Ok(ImportFormat::MercadoBitcoin),
+            "json" => Ok(ImportFormat::Json),
+            _ => Err(Error::Config(t!(
+                "unknown import format: {} (expected {})",
+                name,
+                "mercado-bitcoin, json"
+            ))),
+        }
+    }
+}
+
+/// The exchanges the activity of a report can be fetched from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExchangeName {
+    #[default]
+    Kraken,
+    Binance,
+    Bitstamp,
+    Bybit,
+    Okx,
+    Kucoin,
+    Gemini,
+    Gate,
+    CryptoComApp,
+    Csv,
+    Koinly,
+    CoinTracking,
+}
+
+impl ExchangeName {
+    /// Every exchange, by the name it is given on the command line
+    const NAMES: [(&'static str, ExchangeName); 12] = [
+        ("kraken", ExchangeName::Kraken),
+        ("binance", ExchangeName::Binance),
+        ("bitstamp", ExchangeName::Bitstamp),
+        ("bybit", ExchangeName::Bybit),
+        ("okx", ExchangeName::Okx),
+        ("kucoin", ExchangeName::Kucoin),
+        ("gemini", ExchangeName::Gemini),
+        ("gate", ExchangeName::Gate),
+        ("cryptocom-app", ExchangeName::CryptoComApp),
+        ("csv", ExchangeName::Csv),
+        ("koinly", ExchangeName::Koinly),
+        ("cointracking", ExchangeName::CoinTracking),
+    ];
+
+    pub fn parse(name: &str) -> Result<Self> {
+        ExchangeName::NAMES
+            .iter()
+            .find(|(known, _)| known.eq_ignore_ascii_case(name))
+            .map(|&(_, exchange)| exchange)
+            .ok_or_else(|| {
+                Error::Config(t!(
+                    "unknown exchange: {} (expected one of {})",
+                    name,
+                    ExchangeName::names()
+                ))
+            })
+    }
+
+    /// The name of the exchange on the command line
+    pub fn name(self) -> &'static str {
+        ExchangeName::NAMES
+            .iter()
+            .find(|&&(_, exchange)| exchange == self)
+            .map_or("", |(name, _)| name)
+    }
+
+    /// Every exchange, in the order of the command line names
+    pub fn all() -> impl Iterator<Item = ExchangeName> {
+        ExchangeName::NAMES.iter().map(|&(_, exchange)| exchange)
+    }
+
+    /// The names of every exchange, comma separated
+    fn names() -> String {
+        ExchangeName::NAMES.iter().map(|(name, _)| name).join(", ")
+    }
+
+    /// Whether the activity is imported from a file exported from the
+    /// exchange, given with `--input`, instead of fetched from its API
+    pub fn is_file_importer(self) -> bool {
+        matches!(
+            self,
+            ExchangeName::CryptoComApp
+                | ExchangeName::Csv
+                | ExchangeName::Koinly
+                | ExchangeName::CoinTracking
+        )
+    }
+
+    /// Whether the activity is imported from the export of a tracker with
+    /// the history of many wallets, of which `--wallet` picks one
+    pub fn is_tracker(self) -> bool {
+        matches!(self, ExchangeName::Koinly | ExchangeName::CoinTracking)
+    }
+
+    /// The names of the exchanges imported from a file, comma separated
+    fn file_importer_names() -> String {
+        ExchangeName::NAMES
+            .iter()
+            .filter(|(_, exchange)| exchange.is_file_importer())
+            .map(|(name, _)| name)
+            .join(", ")
+    }
+
+    /// The names of the trackers, comma separated
+    fn tracker_names() -> String {
+        ExchangeName::NAMES
+            .iter()
+            .filter(|(_, exchange)| exchange.is_tracker())
+            .map(|(name, _)| name)
+            .join(", ")
+    }
+}
+
+/// Options of the report generation
+#[derive(Debug)]
+pub struct ReportOptions {
+    pub period: Period,
+    pub report_file: String,
+    /// Exchange to fetch the activity from
+    pub exchange: ExchangeName,
+    /// Never ask the user for missing information
+    pub non_interactive: bool,
+    /// Path of the API key file of the exchange
+    pub keyfile: Option<PathBuf>,
+    /// File exported from the exchange, for the exchanges imported from one
+    pub input: Option<PathBuf>,
+    /// How to read the `input` file, with `--exchange csv`
+    pub mapping: Option<PathBuf>,
+    /// Wallet of the `input` file to report, for the trackers
+    pub wallet: Option<String>,
+    /// Extended public key or output descriptor of a Bitcoin wallet of the
+    /// user, whose transfers the deposits and withdrawals are matched to
+    pub xpub: Option<String>,
+    /// Ethereum addresses of the user, whose transfers the deposits and
+    /// withdrawals are matched to
+    pub eth_addresses: Vec<String>,
+    /// Transaction exports of wallets of the user, whose transfers the
+    /// deposits and withdrawals are matched to
+    pub wallet_files: Vec<PathBuf>,
+    /// Operations worth less than this many BRL are left out of the report
+    pub min_value: Option<Decimal>,
+    /// Offset from UTC of the days of the report, instead of Brasília time
+    pub utc_offset: Option<FixedOffset>,
+    /// Only generate records of these types
+    pub record_types: Option<Vec<String>>,
+    /// Layout the report file is written in
+    pub layout: Layout,
+    /// Where to also write the transactions, as an interchange document
+    pub json_file: Option<PathBuf>,
+    /// Don't save the transactions to the store
+    pub no_store: bool,
+    /// Don't archive the report and what it was made from
+    pub no_archive: bool,
+    /// Generate the report even if its period was marked as filed
+    pub force: bool,
+    /// Compare the balances on Kraken with the history of the account
+    pub reconcile: bool,
+    /// Look up the transaction of each crypto withdrawal on a block explorer
+    pub verify_withdrawals: bool,
+    /// Add the rounding residuals to the last purchase or sale of each month
+    pub fold_residuals: bool,
+    /// What is done with the crypto amounts with more decimals than the layout
+    pub truncation: Truncation,
+    /// What the fees paid with fee credits (Kraken's KFEE) are worth
+    pub fee_credits: FeeCredits,
+    /// Discard the checkpoint of an interrupted run instead of resuming it
+    pub restart: bool,
+    /// Only show which records would be generated
+    pub dry_run: bool,
+    /// Let the user review the transactions before writing the report
+    pub review: bool,
+    /// Convert and write the report batch by batch, without keeping the
+    /// transactions
+    pub stream: bool,
+    /// IDs of Kraken entries to leave out of the report
+    pub exclude_ids: Vec<String>,
+    /// File listing more IDs to leave out, one per line
+    pub exclude_file: Option<PathBuf>,
+    /// Print the raw Kraken data and the intermediate values of the conversion
+    pub debug: bool,
+    /// Turn every warning into an error
+    pub fail_on_warning: bool,
+}
+
+/// Appends a two column table (e.g. flags and their descriptions) to the usage text
+fn push_table(usage: &mut String, title: String, rows: &[(&str, String)]) {
+    usage.push_str("\n\n");
+    usage.push_str(&title);
+    for (name, description) in rows {
+        if name.len() < 20 {
+            usage.push_str(&format!("\n  {:<20}{}", name, description));
+        } else {
+            usage.push_str(&format!("\n  {}\n{:22}{}", name, "", description));
+        }
+    }
+}
+
+fn usage(program: &str) -> String {
+    let commands = [
+        (
+            "rates <asset> <date>",
+            t!("Show the BRL rate used for an asset on a date (YYYY-MM-DD)"),
+        ),
+        (
+            "pairs list [asset]",
+            t!("List the known Kraken pairs, optionally only those of an asset"),
+        ),
+        (
+            "pairs lookup <pair>",
+            t!("Show the base and quote assets of a Kraken pair"),
+        ),
+        (
+            "prefetch-rates <period> [--exchange <name>] [--keyfile <path>] [--input <file>] [--mapping <file>] [--wallet <name>] [--utc-offset <offset>]",
+            t!(
+                "Fetch every BRL rate the report of a period needs and keep it in the HTTP cache, so the report is generated later without waiting for the providers"
+            ),
+        ),
+        (
+            "inspect --trade <id> [--period <period>] [--bundle <bundle.json>] [--exchange <name>] [--keyfile <path>] [--input <file>] [--mapping <file>] [--wallet <name>] [--utc-offset <offset>]",
+            t!(
+                "Show how a single trade or ledger entry is classified, the BRL rates it is converted with and the report line it turns into, without a whole run"
+            ),
+        ),
+        (
+            "bundle export <period> <bundle.json> [--exchange <name>] [--keyfile <path>] [--input <file>] [--mapping <file>] [--wallet <name>] [--utc-offset <offset>]",
+            t!(
+                "Write the activity of a period, the BRL rates its report needs and the configuration to a single file, to generate the report on a machine without network access"
+            ),
+        ),
+        (
+            "bundle import <bundle.json> <report_file>",
+            t!(
+                "Read a bundle written by bundle export, so the report of its period is generated to the report file without fetching anything"
+            ),
+        ),
+        (
+            "init",
+            t!("Set up the configuration and the Kraken API keys interactively"),
+        ),
+        (
+            "doctor",
+            t!("Check the key file, the Kraken API access and the price providers"),
+        ),
+        (
+            "simulate-sale <asset> <amount> --cost-basis <brl> [--price <brl>] [--month-sales <brl>]",
+            t!("Estimate the gain and the tax of selling an asset today"),
+        ),
+        (
+            "update-data [--dir <path>]",
+            t!("Download fresh Kraken asset and pair data (default directory: kraken_data)"),
+        ),
+        (
+            "encode <transactions.json> <report_file> [--layout <version>] [--force]",
+            t!("Write the report of the transactions in a JSON document, e.g. saved with --json"),
+        ),
+        (
+            "schema <interchange|summary>",
+            t!("Print the JSON Schema of the transactions document or of the run summary"),
+        ),
+        (
+            "verify <report_file>",
+            t!("Check that a report file is unchanged since it was generated, by its manifest"),
+        ),
+        (
+            "import --format mercado-bitcoin <file>",
+            t!(
+                "Save the operations of a Brazilian exchange in the store, for the gains and the exemption; the exchange reports them itself"
+            ),
+        ),
+        (
+            "import --format json <transactions.json> --source <name>",
+            t!(
+                "Save the transactions of a JSON document, as read by encode, in the store under the source"
+            ),
+        ),
+        (
+            "query [--asset <asset>] [--record-types <types>] [--period <period>] [--source <name>]",
+            t!("List the transactions saved by previous reports"),
+        ),
+        (
+            "export <file.csv> [query options]",
+            t!("Export the saved transactions, or those matching the query options, to CSV"),
+        ),
+        (
+            "holdings [--date <date>] [--asset <asset>] [--source <name>] [--reconcile]",
+            t!(
+                "Show the assets held on a date (default: December 31 of last year) and their BRL value, by the saved transactions"
+            ),
+        ),
+        (
+            "sales [--year <year>]",
+            t!(
+                "Show the saved sales of each month of a year (default: this one) and whether they are within the exemption"
+            ),
+        ),
+        (
+            "income [--year <year>] [--kind <kind>] [--output <file.csv>]",
+            t!(
+                "Show the saved crypto income of a year (default: this one), like referral rewards, fee rebates and staking rewards, with its value when received, or write its total by month and asset to a CSV file"
+            ),
+        ),
+        (
+            "gains [--year <year>] [--opening-loss <brl>]",
+            t!(
+                "Show the realized gains and the tax of each month of a year, with the losses carried forward"
+            ),
+        ),
+        (
+            "filed [<period>] [--exchange <name>] [--mapping <file>] [--wallet <name>] [--date <date>] [--undo]",
+            t!(
+                "Mark the report of a period as filed with the RFB, so it isn't generated again by mistake, or list the reported periods"
+            ),
+        ),
+        (
+            "serve [--listen <address>]",
+            t!("Serve reports over HTTP, on POST /reports (default address: 127.0.0.1:8080)"),
+        ),
+    ];
+    let options = [
+        (
+            "--period <period>",
+            t!("Period instead of <year> <month>: last-month, this-month, 2024-03 or 2024-Q1"),
+        ),
+        (
+            "--exchange <name>",
+            t!(
+                "Exchange to fetch the activity from, one of: {} (default: kraken)",
+                ExchangeName::names()
+            ),
+        ),
+        ("--keyfile <path>", t!("API key file of the exchange")),
+        (
+            "--input <file>",
+            t!(
+                "File exported from the exchange, for the exchanges imported from a file ({})",
+                ExchangeName::file_importer_names()
+            ),
+        ),
+        (
+            "--mapping <file>",
+            t!("Mapping file of the columns of the CSV file, with --exchange csv"),
+        ),
+        (
+            "--wallet <name>",
+            t!(
+                "Wallet to report of the file exported from a tracker ({})",
+                ExchangeName::tracker_names()
+            ),
+        ),
+        (
+            "--xpub <key>",
+            t!(
+                "Extended public key or descriptor of a Bitcoin wallet of yours, to find the deposits that came from it"
+            ),
+        ),
+        (
+            "--eth-addresses <list>",
+            t!(
+                "Comma separated Ethereum addresses of yours, to find the deposits that came from them"
+            ),
+        ),
+        (
+            "--wallet-files <list>",
+            t!(
+                "Comma separated exports of Ledger Live, Trezor Suite, Electrum or Exodus, to find the deposits that came from those wallets"
+            ),
+        ),
+        (
+            "--min-value <brl>",
+            t!("Leave out operations worth less than this, in BRL"),
+        ),
+        (
+            "--utc-offset <offset>",
+            t!(
+                "Offset from UTC of the days the operations are reported on, like -02:00 (default: -03:00, Brasília time)"
+            ),
+        ),
+        (
+            "--record-types <types>",
+            t!("Only generate records of these comma separated types (e.g. 0410,0510)"),
+        ),
+        (
+            "--layout <version>",
+            t!(
+                "Layout of the report file, to reproduce an old report (default: {})",
+                Layout::default()
+            ),
+        ),
+        (
+            "--json <path>",
+            t!("Also save the transactions as JSON, to inspect or encode them later"),
+        ),
+        (
+            "--no-store",
+            t!("Don't save the transactions to the store used by the query command"),
+        ),
+        (
+            "--no-archive",
+            t!("Don't archive the report with what it was made from, for an audit trail"),
+        ),
+        (
+            "--stream",
+            t!(
+                "Fetch, convert and write the report batch by batch, for histories too long to hold in memory; the transactions aren't saved to the store nor archived"
+            ),
+        ),
+        (
+            "--force",
+            t!(
+                "Overwrite the report file if it exists, and generate the report even if its period was marked as filed"
+            ),
+        ),
+        (
+            "--fold-residuals",
+            t!(
+                "Add what rounding to cents left out to the last purchase or sale of each asset in a month"
+            ),
+        ),
+        (
+            "--truncation <policy>",
+            t!(
+                "What to do with crypto amounts with more decimals than the report: warn, error or accumulate the difference (default: warn)"
+            ),
+        ),
+        (
+            "--fee-credits <policy>",
+            t!(
+                "What the fees paid with Kraken fee credits (KFEE) are worth: value, their worth in US dollars, or zero (default: value)"
+            ),
+        ),
+        (
+            "--reconcile",
+            t!("Afterwards, compare the Kraken balances with the whole history of the account"),
+        ),
+        (
+            "--verify-withdrawals",
+            t!(
+                "Check that the transaction of each Bitcoin and Ether withdrawal pays what the exchange recorded, on the block explorers"
+            ),
+        ),
+        (
+            "--non-interactive",
+            t!("Never prompt for missing information"),
+        ),
+        (
+            "--restart",
+            t!("Discard the progress of an interrupted run"),
+        ),
+        (
+            "--dry-run",
+            t!(
+                "Only show the records that would be generated, without rate lookups or writing files"
+            ),
+        ),
+        (
+            "--exclude-ids <ids>",
+            t!("Leave out the Kraken entries with these comma separated IDs"),
+        ),
+        (
+            "--exclude-file <path>",
+            t!("Leave out the Kraken entries with the IDs listed in this file"),
+        ),
+        (
+            "--review",
+            t!("Review, exclude and edit the records before writing the report"),
+        ),
+        (
+            "--fail-on-warning",
+            t!("Fail instead of warning, e.g. about an old rate or a left out operation"),
+        ),
+        (
+            "--debug",
+            t!("Print the raw Kraken data and the conversion details"),
+        ),
+        (
+            "--profile <name>",
+            t!("Profile of the declarant, with its own keys, store and output directory"),
+        ),
+        (
+            "--lang <lang>",
+            t!("Language of the messages (en or pt-BR)"),
+        ),
+        (
+            "--no-color",
+            t!("Don't color the output, same as setting NO_COLOR"),
+        ),
+    ];
+
+    let mut usage = t!("Usage: {} [options] <year> <month> <report_file>", program);
+    usage.push('\n');
+    usage.push_str(&t!(
+        "       {} [options] --period <period> <report_file>",
+        program
+    ));
+    usage.push('\n');
+    usage.push_str(&t!("       {} <command> <arguments>", program));
+    push_table(&mut usage, t!("Commands:"), &commands);
+    push_table(&mut usage, t!("Options:"), &options);
+    usage
+}
+
+/// Returns the language given with `--lang`, which must be known before
+/// parsing the rest of the command line, so that errors are translated.
+pub fn lang_arg(args: &[String]) -> Option<&str> {
+    args.iter()
+        .position(|arg| arg == "--lang")
+        .and_then(|pos| args.get(pos + 1))
+        .map(String::as_str)
+}
+
+/// Returns the profile given with `--profile`, which selects the files every
+/// command uses
+pub fn profile_arg(args: &[String]) -> Option<&str> {
+    args.iter()
+        .position(|arg| arg == "--profile")
+        .and_then(|pos| args.get(pos + 1))
+        .map(String::as_str)
+}
+
+/// Whether `--no-color` was given, needed before anything is printed
+pub fn no_color_arg(args: &[String]) -> bool {
+    args.iter().any(|arg| arg == "--no-color")
+}
+
+/// Parses the command line arguments, including the program name
+pub fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Command> {
+    let program = args.next().unwrap_or_else(|| "kraken2rfb".to_string());
+    let mut args: Vec<String> = args.collect();
+    // Global flags may come before the command, e.g. `--lang pt-BR rates ...`;
+    // move them past it so the command is the first argument
+    let mut leading = 0;
+    loop {
+        match args.get(leading).map(String::as_str) {
+            Some("--lang" | "--profile") => leading += 2,
+            Some("--no-color") => leading += 1,
+            _ => break,
+        }
+    }
+    let leading = leading.min(args.len());
+    args.rotate_left(leading);
+    let mut args = args.into_iter().peekable();
+    match args.peek().map(String::as_str) {
+        Some("rates") => {
+            args.next();
+            parse_rates(&program, args)
+        }
+        Some("prefetch-rates") => {
+            args.next();
+            parse_prefetch_rates(&program, args)
+        }
+        Some("inspect") => {
+            args.next();
+            parse_inspect(&program, args)
+        }
+        Some("bundle") => {
+            args.next();
+            parse_bundle(&program, args)
+        }
+        Some("pairs") => {
+            args.next();
+            parse_pairs(&program, args)
+        }
+        Some("init") => {
+            args.next();
+            parse_init(&program, args)
+        }
+        Some("doctor") => {
+            args.next();
+            parse_doctor(&program, args)
+        }
+        Some("simulate-sale") => {
+            args.next();
+            parse_simulate_sale(&program, args)
+        }
+        Some("update-data") => {
+            args.next();
+            parse_update_data(&program, args)
+        }
+        Some("encode") => {
+            args.next();
+            parse_encode(&program, args)
+        }
+        Some("schema") => {
+            args.next();
+            parse_schema(&program, args)
+        }
+        Some("verify") => {
+            args.next();
+            parse_verify(&program, args)
+        }
+        Some("import") => {
+            args.next();
+            parse_import(&program, args)
+        }
+        Some("query") => {
+            args.next();
+            parse_query(&program, args)
+        }
+        Some("export") => {
+            args.next();
+            parse_export(&program, args)
+        }
+        Some("holdings") => {
+            args.next();
+            parse_holdings(&program, args)
+        }
+        Some("sales") => {
+            args.next();
+            Ok(Command::Sales {
+                year: parse_year(&program, args)?,
+            })
+        }
+        Some("income") => {
+            args.next();
+            parse_income(&program, args)
+        }
+        Some("gains") => {
+            args.next();
+            parse_gains(&program, args)
+        }
+        Some("filed") => {
+            args.next();
+            parse_filed(&program, args)
+        }
+        Some("serve") => {
+            args.next();
+            parse_serve(&program, args)
+        }
+        Some("report") => {
+            args.next();
+            parse_report(&program, args).map(|options| Command::Report(Box::new(options)))
+        }
+        _ => parse_report(&program, args).map(|options| Command::Report(Box::new(options))),
+    }
+}
+
+/// Handles the flags accepted by every command. Returns `false` if `arg` is
+/// not one of them.
+fn global_flag(arg: &str, args: &mut impl Iterator<Item = String>) -> Result<bool> {
+    match arg {
+        // Already handled by lang_arg() and profile_arg()
+        "--lang" | "--profile" => {
+            value_of(arg, args.next())?;
+            Ok(true)
+        }
+        // Already handled by no_color_arg()
+        "--no-color" => Ok(true),
+        _ => Ok(false),
+    }
+}
+
+fn unknown_option(flag: &str, program: &str) -> Error {
+    Error::Config(t!("unknown option: {}\n{}", flag, usage(program)))
+}
+
+fn wrong_arguments(program: &str) -> Error {
+    Error::Config(t!("wrong number of arguments\n{}", usage(program)))
+}
+
+/// Fails unless a mapping file is given with `--exchange csv`, and only then
+fn check_mapping(exchange: ExchangeName, mapping: &Option<PathBuf>) -> Result<()> {
+    match (exchange, mapping) {
+        (ExchangeName::Csv, None) => Err(Error::Config(t!(
+            "--exchange csv needs a mapping file, given with --mapping"
+        ))),
+        (ExchangeName::Csv, Some(_)) | (_, None) => Ok(()),
+        (_, Some(_)) => Err(Error::Config(t!("--mapping is only for --exchange csv"))),
+    }
+}
+
+/// Fails unless a wallet is given with the trackers, and only then
+fn check_wallet(exchange: ExchangeName, wallet: &Option<String>) -> Result<()> {
+    match (exchange.is_tracker(), wallet) {
+        (true, None) => Err(Error::Config(t!(
+            "--exchange {} needs the name of the wallet to report, given with --wallet",
+            exchange.name()
+        ))),
+        (true, Some(_)) | (false, None) => Ok(()),
+        (false, Some(_)) => Err(Error::Config(t!(
+            "--wallet is only for the exchanges imported from a tracker: {}",
+            ExchangeName::tracker_names()
+        ))),
+    }
+}
+
+fn parse_report(program: &str, mut args: impl Iterator<Item = String>) -> Result<ReportOptions> {
+    let mut positional = Vec::new();
+    let mut period = env::var("PERIOD");
+    let mut exchange = env::var("EXCHANGE")
+        .map(|name| ExchangeName::parse(&name))
+        .transpose()?
+        .unwrap_or_default();
+    let mut non_interactive = env::flag("NON_INTERACTIVE")?;
+    let mut keyfile = None;
+    let mut input = None;
+    let mut mapping = None;
+    let mut wallet = None;
+    let mut xpub = env::var("XPUB");
+    let mut eth_addresses =
+        env::var("ETH_ADDRESSES").map_or_else(Vec::new, |list| split_ids(&list));
+    let mut wallet_files =
+        env::var("WALLET_FILES").map_or_else(Vec::new, |list| split_paths(&list));
+    let mut min_value = env::var("MIN_VALUE")
+        .map(|value| decimal_of(&env::name("MIN_VALUE"), Some(value)))
+        .transpose()?;
+    let mut utc_offset = env::var("UTC_OFFSET")
+        .map(|value| offset_of(&env::name("UTC_OFFSET"), Some(value)))
+        .transpose()?;
+    let mut record_types = env::var("RECORD_TYPES")
+        .map(|value| parse_record_types(&value))
+        .transpose()?;
+    let mut layout = env::var("LAYOUT")
+        .map(|value| Layout::parse(&value))
+        .transpose()?
+        .unwrap_or_default();
+    let mut json_file = None;
+    let mut no_store = env::flag("NO_STORE")?;
+    let mut no_archive = env::flag("NO_ARCHIVE")?;
+    let mut force = false;
+    let mut reconcile = false;
+    let mut verify_withdrawals = env::flag("VERIFY_WITHDRAWALS")?;
+    let mut fold_residuals = false;
+    let mut truncation = env::var("TRUNCATION")
+        .map(|value| Truncation::parse(&env::name("TRUNCATION"), &value))
+        .transpose()?
+        .unwrap_or_default();
+    let mut fee_credits = env::var("FEE_CREDITS")
+        .map(|value| FeeCredits::parse(&env::name("FEE_CREDITS"), &value))
+        .transpose()?
+        .unwrap_or_default();
+    let mut restart = false;
+    let mut dry_run = false;
+    let mut review = false;
+    let mut stream = env::flag("STREAM")?;
+    let mut exclude_ids = Vec::new();
+    let mut exclude_file = env::var("EXCLUDE_FILE").map(PathBuf::from);
+    let mut debug = env::flag("DEBUG")?;
+    let mut fail_on_warning = env::flag("FAIL_ON_WARNING")?;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--non-interactive" => non_interactive = true,
+            "--restart" => restart = true,
+            "--no-store" => no_store = true,
+            "--no-archive" => no_archive = true,
+            "--force" => force = true,
+            "--reconcile" => reconcile = true,
+            "--verify-withdrawals" => verify_withdrawals = true,
+            "--fold-residuals" => fold_residuals = true,
+            "--dry-run" => dry_run = true,
+            "--review" => review = true,
+            "--stream" => stream = true,
+            "--debug" => debug = true,
+            "--fail-on-warning" => fail_on_warning = true,
+            "--period" => period = Some(value_of(&arg, args.next())?),
+            "--exchange" => exchange = ExchangeName::parse(&value_of(&arg, args.next())?)?,
+            "--keyfile" => keyfile = Some(PathBuf::from(value_of(&arg, args.next())?)),
+            "--input" => input = Some(PathBuf::from(value_of(&arg, args.next())?)),
+            "--mapping" => mapping = Some(PathBuf::from(value_of(&arg, args.next())?)),
+            "--wallet" => wallet = Some(value_of(&arg, args.next())?),
+            "--xpub" => xpub = Some(value_of(&arg, args.next())?),
+            "--eth-addresses" => eth_addresses = split_ids(&value_of(&arg, args.next())?),
+            "--wallet-files" => wallet_files = split_paths(&value_of(&arg, args.next())?),
+            "--min-value" => min_value = Some(decimal_of(&arg, args.next())?),
+            "--utc-offset" => utc_offset = Some(offset_of(&arg, args.next())?),
+            "--layout" => layout = Layout::parse(&value_of(&arg, args.next())?)?,
+            "--truncation" => truncation = Truncation::parse(&arg, &value_of(&arg, args.next())?)?,
+            "--fee-credits" => {
+                fee_credits = FeeCredits::parse(&arg, &value_of(&arg, args.next())?)?;
+            }
+            "--json" => json_file = Some(PathBuf::from(value_of(&arg, args.next())?)),
+            "--exclude-ids" => exclude_ids.extend(split_ids(&value_of(&arg, args.next())?)),
+            "--exclude-file" => exclude_file = Some(PathBuf::from(value_of(&arg, args.next())?)),
+            "--record-types" => {
+                record_types = Some(parse_record_types(&value_of(&arg, args.next())?)?);
+            }
+            flag if global_flag(flag, &mut args)? => {}
+            flag if flag.starts_with("--") => return Err(unknown_option(flag, program)),
+            _ => positional.push(arg),
+        }
+    }
+
+    if input.is_some() && !exchange.is_file_importer() {
+        return Err(Error::Config(t!(
+            "--input is only for the exchanges imported from a file: {}",
+            ExchangeName::file_importer_names()
+        )));
+    }
+
+    check_mapping(exchange, &mapping)?;
+    check_wallet(exchange, &wallet)?;
+    if stream {
+        let conflicting = [
+            ("--review", review),
+            ("--json", json_file.is_some()),
+            ("--dry-run", dry_run),
+            ("--xpub", xpub.is_some()),
+            ("--eth-addresses", !eth_addresses.is_empty()),
+            ("--wallet-files", !wallet_files.is_empty()),
+            ("--verify-withdrawals", verify_withdrawals),
+        ];
+        if let Some((flag, _)) = conflicting.iter().find(|(_, given)| *given) {
+            return Err(Error::Config(t!("--stream can't be used with {}", flag)));
+        }
+    }
+
+    // The IDs given on the command line replace the ones in the environment
+    if exclude_ids.is_empty()
+        && let Some(ids) = env::var("EXCLUDE_IDS")
+    {
+        exclude_ids = split_ids(&ids);
+    }
+
+    // The year and month, when given, take precedence over KRAKEN2RFB_PERIOD
+    let (period, report_file) = match (<[String; 3]>::try_from(positional), period) {
+        (Ok([year, month, report_file]), _) => {
+            let year = year
+                .parse()
+                .map_err(|_| Error::Config(t!("invalid year: {}", year)))?;
+            let month = month
+                .parse()
+                .map_err(|_| Error::Config(t!("invalid month: {}", month)))?;
+            (Period::month(year, month)?, report_file)
+        }
+        (Err(positional), Some(period)) => {
+            let [report_file]: [String; 1] = positional
+                .try_into()
+                .map_err(|_| wrong_arguments(program))?;
+            (
+                Period::parse(&period, Local::now().date_naive())?,
+                report_file,
+            )
+        }
+        (Err(_), None) => return Err(wrong_arguments(program)),
+    };
+
+    Ok(ReportOptions {
+        period,
+        report_file,
+        exchange,
+        non_interactive,
+        keyfile,
+        input,
+        mapping,
+        wallet,
+        xpub,
+        eth_addresses,
+        wallet_files,
+        min_value,
+        utc_offset,
+        record_types,
+        layout,
+        json_file,
+        no_store,
+        no_archive,
+        force,
+        reconcile,
+        verify_withdrawals,
+        fold_residuals,
+        truncation,
+        fee_credits,
+        restart,
+        dry_run,
+        review,
+        stream,
+        exclude_ids,
+        exclude_file,
+        debug,
+        fail_on_warning,
+    })
+}
+
+/// Splits a comma separated list of Kraken IDs
+fn split_ids(ids: &str) -> Vec<String> {
+    ids.split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Splits a comma separated list of paths
+fn split_paths(paths: &str) -> Vec<PathBuf> {
+    split_ids(paths).into_iter().map(PathBuf::from).collect()
+}
+
+fn parse_rates(program: &str, mut args: impl Iterator<Item = String>) -> Result<Command> {
+    let mut positional = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            flag if global_flag(flag, &mut args)? => {}
+            flag if flag.starts_with("--") => return Err(unknown_option(flag, program)),
+            _ => positional.push(arg),
+        }
+    }
+
+    let [asset, date]: [String; 2] = positional
+        .try_into()
+        .map_err(|_| wrong_arguments(program))?;
+    Ok(Command::Rates {
+        asset: asset.to_uppercase(),
+        date: parse_date(&date)?,
+    })
+}
+
+/// Parses the period and the options of where its activity is taken from,
+/// returning the other positional arguments
+fn parse_period_source(
+    program: &str,
+    mut args: impl Iterator<Item = String>,
+) -> Result<(PeriodSource, Vec<String>)> {
+    let mut positional = Vec::new();
+    let mut exchange = env::var("EXCHANGE")
+        .map(|name| ExchangeName::parse(&name))
+        .transpose()?
+        .unwrap_or_default();
+    let mut keyfile = None;
+    let mut input = None;
+    let mut mapping = None;
+    let mut wallet = None;
+    let mut utc_offset = env::var("UTC_OFFSET")
+        .map(|value| offset_of(&env::name("UTC_OFFSET"), Some(value)))
+        .transpose()?;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--exchange" => exchange = ExchangeName::parse(&value_of(&arg, args.next())?)?,
+            "--keyfile" => keyfile = Some(PathBuf::from(value_of(&arg, args.next())?)),
+            "--input" => input = Some(PathBuf::from(value_of(&arg, args.next())?)),
+            "--mapping" => mapping = Some(PathBuf::from(value_of(&arg, args.next())?)),
+            "--wallet" => wallet = Some(value_of(&arg, args.next())?),
+            "--utc-offset" => utc_offset = Some(offset_of(&arg, args.next())?),
+            flag if global_flag(flag, &mut args)? => {}
+            flag if flag.starts_with("--") => return Err(unknown_option(flag, program)),
+            _ => positional.push(arg),
+        }
+    }
+    if positional.is_empty() {
+        return Err(wrong_arguments(program));
+    }
+    let period = Period::parse(&positional.remove(0), Local::now().date_naive())?;
+    check_mapping(exchange, &mapping)?;
+    check_wallet(exchange, &wallet)?;
+    let source = PeriodSource {
+        period,
+        exchange,
+        keyfile,
+        input,
+        mapping,
+        wallet,
+        utc_offset,
+    };
+    Ok((source, positional))
+}
+
+fn parse_prefetch_rates(program: &str, args: impl Iterator<Item = String>) -> Result<Command> {
+    let (source, positional) = parse_period_source(program, args)?;
+    if !positional.is_empty() {
+        return Err(wrong_arguments(program));
+    }
+    Ok(Command::PrefetchRates(source))
+}
+
+fn parse_bundle(program: &str, mut args: impl Iterator<Item = String>) -> Result<Command> {
+    match args.next().as_deref() {
+        Some("export") => {
+            let (source, positional) = parse_period_source(program, args)?;
+            let [file]: [String; 1] = positional
+                .try_into()
+                .map_err(|_| wrong_arguments(program))?;
+            Ok(Command::Bundle(BundleCommand::Export {
+                source,
+                file: PathBuf::from(file),
+            }))
+        }
+        Some("import") => {
+            let mut positional = Vec::new();
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    flag if global_flag(flag, &mut args)? => {}
+                    flag if flag.starts_with("--") => return Err(unknown_option(flag, program)),
+                    _ => positional.push(arg),
+                }
+            }
+            let [file, report_file]: [String; 2] = positional
+                .try_into()
+                .map_err(|_| wrong_arguments(program))?;
+            Ok(Command::Bundle(BundleCommand::Import {
+                file: PathBuf::from(file),
+                report_file,
+            }))
+        }
+        _ => Err(wrong_arguments(program)),
+    }
+}
+
+fn parse_inspect(program: &str, mut args: impl Iterator<Item = String>) -> Result<Command> {
+    let mut id = None;
+    let mut period = None;
+    let mut bundle = None;
+    let mut exchange = env::var("EXCHANGE")
+        .map(|name| ExchangeName::parse(&name))
+        .transpose()?
+        .unwrap_or_default();
+    let mut keyfile = None;
+    let mut input = None;
+    let mut mapping = None;
+    let mut wallet = None;
+    let mut utc_offset = env::var("UTC_OFFSET")
+        .map(|value| offset_of(&env::name("UTC_OFFSET"), Some(value)))
+        .transpose()?;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--trade" => id = Some(value_of(&arg, args.next())?),
+            "--period" => {
+                let value = value_of(&arg, args.next())?;
+                period = Some(Period::parse(&value, Local::now().date_naive())?);
+            }
+            "--bundle" => bundle = Some(PathBuf::from(value_of(&arg, args.next())?)),
+            "--exchange" => exchange = ExchangeName::parse(&value_of(&arg, args.next())?)?,
+            "--keyfile" => keyfile = Some(PathBuf::from(value_of(&arg, args.next())?)),
+            "--input" => input = Some(PathBuf::from(value_of(&arg, args.next())?)),
+            "--mapping" => mapping = Some(PathBuf::from(value_of(&arg, args.next())?)),
+            "--wallet" => wallet = Some(value_of(&arg, args.next())?),
+            "--utc-offset" => utc_offset = Some(offset_of(&arg, args.next())?),
+            flag if global_flag(flag, &mut args)? => {}
+            flag if flag.starts_with("--") => return Err(unknown_option(flag, program)),
+            _ => return Err(wrong_arguments(program)),
+        }
+    }
+    let id = id.ok_or_else(|| wrong_arguments(program))?;
+    if bundle.is_some() && period.is_some() {
+        return Err(Error::Config(t!(
+            "--period can't be given with --bundle, which has its own"
+        )));
+    }
+    if bundle.is_none() && period.is_none() && exchange != ExchangeName::Kraken {
+        return Err(Error::Config(t!(
+            "--period is needed to find the entry on {}",
+            exchange.name()
+        )));
+    }
+    check_mapping(exchange, &mapping)?;
+    check_wallet(exchange, &wallet)?;
+    Ok(Command::Inspect(InspectOptions {
+        id,
+        period,
+        bundle,
+        exchange,
+        keyfile,
+        input,
+        mapping,
+        wallet,
+        utc_offset,
+    }))
+}
+
+fn parse_pairs(program: &str, mut args: impl Iterator<Item = String>) -> Result<Command> {
+    let mut positional = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            flag if global_flag(flag, &mut args)? => {}
+            flag if flag.starts_with("--") => return Err(unknown_option(flag, program)),
+            _ => positional.push(arg.to_uppercase()),
+        }
+    }
+
+    let mut positional = positional.into_iter();
+    let query = match (
+        positional.next().as_deref(),
+        positional.next(),
+        positional.next(),
+    ) {
+        (Some("LIST"), asset, None) => PairsQuery::List { asset },
+        (Some("LOOKUP"), Some(pair), None) => PairsQuery::Lookup { pair },
+        _ => return Err(wrong_arguments(program)),
+    };
+    Ok(Command::Pairs(query))
+}
+
+fn parse_init(program: &str, mut args: impl Iterator<Item = String>) -> Result<Command> {
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            flag if global_flag(flag, &mut args)? => {}
+            flag if flag.starts_with("--") => return Err(unknown_option(flag, program)),
+            _ => return Err(wrong_arguments(program)),
+        }
+    }
+    Ok(Command::Init)
+}
+
+fn parse_doctor(program: &str, mut args: impl Iterator<Item = String>) -> Result<Command> {
+    let mut keyfile = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--keyfile" => keyfile = Some(PathBuf::from(value_of(&arg, args.next())?)),
+            flag if global_flag(flag, &mut args)? => {}
+            flag if flag.starts_with("--") => return Err(unknown_option(flag, program)),
+            _ => return Err(wrong_arguments(program)),
+        }
+    }
+    Ok(Command::Doctor { keyfile })
+}
+
+fn parse_update_data(program: &str, mut args: impl Iterator<Item = String>) -> Result<Command> {
+    let mut dir = PathBuf::from("kraken_data");
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dir" => dir = PathBuf::from(value_of(&arg, args.next())?),
+            flag if global_flag(flag, &mut args)? => {}
+            flag if flag.starts_with("--") => return Err(unknown_option(flag, program)),
+            _ => return Err(wrong_arguments(program)),
+        }
+    }
+    Ok(Command::UpdateData { dir })
+}
+
+fn parse_encode(program: &str, mut args: impl Iterator<Item = String>) -> Result<Command> {
+    let mut positional = Vec::new();
+    let mut layout = Layout::default();
+    let mut force = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--layout" => layout = Layout::parse(&value_of(&arg, args.next())?)?,
+            "--force" => force = true,
+            flag if global_flag(flag, &mut args)? => {}
+            flag if flag.starts_with("--") => return Err(unknown_option(flag, program)),
+            _ => positional.push(arg),
+        }
+    }
+
+    let [input, report_file]: [String; 2] = positional
+        .try_into()
+        .map_err(|_| wrong_arguments(program))?;
+    Ok(Command::Encode {
+        input: PathBuf::from(input),
+        report_file,
+        layout,
+        force,
+    })
+}
+
+fn parse_schema(program: &str, mut args: impl Iterator<Item = String>) -> Result<Command> {
+    let mut positional = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            flag if global_flag(flag, &mut args)? => {}
+            flag if flag.starts_with("--") => return Err(unknown_option(flag, program)),
+            _ => positional.push(arg),
+        }
+    }
+    let [name]: [String; 1] = positional
+        .try_into()
+        .map_err(|_| wrong_arguments(program))?;
+    Document::parse(&name).map(Command::Schema)
+}
+
+fn parse_verify(program: &str, mut args: impl Iterator<Item = String>) -> Result<Command> {
+    let mut positional = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            flag if global_flag(flag, &mut args)? => {}
+            flag if flag.starts_with("--") => return Err(unknown_option(flag, program)),
+            _ => positional.push(arg),
+        }
+    }
+    let [report_file]: [String; 1] = positional
+        .try_into()
+        .map_err(|_| wrong_arguments(program))?;
+    Ok(Command::Verify {
+        report_file: PathBuf::from(report_file),
+    })
+}
+
+/// Parses the options of `query` and `export`, returning the other arguments
+fn parse_store_query(
+    program: &str,
+    mut args: impl Iterator<Item = String>,
+) -> Result<(Query, Vec<String>)> {
+    let mut query = Query::default();
+    let mut positional = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--asset" => query.asset = Some(asset_of(value_of(&arg, args.next())?)),
+            "--source" => query.source = Some(value_of(&arg, args.next())?),
+            "--record-types" => {
+                query.record_types = Some(parse_record_types(&value_of(&arg, args.next())?)?);
+            }
+            "--period" => {
+                let period = value_of(&arg, args.next())?;
+                query.period = Some(Period::parse(&period, Local::now().date_naive())?);
+            }
+            flag if global_flag(flag, &mut args)? => {}
+            flag if flag.starts_with("--") => return Err(unknown_option(flag, program)),
+            _ => positional.push(arg),
+        }
+    }
+    Ok((query, positional))
+}
+
+fn parse_query(program: &str, args: impl Iterator<Item = String>) -> Result<Command> {
+    let (query, positional) = parse_store_query(program, args)?;
+    if !positional.is_empty() {
+        return Err(wrong_arguments(program));
+    }
+    Ok(Command::Query(query))
+}
+
+fn parse_import(program: &str, mut args: impl Iterator<Item = String>) -> Result<Command> {
+    let mut format = None;
+    let mut source = None;
+    let mut positional = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => format = Some(ImportFormat::parse(&value_of(&arg, args.next())?)?),
+            "--source" => source = Some(value_of(&arg, args.next())?),
+            flag if global_flag(flag, &mut args)? => {}
+            flag if flag.starts_with("--") => return Err(unknown_option(flag, program)),
+            _ => positional.push(arg),
+        }
+    }
+    let [file]: [String; 1] = positional
+        .try_into()
+        .map_err(|_| wrong_arguments(program))?;
+    let format = format.ok_or_else(|| Error::Config(t!("missing value for {}", "--format")))?;
+    // The exchanges are their own source
+    match (format, &source) {
+        (ImportFormat::Json, None) => {
+            return Err(Error::Config(t!(
+                "--format json needs the source of the transactions, given with --source"
+            )));
+        }
+        (ImportFormat::MercadoBitcoin, Some(_)) => {
+            return Err(Error::Config(t!("--source is only for --format json")));
+        }
+        _ => {}
+    }
+    Ok(Command::Import {
+        format,
+        file: PathBuf::from(file),
+        source,
+    })
+}
+
+fn parse_export(program: &str, args: impl Iterator<Item = String>) -> Result<Command> {
+    let (query, positional) = parse_store_query(program, args)?;
+    let [file]: [String; 1] = positional
+        .try_into()
+        .map_err(|_| wrong_arguments(program))?;
+    Ok(Command::Export {
+        query,
+        file: PathBuf::from(file),
+    })
+}
+
+fn parse_holdings(program: &str, mut args: impl Iterator<Item = String>) -> Result<Command> {
+    let last_year = Local::now().year() - 1;
+    let mut date = NaiveDate::from_ymd_opt(last_year, 12, 31).unwrap();
+    let mut query = Query::default();
+    let mut reconcile = false;
+    let mut keyfile = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--date" => date = parse_date(&value_of(&arg, args.next())?)?,
+            "--asset" => query.asset = Some(asset_of(value_of(&arg, args.next())?)),
+            "--source" => query.source = Some(value_of(&arg, args.next())?),
+            "--reconcile" => reconcile = true,
+            "--keyfile" => keyfile = Some(PathBuf::from(value_of(&arg, args.next())?)),
+            flag if global_flag(flag, &mut args)? => {}
+            flag if flag.starts_with("--") => return Err(unknown_option(flag, program)),
+            _ => return Err(wrong_arguments(program)),
+        }
+    }
+    Ok(Command::Holdings(HoldingsOptions {
+        date,
+        query,
+        reconcile,
+        keyfile,
+    }))
+}
+
+fn year_of(value: String) -> Result<i32> {
+    value
+        .parse()
+        .map_err(|_| Error::Config(t!("invalid year: {}", value)))
+}
+
+/// The year of a command with only `--year`, this one by default
+fn parse_year(program: &str, mut args: impl Iterator<Item = String>) -> Result<i32> {
+    let mut year = Local::now().year();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--year" => year = year_of(value_of(&arg, args.next())?)?,
+            flag if global_flag(flag, &mut args)? => {}
+            flag if flag.starts_with("--") => return Err(unknown_option(flag, program)),
+            _ => return Err(wrong_arguments(program)),
+        }
+    }
+    Ok(year)
+}
+
+fn parse_income(program: &str, mut args: impl Iterator<Item = String>) -> Result<Command> {
+    let mut year = Local::now().year();
+    let mut kind = None;
+    let mut output = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--year" => year = year_of(value_of(&arg, args.next())?)?,
+            "--kind" => kind = Some(IncomeKind::parse(&value_of(&arg, args.next())?)?),
+            "--output" => output = Some(PathBuf::from(value_of(&arg, args.next())?)),
+            flag if global_flag(flag, &mut args)? => {}
+            flag if flag.starts_with("--") => return Err(unknown_option(flag, program)),
+            _ => return Err(wrong_arguments(program)),
+        }
+    }
+    Ok(Command::Income { year, kind, output })
+}
+
+fn parse_filed(program: &str, mut args: impl Iterator<Item = String>) -> Result<Command> {
+    let today = Local::now().date_naive();
+    let mut period = None;
+    let mut exchange = ExchangeName::default();
+    let mut mapping = None;
+    let mut wallet = None;
+    let mut date = today;
+    let mut undo = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--exchange" => exchange = ExchangeName::parse(&value_of(&arg, args.next())?)?,
+            "--mapping" => mapping = Some(PathBuf::from(value_of(&arg, args.next())?)),
+            "--wallet" => wallet = Some(value_of(&arg, args.next())?),
+            "--date" => date = parse_date(&value_of(&arg, args.next())?)?,
+            "--undo" => undo = true,
+            flag if global_flag(flag, &mut args)? => {}
+            flag if flag.starts_with("--") => return Err(unknown_option(flag, program)),
+            _ if period.is_none() => period = Some(Period::parse(&arg, today)?),
+            _ => return Err(wrong_arguments(program)),
+        }
+    }
+    check_mapping(exchange, &mapping)?;
+    check_wallet(exchange, &wallet)?;
+    Ok(Command::Filed {
+        period,
+        exchange,
+        mapping,
+        wallet,
+        date,
+        undo,
+    })
+}
+
+fn parse_gains(program: &str, mut args: impl Iterator<Item = String>) -> Result<Command> {
+    let mut year = Local::now().year();
+    let mut opening_loss = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--year" => year = year_of(value_of(&arg, args.next())?)?,
+            "--opening-loss" => opening_loss = Some(decimal_of(&arg, args.next())?),
+            flag if global_flag(flag, &mut args)? => {}
+            flag if flag.starts_with("--") => return Err(unknown_option(flag, program)),
+            _ => return Err(wrong_arguments(program)),
+        }
+    }
+    Ok(Command::Gains { year, opening_loss })
+}
+
+fn parse_serve(program: &str, mut args: impl Iterator<Item = String>) -> Result<Command> {
+    let mut listen = env::var("LISTEN").unwrap_or_else(|| DEFAULT_LISTEN.to_string());
+    let mut keyfile = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" => listen = value_of(&arg, args.next())?,
+            "--keyfile" => keyfile = Some(PathBuf::from(value_of(&arg, args.next())?)),
+            flag if global_flag(flag, &mut args)? => {}
+            flag if flag.starts_with("--") => return Err(unknown_option(flag, program)),
+            _ => return Err(wrong_arguments(program)),
+        }
+    }
+    Ok(Command::Serve { listen, keyfile })
+}
+
+fn parse_simulate_sale(program: &str, mut args: impl Iterator<Item = String>) -> Result<Command> {
+    let mut positional = Vec::new();
+    let mut cost_basis = None;
+    let mut price = None;
+    let mut month_sales = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--cost-basis" => cost_basis = Some(decimal_of(&arg, args.next())?),
+            "--price" => price = Some(decimal_of(&arg, args.next())?),
+            "--month-sales" => month_sales = Some(decimal_of(&arg, args.next())?),
+            flag if global_flag(flag, &mut args)? => {}
+            flag if flag.starts_with("--") => return Err(unknown_option(flag, program)),
+            _ => positional.push(arg),
+        }
+    }
+
+    let [asset, amount]: [String; 2] = positional
+        .try_into()
+        .map_err(|_| wrong_arguments(program))?;
+    Ok(Command::SimulateSale(SaleSimulation {
+        asset: asset.to_uppercase(),
+        amount: decimal_of("<amount>", Some(amount))?,
+        cost_basis: cost_basis
+            .ok_or_else(|| Error::Config(t!("missing value for {}", "--cost-basis")))?,
+        price,
+        month_sales,
+    }))
+}
+
+/// Parses a date as YYYY-MM-DD or DD/MM/YYYY
+fn parse_date(date: &str) -> Result<NaiveDate> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .or_else(|_| NaiveDate::parse_from_str(date, "%d/%m/%Y"))
+        .map_err(|_| Error::Config(t!("invalid date: {}", date)))
+}
+
+/// Parses a comma separated list of record types, like "0410,0510"
+pub fn parse_record_types(list: &str) -> Result<Vec<String>> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .map(|t| {
+            if RECORD_TYPES.contains(&t) {
+                Ok(t.to_string())
+            } else {
+                Err(Error::Config(t!(
+                    "unknown record type: {} (valid types: {})",
+                    t,
+                    RECORD_TYPES.join(", ")
+                )))
+            }
+        })
+        .collect()
+}
+
+/// Returns the value following a flag, failing if it is missing
+fn value_of(flag: &str, value: Option<String>) -> Result<String> {
+    value.ok_or_else(|| Error::Config(t!("missing value for {}", flag)))
+}
+
+/// Returns the decimal number following a flag. Both `.` and `,` are accepted
+/// as decimal separator.
+fn decimal_of(flag: &str, value: Option<String>) -> Result<Decimal> {
+    let value = value_of(flag, value)?;
+    value
+        .replace(',', ".")
+        .parse()
+        .map_err(|_| Error::Config(t!("invalid value for {}: {}", flag, value)))
+}
+
+/// The common symbol of an asset given on the command line, which may be its
+/// Kraken name, like XXBT or XBT
+fn asset_of(asset: String) -> String {
+    normalize_asset_or_keep(&asset.to_uppercase()).to_string()
+}
+
+/// Returns the offset from UTC following a flag, like `-03:00`
+fn offset_of(flag: &str, value: Option<String>) -> Result<FixedOffset> {
+    let value = value_of(flag, value)?;
+    value
+        .parse()
+        .map_err(|_| Error::Config(t!("invalid value for {}: {}", flag, value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_command(args: &[&str]) -> Result<Command> {
+        parse_args(
+            std::iter::once("kraken2rfb")
+                .chain(args.iter().copied())
+                .map(String::from),
+        )
+    }
+
+    fn parse(args: &[&str]) -> Result<ReportOptions> {
+        match parse_command(args)? {
+            Command::Report(options) => Ok(*options),
+            command => panic!("unexpected command: {:?}", command),
+        }
+    }
+
+    #[test]
+    fn test_positional_arguments() {
+        let options = parse(&["2024", "3", "report.txt"]).unwrap();
+        assert_eq!(options.period, Period::month(2024, 3).unwrap());
+        assert_eq!(options.report_file, "report.txt");
+        assert!(!options.non_interactive);
+        assert_eq!(options.keyfile, None);
+        assert_eq!(options.min_value, None);
+        assert_eq!(options.record_types, None);
+        assert_eq!(options.layout, Layout::V2019);
+        assert_eq!(options.truncation, Truncation::Warn);
+        assert_eq!(options.fee_credits, FeeCredits::Value);
+    }
+
+    #[test]
+    fn test_flags() {
+        let options = parse(&[
+            "--keyfile",
+            "/tmp/keys.json",
+            "2024",
+            "--non-interactive",
+            "--review",
+            "--fail-on-warning",
+            "--exclude-ids",
+            "TZX2WP-XSEOP-CEWAIE,",
+            "--lang",
+            "pt-BR",
+            "3",
+            "--min-value",
+            "0,50",
+            "--record-types",
+            "0410, 0510",
+            "--layout",
+            "2019",
+            "--truncation",
+            "accumulate",
+            "--fee-credits",
+            "zero",
+            "--json",
+            "transactions.json",
+            "--exchange",
+            "Binance",
+            "report.txt",
+        ])
+        .unwrap();
+        assert_eq!(options.exchange, ExchangeName::Binance);
+        assert!(options.non_interactive);
+        assert!(options.review);
+        assert!(!options.debug);
+        assert!(options.fail_on_warning);
+        assert_eq!(options.exclude_ids, ["TZX2WP-XSEOP-CEWAIE"]);
+        assert_eq!(options.keyfile, Some(PathBuf::from("/tmp/keys.json")));
+        assert_eq!(options.min_value, Some(Decimal::new(50, 2)));
+        assert_eq!(
+            options.record_types,
+            Some(vec!["0410".to_string(), "0510".to_string()])
+        );
+        assert_eq!(options.json_file, Some(PathBuf::from("transactions.json")));
+        assert_eq!(options.truncation, Truncation::Accumulate);
+        assert_eq!(options.fee_credits, FeeCredits::Zero);
+    }
+
+    #[test]
+    fn test_input() {
+        let options = parse(&[
+            "2024",
+            "3",
+            "report.txt",
+            "--exchange",
+            "cryptocom-app",
+            "--input",
+            "crypto_transactions.csv",
+        ])
+        .unwrap();
+        assert_eq!(options.exchange, ExchangeName::CryptoComApp);
+        assert_eq!(
+            options.input,
+            Some(PathBuf::from("crypto_transactions.csv"))
+        );
+        // The API exchanges take no file
+        assert!(matches!(
+            parse(&["2024", "3", "report.txt", "--input", "export.csv"]),
+            Err(Error::Config(_))
+        ));
+        // Only the CSV importer takes a mapping file, and it needs one
+        let options = parse(&[
+            "2024",
+            "3",
+            "report.txt",
+            "--exchange",
+            "csv",
+            "--input",
+            "export.csv",
+            "--mapping",
+            "mapping.json",
+        ])
+        .unwrap();
+        assert_eq!(options.mapping, Some(PathBuf::from("mapping.json")));
+        assert!(parse(&["2024", "3", "report.txt", "--exchange", "csv"]).is_err());
+        assert!(parse(&["2024", "3", "report.txt", "--mapping", "mapping.json"]).is_err());
+        // Only the trackers take a wallet, and they need one
+        let options = parse(&[
+            "2024",
+            "3",
+            "report.txt",
+            "--exchange",
+            "koinly",
+            "--input",
+            "koinly.csv",
+            "--wallet",
+            "Binance",
+        ])
+        .unwrap();
+        assert_eq!(options.wallet.as_deref(), Some("Binance"));
+        assert!(parse(&["2024", "3", "report.txt", "--exchange", "koinly"]).is_err());
+        assert!(parse(&["2024", "3", "report.txt", "--wallet", "Binance"]).is_err());
+        let options =
+            parse(&["2024", "3", "report.txt", "--xpub", "zpub6jftahH18ngZx6RF"]).unwrap();
+        assert_eq!(options.xpub.as_deref(), Some("zpub6jftahH18ngZx6RF"));
+        assert!(!options.verify_withdrawals);
+        let options = parse(&["2024", "3", "report.txt", "--verify-withdrawals"]).unwrap();
+        assert!(options.verify_withdrawals);
+        let options = parse(&[
+            "2024",
+            "3",
+            "report.txt",
+            "--wallet-files",
+            "ledgerlive.csv, trezor.csv",
+        ])
+        .unwrap();
+        assert_eq!(
+            options.wallet_files,
+            [PathBuf::from("ledgerlive.csv"), PathBuf::from("trezor.csv")]
+        );
+    }
+
+    #[test]
+    fn test_invalid_arguments() {
+        assert!(matches!(parse(&["2024", "3"]), Err(Error::Config(_))));
+        assert!(matches!(
+            parse(&["2024", "3", "report.txt", "--bogus"]),
+            Err(Error::Config(_))
+        ));
+        assert!(matches!(
+            parse(&["2024", "3", "report.txt", "--keyfile"]),
+            Err(Error::Config(_))
+        ));
+        assert!(matches!(
+            parse(&["2024", "3", "report.txt", "--record-types", "0410,9999"]),
+            Err(Error::Config(_))
+        ));
+        assert!(matches!(
+            parse(&["2024", "3", "report.txt", "--layout", "1999"]),
+            Err(Error::Config(_))
+        ));
+        assert!(matches!(
+            parse(&["2024", "3", "report.txt", "--truncation", "round"]),
+            Err(Error::Config(_))
+        ));
+        assert!(matches!(
+            parse(&["2024", "3", "report.txt", "--fee-credits", "free"]),
+            Err(Error::Config(_))
+        ));
+        assert!(matches!(
+            parse(&["2024", "3", "report.txt", "--exchange", "mtgox"]),
+            Err(Error::Config(_))
+        ));
+        assert!(matches!(
+            parse(&["year", "3", "report.txt"]),
+            Err(Error::Config(_))
+        ));
+        assert!(matches!(
+            parse(&["2024", "13", "report.txt"]),
+            Err(Error::Config(_))
+        ));
+        assert!(parse(&["--period", "2024-Q5", "report.txt"]).is_err());
+        assert!(parse(&["--period", "2024-Q1", "2024", "report.txt"]).is_err());
+    }
+
+    #[test]
+    fn test_utc_offset() {
+        let options = parse(&["2024", "3", "report.txt"]).unwrap();
+        assert_eq!(options.utc_offset, None);
+        let options = parse(&["2024", "3", "report.txt", "--utc-offset", "+00:00"]).unwrap();
+        assert_eq!(options.utc_offset, FixedOffset::east_opt(0));
+        assert!(parse(&["2024", "3", "report.txt", "--utc-offset", "BRT"]).is_err());
+    }
+
+    #[test]
+    fn test_period() {
+        let options = parse(&["--period", "2024-Q1", "report.txt"]).unwrap();
+        assert_eq!(options.period, Period::quarter(2024, 1).unwrap());
+        assert_eq!(options.report_file, "report.txt");
+        assert!(parse(&["report.txt", "--period", "last-month"]).is_ok());
+    }
+
+    #[test]
+    fn test_rates_command() {
+        let command = parse_command(&["rates", "xbt", "2024-03-01"]).unwrap();
+        assert!(matches!(
+            command,
+            Command::Rates { asset, date }
+                if asset == "XBT" && date == NaiveDate::from_ymd_opt(2024, 3, 1).unwrap()
+        ));
+        let command = parse_command(&["rates", "USD", "01/03/2024"]).unwrap();
+        assert!(matches!(
+            command,
+            Command::Rates { date, .. } if date == NaiveDate::from_ymd_opt(2024, 3, 1).unwrap()
+        ));
+        assert!(parse_command(&["rates", "USD"]).is_err());
+        assert!(parse_command(&["rates", "USD", "2024-02-30"]).is_err());
+
+        // The explicit report command is the same as the default one
+        assert_eq!(
+            parse(&["report", "2024", "3", "report.txt"])
+                .unwrap()
+                .period,
+            Period::month(2024, 3).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_pairs_command() {
+        assert!(matches!(
+            parse_command(&["pairs", "lookup", "xethzeur"]).unwrap(),
+            Command::Pairs(PairsQuery::Lookup { pair }) if pair == "XETHZEUR"
+        ));
+        assert!(matches!(
+            parse_command(&["pairs", "list"]).unwrap(),
+            Command::Pairs(PairsQuery::List { asset: None })
+        ));
+        assert!(matches!(
+            parse_command(&["pairs", "list", "eth"]).unwrap(),
+            Command::Pairs(PairsQuery::List { asset: Some(asset) }) if asset == "ETH"
+        ));
+        assert!(matches!(
+            parse_command(&["--no-color", "--lang", "en", "pairs", "list", "--no-color"]).unwrap(),
+            Command::Pairs(PairsQuery::List { asset: None })
+        ));
+        let args = [
+            "--profile",
+            "ana",
+            "sales",
+            "--year",
+            "2024",
+            "--profile",
+            "ana",
+        ];
+        assert!(matches!(
+            parse_command(&args).unwrap(),
+            Command::Sales { year: 2024 }
+        ));
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        assert_eq!(profile_arg(&args), Some("ana"));
+        assert!(parse_command(&["pairs"]).is_err());
+        assert!(parse_command(&["pairs", "lookup"]).is_err());
+        assert!(parse_command(&["pairs", "lookup", "XETHZEUR", "XXBTZEUR"]).is_err());
+    }
+
+    #[test]
+    fn test_doctor_command() {
+        assert!(matches!(
+            parse_command(&["doctor"]).unwrap(),
+            Command::Doctor { keyfile: None }
+        ));
+        assert!(matches!(
+            parse_command(&["doctor", "--keyfile", "keys.json"]).unwrap(),
+            Command::Doctor { keyfile: Some(path) } if path.to_str() == Some("keys.json")
+        ));
+        assert!(parse_command(&["doctor", "extra"]).is_err());
+        assert!(matches!(parse_command(&["init"]).unwrap(), Command::Init));
+    }
+
+    #[test]
+    fn test_serve_command() {
+        assert!(matches!(
+            parse_command(&["serve"]).unwrap(),
+            Command::Serve { listen, keyfile: None } if listen == DEFAULT_LISTEN
+        ));
+        assert!(matches!(
+            parse_command(&["serve", "--listen", "0.0.0.0:9000"]).unwrap(),
+            Command::Serve { listen, .. } if listen == "0.0.0.0:9000"
+        ));
+        assert!(parse_command(&["serve", "--listen"]).is_err());
+
+        assert!(matches!(
+            parse_command(&["update-data", "--dir", "/tmp/data"]).unwrap(),
+            Command::UpdateData { dir } if dir.to_str() == Some("/tmp/data")
+        ));
+    }
+
+    #[test]
+    fn test_sales_command() {
+        assert!(matches!(
+            parse_command(&["sales", "--year", "2024"]).unwrap(),
+            Command::Sales { year: 2024 }
+        ));
+        assert!(parse_command(&["sales", "--year", "last"]).is_err());
+        assert!(matches!(
+            parse_command(&["income", "--year", "2024"]).unwrap(),
+            Command::Income {
+                year: 2024,
+                kind: None,
+                output: None
+            }
+        ));
+        let Command::Income { kind, output, .. } =
+            parse_command(&["income", "--kind", "staking", "--output", "staking.csv"]).unwrap()
+        else {
+            panic!("expected an income command");
+        };
+        assert_eq!(kind, Some(IncomeKind::Staking));
+        assert_eq!(output, Some(PathBuf::from("staking.csv")));
+        assert!(parse_command(&["income", "--kind", "mining"]).is_err());
+    }
+
+    #[test]
+    fn test_schema_command() {
+        assert!(matches!(
+            parse_command(&["schema", "summary"]).unwrap(),
+            Command::Schema(Document::Summary)
+        ));
+        assert!(parse_command(&["schema"]).is_err());
+        assert!(parse_command(&["schema", "store"]).is_err());
+    }
+
+    #[test]
+    fn test_encode_command() {
+        assert!(matches!(
+            parse_command(&["encode", "t.json", "report.txt"]).unwrap(),
+            Command::Encode { force: false, .. }
+        ));
+        assert!(matches!(
+            parse_command(&["encode", "t.json", "report.txt", "--force"]).unwrap(),
+            Command::Encode { force: true, .. }
+        ));
+    }
+
+    #[test]
+    fn test_verify_command() {
+        assert!(matches!(
+            parse_command(&["verify", "report.txt"]).unwrap(),
+            Command::Verify { report_file } if report_file == std::path::Path::new("report.txt")
+        ));
+        assert!(parse_command(&["verify"]).is_err());
+    }
+
+    #[test]
+    fn test_import_command() {
+        assert!(matches!(
+            parse_command(&["import", "--format", "mercado-bitcoin", "extrato.csv"]).unwrap(),
+            Command::Import { format: ImportFormat::MercadoBitcoin, file, source: None }
+                if file == std::path::Path::new("extrato.csv")
+        ));
+        assert!(matches!(
+            parse_command(&["import", "--format", "json", "t.json", "--source", "script"]).unwrap(),
+            Command::Import { format: ImportFormat::Json, source: Some(source), .. }
+                if source == "script"
+        ));
+        // Only the documents need a source, and they do
+        assert!(parse_command(&["import", "--format", "json", "t.json"]).is_err());
+        assert!(
+            parse_command(&[
+                "import",
+                "--format",
+                "mercado-bitcoin",
+                "e.csv",
+                "--source",
+                "mb"
+            ])
+            .is_err()
+        );
+        // The format is needed, and must be known
+        assert!(parse_command(&["import", "extrato.csv"]).is_err());
+        assert!(parse_command(&["import", "--format", "kraken", "extrato.csv"]).is_err());
+        assert!(parse_command(&["import", "--format", "mercado-bitcoin"]).is_err());
+    }
+
+    #[test]
+    fn test_prefetch_rates_command() {
+        let Command::PrefetchRates(options) = parse_command(&[
+            "prefetch-rates",
+            "2024-03",
+            "--exchange",
+            "binance",
+            "--keyfile",
+            "binance_keys.json",
+        ])
+        .unwrap() else {
+            panic!("not a prefetch-rates command");
+        };
+        assert_eq!(options.period, Period::month(2024, 3).unwrap());
+        assert_eq!(options.exchange, ExchangeName::Binance);
+        assert_eq!(options.keyfile, Some(PathBuf::from("binance_keys.json")));
+        assert!(parse_command(&["prefetch-rates"]).is_err());
+        assert!(parse_command(&["prefetch-rates", "2024-03", "--mapping", "map.json"]).is_err());
+        assert!(parse_command(&["prefetch-rates", "2024-03", "2024-04"]).is_err());
+    }
+
+    #[test]
+    fn test_bundle_command() {
+        let Command::Bundle(BundleCommand::Export { source, file }) = parse_command(&[
+            "bundle",
+            "export",
+            "2024-03",
+            "bundle.json",
+            "--input",
+            "crypto_transactions.csv",
+            "--exchange",
+            "cryptocom-app",
+        ])
+        .unwrap() else {
+            panic!("not a bundle export command");
+        };
+        assert_eq!(source.period, Period::month(2024, 3).unwrap());
+        assert_eq!(source.input, Some(PathBuf::from("crypto_transactions.csv")));
+        assert_eq!(file, PathBuf::from("bundle.json"));
+        let Command::Bundle(BundleCommand::Import { file, report_file }) =
+            parse_command(&["bundle", "import", "bundle.json", "report.txt"]).unwrap()
+        else {
+            panic!("not a bundle import command");
+        };
+        assert_eq!(
+            (file.to_str(), report_file.as_str()),
+            (Some("bundle.json"), "report.txt")
+        );
+        assert!(parse_command(&["bundle", "export", "2024-03"]).is_err());
+        assert!(parse_command(&["bundle", "import", "bundle.json"]).is_err());
+        assert!(parse_command(&["bundle"]).is_err());
+    }
+
+    #[test]
+    fn test_inspect_command() {
+        let Command::Inspect(options) =
+            parse_command(&["inspect", "--trade", "TZX2WP-XSEOP-CEWAIE"]).unwrap()
+        else {
+            panic!("not an inspect command");
+        };
+        assert_eq!(options.id, "TZX2WP-XSEOP-CEWAIE");
+        assert_eq!((options.period, options.bundle), (None, None));
+        let Command::Inspect(options) = parse_command(&[
+            "inspect",
+            "--trade",
+            "123",
+            "--period",
+            "2024-03",
+            "--exchange",
+            "binance",
+        ])
+        .unwrap() else {
+            panic!("not an inspect command");
+        };
+        assert_eq!(options.period, Some(Period::month(2024, 3).unwrap()));
+        assert!(parse_command(&["inspect"]).is_err());
+        assert!(parse_command(&["inspect", "--trade", "123", "--exchange", "binance"]).is_err());
+        assert!(
+            parse_command(&[
+                "inspect",
+                "--trade",
+                "123",
+                "--bundle",
+                "bundle.json",
+                "--period",
+                "2024-03"
+            ])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_filed_command() {
+        let Command::Filed {
+            period,
+            exchange,
+            mapping,
+            wallet,
+            date,
+            undo,
+        } = parse_command(&["filed", "2024-03", "--date", "2024-04-30"]).unwrap()
+        else {
+            panic!("not a filed command");
+        };
+        assert_eq!(period, Some(Period::month(2024, 3).unwrap()));
+        assert_eq!(exchange, ExchangeName::Kraken);
+        assert_eq!((mapping, wallet), (None, None));
+        assert_eq!(date, NaiveDate::from_ymd_opt(2024, 4, 30).unwrap());
+        assert!(!undo);
+        assert!(matches!(
+            parse_command(&["filed"]).unwrap(),
+            Command::Filed { period: None, .. }
+        ));
+        assert!(parse_command(&["filed", "2024-03", "2024-04"]).is_err());
+        assert!(
+            parse(&["2024", "3", "report.txt", "--force"])
+                .unwrap()
+                .force
+        );
+    }
+
+    #[test]
+    fn test_gains_command() {
+        let Command::Gains { year, opening_loss } =
+            parse_command(&["gains", "--year", "2024", "--opening-loss", "1500,50"]).unwrap()
+        else {
+            panic!("not a gains command");
+        };
+        assert_eq!(year, 2024);
+        assert_eq!(opening_loss, Some(Decimal::new(150050, 2)));
+    }
+
+    #[test]
+    fn test_simulate_sale_command() {
+        let Command::SimulateSale(sale) = parse_command(&[
+            "simulate-sale",
+            "btc",
+            "0,5",
+            "--cost-basis",
+            "200000",
+            "--month-sales",
+            "1000.50",
+        ])
+        .unwrap() else {
+            panic!("not a simulate-sale command");
+        };
+        assert_eq!(sale.asset, "BTC");
+        assert_eq!(sale.amount, Decimal::new(5, 1));
+        assert_eq!(sale.cost_basis, Decimal::from(200000));
+        assert_eq!(sale.price, None);
+        assert_eq!(sale.month_sales, Some(Decimal::new(100050, 2)));
+
+        assert!(parse_command(&["simulate-sale", "BTC", "0.5"]).is_err());
+        assert!(parse_command(&["simulate-sale", "BTC", "lots", "--cost-basis", "1"]).is_err());
+    }
+
+    #[test]
+    fn test_holdings_command() {
+        let Command::Holdings(options) = parse_command(&[
+            "holdings",
+            "--date",
+            "30/06/2024",
+            "--asset",
+            "btc",
+            "--reconcile",
+        ])
+        .unwrap() else {
+            panic!("not a holdings command");
+        };
+        assert_eq!(options.date, NaiveDate::from_ymd_opt(2024, 6, 30).unwrap());
+        assert_eq!(options.query.asset.as_deref(), Some("BTC"));
+        assert!(options.reconcile);
+
+        let Command::Holdings(options) = parse_command(&["holdings"]).unwrap() else {
+            panic!("not a holdings command");
+        };
+        assert_eq!((options.date.month(), options.date.day()), (12, 31));
+        assert!(parse_command(&["holdings", "2024-12-31"]).is_err());
+    }
+}
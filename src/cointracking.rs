@@ -0,0 +1,300 @@
+//! Importer of the trade list of CoinTracking, the CSV exported in Enter
+//! Coins → Trade List, which has the history of every exchange and wallet of
+//! the account together.
+//!
+//! The report is of one exchange, so only the rows whose `Exchange` is the
+//! wallet given are imported. Of the types of CoinTracking, `Trade`,
+//! `Deposit` and `Withdrawal` are the trades and transfers of the report; the
+//! income (`Staking`, `Mining`, `Airdrop`, `Interest Income` and the like) is
+//! left out, as the other sources leave it, and so are the other types
+//! (`Spend`, `Lost`, `Other Fee`, margin and derivatives entries).
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use rust_decimal::Decimal;
+
+use crate::csv::{Row, Table, row_id};
+use crate::error::{Error, Result};
+use crate::i18n::t;
+use crate::report::transactions::ExchangeInfo;
+use crate::source::{
+    Activity, ExchangeSource, NormalizedDeposit, NormalizedTrade, NormalizedWithdrawal, Side,
+    is_fiat,
+};
+
+/// Columns of the export read by the importer, by the names of the newer
+/// exports; the older ones name the amounts `Buy` and `Sell` and each
+/// currency `Cur.`
+const COLUMNS: [&str; 9] = [
+    "Type",
+    "Buy Amount",
+    "Buy Currency",
+    "Sell Amount",
+    "Sell Currency",
+    "Fee",
+    "Fee Currency",
+    "Exchange",
+    "Date",
+];
+
+/// Optional column with the hash of a crypto transfer
+const TX_ID: &str = "Tx-ID";
+
+/// Formats of the `Date`, which follow the date format of the account
+const DATE_FORMATS: [&str; 4] = [
+    "%Y-%m-%d %H:%M:%S",
+    "%d.%m.%Y %H:%M:%S",
+    "%d.%m.%Y %H:%M",
+    "%m/%d/%Y %H:%M:%S",
+];
+
+/// Source of the activity of an exchange in a CoinTracking trade list
+pub struct CoinTrackingSource {
+    /// The exported CSV file, or `None` if the user gave none
+    file: Option<PathBuf>,
+    /// Name of the exchange in CoinTracking, e.g. "Binance"
+    wallet: String,
+    /// The exchange the wallet is on
+    exchange: ExchangeInfo,
+}
+
+impl CoinTrackingSource {
+    pub fn new(file: Option<PathBuf>, wallet: String, exchange: ExchangeInfo) -> Self {
+        CoinTrackingSource {
+            file,
+            wallet,
+            exchange,
+        }
+    }
+}
+
+impl ExchangeSource for CoinTrackingSource {
+    fn exchange(&self) -> ExchangeInfo {
+        self.exchange.clone()
+    }
+
+    fn fetch(&self, first_day: NaiveDate, last_day: NaiveDate) -> Result<Activity> {
+        let file = self.file.as_deref().ok_or_else(|| {
+            Error::Config(t!(
+                "give the file exported from {} with --input",
+                "CoinTracking"
+            ))
+        })?;
+        let table = rename_columns(Table::read(file, ',')?);
+        Ok(normalize(&table, &self.wallet)?.within(first_day, last_day))
+    }
+}
+
+/// The table with the columns of the older exports renamed as in the newer:
+/// `Buy` and `Sell` are the amounts, and each `Cur.` the currency of the
+/// column before it
+fn rename_columns(table: Table) -> Table {
+    let mut header = table.header.clone();
+    for index in 0..header.len() {
+        let name = match header[index].as_str() {
+            "Buy" | "Sell" => format!("{} Amount", header[index]),
+            "Cur." if index > 0 => {
+                let before = &header[index - 1];
+                format!(
+                    "{} Currency",
+                    before.strip_suffix(" Amount").unwrap_or(before)
+                )
+            }
+            _ => continue,
+        };
+        header[index] = name;
+    }
+    table.with_column_names(header)
+}
+
+/// An amount of a currency in a row, if it has one
+fn amount<'a>(
+    table: &Table,
+    row: &'a Row,
+    amount: &str,
+    currency: &str,
+) -> Result<Option<(&'a str, Decimal)>> {
+    let text = table.get(row, amount)?;
+    let currency = table.get(row, currency)?;
+    if text.is_empty() || currency.is_empty() {
+        return Ok(None);
+    }
+    let value: Decimal = text
+        .parse()
+        .or_else(|_| Decimal::from_scientific(text))
+        .map_err(|e| {
+            table.error(
+                row,
+                t!(
+                    "invalid decimal \"{}\" in field \"{}\": {}",
+                    text,
+                    amount,
+                    e
+                ),
+            )
+        })?;
+    Ok(Some((currency, value.abs())))
+}
+
+fn time(table: &Table, row: &Row) -> Result<DateTime<Utc>> {
+    let text = table.get(row, "Date")?;
+    DATE_FORMATS[1..]
+        .iter()
+        .fold(
+            NaiveDateTime::parse_from_str(text, DATE_FORMATS[0]),
+            |time, format| time.or_else(|_| NaiveDateTime::parse_from_str(text, format)),
+        )
+        .map(|time| time.and_utc())
+        .map_err(|e| table.error(row, t!("invalid time \"{}\" in {}: {}", text, "Date", e)))
+}
+
+/// Converts the rows of `wallet` in a CoinTracking trade list to the typed
+/// entries of [`Activity`]. A `Trade` is a sale if it is of a crypto asset
+/// for fiat, or else a buy of the asset received; its fee counts if it is in
+/// either asset, valued at the price of the trade if it is in the base one.
+/// The `Date` is taken as UTC, the time zone the account must be set to
+/// before the export.
+fn normalize(table: &Table, wallet: &str) -> Result<Activity> {
+    table.require_columns(&COLUMNS)?;
+    let mut seen = HashMap::new();
+    let mut activity = Activity::default();
+    for row in &table.rows {
+        let mut ids = vec![row_id("cointracking", row, &mut seen)];
+        if !table
+            .get(row, "Exchange")?
+            .eq_ignore_ascii_case(wallet.trim())
+        {
+            continue;
+        }
+        let kind = table.get(row, "Type")?;
+        if !matches!(kind, "Trade" | "Deposit" | "Withdrawal") {
+            continue;
+        }
+        if table.has_column(TX_ID) {
+            ids.extend(
+                Some(table.get(row, TX_ID)?)
+                    .filter(|hash| !hash.is_empty())
+                    .map(String::from),
+            );
+        }
+        let time = time(table, row)?;
+        let bought = amount(table, row, "Buy Amount", "Buy Currency")?;
+        let sold = amount(table, row, "Sell Amount", "Sell Currency")?;
+        let fee = amount(table, row, "Fee", "Fee Currency")?;
+        let fee_in = |currency: &str| {
+            fee.filter(|(fee_currency, _)| fee_currency.eq_ignore_ascii_case(currency))
+                .map_or(Decimal::ZERO, |(_, fee)| fee)
+        };
+        let unexpected = || table.error(row, t!("unexpected {} entry: {}", "CoinTracking", kind));
+        match (kind, sold, bought) {
+            ("Trade", Some((from, from_amount)), Some((to, to_amount))) => {
+                let (side, base, quote, volume, cost) = if is_fiat(to) && !is_fiat(from) {
+                    (Side::Sell, from, to, from_amount, to_amount)
+                } else {
+                    (Side::Buy, to, from, to_amount, from_amount)
+                };
+                let price = cost.checked_div(volume).ok_or_else(unexpected)?;
+                activity.trades.push(NormalizedTrade {
+                    base: base.to_string(),
+                    quote: quote.to_string(),
+                    side,
+                    volume,
+                    cost,
+                    fee: fee_in(quote) + fee_in(base) * price,
+                    credit_fee_usd: Decimal::ZERO,
+                    price,
+                    time,
+                    ids,
+                });
+            }
+            ("Deposit", None, Some((asset, amount))) => activity.deposits.push(NormalizedDeposit {
+                asset: asset.to_string(),
+                amount,
+                fee: fee_in(asset),
+                time,
+                ids,
+                origin_exchange: None,
+                origin_wallet: None,
+                network: None,
+            }),
+            ("Withdrawal", Some((asset, amount)), None) => {
+                activity.withdrawals.push(NormalizedWithdrawal {
+                    asset: asset.to_string(),
+                    amount,
+                    fee: fee_in(asset),
+                    time,
+                    ids,
+                    destination: None,
+                    network: None,
+                })
+            }
+            _ => return Err(unexpected()),
+        }
+    }
+    activity.deposits.sort_by_key(|d| d.time);
+    activity.withdrawals.sort_by_key(|w| w.time);
+    activity.trades.sort_by_key(|t| t.time);
+    Ok(activity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_normalize() {
+        let text = "\"Type\",\"Buy\",\"Cur.\",\"Sell\",\"Cur.\",\"Fee\",\"Cur.\",\"Exchange\",\"Group\",\"Comment\",\"Date\"\n\
+            \"Deposit\",\"1000\",\"USDT\",\"\",\"\",\"\",\"\",\"Binance\",\"\",\"\",\"01.03.2024 12:00:00\"\n\
+            \"Trade\",\"0.01\",\"BTC\",\"600\",\"USDT\",\"0.00001\",\"BTC\",\"Binance\",\"\",\"\",\"02.03.2024 12:00:00\"\n\
+            \"Trade\",\"310\",\"EUR\",\"0.005\",\"BTC\",\"0.31\",\"EUR\",\"binance\",\"\",\"\",\"03.03.2024 12:00:00\"\n\
+            \"Withdrawal\",\"\",\"\",\"0.004\",\"BTC\",\"0.0001\",\"BTC\",\"Binance\",\"\",\"\",\"04.03.2024 12:00:00\"\n\
+            \"Staking\",\"0.1\",\"ETH\",\"\",\"\",\"\",\"\",\"Binance\",\"\",\"\",\"05.03.2024 12:00:00\"\n\
+            \"Trade\",\"3000\",\"USD\",\"1\",\"ETH\",\"\",\"\",\"Kraken\",\"\",\"\",\"06.03.2024 12:00:00\"\n";
+        let table = rename_columns(Table::parse(text, ',', "trade_list.csv").unwrap());
+        let activity = normalize(&table, "Binance").unwrap();
+
+        // The staking income is left out
+        assert_eq!(activity.deposits.len(), 1);
+        assert_eq!(activity.deposits[0].amount, dec!(1000));
+        let withdrawal = &activity.withdrawals[0];
+        assert_eq!(
+            (withdrawal.asset.as_str(), withdrawal.amount, withdrawal.fee),
+            ("BTC", dec!(0.004), dec!(0.0001))
+        );
+
+        // The trade on the other exchange is left out
+        let [buy, sell] = activity.trades.as_slice() else {
+            panic!("expected 2 trades, got {:?}", activity.trades);
+        };
+        assert_eq!(
+            (buy.side, buy.base.as_str(), buy.quote.as_str()),
+            (Side::Buy, "BTC", "USDT")
+        );
+        // A fee in the base asset, at the price of the trade
+        assert_eq!((buy.price, buy.fee), (dec!(60000), dec!(0.6)));
+        assert_eq!(
+            (sell.side, sell.volume, sell.cost, sell.fee),
+            (Side::Sell, dec!(0.005), dec!(310), dec!(0.31))
+        );
+        assert_eq!(
+            sell.time,
+            NaiveDate::from_ymd_opt(2024, 3, 3)
+                .unwrap()
+                .and_hms_opt(12, 0, 0)
+                .unwrap()
+                .and_utc()
+        );
+
+        // A deposit must have the amount bought
+        let text = "Type,Buy Amount,Buy Currency,Sell Amount,Sell Currency,Fee,Fee Currency,Exchange,Date\n\
+            Deposit,,,1,BTC,,,Binance,2024-03-01 12:00:00\n";
+        let table = rename_columns(Table::parse(text, ',', "trade_list.csv").unwrap());
+        let error = normalize(&table, "Binance").unwrap_err();
+        assert!(error.to_string().contains("line 2"));
+        let table = rename_columns(Table::parse("Date,Amount\n", ',', "trade_list.csv").unwrap());
+        assert!(normalize(&table, "Binance").is_err());
+    }
+}
@@ -0,0 +1,209 @@
+//! The configuration file, `config.json` in the configuration directory.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::env;
+use crate::error::{Error, Result};
+use crate::i18n::t;
+use crate::paths::{self, config_dir};
+
+/// Name of the configuration file, in the configuration directory
+const CONFIG_NAME: &str = "config.json";
+
+/// The person the reports are made for
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Declarant {
+    pub name: String,
+    /// Only the 11 digits, without punctuation
+    pub cpf: String,
+}
+
+/// Settings of one of the declarants the reports are made for, selected with
+/// `--profile`
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub declarant: Option<Declarant>,
+    /// File with the Kraken API keys of the declarant, instead of
+    /// `kraken_keys.json` in the configuration directory of the profile
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keyfile: Option<PathBuf>,
+    /// Where the reports given by a relative path are written
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_dir: Option<PathBuf>,
+}
+
+/// Settings kept in `config.json`, written by `init`
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// The declarant when no profile is selected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub declarant: Option<Declarant>,
+    /// The profiles, by name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Profile>,
+    /// Fiat currencies beyond the ones known, see [`crate::source::init_fiat`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fiat: Vec<String>,
+}
+
+impl Config {
+    /// Path of the configuration file
+    pub fn path() -> Option<PathBuf> {
+        config_dir().map(|dir| dir.join(CONFIG_NAME))
+    }
+
+    /// Loads the configuration file, or the default configuration if there is
+    /// none, then applies `KRAKEN2RFB_DECLARANT_NAME` and `KRAKEN2RFB_DECLARANT_CPF`.
+    /// With a profile selected, `declarant` is the one of the profile, which
+    /// must be in the file.
+    pub fn load() -> Result<Self> {
+        let mut config = Self::load_file()?;
+        if let Some(name) = paths::profile() {
+            let profile = config.profiles.get(name).ok_or_else(|| {
+                Error::Config(t!(
+                    "unknown profile: {}, run {} to create it",
+                    name,
+                    format!("kraken2rfb init --profile {}", name)
+                ))
+            })?;
+            config.declarant = profile.declarant.clone();
+        }
+        if let (Some(name), Some(cpf)) = (env::var("DECLARANT_NAME"), env::var("DECLARANT_CPF")) {
+            if !is_valid_cpf(&cpf) {
+                return Err(Error::Config(t!(
+                    "invalid value for {}: {}",
+                    env::name("DECLARANT_CPF"),
+                    t!("invalid CPF: {}", cpf)
+                )));
+            }
+            config.declarant = Some(Declarant { name, cpf });
+        }
+        Ok(config)
+    }
+
+    /// Loads only the configuration file, without the environment overrides
+    pub fn load_file() -> Result<Self> {
+        let Some(path) = Self::path() else {
+            return Ok(Config::default());
+        };
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let config: Config = serde_json::from_str(&content).map_err(|e| {
+            Error::Config(t!("invalid configuration file {}: {}", path.display(), e))
+        })?;
+        let declarants = config.declarant.iter().chain(
+            config
+                .profiles
+                .values()
+                .filter_map(|p| p.declarant.as_ref()),
+        );
+        for declarant in declarants {
+            if !is_valid_cpf(&declarant.cpf) {
+                return Err(Error::Config(t!(
+                    "invalid configuration file {}: {}",
+                    path.display(),
+                    t!("invalid CPF: {}", declarant.cpf)
+                )));
+            }
+        }
+        for name in config.profiles.keys() {
+            if !paths::is_valid_profile(name) {
+                return Err(Error::Config(t!(
+                    "invalid configuration file {}: {}",
+                    path.display(),
+                    t!(
+                        "invalid profile name: {} (use only letters, digits, '-' and '_')",
+                        name
+                    )
+                )));
+            }
+        }
+        Ok(config)
+    }
+
+    /// The settings of the selected profile, if any and in the file
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profiles.get(paths::profile()?)
+    }
+
+    pub fn save(&self) -> Result<PathBuf> {
+        let path = Self::path()
+            .ok_or_else(|| Error::Config(t!("neither XDG_CONFIG_HOME nor HOME is set")))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let content = serde_json::to_string_pretty(self)
+            .map_err(|e| Error::Validation(t!("failed to serialize {}: {}", CONFIG_NAME, e)))?;
+        fs::write(&path, content + "\n")?;
+        Ok(path)
+    }
+}
+
+/// Checks the two verification digits of a CPF, given as 11 digits
+pub fn is_valid_cpf(cpf: &str) -> bool {
+    let digits: Vec<u32> = cpf.chars().filter_map(|c| c.to_digit(10)).collect();
+    if digits.len() != 11 || cpf.len() != 11 || digits.iter().all(|&d| d == digits[0]) {
+        return false;
+    }
+    let check_digit = |len: usize| {
+        let sum: u32 = digits[..len]
+            .iter()
+            .zip((2..=len as u32 + 1).rev())
+            .map(|(d, weight)| d * weight)
+            .sum();
+        (sum * 10 % 11) % 10
+    };
+    check_digit(9) == digits[9] && check_digit(10) == digits[10]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cpf() {
+        assert!(is_valid_cpf("52998224725"));
+        assert!(is_valid_cpf("11144477735"));
+        assert!(!is_valid_cpf("52998224724"));
+        assert!(!is_valid_cpf("11111111111"));
+        assert!(!is_valid_cpf("529.982.247-25"));
+        assert!(!is_valid_cpf("5299822472"));
+    }
+
+    #[test]
+    fn test_parse() {
+        let config: Config =
+            serde_json::from_str(r#"{"declarant": {"name": "Maria", "cpf": "52998224725"}}"#)
+                .unwrap();
+        assert_eq!(config.declarant.unwrap().name, "Maria");
+        assert_eq!(
+            serde_json::from_str::<Config>("{}").unwrap(),
+            Config::default()
+        );
+        assert!(serde_json::from_str::<Config>(r#"{"declarnt": {}}"#).is_err());
+
+        let config: Config = serde_json::from_str(
+            r#"{"profiles": {"ana": {"declarant": {"name": "Ana", "cpf": "11144477735"},
+                "keyfile": "/keys/ana.json", "output_dir": "/reports/ana"}}}"#,
+        )
+        .unwrap();
+        let ana = &config.profiles["ana"];
+        assert_eq!(ana.declarant.as_ref().unwrap().cpf, "11144477735");
+        assert_eq!(ana.output_dir, Some(PathBuf::from("/reports/ana")));
+        assert_eq!(config.declarant, None);
+
+        let config: Config = serde_json::from_str(r#"{"fiat": ["PLN", "CZK"]}"#).unwrap();
+        assert_eq!(config.fiat, ["PLN", "CZK"]);
+    }
+}
@@ -0,0 +1,305 @@
+//! Importer of the transaction history of the Crypto.com App, the CSV file
+//! exported in Settings → Transaction history (the crypto wallet one, not the
+//! fiat or card ones).
+//!
+//! Each row has a `Transaction Kind`; the purchases, sales, conversions and
+//! crypto transfers are imported, and the other kinds (rewards, cashback,
+//! Earn and lockup moves, dust conversions) are left out of the activity.
+//! The App charges no separate fee: its spread is in the amounts.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use rust_decimal::Decimal;
+
+use crate::csv::{Row, Table, row_id};
+use crate::error::{Error, Result};
+use crate::i18n::t;
+use crate::report::transactions::ExchangeInfo;
+use crate::source::{
+    Activity, ExchangeSource, NormalizedDeposit, NormalizedTrade, NormalizedWithdrawal, Side,
+    is_fiat,
+};
+
+/// Columns of the export read by the importer
+const COLUMNS: [&str; 8] = [
+    "Timestamp (UTC)",
+    "Currency",
+    "Amount",
+    "To Currency",
+    "To Amount",
+    "Native Currency",
+    "Native Amount",
+    "Transaction Kind",
+];
+
+/// Optional column of the newer exports, with the hash of a crypto transfer
+const TRANSACTION_HASH: &str = "Transaction Hash";
+
+/// Source of the activity in a Crypto.com App export
+pub struct CryptoComAppSource {
+    /// The exported CSV file, or `None` if the user gave none
+    file: Option<PathBuf>,
+}
+
+impl CryptoComAppSource {
+    pub fn new(file: Option<PathBuf>) -> Self {
+        CryptoComAppSource { file }
+    }
+}
+
+impl ExchangeSource for CryptoComAppSource {
+    fn exchange(&self) -> ExchangeInfo {
+        ExchangeInfo {
+            name: "Crypto.com".to_string(),
+            url: "https://crypto.com".to_string(),
+            country: "MT".to_string(),
+        }
+    }
+
+    fn fetch(&self, first_day: NaiveDate, last_day: NaiveDate) -> Result<Activity> {
+        let file = self.file.as_deref().ok_or_else(|| {
+            Error::Config(t!(
+                "give the file exported from {} with --input",
+                "Crypto.com"
+            ))
+        })?;
+        Ok(normalize(&Table::read(file, ',')?)?.within(first_day, last_day))
+    }
+}
+
+/// A row of the export, with its fields read
+struct Entry<'a> {
+    time: DateTime<Utc>,
+    currency: &'a str,
+    amount: Decimal,
+    to_currency: &'a str,
+    to_amount: Option<Decimal>,
+    native_currency: &'a str,
+    native_amount: Option<Decimal>,
+    /// The ID of the row and, for a transfer, its transaction hash
+    ids: Vec<String>,
+}
+
+/// Reads a decimal of the export, which may be in scientific notation for the
+/// tiny amounts, or `None` for an empty field
+fn decimal(table: &Table, row: &Row, column: &str) -> Result<Option<Decimal>> {
+    let text = table.get(row, column)?;
+    if text.is_empty() {
+        return Ok(None);
+    }
+    text.parse()
+        .or_else(|_| Decimal::from_scientific(text))
+        .map(Some)
+        .map_err(|e| {
+            table.error(
+                row,
+                t!(
+                    "invalid decimal \"{}\" in field \"{}\": {}",
+                    text,
+                    column,
+                    e
+                ),
+            )
+        })
+}
+
+fn entry<'a>(table: &Table, row: &'a Row, id: String) -> Result<Entry<'a>> {
+    let text = table.get(row, "Timestamp (UTC)")?;
+    let time = NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S")
+        .map_err(|e| {
+            table.error(
+                row,
+                t!("invalid time \"{}\" in {}: {}", text, "Timestamp (UTC)", e),
+            )
+        })?
+        .and_utc();
+    let mut ids = vec![id];
+    if table.has_column(TRANSACTION_HASH) {
+        ids.extend(
+            Some(table.get(row, TRANSACTION_HASH)?)
+                .filter(|hash| !hash.is_empty())
+                .map(String::from),
+        );
+    }
+    Ok(Entry {
+        time,
+        currency: table.get(row, "Currency")?,
+        amount: decimal(table, row, "Amount")?
+            .ok_or_else(|| table.error(row, t!("missing field \"{}\"", "Amount")))?,
+        to_currency: table.get(row, "To Currency")?,
+        to_amount: decimal(table, row, "To Amount")?,
+        native_currency: table.get(row, "Native Currency")?,
+        native_amount: decimal(table, row, "Native Amount")?,
+        ids,
+    })
+}
+
+/// A trade of `from_amount` of `from` for `to_amount` of `to`: a sale if it
+/// is of a crypto asset for fiat, or else a buy of the asset received
+fn conversion(
+    entry: Entry,
+    from: &str,
+    from_amount: Decimal,
+    to: &str,
+    to_amount: Decimal,
+) -> Option<NormalizedTrade> {
+    let (side, base, quote, volume, cost) = if is_fiat(to) && !is_fiat(from) {
+        (Side::Sell, from, to, from_amount, to_amount)
+    } else {
+        (Side::Buy, to, from, to_amount, from_amount)
+    };
+    Some(NormalizedTrade {
+        base: base.to_string(),
+        quote: quote.to_string(),
+        side,
+        volume,
+        cost,
+        fee: Decimal::ZERO,
+        credit_fee_usd: Decimal::ZERO,
+        price: cost.checked_div(volume)?,
+        time: entry.time,
+        ids: entry.ids,
+    })
+}
+
+/// Converts the rows of a Crypto.com App export to the typed entries of
+/// [`Activity`], by their `Transaction Kind`:
+///
+/// - `crypto_deposit`, and `exchange_to_crypto_transfer` from the Crypto.com
+///   Exchange, are deposits; `crypto_withdrawal` and
+///   `crypto_to_exchange_transfer` are withdrawals
+/// - `crypto_purchase` is a buy of the `Currency` for the `Native Amount`
+///   charged to the card, and `card_top_up` a sale of it for the amount
+///   added to the card
+/// - `viban_purchase`, `crypto_viban_exchange` and `crypto_exchange` convert
+///   the `Currency` to the `To Currency`
+fn normalize(table: &Table) -> Result<Activity> {
+    table.require_columns(&COLUMNS)?;
+    let mut seen = HashMap::new();
+    let mut activity = Activity::default();
+    for row in &table.rows {
+        let id = row_id("cryptocom", row, &mut seen);
+        let kind = table.get(row, "Transaction Kind")?;
+        let unexpected = || table.error(row, t!("unexpected {} entry: {}", "Crypto.com", kind));
+        match kind {
+            "crypto_deposit" | "exchange_to_crypto_transfer" => {
+                let entry = entry(table, row, id)?;
+                activity.deposits.push(NormalizedDeposit {
+                    asset: entry.currency.to_string(),
+                    amount: entry.amount.abs(),
+                    fee: Decimal::ZERO,
+                    time: entry.time,
+                    ids: entry.ids,
+                    origin_exchange: None,
+                    origin_wallet: None,
+                    network: None,
+                });
+            }
+            "crypto_withdrawal" | "crypto_to_exchange_transfer" => {
+                let entry = entry(table, row, id)?;
+                activity.withdrawals.push(NormalizedWithdrawal {
+                    asset: entry.currency.to_string(),
+                    amount: entry.amount.abs(),
+                    fee: Decimal::ZERO,
+                    time: entry.time,
+                    ids: entry.ids,
+                    destination: None,
+                    network: None,
+                });
+            }
+            "crypto_purchase" | "card_top_up" => {
+                let entry = entry(table, row, id)?;
+                let (asset, amount) = (entry.currency, entry.amount.abs());
+                let native = entry.native_currency;
+                let value = entry.native_amount.ok_or_else(unexpected)?.abs();
+                let trade = if kind == "crypto_purchase" {
+                    conversion(entry, native, value, asset, amount)
+                } else {
+                    conversion(entry, asset, amount, native, value)
+                };
+                activity.trades.push(trade.ok_or_else(unexpected)?);
+            }
+            "viban_purchase" | "crypto_viban_exchange" | "crypto_exchange" => {
+                let entry = entry(table, row, id)?;
+                let (from, from_amount) = (entry.currency, entry.amount.abs());
+                let to = entry.to_currency;
+                let to_amount = entry.to_amount.ok_or_else(unexpected)?.abs();
+                if to.is_empty() {
+                    return Err(unexpected());
+                }
+                let trade = conversion(entry, from, from_amount, to, to_amount);
+                activity.trades.push(trade.ok_or_else(unexpected)?);
+            }
+            _ => {}
+        }
+    }
+    activity.deposits.sort_by_key(|d| d.time);
+    activity.withdrawals.sort_by_key(|w| w.time);
+    activity.trades.sort_by_key(|t| t.time);
+    Ok(activity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    const HEADER: &str = "Timestamp (UTC),Transaction Description,Currency,Amount,To Currency,To Amount,Native Currency,Native Amount,Native Amount (in USD),Transaction Kind,Transaction Hash\n";
+
+    #[test]
+    fn test_normalize() {
+        let text = HEADER.to_string()
+            + "2024-03-05 10:00:00,BTC -> EUR,BTC,-0.01,EUR,550.5,BRL,3000,600,crypto_viban_exchange,\n\
+               2024-03-04 09:00:00,Buy BTC,BTC,0.02,,,BRL,6000,1200,crypto_purchase,\n\
+               2024-03-03 08:00:00,BTC -> ETH,BTC,-0.01,ETH,0.2,BRL,3000,600,crypto_exchange,\n\
+               2024-03-02 07:00:00,Withdraw ETH,ETH,-0.1,,,BRL,1500,300,crypto_withdrawal,0xabc\n\
+               2024-03-01 06:00:00,CRO Stake Rewards,CRO,1.5,,,BRL,2,0.4,mco_stake_reward,\n\
+               2024-03-01 06:00:00,Deposit BTC,BTC,1E-8,,,BRL,0.003,0.0006,crypto_deposit,\n\
+               2024-03-01 06:00:00,Deposit BTC,BTC,1E-8,,,BRL,0.003,0.0006,crypto_deposit,\n";
+        let table = Table::parse(&text, ',', "crypto_transactions.csv").unwrap();
+        let activity = normalize(&table).unwrap();
+
+        // Identical rows are told apart by a counter in their IDs
+        let [first, second] = activity.deposits.as_slice() else {
+            panic!("expected 2 deposits, got {:?}", activity.deposits);
+        };
+        assert_eq!(first.amount, dec!(0.00000001));
+        assert_eq!(format!("{}:1", first.ids[0]), second.ids[0]);
+        let withdrawal = &activity.withdrawals[0];
+        assert_eq!(
+            (withdrawal.asset.as_str(), withdrawal.amount),
+            ("ETH", dec!(0.1))
+        );
+        assert_eq!(withdrawal.ids[1], "0xabc");
+
+        let [convert, buy, sell] = activity.trades.as_slice() else {
+            panic!("expected 3 trades, got {:?}", activity.trades);
+        };
+        assert_eq!(
+            (convert.side, convert.base.as_str(), convert.quote.as_str()),
+            (Side::Buy, "ETH", "BTC")
+        );
+        assert_eq!((convert.volume, convert.price), (dec!(0.2), dec!(0.05)));
+        assert_eq!(
+            (buy.side, buy.base.as_str(), buy.quote.as_str(), buy.cost),
+            (Side::Buy, "BTC", "BRL", dec!(6000))
+        );
+        assert_eq!(
+            (sell.side, sell.quote.as_str(), sell.volume, sell.cost),
+            (Side::Sell, "EUR", dec!(0.01), dec!(550.5))
+        );
+
+        let table = Table::parse(
+            &(HEADER.to_string() + "2024-03-05 10:00:00,,BTC,-0.01,,,BRL,,,crypto_exchange,\n"),
+            ',',
+            "crypto_transactions.csv",
+        )
+        .unwrap();
+        let error = normalize(&table).unwrap_err();
+        assert!(error.to_string().contains("line 2"));
+        let table = Table::parse("Date,Amount\n", ',', "fiat_transactions.csv").unwrap();
+        assert!(normalize(&table).is_err());
+    }
+}
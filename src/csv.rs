@@ -0,0 +1,248 @@
+//! Reader of the CSV files exported by exchanges and wallets, for the file
+//! importers: fields separated by a delimiter, optionally quoted with `"`
+//! (a quote inside doubled), and a header row naming the columns.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+use crate::error::{Error, Result};
+use crate::i18n::t;
+
+/// A row of a CSV file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    /// Line of the file the row starts at, from 1
+    pub line: usize,
+    pub fields: Vec<String>,
+}
+
+/// A CSV file, read whole
+#[derive(Debug, Clone)]
+pub struct Table {
+    /// Name of the file, for the error messages
+    pub name: String,
+    pub header: Vec<String>,
+    pub rows: Vec<Row>,
+    columns: HashMap<String, usize>,
+}
+
+/// Splits CSV text in rows of fields
+fn parse_rows(text: &str, delimiter: char, name: &str) -> Result<Vec<Row>> {
+    let mut rows = Vec::new();
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut line = 1;
+    let mut row_line = 1;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => quoted = true,
+            '\n' if quoted => {
+                line += 1;
+                field.push(c);
+            }
+            c if c == delimiter && !quoted => fields.push(std::mem::take(&mut field)),
+            '\r' if !quoted && chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                fields.push(std::mem::take(&mut field));
+                let row = std::mem::take(&mut fields);
+                // Blank lines are skipped
+                if row.len() > 1 || !row[0].is_empty() {
+                    rows.push(Row {
+                        line: row_line,
+                        fields: row,
+                    });
+                }
+                line += 1;
+                row_line = line;
+            }
+            c => field.push(c),
+        }
+    }
+    if quoted {
+        return Err(Error::Validation(t!(
+            "{}, line {}: unterminated quoted field",
+            name,
+            row_line
+        )));
+    }
+    if !field.is_empty() || !fields.is_empty() {
+        fields.push(field);
+        rows.push(Row {
+            line: row_line,
+            fields,
+        });
+    }
+    Ok(rows)
+}
+
+impl Table {
+    /// Parses CSV text, whose first row is the header
+    pub fn parse(text: &str, delimiter: char, name: &str) -> Result<Self> {
+        let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+        Table::from_rows(name.to_string(), parse_rows(text, delimiter, name)?)
+    }
+
+    /// The table of some rows, the first being the header
+    fn from_rows(name: String, rows: Vec<Row>) -> Result<Self> {
+        let mut rows = rows.into_iter();
+        let header: Vec<String> = rows
+            .next()
+            .ok_or_else(|| Error::Validation(t!("{} is empty", name)))?
+            .fields
+            .into_iter()
+            .map(|column| column.trim().to_string())
+            .collect();
+        let columns = header
+            .iter()
+            .enumerate()
+            .map(|(index, column)| (column.clone(), index))
+            .collect();
+        Ok(Table {
+            name,
+            header,
+            rows: rows.collect(),
+            columns,
+        })
+    }
+
+    /// The table whose header is the first row with a column, for the exports
+    /// with a few lines of title before the header; the same table if the
+    /// header already has it or no row does
+    pub fn with_header(self, column: &str) -> Self {
+        if self.has_column(column) {
+            return self;
+        }
+        let Some(index) = self
+            .rows
+            .iter()
+            .position(|row| row.fields.iter().any(|field| field.trim() == column))
+        else {
+            return self;
+        };
+        let mut rows = self.rows;
+        rows.drain(..index);
+        Table::from_rows(self.name, rows).expect("the header row is there")
+    }
+
+    /// The table with another header, of the same number of columns, for the
+    /// exports whose header repeats a name
+    pub fn with_column_names(mut self, header: Vec<String>) -> Self {
+        assert_eq!(header.len(), self.header.len());
+        self.columns = header
+            .iter()
+            .enumerate()
+            .map(|(index, column)| (column.clone(), index))
+            .collect();
+        self.header = header;
+        self
+    }
+
+    /// Reads and parses a CSV file
+    pub fn read(path: &Path, delimiter: char) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| Error::Config(t!("failed to read {}: {}", path.display(), e)))?;
+        Table::parse(&text, delimiter, &path.display().to_string())
+    }
+
+    /// Whether the header has a column
+    pub fn has_column(&self, column: &str) -> bool {
+        self.columns.contains_key(column)
+    }
+
+    /// Fails unless the header has all the columns, as a sign of the file not
+    /// being the expected export
+    pub fn require_columns(&self, columns: &[&str]) -> Result<()> {
+        let missing: Vec<_> = columns.iter().filter(|c| !self.has_column(c)).collect();
+        if missing.is_empty() {
+            return Ok(());
+        }
+        Err(Error::Validation(t!(
+            "{} has no column {}",
+            self.name,
+            missing
+                .iter()
+                .map(|c| format!("\"{}\"", c))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )))
+    }
+
+    /// The field of a row in a column, empty if the row is short of it
+    pub fn get<'a>(&self, row: &'a Row, column: &str) -> Result<&'a str> {
+        let index = *self
+            .columns
+            .get(column)
+            .ok_or_else(|| Error::Validation(t!("{} has no column \"{}\"", self.name, column)))?;
+        Ok(row.fields.get(index).map_or("", |field| field.trim()))
+    }
+
+    /// An error about a row of the table
+    pub fn error(&self, row: &Row, message: String) -> Error {
+        Error::Validation(t!("{}, line {}: {}", self.name, row.line, message))
+    }
+}
+
+/// An ID for a row of a file that has none: `<prefix>:` and a hash of its
+/// fields, followed by how many identical rows came before it, if any, so the
+/// IDs stay the same when the file is exported again
+pub fn row_id(prefix: &str, row: &Row, seen: &mut HashMap<String, usize>) -> String {
+    let digest = Sha256::digest(row.fields.join("\u{1f}").as_bytes());
+    let hash: String = digest[..8]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    let count = seen.entry(hash.clone()).or_default();
+    *count += 1;
+    match *count {
+        1 => format!("{}:{}", prefix, hash),
+        n => format!("{}:{}:{}", prefix, hash, n - 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let text = "\u{feff}Date,Note,Amount\r\n2024-03-01,\"a, \"\"quoted\"\"\nnote\",1.5\r\n\r\n2024-03-02,,-2\n2024-03-03";
+        let table = Table::parse(text, ',', "export.csv").unwrap();
+        assert_eq!(table.header, ["Date", "Note", "Amount"]);
+        let [first, second, third] = table.rows.as_slice() else {
+            panic!("expected 3 rows, got {:?}", table.rows);
+        };
+        assert_eq!(table.get(first, "Note").unwrap(), "a, \"quoted\"\nnote");
+        assert_eq!(second.line, 5);
+        assert_eq!(table.get(second, "Amount").unwrap(), "-2");
+        // A short row has empty fields
+        assert_eq!(table.get(third, "Amount").unwrap(), "");
+        assert!(table.get(first, "Fee").is_err());
+        assert!(table.require_columns(&["Date", "Amount"]).is_ok());
+        let error = table.require_columns(&["Date", "Fee"]).unwrap_err();
+        assert!(error.to_string().contains("\"Fee\""));
+
+        let table = Table::parse("a;b\n1,5;2\n", ';', "export.csv").unwrap();
+        assert_eq!(table.get(&table.rows[0], "a").unwrap(), "1,5");
+        let table = Table::parse(
+            "Report of 2024\n\nDate,Amount\n2024-03-01,1\n",
+            ',',
+            "export.csv",
+        )
+        .unwrap()
+        .with_header("Date");
+        assert_eq!(table.header, ["Date", "Amount"]);
+        assert_eq!(table.rows[0].line, 4);
+        assert!(Table::parse("a,b\n\"1,2\n", ',', "export.csv").is_err());
+        assert!(Table::parse("", ',', "export.csv").is_err());
+    }
+}
@@ -0,0 +1,504 @@
+//! Importer of the CSV files of exchanges without a source of their own,
+//! read by a mapping file: a JSON document naming the exchange and saying
+//! which column has each field of an operation, how dates and decimals are
+//! written and which values of the type column are purchases, sales, deposits
+//! or withdrawals. For example, for an export like
+//! `Data;Tipo;Moeda;Quantidade;Total;Taxa`:
+//!
+//! ```json
+//! {
+//!   "exchange": {"name": "Exemplo", "url": "https://exemplo.com", "country": "SC"},
+//!   "delimiter": ";",
+//!   "decimal_separator": ",",
+//!   "date_format": "%d/%m/%Y %H:%M",
+//!   "utc_offset": "-03:00",
+//!   "quote_asset": "USDT",
+//!   "columns": {
+//!     "time": "Data", "type": "Tipo", "asset": "Moeda",
+//!     "amount": "Quantidade", "cost": "Total", "fee": "Taxa"
+//!   },
+//!   "types": {"Compra": "buy", "Venda": "sell", "Depósito": "deposit", "Saque": "withdrawal", "Bônus": "ignore"}
+//! }
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, Utc};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::csv::{Row, Table, row_id};
+use crate::error::{Error, Result};
+use crate::i18n::t;
+use crate::report::transactions::ExchangeInfo;
+use crate::source::{
+    Activity, ExchangeSource, NormalizedDeposit, NormalizedTrade, NormalizedWithdrawal, Side,
+};
+
+/// What a row is, by the value of its type column
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    Buy,
+    Sell,
+    /// A buy if the amount is positive, or else a sell
+    Trade,
+    Deposit,
+    Withdrawal,
+    /// A deposit if the amount is positive, or else a withdrawal
+    Transfer,
+    /// Left out of the activity
+    Ignore,
+}
+
+/// The columns of the file each field of an operation is in
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Columns {
+    pub time: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// The asset bought, sold or transferred
+    pub asset: String,
+    /// Amount of the asset; its sign only matters to the `trade` and
+    /// `transfer` operations
+    pub amount: String,
+    /// The asset paid or received in a trade, unless the mapping has a
+    /// `quote_asset`
+    #[serde(default)]
+    pub quote: Option<String>,
+    /// Amount of the quote asset of a trade
+    #[serde(default)]
+    pub cost: Option<String>,
+    /// Quote asset per unit of the asset, when there is no `cost`
+    #[serde(default)]
+    pub price: Option<String>,
+    #[serde(default)]
+    pub fee: Option<String>,
+    /// The asset the fee is in: the quote asset of a trade, unless this
+    /// column says it is the asset traded
+    #[serde(default)]
+    pub fee_asset: Option<String>,
+    /// IDs of the rows; without it, IDs are made from the rows themselves
+    #[serde(default)]
+    pub id: Option<String>,
+}
+
+/// How to read the CSV files of an exchange
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Mapping {
+    pub exchange: ExchangeInfo,
+    #[serde(default = "default_delimiter")]
+    pub delimiter: char,
+    /// `.` or `,`; the other one is taken as a thousands separator and left
+    /// out
+    #[serde(default = "default_decimal_separator")]
+    pub decimal_separator: char,
+    /// The chrono format of the times, e.g. `%Y-%m-%d %H:%M:%S`, or of the
+    /// dates alone
+    pub date_format: String,
+    /// Offset from UTC of the times, e.g. `-03:00`, if they aren't in UTC
+    #[serde(default)]
+    pub utc_offset: Option<String>,
+    /// The quote asset of every trade, for the files without a quote column
+    #[serde(default)]
+    pub quote_asset: Option<String>,
+    pub columns: Columns,
+    /// The operation of each value of the type column; rows with another
+    /// value are an error
+    pub types: BTreeMap<String, Operation>,
+}
+
+fn default_delimiter() -> char {
+    ','
+}
+
+fn default_decimal_separator() -> char {
+    '.'
+}
+
+impl Mapping {
+    /// Reads a mapping file and checks that it can be used
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| Error::Config(t!("failed to read {}: {}", path.display(), e)))?;
+        let mapping: Mapping = serde_json::from_str(&content)
+            .map_err(|e| Error::Config(t!("invalid mapping file {}: {}", path.display(), e)))?;
+        mapping
+            .check()
+            .map_err(|e| Error::Config(t!("invalid mapping file {}: {}", path.display(), e)))?;
+        Ok(mapping)
+    }
+
+    /// Why the mapping can't be used, if it can't
+    fn check(&self) -> std::result::Result<(), String> {
+        if !matches!(self.decimal_separator, '.' | ',') {
+            return Err(t!(
+                "the decimal separator must be \".\" or \",\", not \"{}\"",
+                self.decimal_separator
+            ));
+        }
+        self.offset()?;
+        let trades = self
+            .types
+            .values()
+            .any(|op| matches!(op, Operation::Buy | Operation::Sell | Operation::Trade));
+        let columns = &self.columns;
+        if trades && columns.quote.is_none() && self.quote_asset.is_none() {
+            return Err(t!("trades need a quote column or a quote_asset"));
+        }
+        if trades && columns.cost.is_none() && columns.price.is_none() {
+            return Err(t!("trades need a cost or a price column"));
+        }
+        Ok(())
+    }
+
+    /// The offset from UTC of the times
+    fn offset(&self) -> std::result::Result<FixedOffset, String> {
+        match &self.utc_offset {
+            None => Ok(FixedOffset::east_opt(0).expect("zero is a valid offset")),
+            Some(offset) => offset
+                .parse()
+                .map_err(|_| t!("invalid UTC offset: {}", offset)),
+        }
+    }
+
+    /// The operation of a row, by its type column
+    fn operation(&self, kind: &str) -> Option<Operation> {
+        self.types
+            .iter()
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case(kind))
+            .map(|(_, &operation)| operation)
+    }
+}
+
+/// Source of the activity in a CSV file read by a [`Mapping`]
+pub struct CsvSource {
+    file: Option<PathBuf>,
+    mapping: Mapping,
+}
+
+impl CsvSource {
+    /// The source of `file`, or `None` if the user gave none
+    pub fn new(file: Option<PathBuf>, mapping: Mapping) -> Self {
+        CsvSource { file, mapping }
+    }
+}
+
+impl ExchangeSource for CsvSource {
+    fn exchange(&self) -> ExchangeInfo {
+        self.mapping.exchange.clone()
+    }
+
+    fn fetch(&self, first_day: NaiveDate, last_day: NaiveDate) -> Result<Activity> {
+        let file = self.file.as_deref().ok_or_else(|| {
+            Error::Config(t!(
+                "give the file exported from {} with --input",
+                self.mapping.exchange.name
+            ))
+        })?;
+        let table = Table::read(file, self.mapping.delimiter)?;
+        Ok(normalize(&self.mapping, &table)?.within(first_day, last_day))
+    }
+}
+
+/// Reads the rows of a table with a mapping
+struct Reader<'a> {
+    mapping: &'a Mapping,
+    table: &'a Table,
+    offset: FixedOffset,
+}
+
+impl Reader<'_> {
+    fn field<'r>(&self, row: &'r Row, column: &Option<String>) -> Result<Option<&'r str>> {
+        match column {
+            Some(column) => Ok(Some(self.table.get(row, column)?).filter(|text| !text.is_empty())),
+            None => Ok(None),
+        }
+    }
+
+    fn required<'r>(&self, row: &'r Row, column: &str) -> Result<&'r str> {
+        self.field(row, &Some(column.to_string()))?
+            .ok_or_else(|| self.table.error(row, t!("missing field \"{}\"", column)))
+    }
+
+    /// Reads a decimal written with the decimal separator of the mapping
+    fn decimal(&self, row: &Row, column: &Option<String>) -> Result<Option<Decimal>> {
+        let Some(text) = self.field(row, column)? else {
+            return Ok(None);
+        };
+        let thousands = if self.mapping.decimal_separator == ',' {
+            '.'
+        } else {
+            ','
+        };
+        let normalized: String = text
+            .chars()
+            .filter(|&c| c != thousands && !c.is_whitespace())
+            .map(|c| if c == ',' { '.' } else { c })
+            .collect();
+        normalized
+            .parse()
+            .or_else(|_| Decimal::from_scientific(&normalized))
+            .map(Some)
+            .map_err(|e| {
+                self.table.error(
+                    row,
+                    t!(
+                        "invalid decimal \"{}\" in field \"{}\": {}",
+                        text,
+                        column.as_deref().unwrap_or_default(),
+                        e
+                    ),
+                )
+            })
+    }
+
+    fn time(&self, row: &Row) -> Result<DateTime<Utc>> {
+        let column = &self.mapping.columns.time;
+        let text = self.required(row, column)?;
+        let format = &self.mapping.date_format;
+        let time = NaiveDateTime::parse_from_str(text, format)
+            .or_else(|e| match NaiveDate::parse_from_str(text, format) {
+                Ok(date) => Ok(date.and_hms_opt(0, 0, 0).expect("midnight is valid")),
+                Err(_) => Err(e),
+            })
+            .map_err(|e| {
+                self.table
+                    .error(row, t!("invalid time \"{}\" in {}: {}", text, column, e))
+            })?;
+        let time = time
+            .and_local_timezone(self.offset)
+            .single()
+            .expect("a fixed offset has no ambiguous times")
+            .with_timezone(&Utc);
+        DateTime::from_timestamp(time.timestamp(), 0)
+            .ok_or_else(|| Error::Validation(t!("timestamp out of range: {}", text)))
+    }
+}
+
+/// Converts the rows of a CSV file to the typed entries of [`Activity`], by
+/// the operation the mapping gives to their type. The fee is charged in the
+/// asset transferred, or in the quote asset of a trade unless its
+/// `fee_asset` is the asset traded, in which case it is valued at the price
+/// of the trade.
+fn normalize(mapping: &Mapping, table: &Table) -> Result<Activity> {
+    let columns = &mapping.columns;
+    let required: Vec<&str> = [
+        &columns.time,
+        &columns.kind,
+        &columns.asset,
+        &columns.amount,
+    ]
+    .into_iter()
+    .map(String::as_str)
+    .chain(
+        [
+            &columns.quote,
+            &columns.cost,
+            &columns.price,
+            &columns.fee,
+            &columns.fee_asset,
+            &columns.id,
+        ]
+        .into_iter()
+        .flatten()
+        .map(String::as_str),
+    )
+    .collect();
+    table.require_columns(&required)?;
+    let reader = Reader {
+        mapping,
+        table,
+        offset: mapping.offset().map_err(Error::Config)?,
+    };
+
+    let mut seen = HashMap::new();
+    let mut activity = Activity::default();
+    for row in &table.rows {
+        let kind = reader.required(row, &columns.kind)?;
+        let operation = mapping.operation(kind).ok_or_else(|| {
+            table.error(
+                row,
+                t!("unexpected {} entry: {}", &mapping.exchange.name, kind),
+            )
+        })?;
+        if operation == Operation::Ignore {
+            continue;
+        }
+        let ids = match reader.field(row, &columns.id)? {
+            Some(id) => vec![id.to_string()],
+            None => vec![row_id("csv", row, &mut seen)],
+        };
+        let time = reader.time(row)?;
+        let asset = reader.required(row, &columns.asset)?.to_string();
+        let amount = reader
+            .decimal(row, &Some(columns.amount.clone()))?
+            .ok_or_else(|| table.error(row, t!("missing field \"{}\"", &columns.amount)))?;
+        let fee = reader.decimal(row, &columns.fee)?.unwrap_or_default().abs();
+        let operation = match operation {
+            Operation::Trade if amount.is_sign_negative() => Operation::Sell,
+            Operation::Trade => Operation::Buy,
+            Operation::Transfer if amount.is_sign_negative() => Operation::Withdrawal,
+            Operation::Transfer => Operation::Deposit,
+            operation => operation,
+        };
+        let amount = amount.abs();
+        match operation {
+            Operation::Deposit => activity.deposits.push(NormalizedDeposit {
+                asset,
+                amount,
+                fee,
+                time,
+                ids,
+                origin_exchange: None,
+                origin_wallet: None,
+                network: None,
+            }),
+            Operation::Withdrawal => activity.withdrawals.push(NormalizedWithdrawal {
+                asset,
+                amount,
+                fee,
+                time,
+                ids,
+                destination: None,
+                network: None,
+            }),
+            _ => {
+                let quote = match reader.field(row, &columns.quote)? {
+                    Some(quote) => quote.to_string(),
+                    None => mapping
+                        .quote_asset
+                        .clone()
+                        .ok_or_else(|| table.error(row, t!("missing field \"{}\"", "quote")))?,
+                };
+                let price = reader.decimal(row, &columns.price)?.map(|p| p.abs());
+                let cost = match reader.decimal(row, &columns.cost)? {
+                    Some(cost) => cost.abs(),
+                    None => {
+                        amount
+                            * price.ok_or_else(|| {
+                                table.error(row, t!("missing field \"{}\"", "cost"))
+                            })?
+                    }
+                };
+                let price = match price {
+                    Some(price) => price,
+                    None => cost.checked_div(amount).ok_or_else(|| {
+                        table.error(
+                            row,
+                            t!("unexpected {} entry: {}", &mapping.exchange.name, kind),
+                        )
+                    })?,
+                };
+                let fee_in_asset = reader
+                    .field(row, &columns.fee_asset)?
+                    .is_some_and(|fee_asset| fee_asset.eq_ignore_ascii_case(&asset));
+                activity.trades.push(NormalizedTrade {
+                    base: asset,
+                    quote,
+                    side: if operation == Operation::Sell {
+                        Side::Sell
+                    } else {
+                        Side::Buy
+                    },
+                    volume: amount,
+                    cost,
+                    fee: if fee_in_asset { fee * price } else { fee },
+                    credit_fee_usd: Decimal::ZERO,
+                    price,
+                    time,
+                    ids,
+                });
+            }
+        }
+    }
+    activity.deposits.sort_by_key(|d| d.time);
+    activity.withdrawals.sort_by_key(|w| w.time);
+    activity.trades.sort_by_key(|t| t.time);
+    Ok(activity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    const MAPPING: &str = r#"{
+        "exchange": {"name": "Exemplo", "url": "https://exemplo.com", "country": "SC"},
+        "delimiter": ";",
+        "decimal_separator": ",",
+        "date_format": "%d/%m/%Y %H:%M",
+        "utc_offset": "-03:00",
+        "quote_asset": "USDT",
+        "columns": {"time": "Data", "type": "Tipo", "asset": "Moeda", "amount": "Quantidade", "cost": "Total", "fee": "Taxa", "fee_asset": "Moeda da taxa"},
+        "types": {"Compra": "buy", "Venda": "sell", "Transferência": "transfer", "Bônus": "ignore"}
+    }"#;
+
+    #[test]
+    fn test_normalize() {
+        let mapping: Mapping = serde_json::from_str(MAPPING).unwrap();
+        assert_eq!(mapping.check(), Ok(()));
+        let table = Table::parse(
+            "Data;Tipo;Moeda;Quantidade;Total;Taxa;Moeda da taxa\n\
+             01/03/2024 21:30;Compra;BTC;0,01;1.200,50;0,0001;BTC\n\
+             02/03/2024 10:00;venda;BTC;-0,005;650;0,65;USDT\n\
+             03/03/2024 10:00;Transferência;BTC;-0,005;;;\n\
+             03/03/2024 11:00;Bônus;BTC;0,00001;;;\n",
+            ';',
+            "export.csv",
+        )
+        .unwrap();
+        let activity = normalize(&mapping, &table).unwrap();
+
+        let [buy, sell] = activity.trades.as_slice() else {
+            panic!("expected 2 trades, got {:?}", activity.trades);
+        };
+        // The times are converted to UTC
+        assert_eq!(buy.time.to_string(), "2024-03-02 00:30:00 UTC");
+        assert_eq!(
+            (buy.side, buy.quote.as_str(), buy.cost, buy.price),
+            (Side::Buy, "USDT", dec!(1200.50), dec!(120050))
+        );
+        // A fee in the asset bought, at the price of the trade
+        assert_eq!(buy.fee, dec!(12.005));
+        assert!(buy.ids[0].starts_with("csv:"));
+        assert_eq!(
+            (sell.side, sell.volume, sell.fee),
+            (Side::Sell, dec!(0.005), dec!(0.65))
+        );
+        let withdrawal = &activity.withdrawals[0];
+        assert_eq!((withdrawal.amount, withdrawal.fee), (dec!(0.005), dec!(0)));
+        assert!(activity.deposits.is_empty());
+
+        let table = Table::parse(
+            "Data;Tipo;Moeda;Quantidade;Total;Taxa;Moeda da taxa\n01/03/2024 21:30;Staking;BTC;1;;;\n",
+            ';',
+            "export.csv",
+        )
+        .unwrap();
+        let error = normalize(&mapping, &table).unwrap_err();
+        assert!(error.to_string().contains("Staking"));
+        let table = Table::parse("Data;Tipo\n", ';', "export.csv").unwrap();
+        assert!(normalize(&mapping, &table).is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        let mut mapping: Mapping = serde_json::from_str(MAPPING).unwrap();
+        mapping.quote_asset = None;
+        assert!(mapping.check().is_err());
+        mapping.quote_asset = Some("BRL".to_string());
+        mapping.utc_offset = Some("BRT".to_string());
+        assert!(mapping.check().is_err());
+        mapping.utc_offset = None;
+        mapping.decimal_separator = ';';
+        assert!(mapping.check().is_err());
+        assert!(
+            serde_json::from_str::<Mapping>(&MAPPING.replace("\"buy\"", "\"purchase\"")).is_err()
+        );
+    }
+}
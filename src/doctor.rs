@@ -0,0 +1,139 @@
+use std::path::Path;
+
+use chrono::{Days, Local};
+
+use kraken2rfb::config::Config;
+use kraken2rfb::error::{Error, Result};
+use kraken2rfb::exchange_rate::{get_exchange_rate_impl, rate_source};
+use kraken2rfb::i18n::t;
+use kraken2rfb::kraken::{REQUIRED_PERMISSIONS, api_keys_in_env, check_api_access, find_keyfile};
+use kraken2rfb::paths::config_dir;
+use kraken2rfb::style;
+
+/// Outcome of a single diagnostic: a description of what was found, or of
+/// what is wrong
+type Outcome = std::result::Result<String, String>;
+
+/// Checks everything a report run depends on and prints the outcome of each
+/// check. Fails if any check fails, so the command can be used in scripts.
+pub fn run_doctor(keyfile: Option<&Path>) -> Result<()> {
+    let mut checks: Vec<(String, Outcome)> = Vec::new();
+
+    match find_keyfile(keyfile) {
+        Ok(path) if api_keys_in_env() => {
+            checks.push((
+                t!("Key file"),
+                Ok(t!("not used, the API keys are in the environment")),
+            ));
+            check_kraken(&path, &mut checks);
+        }
+        Ok(path) if path.is_file() => {
+            checks.push((t!("Key file"), Ok(path.display().to_string())));
+            #[cfg(unix)]
+            checks.push((t!("Key file permissions"), check_permissions(&path)));
+            check_kraken(&path, &mut checks);
+        }
+        Ok(path) => checks.push((t!("Key file"), Err(t!("{} does not exist", path.display())))),
+        Err(e) => checks.push((t!("Key file"), Err(e.to_string()))),
+    }
+
+    // CoinGecko's free API only serves the last year, so ask for a recent date
+    let date = Local::now().date_naive() - Days::new(1);
+    for (provider, asset) in [("BCB", "USD"), ("CoinGecko", "BTC")] {
+        let outcome = get_exchange_rate_impl(date, asset)
+            .map(|(rate_date, rate)| {
+                t!(
+                    "{} on {}: {} BRL, from {}",
+                    asset,
+                    rate_date,
+                    rate,
+                    rate_source(asset)
+                )
+            })
+            .map_err(|e| e.to_string());
+        checks.push((provider.to_string(), outcome));
+    }
+
+    let outcome = match config_dir() {
+        Some(dir) if dir.is_dir() => Ok(dir.display().to_string()),
+        Some(dir) if dir.exists() => Err(t!("{} is not a directory", dir.display())),
+        Some(dir) => Ok(t!("{} (not created yet)", dir.display())),
+        None => Err(t!("neither XDG_CONFIG_HOME nor HOME is set")),
+    };
+    checks.push((t!("Configuration directory"), outcome));
+
+    let outcome = match (Config::load(), Config::path()) {
+        (Err(e), _) => Err(e.to_string()),
+        (Ok(_), Some(path)) if path.is_file() => Ok(path.display().to_string()),
+        (Ok(_), _) => Ok(t!("not created, run {} to create it", "kraken2rfb init")),
+    };
+    checks.push((t!("Configuration file"), outcome));
+
+    for (name, outcome) in &checks {
+        match outcome {
+            Ok(detail) => println!("[{}] {}: {}", style::success(&t!(" ok ")), name, detail),
+            Err(detail) => println!("[{}] {}: {}", style::failure(&t!("FAIL")), name, detail),
+        }
+    }
+
+    let failed = checks
+        .iter()
+        .filter(|(_, outcome)| outcome.is_err())
+        .count();
+    if failed > 0 {
+        return Err(Error::Config(t!(
+            "{} of {} checks failed",
+            failed,
+            checks.len()
+        )));
+    }
+    println!("{}", t!("All checks passed"));
+    Ok(())
+}
+
+/// The key file holds secrets, so it should be private to its owner
+#[cfg(unix)]
+fn check_permissions(path: &Path) -> Outcome {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = std::fs::metadata(path)
+        .map_err(|e| e.to_string())?
+        .permissions()
+        .mode();
+    if mode & 0o077 != 0 {
+        Err(t!(
+            "{} is accessible by other users (mode {}), run: chmod 600 {}",
+            path.display(),
+            format!("{:o}", mode & 0o777),
+            path.display()
+        ))
+    } else {
+        Ok(t!("only accessible by its owner"))
+    }
+}
+
+/// Checks that Kraken is reachable and that the keys have every permission needed
+fn check_kraken(keyfile: &Path, checks: &mut Vec<(String, Outcome)>) {
+    let results = match check_api_access(keyfile) {
+        Ok(results) => results,
+        Err(e) => {
+            checks.push((t!("Kraken API"), Err(e.to_string())));
+            return;
+        }
+    };
+
+    for ((endpoint, result), (_, permission)) in results.into_iter().zip(REQUIRED_PERMISSIONS) {
+        let name = t!("Kraken API {}", endpoint);
+        let outcome = match result {
+            Ok(()) => Ok(t!("\"{}\" permission granted", permission)),
+            // Authentication errors mean the key lacks the permission
+            Err(e @ Error::Config(_)) => Err(t!(
+                "{} (the key needs the \"{}\" permission)",
+                e,
+                permission
+            )),
+            Err(e) => Err(e.to_string()),
+        };
+        checks.push((name, outcome));
+    }
+}
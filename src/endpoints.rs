@@ -0,0 +1,89 @@
+//! Base URLs of the HTTP APIs the program talks to. They can be pointed
+//! elsewhere, e.g. to a local server replaying recorded responses in the tests,
+//! with [`set`] or the `KRAKEN2RFB_<API>_URL` environment variables.
+
+use std::sync::OnceLock;
+
+use crate::env;
+
+/// Base URLs, without a trailing slash
+#[derive(Debug, Clone, PartialEq)]
+pub struct Endpoints {
+    pub kraken: String,
+    pub bcb: String,
+    pub coingecko: String,
+    pub binance: String,
+    pub bitstamp: String,
+    pub bybit: String,
+    pub okx: String,
+    pub kucoin: String,
+    pub gemini: String,
+    pub gate: String,
+    /// The Esplora API of a block explorer
+    pub esplora: String,
+    /// An Etherscan compatible API
+    pub etherscan: String,
+}
+
+impl Default for Endpoints {
+    fn default() -> Self {
+        Endpoints {
+            kraken: "https://api.kraken.com".to_string(),
+            bcb: "https://api.bcb.gov.br".to_string(),
+            coingecko: "https://api.coingecko.com".to_string(),
+            binance: "https://api.binance.com".to_string(),
+            bitstamp: "https://www.bitstamp.net".to_string(),
+            bybit: "https://api.bybit.com".to_string(),
+            okx: "https://www.okx.com".to_string(),
+            kucoin: "https://api.kucoin.com".to_string(),
+            gemini: "https://api.gemini.com".to_string(),
+            gate: "https://api.gateio.ws".to_string(),
+            esplora: "https://mempool.space/api".to_string(),
+            etherscan: "https://api.etherscan.io".to_string(),
+        }
+    }
+}
+
+impl Endpoints {
+    /// The real APIs, unless overridden by `KRAKEN2RFB_KRAKEN_URL`,
+    /// `KRAKEN2RFB_BCB_URL`, `KRAKEN2RFB_COINGECKO_URL`,
+    /// `KRAKEN2RFB_ESPLORA_URL`, `KRAKEN2RFB_ETHERSCAN_URL` or the
+    /// `KRAKEN2RFB_<EXCHANGE>_URL` of another exchange, e.g.
+    /// `KRAKEN2RFB_BINANCE_URL`
+    pub fn from_env() -> Self {
+        let default = Endpoints::default();
+        let url = |name: &str, default: String| {
+            env::var(name).map_or(default, |url| url.trim_end_matches('/').to_string())
+        };
+        Endpoints {
+            kraken: url("KRAKEN_URL", default.kraken),
+            bcb: url("BCB_URL", default.bcb),
+            coingecko: url("COINGECKO_URL", default.coingecko),
+            binance: url("BINANCE_URL", default.binance),
+            bitstamp: url("BITSTAMP_URL", default.bitstamp),
+            bybit: url("BYBIT_URL", default.bybit),
+            okx: url("OKX_URL", default.okx),
+            kucoin: url("KUCOIN_URL", default.kucoin),
+            gemini: url("GEMINI_URL", default.gemini),
+            gate: url("GATE_URL", default.gate),
+            esplora: url("ESPLORA_URL", default.esplora),
+            etherscan: url("ETHERSCAN_URL", default.etherscan),
+        }
+    }
+}
+
+static ENDPOINTS: OnceLock<Endpoints> = OnceLock::new();
+
+/// Sets the endpoints used by every request. Must be called before the first
+/// request; otherwise the endpoints are already fixed and they are returned
+/// back as the error.
+// Giving the endpoints back is what `OnceLock::set` does, and `set` is called once
+#[allow(clippy::result_large_err)]
+pub fn set(endpoints: Endpoints) -> Result<(), Endpoints> {
+    ENDPOINTS.set(endpoints)
+}
+
+/// The endpoints in use, from [`Endpoints::from_env`] if [`set`] wasn't called
+pub fn get() -> &'static Endpoints {
+    ENDPOINTS.get_or_init(Endpoints::from_env)
+}
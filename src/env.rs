@@ -0,0 +1,52 @@
+//! Environment variables overriding the options, so the program can run in a
+//! container without mounting any file. They are all named `KRAKEN2RFB_<NAME>`,
+//! and the command line flags take precedence over them.
+
+use crate::error::{Error, Result};
+use crate::i18n::t;
+
+/// Prefix of the name of every variable
+pub const PREFIX: &str = "KRAKEN2RFB_";
+
+/// Full name of the variable for `name`, e.g. `KRAKEN2RFB_MIN_VALUE`
+pub fn name(name: &str) -> String {
+    format!("{}{}", PREFIX, name)
+}
+
+/// Value of `KRAKEN2RFB_<name>`, if set and not empty
+pub fn var(name: &str) -> Option<String> {
+    std::env::var(self::name(name))
+        .ok()
+        .filter(|value| !value.is_empty())
+}
+
+/// Value of a yes/no variable, false if not set
+pub fn flag(name: &str) -> Result<bool> {
+    var(name).map_or(Ok(false), |value| parse_flag(name, &value))
+}
+
+fn parse_flag(name: &str, value: &str) -> Result<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" => Ok(true),
+        "0" | "false" | "no" => Ok(false),
+        _ => Err(Error::Config(t!(
+            "invalid value for {}: {}",
+            self::name(name),
+            value
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_flag() {
+        assert!(parse_flag("DEBUG", "1").unwrap());
+        assert!(parse_flag("DEBUG", "True").unwrap());
+        assert!(!parse_flag("DEBUG", "no").unwrap());
+        assert!(parse_flag("DEBUG", "sometimes").is_err());
+        assert_eq!(name("DEBUG"), "KRAKEN2RFB_DEBUG");
+    }
+}
@@ -0,0 +1,94 @@
+//! The error type of the crate and the exit code of each kind of error.
+
+use std::io;
+use std::process::ExitCode;
+
+use chrono::NaiveDate;
+
+use crate::i18n::t;
+
+/// Errors that abort a run. The variants with fields carry what a caller needs
+/// to react to them, e.g. which asset had no rate; the others just a message.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Invalid command line, configuration or key file
+    #[error("{}", t!("configuration error: {}", .0))]
+    Config(String),
+    /// A request to an exchange, BCB or CoinGecko failed
+    #[error("{}", t!("network error: {}", .0))]
+    Network(String),
+    /// Kraken answered a request with errors, like "EAPI:Invalid key"
+    #[error("{}", t!("Kraken error on {}: {}", .endpoint, .errors.join(", ")))]
+    Kraken {
+        endpoint: String,
+        errors: Vec<String>,
+    },
+    /// Another exchange answered a request with an error
+    #[error("{}", t!("{} error on {}: {}", .exchange, .endpoint, .message))]
+    Exchange {
+        exchange: String,
+        endpoint: String,
+        message: String,
+        /// Whether the exchange rejected the API keys
+        auth: bool,
+    },
+    /// No BRL rate could be found for an asset
+    #[error("{}", t!("failed to get exchange rate for {} on {}: {}", .asset, .date, .reason))]
+    Rate {
+        asset: String,
+        date: NaiveDate,
+        reason: String,
+    },
+    /// A Kraken asset missing from the bundled metadata
+    #[error("{}", t!("invalid data: {}", t!("unknown Kraken asset: {}", .0)))]
+    UnknownAsset(String),
+    /// A Kraken pair missing from the bundled metadata
+    #[error("{}", t!("invalid data: {}", t!("unknown Kraken pair: {}", .0)))]
+    UnknownPair(String),
+    /// The fetched data is inconsistent or cannot be represented in the report
+    #[error("{}", t!("invalid data: {}", .0))]
+    Validation(String),
+    /// Failed to read or write a file
+    #[error("{}", t!("I/O error: {}", .0))]
+    Io(#[from] io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Process exit code for this error, so scripts can tell failures apart:
+    /// 2 for configuration, 3 for network, 4 for validation and 5 for I/O errors.
+    pub fn exit_code(&self) -> ExitCode {
+        ExitCode::from(match self {
+            Error::Config(_) => 2,
+            Error::Kraken { .. } | Error::Exchange { .. } if self.is_auth_error() => 2,
+            Error::Network(_)
+            | Error::Kraken { .. }
+            | Error::Exchange { .. }
+            | Error::Rate { .. } => 3,
+            Error::UnknownAsset(_) | Error::UnknownPair(_) | Error::Validation(_) => 4,
+            Error::Io(_) => 5,
+        })
+    }
+
+    /// Whether the exchange rejected the API keys, which is fixed by the user
+    /// and not by retrying
+    pub fn is_auth_error(&self) -> bool {
+        match self {
+            Error::Kraken { errors, .. } => errors.iter().any(|e| {
+                e.starts_with("EAPI:Invalid key")
+                    || e.starts_with("EAPI:Invalid signature")
+                    || e.starts_with("EGeneral:Permission denied")
+            }),
+            Error::Exchange { auth, .. } => *auth,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(feature = "http")]
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Network(e.to_string())
+    }
+}
@@ -0,0 +1,322 @@
+//! Scan of the Ethereum addresses of the user on an Etherscan compatible API:
+//! the ether and ERC-20 token transfers between them and the outside are the
+//! [`ChainTransfer`]s that [`crate::transfers::match_wallet`] matches to the
+//! deposits and withdrawals of an exchange. The API needs a key, from
+//! `KRAKEN2RFB_ETHERSCAN_API_KEY`.
+
+use std::collections::HashSet;
+
+use reqwest::blocking::Client;
+use rust_decimal::Decimal;
+use serde_json::Value;
+
+use crate::endpoints;
+use crate::env;
+use crate::error::{Error, Result};
+use crate::fields::{seconds_field, str_field};
+use crate::http;
+use crate::i18n::t;
+use crate::transfers::ChainTransfer;
+use crate::verify::ChainPayment;
+
+/// Entries asked for per page, the most Etherscan gives
+const PAGE_SIZE: usize = 1000;
+
+/// Decimals of the amounts of ether, given in wei
+const ETHER_DECIMALS: u32 = 18;
+
+fn etherscan_error(action: &str, message: impl std::fmt::Display) -> Error {
+    Error::Network(t!("block explorer error on {}: {}", action, message))
+}
+
+/// Fails unless the address is 0x and 40 hexadecimal digits, and returns it
+/// in lowercase, as the API does
+pub fn parse_address(text: &str) -> Result<String> {
+    let address = text.trim().to_lowercase();
+    match address.strip_prefix("0x") {
+        Some(hex) if hex.len() == 40 && hex.chars().all(|c| c.is_ascii_hexdigit()) => Ok(address),
+        _ => Err(Error::Config(t!("invalid Ethereum address: {}", text))),
+    }
+}
+
+/// The entries of an action of the account module on an address, oldest
+/// first, paged by block
+fn account_entries(client: &Client, key: &str, action: &str, address: &str) -> Result<Vec<Value>> {
+    let mut entries: Vec<Value> = Vec::new();
+    let mut start_block = 0;
+    loop {
+        let params = [
+            ("chainid", "1".to_string()),
+            ("module", "account".to_string()),
+            ("action", action.to_string()),
+            ("address", address.to_string()),
+            ("startblock", start_block.to_string()),
+            ("page", "1".to_string()),
+            ("offset", PAGE_SIZE.to_string()),
+            ("sort", "asc".to_string()),
+            ("apikey", key.to_string()),
+        ];
+        let request = client
+            .get(format!("{}/v2/api", endpoints::get().etherscan))
+            .query(&params);
+        let response = http::send(request)?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(etherscan_error(action, status));
+        }
+        let body: Value = response
+            .json()
+            .map_err(|e| etherscan_error(action, t!("invalid JSON: {}", e)))?;
+        // An address without entries is a "0" status with an empty result
+        let page = match &body["result"] {
+            Value::Array(page) => page,
+            result => {
+                let message = result
+                    .as_str()
+                    .or(body["message"].as_str())
+                    .unwrap_or_default();
+                return Err(etherscan_error(action, message));
+            }
+        };
+        // The next page starts again at the last block, whose entries may go
+        // on past the page
+        let last_block = page
+            .last()
+            .map(|entry| str_field(entry, "blockNumber"))
+            .transpose()?;
+        for entry in page {
+            if !entries
+                .iter()
+                .rev()
+                .take(PAGE_SIZE)
+                .any(|seen| seen == entry)
+            {
+                entries.push(entry.clone());
+            }
+        }
+        match last_block {
+            Some(block) if page.len() == PAGE_SIZE && block != start_block.to_string() => {
+                start_block = block
+                    .parse()
+                    .map_err(|_| etherscan_error(action, t!("invalid block: {}", block)))?;
+            }
+            _ => return Ok(entries),
+        }
+    }
+}
+
+fn api_key() -> Result<String> {
+    env::var("ETHERSCAN_API_KEY").ok_or_else(|| {
+        Error::Config(t!(
+            "the Etherscan API needs a key, given in {}",
+            env::name("ETHERSCAN_API_KEY")
+        ))
+    })
+}
+
+/// The result of a JSON-RPC call through the proxy module, null if the node
+/// knows nothing of it
+fn proxy(client: &Client, key: &str, action: &str, hash: &str) -> Result<Value> {
+    let params = [
+        ("chainid", "1"),
+        ("module", "proxy"),
+        ("action", action),
+        ("txhash", hash),
+        ("apikey", key),
+    ];
+    let request = client
+        .get(format!("{}/v2/api", endpoints::get().etherscan))
+        .query(&params);
+    let response = http::send(request)?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(etherscan_error(action, status));
+    }
+    let mut body: Value = response
+        .json()
+        .map_err(|e| etherscan_error(action, t!("invalid JSON: {}", e)))?;
+    // An error of the API, rather than of the node, comes as a message in
+    // the result
+    match body["result"].take() {
+        Value::String(message) if body["jsonrpc"].is_null() => {
+            Err(etherscan_error(action, message))
+        }
+        result => Ok(result),
+    }
+}
+
+/// What a transaction pays in ether, or `None` if the node doesn't know it.
+/// A failed transaction pays nothing.
+pub fn transaction_payments(hash: &str) -> Result<Option<Vec<ChainPayment>>> {
+    let key = api_key()?;
+    let client = http::client()?;
+    let transaction = proxy(client, &key, "eth_getTransactionByHash", hash)?;
+    if transaction.is_null() {
+        return Ok(None);
+    }
+    let receipt = proxy(client, &key, "eth_getTransactionReceipt", hash)?;
+    if receipt["status"].as_str() == Some("0x0") {
+        return Ok(Some(Vec::new()));
+    }
+    let value = str_field(&transaction, "value")?;
+    let amount = u128::from_str_radix(value.trim_start_matches("0x"), 16)
+        .ok()
+        .and_then(|wei| i128::try_from(wei).ok())
+        .and_then(|wei| Decimal::try_from_i128_with_scale(wei, ETHER_DECIMALS).ok())
+        .ok_or_else(|| Error::Validation(t!("invalid amount {} of {}", value, "ETH")))?
+        .normalize();
+    // A contract creation has no recipient
+    let address = transaction["to"]
+        .as_str()
+        .unwrap_or_default()
+        .to_lowercase();
+    Ok(Some(vec![ChainPayment { address, amount }]))
+}
+
+/// Scans the ether and token transfers of the addresses, and returns their
+/// transfers to and from the outside, oldest first
+pub fn scan_addresses(addresses: &[String]) -> Result<Vec<ChainTransfer>> {
+    let key = api_key()?;
+    let client = http::client()?;
+    let own: HashSet<String> = addresses
+        .iter()
+        .map(|address| parse_address(address))
+        .collect::<Result<_>>()?;
+    let mut transfers = Vec::new();
+    for address in &own {
+        for action in ["txlist", "tokentx"] {
+            for entry in account_entries(client, &key, action, address)? {
+                transfers.extend(wallet_transfer(&entry, &own)?);
+            }
+        }
+    }
+    transfers.sort_by(|a, b| (a.time, &a.txid, &a.address).cmp(&(b.time, &b.txid, &b.address)));
+    Ok(transfers)
+}
+
+/// The transfer of an entry of `txlist` or `tokentx` between the `own`
+/// addresses and the outside, if it is one: ether sent by a transaction that
+/// didn't fail, or a token moved
+fn wallet_transfer(entry: &Value, own: &HashSet<String>) -> Result<Option<ChainTransfer>> {
+    let from = str_field(entry, "from")?.to_lowercase();
+    let to = str_field(entry, "to")?.to_lowercase();
+    let (outgoing, address) = match (own.contains(&from), own.contains(&to)) {
+        (true, false) => (true, from),
+        (false, true) => (false, to),
+        _ => return Ok(None),
+    };
+    if entry["isError"].as_str() == Some("1") {
+        return Ok(None);
+    }
+    let (asset, decimals) = match entry["tokenSymbol"].as_str() {
+        Some(symbol) => {
+            let decimals = str_field(entry, "tokenDecimal")?;
+            let decimals = decimals
+                .parse()
+                .map_err(|_| etherscan_error("tokentx", t!("invalid decimals: {}", decimals)))?;
+            (symbol.to_uppercase(), decimals)
+        }
+        None => ("ETH".to_string(), ETHER_DECIMALS),
+    };
+    let value = str_field(entry, "value")?;
+    let amount = value
+        .parse::<i128>()
+        .ok()
+        .and_then(|value| Decimal::try_from_i128_with_scale(value, decimals).ok())
+        .ok_or_else(|| Error::Validation(t!("invalid amount {} of {}", value, asset)))?
+        .normalize();
+    if amount.is_zero() {
+        return Ok(None);
+    }
+    // The gas is paid in ether by the sender, so a token transfer has no fee
+    // in its asset
+    let fee = if outgoing && entry["tokenSymbol"].is_null() {
+        let (used, price) = (str_field(entry, "gasUsed")?, str_field(entry, "gasPrice")?);
+        used.parse::<i128>()
+            .ok()
+            .zip(price.parse::<i128>().ok())
+            .and_then(|(used, price)| used.checked_mul(price))
+            .and_then(|wei| Decimal::try_from_i128_with_scale(wei, ETHER_DECIMALS).ok())
+            .ok_or_else(|| etherscan_error("txlist", t!("invalid gas: {} at {}", used, price)))?
+            .normalize()
+    } else {
+        Decimal::ZERO
+    };
+    Ok(Some(ChainTransfer {
+        asset,
+        amount,
+        fee,
+        time: seconds_field(entry, "timeStamp")?,
+        txid: str_field(entry, "hash")?.to_string(),
+        address,
+        outgoing,
+        network: Some("Ethereum".to_string()),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    use serde_json::json;
+
+    const OWN: &str = "0x00000000000000000000000000000000000000aa";
+    const KRAKEN: &str = "0x00000000000000000000000000000000000000bb";
+
+    #[test]
+    fn test_wallet_transfer() {
+        let own: HashSet<String> = [OWN.to_string()].into();
+        let sent = json!({
+            "blockNumber": "19340000", "timeStamp": "1709294400", "hash": "0xab",
+            "from": OWN.to_uppercase().replace("0X", "0x"), "to": KRAKEN,
+            "value": "1500000000000000000", "isError": "0",
+            "gasUsed": "21000", "gasPrice": "20000000000",
+        });
+        let transfer = wallet_transfer(&sent, &own).unwrap().unwrap();
+        assert_eq!(
+            (transfer.asset.as_str(), transfer.amount, transfer.outgoing),
+            ("ETH", dec!(1.5), true)
+        );
+        assert_eq!(transfer.fee, dec!(0.00042));
+        assert_eq!(transfer.address, OWN);
+        assert_eq!(
+            transfer.time,
+            "2024-03-01T12:00:00Z"
+                .parse::<chrono::DateTime<chrono::Utc>>()
+                .unwrap()
+        );
+
+        let token = json!({
+            "blockNumber": "19340000", "timeStamp": "1709294400", "hash": "0xcd",
+            "from": KRAKEN, "to": OWN, "value": "250000000",
+            "tokenSymbol": "USDT", "tokenDecimal": "6",
+        });
+        let transfer = wallet_transfer(&token, &own).unwrap().unwrap();
+        assert_eq!(
+            (transfer.asset.as_str(), transfer.amount, transfer.outgoing),
+            ("USDT", dec!(250), false)
+        );
+        assert_eq!(transfer.fee, Decimal::ZERO);
+
+        // Failed, empty and unrelated transactions move nothing
+        let mut failed = sent.clone();
+        failed["isError"] = json!("1");
+        assert_eq!(wallet_transfer(&failed, &own).unwrap(), None);
+        let mut call = sent.clone();
+        call["value"] = json!("0");
+        assert_eq!(wallet_transfer(&call, &own).unwrap(), None);
+        let mut other = sent;
+        other["from"] = json!(KRAKEN);
+        assert_eq!(wallet_transfer(&other, &own).unwrap(), None);
+    }
+
+    #[test]
+    fn test_parse_address() {
+        assert_eq!(
+            parse_address(&OWN.to_uppercase().replace("0X", "0x")).unwrap(),
+            OWN
+        );
+        assert!(parse_address("0x1234").is_err());
+        assert!(parse_address("00000000000000000000000000000000000000aa").is_err());
+    }
+}
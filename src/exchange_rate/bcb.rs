@@ -0,0 +1,135 @@
+//! Fiat currency rates from the BCB (Banco Central do Brasil) API.
+
+use std::time::Duration;
+
+use chrono::{Local, NaiveDate};
+use rust_decimal::Decimal;
+use rust_decimal::prelude::*;
+use serde::Deserialize;
+
+use super::{CURRENCY_TO_BCB_SERIES, Reason, cache_ttl};
+use crate::endpoints;
+use crate::http_cache;
+use crate::i18n::t;
+
+/// How long a response with the rate of today is reused from the HTTP cache,
+/// as it may be published later; the ones of the past days don't change
+const CACHE_TTL: Duration = Duration::from_secs(6 * 60 * 60);
+
+#[derive(Debug, Deserialize)]
+struct BCBValue {
+    #[serde(deserialize_with = "deserialize_date")]
+    data: NaiveDate,
+    #[serde(deserialize_with = "deserialize_decimal")]
+    valor: Decimal,
+}
+
+fn deserialize_date<'de, D>(deserializer: D) -> std::result::Result<NaiveDate, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    NaiveDate::parse_from_str(&s, "%d/%m/%Y").map_err(serde::de::Error::custom)
+}
+
+fn deserialize_decimal<'de, D>(deserializer: D) -> std::result::Result<Decimal, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    Decimal::from_str(&s).map_err(serde::de::Error::custom)
+}
+
+/// Fetches the exchange rate from BCB for a given date and currency code.
+/// If the requested date is not a bank day, returns the most recent available rate.
+///
+/// # Arguments
+/// * `date` - The date to fetch the exchange rate for
+/// * `currency_code` - The currency code (e.g., "USD", "EUR", "JPY")
+///
+/// # Returns
+/// A tuple containing:
+/// * The actual date of the exchange rate (which might be the first bank day before the supplied date)
+/// * The exchange rate as a Decimal for maximum precision
+///
+/// # Errors
+/// Returns an error if:
+/// * The date is in the future
+/// * The API request fails
+/// * The response cannot be parsed
+/// * The currency code is not supported
+/// * No exchange rate data is available within the last 7 days
+pub(super) fn get_fiat_exchange_rate(
+    date: NaiveDate,
+    currency_code: &str,
+) -> std::result::Result<(NaiveDate, Decimal), Reason> {
+    let today = Local::now().date_naive();
+
+    if date > today {
+        return Err(t!("Cannot fetch exchange rate for future dates"));
+    }
+
+    let series_code = CURRENCY_TO_BCB_SERIES
+        .get(currency_code)
+        .ok_or_else(|| t!("Unsupported currency code: {}", currency_code))?;
+
+    // Request data for the last 7 days to ensure we get a valid bank day
+    let start_date = date - chrono::Duration::days(7);
+    let url = format!(
+        "{}/dados/serie/bcdata.sgs.{}/dados?formato=json&dataInicial={}&dataFinal={}",
+        endpoints::get().bcb,
+        series_code,
+        start_date.format("%d/%m/%Y"),
+        date.format("%d/%m/%Y")
+    );
+
+    let text = http_cache::get(&url, cache_ttl(date, CACHE_TTL))
+        .map_err(|e| e.to_string())?
+        .body;
+    let mut response: Vec<BCBValue> = serde_json::from_str(&text).map_err(|e| e.to_string())?;
+
+    if response.is_empty() {
+        return Err(t!(
+            "No exchange rate data available for {} within the last 7 days of {}",
+            currency_code,
+            date
+        ));
+    }
+
+    // Sort by date in descending order to get the most recent rate
+    response.sort_by_key(|v| std::cmp::Reverse(v.data));
+
+    let rate_data = &response[0];
+    Ok((rate_data.data, rate_data.valor))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalid_currency() {
+        let date = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        let result = get_fiat_exchange_rate(date, "INVALID");
+        assert!(result.is_err());
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("Unsupported currency code")
+        );
+    }
+
+    #[test]
+    fn test_future_date() {
+        let future_date = Local::now().date_naive() + chrono::Duration::days(1);
+        let result = get_fiat_exchange_rate(future_date, "USD");
+        assert!(result.is_err());
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("Cannot fetch exchange rate for future dates")
+        );
+    }
+}
@@ -0,0 +1,134 @@
+//! Crypto asset rates from the CoinGecko public API.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chrono::{Local, NaiveDate};
+use rust_decimal::Decimal;
+use rust_decimal::prelude::*;
+use serde::Deserialize;
+use serde_json::Number;
+
+use super::{Reason, cache_ttl};
+use crate::endpoints;
+use crate::http_cache;
+use crate::i18n::t;
+
+/// How long a response with the price of today is reused from the HTTP
+/// cache; the ones of the past days don't change
+const CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// CoinGecko API response for prices, kept as the numbers in the JSON so
+/// they aren't rounded to a float on the way
+#[derive(Debug, Deserialize)]
+struct CoinGeckoMarketData {
+    current_price: HashMap<String, Number>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CoinGeckoCoinData {
+    market_data: CoinGeckoMarketData,
+}
+
+/// CoinGecko API response for historical prices
+/// The structure is the same as the current price response
+type CoinGeckoHistoricalData = CoinGeckoCoinData;
+
+/// Fetches the historical exchange rate of a cryptocurrency against BRL from CoinGecko for a specific date.
+///
+/// # Arguments
+/// * `crypto_id` - The CoinGecko ID of the cryptocurrency (e.g., "bitcoin", "ethereum", "litecoin")
+/// * `date` - The date to fetch the exchange rate for
+///
+/// # Returns
+/// A tuple containing:
+/// * The actual date of the exchange rate (same as the input date if data is available)
+/// * The exchange rate as a Decimal (BRL per unit of cryptocurrency)
+///
+/// # Errors
+/// Returns an error if:
+/// * The date is in the future
+/// * The API request fails
+/// * The response cannot be parsed
+/// * The cryptocurrency ID is not supported by CoinGecko
+/// * No exchange rate data is available for the specified date
+pub(super) fn get_crypto_rate_historical(
+    crypto_id: &str,
+    date: NaiveDate,
+) -> std::result::Result<(NaiveDate, Decimal), Reason> {
+    let today = Local::now().date_naive();
+
+    if date > today {
+        return Err(t!("Cannot fetch exchange rate for future dates"));
+    }
+
+    let formatted_date = date.format("%d-%m-%Y").to_string();
+    let url = format!(
+        "{}/api/v3/coins/{}/history?date={}&localization=false",
+        endpoints::get().coingecko,
+        crypto_id,
+        formatted_date
+    );
+
+    let response = http_cache::get(&url, cache_ttl(date, CACHE_TTL)).map_err(|e| e.to_string())?;
+
+    if response.status == 404 {
+        return Err(t!("Cryptocurrency ID not found: {}", crypto_id));
+    }
+
+    if !response.status.is_success() {
+        return Err(t!("CoinGecko API error: {}", response.status));
+    }
+
+    let historical_data: CoinGeckoHistoricalData =
+        serde_json::from_str(&response.body).map_err(|e| e.to_string())?;
+
+    let price_brl = historical_data
+        .market_data
+        .current_price
+        .get("brl")
+        .ok_or_else(|| t!("BRL price not available for {} on {}", crypto_id, date))?;
+
+    let rate = to_price(price_brl).ok_or_else(|| t!("Failed to convert price to Decimal"))?;
+
+    Ok((date, rate))
+}
+
+/// The price in a JSON number, given as a decimal or, for the tiny prices, in
+/// scientific notation, e.g. `1.2e-7`
+fn to_price(number: &Number) -> Option<Decimal> {
+    let text = number.as_str();
+    Decimal::from_str(text)
+        .or_else(|_| Decimal::from_scientific(text))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_to_price() {
+        let data: CoinGeckoHistoricalData = serde_json::from_str(
+            r#"{"market_data": {"current_price": {"brl": 345678.123456789012, "usd": 1.2e-7}}}"#,
+        )
+        .unwrap();
+        let prices = &data.market_data.current_price;
+        assert_eq!(to_price(&prices["brl"]), Some(dec!(345678.123456789012)));
+        assert_eq!(to_price(&prices["usd"]), Some(dec!(0.00000012)));
+    }
+
+    #[test]
+    fn test_crypto_future_date() {
+        let future_date = Local::now().date_naive() + chrono::Duration::days(1);
+        let result = get_crypto_rate_historical("bitcoin", future_date);
+        assert!(result.is_err());
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("Cannot fetch exchange rate for future dates")
+        );
+    }
+}
@@ -0,0 +1,205 @@
+//! This module provides functionality to retrieve exchange rates from different sources.
+//!
+//! For traditional currencies, it uses the BCB (Banco Central do Brasil) API.
+//! For cryptocurrencies, it uses the CoinGecko public API.
+//!
+//! NOTE on CoinGecko API Usage:
+//!
+//! The CoinGecko API implementation has the following limitations for the free tier:
+//! - Rate limits: 10-30 calls per minute
+//! - Historical data: Limited to recent dates (roughly within the past year)
+//! - May require API key for higher volumes
+//!
+//! For production use with higher volumes or reliable historical data access,
+//! consider subscribing to CoinGecko Pro API and setting a shared client that
+//! includes an API key in the headers, with [`crate::http::set`]:
+//!
+//! ```ignore
+//! let client = reqwest::blocking::Client::builder()
+//!     .default_headers({
+//!         let mut headers = reqwest::header::HeaderMap::new();
+//!         headers.insert("x-cg-pro-api-key",
+//!             reqwest::header::HeaderValue::from_str("YOUR_API_KEY").unwrap());
+//!         headers
+//!     })
+//!     .build()?;
+//! kraken2rfb::http::set(client);
+//! ```
+
+#[cfg(feature = "bcb")]
+mod bcb;
+#[cfg(feature = "coingecko")]
+mod coingecko;
+
+#[cfg(any(feature = "bcb", feature = "coingecko"))]
+use std::time::Duration;
+
+#[cfg(any(feature = "bcb", feature = "coingecko"))]
+use chrono::Local;
+use chrono::NaiveDate;
+use phf::phf_map;
+use rust_decimal::Decimal;
+
+use crate::error::{Error, Result};
+use crate::i18n::t;
+#[cfg(feature = "bcb")]
+use bcb::get_fiat_exchange_rate as fiat_rate;
+
+/// Why a rate couldn't be fetched, wrapped into [`Error::Rate`] by the public functions
+type Reason = String;
+
+/// PHF map to convert currency codes to BCB series codes
+static CURRENCY_TO_BCB_SERIES: phf::Map<&'static str, &'static str> = phf_map! {
+    "USD" => "1",     // Dólar Comercial (venda)
+    "EUR" => "21619", // Euro (venda)
+    "JPY" => "21621", // Iene (venda)
+    "GBP" => "21623", // Libra esterlina (venda)
+    "CHF" => "21625", // Franco Suíço (venda)
+    "DKK" => "21627", // Coroa Dinamarquesa (venda)
+    "NOK" => "21629", // Coroa Norueguesa (venda)
+    "SEK" => "21631", // Coroa Sueca (venda)
+    "AUD" => "21633", // Dólar Australiano (venda)
+    "CAD" => "21635", // Dólar Canadense (venda)
+};
+
+// AssetType enum has been removed as it's no longer needed
+// The get_exchange_rate function now automatically detects the asset type
+
+/// Fetches the exchange rate for a given asset against BRL.
+/// This function automatically detects the asset type and chooses the appropriate data source:
+/// - BCB API for fiat currencies supported by get_currency_series_code()
+/// - CoinGecko API for cryptocurrencies and any other assets
+///
+/// # Arguments
+/// * `date` - The date to fetch the exchange rate for
+/// * `asset_code` - The asset code (e.g., "USD", "EUR", "BTC", "ETH")
+///   * For fiat currencies: Use standard ISO code (e.g., "USD", "EUR", "JPY")
+///   * For cryptocurrencies: Use standard ticker (e.g., "BTC", "ETH", "LTC")
+///
+/// # Returns
+/// A tuple containing:
+/// * The actual date of the exchange rate
+/// * The exchange rate as a Decimal (BRL per unit of asset)
+///
+/// # Errors
+/// Returns an error if:
+/// * The date is in the future
+/// * The API request fails
+/// * The asset code is not supported
+/// * No exchange rate data is available
+pub fn get_exchange_rate_impl(date: NaiveDate, asset_code: &str) -> Result<(NaiveDate, Decimal)> {
+    // The trades on a Brazilian exchange are quoted in reais themselves
+    if asset_code == "BRL" {
+        return Ok((date, Decimal::ONE));
+    }
+    // First try as fiat currency with BCB, and if not a supported fiat
+    // currency, as cryptocurrency with CoinGecko
+    let result = if CURRENCY_TO_BCB_SERIES.contains_key(asset_code) {
+        fiat_rate(date, asset_code)
+    } else {
+        crypto_rate(date, asset_code)
+    };
+    result.map_err(|reason| Error::Rate {
+        asset: asset_code.to_string(),
+        date,
+        reason,
+    })
+}
+
+#[cfg(not(feature = "bcb"))]
+fn fiat_rate(_: NaiveDate, _: &str) -> std::result::Result<(NaiveDate, Decimal), Reason> {
+    Err(t!(
+        "the {} rate provider is not enabled in this build",
+        "BCB"
+    ))
+}
+
+#[cfg(feature = "coingecko")]
+fn crypto_rate(
+    date: NaiveDate,
+    asset_code: &str,
+) -> std::result::Result<(NaiveDate, Decimal), Reason> {
+    coingecko::get_crypto_rate_historical(coingecko_id(asset_code), date)
+}
+
+#[cfg(not(feature = "coingecko"))]
+fn crypto_rate(_: NaiveDate, _: &str) -> std::result::Result<(NaiveDate, Decimal), Reason> {
+    Err(t!(
+        "the {} rate provider is not enabled in this build",
+        "CoinGecko"
+    ))
+}
+
+/// How long a response with the rate of `date` is reused from the HTTP cache:
+/// `recent` while the day isn't over, as its rate may still be published or
+/// change, and for good after that, so the rates fetched once, e.g. by
+/// `prefetch-rates`, aren't asked for again
+#[cfg(any(feature = "bcb", feature = "coingecko"))]
+fn cache_ttl(date: NaiveDate, recent: Duration) -> Duration {
+    if date < Local::now().date_naive() {
+        Duration::MAX
+    } else {
+        recent
+    }
+}
+
+/// Looks up the CoinGecko ID from the ticker
+fn coingecko_id(asset_code: &str) -> &str {
+    // If not found in the map, try using the code directly as a CoinGecko ID
+    CRYPTO_TICKER_TO_ID
+        .get(asset_code)
+        .copied()
+        .unwrap_or(asset_code)
+}
+
+/// Describes where the rate of an asset comes from, e.g. "BCB (series 1)"
+pub fn rate_source(asset_code: &str) -> String {
+    match CURRENCY_TO_BCB_SERIES.get(asset_code) {
+        Some(series) => t!("BCB (series {})", series),
+        None => t!("CoinGecko (id {})", coingecko_id(asset_code)),
+    }
+}
+
+pub fn get_exchange_rate(date: NaiveDate, asset_code: &str) -> Result<(NaiveDate, Decimal)> {
+    let result = get_exchange_rate_impl(date, asset_code);
+    if let Ok((rate_date, rate)) = &result {
+        println!(
+            "{}",
+            t!(
+                "Exchange rate for {} on {} (actual used date {}): {} BRL",
+                asset_code,
+                date,
+                rate_date,
+                rate
+            )
+        );
+    }
+    result
+}
+
+/// Maps standard cryptocurrency tickers to CoinGecko IDs
+/// This is necessary because CoinGecko uses IDs like "bitcoin" instead of tickers like "BTC"
+static CRYPTO_TICKER_TO_ID: phf::Map<&'static str, &'static str> = phf_map! {
+    // Major cryptocurrencies
+    "BTC" => "bitcoin",
+    "ETH" => "ethereum",
+    "LTC" => "litecoin",
+    "XRP" => "ripple",
+    "BCH" => "bitcoin-cash",
+    "BNB" => "binancecoin",
+    "ADA" => "cardano",
+    "DOT" => "polkadot",
+    "DOGE" => "dogecoin",
+    "SOL" => "solana",
+    "USDT" => "tether",
+    "USDC" => "usd-coin",
+    "AVAX" => "avalanche-2",
+    "LINK" => "chainlink",
+    "MATIC" => "matic-network",
+    "XLM" => "stellar",
+    "UNI" => "uniswap",
+    "ATOM" => "cosmos",
+    "ALGO" => "algorand",
+    "XTZ" => "tezos",
+    // Add more mappings as needed
+};
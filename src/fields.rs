@@ -0,0 +1,141 @@
+//! Typed access to the fields of the JSON entries returned by the exchange
+//! APIs, with an error naming the missing or invalid field.
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use rust_decimal::Decimal;
+use serde_json::Value;
+
+use crate::error::{Error, Result};
+use crate::i18n::t;
+
+fn missing(entry: &Value, name: &str) -> Error {
+    Error::Validation(t!("missing field \"{}\" in {}", name, entry))
+}
+
+/// A JSON list in the response of `endpoint` of `exchange`
+pub fn list<'a>(value: &'a Value, exchange: &str, endpoint: &str) -> Result<&'a Vec<Value>> {
+    value
+        .as_array()
+        .ok_or_else(|| Error::Validation(t!("unexpected {} response for {}", exchange, endpoint)))
+}
+
+/// Gets a string field of an entry
+pub fn str_field<'a>(entry: &'a Value, name: &str) -> Result<&'a str> {
+    entry[name].as_str().ok_or_else(|| missing(entry, name))
+}
+
+/// Gets a string field of an entry that may be missing, `None` if it is or
+/// is empty
+pub fn optional_str_field(entry: &Value, name: &str) -> Option<String> {
+    entry[name]
+        .as_str()
+        .filter(|text| !text.is_empty())
+        .map(String::from)
+}
+
+/// Gets an integer field of an entry, like an ID or a time, sent as a number
+/// or as a string
+pub fn int_field(entry: &Value, name: &str) -> Result<i64> {
+    match &entry[name] {
+        Value::String(text) => text.parse().ok(),
+        value => value.as_i64(),
+    }
+    .ok_or_else(|| missing(entry, name))
+}
+
+/// Gets an ID of an entry, sent as a number or as a non-empty string
+pub fn id_field(entry: &Value, name: &str) -> Result<String> {
+    match &entry[name] {
+        Value::Number(id) => Ok(id.to_string()),
+        Value::String(id) if !id.is_empty() => Ok(id.clone()),
+        _ => Err(missing(entry, name)),
+    }
+}
+
+/// Reads a decimal, which most exchanges send as a string and some, in a few
+/// endpoints, as a number
+pub fn decimal(value: &Value, name: &str) -> Result<Decimal> {
+    let text = match value {
+        Value::String(text) => text.clone(),
+        Value::Number(number) => number.to_string(),
+        _ => return Err(missing(value, name)),
+    };
+    text.parse::<Decimal>().map_err(|e| {
+        Error::Validation(t!(
+            "invalid decimal \"{}\" in field \"{}\": {}",
+            text,
+            name,
+            e
+        ))
+    })
+}
+
+/// Gets a decimal field of an entry, see [`decimal`]
+pub fn decimal_field(entry: &Value, name: &str) -> Result<Decimal> {
+    decimal(&entry[name], name).map_err(|e| match e {
+        Error::Validation(_) if entry[name].is_null() => missing(entry, name),
+        e => e,
+    })
+}
+
+/// Gets a time of an entry, in UNIX milliseconds, truncated to seconds
+pub fn millis_field(entry: &Value, name: &str) -> Result<DateTime<Utc>> {
+    let millis = int_field(entry, name)?;
+    DateTime::from_timestamp(millis.div_euclid(1000), 0)
+        .ok_or_else(|| Error::Validation(t!("timestamp out of range: {}", entry[name])))
+}
+
+/// Gets a time of an entry, in UNIX seconds
+pub fn seconds_field(entry: &Value, name: &str) -> Result<DateTime<Utc>> {
+    let seconds = int_field(entry, name)?;
+    DateTime::from_timestamp(seconds, 0)
+        .ok_or_else(|| Error::Validation(t!("timestamp out of range: {}", entry[name])))
+}
+
+/// Gets a time of an entry written in UTC in the chrono `format`, e.g.
+/// `"%Y-%m-%d %H:%M:%S"`, truncated to seconds
+pub fn date_time_field(entry: &Value, name: &str, format: &str) -> Result<DateTime<Utc>> {
+    let text = str_field(entry, name)?;
+    let time = NaiveDateTime::parse_from_str(text, format)
+        .map_err(|e| Error::Validation(t!("invalid time \"{}\" in {}: {}", text, name, e)))?
+        .and_utc();
+    DateTime::from_timestamp(time.timestamp(), 0)
+        .ok_or_else(|| Error::Validation(t!("timestamp out of range: {}", text)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    use serde_json::json;
+
+    #[test]
+    fn test_fields() {
+        let entry = json!({"id": 42, "ref": "", "amount": "0.5", "fee": 0.25, "time": 1709294400700_i64, "sent": "1709294400000", "at": "2024-03-01 12:00:00.123456"});
+        assert_eq!(id_field(&entry, "id").unwrap(), "42");
+        assert!(id_field(&entry, "ref").is_err());
+        assert_eq!(int_field(&entry, "sent").unwrap(), 1709294400000);
+        assert_eq!(decimal_field(&entry, "amount").unwrap(), dec!(0.5));
+        assert_eq!(decimal_field(&entry, "fee").unwrap(), dec!(0.25));
+        assert!(
+            decimal_field(&entry, "cost")
+                .unwrap_err()
+                .to_string()
+                .contains("\"cost\"")
+        );
+        assert_eq!(
+            millis_field(&entry, "time").unwrap().timestamp(),
+            1709294400
+        );
+        assert_eq!(
+            seconds_field(&json!({"at": "1709294400"}), "at")
+                .unwrap()
+                .timestamp(),
+            1709294400
+        );
+        let time = date_time_field(&entry, "at", "%Y-%m-%d %H:%M:%S%.f").unwrap();
+        assert_eq!(time.to_string(), "2024-03-01 12:00:00 UTC");
+        assert!(date_time_field(&entry, "at", "%Y-%m-%d").is_err());
+        assert!(list(&entry, "Bitstamp", "/api/v2/user_transactions/").is_err());
+    }
+}
@@ -0,0 +1,454 @@
+//! Realized gains of the stored transactions, month by month, with the losses
+//! carried forward against the gains of the following months.
+//!
+//! The cost of each asset is its average acquisition cost: the BRL value of
+//! the purchases over the amount bought. A sale realizes its value minus the
+//! average cost of the amount sold. A swap carries the cost of what was given
+//! to what was received, without realizing anything, and a withdrawal takes
+//! its share of the cost out with the amount. What is withdrawn may come back
+//! on another exchange of the user, or go through a wallet of theirs. A
+//! deposit linked to the withdrawal it came from, see
+//! [`crate::store::Store::link_transfers`], takes its cost whole. The cost of
+//! the other withdrawals is kept for the deposits known to come from the
+//! user's own exchanges or wallets (those with an origin exchange or wallet),
+//! which take it back for the amount deposited. The cost of any other deposit isn't
+//! known, so it comes in at zero cost, which overstates the gain of selling
+//! it; the purchases of the deposited assets are best reported too. An
+//! [`Income`] comes in at its value on the day it was received.
+//!
+//! The transactions of a day are taken in the order the assets move: what is
+//! bought or received first, then the withdrawals and the deposits they
+//! become, and what is swapped or sold last. A linked deposit recorded the
+//! day before its withdrawal is taken on the day of the withdrawal.
+//!
+//! A month whose sales are within [`tax::MONTHLY_EXEMPTION`] is exempt, and
+//! its gain neither pays tax nor uses up the losses carried. The losses of
+//! any month are carried forward and taken from the gains of the next taxable
+//! months, until used up.
+
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{Datelike, NaiveDate};
+use rust_decimal::Decimal;
+
+use crate::checked;
+use crate::error::Result;
+use crate::i18n::t;
+use crate::income::Income;
+use crate::report::transactions::Transaction;
+use crate::tax;
+
+/// The amount held of an asset and what it cost, in BRL
+#[derive(Debug, Default, Clone, Copy)]
+struct Position {
+    amount: Decimal,
+    cost: Decimal,
+}
+
+impl Position {
+    fn add(&mut self, amount: Decimal, cost: Decimal, what: &str) -> Result<()> {
+        self.amount = checked::add(self.amount, amount, what)?;
+        self.cost = checked::add(self.cost, cost, what)?;
+        Ok(())
+    }
+
+    /// Takes `amount` out, returning its share of the cost
+    fn take(&mut self, amount: Decimal, what: &str) -> Result<Decimal> {
+        if self.amount <= Decimal::ZERO {
+            return Ok(Decimal::ZERO);
+        }
+        let share = checked::mul(self.cost, amount.min(self.amount), what)?;
+        let cost = checked::div(share, self.amount, what)?;
+        self.amount = checked::sub(self.amount, amount, what)?;
+        self.cost = checked::sub(self.cost, cost, what)?;
+        if self.amount <= Decimal::ZERO {
+            *self = Position::default();
+        }
+        Ok(cost)
+    }
+}
+
+fn position<'a>(positions: &'a mut BTreeMap<String, Position>, asset: &str) -> &'a mut Position {
+    positions.entry(asset.to_string()).or_default()
+}
+
+/// The sales of a month and what they realized
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct MonthGains {
+    /// BRL value of the sales
+    pub sales: Decimal,
+    /// Value of the sales minus their cost, negative for a loss
+    pub gain: Decimal,
+}
+
+/// Order of a transaction among those of its day, see the module docs
+fn day_order(transaction: &Transaction) -> u8 {
+    match transaction {
+        Transaction::Purchase(_) | Transaction::CryptoPaymentReceiver(_) => 0,
+        Transaction::WithdrawalFromExchange(_) | Transaction::CryptoPaymentSender(_) => 1,
+        Transaction::TransferToExchange(_) => 2,
+        Transaction::Swap(_) => 3,
+        Transaction::Sale(_) => 4,
+    }
+}
+
+/// Realized gains of the transactions, each with the key of the transfer it
+/// is part of, if linked, and the assets received as income, sorted by date;
+/// keyed by year and month. The months without sales are left out.
+pub fn realized_gains<'a>(
+    transactions: impl IntoIterator<Item = (&'a Transaction, Option<u64>)>,
+    income: impl IntoIterator<Item = &'a Income>,
+) -> Result<BTreeMap<(i32, u32), MonthGains>> {
+    let mut positions: BTreeMap<String, Position> = BTreeMap::new();
+    // What was withdrawn and its cost, by asset, until deposited back
+    let mut withdrawn: BTreeMap<String, Position> = BTreeMap::new();
+    // The cost of each linked withdrawal, until its deposit
+    let mut linked: HashMap<u64, Decimal> = HashMap::new();
+    let mut months: BTreeMap<(i32, u32), MonthGains> = BTreeMap::new();
+    let mut income = income.into_iter().peekable();
+
+    let mut transactions: Vec<_> = transactions.into_iter().collect();
+    let withdrawal_dates: HashMap<u64, NaiveDate> = transactions
+        .iter()
+        .filter(|(t, _)| matches!(t, Transaction::WithdrawalFromExchange(_)))
+        .filter_map(|&(t, transfer)| Some((transfer?, t.date())))
+        .collect();
+    let date = |(transaction, transfer): &(&Transaction, Option<u64>)| match transaction {
+        Transaction::TransferToExchange(_) => transfer
+            .and_then(|transfer| withdrawal_dates.get(&transfer))
+            .map_or(transaction.date(), |&date| date.max(transaction.date())),
+        _ => transaction.date(),
+    };
+    transactions.sort_by_key(|entry| (date(entry), day_order(entry.0)));
+
+    for entry in &transactions {
+        let (transaction, transfer) = *entry;
+        // The income received by the day of the transaction is held by then
+        let day = date(entry);
+        while let Some(received) = income.next_if(|i| i.date <= day) {
+            let what = t!("the gains, adding {}", received.name());
+            position(&mut positions, &received.asset).add(
+                received.amount,
+                received.value,
+                &what,
+            )?;
+        }
+        let what = t!(
+            "the gains, adding the {} record of {}",
+            transaction.record_type().0,
+            transaction.date()
+        );
+        let what = what.as_str();
+        match transaction {
+            Transaction::Purchase(t) => {
+                position(&mut positions, &t.base.crypto_symbol).add(
+                    t.base.crypto_amount,
+                    t.operation_value,
+                    what,
+                )?;
+            }
+            Transaction::Sale(t) => {
+                let cost = position(&mut positions, &t.base.crypto_symbol)
+                    .take(t.base.crypto_amount, what)?;
+                let date = t.base.operation_date;
+                let month = months.entry((date.year(), date.month())).or_default();
+                month.sales = checked::add(month.sales, t.operation_value, what)?;
+                let gain = checked::sub(t.operation_value, cost, what)?;
+                month.gain = checked::add(month.gain, gain, what)?;
+            }
+            Transaction::Swap(t) => {
+                let cost = position(&mut positions, &t.given_crypto_symbol)
+                    .take(t.given_crypto_amount, what)?;
+                position(&mut positions, &t.received_crypto_symbol).add(
+                    t.received_crypto_amount,
+                    cost,
+                    what,
+                )?;
+            }
+            Transaction::TransferToExchange(t) => {
+                let own = t.origin_exchange_name.is_some() || t.origin_wallet.is_some();
+                let cost = if let Some(cost) = transfer.and_then(|key| linked.remove(&key)) {
+                    cost
+                } else if own {
+                    position(&mut withdrawn, &t.base.crypto_symbol)
+                        .take(t.base.crypto_amount, what)?
+                } else {
+                    Decimal::ZERO
+                };
+                position(&mut positions, &t.base.crypto_symbol).add(
+                    t.base.crypto_amount,
+                    cost,
+                    what,
+                )?;
+            }
+            Transaction::CryptoPaymentReceiver(t) => {
+                position(&mut positions, &t.base.crypto_symbol).add(
+                    t.base.crypto_amount,
+                    Decimal::ZERO,
+                    what,
+                )?;
+            }
+            Transaction::WithdrawalFromExchange(t) => {
+                let cost = position(&mut positions, &t.base.crypto_symbol)
+                    .take(t.base.crypto_amount, what)?;
+                match transfer {
+                    Some(key) => {
+                        linked.insert(key, cost);
+                    }
+                    None => {
+                        position(&mut withdrawn, &t.base.crypto_symbol).add(
+                            t.base.crypto_amount,
+                            cost,
+                            what,
+                        )?;
+                    }
+                }
+            }
+            Transaction::CryptoPaymentSender(t) => {
+                position(&mut positions, &t.base.crypto_symbol).take(t.base.crypto_amount, what)?;
+            }
+        }
+    }
+    Ok(months)
+}
+
+/// The tax of a month, after the losses carried from the previous ones
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonthResult {
+    pub year: i32,
+    pub month: u32,
+    pub gains: MonthGains,
+    pub exempt: bool,
+    /// Losses of previous months taken from the gain
+    pub compensated: Decimal,
+    /// Tax due on the DARF
+    pub tax: Decimal,
+    /// Losses still to be carried forward after the month
+    pub carried_loss: Decimal,
+}
+
+/// Applies the losses, starting with `opening_loss` carried from before the
+/// first month, to the gains of each month in order
+pub fn carry_losses(
+    months: &BTreeMap<(i32, u32), MonthGains>,
+    opening_loss: Decimal,
+) -> Result<Vec<MonthResult>> {
+    let mut carried_loss = opening_loss;
+    months
+        .iter()
+        .map(|(&(year, month), &gains)| {
+            let what = t!("the tax of {}/{}", month, year);
+            let exempt = gains.sales <= tax::MONTHLY_EXEMPTION;
+            let mut compensated = Decimal::ZERO;
+            let mut tax = Decimal::ZERO;
+            if gains.gain < Decimal::ZERO {
+                carried_loss = checked::sub(carried_loss, gains.gain, &what)?;
+            } else if !exempt {
+                compensated = carried_loss.min(gains.gain);
+                carried_loss = checked::sub(carried_loss, compensated, &what)?;
+                let taxable = checked::sub(gains.gain, compensated, &what)?;
+                tax = tax::capital_gains_tax(taxable, &what)?;
+            }
+            Ok(MonthResult {
+                year,
+                month,
+                gains,
+                exempt,
+                compensated,
+                tax,
+                carried_loss,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::transactions::{
+        ExchangeInfo, PurchaseTransaction, SaleTransaction, TransactionBase,
+        TransferToExchangeTransaction, WithdrawalFromExchangeTransaction,
+    };
+    use crate::source::IncomeKind;
+    use chrono::NaiveDate;
+    use rust_decimal_macros::dec;
+
+    fn base(month: u32, amount: Decimal) -> TransactionBase {
+        TransactionBase {
+            operation_date: NaiveDate::from_ymd_opt(2024, month, 1).unwrap(),
+            operation_fees: None,
+            crypto_symbol: "BTC".to_string(),
+            crypto_amount: amount,
+        }
+    }
+
+    fn exchange() -> ExchangeInfo {
+        ExchangeInfo {
+            name: "Kraken".to_string(),
+            url: String::new(),
+            country: "US".to_string(),
+        }
+    }
+
+    fn purchase(month: u32, amount: Decimal, value: Decimal) -> Transaction {
+        Transaction::Purchase(PurchaseTransaction {
+            base: base(month, amount),
+            operation_value: value,
+            buyer_exchange: exchange(),
+        })
+    }
+
+    fn sale(month: u32, amount: Decimal, value: Decimal) -> Transaction {
+        Transaction::Sale(SaleTransaction {
+            base: base(month, amount),
+            operation_value: value,
+            seller_exchange: exchange(),
+        })
+    }
+
+    fn unlinked(transactions: &[Transaction]) -> impl Iterator<Item = (&Transaction, Option<u64>)> {
+        transactions.iter().map(|t| (t, None))
+    }
+
+    #[test]
+    fn test_realized_gains() {
+        let transactions = [
+            purchase(1, dec!(1), dec!(200000)),
+            purchase(1, dec!(1), dec!(300000)),
+            // Average cost of 250000
+            sale(2, dec!(0.5), dec!(100000)),
+            sale(4, dec!(1), dec!(300000)),
+        ];
+        assert_eq!(
+            realized_gains(unlinked(&transactions), &[]).unwrap(),
+            BTreeMap::from([
+                (
+                    (2024, 2),
+                    MonthGains {
+                        sales: dec!(100000),
+                        gain: dec!(-25000),
+                    }
+                ),
+                (
+                    (2024, 4),
+                    MonthGains {
+                        sales: dec!(300000),
+                        gain: dec!(50000),
+                    }
+                ),
+            ])
+        );
+    }
+
+    fn withdrawal(month: u32, amount: Decimal) -> Transaction {
+        Transaction::WithdrawalFromExchange(WithdrawalFromExchangeTransaction {
+            base: base(month, amount),
+            origin_exchange: exchange(),
+        })
+    }
+
+    fn deposit(month: u32, amount: Decimal, origin_exchange: Option<&str>) -> Transaction {
+        Transaction::TransferToExchange(TransferToExchangeTransaction {
+            base: base(month, amount),
+            origin_wallet: None,
+            origin_exchange_name: origin_exchange.map(String::from),
+            deposit_id: None,
+        })
+    }
+
+    #[test]
+    fn test_transfer_cost() {
+        // Bought on Binance, moved to Kraken and sold there, all on the same
+        // day; the deposit is stored before the withdrawal, by record type
+        let transactions = [
+            purchase(3, dec!(1), dec!(300000)),
+            sale(3, dec!(1), dec!(310000)),
+            deposit(3, dec!(1), Some("Binance")),
+            withdrawal(3, dec!(1)),
+        ];
+        assert_eq!(
+            realized_gains(unlinked(&transactions), &[]).unwrap()[&(2024, 3)].gain,
+            dec!(10000)
+        );
+
+        // A deposit from an unknown origin comes in at zero cost, and the
+        // cost withdrawn waits for a deposit from the user's own
+        let transactions = [
+            purchase(1, dec!(1), dec!(300000)),
+            withdrawal(2, dec!(1)),
+            deposit(3, dec!(1), None),
+            sale(3, dec!(1), dec!(310000)),
+            deposit(4, dec!(0.5), Some("Binance")),
+            sale(4, dec!(0.5), dec!(160000)),
+        ];
+        let gains = realized_gains(unlinked(&transactions), &[]).unwrap();
+        assert_eq!(gains[&(2024, 3)].gain, dec!(310000));
+        assert_eq!(gains[&(2024, 4)].gain, dec!(10000));
+    }
+
+    #[test]
+    fn test_linked_transfer_cost() {
+        // The linked deposit takes the cost of its withdrawal whole, the
+        // withdrawal fee included, and leaves none for the next deposit from
+        // an exchange of the user
+        let transactions = [
+            purchase(1, dec!(1), dec!(300000)),
+            withdrawal(2, dec!(1)),
+            deposit(2, dec!(0.999), Some("Binance")),
+            sale(2, dec!(0.999), dec!(310000)),
+            deposit(3, dec!(0.5), Some("Binance")),
+            sale(3, dec!(0.5), dec!(100000)),
+        ];
+        let links = [None, Some(7), Some(7), None, None, None];
+        let gains = realized_gains(transactions.iter().zip(links), &[]).unwrap();
+        assert_eq!(gains[&(2024, 2)].gain, dec!(10000));
+        assert_eq!(gains[&(2024, 3)].gain, dec!(100000));
+    }
+
+    #[test]
+    fn test_income_cost() {
+        let transactions = [
+            purchase(1, dec!(1), dec!(200000)),
+            sale(2, dec!(1.5), dec!(450000)),
+        ];
+        // Half a bitcoin received as income, worth 100000 on the day
+        let income = [Income {
+            date: NaiveDate::from_ymd_opt(2024, 1, 15).unwrap(),
+            kind: IncomeKind::Referral,
+            asset: "BTC".to_string(),
+            amount: dec!(0.5),
+            value: dec!(100000),
+        }];
+        assert_eq!(
+            realized_gains(unlinked(&transactions), &income).unwrap()[&(2024, 2)].gain,
+            dec!(150000)
+        );
+    }
+
+    #[test]
+    fn test_carry_losses() {
+        let gains = |sales, gain| MonthGains { sales, gain };
+        let months = BTreeMap::from([
+            ((2024, 2), gains(dec!(100000), dec!(-25000))),
+            // Exempt, so the losses are kept for later
+            ((2024, 3), gains(dec!(30000), dec!(10000))),
+            ((2024, 4), gains(dec!(300000), dec!(50000))),
+            ((2024, 5), gains(dec!(300000), dec!(50000))),
+        ]);
+        let results = carry_losses(&months, dec!(5000)).unwrap();
+        let summary: Vec<_> = results
+            .iter()
+            .map(|r| (r.month, r.exempt, r.compensated, r.tax, r.carried_loss))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (2, false, dec!(0), dec!(0), dec!(30000)),
+                (3, true, dec!(0), dec!(0), dec!(30000)),
+                // 15% of the 20000 left after the losses
+                (4, false, dec!(30000), dec!(3000), dec!(0)),
+                (5, false, dec!(0), dec!(7500), dec!(0)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,454 @@
+//! Gate.io API client, v4: the spot trades, deposits and withdrawals of the
+//! account.
+//!
+//! The lists are limited to 30 days per request, so a period is fetched in
+//! windows; the trades are paged by page number and the transfers by offset.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use chrono::{NaiveDate, TimeDelta, Utc};
+use hmac::{Hmac, Mac};
+use itertools::Itertools;
+use reqwest::blocking::Client;
+use rust_decimal::Decimal;
+use serde_json::Value;
+use sha2::{Digest, Sha512};
+
+use crate::api_keys::{ApiKeys, KeyLocation};
+use crate::endpoints;
+use crate::error::{Error, Result};
+use crate::fields::{
+    self, decimal, decimal_field, id_field, optional_str_field, seconds_field, str_field,
+};
+use crate::http;
+use crate::i18n::t;
+use crate::report::transactions::ExchangeInfo;
+use crate::source::{
+    Activity, ExchangeSource, NormalizedDeposit, NormalizedTrade, NormalizedWithdrawal, Side,
+    day_millis, millis_range, millis_windows,
+};
+
+/// Where the Gate.io API keys are looked for: `gate_keys.json`, or the
+/// `KRAKEN2RFB_GATE_KEYFILE`, `KRAKEN2RFB_GATE_API_KEY` and
+/// `KRAKEN2RFB_GATE_API_SECRET` environment variables
+pub const KEYS: KeyLocation = KeyLocation {
+    exchange: "Gate.io",
+    file_name: "gate_keys.json",
+    env_prefix: "GATE_",
+    profile_keyfile: false,
+};
+
+/// Longest time range of a request to a list, in days
+const WINDOW_DAYS: i64 = 30;
+
+/// Most trades a page has
+const TRADE_PAGE_LIMIT: usize = 1000;
+
+/// Most deposits or withdrawals a page has
+const TRANSFER_PAGE_LIMIT: usize = 500;
+
+/// Labels by which Gate.io rejects the API keys, their permissions or the
+/// signature
+const AUTH_ERROR_LABELS: [&str; 7] = [
+    "INVALID_KEY",
+    "INVALID_SIGNATURE",
+    "INVALID_CREDENTIALS",
+    "MISSING_REQUIRED_HEADER",
+    "REQUEST_EXPIRED",
+    "IP_FORBIDDEN",
+    "FORBIDDEN",
+];
+
+/// Raw Gate.io activity for a period. A trade whose fee was paid in a third
+/// currency, like GT, has the price of that currency in the quote currency
+/// added as `fee_price`.
+#[derive(Debug, Clone, Default)]
+pub struct GateActivity {
+    pub deposits: Vec<Value>,
+    pub withdrawals: Vec<Value>,
+    pub trades: Vec<Value>,
+}
+
+/// Signature of a request: the HMAC-SHA512, with the API secret, of the
+/// method, the path, the query, the SHA-512 of the body and the timestamp, one
+/// per line, in hexadecimal
+fn signature(secret: &str, message: &str) -> String {
+    let mut mac =
+        Hmac::<Sha512>::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size");
+    mac.update(message.as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn gate_error(endpoint: &str, body: &Value) -> Error {
+    let label = body["label"].as_str();
+    Error::Exchange {
+        exchange: "Gate.io".to_string(),
+        endpoint: endpoint.to_string(),
+        message: match (label, body["message"].as_str()) {
+            (Some(label), Some(message)) => format!("{} ({})", message, label),
+            _ => body.to_string(),
+        },
+        auth: label.is_some_and(|label| AUTH_ERROR_LABELS.contains(&label)),
+    }
+}
+
+/// The signed API of an account
+struct Api {
+    client: &'static Client,
+    keys: ApiKeys,
+}
+
+impl Api {
+    fn new(keyfile: &Path) -> Result<Self> {
+        Ok(Api {
+            client: http::client()?,
+            keys: KEYS.load(keyfile)?,
+        })
+    }
+
+    /// Calls an endpoint, e.g. "/api/v4/spot/my_trades"
+    fn get(&self, path: &str, params: &[(&str, String)], signed: bool) -> Result<Value> {
+        let query = serde_urlencoded::to_string(params).expect("the params are strings");
+        let mut request = self
+            .client
+            .get(format!("{}{}?{}", endpoints::get().gate, path, query));
+        if signed {
+            let timestamp = Utc::now().timestamp().to_string();
+            let body_hash: String = Sha512::digest(b"")
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect();
+            let message = format!("GET\n{}\n{}\n{}\n{}", path, query, body_hash, timestamp);
+            request = request
+                .header("KEY", &self.keys.key)
+                .header("Timestamp", timestamp)
+                .header("SIGN", signature(&self.keys.secret, &message));
+        }
+        let response = http::send(request)?;
+        let success = response.status().is_success();
+        let body: Value = response
+            .json()
+            .map_err(|e| Error::Network(t!("invalid JSON from Gate.io {}: {}", path, e)))?;
+        if !success {
+            return Err(gate_error(path, &body));
+        }
+        Ok(body)
+    }
+
+    /// Pages through a list from `start` to `end`, in milliseconds, in windows
+    /// of 30 days, by page number or, with `by_offset`, by offset
+    fn paged(
+        &self,
+        path: &str,
+        (start, end): (i64, i64),
+        limit: usize,
+        by_offset: bool,
+    ) -> Result<Vec<Value>> {
+        let mut entries = Vec::new();
+        for (from, to) in millis_windows(start, end, WINDOW_DAYS) {
+            for page in 0.. {
+                let mut params = vec![
+                    ("from", (from / 1000).to_string()),
+                    ("to", (to / 1000).to_string()),
+                    ("limit", limit.to_string()),
+                ];
+                params.push(match by_offset {
+                    true => ("offset", (page * limit).to_string()),
+                    false => ("page", (page + 1).to_string()),
+                });
+                let response = self.get(path, &params, true)?;
+                let page = list(&response, path)?;
+                entries.extend(page.iter().cloned());
+                if page.len() < limit {
+                    break;
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Closing price on `date` of `currency` in `quote`, from the daily candle
+    /// of their pair in either direction
+    fn price(&self, currency: &str, quote: &str, date: NaiveDate) -> Result<Decimal> {
+        let path = "/api/v4/spot/candlesticks";
+        let start = day_millis(date) / 1000;
+        let candle = |pair: String| -> Option<Decimal> {
+            let params = [
+                ("currency_pair", pair),
+                ("interval", "1d".to_string()),
+                ("from", start.to_string()),
+                (
+                    "to",
+                    (start + TimeDelta::days(1).num_seconds() - 1).to_string(),
+                ),
+            ];
+            // An unknown pair is an error, which only means the other
+            // direction must be tried
+            let candles = self.get(path, &params, false).ok()?;
+            decimal(&candles.as_array()?.first()?[2], "close").ok()
+        };
+        if let Some(price) = candle(format!("{}_{}", currency, quote)) {
+            return Ok(price);
+        }
+        match candle(format!("{}_{}", quote, currency)) {
+            Some(price) if !price.is_zero() => Ok(Decimal::ONE / price),
+            _ => Err(Error::Validation(t!(
+                "no {} market to value {} in {} on {}",
+                "Gate.io",
+                currency,
+                quote,
+                date
+            ))),
+        }
+    }
+}
+
+pub fn fetch_gate_activity(
+    initial: NaiveDate,
+    final_: NaiveDate,
+    keyfile: &Path,
+) -> Result<GateActivity> {
+    let api = Api::new(keyfile)?;
+    let range = millis_range(initial, final_);
+
+    let deposits = api.paged("/api/v4/wallet/deposits", range, TRANSFER_PAGE_LIMIT, true)?;
+    let withdrawals = api.paged(
+        "/api/v4/wallet/withdrawals",
+        range,
+        TRANSFER_PAGE_LIMIT,
+        true,
+    )?;
+    let mut trades = Vec::new();
+    let mut prices = HashMap::new();
+    for mut trade in api.paged("/api/v4/spot/my_trades", range, TRADE_PAGE_LIMIT, false)? {
+        let (base, quote) = market(&trade)?;
+        let fee_currency = str_field(&trade, "fee_currency")?;
+        if fee_currency != base && fee_currency != quote && !decimal_field(&trade, "fee")?.is_zero()
+        {
+            let date = seconds_field(&trade, "create_time")?.date_naive();
+            let key = (fee_currency.to_string(), quote.to_string(), date);
+            let price = match prices.get(&key) {
+                Some(&price) => price,
+                None => {
+                    let price = api.price(fee_currency, quote, date)?;
+                    *prices.entry(key).or_insert(price)
+                }
+            };
+            trade["fee_price"] = Value::from(price.to_string());
+        }
+        trades.push(trade);
+    }
+
+    Ok(GateActivity {
+        deposits,
+        withdrawals,
+        trades,
+    })
+}
+
+/// The Gate.io account the API keys give access to
+pub struct GateSource {
+    /// Key file given by the user, or `None` to look for it only when fetching
+    keyfile: Option<PathBuf>,
+}
+
+impl GateSource {
+    pub fn new(keyfile: Option<PathBuf>) -> Self {
+        GateSource { keyfile }
+    }
+}
+
+impl ExchangeSource for GateSource {
+    fn exchange(&self) -> ExchangeInfo {
+        ExchangeInfo {
+            name: "Gate.io".to_string(),
+            url: "https://www.gate.io".to_string(),
+            country: "KY".to_string(),
+        }
+    }
+
+    fn fetch(&self, first_day: NaiveDate, last_day: NaiveDate) -> Result<Activity> {
+        let keyfile = KEYS.find_keyfile(self.keyfile.as_deref())?;
+        normalize(fetch_gate_activity(first_day, last_day, &keyfile)?)
+    }
+}
+
+/// The base and quote currencies of the pair of a trade, e.g. "BTC_USDT"
+fn market(trade: &Value) -> Result<(&str, &str)> {
+    str_field(trade, "currency_pair")?
+        .split_once('_')
+        .ok_or_else(|| Error::Validation(t!("unexpected Gate.io entry: {}", trade)))
+}
+
+/// Converts the raw Gate.io activity to the typed entries of [`Activity`].
+///
+/// The fee of a trade is in its `fee_currency`: the currency received, or GT
+/// when the account pays the fees with it, valued at the `fee_price` added
+/// when fetching. A fee in the base currency is valued at the price of the
+/// trade.
+fn normalize(activity: GateActivity) -> Result<Activity> {
+    let transfer_ids = |entry: &Value| -> Vec<String> {
+        ["id", "txid"]
+            .iter()
+            .filter_map(|field| id_field(entry, field).ok())
+            .collect()
+    };
+    let deposits = activity
+        .deposits
+        .iter()
+        .filter(|d| d["status"] == "DONE")
+        .map(|deposit| -> Result<NormalizedDeposit> {
+            Ok(NormalizedDeposit {
+                asset: str_field(deposit, "currency")?.to_string(),
+                amount: decimal_field(deposit, "amount")?,
+                fee: Decimal::ZERO,
+                time: seconds_field(deposit, "timestamp")?,
+                ids: transfer_ids(deposit),
+                origin_exchange: None,
+                origin_wallet: None,
+                network: None,
+            })
+        });
+    let withdrawals = activity
+        .withdrawals
+        .iter()
+        .filter(|w| w["status"] == "DONE")
+        .map(|withdrawal| -> Result<NormalizedWithdrawal> {
+            Ok(NormalizedWithdrawal {
+                asset: str_field(withdrawal, "currency")?.to_string(),
+                amount: decimal_field(withdrawal, "amount")?,
+                fee: decimal_field(withdrawal, "fee").unwrap_or_default(),
+                time: seconds_field(withdrawal, "timestamp")?,
+                ids: transfer_ids(withdrawal),
+                destination: optional_str_field(withdrawal, "address"),
+                network: None,
+            })
+        });
+    let trade = |trade: &Value| -> Result<NormalizedTrade> {
+        let (base, quote) = market(trade)?;
+        let side = match str_field(trade, "side")? {
+            "buy" => Side::Buy,
+            "sell" => Side::Sell,
+            _ => return Err(Error::Validation(t!("unexpected Gate.io entry: {}", trade))),
+        };
+        let price = decimal_field(trade, "price")?;
+        let volume = decimal_field(trade, "amount")?;
+        let fee = decimal_field(trade, "fee")?;
+        let fee = match str_field(trade, "fee_currency")? {
+            _ if fee.is_zero() => Decimal::ZERO,
+            currency if currency == quote => fee,
+            currency if currency == base => fee * price,
+            _ => fee * decimal_field(trade, "fee_price")?,
+        };
+        Ok(NormalizedTrade {
+            base: base.to_string(),
+            quote: quote.to_string(),
+            side,
+            volume,
+            cost: price * volume,
+            fee,
+            credit_fee_usd: Decimal::ZERO,
+            price,
+            time: seconds_field(trade, "create_time")?,
+            ids: vec![id_field(trade, "id")?, id_field(trade, "order_id")?],
+        })
+    };
+
+    let mut trades: Vec<NormalizedTrade> = activity.trades.iter().map(trade).try_collect()?;
+    trades.sort_by_key(|t| t.time);
+    let mut deposits: Vec<NormalizedDeposit> = deposits.try_collect()?;
+    deposits.sort_by_key(|d| d.time);
+    let mut withdrawals: Vec<NormalizedWithdrawal> = withdrawals.try_collect()?;
+    withdrawals.sort_by_key(|w| w.time);
+    Ok(Activity {
+        deposits,
+        withdrawals,
+        trades,
+        income: Vec::new(),
+        wallet_transfers: Vec::new(),
+    })
+}
+
+/// A JSON list in a Gate.io response
+fn list<'a>(value: &'a Value, endpoint: &str) -> Result<&'a Vec<Value>> {
+    fields::list(value, "Gate.io", endpoint)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    use serde_json::json;
+
+    #[test]
+    fn test_signature() {
+        let message = format!(
+            "GET\n/api/v4/spot/my_trades\nfrom=1709251200&to=1711843199&limit=1000&page=1\n{}\n1709294400",
+            "cf83e1357eefb8bdf1542850d66d8007d620e4050b5715dc83f4a921d36ce9ce47d0d13c5d85f2b0ff8318d2877eec2f63b931bd47417a81a538327af927da3e"
+        );
+        assert_eq!(
+            signature("secret", &message),
+            "8aeaac9e90db65360e1533cd79fa66b829969f85e0b0e92cb5f7bade1beccdde22851ab6a77aad933d328714440a1f4dca0a742fb0acef2047fd70735ea81731"
+        );
+    }
+
+    #[test]
+    fn test_normalize() {
+        let activity = normalize(GateActivity {
+            deposits: vec![
+                json!({"id": "d1", "txid": "0xa1", "currency": "USDT", "amount": "100", "status": "DONE", "timestamp": "1709294400"}),
+                json!({"id": "d2", "txid": "0xa2", "currency": "USDT", "amount": "50", "status": "PEND", "timestamp": "1709294400"}),
+            ],
+            withdrawals: vec![
+                json!({"id": "w1", "txid": "0xb1", "currency": "BTC", "amount": "0.1", "fee": "0.0005", "status": "DONE", "timestamp": "1709380800"}),
+            ],
+            trades: vec![
+                json!({"id": "t1", "order_id": "o1", "currency_pair": "BTC_USDT", "side": "buy", "price": "60000", "amount": "0.01", "fee": "0.00001", "fee_currency": "BTC", "create_time": "1709467200"}),
+                json!({"id": "t2", "order_id": "o2", "currency_pair": "ETH_BTC", "side": "sell", "price": "0.05", "amount": "2", "fee": "0.01", "fee_currency": "GT", "fee_price": "0.0002", "create_time": "1709553600"}),
+            ],
+        })
+        .unwrap();
+
+        // The deposit still pending is left out
+        assert_eq!(activity.deposits.len(), 1);
+        assert_eq!(activity.deposits[0].ids, ["d1", "0xa1"]);
+        let withdrawal = &activity.withdrawals[0];
+        assert_eq!(
+            (withdrawal.amount, withdrawal.fee),
+            (dec!(0.1), dec!(0.0005))
+        );
+
+        let [buy, sell] = activity.trades.as_slice() else {
+            panic!("expected 2 trades, got {:?}", activity.trades);
+        };
+        // A fee in the base currency at the price of the trade
+        assert_eq!(
+            (buy.side, buy.cost, buy.fee),
+            (Side::Buy, dec!(600), dec!(0.6))
+        );
+        assert_eq!(buy.ids, ["t1", "o1"]);
+        // A fee in GT at the price of GT in BTC
+        assert_eq!((sell.side, sell.fee), (Side::Sell, dec!(0.000002)));
+        assert_eq!((sell.base.as_str(), sell.quote.as_str()), ("ETH", "BTC"));
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = gate_error(
+            "/api/v4/spot/my_trades",
+            &json!({"label": "INVALID_SIGNATURE", "message": "Signature mismatch"}),
+        );
+        assert!(error.is_auth_error());
+        assert!(error.to_string().contains("(INVALID_SIGNATURE)"));
+        let error = gate_error(
+            "/api/v4/spot/my_trades",
+            &json!({"label": "INVALID_PARAM_VALUE", "message": "Invalid from/to"}),
+        );
+        assert!(!error.is_auth_error());
+    }
+}
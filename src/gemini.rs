@@ -0,0 +1,441 @@
+//! Gemini API client: the trades, deposits and withdrawals of the account.
+//!
+//! A private request has no body: its parameters, with the endpoint and a
+//! nonce, go as a base64 JSON payload in a header, signed with the secret.
+//! The trade and transfer lists are paged by time, from the `timestamp` of
+//! the request: each page has the earliest entries from it, so the next page
+//! starts after the last entry of the previous one.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI64, Ordering};
+
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use chrono::{NaiveDate, Utc};
+use hmac::{Hmac, Mac};
+use itertools::Itertools;
+use reqwest::blocking::Client;
+use rust_decimal::Decimal;
+use serde_json::{Value, json};
+use sha2::Sha384;
+
+use crate::api_keys::{ApiKeys, KeyLocation};
+use crate::endpoints;
+use crate::error::{Error, Result};
+use crate::fields::{
+    self, decimal_field, id_field, int_field, millis_field, optional_str_field, str_field,
+};
+use crate::http;
+use crate::i18n::t;
+use crate::report::transactions::ExchangeInfo;
+use crate::source::{
+    Activity, ExchangeSource, NormalizedDeposit, NormalizedTrade, NormalizedWithdrawal, Side,
+    millis_range,
+};
+
+/// Where the Gemini API keys are looked for: `gemini_keys.json`, or the
+/// `KRAKEN2RFB_GEMINI_KEYFILE`, `KRAKEN2RFB_GEMINI_API_KEY` and
+/// `KRAKEN2RFB_GEMINI_API_SECRET` environment variables
+pub const KEYS: KeyLocation = KeyLocation {
+    exchange: "Gemini",
+    file_name: "gemini_keys.json",
+    env_prefix: "GEMINI_",
+    profile_keyfile: false,
+};
+
+/// Most trades a page has
+const TRADE_PAGE_LIMIT: usize = 500;
+
+/// Most deposits or withdrawals a page has
+const TRANSFER_PAGE_LIMIT: usize = 50;
+
+/// Reasons by which Gemini rejects the API keys, their permissions or the
+/// signature
+const AUTH_ERROR_REASONS: [&str; 7] = [
+    "InvalidApiKey",
+    "InvalidSignature",
+    "InvalidNonce",
+    "MissingApikeyHeader",
+    "MissingPayloadHeader",
+    "MissingSignatureHeader",
+    "MissingRole",
+];
+
+/// Raw Gemini activity for a period: the trades, with the `baseCurrency` and
+/// `quoteCurrency` of their symbol added, and the transfers, deposits and
+/// withdrawals together
+#[derive(Debug, Clone, Default)]
+pub struct GeminiActivity {
+    pub trades: Vec<Value>,
+    pub transfers: Vec<Value>,
+}
+
+/// Signature of a request: the HMAC-SHA384, with the API secret, of its base64
+/// payload, in hexadecimal
+fn signature(secret: &str, payload: &str) -> String {
+    let mut mac =
+        Hmac::<Sha384>::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size");
+    mac.update(payload.as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// A nonce in milliseconds, as Gemini takes them, always greater than the
+/// previous one of this process
+fn nonce() -> i64 {
+    static LAST: AtomicI64 = AtomicI64::new(0);
+    let now = Utc::now().timestamp_millis();
+    let previous = LAST
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |last| {
+            Some(now.max(last + 1))
+        })
+        .expect("the update never fails");
+    now.max(previous + 1)
+}
+
+fn gemini_error(endpoint: &str, body: &Value) -> Error {
+    let reason = body["reason"].as_str();
+    Error::Exchange {
+        exchange: "Gemini".to_string(),
+        endpoint: endpoint.to_string(),
+        message: match (reason, body["message"].as_str()) {
+            (Some(reason), Some(message)) => format!("{} ({})", message, reason),
+            _ => body.to_string(),
+        },
+        auth: reason.is_some_and(|reason| AUTH_ERROR_REASONS.contains(&reason)),
+    }
+}
+
+/// The private API of an account
+struct Api {
+    client: &'static Client,
+    keys: ApiKeys,
+}
+
+impl Api {
+    fn new(keyfile: &Path) -> Result<Self> {
+        Ok(Api {
+            client: http::client()?,
+            keys: KEYS.load(keyfile)?,
+        })
+    }
+
+    /// Sends a request and reads its JSON response
+    fn send(&self, path: &str, request: reqwest::blocking::RequestBuilder) -> Result<Value> {
+        let response = http::send(request)?;
+        let success = response.status().is_success();
+        let body: Value = response
+            .json()
+            .map_err(|e| Error::Network(t!("invalid JSON from Gemini {}: {}", path, e)))?;
+        if !success || body["result"] == "error" {
+            return Err(gemini_error(path, &body));
+        }
+        Ok(body)
+    }
+
+    /// Calls a private endpoint, e.g. "/v1/mytrades", with the parameters of
+    /// its payload
+    fn post(&self, path: &str, params: Value) -> Result<Value> {
+        let mut payload = json!({"request": path, "nonce": nonce().to_string()});
+        if let (Some(payload), Value::Object(params)) = (payload.as_object_mut(), params) {
+            payload.extend(params);
+        }
+        let payload = BASE64.encode(payload.to_string());
+        let request = self
+            .client
+            .post(format!("{}{}", endpoints::get().gemini, path))
+            .header("Content-Type", "text/plain")
+            .header("Content-Length", "0")
+            .header("Cache-Control", "no-cache")
+            .header("X-GEMINI-APIKEY", &self.keys.key)
+            .header("X-GEMINI-SIGNATURE", signature(&self.keys.secret, &payload))
+            .header("X-GEMINI-PAYLOAD", payload);
+        self.send(path, request)
+    }
+
+    /// Pages through a list from `start` to `end`, in milliseconds, by the
+    /// time of its entries
+    fn paged(
+        &self,
+        path: &str,
+        limit_param: &str,
+        limit: usize,
+        (start, end): (i64, i64),
+    ) -> Result<Vec<Value>> {
+        let mut entries = Vec::new();
+        let mut from = start;
+        while from <= end {
+            let page = self.post(path, json!({"timestamp": from, limit_param: limit}))?;
+            let page = list(&page, path)?;
+            let times: Vec<i64> = page
+                .iter()
+                .map(|entry| int_field(entry, "timestampms"))
+                .try_collect()?;
+            entries.extend(
+                page.iter()
+                    .zip(&times)
+                    .filter(|&(_, &time)| time <= end)
+                    .map(|(entry, _)| entry.clone()),
+            );
+            match times.iter().max() {
+                Some(&last) if page.len() >= limit => from = last + 1,
+                _ => break,
+            }
+        }
+        Ok(entries)
+    }
+
+    /// The base and quote currencies of a symbol, e.g. "btcusd"
+    fn symbol(&self, symbol: &str) -> Result<(String, String)> {
+        let path = format!("/v1/symbols/details/{}", symbol);
+        let request = self
+            .client
+            .get(format!("{}{}", endpoints::get().gemini, path));
+        let details = self.send(&path, request)?;
+        Ok((
+            str_field(&details, "base_currency")?.to_string(),
+            str_field(&details, "quote_currency")?.to_string(),
+        ))
+    }
+}
+
+pub fn fetch_gemini_activity(
+    initial: NaiveDate,
+    final_: NaiveDate,
+    keyfile: &Path,
+) -> Result<GeminiActivity> {
+    let api = Api::new(keyfile)?;
+    let range = millis_range(initial, final_);
+
+    let transfers = api.paged(
+        "/v1/transfers",
+        "limit_transfers",
+        TRANSFER_PAGE_LIMIT,
+        range,
+    )?;
+    let mut trades = api.paged("/v1/mytrades", "limit_trades", TRADE_PAGE_LIMIT, range)?;
+
+    let symbols: HashSet<String> = trades
+        .iter()
+        .map(|trade| Ok(str_field(trade, "symbol")?.to_lowercase()))
+        .collect::<Result<_>>()?;
+    let symbols: HashMap<String, (String, String)> = symbols
+        .into_iter()
+        .map(|symbol| {
+            let currencies = api.symbol(&symbol)?;
+            Ok((symbol, currencies))
+        })
+        .collect::<Result<_>>()?;
+    for trade in &mut trades {
+        let (base, quote) = &symbols[&str_field(trade, "symbol")?.to_lowercase()];
+        trade["baseCurrency"] = Value::from(base.as_str());
+        trade["quoteCurrency"] = Value::from(quote.as_str());
+    }
+
+    Ok(GeminiActivity { trades, transfers })
+}
+
+/// The Gemini account the API keys give access to
+pub struct GeminiSource {
+    /// Key file given by the user, or `None` to look for it only when fetching
+    keyfile: Option<PathBuf>,
+}
+
+impl GeminiSource {
+    pub fn new(keyfile: Option<PathBuf>) -> Self {
+        GeminiSource { keyfile }
+    }
+}
+
+impl ExchangeSource for GeminiSource {
+    fn exchange(&self) -> ExchangeInfo {
+        ExchangeInfo {
+            name: "Gemini".to_string(),
+            url: "https://www.gemini.com".to_string(),
+            country: "US".to_string(),
+        }
+    }
+
+    fn fetch(&self, first_day: NaiveDate, last_day: NaiveDate) -> Result<Activity> {
+        let keyfile = KEYS.find_keyfile(self.keyfile.as_deref())?;
+        normalize(fetch_gemini_activity(first_day, last_day, &keyfile)?)
+    }
+}
+
+/// Converts the raw Gemini activity to the typed entries of [`Activity`].
+///
+/// A deposit counts once it is `Complete`, or `Advanced` (a bank deposit
+/// already credited before it clears); a withdrawal once it is `Complete`.
+/// The fee of a trade is in its `fee_currency`, the quote currency, or else
+/// valued at the price of the trade if it is the base one.
+fn normalize(activity: GeminiActivity) -> Result<Activity> {
+    let unexpected = |entry: &Value| Error::Validation(t!("unexpected Gemini entry: {}", entry));
+    let transfer_ids = |entry: &Value| -> Vec<String> {
+        ["eid", "txHash"]
+            .iter()
+            .filter_map(|field| id_field(entry, field).ok())
+            .collect()
+    };
+    let fee = |entry: &Value| -> Result<Decimal> {
+        match &entry["feeAmount"] {
+            Value::Null => Ok(Decimal::ZERO),
+            _ => Ok(decimal_field(entry, "feeAmount")?.abs()),
+        }
+    };
+    let mut deposits = Vec::new();
+    let mut withdrawals = Vec::new();
+    for transfer in &activity.transfers {
+        let status = str_field(transfer, "status")?;
+        match str_field(transfer, "type")? {
+            "Deposit" if status == "Complete" || status == "Advanced" => {
+                deposits.push(NormalizedDeposit {
+                    asset: str_field(transfer, "currency")?.to_string(),
+                    amount: decimal_field(transfer, "amount")?,
+                    fee: fee(transfer)?,
+                    time: millis_field(transfer, "timestampms")?,
+                    ids: transfer_ids(transfer),
+                    origin_exchange: None,
+                    origin_wallet: None,
+                    network: None,
+                })
+            }
+            "Withdrawal" if status == "Complete" => withdrawals.push(NormalizedWithdrawal {
+                asset: str_field(transfer, "currency")?.to_string(),
+                amount: decimal_field(transfer, "amount")?.abs(),
+                fee: fee(transfer)?,
+                time: millis_field(transfer, "timestampms")?,
+                ids: transfer_ids(transfer),
+                destination: optional_str_field(transfer, "destination"),
+                network: None,
+            }),
+            "Deposit" | "Withdrawal" => {}
+            _ => return Err(unexpected(transfer)),
+        }
+    }
+
+    let trade = |trade: &Value| -> Result<NormalizedTrade> {
+        let (base, quote) = (
+            str_field(trade, "baseCurrency")?,
+            str_field(trade, "quoteCurrency")?,
+        );
+        let side = match str_field(trade, "type")? {
+            "Buy" => Side::Buy,
+            "Sell" => Side::Sell,
+            _ => return Err(unexpected(trade)),
+        };
+        let price = decimal_field(trade, "price")?;
+        let volume = decimal_field(trade, "amount")?;
+        let fee = decimal_field(trade, "fee_amount")?;
+        let fee = match str_field(trade, "fee_currency")? {
+            _ if fee.is_zero() => Decimal::ZERO,
+            currency if currency.eq_ignore_ascii_case(quote) => fee,
+            currency if currency.eq_ignore_ascii_case(base) => fee * price,
+            _ => return Err(unexpected(trade)),
+        };
+        Ok(NormalizedTrade {
+            base: base.to_string(),
+            quote: quote.to_string(),
+            side,
+            volume,
+            cost: price * volume,
+            fee,
+            credit_fee_usd: Decimal::ZERO,
+            price,
+            time: millis_field(trade, "timestampms")?,
+            ids: vec![id_field(trade, "tid")?, id_field(trade, "order_id")?],
+        })
+    };
+
+    let mut trades: Vec<NormalizedTrade> = activity.trades.iter().map(trade).try_collect()?;
+    trades.sort_by_key(|t| t.time);
+    deposits.sort_by_key(|d| d.time);
+    withdrawals.sort_by_key(|w| w.time);
+    Ok(Activity {
+        deposits,
+        withdrawals,
+        trades,
+        income: Vec::new(),
+        wallet_transfers: Vec::new(),
+    })
+}
+
+/// A JSON list in a Gemini response
+fn list<'a>(value: &'a Value, endpoint: &str) -> Result<&'a Vec<Value>> {
+    fields::list(value, "Gemini", endpoint)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_signature() {
+        assert_eq!(
+            signature(
+                "secret",
+                "eyJyZXF1ZXN0IjoiL3YxL215dHJhZGVzIiwibm9uY2UiOiIxNzA5Mjk0NDAwMDAwIiwidGltZXN0YW1wIjoxNzA5MjUxMjAwLCJsaW1pdF90cmFkZXMiOjUwMH0="
+            ),
+            "fee21caf236340b0252be38537414e6ee474d71e8bff680312af076736770df3fbc78eab4595a55f2d4b48f146a685ee"
+        );
+        assert!(nonce() < nonce());
+    }
+
+    #[test]
+    fn test_normalize() {
+        let activity = normalize(GeminiActivity {
+            transfers: vec![
+                json!({"type": "Deposit", "status": "Advanced", "timestampms": 1709294400000_i64, "eid": 1001, "currency": "USD", "amount": "1000", "method": "ACH"}),
+                json!({"type": "Deposit", "status": "Pending", "timestampms": 1709294400000_i64, "eid": 1002, "currency": "BTC", "amount": "0.5", "txHash": "0xa2"}),
+                json!({"type": "Withdrawal", "status": "Complete", "timestampms": 1709380800000_i64, "eid": 1003, "currency": "BTC", "amount": "0.1", "feeAmount": "0.0001", "txHash": "0xb1"}),
+            ],
+            trades: vec![
+                json!({"symbol": "BTCUSD", "baseCurrency": "BTC", "quoteCurrency": "USD", "tid": 2001, "order_id": "o1", "type": "Sell", "price": "61000", "amount": "0.01", "fee_currency": "USD", "fee_amount": "2.44", "timestampms": 1709553600000_i64}),
+                json!({"symbol": "ETHBTC", "baseCurrency": "ETH", "quoteCurrency": "BTC", "tid": 2002, "order_id": "o2", "type": "Buy", "price": "0.05", "amount": "2", "fee_currency": "ETH", "fee_amount": "0.002", "timestampms": 1709467200000_i64}),
+            ],
+        })
+        .unwrap();
+
+        // The deposit still pending is left out
+        assert_eq!(activity.deposits.len(), 1);
+        assert_eq!(activity.deposits[0].ids, ["1001"]);
+        let withdrawal = &activity.withdrawals[0];
+        assert_eq!(
+            (withdrawal.amount, withdrawal.fee),
+            (dec!(0.1), dec!(0.0001))
+        );
+        assert_eq!(withdrawal.ids, ["1003", "0xb1"]);
+
+        let [buy, sell] = activity.trades.as_slice() else {
+            panic!("expected 2 trades, got {:?}", activity.trades);
+        };
+        // A fee in the base currency at the price of the trade
+        assert_eq!(
+            (buy.side, buy.cost, buy.fee),
+            (Side::Buy, dec!(0.1), dec!(0.0001))
+        );
+        assert_eq!(buy.ids, ["2002", "o2"]);
+        assert_eq!(
+            (sell.side, sell.cost, sell.fee),
+            (Side::Sell, dec!(610), dec!(2.44))
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = gemini_error(
+            "/v1/mytrades",
+            &json!({"result": "error", "reason": "InvalidSignature", "message": "InvalidSignature"}),
+        );
+        assert!(error.is_auth_error());
+        assert!(error.to_string().contains("(InvalidSignature)"));
+        let error = gemini_error(
+            "/v1/mytrades",
+            &json!({"result": "error", "reason": "RateLimit", "message": "Requests were made too frequently"}),
+        );
+        assert!(!error.is_auth_error());
+    }
+}
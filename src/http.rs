@@ -0,0 +1,366 @@
+//! The HTTP client shared by every backend, so they reuse connections and the
+//! same settings: timeouts, proxy and user agent.
+//!
+//! With `KRAKEN2RFB_HTTP_TRACE` set to a file, every request sent and its
+//! response are appended to it, with the secrets redacted, to tell after the
+//! fact what a provider answered.
+
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use reqwest::blocking::{Client, Request, RequestBuilder, Response};
+use reqwest::header::{CONTENT_TYPE, HeaderMap, RETRY_AFTER};
+use reqwest::{ResponseBuilderExt, StatusCode, Url};
+
+use crate::env;
+use crate::error::{Error, Result};
+use crate::i18n::t;
+use crate::redact;
+
+/// Seconds a request may take, unless set by `KRAKEN2RFB_HTTP_TIMEOUT`
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+
+/// Seconds to wait for a connection, at most the request timeout
+const CONNECT_TIMEOUT_SECS: u64 = 10;
+
+static CLIENT: OnceLock<Client> = OnceLock::new();
+
+/// Sets the client used by every request, e.g. one configured by a library
+/// user. Must be called before the first request; otherwise the client is
+/// already fixed and it is returned back as the error.
+pub fn set(client: Client) -> std::result::Result<(), Client> {
+    CLIENT.set(client)
+}
+
+/// The shared client, built on first use from `KRAKEN2RFB_HTTP_TIMEOUT` and
+/// `KRAKEN2RFB_PROXY` if [`set`] wasn't called. Without `KRAKEN2RFB_PROXY`,
+/// the usual `HTTPS_PROXY` and `HTTP_PROXY` variables are honored.
+pub fn client() -> Result<&'static Client> {
+    if let Some(client) = CLIENT.get() {
+        return Ok(client);
+    }
+    let client = build(env::var("HTTP_TIMEOUT"), env::var("PROXY"))?;
+    Ok(CLIENT.get_or_init(|| client))
+}
+
+/// Times a request rejected for exceeding a rate limit is sent again
+pub const RATE_LIMIT_RETRIES: u32 = 5;
+
+/// Sends a request, waiting and sending it again while the server answers
+/// 429 Too Many Requests, or 418, by which Binance bans a client that kept
+/// going after a 429. The wait is what the `Retry-After` header says or, without
+/// one, doubles from 2 seconds. After the last retry, the rejection is returned
+/// as any other response.
+pub fn send(request: RequestBuilder) -> Result<Response> {
+    let mut attempt = 0;
+    loop {
+        let request = request.try_clone().expect("requests are never streamed");
+        let response = match trace_file()? {
+            Some(file) => send_traced(request, file)?,
+            None => request.send()?,
+        };
+        let status = response.status();
+        let limited = status == StatusCode::TOO_MANY_REQUESTS || status.as_u16() == 418;
+        if !limited || attempt == RATE_LIMIT_RETRIES {
+            return Ok(response);
+        }
+        thread::sleep(retry_after(response.headers()).unwrap_or(Duration::from_secs(2 << attempt)));
+        attempt += 1;
+    }
+}
+
+/// The wait asked for by a `Retry-After` header, in seconds
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let seconds = headers
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(Duration::from_secs(seconds))
+}
+
+static TRACE: OnceLock<Option<Mutex<File>>> = OnceLock::new();
+
+/// The file the requests are traced to, opened for appending on first use
+/// from `KRAKEN2RFB_HTTP_TRACE`, or `None` if not tracing
+fn trace_file() -> Result<Option<&'static Mutex<File>>> {
+    if let Some(trace) = TRACE.get() {
+        return Ok(trace.as_ref());
+    }
+    let file = match env::var("HTTP_TRACE") {
+        Some(path) => Some(Mutex::new(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .map_err(|e| {
+                    Error::Config(t!(
+                        "invalid value for {}: {}",
+                        env::name("HTTP_TRACE"),
+                        format!("{} ({})", path, e)
+                    ))
+                })?,
+        )),
+        None => None,
+    };
+    Ok(TRACE.get_or_init(|| file).as_ref())
+}
+
+/// Most bytes of a response body written to the trace, so a large download
+/// doesn't bloat it
+const MAX_TRACED_BODY: usize = 64 * 1024;
+
+/// Sends a request, appending it and its response, or the error, to `file`.
+/// The body is read to be traced, and the response is built again from it.
+fn send_traced(request: RequestBuilder, file: &Mutex<File>) -> Result<Response> {
+    let (client, request) = request.build_split();
+    let request = request?;
+    let mut trace = format_request(&request);
+    let start = Instant::now();
+    let result = client.execute(request).and_then(|response| {
+        let (status, version, url) = (
+            response.status(),
+            response.version(),
+            response.url().clone(),
+        );
+        let headers = response.headers().clone();
+        let body = response.bytes()?;
+        Ok((status, version, url, headers, body))
+    });
+    let elapsed = start.elapsed().as_millis();
+    let (status, version, url, headers, body) = match result {
+        Ok(parts) => parts,
+        Err(e) => {
+            let _ = writeln!(trace, "<-- {} ({} ms)\n", e, elapsed);
+            write_trace(file, &trace)?;
+            return Err(e.into());
+        }
+    };
+    let _ = writeln!(trace, "<-- {} ({} ms)", status, elapsed);
+    trace += &format_headers(&headers);
+    trace += &format_body(&body, MAX_TRACED_BODY);
+    write_trace(file, &trace)?;
+
+    let mut response = http::Response::builder()
+        .status(status)
+        .version(version)
+        .url(url);
+    if let Some(response_headers) = response.headers_mut() {
+        *response_headers = headers;
+    }
+    let response = response
+        .body(body)
+        .map_err(|e| Error::Network(e.to_string()))?;
+    Ok(Response::from(response))
+}
+
+fn write_trace(file: &Mutex<File>, trace: &str) -> Result<()> {
+    let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+    file.write_all(redact::redact(trace).as_bytes())?;
+    Ok(())
+}
+
+/// Whether a header or parameter named `name` carries a secret, like an API
+/// key or a signature
+fn is_secret(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    [
+        "key",
+        "sign",
+        "secret",
+        "passphrase",
+        "token",
+        "auth",
+        "cookie",
+        "password",
+    ]
+    .iter()
+    .any(|word| name.contains(word))
+}
+
+/// `name=value` pairs joined by `&`, with the values of the secret ones
+/// replaced
+fn redact_params(params: &str) -> String {
+    params
+        .split('&')
+        .map(|param| match param.split_once('=') {
+            Some((name, _)) if is_secret(name) => format!("{}=[REDACTED]", name),
+            _ => param.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// The URL with the values of the secret query parameters replaced
+fn redact_url(url: &Url) -> String {
+    let mut url = url.clone();
+    if let Some(query) = url.query().map(redact_params) {
+        url.set_query(Some(&query));
+    }
+    url.to_string()
+}
+
+fn format_headers(headers: &HeaderMap) -> String {
+    let mut text = String::new();
+    for (name, value) in headers {
+        let value = if is_secret(name.as_str()) {
+            "[REDACTED]"
+        } else {
+            value.to_str().unwrap_or("[binary]")
+        };
+        let _ = writeln!(text, "{}: {}", name, value);
+    }
+    text
+}
+
+/// The body after a blank line, cut at `max` bytes, and the line break that
+/// ends the entry
+fn format_body(body: &[u8], max: usize) -> String {
+    let mut text = String::from("\n");
+    if body.len() > max {
+        text += &String::from_utf8_lossy(&body[..max]);
+        let _ = write!(text, "\n[{} more bytes]", body.len() - max);
+    } else {
+        text += &String::from_utf8_lossy(body);
+    }
+    text + "\n\n"
+}
+
+/// The request line, headers and body of a request, with the secrets in the
+/// URL, the headers and a form body replaced
+fn format_request(request: &Request) -> String {
+    let mut text = format!(
+        "--> {} {} {}\n",
+        Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ"),
+        request.method(),
+        redact_url(request.url())
+    );
+    text += &format_headers(request.headers());
+    if let Some(body) = request.body().and_then(|body| body.as_bytes()) {
+        let is_form = request.headers().get(CONTENT_TYPE).is_some_and(|value| {
+            value
+                .as_bytes()
+                .starts_with(b"application/x-www-form-urlencoded")
+        });
+        let body = String::from_utf8_lossy(body);
+        let body = if is_form {
+            redact_params(&body)
+        } else {
+            body.into_owned()
+        };
+        text += &format!("\n{}\n", body);
+    }
+    text
+}
+
+fn build(timeout: Option<String>, proxy: Option<String>) -> Result<Client> {
+    let timeout = match timeout {
+        Some(value) => value
+            .parse()
+            .ok()
+            .filter(|&secs| secs > 0)
+            .map(Duration::from_secs)
+            .ok_or_else(|| {
+                Error::Config(t!(
+                    "invalid value for {}: {}",
+                    env::name("HTTP_TIMEOUT"),
+                    value
+                ))
+            })?,
+        None => Duration::from_secs(DEFAULT_TIMEOUT_SECS),
+    };
+    let mut builder = Client::builder()
+        .user_agent(concat!(
+            env!("CARGO_PKG_NAME"),
+            "/",
+            env!("CARGO_PKG_VERSION")
+        ))
+        .timeout(timeout)
+        .connect_timeout(timeout.min(Duration::from_secs(CONNECT_TIMEOUT_SECS)));
+    if let Some(url) = proxy {
+        let proxy = reqwest::Proxy::all(&url).map_err(|e| {
+            Error::Config(t!(
+                "invalid value for {}: {}",
+                env::name("PROXY"),
+                format!("{} ({})", url, e)
+            ))
+        })?;
+        builder = builder.proxy(proxy);
+    }
+    Ok(builder.build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build() {
+        assert!(build(None, None).is_ok());
+        assert!(
+            build(
+                Some("5".to_string()),
+                Some("http://localhost:3128".to_string())
+            )
+            .is_ok()
+        );
+        for timeout in ["0", "-1", "soon"] {
+            assert!(
+                matches!(
+                    build(Some(timeout.to_string()), None),
+                    Err(Error::Config(_))
+                ),
+                "{}",
+                timeout
+            );
+        }
+        assert!(matches!(
+            build(None, Some("not a url".to_string())),
+            Err(Error::Config(_))
+        ));
+    }
+
+    #[test]
+    fn test_trace_redaction() {
+        let client = build(None, None).unwrap();
+        let request = client
+            .post("https://api.kraken.com/0/private/Ledgers?apikey=abc&asset=XBT")
+            .header("API-Key", "my-key")
+            .header("Accept", "application/json")
+            .form(&[("nonce", "1"), ("signature", "xyz")])
+            .build()
+            .unwrap();
+        let trace = format_request(&request);
+        assert!(trace.contains(
+            " POST https://api.kraken.com/0/private/Ledgers?apikey=[REDACTED]&asset=XBT\n"
+        ));
+        assert!(trace.contains("api-key: [REDACTED]\n"));
+        assert!(trace.contains("accept: application/json\n"));
+        assert!(trace.contains("\nnonce=1&signature=[REDACTED]\n"));
+        assert!(!trace.contains("my-key") && !trace.contains("xyz"));
+
+        assert_eq!(format_body(b"{}", 10), "\n{}\n\n");
+        assert_eq!(format_body(b"0123456789", 4), "\n0123\n[6 more bytes]\n\n");
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+        headers.insert(RETRY_AFTER, "7".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(7)));
+        // An HTTP date isn't used, the default wait is
+        headers.insert(
+            RETRY_AFTER,
+            "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap(),
+        );
+        assert_eq!(retry_after(&headers), None);
+    }
+}
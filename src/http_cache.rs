@@ -0,0 +1,207 @@
+//! A cache of the responses of the public endpoints (the BCB and CoinGecko
+//! rates and the public Kraken API), kept between runs so the same data isn't
+//! downloaded again every time. A response is reused as is for a while, its
+//! time to live. After that it is asked for again, with `If-None-Match` and
+//! `If-Modified-Since` when the server gave an `ETag` or a `Last-Modified`, so
+//! an unchanged response comes back as a bodyless 304 Not Modified.
+//!
+//! The responses are kept under `http` in [`paths::cache_dir`], one file per
+//! URL. `KRAKEN2RFB_HTTP_CACHE=no` turns the cache off. The cache only saves
+//! traffic: a file that can't be read or written is treated as missing.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use reqwest::StatusCode;
+use reqwest::header::{ETAG, HeaderMap, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::error::Result;
+use crate::{env, http, paths};
+
+static DIR: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+/// Sets the directory of the cache, or turns it off with `None`, e.g. in the
+/// tests. Must be called before the first request; otherwise the directory is
+/// already fixed and it is returned back as the error.
+pub fn set(dir: Option<PathBuf>) -> std::result::Result<(), Option<PathBuf>> {
+    DIR.set(dir)
+}
+
+/// The directory of the cache, `None` if it is off
+fn dir() -> Result<Option<&'static Path>> {
+    if let Some(dir) = DIR.get() {
+        return Ok(dir.as_deref());
+    }
+    let enabled = env::var("HTTP_CACHE").is_none() || env::flag("HTTP_CACHE")?;
+    let dir = enabled
+        .then(paths::cache_dir)
+        .flatten()
+        .map(|dir| dir.join("http"));
+    Ok(DIR.get_or_init(|| dir).as_deref())
+}
+
+/// A response, from the server or from the cache
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: StatusCode,
+    pub body: String,
+}
+
+/// A cached response, always a successful one
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    url: String,
+    /// When it was downloaded, or last confirmed unchanged, in seconds since
+    /// the epoch
+    fetched: u64,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    body: String,
+}
+
+impl Entry {
+    fn is_fresh(&self, now: u64, ttl: Duration) -> bool {
+        now.saturating_sub(self.fetched) < ttl.as_secs()
+    }
+
+    /// The headers asking for the response only if it changed
+    fn conditions(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let conditions = [
+            (IF_NONE_MATCH, &self.etag),
+            (IF_MODIFIED_SINCE, &self.last_modified),
+        ];
+        for (name, value) in conditions {
+            if let Some(value) = value.as_deref().and_then(|v| v.parse().ok()) {
+                headers.insert(name, value);
+            }
+        }
+        headers
+    }
+}
+
+/// The file of the cached response of `url`
+fn entry_path(dir: &Path, url: &str) -> PathBuf {
+    let hash: String = Sha256::digest(url.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    dir.join(format!("{}.json", hash))
+}
+
+fn read_entry(path: &Path, url: &str) -> Option<Entry> {
+    let entry: Entry = serde_json::from_str(&fs::read_to_string(path).ok()?).ok()?;
+    (entry.url == url).then_some(entry)
+}
+
+fn write_entry(path: &Path, entry: &Entry) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, serde_json::to_string(entry)?)?;
+    fs::rename(&tmp, path)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// GETs `url` with [`http::send`], reusing its cached response if it is
+/// younger than `ttl`, or if the server says it didn't change. Only the
+/// successful responses are kept.
+pub fn get(url: &str, ttl: Duration) -> Result<Response> {
+    let Some(dir) = dir()? else {
+        return fetch(url, None).map(|(response, _)| response);
+    };
+    let path = entry_path(dir, url);
+    let now = now();
+    let cached = read_entry(&path, url);
+    if let Some(entry) = &cached
+        && entry.is_fresh(now, ttl)
+    {
+        return Ok(Response {
+            status: StatusCode::OK,
+            body: entry.body.clone(),
+        });
+    }
+    let (response, headers) = fetch(url, cached.as_ref())?;
+    let entry = match cached {
+        Some(entry) if response.status == StatusCode::NOT_MODIFIED => Entry {
+            fetched: now,
+            ..entry
+        },
+        _ if response.status.is_success() => Entry {
+            url: url.to_string(),
+            fetched: now,
+            etag: header(&headers, ETAG),
+            last_modified: header(&headers, LAST_MODIFIED),
+            body: response.body,
+        },
+        _ => return Ok(response),
+    };
+    let _ = write_entry(&path, &entry);
+    Ok(Response {
+        status: StatusCode::OK,
+        body: entry.body,
+    })
+}
+
+/// Sends the request, conditional on `cached` having changed if given
+fn fetch(url: &str, cached: Option<&Entry>) -> Result<(Response, HeaderMap)> {
+    let mut request = http::client()?.get(url);
+    if let Some(entry) = cached {
+        request = request.headers(entry.conditions());
+    }
+    let response = http::send(request)?;
+    let (status, headers) = (response.status(), response.headers().clone());
+    let body = response.text()?;
+    Ok((Response { status, body }, headers))
+}
+
+fn header(headers: &HeaderMap, name: reqwest::header::HeaderName) -> Option<String> {
+    Some(headers.get(name)?.to_str().ok()?.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry() {
+        let mut entry = Entry {
+            url: "https://api.kraken.com/0/public/Assets".to_string(),
+            fetched: 1_000,
+            etag: Some("\"abc\"".to_string()),
+            last_modified: None,
+            body: "{}".to_string(),
+        };
+        let hour = Duration::from_secs(3600);
+        assert!(entry.is_fresh(1_000 + 3599, hour));
+        assert!(!entry.is_fresh(1_000 + 3600, hour));
+
+        let headers = entry.conditions();
+        assert_eq!(headers[IF_NONE_MATCH], "\"abc\"");
+        assert!(!headers.contains_key(IF_MODIFIED_SINCE));
+        entry.last_modified = Some("Wed, 21 Oct 2015 07:28:00 GMT".to_string());
+        assert_eq!(entry.conditions().len(), 2);
+
+        let dir = std::env::temp_dir().join(format!("kraken2rfb-cache-{}", std::process::id()));
+        let path = entry_path(&dir, &entry.url);
+        assert_ne!(
+            path,
+            entry_path(&dir, "https://api.kraken.com/0/public/Time")
+        );
+        write_entry(&path, &entry).unwrap();
+        assert_eq!(read_entry(&path, &entry.url).unwrap().body, "{}");
+        // Another URL with the same file isn't taken for it
+        assert!(read_entry(&path, "https://api.kraken.com/").is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,772 @@
+//! Minimal message translation layer.
+//!
+//! User facing messages are written in English with `{}` placeholders and
+//! passed through the [`t!`] macro, which looks up the translation of the
+//! template for the selected language and fills in the arguments:
+//!
+//! ```ignore
+//! println!("{}", t!("Resuming from checkpoint {}", path.display()));
+//! ```
+//!
+//! Templates without a translation are shown in English.
+
+use std::fmt::{self, Write};
+use std::sync::OnceLock;
+
+use phf::phf_map;
+
+use crate::error::{Error, Result};
+
+/// Language of the messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lang {
+    En,
+    PtBr,
+}
+
+impl Lang {
+    /// Parses a language tag like "pt-BR", "pt_BR.UTF-8" or "en"
+    pub fn from_tag(tag: &str) -> Option<Lang> {
+        let tag = tag.to_lowercase();
+        let primary = tag.split(['-', '_', '.', '@']).next().unwrap_or_default();
+        match primary {
+            "pt" => Some(Lang::PtBr),
+            "en" | "c" | "posix" => Some(Lang::En),
+            _ => None,
+        }
+    }
+
+    /// Detects the language from the locale environment variables, in the
+    /// usual order of precedence: LC_ALL, LC_MESSAGES and LANG.
+    pub fn from_env() -> Lang {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|var| std::env::var(var).ok())
+            .find(|value| !value.is_empty())
+            .and_then(|value| Lang::from_tag(&value))
+            .unwrap_or(Lang::En)
+    }
+}
+
+static LANG: OnceLock<Lang> = OnceLock::new();
+
+/// Selects the language of the messages. Only the first call has any effect.
+pub fn set_lang(lang: Lang) {
+    let _ = LANG.set(lang);
+}
+
+/// Selects the language given with `--lang` or `KRAKEN2RFB_LANG`, or detects
+/// it from the locale
+pub fn init(arg: Option<&str>) -> Result<()> {
+    let lang = match arg.map(String::from).or_else(|| crate::env::var("LANG")) {
+        Some(tag) => Lang::from_tag(&tag)
+            .ok_or_else(|| Error::Config(t!("unsupported language: {}", tag)))?,
+        None => Lang::from_env(),
+    };
+    set_lang(lang);
+    Ok(())
+}
+
+pub fn lang() -> Lang {
+    LANG.get().copied().unwrap_or(Lang::En)
+}
+
+/// Returns the template translated to the selected language
+pub fn translate(template: &'static str) -> &'static str {
+    match lang() {
+        Lang::En => template,
+        Lang::PtBr => PT_BR.get(template).copied().unwrap_or(template),
+    }
+}
+
+/// Replaces each `{}` in the template with the next argument
+pub fn format(template: &str, args: &[&dyn fmt::Display]) -> String {
+    let mut parts = template.split("{}");
+    let mut out = parts.next().unwrap_or_default().to_string();
+    let mut args = args.iter();
+    for part in parts {
+        if let Some(arg) = args.next() {
+            let _ = write!(out, "{}", arg);
+        }
+        out.push_str(part);
+    }
+    out
+}
+
+/// Translates a message template and fills in its arguments
+#[macro_export]
+macro_rules! t {
+    ($template:literal $(, $arg:expr)* $(,)?) => {
+        $crate::i18n::format($crate::i18n::translate($template), &[$(&$arg),*])
+    };
+}
+pub use crate::t;
+
+/// Brazilian Portuguese translations, keyed by the English template
+static PT_BR: phf::Map<&'static str, &'static str> = phf_map! {
+    // Errors
+    "configuration error: {}" => "erro de configuração: {}",
+    "network error: {}" => "erro de rede: {}",
+    "invalid data: {}" => "dados inválidos: {}",
+    "I/O error: {}" => "erro de E/S: {}",
+    "Error: {}" => "Erro: {}",
+    "unsupported language: {}" => "idioma não suportado: {}",
+
+    // Command line
+    "Usage: {} [options] <year> <month> <report_file>" =>
+        "Uso: {} [opções] <ano> <mês> <arquivo_do_relatório>",
+    "       {} [options] --period <period> <report_file>" =>
+        "       {} [opções] --period <período> <arquivo_do_relatório>",
+    "Options:" => "Opções:",
+    "Commands:" => "Comandos:",
+    "       {} <command> <arguments>" => "       {} <comando> <argumentos>",
+    "invalid date: {}" => "data inválida: {}",
+    "Show the BRL rate used for an asset on a date (YYYY-MM-DD)" =>
+        "Mostra a cotação em R$ usada para um ativo em uma data (AAAA-MM-DD)",
+    "List the known Kraken pairs, optionally only those of an asset" =>
+        "Lista os pares conhecidos da Kraken, opcionalmente só os de um ativo",
+    "Show the base and quote assets of a Kraken pair" =>
+        "Mostra os ativos base e de cotação de um par da Kraken",
+    "Check the key file, the Kraken API access and the price providers" =>
+        "Verifica o arquivo de chaves, o acesso à API da Kraken e as fontes de cotação",
+    "API key file of the exchange" => "Arquivo com as chaves da API da corretora",
+    "Exchange to fetch the activity from, one of: {} (default: kraken)" => "Corretora de onde obter as operações, uma de: {} (padrão: kraken)",
+    "unknown exchange: {} (expected one of {})" => "corretora desconhecida: {} (esperada uma de {})",
+    "Leave out operations worth less than this, in BRL" =>
+        "Deixa de fora as operações que valem menos do que isso, em R$",
+    "Only generate records of these comma separated types (e.g. 0410,0510)" =>
+        "Gera somente os registros desses tipos, separados por vírgula (ex.: 0410,0510)",
+    "Layout of the report file, to reproduce an old report (default: {})" =>
+        "Leiaute do arquivo do relatório, para reproduzir um relatório antigo (padrão: {})",
+    "Never prompt for missing information" => "Nunca pergunta as informações que faltam",
+    "Discard the progress of an interrupted run" =>
+        "Descarta o progresso de uma execução interrompida",
+    "Period instead of <year> <month>: last-month, this-month, 2024-03 or 2024-Q1" =>
+        "Período no lugar de <ano> <mês>: last-month, this-month, 2024-03 ou 2024-Q1",
+    "Fail instead of warning, e.g. about an old rate or a left out operation" =>
+        "Falha no lugar de avisar, por exemplo de uma cotação antiga ou operação deixada de fora",
+    "Profile of the declarant, with its own keys, store and output directory" =>
+        "Perfil do declarante, com chaves, armazenamento e diretório de saída próprios",
+    "Language of the messages (en or pt-BR)" => "Idioma das mensagens (en ou pt-BR)",
+    "Don't color the output, same as setting NO_COLOR" => "Não colore a saída, o mesmo que definir NO_COLOR",
+    "unknown option: {}\n{}" => "opção desconhecida: {}\n{}",
+    "wrong number of arguments\n{}" => "número errado de argumentos\n{}",
+    "invalid year: {}" => "ano inválido: {}",
+    "invalid month: {}" => "mês inválido: {}",
+    "invalid month: {}/{}" => "mês inválido: {}/{}",
+    "invalid quarter: {}" => "trimestre inválido: {}",
+    "invalid period: {}" => "período inválido: {}",
+    "invalid value for {}: {}" => "valor inválido para {}: {}",
+    "missing value for {}" => "falta o valor de {}",
+    "unknown record type: {} (valid types: {})" =>
+        "tipo de registro desconhecido: {} (tipos válidos: {})",
+
+    // Run
+    "Generating report for {}, report file: {}" =>
+        "Gerando relatório de {}, arquivo do relatório: {}",
+    "Fetching {} activity from {} to {}" => "Obtendo a atividade na {} de {} a {}",
+    "Fetching and converting {} activity from {} to {} batch by batch" =>
+        "Obtendo e convertendo a atividade na {} de {} a {} em lotes",
+    "The transactions of a streamed report aren't kept, so they weren't saved to the store nor archived" =>
+        "As operações de um relatório gerado em lotes não são guardadas, então não foram salvas no armazenamento nem arquivadas",
+    "Records written:" => "Registros escritos:",
+    "Warning: {}" => "Aviso: {}",
+    "the rate of {} for {} is from {}, {} days before" =>
+        "a cotação de {} para {} é de {}, {} dias antes",
+    "replaced the characters not allowed in the report: {}" =>
+        "os caracteres não permitidos no relatório foram substituídos: {}",
+    "{} (an error because of --fail-on-warning)" => "{} (um erro por causa de --fail-on-warning)",
+    "{} operations worth less than {} BRL were left out of the report, totaling {} BRL:" =>
+        "{} operações que valem menos de R$ {} ficaram de fora do relatório, totalizando R$ {}:",
+    "{} operations worth less than {} BRL were left out of the report, totaling {} BRL" =>
+        "{} operações que valem menos de R$ {} ficaram de fora do relatório, totalizando R$ {}",
+
+    // Summary
+    "Summary:" => "Resumo:",
+    "no records" => "nenhum registro",
+    "{}: {} records" => "{}: {} registros",
+    "Total bought: {} BRL" => "Total comprado: R$ {}",
+    "Total sold: {} BRL" => "Total vendido: R$ {}",
+    "Total fees: {} BRL" => "Total de taxas: R$ {}",
+
+    // Checkpoint
+    "corrupted checkpoint file {} ({}), delete it or use --restart" =>
+        "arquivo de progresso {} corrompido ({}), apague-o ou use --restart",
+    "Resuming from checkpoint {}" => "Continuando a partir do progresso salvo em {}",
+    "Ignoring checkpoint {} from another period ({})" =>
+        "Ignorando o progresso salvo em {}, de outro período ({})",
+    "Ignoring checkpoint {} from another version" =>
+        "Ignorando o progresso salvo em {}, de outra versão",
+    "failed to serialize checkpoint: {}" => "falha ao serializar o progresso: {}",
+
+    // Prompts
+    "The character '|' is not allowed." => "O caractere '|' não é permitido.",
+    "Invalid number: {}" => "Número inválido: {}",
+    "Price in BRL of 1 {} on {} (empty to abort):" =>
+        "Preço em R$ de 1 {} em {} (vazio para abortar):",
+    "Deposit of {} {} on {}" => "Depósito de {} {} em {}",
+    "{}: origin wallet (empty if unknown):" => "{}: carteira de origem (vazio se desconhecida):",
+    "{}: origin exchange name (empty if unknown):" =>
+        "{}: nome da exchange de origem (vazio se desconhecida):",
+
+    // Kraken
+    "{} error on {}: {}" => "erro da {} em {}: {}",
+    "invalid JSON from Binance {}: {}" => "JSON inválido da Binance {}: {}",
+    "unexpected {} response for {}" => "resposta inesperada da {} para {}",
+    "no {} market to value {} in {} on {}" => "nenhum mercado na {} para avaliar {} em {} em {}",
+    "Binance entry with zero amount: {}" => "entrada da Binance com quantidade zero: {}",
+    "invalid JSON from Bitstamp {}: {}" => "JSON inválido da Bitstamp {}: {}",
+    "unexpected Bitstamp entry: {}" => "entrada inesperada da Bitstamp: {}",
+    "invalid JSON from Bybit {}: {}" => "JSON inválido da Bybit {}: {}",
+    "unexpected Bybit entry: {}" => "entrada inesperada da Bybit: {}",
+    "invalid JSON from OKX {}: {}" => "JSON inválido da OKX {}: {}",
+    "unexpected OKX entry: {}" => "entrada inesperada da OKX: {}",
+    "invalid JSON from KuCoin {}: {}" => "JSON inválido da KuCoin {}: {}",
+    "unexpected KuCoin entry: {}" => "entrada inesperada da KuCoin: {}",
+    "the {} API key has no passphrase: add it to {} or set {}" => "a chave da API da {} não tem a senha (passphrase): adicione-a a {} ou defina {}",
+    "invalid URL: {}" => "URL inválida: {}",
+    "invalid JSON from Gemini {}: {}" => "JSON inválido da Gemini {}: {}",
+    "unexpected Gemini entry: {}" => "entrada inesperada da Gemini: {}",
+    "invalid JSON from Gate.io {}: {}" => "JSON inválido da Gate.io {}: {}",
+    "unexpected Gate.io entry: {}" => "entrada inesperada da Gate.io: {}",
+    "invalid mapping file {}: {}" => "arquivo de mapeamento inválido {}: {}",
+    "the decimal separator must be \".\" or \",\", not \"{}\"" => "o separador decimal deve ser \".\" ou \",\", não \"{}\"",
+    "trades need a quote column or a quote_asset" => "as negociações precisam de uma coluna quote ou de um quote_asset",
+    "trades need a cost or a price column" => "as negociações precisam de uma coluna cost ou price",
+    "invalid UTC offset: {}" => "deslocamento de UTC inválido: {}",
+    "--exchange csv needs a mapping file, given with --mapping" => "--exchange csv precisa de um arquivo de mapeamento, informado com --mapping",
+    "--mapping is only for --exchange csv" => "--mapping é só para --exchange csv",
+    "Mapping file of the columns of the CSV file, with --exchange csv" => "Arquivo de mapeamento das colunas do arquivo CSV, com --exchange csv",
+    "Wallet to report of the file exported from a tracker ({})" => "Carteira a declarar do arquivo exportado de um agregador ({})",
+    "--exchange {} needs the name of the wallet to report, given with --wallet" => "--exchange {} precisa do nome da carteira a declarar, dado com --wallet",
+    "--wallet is only for the exchanges imported from a tracker: {}" => "--wallet é só para as exchanges importadas de um agregador: {}",
+    "no exchange known for the wallet {} (expected one of {})" => "nenhuma exchange conhecida para a carteira {} (esperada uma de {})",
+    "Extended public key or descriptor of a Bitcoin wallet of yours, to find the deposits that came from it" => "Chave pública estendida ou descritor de uma carteira Bitcoin sua, para achar os depósitos vindos dela",
+    "invalid extended public key: {}" => "chave pública estendida inválida: {}",
+    "no child key at index {}" => "nenhuma chave filha no índice {}",
+    "RIPEMD-160 is not available: {}" => "RIPEMD-160 não está disponível: {}",
+    "unsupported wallet descriptor: {}" => "descritor de carteira não suportado: {}",
+    "block explorer error on {}: {}" => "erro do explorador de blocos em {}: {}",
+    "invalid JSON: {}" => "JSON inválido: {}",
+    "Scanning the Bitcoin wallet on the block explorer" => "Varrendo a carteira Bitcoin no explorador de blocos",
+    "  Deposit of {} {} on {} came from the wallet (transaction {})" => "  Depósito de {} {} em {} veio da carteira (transação {})",
+    "  Deposit of {} {} on {} came from {}, by its withdrawal in the store" => "  Depósito de {} {} em {} veio de {}, pelo saque dela no armazenamento",
+    "  Withdrawal of {} {} on {} went to the wallet (transaction {})" => "  Retirada de {} {} em {} foi para a carteira (transação {})",
+    "{} of the {} transfers of the wallets matched to the exchange" => "{} das {} transferências das carteiras casadas com a exchange",
+    "Scanning the Ethereum addresses on the block explorer" => "Varrendo os endereços Ethereum no explorador de blocos",
+    "Comma separated Ethereum addresses of yours, to find the deposits that came from them" => "Endereços Ethereum seus, separados por vírgula, para achar os depósitos vindos deles",
+    "invalid Ethereum address: {}" => "endereço Ethereum inválido: {}",
+    "invalid block: {}" => "bloco inválido: {}",
+    "invalid decimals: {}" => "casas decimais inválidas: {}",
+    "invalid gas: {} at {}" => "gás inválido: {} a {}",
+    "invalid amount {} of {}" => "quantidade inválida {} de {}",
+    "the Etherscan API needs a key, given in {}" => "a API do Etherscan precisa de uma chave, dada em {}",
+    "invalid timestamp: {}" => "horário inválido: {}",
+    "{} is not the export of a known wallet" => "{} não é a exportação de uma carteira conhecida",
+    "Reading the wallet export {}" => "Lendo a exportação da carteira {}",
+    "Comma separated exports of Ledger Live, Trezor Suite, Electrum or Exodus, to find the deposits that came from those wallets" => "Exportações do Ledger Live, do Trezor Suite, do Electrum ou do Exodus, separadas por vírgula, para achar os depósitos vindos dessas carteiras",
+    "  Deposit of {} {} on {} came from the wallet (transaction {}, network fee {})" => "  Depósito de {} {} em {} veio da carteira (transação {}, taxa da rede {})",
+    "no output of the transaction pays {} {}" => "nenhuma saída da transação paga {} {}",
+    "the transaction pays nothing to {}" => "a transação não paga nada a {}",
+    "the transaction pays {} {} to {}, not {}" => "a transação paga {} {} a {}, não {}",
+    "Check that the transaction of each Bitcoin and Ether withdrawal pays what the exchange recorded, on the block explorers" => "Confere nos exploradores de blocos se a transação de cada retirada de bitcoin e de ether paga o que a exchange registrou",
+    "Verifying the withdrawals on the block explorers" => "Conferindo as retiradas nos exploradores de blocos",
+    "the transaction {} of the withdrawal of {} {} on {} is not on the blockchain" => "a transação {} da retirada de {} {} em {} não está no blockchain",
+    "the withdrawal of {} {} on {} doesn't match its transaction {}: {}" => "a retirada de {} {} em {} não confere com a sua transação {}: {}",
+    "{} withdrawals confirmed on the blockchain, {} with no transaction to look up" => "{} retiradas confirmadas no blockchain, {} sem transação a consultar",
+    "unknown import format: {} (expected {})" => "formato de importação desconhecido: {} (esperado {})",
+    "Save the operations of a Brazilian exchange in the store, for the gains and the exemption; the exchange reports them itself" => "Salva as operações de uma exchange brasileira no armazenamento, para os ganhos e a isenção; a própria exchange as declara",
+    "No transactions to import in {}" => "Nenhuma transação a importar em {}",
+    "{} transactions of {} saved to the store, from {} to {}" => "{} transações da {} salvas no armazenamento, de {} a {}",
+    "Save the transactions of a JSON document, as read by encode, in the store under the source" => "Salva as operações de um documento JSON, como lido pelo encode, no armazenamento sob a fonte",
+    "--format json needs the source of the transactions, given with --source" => "--format json precisa da fonte das operações, dada com --source",
+    "--source is only for --format json" => "--source é só para --format json",
+    "Offset from UTC of the days the operations are reported on, like -02:00 (default: -03:00, Brasília time)" => "Diferença para UTC dos dias em que as operações são declaradas, como -02:00 (padrão: -03:00, horário de Brasília)",
+    "the pair {} is not in the Kraken data, taken as {}/{}; run update-data to get it" => "o par {} não está nos dados da Kraken, tomado como {}/{}; rode update-data para obtê-lo",
+    "{}: not in the Kraken data, perhaps base {}, quote {}" => "{}: não está nos dados da Kraken, talvez base {}, cotação {}",
+    "invalid fiat currency: {}" => "moeda fiduciária inválida: {}",
+    "{} (expected {})" => "{} (esperado {})",
+    "Deposit {} left out, already reported in {}" => "Depósito {} deixado de fora, já informado em {}",
+    "the Kraken trade {} is flagged {}, but reported as an ordinary trade; check it" => "a negociação {} da Kraken tem as marcações {}, mas é informada como uma negociação comum; confira-a",
+    "Show the saved crypto income of a year (default: this one), like referral rewards, fee rebates and staking rewards, with its value when received, or write its total by month and asset to a CSV file" => "Mostra as receitas em criptoativos salvas de um ano (padrão: o atual), como recompensas por indicação, devoluções de taxas e recompensas de staking, com o seu valor quando recebidas, ou grava o seu total por mês e ativo em um arquivo CSV",
+    "Fetch every BRL rate the report of a period needs and keep it in the HTTP cache, so the report is generated later without waiting for the providers" => "Busca todas as cotações em BRL de que o relatório de um período precisa e as guarda no cache HTTP, para que o relatório seja gerado depois sem esperar pelos provedores",
+    "Fetching {} rates for {}" => "Buscando {} cotações para {}",
+    "{} of {} rates fetched, {} failed" => "{} de {} cotações obtidas, {} falharam",
+    "Write the activity of a period, the BRL rates its report needs and the configuration to a single file, to generate the report on a machine without network access" => "Grava a atividade de um período, as cotações em BRL de que o relatório precisa e a configuração em um único arquivo, para gerar o relatório em uma máquina sem acesso à rede",
+    "Read a bundle written by bundle export, so the report of its period is generated to the report file without fetching anything" => "Lê um pacote gravado por bundle export, para que o relatório do período seja gerado no arquivo de relatório sem buscar nada",
+    "failed to serialize the bundle: {}" => "falha ao serializar o pacote: {}",
+    "invalid bundle file {}: {}" => "arquivo de pacote inválido {}: {}",
+    "the bundle file {} is of another version of kraken2rfb" => "o arquivo de pacote {} é de outra versão do kraken2rfb",
+    "Bundle of {} written to {}, with {} rates" => "Pacote de {} gravado em {}, com {} cotações",
+    "The configuration of the bundle differs from the one here, which is kept" => "A configuração do pacote difere da desta máquina, que é mantida",
+    "Bundle of {} from kraken2rfb {} saved to {}, generate the report with:" => "Pacote de {} do kraken2rfb {} salvo em {}, gere o relatório com:",
+    "Show how a single trade or ledger entry is classified, the BRL rates it is converted with and the report line it turns into, without a whole run" => "Mostra como uma única negociação ou entrada do extrato é classificada, as cotações em BRL com que é convertida e a linha do relatório que gera, sem uma execução completa",
+    "--period can't be given with --bundle, which has its own" => "--period não pode ser usado com --bundle, que tem o seu próprio",
+    "--period is needed to find the entry on {}" => "--period é necessário para encontrar a entrada na {}",
+    "Kraken has no trade or ledger entry {}" => "A Kraken não tem a negociação ou entrada do extrato {}",
+    "no entry with ID {} in the activity" => "nenhuma entrada com ID {} nas operações",
+    "Classified as:" => "Classificada como:",
+    "income of {} {} on {}, worth {} BRL, with no record in the report" => "receita de {} {} em {}, valendo {} BRL, sem registro no relatório",
+    "move of {} {} between the wallets of the account ({}), with no record in the report" => "movimentação de {} {} entre as carteiras da conta ({}), sem registro no relatório",
+    "nothing, e.g. a fiat deposit or a fiat-fiat trade" => "nada, como um depósito em moeda fiduciária ou uma negociação entre moedas fiduciárias",
+    "Rates:" => "Cotações:",
+    "{} on {}: {} BRL ({})" => "{} em {}: {} BRL ({})",
+    "Report lines:" => "Linhas do relatório:",
+    "{} can't be computed without losing precision, in {}" => "{} não pode ser calculado sem perda de precisão, em {}",
+    "{} of {}" => "{} de {}",
+    "deposit" => "depósito",
+    "withdrawal" => "retirada",
+    "trade" => "negociação",
+    "income" => "receita",
+    "the totals, adding the {} record of {}" => "os totais, ao somar o registro {} de {}",
+    "the income of {} {} on {}" => "a receita de {} {} em {}",
+    "the gains, adding the {} record of {}" => "os ganhos, ao somar o registro {} de {}",
+    "the gains, adding {}" => "os ganhos, ao somar {}",
+    "the holdings, adding the {} record of {}" => "a posição, ao somar o registro {} de {}",
+    "the sales, adding the {} record of {}" => "as vendas, ao somar o registro {} de {}",
+    "the tax of {}/{}" => "o imposto de {}/{}",
+    "the holdings of {} on {}" => "a posição de {} em {}",
+    "the sale of {} {}" => "a venda de {} {}",
+    "the income of the report" => "os rendimentos do relatório",
+    "the operations below the minimum value" => "as operações abaixo do valor mínimo",
+    "the sales of {}" => "as vendas de {}",
+    "the income of {}" => "os rendimentos de {}",
+    "the outputs of the transaction to {}" => "as saídas da transação para {}",
+    "the Kraken order {}, adding the trade {}" => "a ordem {} da Kraken, ao somar a negociação {}",
+    "the balance of {} on Kraken" => "o saldo de {} na Kraken",
+    "Income of {} by month and asset written to {}" => "Receitas de {} por mês e ativo gravadas em {}",
+    "unknown income kind: {} (expected {})" => "tipo de receita desconhecido: {} (esperado {})",
+    "staking" => "staking",
+    "Income in {}:" => "Receitas em {}:",
+    "referral" => "indicação",
+    "fee rebate" => "devolução de taxa",
+    "Total of {}: {} BRL" => "Total de {}: {} BRL",
+    "{} income entries worth {} BRL, saved in the store for the gains (the report has no record for them)" => "{} receitas no valor de {} BRL, salvas no armazenamento para os ganhos (o relatório não tem registro para elas)",
+    "the API key can't query the ledger (\"Query Ledger Entries\"), so the referral rewards, fee rebates, staking rewards and adjustments are left out, the moves between wallets are taken as deposits and withdrawals, and the fees paid with fee credits (KFEE) are taken from the trades" => "a chave da API não pode consultar o livro-razão (\"Query Ledger Entries\"), então as recompensas por indicação, as devoluções de taxas, as recompensas de staking e os ajustes ficam de fora, as movimentações entre carteiras são tomadas como depósitos e retiradas, e as taxas pagas com créditos de taxa (KFEE) são tomadas das negociações",
+    "Left out as moves between wallets: {}" => "Deixadas de fora como movimentações entre carteiras: {}",
+    "{} moves between the wallets of the account were left out, being neither deposits nor withdrawals:" => "{} movimentações entre as carteiras da conta foram deixadas de fora, por não serem depósitos nem retiradas:",
+    "What to do with crypto amounts with more decimals than the report: warn, error or accumulate the difference (default: warn)" => "O que fazer com quantidades de criptoativos com mais casas decimais que o relatório: warn (avisar), error (erro) ou accumulate (acumular a diferença) (padrão: warn)",
+    "Rounding residuals of the crypto amounts:" => "Resíduos de arredondamento das quantidades de criptoativos:",
+    "the amount {} {} of the {} record of {} has more than {} decimals, and is written as {} ({} off)" => "a quantidade {} {} do registro {} de {} tem mais de {} casas decimais, e é escrita como {} (diferença de {})",
+    "{} (an error because of --truncation error)" => "{} (um erro por causa de --truncation error)",
+    "empty name in the entry {}" => "nome vazio na entrada {}",
+    "What the fees paid with Kraken fee credits (KFEE) are worth: value, their worth in US dollars, or zero (default: value)" => "Quanto valem as taxas pagas com créditos de taxa da Kraken (KFEE): value, seu valor em dólares americanos, ou zero (padrão: value)",
+    "{} of the {} fills of the Kraken order {} are outside the period, so they aren't aggregated with the others" => "{} das {} execuções da ordem {} da Kraken estão fora do período, então não são agregadas às demais",
+    "the Kraken adjustment of {} {} on {} was applied to the operation {}" => "o ajuste de {} {} da Kraken em {} foi aplicado à operação {}",
+    "the Kraken adjustment {} of {} {} on {} matches no operation of the period, so it is left out; check it" => "o ajuste {} de {} {} da Kraken em {} não corresponde a nenhuma operação do período, então fica de fora; confira",
+    "Deposits and withdrawals by network: {}" => "Depósitos e saques por rede: {}",
+    "Deposit of {} {} by {} on {}" => "Depósito de {} {} pela rede {} em {}",
+    "failed to read {}: {}" => "falha ao ler {}: {}",
+    "{} is empty" => "{} está vazio",
+    "{}, line {}: {}" => "{}, linha {}: {}",
+    "{}, line {}: unterminated quoted field" => "{}, linha {}: campo entre aspas não terminado",
+    "{} has no column {}" => "{} não tem a coluna {}",
+    "{} has no column \"{}\"" => "{} não tem a coluna \"{}\"",
+    "missing field \"{}\"" => "campo \"{}\" ausente",
+    "unexpected {} entry: {}" => "entrada inesperada da {}: {}",
+    "give the file exported from {} with --input" => "informe o arquivo exportado da {} com --input",
+    "--input is only for the exchanges imported from a file: {}" => "--input é só para as corretoras importadas de um arquivo: {}",
+    "File exported from the exchange, for the exchanges imported from a file ({})" => "Arquivo exportado da corretora, para as corretoras importadas de um arquivo ({})",
+    "invalid time \"{}\" in {}: {}" => "horário inválido \"{}\" em {}: {}",
+    "--reconcile is only supported for Kraken" => "--reconcile só é suportado para a Kraken",
+    "--stream can't be used with {}" => "--stream não pode ser usado com {}",
+    "{} key file not found, looked in: {}. Use --keyfile or {} to point to it" =>
+        "arquivo de chaves da {} não encontrado, procurado em: {}. Use --keyfile ou {} para indicá-lo",
+    "failed to read key file {}: {}" => "falha ao ler o arquivo de chaves {}: {}",
+    "invalid JSON in key file {}: {}" => "JSON inválido no arquivo de chaves {}: {}",
+    "API secret is not valid base64: {}" => "o segredo da API não é base64 válido: {}",
+    "API key contains invalid characters" => "a chave da API contém caracteres inválidos",
+    "invalid JSON from Kraken {}: {}" => "JSON inválido recebido da Kraken em {}: {}",
+    "unexpected Kraken response for {}" => "resposta inesperada da Kraken em {}",
+    "Kraken error on {}: {}" => "erro da Kraken em {}: {}",
+    "Kraken {} response is not a list" => "a resposta da Kraken para {} não é uma lista",
+    "Kraken {} entry without valid time: {}" => "entrada da Kraken em {} sem horário válido: {}",
+    "Kraken {} entry without valid amount: {}" => "entrada da Kraken em {} sem valor válido: {}",
+    "Show the assets held on a date (default: December 31 of last year) and their BRL value, by the saved transactions" => "Mostrar os ativos mantidos em uma data (padrão: 31 de dezembro do ano passado) e seu valor em R$, pelas operações salvas",
+    "Holdings on {}:" => "Posição em {}:",
+    "Total: {} BRL" => "Total: R$ {}",
+    "Compared with the current balances on Kraken:" => "Comparação com os saldos atuais na Kraken:",
+    "Compared with the balances on Kraken at the end of {}:" => "Comparação com os saldos na Kraken ao fim de {}:",
+    "  every asset matches" => "  todos os ativos conferem",
+    "  {}: {} on Kraken, {} by the saved transactions" => "  {}: {} na Kraken, {} pelas operações salvas",
+    "the sales of {} add up to {} BRL across all sources, more than the exemption of {} BRL: the gains of the month are taxable" => "as vendas de {} somam R$ {} em todas as fontes, mais que a isenção de R$ {}: os ganhos do mês são tributáveis",
+    "Sales in {}:" => "Vendas em {}:",
+    "taxable" => "tributável",
+    "exempt" => "isento",
+    "Show the realized gains and the tax of each month of a year, with the losses carried forward" => "Mostrar os ganhos realizados e o imposto de cada mês de um ano, com a compensação dos prejuízos",
+    "Gains in {}, with {} BRL of losses from before the saved transactions:" => "Ganhos em {}, com R$ {} de prejuízos anteriores às operações salvas:",
+    "month" => "mês",
+    "sales" => "vendas",
+    "gain" => "ganho",
+    "compensated" => "compensado",
+    "tax" => "imposto",
+    "carried loss" => "prejuízo a compensar",
+    "Add what rounding to cents left out to the last purchase or sale of each asset in a month" => "Somar o que o arredondamento para centavos deixou de fora à última compra ou venda de cada ativo no mês",
+    "Rounding residuals of the BRL values:" => "Resíduos do arredondamento dos valores em R$:",
+    " (added to the last record)" => " (somado ao último registro)",
+    "  {} {} {}: value {} BRL, fees {} BRL{}" => "  {} {} {}: valor R$ {}, taxas R$ {}{}",
+    "Show the saved sales of each month of a year (default: this one) and whether they are within the exemption" => "Mostrar as vendas salvas de cada mês de um ano (padrão: o atual) e se estão dentro da isenção",
+    "Afterwards, compare the Kraken balances with the whole history of the account" => "Ao final, comparar os saldos na Kraken com todo o histórico da conta",
+    "Reconciling the balances with the activity from {} to {}" => "Conferindo os saldos com a atividade de {} a {}",
+    "The balances match the history of the account." => "Os saldos conferem com o histórico da conta.",
+    "the balance of {} on Kraken is {}, but its history adds up to {}" => "o saldo de {} na Kraken é {}, mas o histórico soma {}",
+    "Kraken TradesHistory response has no trades" =>
+        "a resposta do TradesHistory da Kraken não tem negociações",
+
+    // Processing
+    "expected a decimal number, got {}" => "esperado um número decimal, obtido {}",
+    "missing field \"{}\" in {}" => "falta o campo \"{}\" em {}",
+    "invalid decimal \"{}\" in field \"{}\": {}" => "decimal inválido \"{}\" no campo \"{}\": {}",
+    "field can't contain '|' or line breaks: {}" =>
+        "campo não pode conter '|' nem quebras de linha: {}",
+    "unknown Kraken asset: {}" => "ativo da Kraken desconhecido: {}",
+    "unknown Kraken pair: {}" => "par da Kraken desconhecido: {}",
+    "timestamp out of range: {}" => "horário fora do intervalo: {}",
+    "failed to get exchange rate for {} on {}: {}" => "falha ao obter a cotação de {} em {}: {}",
+    "unknown trade type: {}" => "tipo de negociação desconhecido: {}",
+    "unexpected Fiat-Crypto trade pair: {}" => "par de negociação Fiat-Cripto inesperado: {}",
+
+    // Dry run
+    "deposit of {} {}" => "depósito de {} {}",
+    "withdrawal of {} {}" => "retirada de {} {}",
+    "purchase of {} {} for {} {}" => "compra de {} {} por {} {}",
+    "sale of {} {} for {} {}" => "venda de {} {} por {} {}",
+    "Dry run: {} records would be generated (BRL values not computed, nothing written):" =>
+        "Simulação: {} registros seriam gerados (valores em R$ não calculados, nada foi gravado):",
+    "Only show the records that would be generated, without rate lookups or writing files" =>
+        "Somente mostra os registros que seriam gerados, sem obter cotações nem gravar arquivos",
+
+    // Exchange rates
+    "Cannot fetch exchange rate for future dates" => "Não é possível obter cotações de datas futuras",
+    "Cryptocurrency ID not found: {}" => "ID de criptomoeda não encontrado: {}",
+    "CoinGecko API error: {}" => "erro da API do CoinGecko: {}",
+    "BRL price not available for {} on {}" => "preço em R$ não disponível para {} em {}",
+    "Failed to convert price to Decimal" => "falha ao converter o preço para Decimal",
+    "Unsupported currency code: {}" => "Código de moeda não suportado: {}",
+    "No exchange rate data available for {} within the last 7 days of {}" =>
+        "Nenhuma cotação disponível para {} nos 7 dias anteriores a {}",
+    "Exchange rate for {} on {} (actual used date {}): {} BRL" =>
+        "Cotação de {} em {} (data efetivamente usada {}): R$ {}",
+    "the report has no source of activity" => "o relatório não tem nenhuma fonte de atividade",
+    "unknown layout: {} (known: {})" => "leiaute desconhecido: {} (conhecidos: {})",
+    "invalid transactions document: {}" => "documento de operações inválido: {}",
+    "unknown schema: {} (expected interchange or summary)" =>
+        "esquema desconhecido: {} (esperado interchange ou summary)",
+    "invalid profile name: {} (use only letters, digits, '-' and '_')" =>
+        "nome de perfil inválido: {} (use apenas letras, dígitos, '-' e '_')",
+    "unknown profile: {}, run {} to create it" => "perfil desconhecido: {}, execute {} para criá-lo",
+    "corrupted store file {}: {}" => "arquivo de armazenamento corrompido {}: {}",
+    "the store {} is from another version of kraken2rfb" =>
+        "o armazenamento {} é de outra versão do kraken2rfb",
+    "failed to serialize the store: {}" => "falha ao serializar o armazenamento: {}",
+    "failed to save the transactions to the store {}: {}" =>
+        "falha ao salvar as operações no armazenamento {}: {}",
+    "no data directory, set {}" => "nenhum diretório de dados, defina {}",
+    "the entry {} was imported more than once with different data, keeping the first:\n  {}\n  {}" =>
+        "o registro {} foi importado mais de uma vez com dados diferentes, mantendo o primeiro:\n  {}\n  {}",
+    "Export the saved transactions, or those matching the query options, to CSV" =>
+        "Exporta para CSV as operações salvas, ou as que atendem às opções do query",
+    "{} transactions exported to {}" => "{} operações exportadas para {}",
+    "List the transactions saved by previous reports" =>
+        "Lista as operações salvas pelos relatórios anteriores",
+    "Don't save the transactions to the store used by the query command" =>
+        "Não salva as operações no armazenamento usado pelo comando query",
+    "Don't archive the report with what it was made from, for an audit trail" =>
+        "Não arquiva o relatório com os dados de que foi gerado, para auditoria",
+    "Fetch, convert and write the report batch by batch, for histories too long to hold in memory; the transactions aren't saved to the store nor archived" =>
+        "Obtém, converte e escreve o relatório em lotes, para históricos longos demais para a memória; as operações não são salvas no armazenamento nem arquivadas",
+    "the archive {} already exists" => "o arquivo morto {} já existe",
+    "Report archived in {}" => "Relatório arquivado em {}",
+    "Manifest written to {}" => "Manifesto salvo em {}",
+    "the report file {} already exists; use --force to overwrite it" => "o arquivo de relatório {} já existe; use --force para sobrescrevê-lo",
+    "failed to read the manifest {}: {}" => "falha ao ler o manifesto {}: {}",
+    "corrupted manifest file {}: {}" => "arquivo de manifesto corrompido {}: {}",
+    "SHA-256 is {}, expected {}" => "o SHA-256 é {}, o esperado era {}",
+    "{} lines, expected {}" => "{} linhas, o esperado era {}",
+    "{} records of type {}, expected {}" => "{} registros do tipo {}, o esperado era {}",
+    "{} was changed since it was generated: {}" => "{} foi alterado depois de gerado: {}",
+    "{} is unchanged: SHA-256 {}, {} lines" => "{} não foi alterado: SHA-256 {}, {} linhas",
+    "Check that a report file is unchanged since it was generated, by its manifest" => "Verifica, pelo manifesto, que um arquivo de relatório não foi alterado desde que foi gerado",
+    "Overwrite the report file if it exists, and generate the report even if its period was marked as filed" =>
+        "Sobrescreve o arquivo de relatório se ele existir, e gera o relatório mesmo que o período tenha sido marcado como entregue",
+    "Mark the report of a period as filed with the RFB, so it isn't generated again by mistake, or list the reported periods" =>
+        "Marca o relatório de um período como entregue à RFB, para que não seja gerado de novo por engano, ou lista os períodos informados",
+    "corrupted registry file {}: {}" => "arquivo de registro corrompido {}: {}",
+    "failed to save the registry {}: {}" => "falha ao salvar o registro {}: {}",
+    "No period reported yet" => "Nenhum período informado ainda",
+    "generated at {}" => "gerado em {}",
+    "filed on {}" => "entregue em {}",
+    "{} on {}" => "{} em {}",
+    "{} on {} marked as not filed" => "{} na {} marcado como não entregue",
+    "{} on {} marked as filed on {}" => "{} na {} marcado como entregue em {}",
+    "the report of {} was already filed ({}), a new one may differ from the file submitted" =>
+        "o relatório de {} já foi entregue ({}), um novo pode diferir do arquivo enviado",
+    "{}; use --force to generate it anyway" => "{}; use --force para gerá-lo mesmo assim",
+    "failed to archive the report in {}: {}" => "falha ao arquivar o relatório em {}: {}",
+    "unsupported transactions document version {} (supported: {})" =>
+        "versão {} do documento de operações não suportada (suportada: {})",
+    "Write the report of the transactions in a JSON document, e.g. saved with --json" =>
+        "Gera o relatório das operações de um documento JSON, como o salvo com --json",
+    "Print the JSON Schema of the transactions document or of the run summary" =>
+        "Exibe o JSON Schema do documento de operações ou do resumo da execução",
+    "Also save the transactions as JSON, to inspect or encode them later" =>
+        "Salva também as operações em JSON, para inspecioná-las ou gerar o relatório depois",
+    "not resolved beforehand" => "não obtida antecipadamente",
+    "the {} rate provider is not enabled in this build" =>
+        "o provedor de cotações {} não foi incluído nesta compilação",
+    "BCB (series {})" => "BCB (série {})",
+    "CoinGecko (id {})" => "CoinGecko (id {})",
+
+    // Rates command
+    "{} on {}: {} BRL" => "{} em {}: R$ {}",
+    "  source: {}, rate date: {}" => "  fonte: {}, data da cotação: {}",
+
+    // Pairs command
+    "{}: base {}, quote {}" => "{}: base {}, cotação {}",
+    "Similar pairs: {}" => "Pares parecidos: {}",
+
+    "Print the raw Kraken data and the conversion details" =>
+        "Mostra os dados brutos da Kraken e os detalhes da conversão",
+
+    // Exclusions
+    "Leave out the Kraken entries with these comma separated IDs" =>
+        "Deixa de fora as entradas da Kraken com estes IDs, separados por vírgula",
+    "Leave out the Kraken entries with the IDs listed in this file" =>
+        "Deixa de fora as entradas da Kraken com os IDs listados neste arquivo",
+    "failed to read exclusion file {}: {}" => "falha ao ler o arquivo de exclusões {}: {}",
+    "no Kraken entry found with the excluded IDs: {}" =>
+        "nenhuma entrada da Kraken encontrada com os IDs excluídos: {}",
+    "Excluded by ID: {}" => "Excluídos por ID: {}",
+
+    // Review
+    "Review, exclude and edit the records before writing the report" =>
+        "Revisa, exclui e edita os registros antes de gravar o relatório",
+    "bought {} {} for {} BRL" => "compra de {} {} por R$ {}",
+    "sold {} {} for {} BRL" => "venda de {} {} por R$ {}",
+    "swap of {} {} for {} {}" => "permuta de {} {} por {} {}",
+    "deposit of {} {} (wallet: {}, exchange: {})" => "depósito de {} {} (carteira: {}, exchange: {})",
+    "payment received of {} {}" => "pagamento recebido de {} {}",
+    "payment made of {} {}" => "pagamento feito de {} {}",
+    "  <n>              show the details of record n" => "  <n>              mostra os detalhes do registro n",
+    "  x <n>            exclude record n, or include it back" => "  x <n>            exclui o registro n, ou o inclui de volta",
+    "  o <n> <wallet>   set the origin wallet of deposit n" => "  o <n> <carteira> define a carteira de origem do depósito n",
+    "  e <n> <exchange> set the origin exchange of deposit n" => "  e <n> <exchange> define a exchange de origem do depósito n",
+    "  n <n> <note>     annotate record n" => "  n <n> <nota>     anota o registro n",
+    "  l                list the records again" => "  l                lista os registros novamente",
+    "  w                write the report with the included records" => "  w                grava o relatório com os registros incluídos",
+    "  q                quit without writing the report" => "  q                sai sem gravar o relatório",
+    "the text can't contain '|' or line breaks" =>
+        "o texto não pode conter '|' nem quebras de linha",
+    "no record number {}" => "não há registro número {}",
+    "record {} is not a deposit" => "o registro {} não é um depósito",
+    "--review needs an interactive terminal" => "--review precisa de um terminal interativo",
+    "review>" => "revisão>",
+    "Invalid command: {}" => "Comando inválido: {}",
+    "Report not written." => "Relatório não gravado.",
+    "{} records excluded during review" => "{} registros excluídos na revisão",
+    "Notes:" => "Notas:",
+
+    // Sale simulation
+    "Estimate the gain and the tax of selling an asset today" =>
+        "Estima o ganho e o imposto da venda de um ativo hoje",
+    "Sale of {} {} at {} BRL: {} BRL" => "Venda de {} {} a R$ {}: R$ {}",
+    "  Cost: {} BRL" => "  Custo: R$ {}",
+    "  Gain: {} BRL" => "  Ganho: R$ {}",
+    "  Sales in the month: {} BRL" => "  Vendas no mês: R$ {}",
+    "  Exempt: the month's sales don't exceed {} BRL" => "  Isento: as vendas do mês não passam de R$ {}",
+    "  No tax due, there is no gain" => "  Nenhum imposto devido, não há ganho",
+    "  Tax bracket: {}%" => "  Alíquota: {}%",
+    "  DARF (code {}): {} BRL, due by {}" => "  DARF (código {}): R$ {}, vencimento em {}",
+
+    // Update data command
+    "Download fresh Kraken asset and pair data (default directory: kraken_data)" =>
+        "Baixa dados atualizados de ativos e pares da Kraken (diretório padrão: kraken_data)",
+    "Downloading {} into {}" => "Baixando {} em {}",
+    "failed to serialize {}: {}" => "falha ao serializar {}: {}",
+    "no changes" => "nenhuma alteração",
+    "added" => "adicionados",
+    "removed" => "removidos",
+    "changed" => "alterados",
+
+    // Server
+    "Serve reports over HTTP, on POST /reports (default address: 127.0.0.1:8080)" =>
+        "Serve relatórios via HTTP, em POST /reports (endereço padrão: 127.0.0.1:8080)",
+    "failed to listen on {}: {}" => "falha ao escutar em {}: {}",
+    "Listening on http://{}" => "Escutando em http://{}",
+    "only POST is allowed" => "somente POST é permitido",
+    "not found" => "não encontrado",
+    "Failed to send the response: {}" => "Falha ao enviar a resposta: {}",
+    "invalid request: {}" => "requisição inválida: {}",
+
+    // Configuration
+    "invalid configuration file {}: {}" => "arquivo de configuração inválido {}: {}",
+    "invalid CPF: {}" => "CPF inválido: {}",
+    "not created, run {} to create it" => "não criado, execute {} para criá-lo",
+    "not used, the API keys are in the environment" =>
+        "não usado, as chaves da API estão no ambiente",
+    "Configuration file" => "Arquivo de configuração",
+
+    // Prompts
+    "[Y/n]" => "[S/n]",
+    "[y/N]" => "[s/N]",
+    "Please answer yes or no." => "Responda sim ou não.",
+
+    // Init command
+    "Set up the configuration and the Kraken API keys interactively" =>
+        "Configura interativamente o programa e as chaves da API da Kraken",
+    "init needs an interactive terminal" => "init precisa de um terminal interativo",
+    "Ignoring the current configuration: {}" => "Ignorando a configuração atual: {}",
+    "Setting up the profile {}" => "Configurando o perfil {}",
+    "Declarant name" => "Nome do declarante",
+    "Declarant CPF (only the digits)" => "CPF do declarante (somente os dígitos)",
+    "Configuration saved to {}" => "Configuração salva em {}",
+    "Replace the API keys saved in {}?" => "Substituir as chaves da API salvas em {}?",
+    "Create a Kraken API key with only these permissions: {}" =>
+        "Crie uma chave da API da Kraken somente com estas permissões: {}",
+    "API key:" => "Chave da API:",
+    "API secret (not shown):" => "Segredo da API (não será exibido):",
+    "API keys saved to {}, readable only by you" => "Chaves da API salvas em {}, legíveis somente por você",
+    "No API keys given, skipping." => "Nenhuma chave da API informada, pulando.",
+    "Check the access to Kraken and to the price providers now?" =>
+        "Verificar agora o acesso à Kraken e às fontes de cotação?",
+
+    // Doctor command
+    "Key file" => "Arquivo de chaves",
+    "Key file permissions" => "Permissões do arquivo de chaves",
+    "{} does not exist" => "{} não existe",
+    "{} on {}: {} BRL, from {}" => "{} em {}: R$ {}, de {}",
+    "{} is not a directory" => "{} não é um diretório",
+    "{} (not created yet)" => "{} (ainda não criado)",
+    "neither XDG_CONFIG_HOME nor HOME is set" => "nem XDG_CONFIG_HOME nem HOME estão definidas",
+    "Configuration directory" => "Diretório de configuração",
+    " ok " => " ok ",
+    "FAIL" => "FALHA",
+    "{} of {} checks failed" => "{} de {} verificações falharam",
+    "All checks passed" => "Todas as verificações passaram",
+    "{} is accessible by other users (mode {}), run: chmod 600 {}" =>
+        "{} pode ser acessado por outros usuários (modo {}), execute: chmod 600 {}",
+    "only accessible by its owner" => "acessível somente pelo dono",
+    "Kraken API" => "API da Kraken",
+    "Kraken API {}" => "API da Kraken {}",
+    "\"{}\" permission granted" => "permissão \"{}\" concedida",
+    "{} (the key needs the \"{}\" permission)" => "{} (a chave precisa da permissão \"{}\")",
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format("{} + {} = {}", &[&1, &2, &"3"]), "1 + 2 = 3");
+        assert_eq!(format("no placeholders", &[]), "no placeholders");
+    }
+
+    #[test]
+    fn test_lang_tags() {
+        assert_eq!(Lang::from_tag("pt-BR"), Some(Lang::PtBr));
+        assert_eq!(Lang::from_tag("pt_BR.UTF-8"), Some(Lang::PtBr));
+        assert_eq!(Lang::from_tag("en_US.UTF-8"), Some(Lang::En));
+        assert_eq!(Lang::from_tag("C"), Some(Lang::En));
+        assert_eq!(Lang::from_tag("fr"), None);
+    }
+
+    #[test]
+    fn test_translations_keep_placeholders() {
+        for (en, pt) in PT_BR.entries() {
+            assert_eq!(
+                en.matches("{}").count(),
+                pt.matches("{}").count(),
+                "placeholder mismatch in translation of {:?}",
+                en
+            );
+        }
+    }
+
+    /// Undoes the escapes used in the message literals
+    fn unescape(literal: &str) -> String {
+        let mut out = String::new();
+        let mut chars = literal.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                out.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('n') => out.push('\n'),
+                Some(c) => out.push(c),
+                None => {}
+            }
+        }
+        out
+    }
+
+    /// Finds the templates of all `t!` invocations in a source file
+    fn templates_in(source: &str) -> Vec<String> {
+        // Split so this function doesn't find itself
+        let invocation = concat!("t", "!(");
+        let mut templates = Vec::new();
+        let mut rest = source;
+        while let Some(pos) = rest.find(invocation) {
+            let preceded_by_ident = rest[..pos]
+                .chars()
+                .next_back()
+                .is_some_and(|c| c.is_alphanumeric() || c == '_');
+            rest = rest[pos + invocation.len()..].trim_start();
+            if preceded_by_ident {
+                // e.g. format!(
+                continue;
+            }
+            let Some(literal) = rest.strip_prefix('"') else {
+                continue;
+            };
+            let mut end = 0;
+            let mut escaped = false;
+            for (i, c) in literal.char_indices() {
+                match c {
+                    _ if escaped => escaped = false,
+                    '\\' => escaped = true,
+                    '"' => {
+                        end = i;
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            templates.push(unescape(&literal[..end]));
+            rest = &literal[end..];
+        }
+        templates
+    }
+
+    fn source_files(dir: &std::path::Path, files: &mut Vec<std::path::PathBuf>) {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                source_files(&path, files);
+            } else if path.extension().is_some_and(|ext| ext == "rs") {
+                files.push(path);
+            }
+        }
+    }
+
+    #[test]
+    fn test_all_messages_translated() {
+        let mut files = Vec::new();
+        source_files(
+            &std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src"),
+            &mut files,
+        );
+        for file in files {
+            let source = std::fs::read_to_string(&file).unwrap();
+            for template in templates_in(&source) {
+                assert!(
+                    PT_BR.contains_key(template.as_str()),
+                    "missing pt-BR translation in {} for {:?}",
+                    file.display(),
+                    template
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! Income received in crypto assets, like referral rewards and fee rebates.
+//!
+//! The report of the RFB has no record for an income, so it isn't written
+//! there. The asset still comes in at its value on the day it was received,
+//! which is its cost for the gains of selling it (see [`crate::gains`]), and
+//! the income of each month is kept in the store and listed by
+//! `kraken2rfb income`. Some of it, like the staking rewards, may be taxed
+//! as income when received, month by month as in the carnê-leão, so it can
+//! also be written as a report of its own, by month and asset.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use chrono::{Datelike, NaiveDate};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::checked;
+use crate::error::Result;
+use crate::i18n::t;
+use crate::source::IncomeKind;
+
+/// An income valued in BRL
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Income {
+    pub date: NaiveDate,
+    pub kind: IncomeKind,
+    /// Common symbol of the asset received
+    pub asset: String,
+    #[serde(with = "rust_decimal::serde::str")]
+    pub amount: Decimal,
+    /// Value in BRL on the day it was received
+    #[serde(with = "rust_decimal::serde::str")]
+    pub value: Decimal,
+}
+
+impl Income {
+    /// How the income is named in the errors about it
+    pub(crate) fn name(&self) -> String {
+        t!(
+            "the income of {} {} on {}",
+            self.amount,
+            self.asset,
+            self.date
+        )
+    }
+}
+
+/// The BRL value of the income of each month, keyed by year and month. The
+/// months without income are left out.
+pub fn monthly_totals<'a>(
+    income: impl IntoIterator<Item = &'a Income>,
+) -> Result<BTreeMap<(i32, u32), Decimal>> {
+    let mut totals: BTreeMap<(i32, u32), Decimal> = BTreeMap::new();
+    for income in income {
+        let total = totals
+            .entry((income.date.year(), income.date.month()))
+            .or_default();
+        *total = checked::add(*total, income.value, &income.name())?;
+    }
+    Ok(totals)
+}
+
+/// The income of one asset in a month
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssetMonth {
+    pub year: i32,
+    pub month: u32,
+    /// Common symbol of the asset received
+    pub asset: String,
+    /// Sum of the amounts received
+    pub amount: Decimal,
+    /// Sum of their values in BRL, each on the day it was received
+    pub value: Decimal,
+    /// Number of entries summed
+    pub entries: usize,
+}
+
+/// The income of each asset in each month, sorted by month and asset
+pub fn monthly_by_asset<'a>(
+    income: impl IntoIterator<Item = &'a Income>,
+) -> Result<Vec<AssetMonth>> {
+    let mut months: BTreeMap<(i32, u32, &str), AssetMonth> = BTreeMap::new();
+    for income in income {
+        let (year, month) = (income.date.year(), income.date.month());
+        let entry = months
+            .entry((year, month, income.asset.as_str()))
+            .or_insert_with(|| AssetMonth {
+                year,
+                month,
+                asset: income.asset.clone(),
+                amount: Decimal::ZERO,
+                value: Decimal::ZERO,
+                entries: 0,
+            });
+        entry.amount = checked::add(entry.amount, income.amount, &income.name())?;
+        entry.value = checked::add(entry.value, income.value, &income.name())?;
+        entry.entries += 1;
+    }
+    Ok(months.into_values().collect())
+}
+
+/// Header of the CSV written by [`write_monthly_csv`]
+const CSV_HEADER: [&str; 5] = ["month", "asset", "amount", "brl_value", "entries"];
+
+/// Writes the income of each asset in each month as CSV, with a header row,
+/// the months as `YYYY-MM` and the decimals with a `.` separator
+pub fn write_monthly_csv(months: &[AssetMonth], writer: &mut impl Write) -> Result<()> {
+    writeln!(writer, "{}", CSV_HEADER.join(","))?;
+    for month in months {
+        writeln!(
+            writer,
+            "{}-{:02},{},{},{},{}",
+            month.year, month.month, month.asset, month.amount, month.value, month.entries
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_monthly_totals() {
+        let income = |month, value| Income {
+            date: NaiveDate::from_ymd_opt(2024, month, 10).unwrap(),
+            kind: IncomeKind::Referral,
+            asset: "BTC".to_string(),
+            amount: dec!(0.0001),
+            value,
+        };
+        let totals = monthly_totals(&[
+            income(3, dec!(30)),
+            income(3, dec!(1.5)),
+            income(5, dec!(2)),
+        ])
+        .unwrap();
+        assert_eq!(totals[&(2024, 3)], dec!(31.5));
+        assert_eq!(totals[&(2024, 5)], dec!(2));
+        assert_eq!(totals.len(), 2);
+    }
+
+    #[test]
+    fn test_monthly_by_asset() {
+        let reward = |month, asset: &str, amount, value| Income {
+            date: NaiveDate::from_ymd_opt(2024, month, 10).unwrap(),
+            kind: IncomeKind::Staking,
+            asset: asset.to_string(),
+            amount,
+            value,
+        };
+        let months = monthly_by_asset(&[
+            reward(3, "DOT", dec!(0.1), dec!(4)),
+            reward(3, "ADA", dec!(2), dec!(6)),
+            reward(3, "DOT", dec!(0.2), dec!(7.5)),
+            reward(4, "DOT", dec!(0.1), dec!(3.9)),
+        ])
+        .unwrap();
+        let mut csv = Vec::new();
+        write_monthly_csv(&months, &mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "month,asset,amount,brl_value,entries\n\
+             2024-03,ADA,2,6,1\n\
+             2024-03,DOT,0.3,11.5,2\n\
+             2024-04,DOT,0.1,3.9,1\n"
+        );
+    }
+}
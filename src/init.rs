@@ -0,0 +1,118 @@
+use itertools::Itertools;
+
+use crate::doctor::run_doctor;
+use kraken2rfb::config::{Config, Declarant, is_valid_cpf};
+use kraken2rfb::error::{Error, Result};
+use kraken2rfb::i18n::t;
+use kraken2rfb::kraken::{REQUIRED_PERMISSIONS, default_keyfile, save_api_keys};
+use kraken2rfb::paths;
+use kraken2rfb::prompt::Prompter;
+
+/// Asks a question showing the current value, which is kept on an empty answer
+fn ask_with_default(
+    prompter: &mut Prompter,
+    question: &str,
+    current: Option<&str>,
+) -> Result<Option<String>> {
+    let question = match current {
+        Some(current) => format!("{} [{}]:", question, current),
+        None => format!("{}:", question),
+    };
+    Ok(prompter.ask(&question)?.or(current.map(String::from)))
+}
+
+/// Interactive setup: writes the configuration file, saves the API keys where
+/// they are found by default, and checks that everything works.
+pub fn run_init() -> Result<()> {
+    let mut prompter = Prompter::new(false);
+    if !prompter.is_interactive() {
+        return Err(Error::Config(t!("init needs an interactive terminal")));
+    }
+
+    // Without the environment overrides, which must not end up in the file
+    let mut config = Config::load_file().unwrap_or_else(|e| {
+        println!("{}", t!("Ignoring the current configuration: {}", e));
+        Config::default()
+    });
+
+    // Declarant, of the selected profile if any
+    let declarant = match paths::profile() {
+        Some(name) => {
+            println!("{}", t!("Setting up the profile {}", name));
+            &mut config
+                .profiles
+                .entry(name.to_string())
+                .or_default()
+                .declarant
+        }
+        None => &mut config.declarant,
+    };
+    let current = declarant.clone();
+    let name = ask_with_default(
+        &mut prompter,
+        &t!("Declarant name"),
+        current.as_ref().map(|d| d.name.as_str()),
+    )?;
+    let cpf = loop {
+        let cpf = ask_with_default(
+            &mut prompter,
+            &t!("Declarant CPF (only the digits)"),
+            current.as_ref().map(|d| d.cpf.as_str()),
+        )?;
+        match cpf {
+            Some(cpf) if !is_valid_cpf(&cpf) => println!("{}", t!("invalid CPF: {}", cpf)),
+            cpf => break cpf,
+        }
+    };
+    if let (Some(name), Some(cpf)) = (name, cpf) {
+        *declarant = Some(Declarant { name, cpf });
+    }
+    let path = config.save()?;
+    println!("{}", t!("Configuration saved to {}", path.display()));
+
+    // API keys
+    let keyfile = default_keyfile()
+        .ok_or_else(|| Error::Config(t!("neither XDG_CONFIG_HOME nor HOME is set")))?;
+    let replace = !keyfile.exists()
+        || prompter.confirm(
+            &t!("Replace the API keys saved in {}?", keyfile.display()),
+            false,
+        )?;
+    if replace {
+        let permissions = REQUIRED_PERMISSIONS
+            .iter()
+            .map(|(_, permission)| format!("\"{}\"", permission))
+            .unique()
+            .join(", ");
+        println!(
+            "{}",
+            t!(
+                "Create a Kraken API key with only these permissions: {}",
+                permissions
+            )
+        );
+        let key = prompter.ask(&t!("API key:"))?;
+        let secret = prompter.ask_secret(&t!("API secret (not shown):"))?;
+        match (key, secret) {
+            (Some(key), Some(secret)) => {
+                save_api_keys(&keyfile, &key, &secret)?;
+                println!(
+                    "{}",
+                    t!(
+                        "API keys saved to {}, readable only by you",
+                        keyfile.display()
+                    )
+                );
+            }
+            _ => println!("{}", t!("No API keys given, skipping.")),
+        }
+    }
+
+    if prompter.confirm(
+        &t!("Check the access to Kraken and to the price providers now?"),
+        true,
+    )? {
+        run_doctor(None)?;
+    }
+    Ok(())
+}
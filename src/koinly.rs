@@ -0,0 +1,282 @@
+//! Importer of the transaction export of Koinly, the CSV of the transaction
+//! history report, which has the history of every wallet of the account
+//! together.
+//!
+//! The report is of one exchange, so only the rows of one of the wallets are
+//! imported: a row received and sent by the wallet is a trade, one only sent
+//! by it a withdrawal and one only received a deposit, even if Koinly matched
+//! it as a transfer to another wallet of the account. Deposits tagged as
+//! income (rewards, staking, airdrops and the like) are left out, as the
+//! other sources leave them.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use rust_decimal::Decimal;
+
+use crate::csv::{Row, Table, row_id};
+use crate::error::{Error, Result};
+use crate::i18n::t;
+use crate::report::transactions::ExchangeInfo;
+use crate::source::{
+    Activity, ExchangeSource, NormalizedDeposit, NormalizedTrade, NormalizedWithdrawal, Side,
+    is_fiat,
+};
+
+/// Columns of the export read by the importer
+const COLUMNS: [&str; 11] = [
+    "Date (UTC)",
+    "Type",
+    "Tag",
+    "Sending Wallet",
+    "Sent Amount",
+    "Sent Currency",
+    "Receiving Wallet",
+    "Received Amount",
+    "Received Currency",
+    "Fee Amount",
+    "Fee Currency",
+];
+
+/// Optional column with the hash of a crypto transfer
+const TX_HASH: &str = "TxHash";
+
+/// Tags of the deposits Koinly counts as income
+const INCOME_TAGS: [&str; 11] = [
+    "airdrop",
+    "fork",
+    "mining",
+    "reward",
+    "staking",
+    "lending interest",
+    "other income",
+    "income",
+    "cashback",
+    "fee refund",
+    "gift",
+];
+
+/// Source of the activity of a wallet in a Koinly export
+pub struct KoinlySource {
+    /// The exported CSV file, or `None` if the user gave none
+    file: Option<PathBuf>,
+    /// Name of the wallet in Koinly, e.g. "Binance"
+    wallet: String,
+    /// The exchange the wallet is on
+    exchange: ExchangeInfo,
+}
+
+impl KoinlySource {
+    pub fn new(file: Option<PathBuf>, wallet: String, exchange: ExchangeInfo) -> Self {
+        KoinlySource {
+            file,
+            wallet,
+            exchange,
+        }
+    }
+}
+
+impl ExchangeSource for KoinlySource {
+    fn exchange(&self) -> ExchangeInfo {
+        self.exchange.clone()
+    }
+
+    fn fetch(&self, first_day: NaiveDate, last_day: NaiveDate) -> Result<Activity> {
+        let file = self.file.as_deref().ok_or_else(|| {
+            Error::Config(t!("give the file exported from {} with --input", "Koinly"))
+        })?;
+        let table = Table::read(file, ',')?.with_header(COLUMNS[0]);
+        Ok(normalize(&table, &self.wallet)?.within(first_day, last_day))
+    }
+}
+
+/// An amount of a currency in a row, if it has one
+fn amount<'a>(
+    table: &Table,
+    row: &'a Row,
+    amount: &str,
+    currency: &str,
+) -> Result<Option<(&'a str, Decimal)>> {
+    let text = table.get(row, amount)?;
+    let currency = table.get(row, currency)?;
+    if text.is_empty() || currency.is_empty() {
+        return Ok(None);
+    }
+    let value: Decimal = text
+        .parse()
+        .or_else(|_| Decimal::from_scientific(text))
+        .map_err(|e| {
+            table.error(
+                row,
+                t!(
+                    "invalid decimal \"{}\" in field \"{}\": {}",
+                    text,
+                    amount,
+                    e
+                ),
+            )
+        })?;
+    Ok(Some((currency, value.abs())))
+}
+
+fn time(table: &Table, row: &Row) -> Result<DateTime<Utc>> {
+    let column = COLUMNS[0];
+    let text = table.get(row, column)?;
+    let time = text.strip_suffix(" UTC").unwrap_or(text);
+    NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M"))
+        .map(|time| time.and_utc())
+        .map_err(|e| table.error(row, t!("invalid time \"{}\" in {}: {}", text, column, e)))
+}
+
+/// Converts the rows of `wallet` in a Koinly export to the typed entries of
+/// [`Activity`]. A trade is a sale if it is of a crypto asset for fiat, or
+/// else a buy of the asset received; its fee counts if it is in either
+/// asset, valued at the price of the trade if it is in the base one.
+fn normalize(table: &Table, wallet: &str) -> Result<Activity> {
+    table.require_columns(&COLUMNS)?;
+    let is_wallet = |column: &str, row: &Row| -> Result<bool> {
+        Ok(table.get(row, column)?.eq_ignore_ascii_case(wallet.trim()))
+    };
+    let mut seen = HashMap::new();
+    let mut activity = Activity::default();
+    for row in &table.rows {
+        let mut ids = vec![row_id("koinly", row, &mut seen)];
+        let (sending, receiving) = (
+            is_wallet("Sending Wallet", row)?,
+            is_wallet("Receiving Wallet", row)?,
+        );
+        if !sending && !receiving {
+            continue;
+        }
+        if table.has_column(TX_HASH) {
+            ids.extend(
+                Some(table.get(row, TX_HASH)?)
+                    .filter(|hash| !hash.is_empty())
+                    .map(String::from),
+            );
+        }
+        let time = time(table, row)?;
+        let sent = amount(table, row, "Sent Amount", "Sent Currency")?.filter(|_| sending);
+        let received =
+            amount(table, row, "Received Amount", "Received Currency")?.filter(|_| receiving);
+        let fee = amount(table, row, "Fee Amount", "Fee Currency")?;
+        let fee_in = |currency: &str| {
+            fee.filter(|(fee_currency, _)| fee_currency.eq_ignore_ascii_case(currency))
+                .map_or(Decimal::ZERO, |(_, fee)| fee)
+        };
+        let kind = table.get(row, "Type")?;
+        let unexpected = || table.error(row, t!("unexpected {} entry: {}", "Koinly", kind));
+        match (sent, received) {
+            (Some((from, from_amount)), Some((to, to_amount))) => {
+                let (side, base, quote, volume, cost) = if is_fiat(to) && !is_fiat(from) {
+                    (Side::Sell, from, to, from_amount, to_amount)
+                } else {
+                    (Side::Buy, to, from, to_amount, from_amount)
+                };
+                let price = cost.checked_div(volume).ok_or_else(unexpected)?;
+                activity.trades.push(NormalizedTrade {
+                    base: base.to_string(),
+                    quote: quote.to_string(),
+                    side,
+                    volume,
+                    cost,
+                    fee: fee_in(quote) + fee_in(base) * price,
+                    credit_fee_usd: Decimal::ZERO,
+                    price,
+                    time,
+                    ids,
+                });
+            }
+            (Some((asset, amount)), None) => activity.withdrawals.push(NormalizedWithdrawal {
+                asset: asset.to_string(),
+                amount,
+                fee: fee_in(asset),
+                time,
+                ids,
+                destination: None,
+                network: None,
+            }),
+            (None, Some((asset, amount))) => {
+                let tag = table.get(row, "Tag")?.replace('_', " ");
+                if INCOME_TAGS
+                    .iter()
+                    .any(|income| tag.eq_ignore_ascii_case(income))
+                {
+                    continue;
+                }
+                activity.deposits.push(NormalizedDeposit {
+                    asset: asset.to_string(),
+                    amount,
+                    fee: fee_in(asset),
+                    time,
+                    ids,
+                    origin_exchange: None,
+                    origin_wallet: None,
+                    network: None,
+                });
+            }
+            (None, None) => return Err(unexpected()),
+        }
+    }
+    activity.deposits.sort_by_key(|d| d.time);
+    activity.withdrawals.sort_by_key(|w| w.time);
+    activity.trades.sort_by_key(|t| t.time);
+    Ok(activity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_normalize() {
+        let text = "Koinly Transaction History\n\
+            Date (UTC),Type,Tag,Sending Wallet,Sent Amount,Sent Currency,Sent Cost Basis,Receiving Wallet,Received Amount,Received Currency,Received Cost Basis,Fee Amount,Fee Currency,TxHash\n\
+            2024-03-01 12:00:00 UTC,fiat_deposit,,,,,,Binance,1000,USDT,,,,\n\
+            2024-03-02 12:00:00 UTC,exchange,,Binance,600,USDT,,Binance,0.01,BTC,,0.00001,BTC,\n\
+            2024-03-03 12:00:00 UTC,exchange,,Binance,0.005,BTC,,Binance,310,EUR,,0.31,EUR,\n\
+            2024-03-04 12:00:00 UTC,transfer,,Binance,0.004,BTC,,Ledger,0.0039,BTC,,0.0001,BTC,0xab\n\
+            2024-03-05 12:00:00 UTC,crypto_deposit,staking,,,,,Binance,0.1,ETH,,,,\n\
+            2024-03-06 12:00:00 UTC,exchange,,Kraken,1,ETH,,Kraken,3000,USD,,,,\n";
+        let table = Table::parse(text, ',', "koinly.csv")
+            .unwrap()
+            .with_header(COLUMNS[0]);
+        let activity = normalize(&table, "binance").unwrap();
+
+        // The staking reward is left out
+        assert_eq!(activity.deposits.len(), 1);
+        assert_eq!(activity.deposits[0].amount, dec!(1000));
+        // A transfer to another wallet is a withdrawal of the amount sent
+        let withdrawal = &activity.withdrawals[0];
+        assert_eq!(
+            (withdrawal.amount, withdrawal.fee),
+            (dec!(0.004), dec!(0.0001))
+        );
+        assert_eq!(withdrawal.ids[1], "0xab");
+
+        // The trade on the other wallet is left out
+        let [buy, sell] = activity.trades.as_slice() else {
+            panic!("expected 2 trades, got {:?}", activity.trades);
+        };
+        assert_eq!(
+            (buy.side, buy.base.as_str(), buy.quote.as_str()),
+            (Side::Buy, "BTC", "USDT")
+        );
+        // A fee in the base asset, at the price of the trade
+        assert_eq!((buy.price, buy.fee), (dec!(60000), dec!(0.6)));
+        assert_eq!(
+            (sell.side, sell.cost, sell.fee),
+            (Side::Sell, dec!(310), dec!(0.31))
+        );
+        assert!(
+            normalize(
+                &Table::parse("Date,Amount\n", ',', "koinly.csv").unwrap(),
+                "Binance"
+            )
+            .is_err()
+        );
+    }
+}
@@ -1,29 +1,154 @@
+//! Kraken API client: the API keys, the private endpoints with the account
+//! activity and the public ones with the asset metadata.
+
 use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
-use chrono::NaiveDate;
+use chrono::{DateTime, FixedOffset, NaiveDate, TimeDelta, Utc};
 use hmac::{Hmac, Mac};
-use phf::phf_set;
+use itertools::Itertools;
 use reqwest::blocking::Client;
 use reqwest::header::HeaderMap;
 use rust_decimal::Decimal;
-use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use serde_urlencoded;
+use rust_decimal::prelude::ToPrimitive;
+use serde_json::{Map, Value};
 use sha2::{Digest, Sha256, Sha512};
-use std::collections::BTreeMap;
-use std::fs;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use crate::api_keys::{self, ApiKeys, KeyLocation};
+use crate::checked;
+use crate::endpoints;
+use crate::env;
+use crate::error::{Error, Result};
+use crate::fields::optional_str_field;
+use crate::http;
+use crate::http_cache;
+use crate::i18n::t;
+use crate::kraken_pairs;
+use crate::kraken_symbols::{normalize_asset, normalize_asset_or_keep, normalize_staked_asset};
+use crate::period::BRASILIA;
+use crate::reconcile::{Discrepancy, implied_balances, reconcile};
+use crate::report::transactions::ExchangeInfo;
+use crate::source::{
+    Activity, ExchangeSource, IncomeKind, NormalizedDeposit, NormalizedIncome, NormalizedTrade,
+    NormalizedWithdrawal, Side, WalletTransfer, day_ranges,
+};
+use crate::style;
 use crate::to_decimal;
 
-#[derive(Serialize, Deserialize)]
-struct ApiKeys {
-    key: String,
-    secret: String,
+/// Where the Kraken API keys are looked for: `kraken_keys.json`, or the
+/// `KRAKEN2RFB_KEYFILE`, `KRAKEN2RFB_API_KEY` and `KRAKEN2RFB_API_SECRET`
+/// environment variables
+pub const KEYS: KeyLocation = KeyLocation {
+    exchange: "Kraken",
+    file_name: "kraken_keys.json",
+    env_prefix: "",
+    profile_keyfile: true,
+};
+
+/// Raw Kraken activity for a period, sorted by time
+#[derive(Debug, Clone)]
+pub struct KrakenActivity {
+    pub deposits: Vec<Value>,
+    pub withdrawals: Vec<Value>,
+    pub trades: Vec<Value>,
+    /// Ledger entries of the deposits and withdrawals, by refid, with
+    /// [`TransferTime::Settled`]
+    pub ledger: HashMap<String, Value>,
+    /// Credits of the ledger, the referral rewards and fee rebates
+    pub credits: Vec<Value>,
+    /// Transfers of the ledger, the moves between the wallets of the account
+    /// (e.g. to staking), which DepositStatus and WithdrawStatus can list too
+    pub transfers: Vec<Value>,
+    /// Ledger entries of the fee credits, [`KFEE`]: those spent on the fee of
+    /// a trade have its ID as refid
+    pub fee_credits: Vec<Value>,
+    /// Adjustments of the ledger, the corrections Kraken posts to earlier
+    /// operations or to the balances
+    pub adjustments: Vec<Value>,
+    /// Earn and staking entries of the ledger: the allocations of funds to
+    /// Kraken Earn and their deallocations, which DepositStatus and
+    /// WithdrawStatus can list too, and the rewards
+    pub earn: Vec<Value>,
+    /// The orders of the trades, by order ID, with the IDs of their trades,
+    /// when the partial fills are aggregated (`KRAKEN2RFB_AGGREGATE_ORDERS`)
+    pub orders: Option<HashMap<String, Value>>,
+}
+
+/// Kraken's fee credits, spent on the trading fees in place of the quote
+/// asset. They are not an asset of the report.
+pub const KFEE: &str = "KFEE";
+
+/// What a [`KFEE`] is worth in US dollars: 1000 of them pay 10 USD of fees
+const KFEE_USD: Decimal = Decimal::from_parts(1, 0, 0, false, 2);
+
+/// Which time of a deposit or withdrawal dates it, and so the report it goes
+/// in, set by `KRAKEN2RFB_TRANSFER_TIME`. A withdrawal requested on the last
+/// day of a month can be settled on the first of the next one; either way it
+/// goes in exactly one report.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TransferTime {
+    /// When it was requested, the time of DepositStatus and WithdrawStatus
+    #[default]
+    Requested,
+    /// When Kraken credited or debited the account, the time of its ledger
+    /// entry, or the requested one while it has none
+    Settled,
+}
+
+impl TransferTime {
+    /// The policy in `KRAKEN2RFB_TRANSFER_TIME`, `requested` if not set
+    pub fn from_env() -> Result<Self> {
+        match env::var("TRANSFER_TIME").as_deref() {
+            None | Some("requested") => Ok(TransferTime::Requested),
+            Some("settled") => Ok(TransferTime::Settled),
+            Some(value) => Err(Error::Config(t!(
+                "invalid value for {}: {}",
+                env::name("TRANSFER_TIME"),
+                t!("{} (expected {})", value, "requested, settled")
+            ))),
+        }
+    }
+}
+
+/// How long before the first day the deposits and withdrawals are fetched
+/// with [`TransferTime::Settled`], for the ones requested before it and
+/// settled within it
+const SETTLEMENT_DAYS: u64 = 7;
+
+/// Finds the file with the Kraken API keys, see [`KeyLocation::find_keyfile`]
+pub fn find_keyfile(flag: Option<&Path>) -> Result<PathBuf> {
+    KEYS.find_keyfile(flag)
+}
+
+/// Where `init` saves the API keys, see [`KeyLocation::default_keyfile`]
+pub fn default_keyfile() -> Option<PathBuf> {
+    KEYS.default_keyfile()
 }
 
-fn load_api_keys(path: &str) -> ApiKeys {
-    let data = fs::read_to_string(path).expect("Failed to read key file");
-    serde_json::from_str(&data).expect("Invalid JSON in key file")
+/// Saves the API keys to `path`, readable only by the current user
+pub fn save_api_keys(path: &Path, key: &str, secret: &str) -> Result<()> {
+    api_keys::save(
+        path,
+        &ApiKeys {
+            key: key.to_string(),
+            secret: secret.to_string(),
+            passphrase: None,
+        },
+    )
+}
+
+/// Whether the API keys come from the environment instead of a key file
+pub fn api_keys_in_env() -> bool {
+    KEYS.keys_in_env()
+}
+
+/// Loads the API keys from the environment or, if not there, from `path`
+fn load_api_keys(path: &Path) -> Result<ApiKeys> {
+    KEYS.load(path)
 }
 
 fn get_timestamp(date: NaiveDate) -> u64 {
@@ -31,7 +156,7 @@ fn get_timestamp(date: NaiveDate) -> u64 {
 }
 
 // Kraken API signature
-fn kraken_signature(uri_path: &str, data: &BTreeMap<&str, String>, secret: &str) -> String {
+fn kraken_signature(uri_path: &str, data: &BTreeMap<&str, String>, secret: &str) -> Result<String> {
     // Get nonce from data
     let nonce = data.get("nonce").expect("nonce is required");
 
@@ -47,138 +172,1578 @@ fn kraken_signature(uri_path: &str, data: &BTreeMap<&str, String>, secret: &str)
     message.extend_from_slice(&hash);
 
     // Create HMAC-SHA512
-    let decoded_secret = BASE64.decode(secret).expect("Base64 decode failed");
+    let decoded_secret = BASE64
+        .decode(secret)
+        .map_err(|e| Error::Config(t!("API secret is not valid base64: {}", e)))?;
     let mut mac =
         Hmac::<Sha512>::new_from_slice(&decoded_secret).expect("HMAC can take key of any size");
     mac.update(&message);
 
     // Return base64 encoded signature
-    BASE64.encode(mac.finalize().into_bytes())
+    Ok(BASE64.encode(mac.finalize().into_bytes()))
+}
+
+fn kraken_error(endpoint: &str, errors: &[Value]) -> Error {
+    Error::Kraken {
+        endpoint: endpoint.to_string(),
+        errors: errors
+            .iter()
+            .map(|e| e.as_str().map_or_else(|| e.to_string(), String::from))
+            .collect(),
+    }
+}
+
+/// Nonce of a private request: the time in milliseconds, but strictly greater
+/// than the previous one, even for requests made at once by several threads
+fn next_nonce() -> u64 {
+    static LAST: AtomicU64 = AtomicU64::new(0);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    let previous = LAST
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| {
+            Some(now.max(last + 1))
+        })
+        .expect("the update never fails");
+    now.max(previous + 1)
+}
+
+/// Maximum of the API call counter of Kraken's starter tier, beyond which
+/// private requests are rejected with "EAPI:Rate limit exceeded"
+const MAX_CALL_COUNTER: f64 = 15.0;
+
+/// How much the call counter decreases every second, in the starter tier
+const CALL_COUNTER_DECAY: f64 = 0.33;
+
+/// Our estimate of Kraken's API call counter, shared by all the requests
+struct CallCounter {
+    value: f64,
+    updated: Option<Instant>,
+}
+
+impl CallCounter {
+    /// Adds the cost of a call made at `now`, if it fits under the limit, or
+    /// returns how long to wait until it does
+    fn reserve(&mut self, cost: f64, now: Instant) -> Option<Duration> {
+        if let Some(updated) = self.updated {
+            let decay = now.saturating_duration_since(updated).as_secs_f64() * CALL_COUNTER_DECAY;
+            self.value = (self.value - decay).max(0.0);
+        }
+        self.updated = Some(now);
+        if self.value + cost <= MAX_CALL_COUNTER {
+            self.value += cost;
+            None
+        } else {
+            Some(Duration::from_secs_f64(
+                (self.value + cost - MAX_CALL_COUNTER) / CALL_COUNTER_DECAY,
+            ))
+        }
+    }
+}
+
+/// Waits until a private call to `uri_path` doesn't exceed the rate limit. The
+/// history endpoints cost 2, the others 1.
+fn wait_for_call(uri_path: &str) {
+    static COUNTER: Mutex<CallCounter> = Mutex::new(CallCounter {
+        value: 0.0,
+        updated: None,
+    });
+    let cost = if uri_path.ends_with("TradesHistory") || uri_path.ends_with("Ledgers") {
+        2.0
+    } else {
+        1.0
+    };
+    loop {
+        let wait = COUNTER
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .reserve(cost, Instant::now());
+        match wait {
+            Some(wait) => thread::sleep(wait),
+            None => return,
+        }
+    }
 }
 
+/// Times a private request is retried when Kraken rejects its nonce, which
+/// happens when concurrent requests arrive out of order
+const NONCE_RETRIES: usize = 3;
+
 // Helper for authenticated requests
 fn kraken_private_request(
     client: &Client,
     api_keys: &ApiKeys,
     uri_path: &str,
     params: &mut BTreeMap<&str, String>,
-) -> Value {
-    let url = format!("https://api.kraken.com{}", uri_path);
-    let nonce = format!(
-        "{}",
-        SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_millis()
-    );
-    params.insert("nonce", nonce);
+) -> Result<Value> {
+    let mut attempt = 0;
+    loop {
+        wait_for_call(uri_path);
+        match kraken_private_call(client, api_keys, uri_path, params) {
+            Err(Error::Kraken { errors, .. })
+                if attempt < NONCE_RETRIES
+                    && errors.iter().any(|e| e.starts_with("EAPI:Invalid nonce")) =>
+            {
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
 
-    let signature = kraken_signature(uri_path, params, &api_keys.secret);
+fn kraken_private_call(
+    client: &Client,
+    api_keys: &ApiKeys,
+    uri_path: &str,
+    params: &mut BTreeMap<&str, String>,
+) -> Result<Value> {
+    let url = format!("{}{}", endpoints::get().kraken, uri_path);
+    params.insert("nonce", next_nonce().to_string());
+
+    let signature = kraken_signature(uri_path, params, &api_keys.secret)?;
 
     let mut headers = HeaderMap::new();
-    headers.insert("API-Key", api_keys.key.parse().unwrap());
+    headers.insert(
+        "API-Key",
+        api_keys
+            .key
+            .parse()
+            .map_err(|_| Error::Config(t!("API key contains invalid characters")))?,
+    );
     headers.insert("API-Sign", signature.parse().unwrap());
 
-    let res = client
-        .post(url)
-        .headers(headers)
-        .form(params)
-        .send()
-        .expect("API request failed");
-    let mut json: Value = res.json().expect("Invalid JSON");
-    if !json["error"].as_array().unwrap().is_empty() {
-        panic!("Kraken error: {:?}", json["error"]);
+    let res = http::send(client.post(url).headers(headers).form(params))?;
+    let mut json: Value = res
+        .json()
+        .map_err(|e| Error::Network(t!("invalid JSON from Kraken {}: {}", uri_path, e)))?;
+    let errors = json["error"]
+        .as_array()
+        .ok_or_else(|| Error::Validation(t!("unexpected Kraken response for {}", uri_path)))?;
+    if !errors.is_empty() {
+        return Err(kraken_error(uri_path, errors));
     }
-    json["result"].take()
+    Ok(json["result"].take())
 }
 
+/// How long a response of the public API is reused from the HTTP cache
+const PUBLIC_CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Calls a public endpoint of the Kraken API, e.g. "Assets", returning the
+/// whole response, with the "error" and "result" fields
+pub fn kraken_public_request(endpoint: &str) -> Result<Value> {
+    let url = format!("{}/0/public/{}", endpoints::get().kraken, endpoint);
+    let body = http_cache::get(&url, PUBLIC_CACHE_TTL)?.body;
+    let json: Value = serde_json::from_str(&body)
+        .map_err(|e| Error::Network(t!("invalid JSON from Kraken {}: {}", endpoint, e)))?;
+    match json["error"].as_array() {
+        Some(errors) if errors.is_empty() => Ok(json),
+        Some(errors) => Err(kraken_error(endpoint, errors)),
+        None => Err(Error::Validation(t!(
+            "unexpected Kraken response for {}",
+            endpoint
+        ))),
+    }
+}
+
+/// Returns the entries of a Kraken response list whose "time" falls within [start_ts, end_ts]
+fn entries_within(list: &Value, what: &str, start_ts: u64, end_ts: u64) -> Result<Vec<Value>> {
+    let entries = list
+        .as_array()
+        .ok_or_else(|| Error::Validation(t!("Kraken {} response is not a list", what)))?;
+    let mut selected = Vec::new();
+    for entry in entries {
+        let ts = entry["time"].as_u64().ok_or_else(|| {
+            Error::Validation(t!("Kraken {} entry without valid time: {}", what, entry))
+        })?;
+        if ts >= start_ts && ts <= end_ts {
+            selected.push(entry.clone());
+        }
+    }
+    Ok(selected)
+}
+
+/// Fetches the ledger entries matching `filter`, e.g. `("type", "withdrawal")`
+/// or `("asset", "KFEE")`, from `start_ts` to `end_ts`, by ledger ID. The
+/// ledger comes in pages of 50 entries.
+fn fetch_ledger(
+    client: &Client,
+    api_keys: &ApiKeys,
+    filter: (&'static str, &str),
+    start_ts: u64,
+    end_ts: u64,
+) -> Result<HashMap<String, Value>> {
+    let mut ledger = HashMap::new();
+    loop {
+        let mut params = BTreeMap::from([
+            (filter.0, filter.1.to_string()),
+            ("start", start_ts.to_string()),
+            ("end", end_ts.to_string()),
+            ("ofs", ledger.len().to_string()),
+        ]);
+        let page = kraken_private_request(client, api_keys, "/0/private/Ledgers", &mut params)?;
+        let entries = page["ledger"]
+            .as_object()
+            .ok_or_else(|| Error::Validation(t!("unexpected Kraken response for {}", "Ledgers")))?;
+        let fetched = ledger.len();
+        ledger.extend(
+            entries
+                .iter()
+                .map(|(id, entry)| (id.clone(), entry.clone())),
+        );
+        let count = page["count"].as_u64().unwrap_or(0) as usize;
+        if ledger.len() == fetched || ledger.len() >= count {
+            return Ok(ledger);
+        }
+    }
+}
+
+/// Fetches the trades from `start_ts` to `end_ts`, by trade ID. Like the
+/// ledger, the trades come in pages of 50.
+fn fetch_trades(
+    client: &Client,
+    api_keys: &ApiKeys,
+    start_ts: u64,
+    end_ts: u64,
+) -> Result<Map<String, Value>> {
+    let mut trades = Map::new();
+    loop {
+        let mut params = BTreeMap::from([
+            ("start", start_ts.to_string()),
+            ("end", end_ts.to_string()),
+            ("ofs", trades.len().to_string()),
+        ]);
+        let page =
+            kraken_private_request(client, api_keys, "/0/private/TradesHistory", &mut params)?;
+        let entries = page["trades"]
+            .as_object()
+            .ok_or_else(|| Error::Validation(t!("Kraken TradesHistory response has no trades")))?;
+        let fetched = trades.len();
+        trades.extend(
+            entries
+                .iter()
+                .map(|(id, entry)| (id.clone(), entry.clone())),
+        );
+        let count = page["count"].as_u64().unwrap_or(0) as usize;
+        if trades.len() == fetched || trades.len() >= count {
+            return Ok(trades);
+        }
+    }
+}
+
+/// Most orders QueryOrders takes at once
+const QUERY_ORDERS_BATCH: usize = 50;
+
+/// Fetches the orders of `order_ids`, with the IDs of their trades, by order ID
+fn fetch_orders(
+    client: &Client,
+    api_keys: &ApiKeys,
+    order_ids: &[&str],
+) -> Result<HashMap<String, Value>> {
+    let mut orders = HashMap::new();
+    for batch in order_ids.chunks(QUERY_ORDERS_BATCH) {
+        let mut params =
+            BTreeMap::from([("txid", batch.join(",")), ("trades", "true".to_string())]);
+        let result =
+            kraken_private_request(client, api_keys, "/0/private/QueryOrders", &mut params)?;
+        let entries = result.as_object().ok_or_else(|| {
+            Error::Validation(t!("unexpected Kraken response for {}", "QueryOrders"))
+        })?;
+        orders.extend(
+            entries
+                .iter()
+                .map(|(id, order)| (id.clone(), order.clone())),
+        );
+    }
+    Ok(orders)
+}
+
+/// Fetches the activity from `initial` to `final_`, with the orders of the
+/// trades if `aggregate_orders`
 pub fn fetch_kraken_activity(
     initial: NaiveDate,
     final_: NaiveDate,
-    keyfile: &str,
-) -> (Vec<Value>, Vec<Value>, Vec<Value>) {
-    let api_keys = load_api_keys(keyfile);
-    let client = Client::new();
+    keyfile: &Path,
+    transfer_time: TransferTime,
+    aggregate_orders: bool,
+) -> Result<KrakenActivity> {
+    let api_keys = load_api_keys(keyfile)?;
+    let client = http::client()?;
 
     let start_ts = get_timestamp(initial);
     let end_ts = get_timestamp(final_) + 24 * 60 * 60 - 1; // include whole final day
+    let transfers_start_ts = match transfer_time {
+        TransferTime::Requested => start_ts,
+        TransferTime::Settled => start_ts.saturating_sub(SETTLEMENT_DAYS * 24 * 60 * 60),
+    };
 
-    // 1. Deposits
-    let mut params = BTreeMap::new();
-    params.insert("start", start_ts.to_string());
-    params.insert("end", end_ts.to_string());
-    let deposits_json =
-        kraken_private_request(&client, &api_keys, "/0/private/DepositStatus", &mut params);
-    let mut deposits: Vec<Value> = deposits_json
-        .as_array()
-        .unwrap()
-        .iter()
-        .filter(|entry| {
-            entry["time"]
-                .as_u64()
-                .map(|ts| ts >= start_ts && ts <= end_ts)
-                .unwrap()
-        })
-        .cloned()
-        .collect();
+    // The three endpoints are independent, so they are fetched at once; the
+    // trades page after page
+    let request = |uri_path: &str, start_ts: u64| {
+        let mut params =
+            BTreeMap::from([("start", start_ts.to_string()), ("end", end_ts.to_string())]);
+        kraken_private_request(client, &api_keys, uri_path, &mut params)
+    };
+    let (deposits_json, withdrawals_json, trades_json) = thread::scope(|scope| {
+        let join = |handle: thread::ScopedJoinHandle<Result<Value>>| {
+            handle
+                .join()
+                .unwrap_or_else(|e| std::panic::resume_unwind(e))
+        };
+        let deposits = scope.spawn(|| request("/0/private/DepositStatus", transfers_start_ts));
+        let withdrawals = scope.spawn(|| request("/0/private/WithdrawStatus", transfers_start_ts));
+        let trades = fetch_trades(client, &api_keys, start_ts, end_ts);
+        (join(deposits), join(withdrawals), trades)
+    });
 
-    // 2. Withdrawals
-    let mut params = BTreeMap::new();
-    params.insert("start", start_ts.to_string());
-    params.insert("end", end_ts.to_string());
-    let withdrawals_json =
-        kraken_private_request(&client, &api_keys, "/0/private/WithdrawStatus", &mut params);
-    let mut withdrawals: Vec<Value> = withdrawals_json
-        .as_array()
-        .unwrap()
+    let mut deposits =
+        entries_within(&deposits_json?, "DepositStatus", transfers_start_ts, end_ts)?;
+    let mut withdrawals = entries_within(
+        &withdrawals_json?,
+        "WithdrawStatus",
+        transfers_start_ts,
+        end_ts,
+    )?;
+    let mut ledger = HashMap::new();
+    if transfer_time == TransferTime::Settled {
+        for kind in ["deposit", "withdrawal"] {
+            let entries = fetch_ledger(client, &api_keys, ("type", kind), start_ts, end_ts)?;
+            ledger.extend(
+                entries
+                    .into_values()
+                    .filter_map(|entry| Some((entry["refid"].as_str()?.to_string(), entry))),
+            );
+        }
+    }
+    // A key without the permission to query the ledger still gets the rest.
+    // The transfers are fetched as far back as the deposits and withdrawals
+    // they are matched to.
+    let sorted = |entries: HashMap<String, Value>| -> Vec<Value> {
+        entries
+            .into_values()
+            .sorted_by_key(|v| to_decimal(&v["time"]).ok())
+            .collect()
+    };
+    let ledger_of = |filter, start_ts| fetch_ledger(client, &api_keys, filter, start_ts, end_ts);
+    let ledger_entries = (|| -> Result<[Vec<Value>; 5]> {
+        // The rewards of the staking before Kraken Earn have a type of their own
+        let mut earn = ledger_of(("type", "earn"), transfers_start_ts)?;
+        earn.extend(ledger_of(("type", "staking"), start_ts)?);
+        Ok([
+            sorted(ledger_of(("type", "credit"), start_ts)?),
+            sorted(ledger_of(("type", "transfer"), transfers_start_ts)?),
+            sorted(ledger_of(("asset", KFEE), start_ts)?),
+            sorted(ledger_of(("type", "adjustment"), start_ts)?),
+            sorted(earn),
+        ])
+    })();
+    let [credits, transfers, fee_credits, adjustments, earn] = match ledger_entries {
+        Ok(entries) => entries,
+        Err(e) if e.is_auth_error() => {
+            warn(&t!(
+                "the API key can't query the ledger (\"Query Ledger Entries\"), so the referral rewards, fee rebates, staking rewards and adjustments are left out, the moves between wallets are taken as deposits and withdrawals, and the fees paid with fee credits (KFEE) are taken from the trades"
+            ));
+            Default::default()
+        }
+        Err(e) => return Err(e),
+    };
+    let mut trades = Vec::new();
+    for (txid, entry) in trades_json? {
+        let ts = to_decimal(&entry["time"])?;
+        if ts >= Decimal::from(start_ts) && ts <= Decimal::from(end_ts) {
+            // The trade ID is only the key in the response, keep it with the trade
+            let mut entry = entry;
+            entry["txid"] = Value::from(txid.as_str());
+            trades.push(entry);
+        }
+    }
+
+    // Sort all by time ascending (times were validated above)
+    deposits.sort_by_key(|v| v["time"].as_u64());
+    withdrawals.sort_by_key(|v| v["time"].as_u64());
+    trades.sort_by_key(|v| to_decimal(&v["time"]).ok());
+    let orders = if aggregate_orders {
+        let order_ids: Vec<&str> = trades
+            .iter()
+            .filter_map(|trade| trade["ordertxid"].as_str())
+            .unique()
+            .collect();
+        Some(fetch_orders(client, &api_keys, &order_ids)?)
+    } else {
+        None
+    };
+
+    Ok(KrakenActivity {
+        deposits,
+        withdrawals,
+        trades,
+        ledger,
+        credits,
+        transfers,
+        fee_credits,
+        adjustments,
+        earn,
+        orders,
+    })
+}
+
+/// First day of trading on Kraken, so an activity fetched from it on is the
+/// whole history of an account
+pub const FIRST_DAY: NaiveDate = NaiveDate::from_ymd_opt(2013, 9, 10).unwrap();
+
+/// Fetches the current balance of each asset of the account, by common
+/// symbol, or by Kraken name for the assets without one (e.g. the staked
+/// variants like "DOT.S")
+pub fn fetch_kraken_balances(keyfile: &Path) -> Result<BTreeMap<String, Decimal>> {
+    let api_keys = load_api_keys(keyfile)?;
+    let result = kraken_private_request(
+        http::client()?,
+        &api_keys,
+        "/0/private/Balance",
+        &mut BTreeMap::new(),
+    )?;
+    let entries = result
+        .as_object()
+        .ok_or_else(|| Error::Validation(t!("unexpected Kraken response for {}", "Balance")))?;
+    let mut balances: BTreeMap<String, Decimal> = BTreeMap::new();
+    for (asset, amount) in entries {
+        // The fee credits have no equivalent in the history
+        if asset == KFEE {
+            continue;
+        }
+        let amount = amount
+            .as_str()
+            .and_then(|amount| amount.parse::<Decimal>().ok())
+            .ok_or_else(|| {
+                Error::Validation(t!(
+                    "Kraken {} entry without valid amount: {}",
+                    "Balance",
+                    amount
+                ))
+            })?;
+        let asset = normalize_asset_or_keep(asset);
+        *balances.entry(asset.to_string()).or_default() += amount;
+    }
+    Ok(balances)
+}
+
+/// Fetches the balance of each asset of the account at `time`, keyed as in
+/// [`fetch_kraken_balances`], see [`ledger_balances`]. Needs the "Query
+/// Ledger Entries" permission, and fetches the whole ledger up to `time`.
+pub fn fetch_kraken_balances_at(
+    keyfile: &Path,
+    time: DateTime<Utc>,
+) -> Result<BTreeMap<String, Decimal>> {
+    let api_keys = load_api_keys(keyfile)?;
+    let ledger = fetch_ledger(
+        http::client()?,
+        &api_keys,
+        ("type", "all"),
+        get_timestamp(FIRST_DAY),
+        time.timestamp().max(0) as u64,
+    )?;
+    ledger_balances(&ledger)
+}
+
+/// The balance of each asset after the ledger entries: that of the last entry
+/// of the asset, by its time with the fractions of a second
+fn ledger_balances(ledger: &HashMap<String, Value>) -> Result<BTreeMap<String, Decimal>> {
+    let mut last: HashMap<&str, (Decimal, &Value)> = HashMap::new();
+    for entry in ledger.values() {
+        let asset = str_field(entry, "asset")?;
+        let time = to_decimal(&entry["time"])?;
+        if last
+            .get(asset)
+            .is_none_or(|&(last_time, _)| last_time < time)
+        {
+            last.insert(asset, (time, entry));
+        }
+    }
+    let mut balances: BTreeMap<String, Decimal> = BTreeMap::new();
+    for (asset, (_, entry)) in last {
+        // The fee credits have no equivalent in the history
+        if asset == KFEE {
+            continue;
+        }
+        let asset = normalize_asset_or_keep(asset);
+        let what = t!("the balance of {} on Kraken", asset);
+        let total = balances.entry(asset.to_string()).or_default();
+        *total = checked::add(*total, decimal_field(entry, "balance")?, &what)?;
+    }
+    Ok(balances)
+}
+
+/// Finds a trade by its trade ID, or a ledger entry by its ledger ID, returning
+/// when it took place and the IDs it is known by in the activity: the trade
+/// ID, or the ledger and reference IDs. `None` if Kraken knows neither.
+pub fn fetch_entry(keyfile: &Path, id: &str) -> Result<Option<(DateTime<Utc>, Vec<String>)>> {
+    let api_keys = load_api_keys(keyfile)?;
+    let client = http::client()?;
+    let queries = [
+        ("QueryTrades", "txid", &[][..]),
+        ("QueryLedgers", "id", &["refid"][..]),
+    ];
+    for (endpoint, param, fields) in queries {
+        let mut params = BTreeMap::from([(param, id.to_string())]);
+        let uri_path = format!("/0/private/{}", endpoint);
+        let result = match kraken_private_request(client, &api_keys, &uri_path, &mut params) {
+            // An unknown ID is an invalid argument
+            Err(Error::Kraken { .. }) => continue,
+            result => result?,
+        };
+        if let Some(entry) = result.get(id) {
+            let mut entry_ids = vec![id.to_string()];
+            entry_ids.extend(ids(entry, fields));
+            return Ok(Some((time_field(entry)?, entry_ids)));
+        }
+    }
+    Ok(None)
+}
+
+/// Private endpoints used to fetch the activity, with the API key permission
+/// each of them requires
+pub const REQUIRED_PERMISSIONS: [(&str, &str); 3] = [
+    ("DepositStatus", "Query Funds"),
+    ("WithdrawStatus", "Query Funds"),
+    ("TradesHistory", "Query Closed Orders & Trades"),
+];
+
+/// Calls each endpoint of [`REQUIRED_PERMISSIONS`] once, to check that the API
+/// keys in `keyfile` are valid and allowed to fetch the activity. Fails only if
+/// the key file can't be loaded; the result of each endpoint is returned.
+pub fn check_api_access(keyfile: &Path) -> Result<Vec<(&'static str, Result<()>)>> {
+    let api_keys = load_api_keys(keyfile)?;
+    let client = http::client()?;
+    Ok(REQUIRED_PERMISSIONS
         .iter()
-        .filter(|entry| {
-            entry["time"]
-                .as_u64()
-                .map(|ts| ts >= start_ts && ts <= end_ts)
-                .unwrap()
+        .map(|(endpoint, _)| {
+            let uri_path = format!("/0/private/{}", endpoint);
+            let result = kraken_private_request(client, &api_keys, &uri_path, &mut BTreeMap::new());
+            (*endpoint, result.map(|_| ()))
         })
-        .cloned()
-        .collect();
+        .collect())
+}
 
-    // 3. Trades
-    let mut params = BTreeMap::new();
-    params.insert("start", start_ts.to_string());
-    params.insert("end", end_ts.to_string());
-    let trades_json =
-        kraken_private_request(&client, &api_keys, "/0/private/TradesHistory", &mut params);
-    let mut trades: Vec<Value> = trades_json["trades"]
-        .as_object()
-        .unwrap()
-        .values()
-        .filter(|entry| {
-            let ts = to_decimal(&entry["time"]);
-            ts >= Decimal::from(start_ts) && ts <= Decimal::from(end_ts)
+/// Days of each batch of [`KrakenSource::fetch_batches`]. Each batch makes
+/// every request of a fetch again, about 45 seconds of the rate limit of the
+/// starter tier, so they aren't made shorter than a week.
+const BATCH_DAYS: u64 = 7;
+
+/// The Kraken account the API keys give access to
+pub struct KrakenSource {
+    /// Key file given by the user, or `None` to look for it with
+    /// [`find_keyfile`] only when fetching
+    keyfile: Option<PathBuf>,
+    /// Offset of the days of the report, which the fills of an order are
+    /// aggregated by
+    offset: FixedOffset,
+}
+
+impl KrakenSource {
+    pub fn new(keyfile: Option<PathBuf>) -> Self {
+        KrakenSource {
+            keyfile,
+            offset: BRASILIA,
+        }
+    }
+
+    /// Aggregates the fills of an order on the days at `offset`, that of
+    /// [`crate::report::ProcessOptions::offset`], rather than in Brasília time
+    pub fn with_offset(self, offset: FixedOffset) -> Self {
+        KrakenSource { offset, ..self }
+    }
+
+    /// The current balance of each asset, see [`fetch_kraken_balances`]
+    pub fn balances(&self) -> Result<BTreeMap<String, Decimal>> {
+        fetch_kraken_balances(&find_keyfile(self.keyfile.as_deref())?)
+    }
+
+    /// The balance of each asset at `time`, see [`fetch_kraken_balances_at`]
+    pub fn balances_at(&self, time: DateTime<Utc>) -> Result<BTreeMap<String, Decimal>> {
+        fetch_kraken_balances_at(&find_keyfile(self.keyfile.as_deref())?, time)
+    }
+
+    /// The balances at the end of `date` in Brasília time, the time the store
+    /// dates the transactions in, left by its ledger entries; the current
+    /// ones if `date` is `today` or later
+    pub fn balances_on(
+        &self,
+        date: NaiveDate,
+        today: NaiveDate,
+    ) -> Result<BTreeMap<String, Decimal>> {
+        if date >= today {
+            return self.balances();
+        }
+        let end = date
+            .succ_opt()
+            .and_then(|next| next.and_hms_opt(0, 0, 0))
+            .and_then(|next| next.and_local_timezone(BRASILIA).single())
+            .ok_or_else(|| Error::Config(t!("invalid date: {}", date)))?;
+        self.balances_at(end.to_utc() - TimeDelta::seconds(1))
+    }
+
+    /// Compares the current balances with the whole history of the account,
+    /// fetched from [`FIRST_DAY`] to `today`
+    pub fn reconcile_history(&self, today: NaiveDate) -> Result<Vec<Discrepancy>> {
+        let history = self.fetch(FIRST_DAY, today)?;
+        Ok(reconcile(&implied_balances(&history), &self.balances()?))
+    }
+
+    /// A trade or ledger entry by its ID, see [`fetch_entry`]
+    pub fn entry(&self, id: &str) -> Result<Option<(DateTime<Utc>, Vec<String>)>> {
+        fetch_entry(&find_keyfile(self.keyfile.as_deref())?, id)
+    }
+}
+
+impl ExchangeSource for KrakenSource {
+    fn exchange(&self) -> ExchangeInfo {
+        ExchangeInfo {
+            name: "Kraken".to_string(),
+            url: "https://www.kraken.com".to_string(),
+            country: "US".to_string(),
+        }
+    }
+
+    fn fetch(&self, first_day: NaiveDate, last_day: NaiveDate) -> Result<Activity> {
+        let keyfile = find_keyfile(self.keyfile.as_deref())?;
+        let transfer_time = TransferTime::from_env()?;
+        let aggregate_orders = env::flag("AGGREGATE_ORDERS")?;
+        // With the settled times, the transfers requested before the first
+        // day and settled after the last are fetched but left out
+        Ok(normalize(
+            fetch_kraken_activity(
+                first_day,
+                last_day,
+                &keyfile,
+                transfer_time,
+                aggregate_orders,
+            )?,
+            self.offset,
+        )?
+        .within(first_day, last_day))
+    }
+
+    /// Batches of [`BATCH_DAYS`] days, so a month of an account with many
+    /// trades isn't held at once
+    fn fetch_batches<'s>(
+        &'s self,
+        first_day: NaiveDate,
+        last_day: NaiveDate,
+    ) -> Box<dyn Iterator<Item = Result<Activity>> + 's> {
+        Box::new(
+            day_ranges(first_day, last_day, BATCH_DAYS)
+                .map(|(first, last)| self.fetch(first, last)),
+        )
+    }
+}
+
+/// What an Earn or staking entry of the Kraken ledger does with the funds, by
+/// its type and subtype
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EarnEvent {
+    /// Allocated to a strategy, bonding them: "allocation" or "autoallocation"
+    Bond,
+    /// Deallocated from a strategy, unbonding them: "deallocation"
+    Unbond,
+    /// Moved from one strategy to another: "migration"
+    Migration,
+    /// Paid by a strategy: "reward", or any entry of the older "staking" type
+    Reward,
+    /// Anything else, which is neither a move of the funds nor an income
+    Other,
+}
+
+impl EarnEvent {
+    fn of(entry: &Value) -> EarnEvent {
+        if entry["type"].as_str() == Some("staking") {
+            return EarnEvent::Reward;
+        }
+        match entry["subtype"].as_str().unwrap_or_default() {
+            "allocation" | "autoallocation" => EarnEvent::Bond,
+            "deallocation" => EarnEvent::Unbond,
+            "migration" => EarnEvent::Migration,
+            "reward" => EarnEvent::Reward,
+            _ => EarnEvent::Other,
+        }
+    }
+
+    /// Whether the funds only move between the wallets of the account
+    fn is_move(self) -> bool {
+        matches!(
+            self,
+            EarnEvent::Bond | EarnEvent::Unbond | EarnEvent::Migration
+        )
+    }
+}
+
+/// Converts the raw Kraken activity to the typed entries of [`Activity`], with
+/// the asset names and pairs as common symbols and the times in whole seconds.
+/// A deposit or withdrawal in the ledger is dated by its ledger entry, and one
+/// with the refid of a transfer or of an Earn bonding or unbonding is left
+/// out, listed as a move between wallets.
+/// The fee of a trade paid with [`KFEE`] is its worth in US dollars, and the
+/// KFEE entries are left out of the rest. With the orders, the trades are
+/// aggregated by [`aggregate_fills`], on the days at `offset`.
+fn normalize(activity: KrakenActivity, offset: FixedOffset) -> Result<Activity> {
+    let transfer_time = |entry: &Value| -> Result<DateTime<Utc>> {
+        match entry["refid"]
+            .as_str()
+            .and_then(|refid| activity.ledger.get(refid))
+        {
+            Some(settled) => time_field(settled),
+            None => time_field(entry),
+        }
+    };
+    let asset = |entry: &Value| -> Result<String> {
+        let asset = str_field(entry, "asset")?;
+        normalize_asset(asset)
+            .map(String::from)
+            .ok_or_else(|| Error::UnknownAsset(asset.to_string()))
+    };
+    // Newer entries name the network apart from the method, e.g. "Bitcoin
+    // Lightning"; the older ones only have the method
+    let network = |entry: &Value| {
+        optional_str_field(entry, "network").or_else(|| optional_str_field(entry, "method"))
+    };
+    let deposit = |entry: &Value| -> Result<NormalizedDeposit> {
+        Ok(NormalizedDeposit {
+            asset: asset(entry)?,
+            amount: decimal_field(entry, "amount")?,
+            fee: fee_field(entry)?,
+            time: transfer_time(entry)?,
+            ids: ids(entry, &["refid", "txid"]),
+            origin_exchange: None,
+            origin_wallet: None,
+            network: network(entry),
+        })
+    };
+    let withdrawal = |entry: &Value| -> Result<NormalizedWithdrawal> {
+        Ok(NormalizedWithdrawal {
+            asset: asset(entry)?,
+            amount: decimal_field(entry, "amount")?,
+            fee: fee_field(entry)?,
+            time: transfer_time(entry)?,
+            ids: ids(entry, &["refid", "txid"]),
+            destination: optional_str_field(entry, "info"),
+            network: network(entry),
         })
-        .cloned()
+    };
+    // The pairs missing from the map are guessed once each, with a warning,
+    // so a pair listed after the data was downloaded doesn't stop the run
+    let mut guessed: HashMap<&str, (String, String)> = HashMap::new();
+    for entry in &activity.trades {
+        let pair = str_field(entry, "pair")?;
+        if kraken_pairs::parse_pair(pair).is_some() || guessed.contains_key(pair) {
+            continue;
+        }
+        let (base, quote) =
+            kraken_pairs::guess_pair(pair).ok_or_else(|| Error::UnknownPair(pair.to_string()))?;
+        warn(&t!(
+            "the pair {} is not in the Kraken data, taken as {}/{}; run update-data to get it",
+            pair,
+            base,
+            quote
+        ));
+        guessed.insert(pair, (base, quote));
+    }
+    // KFEE spent on each trade, by trade ID: charged as the fee or taken from
+    // the amount of the entry, depending on the entry
+    let mut fee_credits: HashMap<&str, Decimal> = HashMap::new();
+    for entry in &activity.fee_credits {
+        let Some(refid) = entry["refid"].as_str() else {
+            continue;
+        };
+        let spent = fee_field(entry)? - decimal_field(entry, "amount")?;
+        *fee_credits.entry(refid).or_default() += spent;
+    }
+    let trade = |entry: &Value| -> Result<NormalizedTrade> {
+        let pair = str_field(entry, "pair")?;
+        let (base, quote) = match kraken_pairs::parse_pair(pair) {
+            Some(pair) => pair,
+            None => {
+                let (base, quote) = guessed
+                    .get(pair)
+                    .ok_or_else(|| Error::UnknownPair(pair.to_string()))?;
+                (base.as_str(), quote.as_str())
+            }
+        };
+        let side = match str_field(entry, "type")? {
+            "buy" => Side::Buy,
+            "sell" => Side::Sell,
+            other => return Err(Error::Validation(t!("unknown trade type: {}", other))),
+        };
+        // The trades of margin positions are flagged, e.g. "closing" or
+        // "liquidation", and may need another treatment than a spot trade
+        let flags = trade_flags(entry);
+        if !flags.is_empty() {
+            warn(&t!(
+                "the Kraken trade {} is flagged {}, but reported as an ordinary trade; check it",
+                entry["txid"].as_str().unwrap_or_default(),
+                flags.join(", ")
+            ));
+        }
+        // The quote asset isn't charged when the fee is paid with KFEE
+        let (fee, credit_fee_usd) = match entry["txid"]
+            .as_str()
+            .and_then(|txid| fee_credits.get(txid))
+            .filter(|spent| spent.is_sign_positive() && !spent.is_zero())
+        {
+            Some(spent) => (Decimal::ZERO, spent * KFEE_USD),
+            None => (fee_field(entry)?, Decimal::ZERO),
+        };
+        Ok(NormalizedTrade {
+            base: base.to_string(),
+            quote: quote.to_string(),
+            side,
+            volume: decimal_field(entry, "vol")?,
+            cost: decimal_field(entry, "cost")?,
+            fee,
+            credit_fee_usd,
+            price: decimal_field(entry, "price")?,
+            time: time_field(entry)?,
+            ids: ids(entry, &["txid", "ordertxid"]),
+        })
+    };
+    // A credit is a fee rebate if its subtype says so; the others are the
+    // referral rewards and the like
+    let income = |entry: &Value| -> Result<NormalizedIncome> {
+        let kind = match entry["subtype"].as_str() {
+            Some(subtype) if subtype.contains("rebate") => IncomeKind::FeeRebate,
+            _ => IncomeKind::Referral,
+        };
+        Ok(NormalizedIncome {
+            kind,
+            asset: asset(entry)?,
+            amount: decimal_field(entry, "amount")? - fee_field(entry)?,
+            time: time_field(entry)?,
+            ids: ids(entry, &["refid"]),
+        })
+    };
+    // A reward is paid in the staked variant of the asset, e.g. "DOT.S", but
+    // is worth what the asset is
+    let staking_reward = |entry: &Value| -> Result<NormalizedIncome> {
+        let asset = str_field(entry, "asset")?;
+        Ok(NormalizedIncome {
+            kind: IncomeKind::Staking,
+            asset: normalize_staked_asset(asset)
+                .ok_or_else(|| Error::UnknownAsset(asset.to_string()))?
+                .to_string(),
+            amount: decimal_field(entry, "amount")? - fee_field(entry)?,
+            time: time_field(entry)?,
+            ids: ids(entry, &["refid"]),
+        })
+    };
+    let wallet_transfer = |entry: &Value| -> Result<WalletTransfer> {
+        let subtype = entry["subtype"].as_str().unwrap_or_default();
+        Ok(WalletTransfer {
+            asset: normalize_asset_or_keep(str_field(entry, "asset")?).to_string(),
+            amount: decimal_field(entry, "amount")?,
+            time: time_field(entry)?,
+            description: match entry["type"].as_str() {
+                Some("earn") => format!("earn {}", subtype),
+                _ => subtype.to_string(),
+            },
+            ids: ids(entry, &["refid"]),
+        })
+    };
+    let earn_moves = || {
+        activity
+            .earn
+            .iter()
+            .filter(|entry| EarnEvent::of(entry).is_move())
+    };
+    let transfer_refids: HashSet<&str> = activity
+        .transfers
+        .iter()
+        .chain(earn_moves())
+        .filter_map(|entry| entry["refid"].as_str())
         .collect();
+    let not_kfee = |entry: &&Value| entry["asset"].as_str() != Some(KFEE);
+    let not_transfer = |entry: &&Value| {
+        !entry["refid"]
+            .as_str()
+            .is_some_and(|refid| transfer_refids.contains(refid))
+            && not_kfee(entry)
+    };
+    let mut normalized = Activity {
+        deposits: activity
+            .deposits
+            .iter()
+            .filter(not_transfer)
+            .map(deposit)
+            .try_collect()?,
+        withdrawals: activity
+            .withdrawals
+            .iter()
+            .filter(not_transfer)
+            .map(withdrawal)
+            .try_collect()?,
+        trades: match &activity.orders {
+            Some(orders) => aggregate_fills(
+                activity.trades.iter().map(trade).try_collect()?,
+                orders,
+                offset,
+            )?,
+            None => activity.trades.iter().map(trade).try_collect()?,
+        },
+        income: activity
+            .credits
+            .iter()
+            .filter(not_kfee)
+            .map(income)
+            .chain(
+                activity
+                    .earn
+                    .iter()
+                    .filter(|entry| EarnEvent::of(entry) == EarnEvent::Reward)
+                    .map(staking_reward),
+            )
+            .sorted_by_key(|income| income.as_ref().ok().map(|income| income.time))
+            .try_collect()?,
+        wallet_transfers: activity
+            .transfers
+            .iter()
+            .chain(earn_moves())
+            .filter(not_kfee)
+            .sorted_by_key(|entry| to_decimal(&entry["time"]).ok())
+            .map(wallet_transfer)
+            .try_collect()?,
+    };
+    for entry in activity.adjustments.iter().filter(not_kfee) {
+        apply_adjustment(&mut normalized, entry)?;
+    }
+    Ok(normalized)
+}
+
+/// Applies an adjustment of the ledger to the operation with its refid, a
+/// trade of its asset or a deposit or withdrawal of it: a credit adds to what
+/// the account received, or takes from what it gave. One that matches no
+/// operation of the period is left out, with a warning.
+fn apply_adjustment(activity: &mut Activity, entry: &Value) -> Result<()> {
+    let refid = str_field(entry, "refid")?;
+    let asset = normalize_asset_or_keep(str_field(entry, "asset")?);
+    let amount = decimal_field(entry, "amount")? - fee_field(entry)?;
+    let has_refid = |ids: &[String]| ids.iter().any(|id| id == refid);
+    let trade = activity
+        .trades
+        .iter_mut()
+        .find(|t| has_refid(&t.ids) && (t.base == asset || t.quote == asset));
+    let deposit = activity
+        .deposits
+        .iter_mut()
+        .find(|d| has_refid(&d.ids) && d.asset == asset);
+    let withdrawal = activity
+        .withdrawals
+        .iter_mut()
+        .find(|w| has_refid(&w.ids) && w.asset == asset);
+    let adjusted = if let Some(trade) = trade {
+        // What a purchase receives is its base asset, and a sale its quote
+        let received = match trade.side {
+            Side::Buy => trade.base == asset,
+            Side::Sell => trade.quote == asset,
+        };
+        let change = if received { amount } else { -amount };
+        if trade.base == asset {
+            trade.volume += change;
+        } else {
+            trade.cost += change;
+        }
+        if !trade.volume.is_zero() {
+            trade.price = trade.cost / trade.volume;
+        }
+        trade.ids.first()
+    } else if let Some(deposit) = deposit {
+        deposit.amount += amount;
+        deposit.ids.first()
+    } else if let Some(withdrawal) = withdrawal {
+        withdrawal.amount -= amount;
+        withdrawal.ids.first()
+    } else {
+        None
+    };
+    let date = time_field(entry)?.date_naive();
+    match adjusted {
+        Some(id) => warn(&t!(
+            "the Kraken adjustment of {} {} on {} was applied to the operation {}",
+            amount,
+            asset,
+            date,
+            id
+        )),
+        None => warn(&t!(
+            "the Kraken adjustment {} of {} {} on {} matches no operation of the period, so it is left out; check it",
+            refid,
+            amount,
+            asset,
+            date
+        )),
+    }
+    Ok(())
+}
+
+/// Merges the partial fills of each order on each day at `offset` into one
+/// trade with their amounts and fees summed, dated by the first and
+/// identified by the order ID and then the trade IDs. The order of a fill is
+/// the one of `orders` listing it, or its `ordertxid`.
+fn aggregate_fills(
+    trades: Vec<NormalizedTrade>,
+    orders: &HashMap<String, Value>,
+    offset: FixedOffset,
+) -> Result<Vec<NormalizedTrade>> {
+    let mut order_of: HashMap<&str, &str> = HashMap::new();
+    for (order_id, order) in orders {
+        for txid in order["trades"].as_array().into_iter().flatten() {
+            if let Some(txid) = txid.as_str() {
+                order_of.insert(txid, order_id);
+            }
+        }
+    }
+    let mut aggregated: Vec<NormalizedTrade> = Vec::with_capacity(trades.len());
+    let mut index: HashMap<(String, NaiveDate), usize> = HashMap::new();
+    let mut fills: BTreeMap<String, usize> = BTreeMap::new();
+    for trade in trades {
+        let txid = trade.ids.first().cloned().unwrap_or_default();
+        let Some(order_id) = order_of
+            .get(txid.as_str())
+            .map(|id| id.to_string())
+            .or_else(|| trade.ids.get(1).cloned())
+        else {
+            aggregated.push(trade);
+            continue;
+        };
+        *fills.entry(order_id.clone()).or_default() += 1;
+        let day = trade.time.with_timezone(&offset).date_naive();
+        match index
+            .get(&(order_id.clone(), day))
+            .map(|&i| &mut aggregated[i])
+        {
+            Some(order)
+                if (&order.base, &order.quote, order.side)
+                    == (&trade.base, &trade.quote, trade.side) =>
+            {
+                let what = t!("the Kraken order {}, adding the trade {}", order_id, txid);
+                order.volume = checked::add(order.volume, trade.volume, &what)?;
+                order.cost = checked::add(order.cost, trade.cost, &what)?;
+                order.fee = checked::add(order.fee, trade.fee, &what)?;
+                order.credit_fee_usd =
+                    checked::add(order.credit_fee_usd, trade.credit_fee_usd, &what)?;
+                if !order.volume.is_zero() {
+                    order.price = checked::div(order.cost, order.volume, &what)?;
+                }
+                order.ids.push(txid);
+            }
+            _ => {
+                index.insert((order_id.clone(), day), aggregated.len());
+                aggregated.push(NormalizedTrade {
+                    ids: vec![order_id, txid],
+                    ..trade
+                });
+            }
+        }
+    }
+    for (order_id, count) in fills {
+        let listed = orders
+            .get(&order_id)
+            .and_then(|order| order["trades"].as_array())
+            .map_or(0, Vec::len);
+        if listed > count {
+            warn(&t!(
+                "{} of the {} fills of the Kraken order {} are outside the period, so they aren't aggregated with the others",
+                listed - count,
+                listed,
+                order_id
+            ));
+        }
+    }
+    Ok(aggregated)
+}
+
+/// Prints a warning about the Kraken data
+fn warn(message: &str) {
+    eprintln!("{}", style::warning(&t!("Warning: {}", message)));
+}
+
+/// The flags in the comma separated `misc` field of a trade
+fn trade_flags(entry: &Value) -> Vec<&str> {
+    entry["misc"]
+        .as_str()
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|flag| !flag.is_empty())
+        .collect()
+}
+
+/// Gets a string field of a Kraken entry
+fn str_field<'a>(entry: &'a Value, name: &str) -> Result<&'a str> {
+    entry[name]
+        .as_str()
+        .ok_or_else(|| Error::Validation(t!("missing field \"{}\" in {}", name, entry)))
+}
+
+/// Gets a decimal field, encoded as a string, of a Kraken entry
+fn decimal_field(entry: &Value, name: &str) -> Result<Decimal> {
+    let value = str_field(entry, name)?;
+    value.parse::<Decimal>().map_err(|e| {
+        Error::Validation(t!(
+            "invalid decimal \"{}\" in field \"{}\": {}",
+            value,
+            name,
+            e
+        ))
+    })
+}
+
+/// Gets the fee of a Kraken entry, zero if it has none: some entries leave
+/// the field out, or send it empty or as a number
+fn fee_field(entry: &Value) -> Result<Decimal> {
+    match &entry["fee"] {
+        Value::Null => Ok(Decimal::ZERO),
+        Value::String(fee) if fee.trim().is_empty() => Ok(Decimal::ZERO),
+        Value::Number(_) => to_decimal(&entry["fee"]),
+        _ => decimal_field(entry, "fee"),
+    }
+}
+
+/// Gets the time of a Kraken entry, in UNIX seconds, with a fraction for trades
+fn time_field(entry: &Value) -> Result<DateTime<Utc>> {
+    to_decimal(&entry["time"])?
+        .trunc()
+        .to_i64()
+        .and_then(|ts| DateTime::from_timestamp(ts, 0))
+        .ok_or_else(|| Error::Validation(t!("timestamp out of range: {}", entry["time"])))
+}
+
+/// The IDs of a Kraken entry, in the order of the fields they are in
+fn ids(entry: &Value, fields: &[&str]) -> Vec<String> {
+    fields
+        .iter()
+        .filter_map(|field| entry[field].as_str())
+        .map(String::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
 
-    // Sort all by time ascending
-    deposits.sort_by_key(|v| v["time"].as_u64().unwrap());
-    withdrawals.sort_by_key(|v| v["time"].as_u64().unwrap());
-    trades.sort_by_key(|v| to_decimal(&v["time"]));
-
-    (deposits, withdrawals, trades)
-}
-
-pub fn is_fiat(ticker: &str) -> bool {
-    static FIAT_CURRENCIES: phf::Set<&'static str> = phf_set! {
-        "USD", "ZUSD",
-        "EUR", "ZEUR",
-        "GBP", "ZGBP",
-        "JPY", "ZJPY",
-        "CAD", "ZCAD",
-        "AUD", "ZAUD",
-        "MXN", "ZMXN",
-        "CHF", "ZCHF",
-        "BRL", "ZBRL",
-        "ARS", "ZARS",
-        "AED", "ZAED",
-    };
-    FIAT_CURRENCIES.contains(ticker.to_uppercase().as_str())
+    #[test]
+    fn test_normalize() {
+        let activity = normalize(KrakenActivity {
+            deposits: vec![json!({"asset": "XXBT", "refid": "FTQcuak-V6Za8qrWnhzTx67yYHz8Tg", "amount": "0.5", "fee": "0", "time": 1709294400, "method": "Bitcoin", "network": "Bitcoin Lightning"})],
+            withdrawals: vec![json!({"asset": "ZEUR", "amount": "10", "fee": "0", "time": 1709294400, "info": "IBAN DE89", "method": "SEPA"})],
+            trades: vec![
+                serde_json::from_str(
+                    r#"{"pair": "XETHXXBT", "txid": "TZX2WP-XSEOP-CEWAIE", "ordertxid": "OQCLML-BW3P3-BUCMWZ", "type": "sell", "vol": "1", "cost": "0.05", "fee": "0.0001", "price": "0.05", "time": 1709380800.7}"#,
+                )
+                .unwrap(),
+            ],
+            ledger: HashMap::new(),
+            credits: vec![
+                json!({"refid": "QCKPZ5-TQO7K", "type": "credit", "subtype": "", "asset": "XXBT", "amount": "0.0002", "fee": "0.0000", "time": 1709467200.25}),
+                json!({"refid": "QCKPZ6-UW2WX", "type": "credit", "subtype": "feerebate", "asset": "ZEUR", "amount": "1.5", "fee": "0", "time": 1709467200}),
+            ],
+            transfers: vec![],
+            fee_credits: vec![],
+            orders: None,
+            adjustments: vec![],
+            earn: vec![],
+        }, BRASILIA)
+        .unwrap();
+        assert_eq!(activity.deposits[0].asset, "BTC");
+        assert_eq!(activity.deposits[0].amount, Decimal::new(5, 1));
+        assert_eq!(activity.deposits[0].ids, ["FTQcuak-V6Za8qrWnhzTx67yYHz8Tg"]);
+        assert_eq!(
+            activity.deposits[0].network.as_deref(),
+            Some("Bitcoin Lightning")
+        );
+        assert_eq!(activity.withdrawals[0].network.as_deref(), Some("SEPA"));
+        assert_eq!(activity.withdrawals[0].asset, "EUR");
+        assert_eq!(
+            activity.withdrawals[0].destination.as_deref(),
+            Some("IBAN DE89")
+        );
+        let trade = &activity.trades[0];
+        assert_eq!((trade.base.as_str(), trade.quote.as_str()), ("ETH", "BTC"));
+        assert_eq!(trade.side, Side::Sell);
+        assert_eq!(trade.time.timestamp(), 1709380800);
+        assert_eq!(trade.ids, ["TZX2WP-XSEOP-CEWAIE", "OQCLML-BW3P3-BUCMWZ"]);
+        let [referral, rebate] = activity.income.as_slice() else {
+            panic!("expected 2 income entries, got {:?}", activity.income);
+        };
+        assert_eq!(
+            (referral.kind, referral.asset.as_str(), referral.amount),
+            (IncomeKind::Referral, "BTC", Decimal::new(2, 4))
+        );
+        assert_eq!(
+            (rebate.kind, rebate.asset.as_str()),
+            (IncomeKind::FeeRebate, "EUR")
+        );
+
+        let trade = |pair: &str, trade_type: &str| KrakenActivity {
+            deposits: vec![],
+            withdrawals: vec![],
+            trades: vec![
+                json!({"pair": pair, "type": trade_type, "vol": "1", "cost": "1", "fee": "0", "price": "1", "time": 0}),
+            ],
+            ledger: HashMap::new(),
+            credits: vec![],
+            transfers: vec![],
+            fee_credits: vec![],
+            orders: None,
+            adjustments: vec![],
+            earn: vec![],
+        };
+        let unknown = normalize(trade("NOTAPAIR", "buy"), BRASILIA);
+        assert!(matches!(unknown, Err(Error::UnknownPair(pair)) if pair == "NOTAPAIR"));
+        // A pair missing from the map is guessed from its assets
+        let guessed = normalize(trade("XBT/NEWCOIN", "buy"), BRASILIA).unwrap();
+        let guess = &guessed.trades[0];
+        assert_eq!(
+            (guess.base.as_str(), guess.quote.as_str()),
+            ("BTC", "NEWCOIN")
+        );
+        let margin = normalize(trade("XETHXXBT", "settle"), BRASILIA);
+        assert!(matches!(margin, Err(Error::Validation(_))));
+    }
+
+    #[test]
+    fn test_settled_time() {
+        // Requested on March 31st, debited on April 1st
+        let withdrawal = json!({"asset": "XXBT", "refid": "AGBZNBO-5P2XSB", "amount": "0.1", "fee": "0", "time": 1711926000});
+        let activity = |ledger| KrakenActivity {
+            deposits: vec![],
+            withdrawals: vec![withdrawal.clone()],
+            trades: vec![],
+            ledger,
+            credits: vec![],
+            transfers: vec![],
+            fee_credits: vec![],
+            orders: None,
+            adjustments: vec![],
+            earn: vec![],
+        };
+        let requested = normalize(activity(HashMap::new()), BRASILIA).unwrap();
+        assert_eq!(requested.withdrawals[0].time.timestamp(), 1711926000);
+        let ledger = HashMap::from([(
+            "AGBZNBO-5P2XSB".to_string(),
+            json!({"refid": "AGBZNBO-5P2XSB", "type": "withdrawal", "time": 1711933200.5}),
+        )]);
+        let settled = normalize(activity(ledger), BRASILIA).unwrap();
+        assert_eq!(settled.withdrawals[0].time.timestamp(), 1711933200);
+        let march = |activity: Activity| {
+            let day = |m, d| NaiveDate::from_ymd_opt(2024, m, d).unwrap();
+            activity.within(day(3, 1), day(3, 31)).withdrawals.len()
+        };
+        assert_eq!((march(requested), march(settled)), (1, 0));
+    }
+
+    #[test]
+    fn test_wallet_transfers() {
+        // DOT staked: out of the spot wallet, and listed as a withdrawal too
+        let activity = normalize(KrakenActivity {
+            deposits: vec![json!({"asset": "XXBT", "refid": "FTQcuak-V6Za8q", "amount": "0.5", "fee": "0", "time": 1709294400})],
+            withdrawals: vec![json!({"asset": "DOT", "refid": "BOG5AE5-KSCNR4", "amount": "10", "fee": "0", "time": 1709294400})],
+            trades: vec![],
+            ledger: HashMap::new(),
+            credits: vec![],
+            transfers: vec![
+                json!({"refid": "BOG5AE5-KSCNR4", "type": "transfer", "subtype": "spottostaking", "asset": "DOT", "amount": "-10", "fee": "0", "time": 1709294401.5}),
+                json!({"refid": "BOG5AE5-KSCNR4", "type": "transfer", "subtype": "stakingfromspot", "asset": "DOT.S", "amount": "10", "fee": "0", "time": 1709294402}),
+            ],
+            fee_credits: vec![],
+            orders: None,
+            adjustments: vec![],
+            earn: vec![],
+        }, BRASILIA)
+        .unwrap();
+        assert_eq!(activity.deposits.len(), 1);
+        assert!(activity.withdrawals.is_empty());
+        let [spot, staking] = activity.wallet_transfers.as_slice() else {
+            panic!("expected 2 moves, got {:?}", activity.wallet_transfers);
+        };
+        assert_eq!(
+            (spot.asset.as_str(), spot.amount, spot.description.as_str()),
+            ("DOT", Decimal::from(-10), "spottostaking")
+        );
+        assert_eq!(
+            (staking.asset.as_str(), staking.ids.as_slice()),
+            ("DOT.S", ["BOG5AE5-KSCNR4".to_string()].as_slice())
+        );
+    }
+
+    #[test]
+    fn test_earn() {
+        let earn = |refid: &str, subtype: &str, asset: &str, amount: &str, time: u64| json!({"refid": refid, "type": "earn", "subtype": subtype, "asset": asset, "amount": amount, "fee": "0", "time": time});
+        let activity = normalize(KrakenActivity {
+            deposits: vec![],
+            withdrawals: vec![json!({"asset": "DOT", "refid": "ELFI3PZ-SBXF4J", "amount": "10", "fee": "0", "time": 1709294400})],
+            trades: vec![],
+            ledger: HashMap::new(),
+            credits: vec![],
+            transfers: vec![],
+            fee_credits: vec![],
+            adjustments: vec![],
+            earn: vec![
+                earn("ELFI3PZ-SBXF4J", "allocation", "DOT", "-10", 1709294401),
+                earn("ELFI3PZ-SBXF4J", "allocation", "DOT.B", "10", 1709294401),
+                earn("RKB7ODD-4V3BLB", "reward", "DOT.B", "0.01", 1709380800),
+                earn("ULZHQ3Q-NAO7DH", "deallocation", "DOT.B", "-5", 1709467200),
+            ],
+            orders: None,
+        }, BRASILIA)
+        .unwrap();
+        // Bonding isn't a withdrawal, and the reward is an income
+        assert!(activity.withdrawals.is_empty());
+        let [reward] = activity.income.as_slice() else {
+            panic!("expected 1 income entry, got {:?}", activity.income);
+        };
+        assert_eq!(
+            (reward.kind, reward.asset.as_str(), reward.amount),
+            (IncomeKind::Staking, "DOT", Decimal::new(1, 2))
+        );
+        let moves: Vec<(&str, Decimal, &str)> = activity
+            .wallet_transfers
+            .iter()
+            .map(|m| (m.asset.as_str(), m.amount, m.description.as_str()))
+            .collect();
+        assert_eq!(
+            moves,
+            [
+                ("DOT", Decimal::from(-10), "earn allocation"),
+                ("DOT.B", Decimal::from(10), "earn allocation"),
+                ("DOT.B", Decimal::from(-5), "earn deallocation"),
+            ]
+        );
+        assert_eq!(
+            EarnEvent::of(&json!({"subtype": "autoallocation"})),
+            EarnEvent::Bond
+        );
+        assert_eq!(
+            EarnEvent::of(&json!({"subtype": "deallocation"})),
+            EarnEvent::Unbond
+        );
+    }
+
+    #[test]
+    fn test_fee_credits() {
+        let trade = |txid: &str| json!({"pair": "XXBTZUSD", "txid": txid, "type": "buy", "vol": "0.01", "cost": "650", "fee": "1.04", "price": "65000", "time": 1709380800});
+        let activity = normalize(KrakenActivity {
+            deposits: vec![],
+            withdrawals: vec![],
+            trades: vec![trade("TZX2WP-XSEOP-CEWAIE"), trade("TQ5L3S-2NDCM-KFD2X3")],
+            ledger: HashMap::new(),
+            credits: vec![
+                json!({"refid": "QCKPZ7-F3D4M", "type": "credit", "subtype": "", "asset": "KFEE", "amount": "1000.00", "fee": "0.00", "time": 1709294400}),
+            ],
+            transfers: vec![],
+            fee_credits: vec![
+                json!({"refid": "QCKPZ7-F3D4M", "type": "credit", "asset": "KFEE", "amount": "1000.00", "fee": "0.00", "time": 1709294400}),
+                json!({"refid": "TZX2WP-XSEOP-CEWAIE", "type": "trade", "asset": "KFEE", "amount": "0.00", "fee": "104.00", "time": 1709380800}),
+            ],
+            orders: None,
+            adjustments: vec![],
+            earn: vec![],
+        }, BRASILIA)
+        .unwrap();
+        // The fee of the first trade was paid with 104 KFEE, worth 1.04 USD
+        let fees: Vec<(Decimal, Decimal)> = activity
+            .trades
+            .iter()
+            .map(|trade| (trade.fee, trade.credit_fee_usd))
+            .collect();
+        assert_eq!(
+            fees,
+            [
+                (Decimal::ZERO, Decimal::new(104, 2)),
+                (Decimal::new(104, 2), Decimal::ZERO)
+            ]
+        );
+        // The KFEE given isn't an income
+        assert!(activity.income.is_empty());
+    }
+
+    #[test]
+    fn test_ledger_balances() {
+        let entry = |asset: &str, time: &str, balance: &str| json!({"asset": asset, "time": serde_json::Number::from_string_unchecked(time.to_string()), "balance": balance});
+        let ledger = HashMap::from([
+            ("L1".to_string(), entry("XXBT", "1709294400.1234", "0.5")),
+            // The same second, later
+            ("L2".to_string(), entry("XXBT", "1709294400.5678", "0.25")),
+            ("L3".to_string(), entry("ZEUR", "1709294400.1234", "100.0")),
+            ("L4".to_string(), entry("ZEUR", "1709200000.0000", "130.0")),
+            ("L5".to_string(), entry("KFEE", "1709294400.1234", "500.00")),
+        ]);
+        assert_eq!(
+            ledger_balances(&ledger).unwrap(),
+            BTreeMap::from([
+                ("BTC".to_string(), Decimal::new(25, 2)),
+                ("EUR".to_string(), Decimal::new(1000, 1)),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_aggregate_fills() {
+        let fill = |txid: &str, vol: &str, cost: &str, time: u64| json!({"pair": "XXBTZEUR", "txid": txid, "ordertxid": "OQCLML-BW3P3-BUCMWZ", "type": "buy", "vol": vol, "cost": cost, "fee": "0.1", "price": "60000", "time": time});
+        let activity = |orders| KrakenActivity {
+            deposits: vec![],
+            withdrawals: vec![],
+            trades: vec![
+                fill("TZX2WP-XSEOP-CEWAIE", "0.01", "600", 1709294400),
+                fill("TQ5L3S-2NDCM-KFD2X3", "0.03", "1830", 1709298000),
+                // The next day in Brasília
+                fill("T7D2KV-PQ6AB-3MFDY2", "0.01", "610", 1709391600),
+            ],
+            ledger: HashMap::new(),
+            credits: vec![],
+            transfers: vec![],
+            fee_credits: vec![],
+            orders,
+            adjustments: vec![],
+            earn: vec![],
+        };
+        assert_eq!(normalize(activity(None), BRASILIA).unwrap().trades.len(), 3);
+
+        let orders = HashMap::from([(
+            "OQCLML-BW3P3-BUCMWZ".to_string(),
+            json!({"status": "closed", "trades": ["TZX2WP-XSEOP-CEWAIE", "TQ5L3S-2NDCM-KFD2X3", "T7D2KV-PQ6AB-3MFDY2"]}),
+        )]);
+        let trades = normalize(activity(Some(orders)), BRASILIA).unwrap().trades;
+        let [first, second] = trades.as_slice() else {
+            panic!("expected 2 trades, got {:?}", trades);
+        };
+        assert_eq!(
+            (first.volume, first.cost, first.fee, first.price),
+            (
+                Decimal::new(4, 2),
+                Decimal::from(2430),
+                Decimal::new(2, 1),
+                Decimal::from(60750)
+            )
+        );
+        assert_eq!(first.time.timestamp(), 1709294400);
+        assert_eq!(
+            first.ids,
+            [
+                "OQCLML-BW3P3-BUCMWZ",
+                "TZX2WP-XSEOP-CEWAIE",
+                "TQ5L3S-2NDCM-KFD2X3"
+            ]
+        );
+        assert_eq!(second.ids, ["OQCLML-BW3P3-BUCMWZ", "T7D2KV-PQ6AB-3MFDY2"]);
+
+        // 22:00 in Brasília is the next day in UTC
+        let orders = HashMap::from([(
+            "OQCLML-BW3P3-BUCMWZ".to_string(),
+            json!({"status": "closed", "trades": ["TZX2WP-XSEOP-CEWAIE", "TQ5L3S-2NDCM-KFD2X3"]}),
+        )]);
+        let evening = |offset| {
+            let fills = vec![
+                fill("TZX2WP-XSEOP-CEWAIE", "0.01", "600", 1709294400),
+                fill("TQ5L3S-2NDCM-KFD2X3", "0.03", "1830", 1709341200),
+            ];
+            let activity = KrakenActivity {
+                trades: fills,
+                ..activity(Some(orders.clone()))
+            };
+            normalize(activity, offset).unwrap().trades.len()
+        };
+        assert_eq!(evening(BRASILIA), 1);
+        assert_eq!(evening(FixedOffset::east_opt(0).unwrap()), 2);
+    }
+
+    #[test]
+    fn test_adjustments() {
+        let adjustment = |refid: &str, asset: &str, amount: &str| json!({"refid": refid, "type": "adjustment", "subtype": "", "asset": asset, "amount": amount, "fee": "0", "time": 1709467200});
+        let activity = normalize(KrakenActivity {
+            deposits: vec![json!({"asset": "XXBT", "refid": "FTQcuak-V6Za8q", "amount": "0.5", "fee": "0", "time": 1709294400})],
+            withdrawals: vec![],
+            trades: vec![json!({"pair": "XXBTZEUR", "txid": "TZX2WP-XSEOP-CEWAIE", "ordertxid": "OQCLML-BW3P3-BUCMWZ", "type": "sell", "vol": "0.1", "cost": "6000", "fee": "0", "price": "60000", "time": 1709380800})],
+            ledger: HashMap::new(),
+            credits: vec![],
+            transfers: vec![],
+            fee_credits: vec![],
+            adjustments: vec![
+                // 10 EUR more for the sale, and 0.01 BTC less for the deposit
+                adjustment("TZX2WP-XSEOP-CEWAIE", "ZEUR", "10"),
+                adjustment("FTQcuak-V6Za8q", "XXBT", "-0.01"),
+                // Of an operation out of the period, or of the balance only
+                adjustment("AGBZNBO-5P2XSB", "XXBT", "0.2"),
+            ],
+            earn: vec![],
+            orders: None,
+        }, BRASILIA)
+        .unwrap();
+        let trade = &activity.trades[0];
+        assert_eq!(
+            (trade.volume, trade.cost, trade.price),
+            (
+                Decimal::new(1, 1),
+                Decimal::from(6010),
+                Decimal::from(60100)
+            )
+        );
+        assert_eq!(activity.deposits[0].amount, Decimal::new(49, 2));
+    }
+
+    #[test]
+    fn test_trade_flags() {
+        assert_eq!(
+            trade_flags(&json!({"misc": "closing, liquidation"})),
+            ["closing", "liquidation"]
+        );
+        assert!(trade_flags(&json!({"misc": ""})).is_empty());
+        assert!(trade_flags(&json!({})).is_empty());
+    }
+
+    #[test]
+    fn test_fee_field() {
+        assert_eq!(
+            fee_field(&json!({"fee": "0.0001"})).unwrap(),
+            Decimal::new(1, 4)
+        );
+        // An entry without a fee has none
+        for entry in [
+            json!({}),
+            json!({"fee": null}),
+            json!({"fee": ""}),
+            json!({"fee": 0}),
+        ] {
+            assert_eq!(fee_field(&entry).unwrap(), Decimal::ZERO);
+        }
+        assert!(fee_field(&json!({"fee": "abc"})).is_err());
+    }
+
+    #[test]
+    fn test_nonce_from_threads() {
+        let nonces: Vec<u64> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| scope.spawn(|| (0..100).map(|_| next_nonce()).collect::<Vec<_>>()))
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|h| h.join().unwrap())
+                .collect()
+        });
+        assert_eq!(nonces.iter().unique().count(), nonces.len());
+    }
+
+    #[test]
+    fn test_call_counter() {
+        let start = Instant::now();
+        let mut counter = CallCounter {
+            value: 0.0,
+            updated: None,
+        };
+        for _ in 0..7 {
+            assert_eq!(counter.reserve(2.0, start), None);
+        }
+        // 14 of 15 used, so another history call waits for 1 point to decay
+        let wait = counter.reserve(2.0, start).unwrap();
+        assert!((wait.as_secs_f64() - 1.0 / CALL_COUNTER_DECAY).abs() < 1e-9);
+        assert_eq!(counter.reserve(1.0, start), None);
+        assert_eq!(counter.reserve(2.0, start + Duration::from_secs(10)), None);
+    }
 }
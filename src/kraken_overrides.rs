@@ -0,0 +1,116 @@
+//! Entries added to the compiled Kraken asset and pair maps, or replacing
+//! theirs, read at startup from `kraken_overrides.json` in the configuration
+//! directory: a quick fix for an asset or pair Kraken listed after the data
+//! was downloaded, until the program is rebuilt with `update-data`.
+//!
+//! ```json
+//! {
+//!     "assets": {"XNEW": "NEW"},
+//!     "pairs": {"NEWEUR": ["NEW", "EUR"]}
+//! }
+//! ```
+//!
+//! The assets map Kraken names to common symbols, and the pairs map Kraken
+//! pair names to the common symbols of their base and quote.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use serde::Deserialize;
+
+use crate::env;
+use crate::error::{Error, Result};
+use crate::i18n::t;
+use crate::paths::config_dir;
+
+/// Name of the file, in the configuration directory
+const OVERRIDES_NAME: &str = "kraken_overrides.json";
+
+/// The entries of the file
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Overrides {
+    /// Common symbol of each Kraken asset name
+    #[serde(default)]
+    pub assets: HashMap<String, String>,
+    /// Base and quote of each Kraken pair
+    #[serde(default)]
+    pub pairs: HashMap<String, (String, String)>,
+}
+
+static OVERRIDES: OnceLock<Overrides> = OnceLock::new();
+
+/// Path of the file: `KRAKEN2RFB_KRAKEN_OVERRIDES` if set, or else
+/// `kraken_overrides.json` in the configuration directory
+pub fn path() -> Option<PathBuf> {
+    env::var("KRAKEN_OVERRIDES")
+        .map(PathBuf::from)
+        .or_else(|| config_dir().map(|dir| dir.join(OVERRIDES_NAME)))
+}
+
+/// Parses the overrides, checking that no name or symbol is empty
+fn parse(content: &str) -> std::result::Result<Overrides, String> {
+    let overrides: Overrides = serde_json::from_str(content).map_err(|e| e.to_string())?;
+    let blank = |names: &[&String]| names.iter().any(|name| name.trim().is_empty());
+    let blank_asset = overrides
+        .assets
+        .iter()
+        .find(|(asset, symbol)| blank(&[asset, symbol]))
+        .map(|(asset, _)| asset);
+    let blank_pair = overrides
+        .pairs
+        .iter()
+        .find(|(pair, (base, quote))| blank(&[pair, base, quote]))
+        .map(|(pair, _)| pair);
+    if let Some(name) = blank_asset.or(blank_pair) {
+        return Err(t!("empty name in the entry {}", format!("{:?}", name)));
+    }
+    Ok(overrides)
+}
+
+/// Loads the file, if there is one. Only the first call has any effect; the
+/// maps are used without overrides before it.
+pub fn init() -> Result<()> {
+    let overrides = match path() {
+        Some(path) => match fs::read_to_string(&path) {
+            Ok(content) => parse(&content).map_err(|e| {
+                Error::Config(t!("invalid configuration file {}: {}", path.display(), e))
+            })?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Overrides::default(),
+            Err(e) => return Err(e.into()),
+        },
+        None => Overrides::default(),
+    };
+    let _ = OVERRIDES.set(overrides);
+    Ok(())
+}
+
+/// The overrides loaded by [`init`], none before it
+pub fn get() -> &'static Overrides {
+    static NONE: OnceLock<Overrides> = OnceLock::new();
+    OVERRIDES
+        .get()
+        .unwrap_or_else(|| NONE.get_or_init(Overrides::default))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let overrides =
+            parse(r#"{"assets": {"XNEW": "NEW"}, "pairs": {"NEWEUR": ["NEW", "EUR"]}}"#).unwrap();
+        assert_eq!(overrides.assets["XNEW"], "NEW");
+        assert_eq!(
+            overrides.pairs["NEWEUR"],
+            ("NEW".to_string(), "EUR".to_string())
+        );
+        assert!(parse("{}").unwrap().pairs.is_empty());
+        assert!(parse(r#"{"asset": {}}"#).is_err());
+        assert!(parse(r#"{"pairs": {"NEWEUR": ["NEW"]}}"#).is_err());
+        assert!(parse(r#"{"pairs": {"NEWEUR": ["NEW", " "]}}"#).is_err());
+    }
+}
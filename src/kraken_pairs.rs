@@ -1,7 +1,96 @@
+//! Kraken asset pairs, split into base and quote assets.
+
+use crate::kraken_overrides;
+use crate::kraken_symbols::{normalize_asset, normalize_asset_or_keep};
+
 // Generated at build time by build.rs
 include!(concat!(env!("OUT_DIR"), "/kraken_pairs_map.rs"));
+include!(concat!(env!("OUT_DIR"), "/kraken_pair_altnames_map.rs"));
 
-/// Get the base and quote altnames for a given Kraken trading pair
+/// Get the base and quote altnames for a given Kraken trading pair, by its ID,
+/// like `XETHZEUR`, or by its altname or wsname, like `ETHEUR` or `ETH/EUR`,
+/// as in the CSV exports. The overrides (see [`kraken_overrides`]) come first.
 pub fn parse_pair(pair: &str) -> Option<(&'static str, &'static str)> {
-    KRAKEN_PAIRS.get(pair).copied()
+    if let Some((base, quote)) = kraken_overrides::get().pairs.get(pair) {
+        return Some((base, quote));
+    }
+    KRAKEN_PAIRS
+        .get(pair)
+        .or_else(|| KRAKEN_PAIRS.get(KRAKEN_PAIR_ALTNAMES.get(pair)?))
+        .copied()
+}
+
+/// Guesses the base and quote of a pair missing from the map, e.g. one listed
+/// after the data was downloaded: split at the `/` of the `wsname` form, like
+/// `XBT/USD`, or where both sides are known Kraken assets, like `XXBTZUSD`.
+/// `None` if there is no such split, or more than one.
+pub fn guess_pair(pair: &str) -> Option<(String, String)> {
+    if let Some((base, quote)) = pair.split_once('/') {
+        return (!base.is_empty() && !quote.is_empty()).then(|| {
+            (
+                normalize_asset_or_keep(base).to_string(),
+                normalize_asset_or_keep(quote).to_string(),
+            )
+        });
+    }
+    let mut splits = (1..pair.len())
+        .filter(|&i| pair.is_char_boundary(i))
+        .filter_map(|i| Some((normalize_asset(&pair[..i])?, normalize_asset(&pair[i..])?)));
+    let (base, quote) = splits.next()?;
+    splits
+        .all(|split| split == (base, quote))
+        .then(|| (base.to_string(), quote.to_string()))
+}
+
+/// All known pairs as (pair, base, quote), with the overrides, sorted by
+/// pair name
+pub fn all_pairs() -> Vec<(&'static str, &'static str, &'static str)> {
+    let overrides = &kraken_overrides::get().pairs;
+    let mut pairs: Vec<_> = KRAKEN_PAIRS
+        .entries()
+        .filter(|(pair, _)| !overrides.contains_key(**pair))
+        .map(|(pair, (base, quote))| (*pair, *base, *quote))
+        .chain(
+            overrides
+                .iter()
+                .map(|(pair, (base, quote))| (pair.as_str(), base.as_str(), quote.as_str())),
+        )
+        .collect();
+    pairs.sort_unstable();
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_all_pairs() {
+        let pairs = all_pairs();
+        assert_eq!(pairs.len(), KRAKEN_PAIRS.len());
+        assert!(pairs.is_sorted());
+        assert!(pairs.contains(&("XETHZEUR", "ETH", "EUR")));
+        assert_eq!(parse_pair("XXBTZEUR"), Some(("BTC", "EUR")));
+        assert_eq!(parse_pair("BOGUS"), None);
+    }
+
+    #[test]
+    fn test_parse_altname() {
+        assert_eq!(parse_pair("ETHEUR"), Some(("ETH", "EUR")));
+        assert_eq!(parse_pair("ETH/EUR"), Some(("ETH", "EUR")));
+        assert_eq!(parse_pair("XBTUSD"), Some(("BTC", "USD")));
+        assert_eq!(parse_pair("XBT/USD"), Some(("BTC", "USD")));
+        assert_eq!(parse_pair("1INCH/EUR"), Some(("1INCH", "EUR")));
+    }
+
+    #[test]
+    fn test_guess_pair() {
+        let pair = |base: &str, quote: &str| Some((base.to_string(), quote.to_string()));
+        assert_eq!(guess_pair("XBT/USD"), pair("BTC", "USD"));
+        assert_eq!(guess_pair("NEWCOIN/EUR"), pair("NEWCOIN", "EUR"));
+        assert_eq!(guess_pair("XXBTZUSD"), pair("BTC", "USD"));
+        assert_eq!(guess_pair("ETHEUR"), pair("ETH", "EUR"));
+        assert_eq!(guess_pair("BOGUS"), None);
+        assert_eq!(guess_pair("/EUR"), None);
+    }
 }
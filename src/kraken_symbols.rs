@@ -0,0 +1,73 @@
+//! Kraken asset names and their common symbols, e.g. `XXBT` and `BTC`.
+
+use crate::kraken_overrides;
+
+// Generated at build time by build.rs
+include!(concat!(env!("OUT_DIR"), "/kraken_symbols_map.rs"));
+
+/// Get the common symbol (altname) for a given Kraken asset name, from the
+/// overrides (see [`kraken_overrides`]) or the compiled map
+pub fn get_common_symbol(asset: &str) -> Option<&'static str> {
+    match kraken_overrides::get().assets.get(asset) {
+        Some(symbol) => Some(symbol),
+        None => KRAKEN_SYMBOLS.get(asset).copied(),
+    }
+}
+
+/// The common symbol of an asset given by its Kraken name, its altname or the
+/// common symbol itself: `XXBT`, `XBT` and `BTC` are all `BTC`, and `ZUSD`
+/// and `USD` are `USD`. `None` if Kraken doesn't know the asset.
+pub fn normalize_asset(asset: &str) -> Option<&'static str> {
+    // The only altname that isn't the common symbol, see build.rs
+    if asset == "XBT" {
+        return get_common_symbol("XXBT");
+    }
+    get_common_symbol(asset).or_else(|| {
+        let overridden = kraken_overrides::get().assets.values().map(String::as_str);
+        KRAKEN_SYMBOLS
+            .values()
+            .copied()
+            .chain(overridden)
+            .find(|&symbol| symbol == asset)
+    })
+}
+
+/// The common symbol of an asset, as [`normalize_asset`], or the asset as
+/// given if Kraken doesn't know it, e.g. one typed by the user or of another
+/// exchange
+pub fn normalize_asset_or_keep(asset: &str) -> &str {
+    normalize_asset(asset).unwrap_or(asset)
+}
+
+/// The common symbol of the asset of a staked or Earn variant, given by its
+/// Kraken name, e.g. `DOT` of `DOT.S`, `DOT28.S` or `DOT.B`, or of the asset
+/// itself as [`normalize_asset`]
+pub fn normalize_staked_asset(asset: &str) -> Option<&'static str> {
+    let Some((name, _)) = asset.split_once('.') else {
+        return normalize_asset(asset);
+    };
+    // The bonding period is in the name of some, like the 28 days of DOT28.S
+    normalize_asset(name)
+        .or_else(|| normalize_asset(name.trim_end_matches(|c: char| c.is_ascii_digit())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_asset() {
+        for asset in ["XXBT", "XBT", "BTC"] {
+            assert_eq!(normalize_asset(asset), Some("BTC"));
+        }
+        assert_eq!(normalize_asset("XETH"), Some("ETH"));
+        assert_eq!(normalize_asset("ZUSD"), Some("USD"));
+        assert_eq!(normalize_asset("USD"), Some("USD"));
+        assert_eq!(normalize_asset("NOTANASSET"), None);
+        assert_eq!(normalize_asset_or_keep("NOTANASSET"), "NOTANASSET");
+        for asset in ["DOT.S", "DOT28.S", "DOT.B", "DOT"] {
+            assert_eq!(normalize_staked_asset(asset), Some("DOT"));
+        }
+        assert_eq!(normalize_staked_asset("NOTANASSET.S"), None);
+    }
+}
@@ -0,0 +1,436 @@
+//! KuCoin API client: the spot fills, deposits and withdrawals of the
+//! account.
+//!
+//! Besides the signature of each request, KuCoin asks for the passphrase of
+//! the API key, signed with the secret as well (version 2 of the keys). The
+//! lists are paged by page number and limited to 7 days per request.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use chrono::{NaiveDate, TimeDelta, Utc};
+use hmac::{Hmac, Mac};
+use itertools::Itertools;
+use reqwest::blocking::Client;
+use rust_decimal::Decimal;
+use serde_json::Value;
+use sha2::Sha256;
+
+use crate::api_keys::{ApiKeys, KeyLocation};
+use crate::endpoints;
+use crate::error::{Error, Result};
+use crate::fields::{
+    self, decimal, decimal_field, id_field, int_field, millis_field, optional_str_field, str_field,
+};
+use crate::http;
+use crate::i18n::t;
+use crate::report::transactions::ExchangeInfo;
+use crate::source::{
+    Activity, ExchangeSource, NormalizedDeposit, NormalizedTrade, NormalizedWithdrawal, Side,
+    day_millis, millis_range, millis_windows,
+};
+
+/// Where the KuCoin API keys are looked for: `kucoin_keys.json`, with the
+/// `passphrase` of the key, or the `KRAKEN2RFB_KUCOIN_KEYFILE`,
+/// `KRAKEN2RFB_KUCOIN_API_KEY`, `KRAKEN2RFB_KUCOIN_API_SECRET` and
+/// `KRAKEN2RFB_KUCOIN_API_PASSPHRASE` environment variables
+pub const KEYS: KeyLocation = KeyLocation {
+    exchange: "KuCoin",
+    file_name: "kucoin_keys.json",
+    env_prefix: "KUCOIN_",
+    profile_keyfile: false,
+};
+
+/// Longest time range of a request to a list, in days
+const WINDOW_DAYS: i64 = 7;
+
+/// Most entries a page has
+const PAGE_SIZE: usize = 500;
+
+/// Code of a successful response
+const SUCCESS_CODE: &str = "200000";
+
+/// Error codes by which KuCoin rejects the API keys, the passphrase or the
+/// signature
+const AUTH_ERROR_CODES: [&str; 8] = [
+    "400001", "400002", "400003", "400004", "400005", "400006", "400007", "411100",
+];
+
+/// Raw KuCoin activity for a period. A fill whose fee was paid in a third
+/// currency, like KCS, has the price of that currency in the quote currency
+/// added as `feePrice`.
+#[derive(Debug, Clone, Default)]
+pub struct KucoinActivity {
+    pub deposits: Vec<Value>,
+    pub withdrawals: Vec<Value>,
+    pub fills: Vec<Value>,
+}
+
+/// Signature of a request: the HMAC-SHA256, with the API secret, of the
+/// timestamp, the method, the path with the query and the body, in base64.
+/// The passphrase is signed the same way.
+fn signature(secret: &str, message: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size");
+    mac.update(message.as_bytes());
+    BASE64.encode(mac.finalize().into_bytes())
+}
+
+fn kucoin_error(endpoint: &str, body: &Value) -> Error {
+    let code = body["code"].as_str();
+    Error::Exchange {
+        exchange: "KuCoin".to_string(),
+        endpoint: endpoint.to_string(),
+        message: match (code, body["msg"].as_str()) {
+            (Some(code), Some(msg)) => format!("{} ({})", msg, code),
+            _ => body.to_string(),
+        },
+        auth: code.is_some_and(|code| AUTH_ERROR_CODES.contains(&code)),
+    }
+}
+
+/// The signed API of an account
+struct Api {
+    client: &'static Client,
+    keys: ApiKeys,
+    /// The passphrase, signed
+    passphrase: String,
+}
+
+impl Api {
+    fn new(keyfile: &Path) -> Result<Self> {
+        let (keys, passphrase) = KEYS.load_with_passphrase(keyfile)?;
+        Ok(Api {
+            client: http::client()?,
+            passphrase: signature(&keys.secret, &passphrase),
+            keys,
+        })
+    }
+
+    /// Calls an endpoint, e.g. "/api/v1/fills", and returns its `data`
+    fn get(&self, path: &str, params: &[(&str, String)], signed: bool) -> Result<Value> {
+        let query = serde_urlencoded::to_string(params).expect("the params are strings");
+        let request_path = format!("{}?{}", path, query);
+        let mut request = self
+            .client
+            .get(format!("{}{}", endpoints::get().kucoin, request_path));
+        if signed {
+            let timestamp = Utc::now().timestamp_millis().to_string();
+            let message = format!("{}GET{}", timestamp, request_path);
+            request = request
+                .header("KC-API-KEY", &self.keys.key)
+                .header("KC-API-SIGN", signature(&self.keys.secret, &message))
+                .header("KC-API-TIMESTAMP", timestamp)
+                .header("KC-API-PASSPHRASE", &self.passphrase)
+                .header("KC-API-KEY-VERSION", "2");
+        }
+        let response = http::send(request)?;
+        let success = response.status().is_success();
+        let body: Value = response
+            .json()
+            .map_err(|e| Error::Network(t!("invalid JSON from KuCoin {}: {}", path, e)))?;
+        if !success || body["code"] != SUCCESS_CODE {
+            return Err(kucoin_error(path, &body));
+        }
+        Ok(body["data"].clone())
+    }
+
+    /// Pages through a list from `start` to `end`, in windows of 7 days
+    fn paged(&self, path: &str, (start, end): (i64, i64)) -> Result<Vec<Value>> {
+        let mut entries = Vec::new();
+        for (from, to) in millis_windows(start, end, WINDOW_DAYS) {
+            for page in 1.. {
+                let data = self.get(
+                    path,
+                    &[
+                        ("startAt", from.to_string()),
+                        ("endAt", to.to_string()),
+                        ("pageSize", PAGE_SIZE.to_string()),
+                        ("currentPage", page.to_string()),
+                    ],
+                    true,
+                )?;
+                entries.extend(list(&data["items"], path)?.iter().cloned());
+                if page >= int_field(&data, "totalPage").unwrap_or(0) {
+                    break;
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Closing price on `date` of `currency` in `quote`, from the daily candle
+    /// of their symbol in either direction
+    fn price(&self, currency: &str, quote: &str, date: NaiveDate) -> Result<Decimal> {
+        let path = "/api/v1/market/candles";
+        let start = day_millis(date) / 1000;
+        let candle = |symbol: String| -> Option<Decimal> {
+            let params = [
+                ("symbol", symbol),
+                ("type", "1day".to_string()),
+                ("startAt", start.to_string()),
+                (
+                    "endAt",
+                    (start + TimeDelta::days(1).num_seconds() - 1).to_string(),
+                ),
+            ];
+            // An unknown symbol is an error, which only means the other
+            // direction must be tried
+            let candles = self.get(path, &params, false).ok()?;
+            decimal(&candles.as_array()?.first()?[2], "close").ok()
+        };
+        if let Some(price) = candle(format!("{}-{}", currency, quote)) {
+            return Ok(price);
+        }
+        match candle(format!("{}-{}", quote, currency)) {
+            Some(price) if !price.is_zero() => Ok(Decimal::ONE / price),
+            _ => Err(Error::Validation(t!(
+                "no {} market to value {} in {} on {}",
+                "KuCoin",
+                currency,
+                quote,
+                date
+            ))),
+        }
+    }
+}
+
+pub fn fetch_kucoin_activity(
+    initial: NaiveDate,
+    final_: NaiveDate,
+    keyfile: &Path,
+) -> Result<KucoinActivity> {
+    let api = Api::new(keyfile)?;
+    let range = millis_range(initial, final_);
+
+    let deposits = api.paged("/api/v1/deposits", range)?;
+    let withdrawals = api.paged("/api/v1/withdrawals", range)?;
+    let mut fills = Vec::new();
+    let mut prices = HashMap::new();
+    for mut fill in api.paged("/api/v1/fills", range)? {
+        let (base, quote) = market(&fill)?;
+        let fee_currency = str_field(&fill, "feeCurrency")?;
+        if fee_currency != base && fee_currency != quote && !decimal_field(&fill, "fee")?.is_zero()
+        {
+            let date = millis_field(&fill, "createdAt")?.date_naive();
+            let key = (fee_currency.to_string(), quote.to_string(), date);
+            let price = match prices.get(&key) {
+                Some(&price) => price,
+                None => {
+                    let price = api.price(fee_currency, quote, date)?;
+                    *prices.entry(key).or_insert(price)
+                }
+            };
+            fill["feePrice"] = Value::from(price.to_string());
+        }
+        fills.push(fill);
+    }
+
+    Ok(KucoinActivity {
+        deposits,
+        withdrawals,
+        fills,
+    })
+}
+
+/// The KuCoin account the API keys give access to
+pub struct KucoinSource {
+    /// Key file given by the user, or `None` to look for it only when fetching
+    keyfile: Option<PathBuf>,
+}
+
+impl KucoinSource {
+    pub fn new(keyfile: Option<PathBuf>) -> Self {
+        KucoinSource { keyfile }
+    }
+}
+
+impl ExchangeSource for KucoinSource {
+    fn exchange(&self) -> ExchangeInfo {
+        ExchangeInfo {
+            name: "KuCoin".to_string(),
+            url: "https://www.kucoin.com".to_string(),
+            country: "SC".to_string(),
+        }
+    }
+
+    fn fetch(&self, first_day: NaiveDate, last_day: NaiveDate) -> Result<Activity> {
+        let keyfile = KEYS.find_keyfile(self.keyfile.as_deref())?;
+        normalize(fetch_kucoin_activity(first_day, last_day, &keyfile)?)
+    }
+}
+
+/// The base and quote currencies of the symbol of a fill, e.g. "BTC-USDT"
+fn market(fill: &Value) -> Result<(&str, &str)> {
+    str_field(fill, "symbol")?
+        .split_once('-')
+        .ok_or_else(|| Error::Validation(t!("unexpected KuCoin entry: {}", fill)))
+}
+
+/// Converts the raw KuCoin activity to the typed entries of [`Activity`].
+///
+/// The fee of a fill is in its `feeCurrency`: usually the quote currency,
+/// or else valued at the price of the fill if it is the base currency, or at
+/// the `feePrice` added when fetching.
+fn normalize(activity: KucoinActivity) -> Result<Activity> {
+    let transfer_ids = |entry: &Value| -> Vec<String> {
+        ["id", "walletTxId"]
+            .iter()
+            .filter_map(|field| id_field(entry, field).ok())
+            .collect()
+    };
+    let deposits = activity
+        .deposits
+        .iter()
+        .filter(|d| d["status"] == "SUCCESS")
+        .map(|deposit| -> Result<NormalizedDeposit> {
+            Ok(NormalizedDeposit {
+                asset: str_field(deposit, "currency")?.to_string(),
+                amount: decimal_field(deposit, "amount")?,
+                fee: decimal_field(deposit, "fee").unwrap_or_default(),
+                time: millis_field(deposit, "createdAt")?,
+                ids: transfer_ids(deposit),
+                origin_exchange: None,
+                origin_wallet: None,
+                network: None,
+            })
+        });
+    let withdrawals = activity
+        .withdrawals
+        .iter()
+        .filter(|w| w["status"] == "SUCCESS")
+        .map(|withdrawal| -> Result<NormalizedWithdrawal> {
+            Ok(NormalizedWithdrawal {
+                asset: str_field(withdrawal, "currency")?.to_string(),
+                amount: decimal_field(withdrawal, "amount")?,
+                fee: decimal_field(withdrawal, "fee")?,
+                time: millis_field(withdrawal, "createdAt")?,
+                ids: transfer_ids(withdrawal),
+                destination: optional_str_field(withdrawal, "address"),
+                network: None,
+            })
+        });
+    let fill = |fill: &Value| -> Result<NormalizedTrade> {
+        let (base, quote) = market(fill)?;
+        let side = match str_field(fill, "side")? {
+            "buy" => Side::Buy,
+            "sell" => Side::Sell,
+            _ => return Err(Error::Validation(t!("unexpected KuCoin entry: {}", fill))),
+        };
+        let price = decimal_field(fill, "price")?;
+        let fee = decimal_field(fill, "fee")?;
+        let fee = match str_field(fill, "feeCurrency")? {
+            _ if fee.is_zero() => Decimal::ZERO,
+            currency if currency == quote => fee,
+            currency if currency == base => fee * price,
+            _ => fee * decimal_field(fill, "feePrice")?,
+        };
+        Ok(NormalizedTrade {
+            base: base.to_string(),
+            quote: quote.to_string(),
+            side,
+            volume: decimal_field(fill, "size")?,
+            cost: decimal_field(fill, "funds")?,
+            fee,
+            credit_fee_usd: Decimal::ZERO,
+            price,
+            time: millis_field(fill, "createdAt")?,
+            ids: vec![id_field(fill, "tradeId")?, id_field(fill, "orderId")?],
+        })
+    };
+
+    let mut trades: Vec<NormalizedTrade> = activity.fills.iter().map(fill).try_collect()?;
+    trades.sort_by_key(|t| t.time);
+    let mut deposits: Vec<NormalizedDeposit> = deposits.try_collect()?;
+    deposits.sort_by_key(|d| d.time);
+    let mut withdrawals: Vec<NormalizedWithdrawal> = withdrawals.try_collect()?;
+    withdrawals.sort_by_key(|w| w.time);
+    Ok(Activity {
+        deposits,
+        withdrawals,
+        trades,
+        income: Vec::new(),
+        wallet_transfers: Vec::new(),
+    })
+}
+
+/// A JSON list in a KuCoin response
+fn list<'a>(value: &'a Value, endpoint: &str) -> Result<&'a Vec<Value>> {
+    fields::list(value, "KuCoin", endpoint)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    use serde_json::json;
+
+    #[test]
+    fn test_signature() {
+        let message = "1709294400000GET/api/v1/fills?startAt=1709251200000&endAt=1709855999999&pageSize=500&currentPage=1";
+        assert_eq!(
+            signature("secret", message),
+            "eqQk6kr+mJFdYRPpMc0RRuxy08IXytAyum7TiT/IbNo="
+        );
+        assert_eq!(
+            signature("secret", "passphrase"),
+            "sWd5rQWAxDzYJTY6K2sov6seA0l3uNP70anWxITg8IA="
+        );
+    }
+
+    #[test]
+    fn test_normalize() {
+        let activity = normalize(KucoinActivity {
+            deposits: vec![
+                json!({"currency": "USDT", "amount": "100", "fee": "0", "walletTxId": "0xa1", "status": "SUCCESS", "createdAt": 1709294400000_i64}),
+                json!({"currency": "USDT", "amount": "50", "fee": "0", "walletTxId": "0xa2", "status": "PROCESSING", "createdAt": 1709294400000_i64}),
+            ],
+            withdrawals: vec![
+                json!({"id": "w1", "currency": "BTC", "amount": "0.1", "fee": "0.0005", "walletTxId": "0xb1", "status": "SUCCESS", "createdAt": 1709380800000_i64}),
+            ],
+            fills: vec![
+                json!({"symbol": "BTC-USDT", "tradeId": "t1", "orderId": "o1", "side": "buy", "price": "60000", "size": "0.01", "funds": "600", "fee": "0.6", "feeCurrency": "USDT", "createdAt": 1709467200000_i64}),
+                json!({"symbol": "ETH-BTC", "tradeId": "t2", "orderId": "o2", "side": "sell", "price": "0.05", "size": "2", "funds": "0.1", "fee": "0.01", "feeCurrency": "KCS", "feePrice": "0.0002", "createdAt": 1709553600000_i64}),
+            ],
+        })
+        .unwrap();
+
+        // The deposit still processing is left out
+        assert_eq!(activity.deposits.len(), 1);
+        assert_eq!(activity.deposits[0].ids, ["0xa1"]);
+        let withdrawal = &activity.withdrawals[0];
+        assert_eq!(
+            (withdrawal.amount, withdrawal.fee),
+            (dec!(0.1), dec!(0.0005))
+        );
+        assert_eq!(withdrawal.ids, ["w1", "0xb1"]);
+
+        let [buy, sell] = activity.trades.as_slice() else {
+            panic!("expected 2 trades, got {:?}", activity.trades);
+        };
+        assert_eq!(
+            (buy.side, buy.cost, buy.fee),
+            (Side::Buy, dec!(600), dec!(0.6))
+        );
+        assert_eq!(buy.ids, ["t1", "o1"]);
+        // A fee in KCS at the price of KCS in BTC
+        assert_eq!((sell.side, sell.fee), (Side::Sell, dec!(0.000002)));
+        assert_eq!((sell.base.as_str(), sell.quote.as_str()), ("ETH", "BTC"));
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = kucoin_error(
+            "/api/v1/fills",
+            &json!({"code": "400004", "msg": "Invalid KC-API-PASSPHRASE"}),
+        );
+        assert!(error.is_auth_error());
+        assert!(error.to_string().contains("(400004)"));
+        let error = kucoin_error(
+            "/api/v1/fills",
+            &json!({"code": "400100", "msg": "startAt and endAt range cannot exceed 7 days"}),
+        );
+        assert!(!error.is_auth_error());
+    }
+}
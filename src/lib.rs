@@ -0,0 +1,114 @@
+//! Generates the monthly crypto operations report of Receita Federal
+//! (IN RFB 1888/2019) from the activity of a Kraken account.
+//!
+//! The pipeline is: an [`source::ExchangeSource`], like [`kraken::KrakenSource`],
+//! fetches the deposits, withdrawals and trades of a period; [`report::process_activity`]
+//! turns them into [`report::transactions::Transaction`]s valued in BRL, with the
+//! rates from [`exchange_rate`]; and [`report::generate_report`] writes them in
+//! the layout of the IN 1888. The `kraken2rfb` binary is a thin command line
+//! front end over these modules.
+//!
+//! The conversion itself, [`report::convert_activity`], does no I/O: it gets
+//! the rates and the answers of the user from a [`report::resolve::Resolver`].
+//! [`report::process_activity`] is the blocking adapter that fetches and asks
+//! for them; an async caller can resolve [`report::resolve::required_rates`]
+//! on its own instead. [`builder::ReportBuilder`] puts the whole pipeline
+//! behind a single call.
+//!
+//! Each backend is behind a cargo feature, all enabled by default: `kraken`,
+//! `binance`, `bitstamp`, `bybit`, `okx`, `kucoin`, `gemini` and `gate` (the
+//! exchange clients),
+//! `cryptocom`, `csv_import`, `koinly` and `cointracking` (the importers of the
+//! Crypto.com App export, of other CSV files and of the Koinly and
+//! CoinTracking exports), `mercadobitcoin` (the importer of the history of a
+//! Brazilian exchange, which only counts for the gains), `bitcoin` and `ethereum` (the scans of the wallets of
+//! the user on block explorers), `wallet_files` (the reader of the exports of
+//! the wallets that can't be scanned), `bcb` and `coingecko` (the rate providers), and
+//! `cli` (the binary, which needs all of them). The `python` feature, off by
+//! default, builds the crate as the `kraken2rfb` Python module too. A library user can build only what it needs with
+//! `default-features = false`; with none of the backends, the crate has no
+//! HTTP client at all.
+
+use rust_decimal::Decimal;
+use serde_json::Value;
+
+pub mod api_keys;
+pub mod archive;
+#[cfg(feature = "binance")]
+pub mod binance;
+#[cfg(feature = "bitcoin")]
+pub mod bitcoin;
+#[cfg(feature = "bitstamp")]
+pub mod bitstamp;
+pub mod builder;
+pub mod bundle;
+#[cfg(feature = "bybit")]
+pub mod bybit;
+pub mod checked;
+pub mod checkpoint;
+#[cfg(feature = "cointracking")]
+pub mod cointracking;
+pub mod config;
+#[cfg(feature = "cryptocom")]
+pub mod cryptocom;
+pub mod csv;
+#[cfg(feature = "csv_import")]
+pub mod csv_import;
+pub mod endpoints;
+pub mod env;
+pub mod error;
+#[cfg(feature = "ethereum")]
+pub mod ethereum;
+pub mod exchange_rate;
+pub mod fields;
+pub mod gains;
+#[cfg(feature = "gate")]
+pub mod gate;
+#[cfg(feature = "gemini")]
+pub mod gemini;
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(feature = "http")]
+pub mod http_cache;
+pub mod i18n;
+pub mod income;
+#[cfg(feature = "koinly")]
+pub mod koinly;
+#[cfg(feature = "kraken")]
+pub mod kraken;
+pub mod kraken_overrides;
+pub mod kraken_pairs;
+pub mod kraken_symbols;
+#[cfg(feature = "kucoin")]
+pub mod kucoin;
+#[cfg(feature = "mercadobitcoin")]
+pub mod mercadobitcoin;
+#[cfg(feature = "okx")]
+pub mod okx;
+pub mod paths;
+pub mod period;
+pub mod prompt;
+#[cfg(feature = "python")]
+mod python;
+pub mod reconcile;
+pub mod redact;
+pub mod registry;
+pub mod report;
+pub mod source;
+pub mod store;
+pub mod style;
+pub mod tax;
+pub mod transfers;
+pub mod verify;
+#[cfg(feature = "wallet_files")]
+pub mod wallet_file;
+
+use error::{Error, Result};
+
+/// Reads a decimal from a JSON number, as Kraken sends the timestamps
+pub fn to_decimal(value: &Value) -> Result<Decimal> {
+    value
+        .as_number()
+        .and_then(|n| Decimal::try_from(n.as_str()).ok())
+        .ok_or_else(|| Error::Validation(t!("expected a decimal number, got {}", value)))
+}
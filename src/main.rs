@@ -1,80 +1,1705 @@
-mod exchange_rate;
-mod kraken;
-mod kraken_pairs;
-mod kraken_symbols;
-mod report;
-
-use chrono::NaiveDate;
-use kraken::fetch_kraken_activity;
-use report::process_kraken_data;
-use rust_decimal::Decimal;
-use serde_json::Value;
+mod cli;
+mod doctor;
+mod init;
+mod server;
+mod update_data;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
-use crate::report::{generate_report, transactions::Transaction};
+use chrono::{Datelike, FixedOffset, NaiveDate, Utc};
+use cli::{
+    BundleCommand, Command, ExchangeName, HoldingsOptions, ImportFormat, InspectOptions,
+    PairsQuery, PeriodSource, ReportOptions, SaleSimulation,
+};
+use itertools::Itertools;
+use kraken2rfb::archive::{Archive, ArchivedRun, archive_run};
+use kraken2rfb::binance::BinanceSource;
+use kraken2rfb::bitcoin::{self, Descriptor, scan_wallet};
+use kraken2rfb::bitstamp::BitstampSource;
+use kraken2rfb::builder::ReportBuilder;
+use kraken2rfb::bundle::{Bundle, BundledRate};
+use kraken2rfb::bybit::BybitSource;
+use kraken2rfb::checkpoint::Checkpoint;
+use kraken2rfb::cointracking::CoinTrackingSource;
+use kraken2rfb::config::Config;
+use kraken2rfb::cryptocom::CryptoComAppSource;
+use kraken2rfb::csv_import::{CsvSource, Mapping};
+use kraken2rfb::env;
+use kraken2rfb::error::{Error, Result};
+use kraken2rfb::ethereum::{self, scan_addresses};
+use kraken2rfb::exchange_rate::{get_exchange_rate_impl, rate_source};
+use kraken2rfb::gate::GateSource;
+use kraken2rfb::gemini::GeminiSource;
+use kraken2rfb::i18n::{self, t};
+use kraken2rfb::income;
+use kraken2rfb::koinly::KoinlySource;
+use kraken2rfb::kraken::{self, KrakenSource};
+use kraken2rfb::kraken_overrides;
+use kraken2rfb::kraken_symbols::normalize_asset_or_keep;
+use kraken2rfb::kucoin::KucoinSource;
+use kraken2rfb::mercadobitcoin::MercadoBitcoinSource;
+use kraken2rfb::okx::OkxSource;
+use kraken2rfb::period::{BRASILIA, Period};
+use kraken2rfb::prompt::Prompter;
+use kraken2rfb::registry::Registry;
+use kraken2rfb::report::inspect::inspect;
+use kraken2rfb::report::interchange;
+use kraken2rfb::report::layout::Layout;
+use kraken2rfb::report::manifest::ReportManifest;
+use kraken2rfb::report::preview::preview_activity;
+use kraken2rfb::report::resolve::{Providers, required_rates};
+use kraken2rfb::report::review::{describe, review};
+use kraken2rfb::report::summary::RunSummary;
+use kraken2rfb::report::transactions::ExchangeInfo;
+use kraken2rfb::report::{
+    ProcessOptions, convert_activity, generate_report, interactive_resolver, process_activity,
+};
+use kraken2rfb::source::{self, Activity, ExchangeSource, IncomeKind, fetch_days, is_fiat};
+use kraken2rfb::store::{Query, Store, write_csv};
+use kraken2rfb::transfers::match_wallet;
+use kraken2rfb::verify::{self, Chain, Finding};
+use kraken2rfb::{checked, kraken_pairs, paths, redact, style, tax, wallet_file};
+use rust_decimal::Decimal;
 
-fn to_decimal(value: &Value) -> Decimal {
-    Decimal::try_from(value.as_number().unwrap().as_str()).unwrap()
+fn main() -> ExitCode {
+    redact::install_panic_hook();
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", style::error(&redact::redact(&t!("Error: {}", e))));
+            e.exit_code()
+        }
+    }
 }
 
-fn main() {
+fn run() -> Result<()> {
     // Comman line is like:
-    // ./generate_report <year> <month> <report_file>
+    // ./generate_report [options] <year> <month> <report_file>
     // where <year> and <month> are used to fetch data from Kraken API
     // and <report_file> is the output file for the report.
-    let mut args = std::env::args();
-    let command = args.next().unwrap();
-    if std::env::args().len() < 4 {
-        eprintln!("Usage: {command} <year> <month> <report_file>");
-        return;
+    let args: Vec<String> = std::env::args().collect();
+    i18n::init(cli::lang_arg(&args))?;
+    style::init(cli::no_color_arg(&args));
+    paths::init_profile(cli::profile_arg(&args))?;
+    let command = cli::parse_args(args.into_iter())?;
+    // A mistyped profile would otherwise start an empty store of its own;
+    // only init may create it
+    if paths::profile().is_some() && !matches!(command, Command::Init) {
+        Config::load()?;
+    }
+    if !matches!(command, Command::Init) {
+        source::init_fiat(&Config::load_file()?.fiat)?;
+        kraken_overrides::init()?;
+    }
+    match command {
+        Command::Report(options) => run_report(*options).map(|_| ()),
+        Command::Rates { asset, date } => show_rate(&asset, date),
+        Command::PrefetchRates(options) => prefetch_rates(options),
+        Command::Bundle(BundleCommand::Export { source, file }) => bundle_export(source, &file),
+        Command::Bundle(BundleCommand::Import { file, report_file }) => {
+            bundle_import(&file, report_file)
+        }
+        Command::Inspect(options) => inspect_entry(options),
+        Command::Pairs(query) => show_pairs(query),
+        Command::Init => init::run_init(),
+        Command::Doctor { keyfile } => doctor::run_doctor(keyfile.as_deref()),
+        Command::SimulateSale(sale) => simulate_sale(sale),
+        Command::UpdateData { dir } => update_data::update_data(&dir),
+        Command::Encode {
+            input,
+            report_file,
+            layout,
+            force,
+        } => encode(&input, &report_file, layout, force),
+        Command::Schema(document) => {
+            println!("{:#}", document.schema());
+            Ok(())
+        }
+        Command::Verify { report_file } => verify(&report_file),
+        Command::Import {
+            format,
+            file,
+            source,
+        } => import(format, file, source),
+        Command::Query(query) => query_store(&query),
+        Command::Export { query, file } => export_store(&query, &file),
+        Command::Holdings(options) => show_holdings(options),
+        Command::Sales { year } => show_sales(year),
+        Command::Income { year, kind, output } => show_income(year, kind, output.as_deref()),
+        Command::Gains { year, opening_loss } => show_gains(year, opening_loss),
+        Command::Filed {
+            period,
+            exchange,
+            mapping,
+            wallet,
+            date,
+            undo,
+        } => filed(period, exchange, mapping, wallet, date, undo),
+        Command::Serve { listen, keyfile } => server::serve(&listen, keyfile),
+    }
+}
+
+/// Prints the BRL rate of an asset, as used in the report
+fn show_rate(asset: &str, date: NaiveDate) -> Result<()> {
+    // Accept Kraken names like XXBT or XBT too
+    let asset = normalize_asset_or_keep(asset);
+    let (rate_date, rate) = get_exchange_rate_impl(date, asset)?;
+    println!("{}", t!("{} on {}: {} BRL", asset, date, rate));
+    println!(
+        "{}",
+        t!("  source: {}, rate date: {}", rate_source(asset), rate_date)
+    );
+    Ok(())
+}
+
+/// Fetches the activity of a period, with the options it is processed with
+fn fetch_period(options: &PeriodSource) -> Result<(Activity, ProcessOptions)> {
+    let process_options = ProcessOptions {
+        utc_offset: options.utc_offset,
+        ..Default::default()
+    };
+    let source = exchange_source(
+        options.exchange,
+        options.keyfile.clone(),
+        options.input.clone(),
+        options.mapping.clone(),
+        options.wallet.clone(),
+        process_options.offset(),
+    )?;
+    let (first_day, last_day) = (options.period.first_day(), options.period.last_day());
+    println!(
+        "{}",
+        t!(
+            "Fetching {} activity from {} to {}",
+            source.exchange().name,
+            first_day,
+            last_day
+        )
+    );
+    let activity = fetch_days(
+        source.as_ref(),
+        first_day,
+        last_day,
+        process_options.offset(),
+    )?;
+    Ok((activity, process_options))
+}
+
+/// Fetches every rate the report of a period needs, by the activity of the
+/// period, so they are in the HTTP cache when it is generated. The rates that
+/// can't be fetched are listed, and the first of them is returned as the
+/// error once all were tried.
+fn prefetch_rates(options: PeriodSource) -> Result<()> {
+    let (activity, process_options) = fetch_period(&options)?;
+    let rates = required_rates(&activity, &process_options)?;
+    println!(
+        "{}",
+        t!("Fetching {} rates for {}", rates.len(), options.period)
+    );
+    let mut failed = Vec::new();
+    for (asset, date) in &rates {
+        if let Err(e) = get_exchange_rate_impl(*date, asset) {
+            eprintln!("  {}", e);
+            failed.push(e);
+        }
+    }
+    println!(
+        "{}",
+        t!(
+            "{} of {} rates fetched, {} failed",
+            rates.len() - failed.len(),
+            rates.len(),
+            failed.len()
+        )
+    );
+    match failed.into_iter().next() {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+/// Writes the activity of a period, the rates its report needs and the
+/// configuration to a bundle. The rates that can't be fetched are left out,
+/// so they are asked for when the report is generated.
+fn bundle_export(options: PeriodSource, file: &Path) -> Result<()> {
+    let (activity, process_options) = fetch_period(&options)?;
+    let required = required_rates(&activity, &process_options)?;
+    println!(
+        "{}",
+        t!("Fetching {} rates for {}", required.len(), options.period)
+    );
+    let mut rates = Vec::new();
+    for (asset, date) in required {
+        match get_exchange_rate_impl(date, &asset) {
+            Ok((_, rate)) => rates.push(BundledRate { asset, date, rate }),
+            Err(e) => eprintln!("  {}", e),
+        }
+    }
+    let bundle = Bundle::new(
+        options.period,
+        options.exchange.name(),
+        activity,
+        rates,
+        Config::load_file()?,
+    );
+    bundle.write(file)?;
+    println!(
+        "{}",
+        t!(
+            "Bundle of {} written to {}, with {} rates",
+            options.period,
+            file.display(),
+            bundle.rates.len()
+        )
+    );
+    Ok(())
+}
+
+/// Saves a bundle as the checkpoint of the report file, and its configuration
+/// if there is none here yet
+fn bundle_import(file: &Path, report_file: String) -> Result<()> {
+    let bundle = Bundle::read(file)?;
+    let report_file = output_path(report_file)?;
+    let checkpoint = bundle.save_checkpoint(Checkpoint::path_for(Path::new(&report_file)))?;
+    let exists = Config::path().is_some_and(|path| path.exists());
+    if !exists {
+        let path = bundle.config.save()?;
+        println!("{}", t!("Configuration saved to {}", path.display()));
+    } else if Config::load_file()? != bundle.config {
+        eprintln!(
+            "{}",
+            style::warning(&t!(
+                "The configuration of the bundle differs from the one here, which is kept"
+            ))
+        );
+    }
+    println!(
+        "{}",
+        t!(
+            "Bundle of {} from kraken2rfb {} saved to {}, generate the report with:",
+            bundle.period,
+            bundle.tool_version,
+            checkpoint.path().display()
+        )
+    );
+    println!(
+        "  kraken2rfb report --period {} --exchange {} {}",
+        bundle.period, bundle.exchange, report_file
+    );
+    Ok(())
+}
+
+/// Shows what the report makes of a single trade or ledger entry: the entry,
+/// the records it is classified as, the rates it is converted with and its
+/// lines of the report. The entry is taken from a bundle, from the activity
+/// of the period given, or from the day Kraken says it took place.
+fn inspect_entry(options: InspectOptions) -> Result<()> {
+    let process_options = ProcessOptions {
+        utc_offset: options.utc_offset,
+        debug: true,
+        ..Default::default()
+    };
+    let mut ids = vec![options.id.clone()];
+    let bundle = options.bundle.as_deref().map(Bundle::read).transpose()?;
+    let exchange = match &bundle {
+        Some(bundle) => ExchangeName::parse(&bundle.exchange)?,
+        None => options.exchange,
+    };
+    let source = exchange_source(
+        exchange,
+        options.keyfile.clone(),
+        options.input.clone(),
+        options.mapping.clone(),
+        options.wallet.clone(),
+        process_options.offset(),
+    )?;
+    let mut rates = BTreeMap::new();
+    let activity = match (bundle, options.period) {
+        (Some(bundle), _) => {
+            rates = bundle
+                .rates
+                .into_iter()
+                .map(|rate| ((rate.asset, rate.date), rate.rate))
+                .collect();
+            bundle.activity
+        }
+        (None, Some(period)) => {
+            let (first_day, last_day) = (period.first_day(), period.last_day());
+            println!(
+                "{}",
+                t!(
+                    "Fetching {} activity from {} to {}",
+                    source.exchange().name,
+                    first_day,
+                    last_day
+                )
+            );
+            fetch_days(
+                source.as_ref(),
+                first_day,
+                last_day,
+                process_options.offset(),
+            )?
+        }
+        // Only Kraken is asked for the entry, see cli::parse_inspect
+        (None, None) => {
+            let kraken = KrakenSource::new(options.keyfile.clone());
+            let (time, entry_ids) = kraken.entry(&options.id)?.ok_or_else(|| {
+                Error::Config(t!("Kraken has no trade or ledger entry {}", options.id))
+            })?;
+            ids = entry_ids;
+            let day = process_options.day(time);
+            println!(
+                "{}",
+                t!("Fetching {} activity from {} to {}", "Kraken", day, day)
+            );
+            fetch_days(source.as_ref(), day, day, process_options.offset())?
+        }
+    };
+
+    let activity = activity.with_ids(&ids);
+    if activity.is_empty() {
+        return Err(Error::Config(t!(
+            "no entry with ID {} in the activity",
+            options.id
+        )));
+    }
+    // Amounts aren't secret here, they were asked for, but addresses still are
+    println!(
+        "{}",
+        redact::redact(&format!(
+            "Deposits: {:#?}\nWithdrawals: {:#?}\nTrades: {:#?}\nIncome: {:#?}\nWallet transfers: {:#?}",
+            activity.deposits,
+            activity.withdrawals,
+            activity.trades,
+            activity.income,
+            activity.wallet_transfers
+        ))
+    );
+
+    let mut resolver = Providers::with_rates(rates);
+    let inspection = inspect(
+        activity,
+        &source.exchange(),
+        &process_options,
+        &mut resolver,
+    )?;
+    println!("{}", t!("Classified as:"));
+    for record in &inspection.records {
+        println!(
+            "  {} {} {}",
+            record.record_type, record.date, record.description
+        );
+    }
+    for income in &inspection.processed.income {
+        println!(
+            "  {}",
+            t!(
+                "income of {} {} on {}, worth {} BRL, with no record in the report",
+                income.amount,
+                income.asset,
+                income.date,
+                income.value
+            )
+        );
+    }
+    for transfer in &inspection.processed.wallet_transfers {
+        println!(
+            "  {}",
+            t!(
+                "move of {} {} between the wallets of the account ({}), with no record in the report",
+                transfer.amount,
+                transfer.asset,
+                transfer.description
+            )
+        );
+    }
+    if inspection.records.is_empty()
+        && inspection.processed.income.is_empty()
+        && inspection.processed.wallet_transfers.is_empty()
+    {
+        println!(
+            "  {}",
+            t!("nothing, e.g. a fiat deposit or a fiat-fiat trade")
+        );
+    }
+    println!("{}", t!("Rates:"));
+    for ((asset, date), rate) in &inspection.rates {
+        println!(
+            "  {}",
+            t!(
+                "{} on {}: {} BRL ({})",
+                asset,
+                date,
+                rate,
+                rate_source(asset)
+            )
+        );
+    }
+    println!("{}", t!("Report lines:"));
+    for (transaction, line) in inspection
+        .processed
+        .transactions
+        .iter()
+        .zip(&inspection.lines)
+    {
+        println!("  {}", describe(transaction));
+        println!("  {}", line);
+    }
+    Ok(())
+}
+
+/// Prints how Kraken pairs resolve to base and quote assets
+fn show_pairs(query: PairsQuery) -> Result<()> {
+    match query {
+        PairsQuery::List { asset } => {
+            let asset = asset.as_deref().map(normalize_asset_or_keep);
+            for (pair, base, quote) in kraken_pairs::all_pairs() {
+                if asset.is_none_or(|a| a == base || a == quote) {
+                    println!("{:<16}{} / {}", pair, base, quote);
+                }
+            }
+            Ok(())
+        }
+        PairsQuery::Lookup { pair } => {
+            if let Some((base, quote)) = kraken_pairs::parse_pair(&pair) {
+                println!("{}", t!("{}: base {}, quote {}", pair, base, quote));
+                return Ok(());
+            }
+
+            // Altnames like ETHEUR resolve above, but users also write common
+            // symbols, e.g. BTCEUR: Kraken calls bitcoin XBT, the map uses BTC.
+            let altname = pair.replace("XBT", "BTC");
+            let similar = kraken_pairs::all_pairs()
+                .into_iter()
+                .filter(|(name, base, quote)| {
+                    name.contains(&*pair) || altname == [*base, *quote].concat()
+                })
+                .map(|(name, _, _)| name)
+                .join(", ");
+            if !similar.is_empty() {
+                println!("{}", t!("Similar pairs: {}", similar));
+            }
+            if let Some((base, quote)) = kraken_pairs::guess_pair(&pair) {
+                println!(
+                    "{}",
+                    t!(
+                        "{}: not in the Kraken data, perhaps base {}, quote {}",
+                        pair,
+                        base,
+                        quote
+                    )
+                );
+            }
+            Err(Error::UnknownPair(pair))
+        }
     }
+}
+
+/// Writes the report of the transactions in an interchange document
+fn encode(input: &Path, report_file: &str, layout: Layout, force: bool) -> Result<()> {
+    check_overwrite(report_file, force)?;
+    let mut transactions = interchange::read(File::open(input)?)?;
+    // The records of each type go together, in the order they were given
+    transactions.sort_by_key(|t| t.record_type().0);
+    let summary = RunSummary::from_transactions(&transactions)?;
+    generate_report(&transactions, report_file, layout)?;
+    write_manifest(Path::new(report_file))?;
+    println!("{}", summary);
+    Ok(())
+}
+
+/// Fails if the report file exists, unless forced, so a report corrected by
+/// hand isn't silently replaced
+fn check_overwrite(report_file: &str, force: bool) -> Result<()> {
+    if !force && Path::new(report_file).exists() {
+        return Err(Error::Config(t!(
+            "the report file {} already exists; use --force to overwrite it",
+            report_file
+        )));
+    }
+    Ok(())
+}
+
+/// Writes the manifest of a report file, to check it later with `verify`
+fn write_manifest(report_file: &Path) -> Result<()> {
+    let path = ReportManifest::write_for(report_file)?;
+    println!("{}", t!("Manifest written to {}", path.display()));
+    Ok(())
+}
+
+/// Checks that a report file is as described by its manifest
+fn verify(report_file: &Path) -> Result<()> {
+    let expected = ReportManifest::read_for(report_file)?;
+    let differences = expected.differences(&ReportManifest::compute(report_file)?);
+    if !differences.is_empty() {
+        return Err(Error::Validation(t!(
+            "{} was changed since it was generated: {}",
+            report_file.display(),
+            differences.join("; ")
+        )));
+    }
+    println!(
+        "{}",
+        t!(
+            "{} is unchanged: SHA-256 {}, {} lines",
+            report_file.display(),
+            expected.sha256,
+            expected.lines
+        )
+    );
+    Ok(())
+}
+
+fn open_store() -> Result<Store> {
+    let path = Store::default_path()
+        .ok_or_else(|| Error::Config(t!("no data directory, set {}", env::name("DATA_DIR"))))?;
+    Store::open(path)
+}
+
+fn open_registry() -> Result<Registry> {
+    let path = Registry::default_path()
+        .ok_or_else(|| Error::Config(t!("no data directory, set {}", env::name("DATA_DIR"))))?;
+    Registry::open(path)
+}
+
+/// Marks the Kraken report of a period as filed on `date`, or as not filed
+/// with `undo`, or lists the reported periods without a period
+fn filed(
+    period: Option<Period>,
+    exchange: ExchangeName,
+    mapping: Option<PathBuf>,
+    wallet: Option<String>,
+    date: NaiveDate,
+    undo: bool,
+) -> Result<()> {
+    let mut registry = open_registry()?;
+    let Some(period) = period else {
+        if registry.entries().is_empty() {
+            println!("{}", t!("No period reported yet"));
+        }
+        for entry in registry.entries() {
+            let generated = entry.generated_at.map_or(String::new(), |time| {
+                t!("generated at {}", time.format("%Y-%m-%d %H:%M UTC"))
+            });
+            let filed = entry
+                .filed_on
+                .map_or(String::new(), |date| t!("filed on {}", date));
+            println!(
+                "  {} {:<10} {:<32} {}",
+                entry.period, entry.exchange, generated, filed
+            );
+        }
+        return Ok(());
+    };
+
+    let exchange = exchange_source(exchange, None, None, mapping, wallet, BRASILIA)?
+        .exchange()
+        .name;
+    registry.set_filed(&exchange, period, (!undo).then_some(date));
+    registry.save()?;
+    if undo {
+        println!("{}", t!("{} on {} marked as not filed", period, exchange));
+    } else {
+        println!(
+            "{}",
+            t!("{} on {} marked as filed on {}", period, exchange, date)
+        );
+    }
+    Ok(())
+}
+
+/// Prints the stored transactions matching the query, and their totals
+fn query_store(query: &Query) -> Result<()> {
+    let store = open_store()?;
+    let mut transactions = Vec::new();
+    for entry in store.query(query) {
+        let transaction = &entry.transaction;
+        println!(
+            "{} {} {:<12} {}",
+            transaction.date(),
+            transaction.record_type().0,
+            entry.source,
+            describe(transaction)
+        );
+        transactions.push(transaction.clone());
+    }
+    println!("{}", RunSummary::from_transactions(&transactions)?);
+    Ok(())
+}
 
-    let year = args.next().unwrap();
-    let month = args.next().unwrap();
-    let report_file = args.next().unwrap();
+/// Writes the stored transactions matching the query to a CSV file
+fn export_store(query: &Query, file: &Path) -> Result<()> {
+    let store = open_store()?;
+    let entries: Vec<_> = store.query(query).collect();
+    write_csv(
+        entries.iter().copied(),
+        &mut BufWriter::new(File::create(file)?),
+    )?;
     println!(
-        "Generating report for year: {}, month: {}, report file: {}",
-        year, month, report_file,
+        "{}",
+        t!(
+            "{} transactions exported to {}",
+            entries.len(),
+            file.display()
+        )
     );
+    Ok(())
+}
 
-    let year: i32 = year.parse().expect("Invalid year");
-    let month: u32 = month.parse().expect("Invalid month");
+/// Saves the transactions of an import file in the store, replacing what it
+/// had of the source in the months they span. They count for the gains and
+/// the exemption, but no report is made of them.
+fn import(format: ImportFormat, file: PathBuf, source: Option<String>) -> Result<()> {
+    let (source, transactions) = match format {
+        ImportFormat::MercadoBitcoin => {
+            let exchange_source = MercadoBitcoinSource::new(Some(file.clone()));
+            let exchange = exchange_source.exchange();
+            let activity = exchange_source.fetch(NaiveDate::MIN, NaiveDate::MAX)?;
+            let transactions = convert_activity(
+                activity,
+                &exchange,
+                &ProcessOptions::default(),
+                &mut Providers::default(),
+            )?
+            .transactions;
+            (exchange.name, transactions)
+        }
+        // Already valued in BRL, as the report has them; the command line
+        // makes sure they come with a source
+        ImportFormat::Json => (
+            source.unwrap_or_default(),
+            interchange::read(File::open(&file)?)?,
+        ),
+    };
+    let mut store = open_store()?;
+    let Some((first, last)) = store.import(&source, &transactions)? else {
+        println!("{}", t!("No transactions to import in {}", file.display()));
+        return Ok(());
+    };
+    store.save()?;
+    println!(
+        "{}",
+        t!(
+            "{} transactions of {} saved to the store, from {} to {}",
+            transactions.len(),
+            source,
+            first,
+            last
+        )
+    );
+    Ok(())
+}
+
+/// Prints the assets held on a date by the stored transactions, with their
+/// BRL value on that date, and optionally how the Kraken ones compare with
+/// its balances on that date
+fn show_holdings(options: HoldingsOptions) -> Result<()> {
+    let store = open_store()?;
+    let date = options.date;
+    println!("{}", t!("Holdings on {}:", date));
+    let (holdings, total) =
+        store.valued_holdings(&options.query, date, &mut Providers::default())?;
+    for holding in holdings {
+        println!(
+            "  {:<8} {:>24} {:>16} BRL",
+            holding.asset, holding.amount, holding.value
+        );
+    }
+    println!("{}", t!("Total: {} BRL", total));
 
-    let first_day = NaiveDate::from_ymd_opt(year, month, 1).unwrap();
-    let last_day = {
-        // Get the last day of the month by creating the first day of the next month and subtracting one day
-        let next_month = if month == 12 {
-            NaiveDate::from_ymd_opt(year + 1, 1, 1).unwrap()
+    if options.reconcile {
+        let source = KrakenSource::new(options.keyfile);
+        let today = Utc::now().with_timezone(&BRASILIA).date_naive();
+        if date < today {
+            println!(
+                "{}",
+                t!(
+                    "Compared with the balances on Kraken at the end of {}:",
+                    date
+                )
+            );
         } else {
-            NaiveDate::from_ymd_opt(year, month + 1, 1).unwrap()
-        };
-        next_month.pred_opt().unwrap()
+            println!("{}", t!("Compared with the current balances on Kraken:"));
+        }
+        let discrepancies = store.reconcile_holdings(
+            &source.exchange().name,
+            options.query.asset.as_deref(),
+            date,
+            source.balances_on(date, today)?,
+        )?;
+        if discrepancies.is_empty() {
+            println!("{}", t!("  every asset matches"));
+        }
+        for discrepancy in discrepancies {
+            println!(
+                "{}",
+                t!(
+                    "  {}: {} on Kraken, {} by the saved transactions",
+                    discrepancy.asset,
+                    discrepancy.actual,
+                    discrepancy.implied
+                )
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Reads a file with one ID per line. Empty lines and lines starting with
+/// `#` are ignored, and anything after the ID on a line is taken as a comment.
+fn read_id_file(path: &Path) -> Result<Vec<String>> {
+    let content = std::fs::read_to_string(path).map_err(|e| {
+        Error::Config(t!(
+            "failed to read exclusion file {}: {}",
+            path.display(),
+            e
+        ))
+    })?;
+    Ok(content
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .filter_map(|line| line.split_whitespace().next())
+        .map(String::from)
+        .collect())
+}
+
+/// Prints the gain and tax estimate of selling an asset today
+fn simulate_sale(sale: SaleSimulation) -> Result<()> {
+    let today = chrono::Local::now().date_naive();
+    let asset = normalize_asset_or_keep(&sale.asset);
+    let price = match sale.price {
+        Some(price) => price,
+        None => get_exchange_rate_impl(today, asset)?.1,
+    };
+
+    let month_sales = match sale.month_sales {
+        Some(month_sales) => month_sales,
+        None => stored_month_sales(today)?,
     };
 
+    let what = t!("the sale of {} {}", sale.amount, asset);
+    let estimate = tax::estimate_sale(sale.amount, price, sale.cost_basis, month_sales, &what)?;
+    println!(
+        "{}",
+        t!(
+            "Sale of {} {} at {} BRL: {} BRL",
+            sale.amount,
+            asset,
+            price,
+            estimate.sale_value
+        )
+    );
+    println!("{}", t!("  Cost: {} BRL", estimate.cost));
+    println!("{}", t!("  Gain: {} BRL", estimate.gain));
     println!(
-        "Fetching Kraken activity from {} to {}",
-        first_day, last_day
+        "{}",
+        t!("  Sales in the month: {} BRL", estimate.month_sales)
     );
-    let (deposits, withdrawals, trades) =
-        fetch_kraken_activity(first_day, last_day, "kraken_keys.json");
-    println!("Deposits: {:#?}", deposits);
-    println!("Withdrawals: {:#?}", withdrawals);
-    println!("Trades: {:#?}", trades);
+    if estimate.exempt {
+        println!(
+            "{}",
+            t!(
+                "  Exempt: the month's sales don't exceed {} BRL",
+                tax::MONTHLY_EXEMPTION
+            )
+        );
+    } else if estimate.tax.is_zero() {
+        println!("{}", t!("  No tax due, there is no gain"));
+    } else {
+        println!(
+            "{}",
+            t!(
+                "  Tax bracket: {}%",
+                (tax::marginal_rate(estimate.gain) * Decimal::ONE_HUNDRED).normalize()
+            )
+        );
+        println!(
+            "{}",
+            t!(
+                "  DARF (code {}): {} BRL, due by {}",
+                tax::DARF_CODE,
+                estimate.tax,
+                tax::darf_due_date(today)
+            )
+        );
+    }
+    Ok(())
+}
+
+/// Where a report given by a relative path is written: in the output directory
+/// of the selected profile, if it has one, which is created if needed
+fn output_path(report_file: String) -> Result<String> {
+    let output_dir = Config::load()?.profile().and_then(|p| p.output_dir.clone());
+    match output_dir {
+        Some(dir) if Path::new(&report_file).is_relative() => {
+            fs::create_dir_all(&dir)?;
+            Ok(dir.join(report_file).display().to_string())
+        }
+        _ => Ok(report_file),
+    }
+}
 
-    let transactions = process_kraken_data(deposits, withdrawals, trades);
+/// The source of the activity on an exchange, with the key file given by the
+/// user, or the file exported from the exchange for the file importers, read
+/// by the mapping file with `--exchange csv`, or of the wallet exported from a
+/// tracker. `offset` is that of the days of the report.
+fn exchange_source(
+    exchange: ExchangeName,
+    keyfile: Option<PathBuf>,
+    input: Option<PathBuf>,
+    mapping: Option<PathBuf>,
+    wallet: Option<String>,
+    offset: FixedOffset,
+) -> Result<Box<dyn ExchangeSource>> {
+    Ok(match exchange {
+        ExchangeName::Kraken => Box::new(KrakenSource::new(keyfile).with_offset(offset)),
+        ExchangeName::Binance => Box::new(BinanceSource::new(keyfile)),
+        ExchangeName::Bitstamp => Box::new(BitstampSource::new(keyfile)),
+        ExchangeName::Bybit => Box::new(BybitSource::new(keyfile)),
+        ExchangeName::Okx => Box::new(OkxSource::new(keyfile)),
+        ExchangeName::Kucoin => Box::new(KucoinSource::new(keyfile)),
+        ExchangeName::Gemini => Box::new(GeminiSource::new(keyfile)),
+        ExchangeName::Gate => Box::new(GateSource::new(keyfile)),
+        ExchangeName::CryptoComApp => Box::new(CryptoComAppSource::new(input)),
+        ExchangeName::Csv => {
+            let mapping = mapping.ok_or_else(|| {
+                Error::Config(t!(
+                    "--exchange csv needs a mapping file, given with --mapping"
+                ))
+            })?;
+            Box::new(CsvSource::new(input, Mapping::load(&mapping)?))
+        }
+        ExchangeName::Koinly => {
+            let wallet = tracker_wallet(exchange, wallet)?;
+            let info = wallet_exchange(&wallet)?;
+            Box::new(KoinlySource::new(input, wallet, info))
+        }
+        ExchangeName::CoinTracking => {
+            let wallet = tracker_wallet(exchange, wallet)?;
+            let info = wallet_exchange(&wallet)?;
+            Box::new(CoinTrackingSource::new(input, wallet, info))
+        }
+    })
+}
 
-    let mut brl_spent_in_purchases = Decimal::ZERO;
-    for t in &transactions {
-        if let Transaction::Purchase(purchase) = t {
-            brl_spent_in_purchases += purchase.operation_value;
-            if let Some(fee) = purchase.base.operation_fees {
-                brl_spent_in_purchases += fee;
+/// The wallet given with `--wallet`, which the trackers need
+fn tracker_wallet(exchange: ExchangeName, wallet: Option<String>) -> Result<String> {
+    wallet.ok_or_else(|| {
+        Error::Config(t!(
+            "--exchange {} needs the name of the wallet to report, given with --wallet",
+            exchange.name()
+        ))
+    })
+}
+
+/// The exchange of a wallet of a tracker, the one whose name starts the name
+/// of the wallet, or is started by it, ignoring the case: "Binance Spot" and
+/// "binance" are both Binance
+fn wallet_exchange(wallet: &str) -> Result<ExchangeInfo> {
+    let lowercase = wallet.trim().to_lowercase();
+    let known = ExchangeName::all()
+        .filter(|exchange| !exchange.is_tracker() && *exchange != ExchangeName::Csv)
+        .map(|exchange| {
+            exchange_source(exchange, None, None, None, None, BRASILIA).map(|s| s.exchange())
+        })
+        .collect::<Result<Vec<_>>>()?;
+    known
+        .iter()
+        .find(|info| {
+            let name = info.name.to_lowercase();
+            !lowercase.is_empty() && (lowercase.starts_with(&name) || name.starts_with(&lowercase))
+        })
+        .cloned()
+        .ok_or_else(|| {
+            Error::Config(t!(
+                "no exchange known for the wallet {} (expected one of {})",
+                wallet,
+                known.iter().map(|info| &info.name).join(", ")
+            ))
+        })
+}
+
+/// Matches the deposits and withdrawals of the activity to the transfers of
+/// the Bitcoin wallet and Ethereum addresses of the user given, so the
+/// deposits that came from them name them without asking
+fn match_own_wallets(activity: &mut Activity, options: &ReportOptions) -> Result<()> {
+    let mut transfers = Vec::new();
+    if let Some(xpub) = &options.xpub {
+        let descriptor = Descriptor::parse(xpub)?;
+        println!(
+            "{}",
+            t!("Scanning the Bitcoin wallet on the block explorer")
+        );
+        transfers.extend(scan_wallet(&descriptor)?);
+    }
+    if !options.eth_addresses.is_empty() {
+        println!(
+            "{}",
+            t!("Scanning the Ethereum addresses on the block explorer")
+        );
+        transfers.extend(scan_addresses(&options.eth_addresses)?);
+    }
+    for file in &options.wallet_files {
+        println!("{}", t!("Reading the wallet export {}", file.display()));
+        transfers.extend(wallet_file::read(file)?);
+    }
+    let matches = match_wallet(activity, &transfers);
+    for matched in &matches {
+        let transfer = &matched.transfer;
+        let (amount, date) = (transfer.amount, transfer.time.date_naive());
+        let message = if transfer.outgoing && !transfer.fee.is_zero() {
+            t!(
+                "  Deposit of {} {} on {} came from the wallet (transaction {}, network fee {})",
+                amount,
+                transfer.asset,
+                date,
+                transfer.txid,
+                transfer.fee
+            )
+        } else if transfer.outgoing {
+            t!(
+                "  Deposit of {} {} on {} came from the wallet (transaction {})",
+                amount,
+                transfer.asset,
+                date,
+                transfer.txid
+            )
+        } else {
+            t!(
+                "  Withdrawal of {} {} on {} went to the wallet (transaction {})",
+                amount,
+                transfer.asset,
+                date,
+                transfer.txid
+            )
+        };
+        println!("{}", message);
+    }
+    println!(
+        "{}",
+        t!(
+            "{} of the {} transfers of the wallets matched to the exchange",
+            matches.len(),
+            transfers.len()
+        )
+    );
+    Ok(())
+}
+
+/// Looks up the transaction of each crypto withdrawal on a block explorer,
+/// and warns about those that don't pay what the exchange recorded
+fn verify_withdrawals(activity: &Activity, process_options: &ProcessOptions) -> Result<()> {
+    println!("{}", t!("Verifying the withdrawals on the block explorers"));
+    let (mut confirmed, mut unverified) = (0, 0);
+    for withdrawal in &activity.withdrawals {
+        let Some((chain, txid)) = verify::withdrawal_transaction(withdrawal) else {
+            if !is_fiat(&withdrawal.asset) {
+                unverified += 1;
             }
+            continue;
+        };
+        let payments = match chain {
+            Chain::Bitcoin => bitcoin::transaction_payments(&txid)?,
+            Chain::Ethereum => ethereum::transaction_payments(&txid)?,
+        };
+        let (amount, asset, date) = (
+            withdrawal.amount,
+            &withdrawal.asset,
+            withdrawal.time.date_naive(),
+        );
+        match verify::check(withdrawal, payments.as_deref()) {
+            Finding::Confirmed => confirmed += 1,
+            Finding::NotFound => process_options.warn(t!(
+                "the transaction {} of the withdrawal of {} {} on {} is not on the blockchain",
+                txid,
+                amount,
+                asset,
+                date
+            ))?,
+            Finding::Mismatch(message) => process_options.warn(t!(
+                "the withdrawal of {} {} on {} doesn't match its transaction {}: {}",
+                amount,
+                asset,
+                date,
+                txid,
+                message
+            ))?,
+        }
+    }
+    println!(
+        "{}",
+        t!(
+            "{} withdrawals confirmed on the blockchain, {} with no transaction to look up",
+            confirmed,
+            unverified
+        )
+    );
+    Ok(())
+}
+
+/// Generates the report file. Returns its summary, or `None` if no report was
+/// written (on `--dry-run`, or if the user gave up on the review).
+fn run_report(options: ReportOptions) -> Result<Option<RunSummary>> {
+    let period = options.period;
+    let report_file = output_path(options.report_file.clone())?;
+    if !options.dry_run {
+        check_overwrite(&report_file, options.force)?;
+    }
+    println!(
+        "{}",
+        t!(
+            "Generating report for {}, report file: {}",
+            period,
+            report_file,
+        )
+    );
+
+    let (first_day, last_day) = (period.first_day(), period.last_day());
+
+    let mut exclude_ids: BTreeSet<String> = options.exclude_ids.iter().cloned().collect();
+    if let Some(path) = &options.exclude_file {
+        exclude_ids.extend(read_id_file(path)?);
+    }
+    let process_options = ProcessOptions {
+        min_value: options.min_value,
+        record_types: options.record_types.clone(),
+        exclude_ids,
+        debug: options.debug,
+        fail_on_warning: options.fail_on_warning,
+        fold_residuals: options.fold_residuals,
+        utc_offset: options.utc_offset,
+        layout: options.layout,
+        truncation: options.truncation,
+        fee_credits: options.fee_credits,
+        ..Default::default()
+    };
+
+    if options.reconcile && options.exchange != ExchangeName::Kraken {
+        return Err(Error::Config(t!(
+            "--reconcile is only supported for Kraken"
+        )));
+    }
+    let source = exchange_source(
+        options.exchange,
+        options.keyfile.clone(),
+        options.input.clone(),
+        options.mapping.clone(),
+        options.wallet.clone(),
+        process_options.offset(),
+    )?;
+    let exchange = source.exchange();
+    if options.dry_run {
+        dry_run(first_day, last_day, source.as_ref(), &process_options)?;
+        return Ok(None);
+    }
+
+    // A filed report is only generated again on purpose, since the new file
+    // could differ from the one submitted
+    let registry_path = Registry::default_path();
+    let registry = registry_path.clone().map(Registry::open).transpose()?;
+    if let Some(registry) = &registry
+        && let Some(message) = registry.filed_message(&exchange.name, period)
+    {
+        if !options.force {
+            return Err(Error::Config(t!(
+                "{}; use --force to generate it anyway",
+                message
+            )));
+        }
+        process_options.warn(message)?;
+    }
+    if options.stream {
+        stream_report(&options, process_options, source, &report_file)?;
+        return Ok(None);
+    }
+
+    let checkpoint_path = Checkpoint::path_for(Path::new(&report_file));
+    let mut checkpoint = if options.restart {
+        Checkpoint::restart(checkpoint_path, period)?
+    } else {
+        Checkpoint::open(checkpoint_path, period)?
+    };
+
+    let mut activity = match checkpoint.activity() {
+        Some(activity) => activity.clone(),
+        None => {
+            println!(
+                "{}",
+                t!(
+                    "Fetching {} activity from {} to {}",
+                    exchange.name,
+                    first_day,
+                    last_day
+                )
+            );
+            let activity = fetch_days(
+                source.as_ref(),
+                first_day,
+                last_day,
+                process_options.offset(),
+            )?;
+            checkpoint.set_activity(activity.clone())?;
+            activity
+        }
+    };
+    // An exchange can list a deposit again in the next period, e.g. one
+    // pending across the end of the month, which was already reported
+    if let Some(registry) = &registry {
+        for (id, reported) in registry.remove_reported(&exchange.name, period, &mut activity) {
+            println!(
+                "{}",
+                t!("Deposit {} left out, already reported in {}", id, reported)
+            );
+        }
+    }
+    // The deposits that came from another exchange of the user, whose
+    // withdrawals are in the store from its own report or an import
+    if !options.no_store
+        && let Some(path) = Store::default_path()
+    {
+        let store = Store::open(path)?;
+        for i in store.match_deposits(&exchange.name, &mut activity, process_options.offset()) {
+            let deposit = &activity.deposits[i];
+            println!(
+                "{}",
+                t!(
+                    "  Deposit of {} {} on {} came from {}, by its withdrawal in the store",
+                    deposit.amount,
+                    deposit.asset,
+                    process_options.day(deposit.time),
+                    deposit.origin_exchange.as_deref().unwrap_or_default()
+                )
+            );
+        }
+    }
+    if options.xpub.is_some()
+        || !options.eth_addresses.is_empty()
+        || !options.wallet_files.is_empty()
+    {
+        match_own_wallets(&mut activity, &options)?;
+    }
+    if options.verify_withdrawals {
+        verify_withdrawals(&activity, &process_options)?;
+    }
+    // The raw data has amounts, so it is only shown when asked for, and the
+    // addresses are hidden even then
+    if options.debug {
+        let dump = format!(
+            "Deposits: {:#?}\nWithdrawals: {:#?}\nTrades: {:#?}",
+            activity.deposits, activity.withdrawals, activity.trades
+        );
+        println!("{}", redact::redact(&dump));
+    }
+
+    let mut prompter = Prompter::new(options.non_interactive);
+    let processed = process_activity(
+        activity,
+        &exchange,
+        &process_options,
+        &mut prompter,
+        &mut checkpoint,
+    )?;
+    let mut transactions = processed.transactions;
+    if !processed.income.is_empty() {
+        let value = checked::sum(
+            processed.income.iter().map(|income| income.value),
+            &t!("the income of the report"),
+        )?;
+        println!(
+            "{}",
+            t!(
+                "{} income entries worth {} BRL, saved in the store for the gains (the report has no record for them)",
+                processed.income.len(),
+                value
+            )
+        );
+    }
+    if !processed.wallet_transfers.is_empty() {
+        println!(
+            "{}",
+            t!(
+                "{} moves between the wallets of the account were left out, being neither deposits nor withdrawals:",
+                processed.wallet_transfers.len()
+            )
+        );
+        for transfer in &processed.wallet_transfers {
+            println!(
+                "  {} {} {} ({})",
+                transfer.time.format("%Y-%m-%d"),
+                transfer.amount,
+                transfer.asset,
+                transfer.description
+            );
         }
     }
-    println!("Total BRL spent in purchases: {}", brl_spent_in_purchases);
 
-    //println!("============\nTransactions: {:#?}", transactions);
+    if let Some(min_value) = options.min_value
+        && !processed.below_min_value.is_empty()
+    {
+        let total = checked::sum(
+            processed.below_min_value.iter().map(|s| s.brl_value),
+            &t!("the operations below the minimum value"),
+        )?;
+        process_options.warn(t!(
+            "{} operations worth less than {} BRL were left out of the report, totaling {} BRL:",
+            processed.below_min_value.len(),
+            min_value,
+            total.round_dp(2)
+        ))?;
+        let counts = processed
+            .below_min_value
+            .iter()
+            .counts_by(|s| s.transaction.record_type().0);
+        for (record_type, count) in counts.into_iter().sorted() {
+            eprintln!("  {}: {}", record_type, count);
+        }
+    }
+
+    if !processed.residuals.is_empty() {
+        println!("{}", t!("Rounding residuals of the BRL values:"));
+        for residual in &processed.residuals {
+            let folded = if residual.folded {
+                t!(" (added to the last record)")
+            } else {
+                String::new()
+            };
+            println!(
+                "{}",
+                t!(
+                    "  {} {} {}: value {} BRL, fees {} BRL{}",
+                    format!("{}-{:02}", residual.year, residual.month),
+                    residual.record_type,
+                    residual.asset,
+                    residual.value,
+                    residual.fees,
+                    folded
+                )
+            );
+        }
+    }
+
+    if !processed.amount_residuals.is_empty() {
+        println!("{}", t!("Rounding residuals of the crypto amounts:"));
+        for residual in &processed.amount_residuals {
+            println!(
+                "  {} {}: {}",
+                residual.record_type, residual.asset, residual.amount
+            );
+        }
+    }
+
+    let mut notes = Vec::new();
+    if options.review {
+        let Some(reviewed) = review(transactions, &mut prompter)? else {
+            // The checkpoint is kept, so the review can be restarted without
+            // fetching or asking anything again
+            println!("{}", t!("Report not written."));
+            return Ok(None);
+        };
+        if reviewed.excluded > 0 {
+            println!(
+                "{}",
+                t!("{} records excluded during review", reviewed.excluded)
+            );
+        }
+        transactions = reviewed.transactions;
+        notes = reviewed.notes;
+    }
+
+    let unmatched = process_options
+        .exclude_ids
+        .iter()
+        .filter(|id| !processed.excluded_ids.contains(id))
+        .join(", ");
+    if !unmatched.is_empty() {
+        process_options.warn(t!(
+            "no Kraken entry found with the excluded IDs: {}",
+            unmatched
+        ))?;
+    }
+
+    let mut summary = RunSummary::from_transactions(&transactions)?;
+    summary.excluded_ids = processed.excluded_ids;
+    summary.wallet_transfers = processed
+        .wallet_transfers
+        .iter()
+        .filter_map(|transfer| transfer.ids.first().cloned())
+        .unique()
+        .collect();
+    summary.transfer_networks = processed.transfer_networks;
+
+    if options.debug {
+        println!("Transactions: {:#?}", transactions);
+    }
 
+    if let Some(path) = &options.json_file {
+        interchange::write(&transactions, &mut BufWriter::new(File::create(path)?))?;
+    }
     // Get first command line argument as report file name
-    generate_report(transactions, &report_file).expect("Failed to generate report");
+    generate_report(&transactions, &report_file, options.layout)?;
+    write_manifest(Path::new(&report_file))?;
+    if !options.no_archive
+        && let Some(root) = Archive::default_root()
+    {
+        // The report is already written, so failing to archive is only a warning
+        let mut settings = process_options.settings();
+        if options.review {
+            settings.insert("review".to_string(), "true".to_string());
+        }
+        let run = ArchivedRun {
+            period,
+            exchange: &exchange.name,
+            report_file: Path::new(&report_file),
+            transactions: &transactions,
+            summary: &summary,
+            checkpoint: checkpoint.path(),
+            options: settings,
+        };
+        match archive_run(&root, run) {
+            Ok(path) => println!("{}", t!("Report archived in {}", path.display())),
+            Err(e) => process_options.warn(t!(
+                "failed to archive the report in {}: {}",
+                root.display(),
+                e
+            ))?,
+        }
+    }
+    checkpoint.finish()?;
+    if let Some(path) = registry_path {
+        let result = Registry::open(path.clone()).and_then(|mut registry| {
+            registry.record_report(&exchange.name, period, Utc::now(), &transactions);
+            registry.save()
+        });
+        if let Err(e) = result {
+            process_options.warn(t!("failed to save the registry {}: {}", path.display(), e))?;
+        }
+    }
+    if !options.no_store
+        && let Some(path) = Store::default_path()
+    {
+        // The report is already written, so failing to store is only a warning
+        let result = Store::open(path.clone()).and_then(|mut store| {
+            store.save_report(
+                &exchange.name,
+                period,
+                &transactions,
+                &processed.income,
+                &processed.internal_transfers,
+                process_options.offset(),
+            );
+            store.save()?;
+            store.taxable_months(period)
+        });
+        match result {
+            Ok(months) => {
+                for (month, sales) in months {
+                    process_options.warn(t!(
+                        "the sales of {} add up to {} BRL across all sources, more than the exemption of {} BRL: the gains of the month are taxable",
+                        month.format("%Y-%m"),
+                        sales,
+                        tax::MONTHLY_EXEMPTION
+                    ))?;
+                }
+            }
+            Err(e) => process_options.warn(t!(
+                "failed to save the transactions to the store {}: {}",
+                path.display(),
+                e
+            ))?,
+        }
+    }
+    if options.reconcile {
+        reconcile_balances(&KrakenSource::new(options.keyfile), &process_options)?;
+    }
+    println!("{}", summary);
+    if !notes.is_empty() {
+        println!("{}", t!("Notes:"));
+        for note in notes {
+            println!("  {}", note);
+        }
+    }
+    Ok(Some(summary))
+}
+
+/// Prints the sales of each month of a year, across every source in the
+/// store, and whether they are within the monthly exemption
+fn show_sales(year: i32) -> Result<()> {
+    let store = open_store()?;
+    let sales = store.monthly_sales(year)?;
+    println!("{}", t!("Sales in {}:", year));
+    for (month, value) in &sales {
+        let status = if *value > tax::MONTHLY_EXEMPTION {
+            t!("taxable")
+        } else {
+            t!("exempt")
+        };
+        println!("  {}-{:02} {:>16} BRL  {}", year, month, value, status);
+    }
+    let total = checked::sum(sales.values().copied(), &t!("the sales of {}", year))?;
+    println!("{}", t!("Total: {} BRL", total));
+    Ok(())
+}
+
+/// Prints the stored income of a year, of `kind` only if given, with the
+/// total of each month, or writes the total of each asset in each month to
+/// `output`
+fn show_income(year: i32, kind: Option<IncomeKind>, output: Option<&Path>) -> Result<()> {
+    let store = open_store()?;
+    let entries: Vec<_> = store
+        .income(Some(year))
+        .filter(|entry| kind.is_none_or(|kind| entry.income.kind == kind))
+        .collect();
+    if let Some(file) = output {
+        let months = income::monthly_by_asset(entries.iter().map(|entry| &entry.income))?;
+        income::write_monthly_csv(&months, &mut BufWriter::new(File::create(file)?))?;
+        println!(
+            "{}",
+            t!(
+                "Income of {} by month and asset written to {}",
+                year,
+                file.display()
+            )
+        );
+        return Ok(());
+    }
+    println!("{}", t!("Income in {}:", year));
+    for entry in &entries {
+        let income = &entry.income;
+        let kind = match income.kind {
+            IncomeKind::Referral => t!("referral"),
+            IncomeKind::FeeRebate => t!("fee rebate"),
+            IncomeKind::Staking => t!("staking"),
+        };
+        println!(
+            "  {} {:<12} {:<12} {:>24} {:<8} {:>16} BRL",
+            income.date, entry.source, kind, income.amount, income.asset, income.value
+        );
+    }
+    let totals = income::monthly_totals(entries.iter().map(|entry| &entry.income))?;
+    for ((year, month), total) in &totals {
+        println!(
+            "{}",
+            t!(
+                "Total of {}: {} BRL",
+                format!("{}-{:02}", year, month),
+                total
+            )
+        );
+    }
+    let total = checked::sum(totals.values().copied(), &t!("the income of {}", year))?;
+    println!("{}", t!("Total: {} BRL", total));
+    Ok(())
+}
+
+/// Prints the gains and the tax of each month of a year with sales, with the
+/// losses carried forward from the start of the stored history, after saving
+/// the losses from before it if given
+fn show_gains(year: i32, opening_loss: Option<Decimal>) -> Result<()> {
+    let mut store = open_store()?;
+    if let Some(loss) = opening_loss {
+        store.set_opening_loss(loss);
+        store.save()?;
+    }
+    println!(
+        "{}",
+        t!(
+            "Gains in {}, with {} BRL of losses from before the saved transactions:",
+            year,
+            store.opening_loss()
+        )
+    );
+    println!(
+        "  {:<7} {:>16} {:>16} {:>16} {:>14} {:>16}",
+        t!("month"),
+        t!("sales"),
+        t!("gain"),
+        t!("compensated"),
+        t!("tax"),
+        t!("carried loss")
+    );
+    for result in store.monthly_results()? {
+        if result.year != year {
+            continue;
+        }
+        let tax = if result.exempt {
+            t!("exempt")
+        } else {
+            result.tax.to_string()
+        };
+        println!(
+            "  {}-{:02} {:>16} {:>16} {:>16} {:>14} {:>16}",
+            result.year,
+            result.month,
+            result.gains.sales,
+            result.gains.gain,
+            result.compensated,
+            tax,
+            result.carried_loss
+        );
+    }
+    Ok(())
+}
+
+/// The BRL value of the sales saved in the store in the month of `date`, zero
+/// without a data directory
+fn stored_month_sales(date: NaiveDate) -> Result<Decimal> {
+    let Some(path) = Store::default_path() else {
+        return Ok(Decimal::ZERO);
+    };
+    Store::open(path)?.month_sales(date.year(), date.month())
+}
+
+/// Compares the balances on Kraken with the whole history of the account,
+/// warning about each asset that differs
+/// Generates the report batch by batch with [`ReportBuilder::stream`], for
+/// the histories too long to hold in memory. The records are written as they
+/// are converted, so the transactions aren't kept for the store or the
+/// archive, and the deposits already reported in another period are left out
+/// by their IDs.
+fn stream_report(
+    options: &ReportOptions,
+    mut process_options: ProcessOptions,
+    source: Box<dyn ExchangeSource>,
+    report_file: &str,
+) -> Result<()> {
+    let period = options.period;
+    let exchange = source.exchange();
+    let registry_path = Registry::default_path();
+    if let Some(path) = &registry_path {
+        let reported = Registry::open(path.clone())?.reported_deposits(&exchange.name, period);
+        process_options.exclude_ids.extend(reported.into_keys());
+    }
+    let checkpoint_path = Checkpoint::path_for(Path::new(report_file));
+    let mut checkpoint = if options.restart {
+        Checkpoint::restart(checkpoint_path, period)?
+    } else {
+        Checkpoint::open(checkpoint_path, period)?
+    };
+    let mut prompter = Prompter::new(options.non_interactive);
+    println!(
+        "{}",
+        t!(
+            "Fetching and converting {} activity from {} to {} batch by batch",
+            exchange.name,
+            period.first_day(),
+            period.last_day()
+        )
+    );
+    let report = ReportBuilder::new(period)
+        .source(source)
+        .options(process_options.clone())
+        .resolver(interactive_resolver(
+            &process_options,
+            &mut prompter,
+            &mut checkpoint,
+        ))
+        .output_file(report_file)
+        .stream()?;
+    write_manifest(Path::new(report_file))?;
+    checkpoint.finish()?;
+    if let Some(path) = registry_path {
+        let result = Registry::open(path.clone()).and_then(|mut registry| {
+            registry.record_generated(&exchange.name, period, Utc::now(), report.deposit_ids);
+            registry.save()
+        });
+        if let Err(e) = result {
+            process_options.warn(t!("failed to save the registry {}: {}", path.display(), e))?;
+        }
+    }
+
+    if let Some(min_value) = options.min_value
+        && report.below_min_value > 0
+    {
+        process_options.warn(t!(
+            "{} operations worth less than {} BRL were left out of the report, totaling {} BRL",
+            report.below_min_value,
+            min_value,
+            report.below_min_value_total.round_dp(2)
+        ))?;
+    }
+    if !options.no_store || !options.no_archive {
+        println!(
+            "{}",
+            t!(
+                "The transactions of a streamed report aren't kept, so they weren't saved to the store nor archived"
+            )
+        );
+    }
+    if options.reconcile {
+        reconcile_balances(
+            &KrakenSource::new(options.keyfile.clone()),
+            &process_options,
+        )?;
+    }
+    println!("{}", t!("Records written:"));
+    for (record_type, count) in &report.record_counts {
+        println!("  {}: {}", record_type, count);
+    }
+    Ok(())
+}
+
+fn reconcile_balances(source: &KrakenSource, process_options: &ProcessOptions) -> Result<()> {
+    let today = Utc::now().date_naive();
+    println!(
+        "{}",
+        t!(
+            "Reconciling the balances with the activity from {} to {}",
+            kraken::FIRST_DAY,
+            today
+        )
+    );
+    let discrepancies = source.reconcile_history(today)?;
+    if discrepancies.is_empty() {
+        println!("{}", t!("The balances match the history of the account."));
+    }
+    for discrepancy in discrepancies {
+        process_options.warn(t!(
+            "the balance of {} on Kraken is {}, but its history adds up to {}",
+            discrepancy.asset,
+            discrepancy.actual,
+            discrepancy.implied
+        ))?;
+    }
+    Ok(())
+}
+
+/// Fetches the activity and lists the records that would be generated,
+/// without looking up rates or writing any file
+fn dry_run(
+    first_day: NaiveDate,
+    last_day: NaiveDate,
+    source: &dyn ExchangeSource,
+    process_options: &ProcessOptions,
+) -> Result<()> {
+    println!(
+        "{}",
+        t!(
+            "Fetching {} activity from {} to {}",
+            source.exchange().name,
+            first_day,
+            last_day
+        )
+    );
+    let activity = fetch_days(source, first_day, last_day, process_options.offset())?;
+
+    let previews = preview_activity(&activity, process_options)?;
+    println!(
+        "{}",
+        t!(
+            "Dry run: {} records would be generated (BRL values not computed, nothing written):",
+            previews.len()
+        )
+    );
+    for preview in previews {
+        println!(
+            "  {} {} {}",
+            preview.record_type, preview.date, preview.description
+        );
+    }
+    Ok(())
 }
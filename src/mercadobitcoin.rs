@@ -0,0 +1,262 @@
+//! Importer of the operation history of Mercado Bitcoin, the CSV exported in
+//! Extrato → Exportar, with a row per purchase, sale, deposit and withdrawal
+//! of the account.
+//!
+//! Mercado Bitcoin is a Brazilian exchange, which reports the operations of
+//! its users to the RFB itself, so they don't go into the report of the
+//! user. They still count for the average cost of the assets and for the
+//! monthly exemption of sales, so `kraken2rfb import` saves them in the
+//! store, where the gains are computed from.
+//!
+//! The export is in the Brazilian format: fields separated by `;`, decimals
+//! with a comma, and the times in Brasília time.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use rust_decimal::Decimal;
+
+use crate::csv::{Row, Table, row_id};
+use crate::error::{Error, Result};
+use crate::i18n::t;
+use crate::period::BRASILIA;
+use crate::report::transactions::ExchangeInfo;
+use crate::source::{
+    Activity, ExchangeSource, NormalizedDeposit, NormalizedTrade, NormalizedWithdrawal, Side,
+};
+
+/// Columns of the export read by the importer
+const COLUMNS: [&str; 8] = [
+    "Data",
+    "Operação",
+    "Moeda",
+    "Quantidade",
+    "Preço unitário",
+    "Valor total",
+    "Taxa",
+    "Moeda da taxa",
+];
+
+/// Optional column with the ID of the operation on the exchange
+const ID: &str = "ID";
+
+/// Source of the activity in a Mercado Bitcoin export
+pub struct MercadoBitcoinSource {
+    /// The exported CSV file, or `None` if the user gave none
+    file: Option<PathBuf>,
+}
+
+impl MercadoBitcoinSource {
+    pub fn new(file: Option<PathBuf>) -> Self {
+        MercadoBitcoinSource { file }
+    }
+}
+
+impl ExchangeSource for MercadoBitcoinSource {
+    fn exchange(&self) -> ExchangeInfo {
+        ExchangeInfo {
+            name: "Mercado Bitcoin".to_string(),
+            url: "https://www.mercadobitcoin.com.br".to_string(),
+            country: "BR".to_string(),
+        }
+    }
+
+    fn fetch(&self, first_day: NaiveDate, last_day: NaiveDate) -> Result<Activity> {
+        let file = self.file.as_deref().ok_or_else(|| {
+            Error::Config(t!(
+                "give the file exported from {} with --input",
+                "Mercado Bitcoin"
+            ))
+        })?;
+        Ok(normalize(&Table::read(file, ';')?)?.within(first_day, last_day))
+    }
+}
+
+/// Reads a decimal of the export, e.g. "1.234,56", or `None` for an empty
+/// field
+fn decimal(table: &Table, row: &Row, column: &str) -> Result<Option<Decimal>> {
+    let text = table.get(row, column)?;
+    if text.is_empty() {
+        return Ok(None);
+    }
+    let number = if text.contains(',') {
+        text.replace('.', "").replace(',', ".")
+    } else {
+        text.to_string()
+    };
+    number
+        .parse::<Decimal>()
+        .map(|value| Some(value.abs()))
+        .map_err(|e| {
+            table.error(
+                row,
+                t!(
+                    "invalid decimal \"{}\" in field \"{}\": {}",
+                    text,
+                    column,
+                    e
+                ),
+            )
+        })
+}
+
+fn time(table: &Table, row: &Row) -> Result<DateTime<Utc>> {
+    let text = table.get(row, "Data")?;
+    NaiveDateTime::parse_from_str(text, "%d/%m/%Y %H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(text, "%d/%m/%Y %H:%M"))
+        .map(|time| {
+            time.and_local_timezone(BRASILIA)
+                .unwrap()
+                .with_timezone(&Utc)
+        })
+        .map_err(|e| table.error(row, t!("invalid time \"{}\" in {}: {}", text, "Data", e)))
+}
+
+/// Converts the rows of a Mercado Bitcoin export to the typed entries of
+/// [`Activity`]. Every trade is of a crypto asset for reais; its fee counts
+/// if it is in either, valued at the price of the trade if it is in the
+/// asset.
+fn normalize(table: &Table) -> Result<Activity> {
+    table.require_columns(&COLUMNS)?;
+    let mut seen = HashMap::new();
+    let mut activity = Activity::default();
+    for row in &table.rows {
+        let mut ids = vec![row_id("mercadobitcoin", row, &mut seen)];
+        if table.has_column(ID) {
+            ids.extend(
+                Some(table.get(row, ID)?)
+                    .filter(|id| !id.is_empty())
+                    .map(String::from),
+            );
+        }
+        let time = time(table, row)?;
+        let asset = table.get(row, "Moeda")?.to_uppercase();
+        let kind = table.get(row, "Operação")?;
+        let required = |column: &str| -> Result<Decimal> {
+            decimal(table, row, column)?
+                .ok_or_else(|| table.error(row, t!("missing field \"{}\"", column)))
+        };
+        let amount = required("Quantidade")?;
+        let fee = decimal(table, row, "Taxa")?.unwrap_or_default();
+        let fee_currency = table.get(row, "Moeda da taxa")?.to_uppercase();
+        match kind {
+            "Compra" | "Venda" => {
+                let cost = required("Valor total")?;
+                let price = match decimal(table, row, "Preço unitário")? {
+                    Some(price) => price,
+                    None => cost.checked_div(amount).ok_or_else(|| {
+                        table.error(row, t!("unexpected {} entry: {}", "Mercado Bitcoin", kind))
+                    })?,
+                };
+                let fee = match fee_currency.as_str() {
+                    "BRL" => fee,
+                    currency if currency == asset => fee * price,
+                    _ => Decimal::ZERO,
+                };
+                activity.trades.push(NormalizedTrade {
+                    base: asset,
+                    quote: "BRL".to_string(),
+                    side: if kind == "Compra" {
+                        Side::Buy
+                    } else {
+                        Side::Sell
+                    },
+                    volume: amount,
+                    cost,
+                    fee,
+                    credit_fee_usd: Decimal::ZERO,
+                    price,
+                    time,
+                    ids,
+                });
+            }
+            "Depósito" => activity.deposits.push(NormalizedDeposit {
+                fee: if fee_currency == asset {
+                    fee
+                } else {
+                    Decimal::ZERO
+                },
+                asset,
+                amount,
+                time,
+                ids,
+                origin_exchange: None,
+                origin_wallet: None,
+                network: None,
+            }),
+            "Saque" => activity.withdrawals.push(NormalizedWithdrawal {
+                fee: if fee_currency == asset {
+                    fee
+                } else {
+                    Decimal::ZERO
+                },
+                asset,
+                amount,
+                time,
+                ids,
+                destination: None,
+                network: None,
+            }),
+            _ => {
+                return Err(
+                    table.error(row, t!("unexpected {} entry: {}", "Mercado Bitcoin", kind))
+                );
+            }
+        }
+    }
+    activity.deposits.sort_by_key(|d| d.time);
+    activity.withdrawals.sort_by_key(|w| w.time);
+    activity.trades.sort_by_key(|t| t.time);
+    Ok(activity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_normalize() {
+        let text = "Data;Operação;Moeda;Quantidade;Preço unitário;Valor total;Taxa;Moeda da taxa;ID\n\
+            01/03/2024 09:00:00;Depósito;BRL;10.000,00;;;;;d1\n\
+            01/03/2024 09:30:00;Compra;BTC;0,02;300.000,00;6.000,00;0,0001;BTC;o1\n\
+            02/03/2024 10:00;Venda;BTC;0,01;310.000,00;3.100,00;15,50;BRL;o2\n\
+            03/03/2024 12:00:00;Saque;BTC;0,005;;;0,0002;BTC;w1\n";
+        let activity = normalize(&Table::parse(text, ';', "extrato.csv").unwrap()).unwrap();
+
+        let [buy, sell] = activity.trades.as_slice() else {
+            panic!("expected 2 trades, got {:?}", activity.trades);
+        };
+        assert_eq!(
+            (buy.side, buy.base.as_str(), buy.quote.as_str()),
+            (Side::Buy, "BTC", "BRL")
+        );
+        // A fee in the asset, at the price of the trade
+        assert_eq!(
+            (buy.volume, buy.cost, buy.fee),
+            (dec!(0.02), dec!(6000), dec!(30))
+        );
+        assert_eq!(buy.ids[1], "o1");
+        // The times are in Brasília time
+        assert_eq!(
+            buy.time,
+            "2024-03-01T12:30:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+        assert_eq!(
+            (sell.side, sell.cost, sell.fee),
+            (Side::Sell, dec!(3100), dec!(15.5))
+        );
+        assert_eq!(activity.deposits[0].amount, dec!(10000));
+        let withdrawal = &activity.withdrawals[0];
+        assert_eq!(
+            (withdrawal.amount, withdrawal.fee),
+            (dec!(0.005), dec!(0.0002))
+        );
+
+        let text = "Data;Operação;Moeda;Quantidade;Preço unitário;Valor total;Taxa;Moeda da taxa\n\
+            01/03/2024 09:00:00;Staking;ETH;0,1;;;;\n";
+        let error = normalize(&Table::parse(text, ';', "extrato.csv").unwrap()).unwrap_err();
+        assert!(error.to_string().contains("line 2"));
+    }
+}
@@ -0,0 +1,472 @@
+//! OKX API client, v5: the spot fills, the bills of the conversions, and the
+//! deposits and withdrawals of the account.
+//!
+//! The lists are newest first, in pages of at most 100 entries; each page is
+//! asked for with `after` set to the pagination ID of the last entry of the
+//! previous one, the bill ID for fills and bills or the time for transfers.
+//! OKX keeps the fills and bills of the last 3 months only.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use chrono::{NaiveDate, Utc};
+use hmac::{Hmac, Mac};
+use itertools::Itertools;
+use reqwest::blocking::Client;
+use rust_decimal::Decimal;
+use serde_json::Value;
+use sha2::Sha256;
+
+use crate::api_keys::{ApiKeys, KeyLocation};
+use crate::endpoints;
+use crate::error::{Error, Result};
+use crate::fields::{
+    self, decimal_field, id_field, int_field, millis_field, optional_str_field, str_field,
+};
+use crate::http;
+use crate::i18n::t;
+use crate::report::transactions::ExchangeInfo;
+use crate::source::{
+    Activity, ExchangeSource, NormalizedDeposit, NormalizedTrade, NormalizedWithdrawal, Side,
+    is_fiat, millis_range,
+};
+
+/// Where the OKX API keys are looked for: `okx_keys.json`, with the
+/// `passphrase` of the key, or the `KRAKEN2RFB_OKX_KEYFILE`,
+/// `KRAKEN2RFB_OKX_API_KEY`, `KRAKEN2RFB_OKX_API_SECRET` and
+/// `KRAKEN2RFB_OKX_API_PASSPHRASE` environment variables
+pub const KEYS: KeyLocation = KeyLocation {
+    exchange: "OKX",
+    file_name: "okx_keys.json",
+    env_prefix: "OKX_",
+    profile_keyfile: false,
+};
+
+/// Most entries a page has
+const PAGE_LIMIT: usize = 100;
+
+/// Types of the bills of a conversion between two currencies: convert, easy
+/// convert and simple trade
+const CONVERSION_BILL_TYPES: [&str; 3] = ["27", "28", "30"];
+
+/// Error codes by which OKX rejects the API keys or the signature
+const AUTH_ERROR_CODES: [&str; 10] = [
+    "50100", "50101", "50102", "50103", "50104", "50105", "50110", "50111", "50113", "50120",
+];
+
+/// Raw OKX activity for a period
+#[derive(Debug, Clone, Default)]
+pub struct OkxActivity {
+    pub deposits: Vec<Value>,
+    pub withdrawals: Vec<Value>,
+    /// The spot fills
+    pub fills: Vec<Value>,
+    /// The bills of the conversions, two for each: one taking the currency
+    /// given and one crediting the currency received
+    pub bills: Vec<Value>,
+}
+
+/// Signature of a request: the HMAC-SHA256, with the API secret, of the
+/// timestamp, the method, the path with the query and the body, in base64
+fn signature(secret: &str, message: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size");
+    mac.update(message.as_bytes());
+    BASE64.encode(mac.finalize().into_bytes())
+}
+
+fn okx_error(endpoint: &str, body: &Value) -> Error {
+    let code = body["code"].as_str();
+    Error::Exchange {
+        exchange: "OKX".to_string(),
+        endpoint: endpoint.to_string(),
+        message: match (code, body["msg"].as_str()) {
+            (Some(code), Some(msg)) if !msg.is_empty() => format!("{} ({})", msg, code),
+            _ => body.to_string(),
+        },
+        auth: code.is_some_and(|code| AUTH_ERROR_CODES.contains(&code)),
+    }
+}
+
+/// The signed API of an account
+struct Api {
+    client: &'static Client,
+    keys: ApiKeys,
+    passphrase: String,
+}
+
+impl Api {
+    fn new(keyfile: &Path) -> Result<Self> {
+        let (keys, passphrase) = KEYS.load_with_passphrase(keyfile)?;
+        Ok(Api {
+            client: http::client()?,
+            keys,
+            passphrase,
+        })
+    }
+
+    /// Calls an endpoint, e.g. "/api/v5/trade/fills-history", and returns its
+    /// `data`
+    fn get(&self, path: &str, params: &[(&str, String)]) -> Result<Vec<Value>> {
+        let query = serde_urlencoded::to_string(params).expect("the params are strings");
+        let request_path = format!("{}?{}", path, query);
+        let timestamp = Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+        let message = format!("{}GET{}", timestamp, request_path);
+        let request = self
+            .client
+            .get(format!("{}{}", endpoints::get().okx, request_path))
+            .header("OK-ACCESS-KEY", &self.keys.key)
+            .header("OK-ACCESS-SIGN", signature(&self.keys.secret, &message))
+            .header("OK-ACCESS-TIMESTAMP", timestamp)
+            .header("OK-ACCESS-PASSPHRASE", &self.passphrase);
+        let response = http::send(request)?;
+        let success = response.status().is_success();
+        let body: Value = response
+            .json()
+            .map_err(|e| Error::Network(t!("invalid JSON from OKX {}: {}", path, e)))?;
+        if !success || body["code"] != "0" {
+            return Err(okx_error(path, &body));
+        }
+        Ok(fields::list(&body["data"], "OKX", path)?.clone())
+    }
+
+    /// Pages through a list from `start` to `end`, in UNIX milliseconds, by
+    /// the `cursor` field of its entries
+    fn paged(
+        &self,
+        path: &str,
+        params: &[(&str, String)],
+        (start, end): (i64, i64),
+        cursor: &str,
+    ) -> Result<Vec<Value>> {
+        let mut entries = Vec::new();
+        let mut after = None;
+        loop {
+            let mut page_params = params.to_vec();
+            page_params.extend([
+                ("begin", start.to_string()),
+                ("end", end.to_string()),
+                ("limit", PAGE_LIMIT.to_string()),
+            ]);
+            page_params.extend(after.map(|after| ("after", after)));
+            let page = self.get(path, &page_params)?;
+            let next = page.last().map(|last| id_field(last, cursor)).transpose()?;
+            // The transfers are listed by time only, so the range is checked
+            // here too
+            entries.extend(
+                page.iter()
+                    .filter(|e| int_field(e, "ts").is_ok_and(|ts| start <= ts && ts <= end))
+                    .cloned(),
+            );
+            match next {
+                Some(next) if page.len() == PAGE_LIMIT => after = Some(next),
+                _ => break,
+            }
+        }
+        entries.reverse();
+        Ok(entries)
+    }
+}
+
+pub fn fetch_okx_activity(
+    initial: NaiveDate,
+    final_: NaiveDate,
+    keyfile: &Path,
+) -> Result<OkxActivity> {
+    let api = Api::new(keyfile)?;
+    let range = millis_range(initial, final_);
+
+    let deposits = api.paged("/api/v5/asset/deposit-history", &[], range, "ts")?;
+    let withdrawals = api.paged("/api/v5/asset/withdrawal-history", &[], range, "ts")?;
+    let fills = api.paged(
+        "/api/v5/trade/fills-history",
+        &[("instType", "SPOT".to_string())],
+        range,
+        "billId",
+    )?;
+    let mut bills = Vec::new();
+    for kind in CONVERSION_BILL_TYPES {
+        bills.extend(api.paged(
+            "/api/v5/account/bills-archive",
+            &[("type", kind.to_string())],
+            range,
+            "billId",
+        )?);
+    }
+
+    Ok(OkxActivity {
+        deposits,
+        withdrawals,
+        fills,
+        bills,
+    })
+}
+
+/// The OKX account the API keys give access to
+pub struct OkxSource {
+    /// Key file given by the user, or `None` to look for it only when fetching
+    keyfile: Option<PathBuf>,
+}
+
+impl OkxSource {
+    pub fn new(keyfile: Option<PathBuf>) -> Self {
+        OkxSource { keyfile }
+    }
+}
+
+impl ExchangeSource for OkxSource {
+    fn exchange(&self) -> ExchangeInfo {
+        ExchangeInfo {
+            name: "OKX".to_string(),
+            url: "https://www.okx.com".to_string(),
+            country: "SC".to_string(),
+        }
+    }
+
+    fn fetch(&self, first_day: NaiveDate, last_day: NaiveDate) -> Result<Activity> {
+        let keyfile = KEYS.find_keyfile(self.keyfile.as_deref())?;
+        normalize(fetch_okx_activity(first_day, last_day, &keyfile)?)
+    }
+}
+
+/// A fee as OKX sends it, negative when charged and positive for a rebate,
+/// as the amount charged
+fn charged(fee: Decimal) -> Decimal {
+    (-fee).max(Decimal::ZERO)
+}
+
+/// Converts the raw OKX activity to the typed entries of [`Activity`].
+///
+/// A fill is of an instrument like "BTC-USDT", base and quote; its fee is in
+/// `feeCcy`, valued at the price of the fill if it is the base. The two bills
+/// of a conversion, by their order ID, are a trade like a convert on
+/// Binance: the sale of a crypto asset for fiat, or else the buy of the asset
+/// received.
+fn normalize(activity: OkxActivity) -> Result<Activity> {
+    let transfer_ids = |entry: &Value, own: &str| -> Vec<String> {
+        [own, "txId"]
+            .iter()
+            .filter_map(|field| id_field(entry, field).ok())
+            .collect()
+    };
+    // Transfers are completed with state 2
+    let deposits = activity.deposits.iter().filter(|d| d["state"] == "2").map(
+        |deposit| -> Result<NormalizedDeposit> {
+            Ok(NormalizedDeposit {
+                asset: str_field(deposit, "ccy")?.to_string(),
+                amount: decimal_field(deposit, "amt")?,
+                fee: Decimal::ZERO,
+                time: millis_field(deposit, "ts")?,
+                ids: transfer_ids(deposit, "depId"),
+                origin_exchange: None,
+                origin_wallet: None,
+                network: None,
+            })
+        },
+    );
+    let withdrawals = activity
+        .withdrawals
+        .iter()
+        .filter(|w| w["state"] == "2")
+        .map(|withdrawal| -> Result<NormalizedWithdrawal> {
+            Ok(NormalizedWithdrawal {
+                asset: str_field(withdrawal, "ccy")?.to_string(),
+                amount: decimal_field(withdrawal, "amt")?,
+                fee: decimal_field(withdrawal, "fee")?.abs(),
+                time: millis_field(withdrawal, "ts")?,
+                ids: transfer_ids(withdrawal, "wdId"),
+                destination: optional_str_field(withdrawal, "to"),
+                network: None,
+            })
+        });
+    let fill = |fill: &Value| -> Result<NormalizedTrade> {
+        let unexpected = || Error::Validation(t!("unexpected OKX entry: {}", fill));
+        let (base, quote) = str_field(fill, "instId")?
+            .split_once('-')
+            .ok_or_else(unexpected)?;
+        let side = match str_field(fill, "side")? {
+            "buy" => Side::Buy,
+            "sell" => Side::Sell,
+            _ => return Err(unexpected()),
+        };
+        let price = decimal_field(fill, "fillPx")?;
+        let volume = decimal_field(fill, "fillSz")?;
+        let fee = charged(decimal_field(fill, "fee")?);
+        let fee = match str_field(fill, "feeCcy")? {
+            _ if fee.is_zero() => Decimal::ZERO,
+            currency if currency == quote => fee,
+            currency if currency == base => fee * price,
+            _ => return Err(unexpected()),
+        };
+        Ok(NormalizedTrade {
+            base: base.to_string(),
+            quote: quote.to_string(),
+            side,
+            volume,
+            cost: volume * price,
+            fee,
+            credit_fee_usd: Decimal::ZERO,
+            price,
+            time: millis_field(fill, "ts")?,
+            ids: vec![id_field(fill, "tradeId")?, id_field(fill, "ordId")?],
+        })
+    };
+    let conversion = |bills: &[&Value]| -> Result<NormalizedTrade> {
+        let unexpected = || {
+            Error::Validation(t!(
+                "unexpected OKX entry: {}",
+                Value::from_iter(bills.iter().copied().cloned())
+            ))
+        };
+        let change = |bill: &&Value| decimal_field(bill, "balChg");
+        let (Some(given), Some(received)) = (
+            bills
+                .iter()
+                .find(|b| change(b).is_ok_and(|c| c.is_sign_negative())),
+            bills
+                .iter()
+                .find(|b| change(b).is_ok_and(|c| c.is_sign_positive())),
+        ) else {
+            return Err(unexpected());
+        };
+        if bills.len() != 2 {
+            return Err(unexpected());
+        }
+        let (from, to) = (str_field(given, "ccy")?, str_field(received, "ccy")?);
+        let (from_amount, to_amount) = (change(given)?.abs(), change(received)?);
+        let (side, base, quote, volume, cost) = if is_fiat(to) && !is_fiat(from) {
+            (Side::Sell, from, to, from_amount, to_amount)
+        } else {
+            (Side::Buy, to, from, to_amount, from_amount)
+        };
+        Ok(NormalizedTrade {
+            base: base.to_string(),
+            quote: quote.to_string(),
+            side,
+            volume,
+            cost,
+            fee: Decimal::ZERO,
+            credit_fee_usd: Decimal::ZERO,
+            price: cost.checked_div(volume).ok_or_else(unexpected)?,
+            time: millis_field(received, "ts")?,
+            ids: vec![format!("convert:{}", id_field(received, "ordId")?)],
+        })
+    };
+
+    let mut trades: Vec<NormalizedTrade> = activity.fills.iter().map(fill).try_collect()?;
+    let mut conversions: BTreeMap<String, Vec<&Value>> = BTreeMap::new();
+    for bill in &activity.bills {
+        conversions
+            .entry(id_field(bill, "ordId")?)
+            .or_default()
+            .push(bill);
+    }
+    for bills in conversions.values() {
+        trades.push(conversion(bills)?);
+    }
+    trades.sort_by_key(|t| t.time);
+    let mut deposits: Vec<NormalizedDeposit> = deposits.try_collect()?;
+    deposits.sort_by_key(|d| d.time);
+    let mut withdrawals: Vec<NormalizedWithdrawal> = withdrawals.try_collect()?;
+    withdrawals.sort_by_key(|w| w.time);
+    Ok(Activity {
+        deposits,
+        withdrawals,
+        trades,
+        income: Vec::new(),
+        wallet_transfers: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    use serde_json::json;
+
+    #[test]
+    fn test_signature() {
+        assert_eq!(
+            signature(
+                "secret",
+                "2020-12-08T09:08:57.715ZGET/api/v5/trade/fills-history?instType=SPOT&limit=100"
+            ),
+            "sHYs23QbDz0hTLTQfz5Of9PVcT+nSULLhimHnw3ltwM="
+        );
+    }
+
+    #[test]
+    fn test_normalize() {
+        let activity = normalize(OkxActivity {
+            deposits: vec![
+                json!({"depId": "d1", "ccy": "USDT", "amt": "100", "txId": "0xa1", "state": "2", "ts": "1709294400000"}),
+                json!({"depId": "d2", "ccy": "USDT", "amt": "50", "txId": "", "state": "0", "ts": "1709294400000"}),
+            ],
+            withdrawals: vec![
+                json!({"wdId": "w1", "ccy": "BTC", "amt": "0.1", "fee": "0.0002", "txId": "0xb1", "state": "2", "ts": "1709380800000"}),
+            ],
+            fills: vec![
+                json!({"instId": "BTC-USDT", "tradeId": "t1", "ordId": "o1", "billId": "b1", "fillPx": "60000", "fillSz": "0.01", "side": "buy", "fee": "-0.00001", "feeCcy": "BTC", "ts": "1709467200000"}),
+                json!({"instId": "ETH-BTC", "tradeId": "t2", "ordId": "o2", "billId": "b2", "fillPx": "0.05", "fillSz": "2", "side": "sell", "fee": "0.00001", "feeCcy": "BTC", "ts": "1709553600000"}),
+            ],
+            bills: vec![
+                json!({"billId": "b3", "ordId": "c1", "ccy": "BRL", "balChg": "-1000", "type": "30", "ts": "1709640000000"}),
+                json!({"billId": "b4", "ordId": "c1", "ccy": "USDT", "balChg": "200", "type": "30", "ts": "1709640000000"}),
+            ],
+        })
+        .unwrap();
+
+        // The deposit not yet completed is left out
+        assert_eq!(activity.deposits.len(), 1);
+        assert_eq!(activity.deposits[0].ids, ["d1", "0xa1"]);
+        let withdrawal = &activity.withdrawals[0];
+        assert_eq!(
+            (withdrawal.amount, withdrawal.fee),
+            (dec!(0.1), dec!(0.0002))
+        );
+
+        let [buy, sell, convert] = activity.trades.as_slice() else {
+            panic!("expected 3 trades, got {:?}", activity.trades);
+        };
+        // A fee in the base currency is valued at the price of the fill
+        assert_eq!(
+            (buy.side, buy.cost, buy.fee),
+            (Side::Buy, dec!(600), dec!(0.6))
+        );
+        assert_eq!(buy.ids, ["t1", "o1"]);
+        // A rebate isn't a fee
+        assert_eq!((sell.side, sell.fee), (Side::Sell, Decimal::ZERO));
+        assert_eq!((sell.base.as_str(), sell.quote.as_str()), ("ETH", "BTC"));
+        assert_eq!(
+            (convert.side, convert.base.as_str(), convert.quote.as_str()),
+            (Side::Buy, "USDT", "BRL")
+        );
+        assert_eq!((convert.volume, convert.price), (dec!(200), dec!(5)));
+        assert_eq!(convert.ids, ["convert:c1"]);
+
+        // A conversion missing one of its bills
+        let error = normalize(OkxActivity {
+            bills: vec![
+                json!({"billId": "b3", "ordId": "c1", "ccy": "BRL", "balChg": "-1000", "type": "30", "ts": "1709640000000"}),
+            ],
+            ..OkxActivity::default()
+        });
+        assert!(error.is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = okx_error(
+            "/api/v5/trade/fills-history",
+            &json!({"code": "50113", "msg": "Invalid Sign", "data": []}),
+        );
+        assert!(error.is_auth_error());
+        assert!(error.to_string().contains("(50113)"));
+        let error = okx_error(
+            "/api/v5/trade/fills-history",
+            &json!({"code": "51000", "msg": "Parameter instType error", "data": []}),
+        );
+        assert!(!error.is_auth_error());
+    }
+}
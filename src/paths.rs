@@ -0,0 +1,102 @@
+//! Where the user's files are kept. With a profile selected (see
+//! [`init_profile`]), the keys and the data of each declarant are kept apart,
+//! under `profiles/<name>` in the configuration and data directories.
+
+use std::env;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use crate::error::{Error, Result};
+use crate::i18n::t;
+
+static PROFILE: OnceLock<Option<String>> = OnceLock::new();
+
+/// Selects the profile given with `--profile` or `KRAKEN2RFB_PROFILE`, if any.
+/// Only the first call has any effect.
+pub fn init_profile(arg: Option<&str>) -> Result<()> {
+    let name = arg.map(String::from).or_else(|| crate::env::var("PROFILE"));
+    if let Some(name) = &name
+        && !is_valid_profile(name)
+    {
+        return Err(Error::Config(t!(
+            "invalid profile name: {} (use only letters, digits, '-' and '_')",
+            name
+        )));
+    }
+    let _ = PROFILE.set(name);
+    Ok(())
+}
+
+/// The name of a profile is part of paths, so it is kept to a safe set
+pub fn is_valid_profile(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// The selected profile, if any
+pub fn profile() -> Option<&'static str> {
+    PROFILE.get().and_then(|name| name.as_deref())
+}
+
+/// Where the files of the selected profile are kept under `dir`: `dir` itself
+/// without a profile
+fn profile_dir(dir: PathBuf) -> PathBuf {
+    match profile() {
+        Some(name) => dir.join("profiles").join(name),
+        None => dir,
+    }
+}
+
+/// Directory of the user's kraken2rfb configuration files: `$KRAKEN2RFB_CONFIG_DIR`
+/// if set, otherwise `$XDG_CONFIG_HOME/kraken2rfb`, or `~/.config/kraken2rfb` if
+/// XDG_CONFIG_HOME is not set.
+pub fn config_dir() -> Option<PathBuf> {
+    if let Some(dir) = crate::env::var("CONFIG_DIR") {
+        return Some(PathBuf::from(dir));
+    }
+    let base = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("kraken2rfb"))
+}
+
+/// Directory of the configuration files of the selected profile, like its
+/// API keys: [`config_dir`] itself without a profile. The configuration file
+/// is shared by all profiles.
+pub fn profile_config_dir() -> Option<PathBuf> {
+    config_dir().map(profile_dir)
+}
+
+/// Directory of the data kraken2rfb keeps between runs, like the transaction
+/// store: `$KRAKEN2RFB_DATA_DIR` if set, otherwise `$XDG_DATA_HOME/kraken2rfb`,
+/// or `~/.local/share/kraken2rfb` if XDG_DATA_HOME is not set. Each profile
+/// has its own, under `profiles/<name>`.
+pub fn data_dir() -> Option<PathBuf> {
+    let base = match crate::env::var("DATA_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => match env::var_os("XDG_DATA_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".local/share"),
+        }
+        .join("kraken2rfb"),
+    };
+    Some(profile_dir(base))
+}
+
+/// Directory of the files kraken2rfb can always download again, like the
+/// cached HTTP responses: `$KRAKEN2RFB_CACHE_DIR` if set, otherwise
+/// `$XDG_CACHE_HOME/kraken2rfb`, or `~/.cache/kraken2rfb` if XDG_CACHE_HOME is
+/// not set. It is shared by all profiles.
+pub fn cache_dir() -> Option<PathBuf> {
+    if let Some(dir) = crate::env::var("CACHE_DIR") {
+        return Some(PathBuf::from(dir));
+    }
+    let base = match env::var_os("XDG_CACHE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+    };
+    Some(base.join("kraken2rfb"))
+}
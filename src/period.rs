@@ -0,0 +1,166 @@
+//! The period of a report: a month or a quarter.
+
+use std::fmt;
+
+use chrono::{Datelike, FixedOffset, Months, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::i18n::t;
+
+/// Brasília time, 3 hours behind UTC, the time of the days of the report.
+/// Daylight saving time was abolished in 2019, and isn't applied before.
+pub const BRASILIA: FixedOffset = FixedOffset::west_opt(3 * 60 * 60).unwrap();
+
+/// Months covered by a report: a single month, or a quarter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Period {
+    pub year: i32,
+    /// First month of the period
+    pub month: u32,
+    /// Number of months, 1 or 3
+    #[serde(default = "one")]
+    pub months: u32,
+}
+
+fn one() -> u32 {
+    1
+}
+
+impl Period {
+    /// A single month, failing if it doesn't exist
+    pub fn month(year: i32, month: u32) -> Result<Self> {
+        let period = Period {
+            year,
+            month,
+            months: 1,
+        };
+        period.checked()
+    }
+
+    /// A quarter, numbered from 1 to 4
+    pub fn quarter(year: i32, quarter: u32) -> Result<Self> {
+        if !(1..=4).contains(&quarter) {
+            return Err(Error::Config(t!("invalid quarter: {}", quarter)));
+        }
+        let period = Period {
+            year,
+            month: quarter * 3 - 2,
+            months: 3,
+        };
+        period.checked()
+    }
+
+    fn checked(self) -> Result<Self> {
+        let end = self
+            .first_day_opt()
+            .and_then(|day| day.checked_add_months(Months::new(self.months)));
+        if (1..=12).contains(&self.month) && end.is_some() {
+            Ok(self)
+        } else {
+            Err(Error::Config(t!(
+                "invalid month: {}/{}",
+                self.month,
+                self.year
+            )))
+        }
+    }
+
+    /// Parses a period given as `last-month`, `this-month`, `YYYY-MM` or
+    /// `YYYY-Qn`, with the relative ones computed from `today`
+    pub fn parse(text: &str, today: NaiveDate) -> Result<Self> {
+        let invalid = || Error::Config(t!("invalid period: {}", text));
+        match text {
+            "this-month" => Period::month(today.year(), today.month()),
+            "last-month" => {
+                let day = today - Months::new(1);
+                Period::month(day.year(), day.month())
+            }
+            _ => {
+                let (year, rest) = text.split_once('-').ok_or_else(invalid)?;
+                let year = year.parse().map_err(|_| invalid())?;
+                match rest.strip_prefix(['Q', 'q']) {
+                    Some(quarter) => Period::quarter(year, quarter.parse().map_err(|_| invalid())?),
+                    None => Period::month(year, rest.parse().map_err(|_| invalid())?),
+                }
+            }
+        }
+    }
+
+    fn first_day_opt(&self) -> Option<NaiveDate> {
+        NaiveDate::from_ymd_opt(self.year, self.month, 1)
+    }
+
+    pub fn first_day(&self) -> NaiveDate {
+        self.first_day_opt().expect("period was validated")
+    }
+
+    pub fn last_day(&self) -> NaiveDate {
+        (self.first_day() + Months::new(self.months))
+            .pred_opt()
+            .expect("period was validated")
+    }
+}
+
+impl fmt::Display for Period {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.months == 3 {
+            write!(f, "{}-Q{}", self.year, self.month.div_ceil(3))
+        } else {
+            write!(f, "{}-{:02}", self.year, self.month)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let today = date(2025, 1, 31);
+        assert_eq!(
+            Period::parse("last-month", today).unwrap(),
+            Period::month(2024, 12).unwrap()
+        );
+        assert_eq!(
+            Period::parse("this-month", today).unwrap(),
+            Period::month(2025, 1).unwrap()
+        );
+        assert_eq!(
+            Period::parse("2024-03", today).unwrap(),
+            Period::month(2024, 3).unwrap()
+        );
+
+        let quarter = Period::parse("2024-Q1", today).unwrap();
+        assert_eq!(
+            (quarter.first_day(), quarter.last_day()),
+            (date(2024, 1, 1), date(2024, 3, 31))
+        );
+        assert_eq!(quarter.to_string(), "2024-Q1");
+        assert_eq!(
+            Period::parse("2024-q4", today).unwrap().last_day(),
+            date(2024, 12, 31)
+        );
+
+        for invalid in ["2024-13", "2024-Q5", "2024", "March", "last month"] {
+            assert!(Period::parse(invalid, today).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_last_day() {
+        assert_eq!(
+            Period::month(2024, 2).unwrap().last_day(),
+            date(2024, 2, 29)
+        );
+        assert_eq!(
+            Period::month(2023, 12).unwrap().last_day(),
+            date(2023, 12, 31)
+        );
+    }
+}
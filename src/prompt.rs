@@ -0,0 +1,91 @@
+//! Questions to the user, when running on a terminal.
+
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use rust_decimal::Decimal;
+
+use crate::error::Result;
+use crate::i18n::t;
+
+/// Asks the user for information that can't be derived from the fetched data.
+///
+/// When prompting is disabled (`--non-interactive`, or stdin is not a
+/// terminal), every question is answered with `None`, and the caller falls
+/// back to its non-interactive behavior.
+pub struct Prompter {
+    interactive: bool,
+}
+
+impl Prompter {
+    pub fn new(non_interactive: bool) -> Self {
+        Prompter {
+            interactive: !non_interactive && io::stdin().is_terminal(),
+        }
+    }
+
+    pub fn is_interactive(&self) -> bool {
+        self.interactive
+    }
+
+    /// Asks a free-form question. An empty answer is returned as `None`.
+    pub fn ask(&mut self, question: &str) -> Result<Option<String>> {
+        if !self.interactive {
+            return Ok(None);
+        }
+
+        print!("{} ", question);
+        io::stdout().flush()?;
+
+        let mut answer = String::new();
+        if io::stdin().lock().read_line(&mut answer)? == 0 {
+            // stdin was closed, stop asking
+            self.interactive = false;
+            return Ok(None);
+        }
+        let answer = answer.trim();
+        if answer.contains('|') {
+            // The answers end up in the report, where '|' is the field separator
+            println!("{}", t!("The character '|' is not allowed."));
+            return self.ask(question);
+        }
+        Ok((!answer.is_empty()).then(|| answer.to_string()))
+    }
+
+    /// Asks for a secret, like a password, without echoing the answer. An
+    /// empty answer is returned as `None`.
+    pub fn ask_secret(&mut self, question: &str) -> Result<Option<String>> {
+        if !self.interactive {
+            return Ok(None);
+        }
+        let answer = rpassword::prompt_password(format!("{} ", question))?;
+        let answer = answer.trim();
+        Ok((!answer.is_empty()).then(|| answer.to_string()))
+    }
+
+    /// Asks a yes or no question, with the given default answer
+    pub fn confirm(&mut self, question: &str, default: bool) -> Result<bool> {
+        let hint = if default { t!("[Y/n]") } else { t!("[y/N]") };
+        loop {
+            let Some(answer) = self.ask(&format!("{} {}", question, hint))? else {
+                return Ok(default);
+            };
+            match answer.to_lowercase().as_str() {
+                "y" | "yes" | "s" | "sim" => return Ok(true),
+                "n" | "no" | "não" | "nao" => return Ok(false),
+                _ => println!("{}", t!("Please answer yes or no.")),
+            }
+        }
+    }
+
+    /// Asks for a decimal number, repeating the question until the answer is
+    /// valid or empty. Both `.` and `,` are accepted as decimal separator.
+    pub fn ask_decimal(&mut self, question: &str) -> Result<Option<Decimal>> {
+        while let Some(answer) = self.ask(question)? {
+            match answer.replace(',', ".").parse::<Decimal>() {
+                Ok(value) => return Ok(Some(value)),
+                Err(_) => println!("{}", t!("Invalid number: {}", answer)),
+            }
+        }
+        Ok(None)
+    }
+}
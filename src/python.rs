@@ -0,0 +1,290 @@
+//! The `kraken2rfb` Python module, with the `python` feature: the steps of
+//! the pipeline as functions, so a Python script calls them directly instead
+//! of running the binary and reading its output.
+//!
+//! The data crosses as the plain Python objects of its JSON form: the
+//! activity as in a [`crate::bundle::Bundle`], the transactions as the
+//! document of [`crate::report::interchange`], the income as
+//! [`crate::income::Income`], with the decimals as strings.
+//!
+//! ```python
+//! import kraken2rfb
+//!
+//! activity = kraken2rfb.fetch("kraken", "2024-03")
+//! document, income = kraken2rfb.process(activity, "kraken", min_value="1.00")
+//! open("2024-03.txt", "wb").write(kraken2rfb.encode(document))
+//! for month in kraken2rfb.gains(document, income):
+//!     print(month["year"], month["month"], month["tax"])
+//! ```
+
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use chrono::{FixedOffset, Local};
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::json;
+
+use crate::error::{Error, Result};
+use crate::gains::{carry_losses, realized_gains};
+use crate::i18n::t;
+use crate::income::Income;
+use crate::period::{BRASILIA, Period};
+use crate::report::layout::Layout;
+use crate::report::resolve::Providers;
+use crate::report::transactions::{ExchangeInfo, Transaction};
+use crate::report::{ProcessOptions, convert_activity, interchange, write_report};
+use crate::source::{Activity, ExchangeSource, fetch_days};
+
+create_exception!(
+    kraken2rfb,
+    Kraken2RfbError,
+    PyException,
+    "An error of kraken2rfb, with the message the binary prints for it"
+);
+
+fn py_err(error: Error) -> PyErr {
+    Kraken2RfbError::new_err(error.to_string())
+}
+
+/// Converts a value to the Python object of its JSON form
+fn to_py<'py>(py: Python<'py>, value: &impl Serialize) -> PyResult<Bound<'py, PyAny>> {
+    let text =
+        serde_json::to_string(value).map_err(|e| py_err(Error::Validation(e.to_string())))?;
+    py.import("json")?.call_method1("loads", (text,))
+}
+
+/// The JSON text of a Python object
+fn dumps(value: &Bound<'_, PyAny>) -> PyResult<String> {
+    value
+        .py()
+        .import("json")?
+        .call_method1("dumps", (value,))?
+        .extract()
+}
+
+/// Reads a value from a Python object of its JSON form; `what` names the
+/// argument in the error
+fn from_py<T: DeserializeOwned>(value: &Bound<'_, PyAny>, what: &str) -> PyResult<T> {
+    serde_json::from_str(&dumps(value)?)
+        .map_err(|e| py_err(Error::Config(t!("invalid value for {}: {}", what, e))))
+}
+
+/// Reads a decimal given as a string, an int or a `decimal.Decimal`
+fn decimal_of(value: &Bound<'_, PyAny>, what: &str) -> PyResult<Decimal> {
+    let text = value.str()?.to_string();
+    Decimal::from_str(&text)
+        .map_err(|_| py_err(Error::Config(t!("invalid value for {}: {}", what, text))))
+}
+
+/// Reads an offset from UTC like `-03:00`, Brasília time if not given
+fn offset_of(value: Option<&str>) -> PyResult<FixedOffset> {
+    value.map_or(Ok(BRASILIA), |value| {
+        value.parse().map_err(|_| {
+            py_err(Error::Config(t!(
+                "invalid value for {}: {}",
+                "utc_offset",
+                value
+            )))
+        })
+    })
+}
+
+/// The exchanges with an API client, by their names on the command line
+const EXCHANGES: &[&str] = &[
+    "kraken",
+    #[cfg(feature = "binance")]
+    "binance",
+    #[cfg(feature = "bitstamp")]
+    "bitstamp",
+    #[cfg(feature = "bybit")]
+    "bybit",
+    #[cfg(feature = "okx")]
+    "okx",
+    #[cfg(feature = "kucoin")]
+    "kucoin",
+    #[cfg(feature = "gemini")]
+    "gemini",
+    #[cfg(feature = "gate")]
+    "gate",
+];
+
+/// The API client of an exchange, by its name on the command line
+fn exchange_source(
+    exchange: &str,
+    keyfile: Option<PathBuf>,
+    offset: FixedOffset,
+) -> Result<Box<dyn ExchangeSource>> {
+    Ok(match exchange.to_ascii_lowercase().as_str() {
+        "kraken" => Box::new(crate::kraken::KrakenSource::new(keyfile).with_offset(offset)),
+        #[cfg(feature = "binance")]
+        "binance" => Box::new(crate::binance::BinanceSource::new(keyfile)),
+        #[cfg(feature = "bitstamp")]
+        "bitstamp" => Box::new(crate::bitstamp::BitstampSource::new(keyfile)),
+        #[cfg(feature = "bybit")]
+        "bybit" => Box::new(crate::bybit::BybitSource::new(keyfile)),
+        #[cfg(feature = "okx")]
+        "okx" => Box::new(crate::okx::OkxSource::new(keyfile)),
+        #[cfg(feature = "kucoin")]
+        "kucoin" => Box::new(crate::kucoin::KucoinSource::new(keyfile)),
+        #[cfg(feature = "gemini")]
+        "gemini" => Box::new(crate::gemini::GeminiSource::new(keyfile)),
+        #[cfg(feature = "gate")]
+        "gate" => Box::new(crate::gate::GateSource::new(keyfile)),
+        _ => {
+            return Err(Error::Config(t!(
+                "unknown exchange: {} (expected one of {})",
+                exchange,
+                EXCHANGES.join(", ")
+            )));
+        }
+    })
+}
+
+/// Fetches the activity of a period (`2024-03`, `2024-Q1`, `last-month`...)
+/// from the API of an exchange, as a dict
+#[pyfunction]
+#[pyo3(signature = (exchange, period, keyfile=None, utc_offset=None))]
+fn fetch<'py>(
+    py: Python<'py>,
+    exchange: &str,
+    period: &str,
+    keyfile: Option<PathBuf>,
+    utc_offset: Option<&str>,
+) -> PyResult<Bound<'py, PyAny>> {
+    let offset = offset_of(utc_offset)?;
+    let period = Period::parse(period, Local::now().date_naive()).map_err(py_err)?;
+    let activity = py
+        .detach(|| {
+            let source = exchange_source(exchange, keyfile, offset)?;
+            fetch_days(
+                source.as_ref(),
+                period.first_day(),
+                period.last_day(),
+                offset,
+            )
+        })
+        .map_err(py_err)?;
+    to_py(py, &activity)
+}
+
+/// Converts an activity into the transactions of the report, valued in BRL
+/// with the rates of the built-in providers. `exchange` is the name of an
+/// exchange, or a dict with its `name`, `url` and `country`. Returns the
+/// interchange document and the income, which has no record in the report.
+#[pyfunction]
+#[pyo3(signature = (activity, exchange, utc_offset=None, min_value=None))]
+fn process<'py>(
+    py: Python<'py>,
+    activity: &Bound<'py, PyAny>,
+    exchange: &Bound<'py, PyAny>,
+    utc_offset: Option<&str>,
+    min_value: Option<&Bound<'py, PyAny>>,
+) -> PyResult<(Bound<'py, PyAny>, Bound<'py, PyAny>)> {
+    let activity: Activity = from_py(activity, "activity")?;
+    let offset = offset_of(utc_offset)?;
+    let exchange: ExchangeInfo = match exchange.extract::<String>() {
+        Ok(name) => exchange_source(&name, None, offset)
+            .map_err(py_err)?
+            .exchange(),
+        Err(_) => from_py(exchange, "exchange")?,
+    };
+    let options = ProcessOptions {
+        min_value: min_value
+            .map(|value| decimal_of(value, "min_value"))
+            .transpose()?,
+        utc_offset: Some(offset),
+        ..Default::default()
+    };
+    let processed = py
+        .detach(|| convert_activity(activity, &exchange, &options, &mut Providers::default()))
+        .map_err(py_err)?;
+    let mut document = Vec::new();
+    interchange::write(&processed.transactions, &mut document).map_err(py_err)?;
+    let document = py
+        .import("json")?
+        .call_method1("loads", (PyBytes::new(py, &document),))?;
+    Ok((document, to_py(py, &processed.income)?))
+}
+
+/// Writes the transactions of an interchange document in the layout of the
+/// IN 1888 (`layout`, the current one if not given), as the bytes of the file
+#[pyfunction]
+#[pyo3(signature = (document, layout=None))]
+fn encode<'py>(
+    py: Python<'py>,
+    document: &Bound<'py, PyAny>,
+    layout: Option<&str>,
+) -> PyResult<Bound<'py, PyBytes>> {
+    let transactions = read_document(document)?;
+    let layout = layout
+        .map(Layout::parse)
+        .transpose()
+        .map_err(py_err)?
+        .unwrap_or_default();
+    let mut report = Vec::new();
+    write_report(&transactions, layout, &mut report).map_err(py_err)?;
+    Ok(PyBytes::new(py, &report))
+}
+
+/// The monthly capital gains of the sales of an interchange document, and
+/// the tax of each month after the losses carried from the previous ones,
+/// starting with `opening_loss`. The income, as returned by `process`, gives
+/// the cost of the assets received as income.
+#[pyfunction]
+#[pyo3(signature = (document, income=None, opening_loss=None))]
+fn gains<'py>(
+    py: Python<'py>,
+    document: &Bound<'py, PyAny>,
+    income: Option<&Bound<'py, PyAny>>,
+    opening_loss: Option<&Bound<'py, PyAny>>,
+) -> PyResult<Bound<'py, PyAny>> {
+    let transactions = read_document(document)?;
+    let income: Vec<Income> = income
+        .map(|income| from_py(income, "income"))
+        .transpose()?
+        .unwrap_or_default();
+    let opening_loss = opening_loss
+        .map(|loss| decimal_of(loss, "opening_loss"))
+        .transpose()?
+        .unwrap_or_default();
+    let months = realized_gains(transactions.iter().map(|t| (t, None)), &income)
+        .and_then(|months| carry_losses(&months, opening_loss))
+        .map_err(py_err)?;
+    let months: Vec<_> = months
+        .iter()
+        .map(|month| {
+            json!({
+                "year": month.year,
+                "month": month.month,
+                "sales": month.gains.sales.to_string(),
+                "gain": month.gains.gain.to_string(),
+                "exempt": month.exempt,
+                "compensated": month.compensated.to_string(),
+                "tax": month.tax.to_string(),
+                "carried_loss": month.carried_loss.to_string(),
+            })
+        })
+        .collect();
+    to_py(py, &months)
+}
+
+/// Reads the transactions of an interchange document
+fn read_document(document: &Bound<'_, PyAny>) -> PyResult<Vec<Transaction>> {
+    interchange::read(dumps(document)?.as_bytes()).map_err(py_err)
+}
+
+#[pymodule]
+fn kraken2rfb(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add("Error", module.py().get_type::<Kraken2RfbError>())?;
+    module.add_function(wrap_pyfunction!(fetch, module)?)?;
+    module.add_function(wrap_pyfunction!(process, module)?)?;
+    module.add_function(wrap_pyfunction!(encode, module)?)?;
+    module.add_function(wrap_pyfunction!(gains, module)?)?;
+    Ok(())
+}
@@ -0,0 +1,159 @@
+//! Reconciliation of the balances of an account with its imported history:
+//! the balance of each asset the exchange reports should be what the
+//! deposits, withdrawals, income and trades add up to. A difference means
+//! entries are missing from the history, e.g. because a page of it wasn't
+//! fetched.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use rust_decimal::Decimal;
+
+use crate::source::{Activity, Side};
+
+/// An asset whose balance on the exchange differs from the imported history
+#[derive(Debug, Clone, PartialEq)]
+pub struct Discrepancy {
+    /// Common symbol of the asset, or the exchange's name for it if unknown
+    pub asset: String,
+    /// Balance the imported history adds up to
+    pub implied: Decimal,
+    /// Balance reported by the exchange
+    pub actual: Decimal,
+}
+
+/// The balance of each asset after the activity, from an empty account. The
+/// fees are taken from the balance on top of the amounts: a deposit credits
+/// its amount minus the fee, a withdrawal debits its amount plus the fee, and
+/// a trade's fee is paid in the quote asset. Income is credited net of fees.
+/// A move between the wallets of the account takes from the asset of one and
+/// adds to the one of the other, e.g. from "DOT" to the staked "DOT.S", as the
+/// exchange lists them in its balances.
+pub fn implied_balances(activity: &Activity) -> BTreeMap<String, Decimal> {
+    let mut balances: BTreeMap<String, Decimal> = BTreeMap::new();
+    let mut add = |asset: &str, amount: Decimal| {
+        *balances.entry(asset.to_string()).or_default() += amount;
+    };
+    for deposit in &activity.deposits {
+        add(&deposit.asset, deposit.amount - deposit.fee);
+    }
+    for withdrawal in &activity.withdrawals {
+        add(&withdrawal.asset, -withdrawal.amount - withdrawal.fee);
+    }
+    for income in &activity.income {
+        add(&income.asset, income.amount);
+    }
+    for transfer in &activity.wallet_transfers {
+        add(&transfer.asset, transfer.amount);
+    }
+    for trade in &activity.trades {
+        match trade.side {
+            Side::Buy => {
+                add(&trade.base, trade.volume);
+                add(&trade.quote, -trade.cost - trade.fee);
+            }
+            Side::Sell => {
+                add(&trade.base, -trade.volume);
+                add(&trade.quote, trade.cost - trade.fee);
+            }
+        }
+    }
+    balances
+}
+
+/// The assets whose balances differ, in either direction. Assets missing
+/// from one side count as a zero balance there.
+pub fn reconcile(
+    implied: &BTreeMap<String, Decimal>,
+    actual: &BTreeMap<String, Decimal>,
+) -> Vec<Discrepancy> {
+    let assets: BTreeSet<&String> = implied.keys().chain(actual.keys()).collect();
+    assets
+        .into_iter()
+        .filter_map(|asset| {
+            let implied = implied.get(asset).copied().unwrap_or_default();
+            let actual = actual.get(asset).copied().unwrap_or_default();
+            (implied != actual).then(|| Discrepancy {
+                asset: asset.clone(),
+                implied,
+                actual,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::{NormalizedDeposit, NormalizedTrade, NormalizedWithdrawal};
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_reconcile() {
+        let time = "2024-03-01T12:00:00Z".parse().unwrap();
+        let activity = Activity {
+            deposits: vec![NormalizedDeposit {
+                asset: "EUR".to_string(),
+                amount: dec!(1000),
+                fee: dec!(1),
+                time,
+                ids: Vec::new(),
+                origin_exchange: None,
+                origin_wallet: None,
+                network: None,
+            }],
+            withdrawals: vec![NormalizedWithdrawal {
+                asset: "BTC".to_string(),
+                amount: dec!(0.01),
+                fee: dec!(0.0001),
+                time,
+                ids: Vec::new(),
+                destination: None,
+                network: None,
+            }],
+            trades: vec![NormalizedTrade {
+                base: "BTC".to_string(),
+                quote: "EUR".to_string(),
+                side: Side::Buy,
+                volume: dec!(0.02),
+                cost: dec!(900),
+                fee: dec!(2),
+                credit_fee_usd: Decimal::ZERO,
+                price: dec!(45000),
+                time,
+                ids: Vec::new(),
+            }],
+            income: Vec::new(),
+            wallet_transfers: Vec::new(),
+        };
+        let implied = implied_balances(&activity);
+        assert_eq!(
+            implied,
+            BTreeMap::from([
+                ("BTC".to_string(), dec!(0.0099)),
+                ("EUR".to_string(), dec!(97)),
+            ])
+        );
+
+        let actual = BTreeMap::from([
+            ("BTC".to_string(), dec!(0.0099)),
+            ("EUR".to_string(), dec!(97.5)),
+            ("ETH".to_string(), dec!(0)),
+            ("DOT".to_string(), dec!(3)),
+        ]);
+        assert_eq!(
+            reconcile(&implied, &actual),
+            [
+                Discrepancy {
+                    asset: "DOT".to_string(),
+                    implied: dec!(0),
+                    actual: dec!(3),
+                },
+                Discrepancy {
+                    asset: "EUR".to_string(),
+                    implied: dec!(97),
+                    actual: dec!(97.5),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,110 @@
+//! Keeps secrets and personal data out of anything printed: API keys are
+//! replaced wherever they appear, and wallet addresses are shortened to their
+//! first and last characters, enough to tell them apart.
+
+use std::sync::Mutex;
+
+/// Secrets loaded during this run, like the Kraken API key and secret
+static SECRETS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Registers a secret to be hidden from any text passed to [`redact`]
+pub fn register_secret(secret: &str) {
+    // Too short to be a real secret, and would mangle unrelated text
+    if secret.len() < 8 {
+        return;
+    }
+    let mut secrets = SECRETS.lock().unwrap_or_else(|e| e.into_inner());
+    if !secrets.iter().any(|s| s == secret) {
+        secrets.push(secret.to_string());
+    }
+}
+
+/// Whether a word looks like a Bitcoin or Ethereum address
+fn is_address(word: &str) -> bool {
+    let is_base58 = |c: char| c.is_ascii_alphanumeric() && !"0OIl".contains(c);
+    if let Some(hex) = word.strip_prefix("0x") {
+        return hex.len() == 40 && hex.chars().all(|c| c.is_ascii_hexdigit());
+    }
+    if word.starts_with("bc1") || word.starts_with("tb1") {
+        return (14..=74).contains(&word.len())
+            && word
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit());
+    }
+    (word.starts_with('1') || word.starts_with('3'))
+        && (26..=35).contains(&word.len())
+        && word.chars().all(is_base58)
+        && word.chars().any(|c| c.is_ascii_alphabetic())
+}
+
+/// Returns `text` with the registered secrets and any wallet addresses hidden
+pub fn redact(text: &str) -> String {
+    let mut text = text.to_string();
+    for secret in SECRETS.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+        text = text.replace(secret, "[REDACTED]");
+    }
+
+    let mut redacted = String::with_capacity(text.len());
+    let mut rest = text.as_str();
+    while !rest.is_empty() {
+        let end = rest
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .unwrap_or(rest.len());
+        // A word, or a single character that can't be part of one
+        let end = if end == 0 {
+            rest.chars().next().map_or(1, char::len_utf8)
+        } else {
+            end
+        };
+        let (word, tail) = rest.split_at(end);
+        if is_address(word) {
+            redacted.push_str(&word[..4]);
+            redacted.push('…');
+            redacted.push_str(&word[word.len() - 4..]);
+        } else {
+            redacted.push_str(word);
+        }
+        rest = tail;
+    }
+    redacted
+}
+
+/// Makes panic messages go through [`redact`], since they may quote the data
+/// being processed
+pub fn install_panic_hook() {
+    std::panic::set_hook(Box::new(|info| {
+        eprintln!("{}", redact(&info.to_string()));
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact() {
+        register_secret("c2VjcmV0LWtleS1mb3ItdGVzdHM=");
+        assert_eq!(
+            redact("invalid secret c2VjcmV0LWtleS1mb3ItdGVzdHM= given"),
+            "invalid secret [REDACTED] given"
+        );
+
+        assert_eq!(
+            redact("\"info\": \"bc1qxy2kgdygjrsqtzq2n0yrf2493p83kkfjhx0wlh\","),
+            "\"info\": \"bc1q…0wlh\","
+        );
+        assert_eq!(
+            redact("to 0x52908400098527886E0F7030069857D2E4169EE7."),
+            "to 0x52…9EE7."
+        );
+        assert_eq!(
+            redact("from 1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2"),
+            "from 1BvB…NVN2"
+        );
+
+        // Amounts, dates and Kraken IDs are kept
+        let text = "0.12345678 XXBT on 2024-03-01, refid FTQcuak-V6Za8qrWnhzTx67yYHz8Tg";
+        assert_eq!(redact(text), text);
+        assert_eq!(redact("valor inválido: ação"), "valor inválido: ação");
+    }
+}
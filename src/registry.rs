@@ -0,0 +1,345 @@
+//! Registry of the periods reported so far: when each report was last
+//! generated and whether it was filed with the RFB. A filed period isn't
+//! generated again unless forced, since a new file could differ from the one
+//! submitted.
+//!
+//! It also keeps the deposits in each report, since an exchange can list a
+//! deposit again in the next period, e.g. one pending across the end of the
+//! month, and a deposit must only be reported once.
+//!
+//! The registry is a JSON file, `registry.json` in [`paths::data_dir`].
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::i18n::t;
+use crate::paths;
+use crate::period::Period;
+use crate::report::transactions::Transaction;
+use crate::source::Activity;
+
+/// Name of the registry file in the data directory
+const REGISTRY_NAME: &str = "registry.json";
+
+/// A period reported for an exchange
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    /// Name of the exchange, as in the records of the report
+    pub exchange: String,
+    pub period: Period,
+    /// When its report was last generated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub generated_at: Option<DateTime<Utc>>,
+    /// When its report was filed with the RFB
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filed_on: Option<NaiveDate>,
+    /// Exchange IDs of the deposits in its report, the own ID of each
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deposit_ids: Vec<String>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct RegistryData {
+    entries: Vec<Entry>,
+}
+
+/// Whether two periods have a month in common, like a month and its quarter
+fn overlaps(a: Period, b: Period) -> bool {
+    a.first_day() <= b.last_day() && b.first_day() <= a.last_day()
+}
+
+/// The registry of reported periods, loaded in memory
+pub struct Registry {
+    path: PathBuf,
+    entries: Vec<Entry>,
+}
+
+impl Registry {
+    /// Path of the registry in the data directory, `None` if there is none
+    pub fn default_path() -> Option<PathBuf> {
+        paths::data_dir().map(|dir| dir.join(REGISTRY_NAME))
+    }
+
+    /// Opens the registry at `path`, empty if it doesn't exist yet
+    pub fn open(path: PathBuf) -> Result<Self> {
+        let data = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).map_err(|e| {
+                Error::Config(t!("corrupted registry file {}: {}", path.display(), e))
+            })?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => RegistryData::default(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Registry {
+            path,
+            entries: data.entries,
+        })
+    }
+
+    /// All the entries, sorted by exchange and period
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// The filed entries of the exchange with a month in `period`
+    pub fn filed(&self, exchange: &str, period: Period) -> Vec<&Entry> {
+        self.entries
+            .iter()
+            .filter(|e| {
+                e.exchange == exchange && e.filed_on.is_some() && overlaps(e.period, period)
+            })
+            .collect()
+    }
+
+    /// Why the report of the exchange for `period` shouldn't be generated
+    /// again: the months of it already filed, `None` if there are none
+    pub fn filed_message(&self, exchange: &str, period: Period) -> Option<String> {
+        let filed = self.filed(exchange, period);
+        if filed.is_empty() {
+            return None;
+        }
+        let filed = filed
+            .iter()
+            .filter_map(|e| Some(t!("{} on {}", e.period, e.filed_on?)))
+            .join(", ");
+        Some(t!(
+            "the report of {} was already filed ({}), a new one may differ from the file submitted",
+            period,
+            filed
+        ))
+    }
+
+    fn entry(&mut self, exchange: &str, period: Period) -> &mut Entry {
+        let matches = |e: &Entry| e.exchange == exchange && e.period == period;
+        if !self.entries.iter().any(matches) {
+            self.entries.push(Entry {
+                exchange: exchange.to_string(),
+                period,
+                generated_at: None,
+                filed_on: None,
+                deposit_ids: Vec::new(),
+            });
+            self.entries
+                .sort_by_key(|e| (e.exchange.clone(), e.period.first_day(), e.period.months));
+        }
+        let index = self.entries.iter().position(matches);
+        &mut self.entries[index.expect("the entry was just added")]
+    }
+
+    /// Records that the report of the period was generated at `time`
+    pub fn set_generated(&mut self, exchange: &str, period: Period, time: DateTime<Utc>) {
+        self.entry(exchange, period).generated_at = Some(time);
+    }
+
+    /// Records the deposits in the report of the period, replacing the ones of
+    /// its last report
+    pub fn set_deposits(&mut self, exchange: &str, period: Period, ids: Vec<String>) {
+        self.entry(exchange, period).deposit_ids = ids;
+    }
+
+    /// The deposits of the exchange already reported in a period with no
+    /// month in `period`, by ID, with the period they were reported in
+    pub fn reported_deposits(&self, exchange: &str, period: Period) -> HashMap<String, Period> {
+        self.entries
+            .iter()
+            .filter(|e| e.exchange == exchange && !overlaps(e.period, period))
+            .flat_map(|e| e.deposit_ids.iter().map(|id| (id.clone(), e.period)))
+            .collect()
+    }
+
+    /// Removes from `activity` the deposits of the exchange already reported
+    /// in a period with no month in `period`, returning their IDs with the
+    /// period they were reported in
+    pub fn remove_reported(
+        &self,
+        exchange: &str,
+        period: Period,
+        activity: &mut Activity,
+    ) -> Vec<(String, Period)> {
+        let reported = self.reported_deposits(exchange, period);
+        let mut removed = Vec::new();
+        activity.deposits.retain(|deposit| {
+            match deposit
+                .ids
+                .first()
+                .and_then(|id| Some((id, reported.get(id)?)))
+            {
+                Some((id, &period)) => {
+                    removed.push((id.clone(), period));
+                    false
+                }
+                None => true,
+            }
+        });
+        removed
+    }
+
+    /// Records the report of the period generated at `time`, with the
+    /// deposits of its 0410 records. Those excluded, below the minimum value,
+    /// of another record type or left out in the review have no record, so
+    /// they can still be reported in another period.
+    pub fn record_report(
+        &mut self,
+        exchange: &str,
+        period: Period,
+        time: DateTime<Utc>,
+        transactions: &[Transaction],
+    ) {
+        let deposit_ids = transactions
+            .iter()
+            .filter_map(|transaction| match transaction {
+                Transaction::TransferToExchange(t) => t.deposit_id.clone(),
+                _ => None,
+            })
+            .collect();
+        self.record_generated(exchange, period, time, deposit_ids);
+    }
+
+    /// Like [`Registry::record_report`], by the IDs of the deposits of the
+    /// 0410 records, for a report whose transactions weren't kept
+    pub fn record_generated(
+        &mut self,
+        exchange: &str,
+        period: Period,
+        time: DateTime<Utc>,
+        deposit_ids: Vec<String>,
+    ) {
+        self.set_generated(exchange, period, time);
+        self.set_deposits(exchange, period, deposit_ids);
+    }
+
+    /// Marks the period as filed on `date`, or as not filed with `None`
+    pub fn set_filed(&mut self, exchange: &str, period: Period, date: Option<NaiveDate>) {
+        self.entry(exchange, period).filed_on = date;
+    }
+
+    /// Writes the registry back to its file
+    pub fn save(&self) -> Result<()> {
+        let data = RegistryData {
+            entries: self.entries.clone(),
+        };
+        let content = serde_json::to_string_pretty(&data)
+            .map_err(|e| Error::Validation(t!("failed to serialize {}: {}", REGISTRY_NAME, e)))?;
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // Write to a temporary file first, so a crash never leaves a truncated registry
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, content + "\n")?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::transactions::{TransactionBase, TransferToExchangeTransaction};
+    use crate::source::NormalizedDeposit;
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_registry() {
+        let path = std::env::temp_dir().join(format!(
+            "kraken2rfb-test-{}.registry.json",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        let march = Period::month(2024, 3).unwrap();
+        let quarter = Period::quarter(2024, 1).unwrap();
+        let april = Period::month(2024, 4).unwrap();
+
+        let mut registry = Registry::open(path.clone()).unwrap();
+        registry.set_generated("Kraken", april, "2024-05-02T10:00:00Z".parse().unwrap());
+        registry.set_filed("Kraken", march, NaiveDate::from_ymd_opt(2024, 4, 30));
+        registry.save().unwrap();
+
+        let mut registry = Registry::open(path.clone()).unwrap();
+        let periods: Vec<_> = registry.entries().iter().map(|e| e.period).collect();
+        assert_eq!(periods, [march, april]);
+        // The quarter has the filed month
+        assert_eq!(registry.filed("Kraken", quarter)[0].period, march);
+        assert!(registry.filed("Kraken", april).is_empty());
+        assert!(registry.filed("Binance", march).is_empty());
+
+        registry.set_filed("Kraken", march, None);
+        assert!(registry.filed("Kraken", quarter).is_empty());
+
+        // A deposit is reported in one period only, but its own can report it again
+        registry.set_deposits("Kraken", march, vec!["FTQcuak".to_string()]);
+        let reported = registry.reported_deposits("Kraken", april);
+        assert_eq!(reported.get("FTQcuak"), Some(&march));
+        assert!(registry.reported_deposits("Kraken", quarter).is_empty());
+        assert!(registry.reported_deposits("Binance", april).is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_record_report() {
+        let march = Period::month(2024, 3).unwrap();
+        let april = Period::month(2024, 4).unwrap();
+        let deposit = |id: Option<&str>| {
+            Transaction::TransferToExchange(TransferToExchangeTransaction {
+                base: TransactionBase {
+                    operation_date: NaiveDate::from_ymd_opt(2024, 3, 2).unwrap(),
+                    operation_fees: None,
+                    crypto_symbol: "BTC".to_string(),
+                    crypto_amount: dec!(0.1),
+                },
+                origin_wallet: None,
+                origin_exchange_name: None,
+                deposit_id: id.map(String::from),
+            })
+        };
+        let normalized = |id: &str| NormalizedDeposit {
+            asset: "BTC".to_string(),
+            amount: dec!(0.1),
+            fee: Decimal::ZERO,
+            time: "2024-03-02T12:00:00Z".parse().unwrap(),
+            ids: vec![id.to_string()],
+            origin_exchange: None,
+            origin_wallet: None,
+            network: None,
+        };
+
+        let mut registry = Registry {
+            path: PathBuf::new(),
+            entries: Vec::new(),
+        };
+        let time = "2024-04-02T10:00:00Z".parse().unwrap();
+        registry.record_report("Kraken", march, time, &[deposit(Some("D1")), deposit(None)]);
+        assert_eq!(registry.entries()[0].generated_at, Some(time));
+        assert_eq!(registry.entries()[0].deposit_ids, ["D1"]);
+        assert_eq!(registry.filed_message("Kraken", march), None);
+
+        // Another period leaves out the deposit reported in March
+        let mut activity = Activity {
+            deposits: vec![normalized("D1"), normalized("D2")],
+            ..Activity::default()
+        };
+        let removed = registry.remove_reported("Kraken", april, &mut activity);
+        assert_eq!(removed, [("D1".to_string(), march)]);
+        assert_eq!(activity.deposits, [normalized("D2")]);
+        let mut activity = Activity {
+            deposits: vec![normalized("D1")],
+            ..Activity::default()
+        };
+        assert!(
+            registry
+                .remove_reported("Kraken", march, &mut activity)
+                .is_empty()
+        );
+        assert_eq!(activity.deposits.len(), 1);
+
+        registry.set_filed("Kraken", march, NaiveDate::from_ymd_opt(2024, 4, 30));
+        let message = registry.filed_message("Kraken", march).unwrap();
+        assert!(message.contains("2024-03") && message.contains("2024-04-30"));
+        assert_eq!(registry.filed_message("Kraken", april), None);
+    }
+}
@@ -1,9 +1,14 @@
+//! Field formatting and row layout of the report file.
+
 use chrono::Datelike;
 use chrono::NaiveDate;
 use itertools::Itertools;
 use rust_decimal::Decimal;
 use std::fmt;
-use std::io::{self, Write};
+use std::io::Write;
+
+use crate::error::{Error, Result};
+use crate::i18n::t;
 
 pub enum Field<'a> {
     Date(NaiveDate),
@@ -14,9 +19,6 @@ pub enum Field<'a> {
 
 impl<'a> From<&'a str> for Field<'a> {
     fn from(value: &'a str) -> Self {
-        if value.contains('|') {
-            panic!("Field value cannot contain '|' character");
-        }
         Field::AlphaNumber { value }
     }
 }
@@ -44,8 +46,11 @@ impl<'a> fmt::Display for Field<'a> {
                 // Format with specified precision, using comma as decimal separator
                 // and no thousand separators
                 let rounded = value.round_dp(*precision);
-                let formatted = format!("{:.1$}", rounded, *precision as usize).replace('.', ",");
-                write!(f, "{}", formatted)
+                let formatted = format!("{:.1$}", rounded, *precision as usize);
+                match formatted.split_once('.') {
+                    Some((integer, fraction)) => write!(f, "{},{}", integer, fraction),
+                    None => write!(f, "{}", formatted),
+                }
             }
             Field::AlphaNumber { value } => {
                 // Display alphanumeric value as is
@@ -59,6 +64,15 @@ impl<'a> fmt::Display for Field<'a> {
     }
 }
 
+/// Replaces the characters a field can't contain, the `|` separator and line
+/// breaks, by spaces. Returns `None` if there were none.
+pub fn sanitize(value: &str) -> Option<String> {
+    let not_allowed = |c: char| c == '|' || c.is_control();
+    value
+        .contains(not_allowed)
+        .then(|| value.replace(not_allowed, " "))
+}
+
 /// Writes a register row to the given writer, joining fields with pipe delimiters and adding CRLF.
 ///
 /// # Arguments
@@ -68,11 +82,13 @@ impl<'a> fmt::Display for Field<'a> {
 ///
 /// # Returns
 ///
-/// * `io::Result<()>` - Result indicating success or failure of the write operation
+/// * `Result<()>` - Fails if the write fails, or if a field contains characters
+///   not allowed in the report (see [`sanitize`])
 ///
 /// # Example
 ///
 /// ```
+/// use kraken2rfb::report::encoding::{Field, write_register_row};
 /// use std::io::Cursor;
 /// let mut writer = Cursor::new(Vec::new());
 /// let fields = vec![
@@ -81,9 +97,19 @@ impl<'a> fmt::Display for Field<'a> {
 ///     Field::AlphaNumber { value: "12345678912" },
 /// ];
 /// write_register_row(&mut writer, &fields).unwrap();
-/// assert_eq!(writer.into_inner(), b"I550|José Silva|12345678912|\r\n");
+/// assert_eq!(writer.into_inner(), "I550|José Silva|12345678912\r\n".as_bytes());
 /// ```
-pub fn write_register_row<W: Write>(writer: &mut W, fields: &[Field<'_>]) -> io::Result<()> {
+pub fn write_register_row<W: Write>(writer: &mut W, fields: &[Field<'_>]) -> Result<()> {
+    for field in fields {
+        if let Field::AlphaNumber { value } = field
+            && sanitize(value).is_some()
+        {
+            return Err(Error::Validation(t!(
+                "field can't contain '|' or line breaks: {}",
+                format!("{:?}", value)
+            )));
+        }
+    }
     // Join all fields with pipe delimiter and add final pipe and CRLF
     write!(writer, "{}\r\n", fields.iter().format("|"))?;
     Ok(())
@@ -139,6 +165,19 @@ mod tests {
         assert_eq!(formatted_string, "VALID_STRING");
     }
 
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(sanitize("Ledger Nano"), None);
+        assert_eq!(sanitize("bc1q|Ledger\n").as_deref(), Some("bc1q Ledger "));
+
+        let mut writer = Cursor::new(Vec::new());
+        let fields = [Field::from("0410"), Field::from("Ledger|Nano")];
+        assert!(matches!(
+            write_register_row(&mut writer, &fields),
+            Err(Error::Validation(_))
+        ));
+    }
+
     #[test]
     fn test_write_register_row() {
         let mut writer = Cursor::new(Vec::new());
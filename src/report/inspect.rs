@@ -0,0 +1,124 @@
+//! What the report makes of a few entries of the activity, e.g. a single
+//! suspicious trade: how they are classified, the rates they are converted
+//! with and the lines they turn into, without a whole run.
+
+use std::collections::BTreeMap;
+
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+
+use super::preview::{RecordPreview, preview_activity};
+use super::resolve::Resolver;
+use super::transactions::ExchangeInfo;
+use super::{ProcessOptions, ProcessedData, convert_activity};
+use crate::error::{Error, Result};
+use crate::source::Activity;
+
+/// The report of a few entries
+#[derive(Debug)]
+pub struct Inspection {
+    /// The records the entries are classified as, before any rate is known
+    pub records: Vec<RecordPreview>,
+    /// BRL price of 1 unit of each rate the conversion used, keyed by asset
+    /// and date
+    pub rates: BTreeMap<(String, NaiveDate), Decimal>,
+    pub processed: ProcessedData,
+    /// Lines of the report of the transactions, in order, without the line
+    /// break
+    pub lines: Vec<String>,
+}
+
+/// Converts `activity` with the rates and answers of `resolver`, keeping the
+/// rates it used
+pub fn inspect(
+    activity: Activity,
+    exchange: &ExchangeInfo,
+    options: &ProcessOptions,
+    resolver: &mut (impl Resolver + ?Sized),
+) -> Result<Inspection> {
+    /// Passes the calls on, recording the rates given
+    struct Recorder<'a, R: ?Sized> {
+        inner: &'a mut R,
+        rates: BTreeMap<(String, NaiveDate), Decimal>,
+    }
+
+    impl<R: Resolver + ?Sized> Resolver for Recorder<'_, R> {
+        fn rate(&mut self, asset: &str, date: NaiveDate) -> Result<Decimal> {
+            let rate = self.inner.rate(asset, date)?;
+            self.rates.insert((asset.to_string(), date), rate);
+            Ok(rate)
+        }
+
+        fn answer(&mut self, question: &str) -> Result<Option<String>> {
+            self.inner.answer(question)
+        }
+    }
+
+    let records = preview_activity(&activity, options)?;
+    let mut recorder = Recorder {
+        inner: resolver,
+        rates: BTreeMap::new(),
+    };
+    let processed = convert_activity(activity, exchange, options, &mut recorder)?;
+    let mut lines = Vec::new();
+    for transaction in &processed.transactions {
+        let mut line = Vec::new();
+        transaction.write_transaction(&mut line, options.layout)?;
+        let line = String::from_utf8(line).map_err(|e| Error::Validation(e.to_string()))?;
+        lines.push(line.trim_end().to_string());
+    }
+    Ok(Inspection {
+        records,
+        rates: recorder.rates,
+        processed,
+        lines,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::resolve::Resolved;
+    use crate::source::{NormalizedTrade, Side};
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_inspect() {
+        let date = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        let activity = Activity {
+            trades: vec![NormalizedTrade {
+                base: "BTC".to_string(),
+                quote: "EUR".to_string(),
+                side: Side::Buy,
+                volume: dec!(0.1),
+                cost: dec!(5000),
+                fee: dec!(10),
+                credit_fee_usd: Decimal::ZERO,
+                price: dec!(50000),
+                time: "2024-03-01T15:00:00Z".parse().unwrap(),
+                ids: vec!["TX1".to_string()],
+            }],
+            ..Activity::default()
+        };
+        let exchange = ExchangeInfo {
+            name: "Kraken".to_string(),
+            url: "https://www.kraken.com".to_string(),
+            country: "US".to_string(),
+        };
+        let mut resolved = Resolved::default();
+        resolved.rates.insert(("EUR".to_string(), date), dec!(5.4));
+        let inspection = inspect(
+            activity,
+            &exchange,
+            &ProcessOptions::default(),
+            &mut resolved,
+        )
+        .unwrap();
+        assert_eq!(inspection.records.len(), 1);
+        assert_eq!(inspection.records[0].record_type, "0110");
+        assert_eq!(inspection.rates, resolved.rates);
+        assert_eq!(inspection.lines.len(), 1);
+        assert!(inspection.lines[0].starts_with("0110|01032024|"));
+        assert!(inspection.lines[0].contains("26946,00"));
+    }
+}
@@ -0,0 +1,192 @@
+//! JSON interchange format of the transactions, so a report can be saved,
+//! inspected and encoded again, and other tools can hand transactions to this
+//! crate to be written in the layout of the report.
+//!
+//! A document is an object with the `version` of the format and the list of
+//! `transactions`. Each transaction has a `type` (`purchase`, `sale`, `swap`,
+//! `transfer_to_exchange`, `withdrawal_from_exchange`,
+//! `crypto_payment_receiver` or `crypto_payment_sender`) and the fields of its
+//! struct in [`super::transactions`], by name. Dates are `YYYY-MM-DD`, and
+//! amounts and BRL values are decimal strings, so no precision is lost; the
+//! optional fields may be `null` or left out:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "transactions": [
+//!     {
+//!       "type": "withdrawal_from_exchange",
+//!       "base": {
+//!         "operation_date": "2024-03-01",
+//!         "operation_fees": "30.00",
+//!         "crypto_symbol": "BTC",
+//!         "crypto_amount": "0.5"
+//!       },
+//!       "origin_exchange": {
+//!         "name": "Kraken",
+//!         "url": "https://www.kraken.com",
+//!         "country": "US"
+//!       }
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! The version is increased on any change that an older reader would get
+//! wrong, so documents of other versions are refused instead of misread.
+
+use std::io::{Read, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::i18n::t;
+use crate::report::transactions::Transaction;
+
+/// Version of the interchange format written and read by this crate
+pub const VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct DocumentRef<'a> {
+    version: u32,
+    transactions: &'a [Transaction],
+}
+
+/// A document of the supported version, whose `version` was checked already
+#[derive(Deserialize)]
+struct Document {
+    transactions: Vec<Transaction>,
+}
+
+/// (De)serialization of an optional decimal as a string or `null`, which
+/// `rust_decimal::serde::str_option` fails to read inside a tagged enum
+pub(crate) mod optional_decimal {
+    use rust_decimal::Decimal;
+    use serde::{Deserialize, Deserializer, Serializer, de};
+
+    pub fn serialize<S: Serializer>(
+        value: &Option<Decimal>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        rust_decimal::serde::str_option::serialize(value, serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Decimal>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|text| text.parse().map_err(de::Error::custom))
+            .transpose()
+    }
+}
+
+/// Writes the transactions as an interchange document
+pub fn write(transactions: &[Transaction], writer: &mut impl Write) -> Result<()> {
+    let document = DocumentRef {
+        version: VERSION,
+        transactions,
+    };
+    serde_json::to_writer_pretty(&mut *writer, &document).map_err(|e| Error::Io(e.into()))?;
+    writeln!(writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Reads the transactions of an interchange document
+pub fn read(reader: impl Read) -> Result<Vec<Transaction>> {
+    #[derive(Deserialize)]
+    struct Version {
+        version: u32,
+    }
+
+    let text = std::io::read_to_string(reader)?;
+    let invalid =
+        |e: serde_json::Error| Error::Validation(t!("invalid transactions document: {}", e));
+    // The version is checked first, since the rest depends on it
+    let version = serde_json::from_str::<Version>(&text)
+        .map_err(invalid)?
+        .version;
+    if version != VERSION {
+        return Err(Error::Validation(t!(
+            "unsupported transactions document version {} (supported: {})",
+            version,
+            VERSION
+        )));
+    }
+    Ok(serde_json::from_str::<Document>(&text)
+        .map_err(invalid)?
+        .transactions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::layout::Layout;
+    use crate::report::write_report;
+
+    /// The document in the module documentation
+    const DOCUMENT: &str = r#"{
+      "version": 1,
+      "transactions": [
+        {
+          "type": "withdrawal_from_exchange",
+          "base": {
+            "operation_date": "2024-03-01",
+            "operation_fees": "30.00",
+            "crypto_symbol": "BTC",
+            "crypto_amount": "0.5"
+          },
+          "origin_exchange": {
+            "name": "Kraken",
+            "url": "https://www.kraken.com",
+            "country": "US"
+          }
+        },
+        {
+          "type": "transfer_to_exchange",
+          "base": {
+            "operation_date": "2024-03-02",
+            "crypto_symbol": "ETH",
+            "crypto_amount": "2.000000000001"
+          },
+          "origin_wallet": "0xb794f5ea0ba39494ce839613fffba74279579268"
+        }
+      ]
+    }"#;
+
+    #[test]
+    fn test_round_trip() {
+        let transactions = read(DOCUMENT.as_bytes()).unwrap();
+        let mut report = Vec::new();
+        write_report(&transactions, Layout::V2019, &mut report).unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "0510|01032024|V|30,00|BTC|0,5000000000|Kraken|https://www.kraken.com|US\r\n\
+             0410|02032024|IV||ETH|2,0000000000|0xb794f5ea0ba39494ce839613fffba74279579268|\r\n"
+        );
+
+        let mut written = Vec::new();
+        write(&transactions, &mut written).unwrap();
+        let again = read(written.as_slice()).unwrap();
+        assert_eq!(format!("{:?}", again), format!("{:?}", transactions));
+        let Transaction::TransferToExchange(transfer) = &again[1] else {
+            panic!("expected a transfer");
+        };
+        // Not rounded to the precision of the report
+        assert_eq!(transfer.base.crypto_amount.to_string(), "2.000000000001");
+    }
+
+    #[test]
+    fn test_invalid() {
+        let other_version = DOCUMENT.replace(r#""version": 1"#, r#""version": 2"#);
+        assert!(matches!(
+            read(other_version.as_bytes()),
+            Err(Error::Validation(_))
+        ));
+        let unknown_type = DOCUMENT.replace("transfer_to_exchange", "gift");
+        assert!(matches!(
+            read(unknown_type.as_bytes()),
+            Err(Error::Validation(_))
+        ));
+    }
+}
@@ -0,0 +1,255 @@
+//! Versions of the layout of the report file. Each version maps the
+//! transactions to the fields of their records, so a revision of the layout by
+//! the RFB is added as a new version next to the old ones, and a past report
+//! can still be reproduced with the version it was made with.
+
+use std::fmt;
+
+use rust_decimal::Decimal;
+
+use crate::error::{Error, Result};
+use crate::i18n::t;
+use crate::report::encoding::Field;
+use crate::report::transactions::{ExchangeInfo, Transaction, TransactionBase};
+
+/// A version of the layout of the report file
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// The layout published with IN RFB 1888/2019
+    #[default]
+    V2019,
+}
+
+impl Layout {
+    /// Every version, oldest first
+    pub const ALL: [Layout; 1] = [Layout::V2019];
+
+    /// Parses a version by its name, e.g. "2019"
+    pub fn parse(name: &str) -> Result<Self> {
+        Layout::ALL
+            .into_iter()
+            .find(|layout| layout.to_string() == name)
+            .ok_or_else(|| {
+                let known = Layout::ALL.map(|layout| layout.to_string()).join(", ");
+                Error::Config(t!("unknown layout: {} (known: {})", name, known))
+            })
+    }
+
+    /// The fields of the record of a transaction, in order
+    pub fn fields<'a>(&self, transaction: &'a Transaction) -> Vec<Field<'a>> {
+        match self {
+            Layout::V2019 => v2019::fields(transaction),
+        }
+    }
+
+    /// How many decimals the crypto amounts of a record type are written with
+    pub fn amount_precision(&self, record_type: &str) -> u32 {
+        match self {
+            Layout::V2019 => v2019::amount_precision(record_type),
+        }
+    }
+}
+
+impl fmt::Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Layout::V2019 => write!(f, "2019"),
+        }
+    }
+}
+
+mod v2019 {
+    use super::*;
+
+    /// Decimals of the crypto amounts, except in the sales
+    const AMOUNT_PRECISION: u32 = 10;
+
+    /// Decimals of the crypto amount of a sale
+    const SALE_AMOUNT_PRECISION: u32 = 12;
+
+    pub(super) fn amount_precision(record_type: &str) -> u32 {
+        match record_type {
+            "0120" => SALE_AMOUNT_PRECISION,
+            _ => AMOUNT_PRECISION,
+        }
+    }
+
+    fn exchange_fields(exchange: &ExchangeInfo) -> Vec<Field<'_>> {
+        vec![
+            Field::AlphaNumber {
+                value: &exchange.name,
+            },
+            Field::AlphaNumber {
+                value: &exchange.url,
+            },
+            Field::AlphaNumber {
+                value: &exchange.country,
+            },
+        ]
+    }
+
+    fn fees_field(fees: &Option<Decimal>) -> Field<'_> {
+        fees.as_ref()
+            .map_or(Field::Empty, |fees| Field::DecimalNumber {
+                value: fees,
+                precision: 2,
+            })
+    }
+
+    /// Date, code, fees, symbol and amount, shared by most records
+    fn common_fields<'a>(base: &'a TransactionBase, record_code: &'a str) -> Vec<Field<'a>> {
+        vec![
+            Field::Date(base.operation_date),
+            Field::AlphaNumber { value: record_code },
+            fees_field(&base.operation_fees),
+            Field::AlphaNumber {
+                value: &base.crypto_symbol,
+            },
+            Field::DecimalNumber {
+                value: &base.crypto_amount,
+                precision: AMOUNT_PRECISION,
+            },
+        ]
+    }
+
+    pub(super) fn fields(transaction: &Transaction) -> Vec<Field<'_>> {
+        let (record_type, record_code) = transaction.record_type();
+        let mut fields = vec![Field::AlphaNumber { value: record_type }];
+        match transaction {
+            Transaction::Purchase(t) => {
+                fields.extend([
+                    Field::Date(t.base.operation_date),
+                    Field::AlphaNumber { value: record_code },
+                    Field::DecimalNumber {
+                        value: &t.operation_value,
+                        precision: 2,
+                    },
+                    fees_field(&t.base.operation_fees),
+                    Field::AlphaNumber {
+                        value: &t.base.crypto_symbol,
+                    },
+                    Field::DecimalNumber {
+                        value: &t.base.crypto_amount,
+                        precision: AMOUNT_PRECISION,
+                    },
+                ]);
+                fields.extend(exchange_fields(&t.buyer_exchange));
+            }
+            Transaction::Sale(t) => {
+                fields.extend([
+                    Field::Date(t.base.operation_date),
+                    Field::AlphaNumber { value: record_code },
+                    Field::DecimalNumber {
+                        value: &t.operation_value,
+                        precision: 2,
+                    },
+                    fees_field(&t.base.operation_fees),
+                    Field::AlphaNumber {
+                        value: &t.base.crypto_symbol,
+                    },
+                    Field::DecimalNumber {
+                        value: &t.base.crypto_amount,
+                        precision: SALE_AMOUNT_PRECISION,
+                    },
+                ]);
+                fields.extend(exchange_fields(&t.seller_exchange));
+            }
+            Transaction::Swap(t) => {
+                fields.extend([
+                    Field::Date(t.operation_date),
+                    Field::AlphaNumber { value: record_code },
+                    fees_field(&t.operation_fees),
+                    Field::AlphaNumber {
+                        value: &t.received_crypto_symbol,
+                    },
+                    Field::DecimalNumber {
+                        value: &t.received_crypto_amount,
+                        precision: AMOUNT_PRECISION,
+                    },
+                    Field::AlphaNumber {
+                        value: &t.given_crypto_symbol,
+                    },
+                    Field::DecimalNumber {
+                        value: &t.given_crypto_amount,
+                        precision: AMOUNT_PRECISION,
+                    },
+                ]);
+                fields.extend(exchange_fields(&t.exchange));
+            }
+            Transaction::TransferToExchange(t) => {
+                fields.extend(common_fields(&t.base, record_code));
+                fields.extend([
+                    t.origin_wallet
+                        .as_ref()
+                        .map_or(Field::Empty, |w| Field::AlphaNumber { value: w }),
+                    t.origin_exchange_name
+                        .as_ref()
+                        .map_or(Field::Empty, |n| Field::AlphaNumber { value: n }),
+                ]);
+            }
+            Transaction::WithdrawalFromExchange(t) => {
+                fields.extend(common_fields(&t.base, record_code));
+                fields.extend(exchange_fields(&t.origin_exchange));
+            }
+            Transaction::CryptoPaymentReceiver(t) => {
+                fields.extend(common_fields(&t.base, record_code));
+                fields.extend(exchange_fields(&t.receiver_exchange));
+            }
+            Transaction::CryptoPaymentSender(t) => {
+                fields.extend(common_fields(&t.base, record_code));
+                fields.extend(exchange_fields(&t.sender_exchange));
+            }
+        }
+        fields
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::transactions::SaleTransaction;
+    use chrono::NaiveDate;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_parse() {
+        for layout in Layout::ALL {
+            assert_eq!(Layout::parse(&layout.to_string()).unwrap(), layout);
+        }
+        assert!(matches!(Layout::parse("1999"), Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn test_v2019_sale() {
+        let sale = Transaction::Sale(SaleTransaction {
+            base: TransactionBase {
+                operation_date: NaiveDate::from_ymd_opt(2024, 3, 1).unwrap(),
+                operation_fees: None,
+                crypto_symbol: "BTC".to_string(),
+                crypto_amount: dec!(0.5),
+            },
+            operation_value: dec!(150000),
+            seller_exchange: ExchangeInfo {
+                name: "Kraken".to_string(),
+                url: "https://www.kraken.com".to_string(),
+                country: "US".to_string(),
+            },
+        });
+        let fields = Layout::V2019.fields(&sale);
+        assert_eq!(
+            fields.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            [
+                "0120",
+                "01032024",
+                "I",
+                "150000,00",
+                "",
+                "BTC",
+                "0,500000000000",
+                "Kraken",
+                "https://www.kraken.com",
+                "US"
+            ]
+        );
+    }
+}
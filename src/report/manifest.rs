@@ -0,0 +1,163 @@
+//! Manifest written next to each report file, `<report>.manifest.json`, with
+//! the SHA-256 of the file, its number of lines and its number of records of
+//! each type, so the file uploaded to the RFB can later be checked for changes.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::error::{Error, Result};
+use crate::i18n::t;
+
+/// Checksum and counts of a report file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReportManifest {
+    /// Name of the report file, in the directory of the manifest
+    pub file: String,
+    /// SHA-256 of the file, in lowercase hexadecimal
+    pub sha256: String,
+    pub lines: usize,
+    /// Number of records per record type
+    pub record_counts: BTreeMap<String, usize>,
+}
+
+impl ReportManifest {
+    /// Computes the manifest of the content of a report file
+    pub fn of(file: &str, content: &[u8]) -> Self {
+        let sha256 = Sha256::digest(content)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        let mut lines = 0;
+        let mut record_counts = BTreeMap::new();
+        for line in content.split_inclusive(|&b| b == b'\n') {
+            lines += 1;
+            let record_type = line.split(|&b| b == b'|').next().unwrap_or_default();
+            let record_type = String::from_utf8_lossy(record_type).trim_end().to_string();
+            *record_counts.entry(record_type).or_default() += 1;
+        }
+        ReportManifest {
+            file: file.to_string(),
+            sha256,
+            lines,
+            record_counts,
+        }
+    }
+
+    /// Path of the manifest of a report file
+    pub fn path_for(report: &Path) -> PathBuf {
+        let mut name = report.file_name().unwrap_or_default().to_os_string();
+        name.push(".manifest.json");
+        report.with_file_name(name)
+    }
+
+    /// Computes the manifest of a report file as it is now
+    pub fn compute(report: &Path) -> Result<Self> {
+        let file = report.file_name().unwrap_or_default().to_string_lossy();
+        Ok(ReportManifest::of(&file, &fs::read(report)?))
+    }
+
+    /// Writes the manifest of a report file next to it, and returns its path
+    pub fn write_for(report: &Path) -> Result<PathBuf> {
+        let manifest = ReportManifest::compute(report)?;
+        let content = serde_json::to_string_pretty(&manifest)
+            .map_err(|e| Error::Validation(t!("failed to serialize {}: {}", "manifest", e)))?;
+        let path = ReportManifest::path_for(report);
+        fs::write(&path, content + "\n")?;
+        Ok(path)
+    }
+
+    /// Reads the manifest written next to a report file
+    pub fn read_for(report: &Path) -> Result<Self> {
+        let path = ReportManifest::path_for(report);
+        let content = fs::read_to_string(&path).map_err(|e| {
+            Error::Config(t!("failed to read the manifest {}: {}", path.display(), e))
+        })?;
+        serde_json::from_str(&content)
+            .map_err(|e| Error::Config(t!("corrupted manifest file {}: {}", path.display(), e)))
+    }
+
+    /// Describes how `other`, the manifest of the file as it is now, differs
+    /// from this one; empty if the file is unchanged
+    pub fn differences(&self, other: &ReportManifest) -> Vec<String> {
+        let mut differences = Vec::new();
+        if self.sha256 != other.sha256 {
+            differences.push(t!("SHA-256 is {}, expected {}", other.sha256, self.sha256));
+        }
+        if self.lines != other.lines {
+            differences.push(t!("{} lines, expected {}", other.lines, self.lines));
+        }
+        let record_types = self.record_counts.keys().chain(other.record_counts.keys());
+        for record_type in record_types.collect::<BTreeSet<_>>() {
+            let count = |manifest: &ReportManifest| {
+                manifest
+                    .record_counts
+                    .get(record_type)
+                    .copied()
+                    .unwrap_or(0)
+            };
+            if count(self) != count(other) {
+                differences.push(t!(
+                    "{} records of type {}, expected {}",
+                    count(other),
+                    record_type,
+                    count(self)
+                ));
+            }
+        }
+        differences
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest() {
+        let content = b"0110|01032024|BTC\r\n0110|02032024|BTC\r\n0510|03032024|BTC\r\n";
+        let manifest = ReportManifest::of("report.txt", content);
+        // The SHA-256 of the content, as given by sha256sum
+        assert_eq!(
+            manifest.sha256,
+            "17e04977f2a65211da27606aac7ba3a82cf56a8617e455eae02ed29ca87abd9d"
+        );
+        assert_eq!(manifest.lines, 3);
+        assert_eq!(
+            manifest.record_counts,
+            BTreeMap::from([("0110".to_string(), 2), ("0510".to_string(), 1)])
+        );
+        assert_eq!(
+            ReportManifest::path_for(Path::new("out/report.txt")),
+            Path::new("out/report.txt.manifest.json")
+        );
+        assert_eq!(ReportManifest::of("empty.txt", b"").lines, 0);
+
+        assert!(manifest.differences(&manifest).is_empty());
+        let altered =
+            ReportManifest::of("report.txt", b"0110|01032024|BTC\r\n0510|03032024|ETH\r\n");
+        let differences = manifest.differences(&altered);
+        assert_eq!(differences.len(), 3, "{:?}", differences);
+        assert!(differences[2].contains("0110"));
+    }
+
+    #[test]
+    fn test_write_and_read() {
+        let report =
+            std::env::temp_dir().join(format!("kraken2rfb-test-{}-report.txt", std::process::id()));
+        fs::write(&report, "0510|03032024|BTC\r\n").unwrap();
+        let path = ReportManifest::write_for(&report).unwrap();
+        let manifest = ReportManifest::read_for(&report).unwrap();
+        assert_eq!(manifest, ReportManifest::compute(&report).unwrap());
+        assert_eq!(manifest.file, report.file_name().unwrap().to_string_lossy());
+
+        fs::write(&report, "0510|03032024|ETH\r\n").unwrap();
+        let now = ReportManifest::compute(&report).unwrap();
+        assert_eq!(manifest.differences(&now).len(), 1);
+        fs::remove_file(&report).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,198 +1,643 @@
+//! Conversion of the activity of an exchange into the records of the report.
+
 pub mod encoding;
+pub mod inspect;
+pub mod interchange;
+pub mod layout;
+pub mod manifest;
+pub mod precision;
+pub mod preview;
+pub mod residuals;
+pub mod resolve;
+pub mod review;
+pub mod schema;
+pub mod stream;
+pub mod summary;
 pub mod transactions;
 
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
 use std::fs::File;
-use std::io::BufWriter;
-
-use crate::kraken::is_fiat;
-use crate::kraken_symbols::get_common_symbol;
-use crate::{exchange_rate::get_exchange_rate, kraken_pairs, to_decimal};
-use chrono::DateTime;
-use rust_decimal::Decimal;
-use serde_json::Value;
+use std::io::{BufWriter, Write};
+
+use chrono::{DateTime, FixedOffset, NaiveDate, Utc};
+
+use crate::checked;
+use crate::checkpoint::Checkpoint;
+use crate::error::{Error, Result};
+use crate::i18n::t;
+use crate::income::Income;
+use crate::period::BRASILIA;
+use crate::prompt::Prompter;
+use crate::source::{Activity, Conflict, Side, WalletTransfer, is_fiat};
+use crate::style;
+use crate::transfers::InternalTransfer;
+use layout::Layout;
+use precision::{AmountResidual, Truncation};
+use residuals::{Exact, Residual};
+use resolve::{InteractiveResolver, Resolver};
+use rust_decimal::{Decimal, RoundingStrategy};
 use transactions::{
     ExchangeInfo, PurchaseTransaction, SaleTransaction, SwapTransaction, Transaction,
     TransactionBase, TransferToExchangeTransaction, WithdrawalFromExchangeTransaction,
 };
 
-/// Get the standard Kraken exchange information
-fn kraken_exchange_info() -> ExchangeInfo {
-    ExchangeInfo {
-        name: "Kraken".to_string(),
-        url: "https://www.kraken.com".to_string(),
-        country: "US".to_string(),
+/// Record type a trade turns into, if any: none for fiat-fiat trades and
+/// those with the fiat as base asset
+fn trade_record_type(base: &str, quote: &str, side: Side) -> Option<&'static str> {
+    match (is_fiat(base), is_fiat(quote), side) {
+        (false, true, Side::Buy) => Some("0110"),
+        (false, true, Side::Sell) => Some("0120"),
+        (false, false, _) => Some("0210"),
+        _ => None,
+    }
+}
+
+/// How an entry is named in the errors about it, e.g. "trade TZX2WP-XSEOP-CEWAIE":
+/// by its own exchange ID, or by its time if it has none
+fn entry_name(kind: &str, ids: &[String], time: DateTime<Utc>) -> String {
+    match ids.first() {
+        Some(id) => format!("{} {}", kind, id),
+        None => t!("{} of {}", kind, time),
+    }
+}
+
+/// How the BRL values are rounded to cents
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    /// Half to the nearest even cent, e.g. 0,125 to 0,12
+    #[default]
+    HalfEven,
+    /// Half away from zero, e.g. 0,125 to 0,13
+    HalfUp,
+}
+
+/// How the fees of purchases and sales enter their values and amounts
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FeePolicy {
+    /// The value and amount are what was actually paid or received: the fee is
+    /// taken from them
+    #[default]
+    Net,
+    /// The value and amount are those of the order, with the fee only reported
+    /// in its own field
+    Gross,
+}
+
+/// What the fees paid with the fee credits of an exchange, like Kraken's
+/// KFEE, are worth in the fees of the records
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FeeCredits {
+    /// What the credits are worth in US dollars, converted to BRL
+    #[default]
+    Value,
+    /// Nothing, as the credits were given by the exchange
+    Zero,
+}
+
+impl FeeCredits {
+    /// Every policy
+    pub const ALL: [FeeCredits; 2] = [FeeCredits::Value, FeeCredits::Zero];
+
+    /// Parses a policy by its name, e.g. "zero", given as `what`
+    pub fn parse(what: &str, name: &str) -> Result<Self> {
+        FeeCredits::ALL
+            .into_iter()
+            .find(|policy| policy.to_string() == name)
+            .ok_or_else(|| {
+                let known = FeeCredits::ALL.map(|policy| policy.to_string());
+                Error::Config(t!(
+                    "invalid value for {}: {}",
+                    what,
+                    t!("{} (expected {})", name, known.join(", "))
+                ))
+            })
+    }
+}
+
+impl fmt::Display for FeeCredits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FeeCredits::Value => write!(f, "value"),
+            FeeCredits::Zero => write!(f, "zero"),
+        }
+    }
+}
+
+/// Settings that change how Kraken data is turned into report transactions
+#[derive(Debug, Default, Clone)]
+pub struct ProcessOptions {
+    /// Operations worth less than this many BRL are left out of the report
+    pub min_value: Option<Decimal>,
+    /// Only records of these types (e.g. "0410") are generated, if set
+    pub record_types: Option<Vec<String>>,
+    /// Kraken entries with any of these IDs (trade, order, reference or
+    /// transaction ID) are left out of the report
+    pub exclude_ids: BTreeSet<String>,
+    /// Print the intermediate values of the conversion to BRL
+    pub debug: bool,
+    /// Fail on the first warning instead of printing it and going on
+    pub fail_on_warning: bool,
+    pub rounding: Rounding,
+    pub fees: FeePolicy,
+    pub fee_credits: FeeCredits,
+    /// Add the rounding residuals of the purchases and sales of each asset in
+    /// a month to the last of them, so their total is exact
+    pub fold_residuals: bool,
+    /// Offset from UTC of the days the operations are reported on, Brasília
+    /// time if `None`
+    pub utc_offset: Option<FixedOffset>,
+    /// Layout the report is written in, which sets the decimals of the crypto
+    /// amounts
+    pub layout: Layout,
+    /// What is done with the crypto amounts with more decimals than that
+    pub truncation: Truncation,
+}
+
+impl ProcessOptions {
+    /// Offset from UTC of the days of the report
+    pub fn offset(&self) -> FixedOffset {
+        self.utc_offset.unwrap_or(BRASILIA)
+    }
+
+    /// The day of the report an operation at `time` is on
+    pub fn day(&self, time: DateTime<Utc>) -> NaiveDate {
+        time.with_timezone(&self.offset()).date_naive()
+    }
+
+    /// The options that change the report, by name, as an archived run
+    /// records them; those left at their defaults are left out, but the layout
+    pub fn settings(&self) -> BTreeMap<String, String> {
+        let mut settings = BTreeMap::from([("layout".to_string(), self.layout.to_string())]);
+        if let Some(min_value) = self.min_value {
+            settings.insert("min_value".to_string(), min_value.to_string());
+        }
+        if let Some(record_types) = &self.record_types {
+            settings.insert("record_types".to_string(), record_types.join(","));
+        }
+        if self.truncation != Truncation::default() {
+            settings.insert("truncation".to_string(), self.truncation.to_string());
+        }
+        if self.fee_credits != FeeCredits::default() {
+            settings.insert("fee_credits".to_string(), self.fee_credits.to_string());
+        }
+        if self.fold_residuals {
+            settings.insert("fold_residuals".to_string(), "true".to_string());
+        }
+        settings
+    }
+
+    fn wants_record_type(&self, record_type: &str) -> bool {
+        self.record_types
+            .as_ref()
+            .is_none_or(|types| types.iter().any(|t| t == record_type))
+    }
+
+    /// Rounds a BRL value to cents
+    fn brl(&self, value: Decimal) -> Decimal {
+        let strategy = match self.rounding {
+            Rounding::HalfEven => RoundingStrategy::MidpointNearestEven,
+            Rounding::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+        };
+        value.round_dp_with_strategy(2, strategy)
+    }
+
+    fn is_below_min_value(&self, brl_value: Decimal) -> bool {
+        self.min_value.is_some_and(|min| brl_value < min)
+    }
+
+    /// Prints a warning about the data, or fails with it under `--fail-on-warning`
+    pub fn warn(&self, message: String) -> Result<()> {
+        if self.fail_on_warning {
+            return Err(Error::Validation(t!(
+                "{} (an error because of --fail-on-warning)",
+                message
+            )));
+        }
+        eprintln!("{}", style::warning(&t!("Warning: {}", message)));
+        Ok(())
+    }
+
+    fn trace(&self, message: fmt::Arguments) {
+        if self.debug {
+            println!("### {}", message);
+        }
+    }
+
+    /// The ID by which an entry with these exchange IDs is excluded, if it is
+    fn excluded_id<'a>(&self, ids: &'a [String]) -> Option<&'a str> {
+        ids.iter()
+            .map(String::as_str)
+            .find(|id| self.exclude_ids.contains(*id))
+    }
+
+    /// Removes the excluded entries, collecting the IDs they were excluded by
+    fn without_excluded<T>(
+        &self,
+        entries: Vec<T>,
+        ids: fn(&T) -> &[String],
+        excluded_ids: &mut Vec<String>,
+    ) -> Vec<T> {
+        entries
+            .into_iter()
+            .filter(|entry| match self.excluded_id(ids(entry)) {
+                Some(id) => {
+                    excluded_ids.push(id.to_string());
+                    false
+                }
+                None => true,
+            })
+            .collect()
+    }
+}
+
+/// An operation that was left out of the report for being below the minimum value
+#[derive(Debug)]
+pub struct SkippedTransaction {
+    pub transaction: Transaction,
+    /// Value of the operation in BRL
+    pub brl_value: Decimal,
+}
+
+/// Transactions resulting from processing Kraken data
+#[derive(Debug, Default)]
+pub struct ProcessedData {
+    /// Transactions to be written to the report
+    pub transactions: Vec<Transaction>,
+    /// Operations left out because of `ProcessOptions::min_value`
+    pub below_min_value: Vec<SkippedTransaction>,
+    /// IDs of the Kraken entries left out because of `ProcessOptions::exclude_ids`
+    pub excluded_ids: Vec<String>,
+    /// Withdrawals matched to deposits on another exchange of the report,
+    /// which move the assets between the user's own accounts
+    pub internal_transfers: Vec<InternalTransfer>,
+    /// What rounding to cents left out of the purchases and sales
+    pub residuals: Vec<Residual>,
+    /// What rounding the crypto amounts left out, with [`Truncation::Accumulate`]
+    pub amount_residuals: Vec<AmountResidual>,
+    /// The crypto income, which has no record in the report
+    pub income: Vec<Income>,
+    /// Moves between the wallets of an account, which have no record either
+    pub wallet_transfers: Vec<WalletTransfer>,
+    /// Number of crypto deposits and withdrawals by the network they were
+    /// made on, for those the exchange names it
+    pub transfer_networks: BTreeMap<String, usize>,
+}
+
+impl ProcessedData {
+    fn count_network(&mut self, network: &Option<String>) {
+        if let Some(network) = network {
+            *self.transfer_networks.entry(network.clone()).or_default() += 1;
+        }
+    }
+
+    /// Adds a transaction to the report, unless it is below the minimum value.
+    /// Returns whether it was added.
+    fn add(
+        &mut self,
+        options: &ProcessOptions,
+        transaction: Transaction,
+        brl_value: Decimal,
+    ) -> bool {
+        if options.is_below_min_value(brl_value) {
+            self.below_min_value.push(SkippedTransaction {
+                transaction,
+                brl_value,
+            });
+            false
+        } else {
+            self.transactions.push(transaction);
+            true
+        }
+    }
+
+    /// Adds a purchase or sale, keeping its exact BRL values for the residuals
+    fn add_trade(
+        &mut self,
+        options: &ProcessOptions,
+        transaction: Transaction,
+        exact: Exact,
+        exacts: &mut Vec<Exact>,
+    ) {
+        if self.add(options, transaction, exact.value) {
+            exacts.push(Exact {
+                index: self.transactions.len() - 1,
+                ..exact
+            });
+        }
+    }
+}
+
+/// Process the activity of an exchange into BCB report transactions, fetching
+/// the rates and asking the user through the checkpoint
+pub fn process_activity(
+    activity: Activity,
+    exchange: &ExchangeInfo,
+    options: &ProcessOptions,
+    prompter: &mut Prompter,
+    checkpoint: &mut Checkpoint,
+) -> Result<ProcessedData> {
+    let mut resolver = interactive_resolver(options, prompter, checkpoint);
+    convert_activity(activity, exchange, options, &mut resolver)
+}
+
+/// The resolver of [`process_activity`], e.g. for
+/// [`crate::builder::ReportBuilder::stream`]: the rates of the providers and
+/// the answers of the user, reusing and saving those of the checkpoint
+pub fn interactive_resolver<'a>(
+    options: &'a ProcessOptions,
+    prompter: &'a mut Prompter,
+    checkpoint: &'a mut Checkpoint,
+) -> impl Resolver + 'a {
+    InteractiveResolver {
+        options,
+        prompter,
+        checkpoint,
+    }
+}
+
+/// Removes the entries imported more than once, warning about the repeated
+/// ones whose data differs
+pub(crate) fn dedup_activity(activity: Activity, options: &ProcessOptions) -> Result<Activity> {
+    let (activity, conflicts) = activity.dedup();
+    for conflict in conflicts {
+        let (id, first, other) = match &conflict {
+            Conflict::Deposit(a, b) => (&a.ids[0], format!("{:?}", a), format!("{:?}", b)),
+            Conflict::Withdrawal(a, b) => (&a.ids[0], format!("{:?}", a), format!("{:?}", b)),
+            Conflict::Trade(a, b) => (&a.ids[0], format!("{:?}", a), format!("{:?}", b)),
+            Conflict::Income(a, b) => (&a.ids[0], format!("{:?}", a), format!("{:?}", b)),
+            Conflict::WalletTransfer(a, b) => (&a.ids[0], format!("{:?}", a), format!("{:?}", b)),
+        };
+        options.warn(t!(
+            "the entry {} was imported more than once with different data, keeping the first:\n  {}\n  {}",
+            id,
+            first,
+            other
+        ))?;
     }
+    Ok(activity)
 }
 
-/// Process Kraken data into BCB report transactions
-pub fn process_kraken_data(
-    deposits: Vec<Value>,
-    withdrawals: Vec<Value>,
-    trades: Vec<Value>,
-) -> Vec<Transaction> {
-    let mut transactions = Vec::new();
+/// Converts the activity of an exchange into BCB report transactions, with the
+/// rates and answers given by `resolver`. This is pure computation: all the
+/// I/O is up to the resolver.
+pub fn convert_activity(
+    activity: Activity,
+    exchange: &ExchangeInfo,
+    options: &ProcessOptions,
+    resolver: &mut (impl Resolver + ?Sized),
+) -> Result<ProcessedData> {
+    let mut processed = ProcessedData::default();
+    let activity = dedup_activity(activity, options)?;
+
+    // Excluded entries and records of unwanted types are skipped before any
+    // rate lookup or prompt
+    let excluded_ids = &mut processed.excluded_ids;
+    let deposits = options.without_excluded(activity.deposits, |d| &d.ids, excluded_ids);
+    let withdrawals = options.without_excluded(activity.withdrawals, |w| &w.ids, excluded_ids);
+    let trades = options.without_excluded(activity.trades, |t| &t.ids, excluded_ids);
+    let income = options.without_excluded(activity.income, |i| &i.ids, excluded_ids);
+    processed.wallet_transfers = activity.wallet_transfers;
+    let deposits = if options.wants_record_type("0410") {
+        deposits
+    } else {
+        Vec::new()
+    };
+    let withdrawals = if options.wants_record_type("0510") {
+        withdrawals
+    } else {
+        Vec::new()
+    };
 
     // Process deposits (only non-fiat)
     for deposit in deposits {
-        let asset = get_common_symbol(deposit["asset"].as_str().unwrap()).unwrap();
+        let asset = deposit.asset.as_str();
         if !is_fiat(asset) {
-            let amount = deposit["amount"]
-                .as_str()
-                .unwrap()
-                .parse::<Decimal>()
-                .unwrap();
-            let fee = deposit["fee"].as_str().unwrap().parse::<Decimal>().unwrap();
-            let time = DateTime::from_timestamp(deposit["time"].as_u64().unwrap() as i64, 0)
-                .unwrap()
-                .date_naive();
-
-            let transfer = Transaction::TransferToExchange(TransferToExchangeTransaction {
+            processed.count_network(&deposit.network);
+            let (amount, fee) = (deposit.amount, deposit.fee);
+            let time = options.day(deposit.time);
+            let what = entry_name(&t!("deposit"), &deposit.ids, deposit.time);
+
+            // Deposits without a fee don't need a rate otherwise, so it is
+            // only looked up for one or when filtering
+            let brl_rate = if !fee.is_zero() || options.min_value.is_some() {
+                Some(resolver.rate(asset, time)?)
+            } else {
+                None
+            };
+
+            // Convert fee from crypto to BRL, like the withdrawal fees
+            let brl_fee = match brl_rate {
+                Some(brl_rate) => {
+                    let brl_fee = checked::mul(fee, brl_rate, &what)?;
+                    options.trace(format_args!("Deposit asset: {asset}"));
+                    options.trace(format_args!(
+                        "Original fee: {fee} {asset}, converted fee: {brl_fee} BRL"
+                    ));
+                    options.brl(brl_fee)
+                }
+                None => Decimal::ZERO,
+            };
+
+            let mut transfer = TransferToExchangeTransaction {
                 base: TransactionBase {
                     operation_date: time,
-                    operation_fees: Some(fee),
+                    operation_fees: Some(brl_fee),
                     crypto_symbol: asset.to_string(),
                     crypto_amount: amount,
                 },
                 origin_wallet: None,
                 origin_exchange_name: None,
-            });
+                deposit_id: deposit.ids.first().cloned(),
+            };
+
+            if let Some(brl_rate) = brl_rate
+                && options.min_value.is_some()
+            {
+                let brl_value = checked::mul(amount, brl_rate, &what)?;
+                if options.is_below_min_value(brl_value) {
+                    processed.below_min_value.push(SkippedTransaction {
+                        transaction: Transaction::TransferToExchange(transfer),
+                        brl_value,
+                    });
+                    continue;
+                }
+            }
+
+            if let Some(origin) = &deposit.origin_exchange {
+                // Matched to a withdrawal from another exchange of the report
+                transfer.origin_exchange_name = Some(origin.clone());
+            } else if let Some(wallet) = &deposit.origin_wallet {
+                // Matched to a transfer from a wallet of the user
+                transfer.origin_wallet = Some(wallet.clone());
+            } else {
+                // The exchange doesn't know where the deposit came from, only the user does
+                let description = match &deposit.network {
+                    Some(network) => {
+                        t!("Deposit of {} {} by {} on {}", amount, asset, network, time)
+                    }
+                    None => t!("Deposit of {} {} on {}", amount, asset, time),
+                };
+                transfer.origin_wallet =
+                    resolver.answer(&t!("{}: origin wallet (empty if unknown):", description))?;
+                if transfer.origin_wallet.is_none() {
+                    transfer.origin_exchange_name = resolver.answer(&t!(
+                        "{}: origin exchange name (empty if unknown):",
+                        description
+                    ))?;
+                }
+            }
 
-            transactions.push(transfer);
+            processed
+                .transactions
+                .push(Transaction::TransferToExchange(transfer));
         }
     }
 
     // Process withdrawals (only non-fiat)
     for withdrawal in withdrawals {
-        let asset = get_common_symbol(withdrawal["asset"].as_str().unwrap()).unwrap();
+        let asset = withdrawal.asset.as_str();
         if !is_fiat(asset) {
-            let amount = withdrawal["amount"]
-                .as_str()
-                .unwrap()
-                .parse::<Decimal>()
-                .unwrap();
-            let fee = withdrawal["fee"]
-                .as_str()
-                .unwrap()
-                .parse::<Decimal>()
-                .unwrap();
-            let time = DateTime::from_timestamp(withdrawal["time"].as_u64().unwrap() as i64, 0)
-                .unwrap()
-                .date_naive();
+            processed.count_network(&withdrawal.network);
+            let (amount, fee) = (withdrawal.amount, withdrawal.fee);
+            let time = options.day(withdrawal.time);
+            let what = entry_name(&t!("withdrawal"), &withdrawal.ids, withdrawal.time);
 
             // Convert fee from crypto to BRL
-            let (_rate_date, brl_rate) = get_exchange_rate(time, asset).unwrap_or_else(|e| {
-                panic!(
-                    "Failed to get exchange rate for {} on {}: {}",
-                    asset, time, e
-                )
-            });
+            let brl_rate = resolver.rate(asset, time)?;
+            let brl_fee = checked::mul(fee, brl_rate, &what)?;
 
-            println!("### Withdrawal asset: {asset}");
-            println!(
-                "### Original fee: {fee} {asset}, converted fee: {} BRL",
-                fee * brl_rate
-            );
+            options.trace(format_args!("Withdrawal asset: {asset}"));
+            if fee.is_zero() {
+                options.trace(format_args!("No fee given, taken as zero"));
+            }
+            options.trace(format_args!(
+                "Original fee: {fee} {asset}, converted fee: {brl_fee} BRL"
+            ));
 
+            let brl_value = checked::mul(amount, brl_rate, &what)?;
             let withdrawal =
                 Transaction::WithdrawalFromExchange(WithdrawalFromExchangeTransaction {
                     base: TransactionBase {
                         operation_date: time,
-                        operation_fees: Some(fee * brl_rate),
+                        operation_fees: Some(options.brl(brl_fee)),
                         crypto_symbol: asset.to_string(),
                         crypto_amount: amount,
                     },
-                    origin_exchange: kraken_exchange_info(),
+                    origin_exchange: exchange.clone(),
                 });
 
-            transactions.push(withdrawal);
+            processed.add(options, withdrawal, brl_value);
         }
     }
 
     // Process trades
+    let mut exacts = Vec::new();
     for trade in trades {
-        let pair = trade["pair"].as_str().unwrap();
-        let (base, quote) = kraken_pairs::parse_pair(pair).unwrap();
-        let vol = trade["vol"].as_str().unwrap().parse::<Decimal>().unwrap(); // BASE amount
-        let cost = trade["cost"].as_str().unwrap().parse::<Decimal>().unwrap(); // QUOTE amount
-        let fee = trade["fee"].as_str().unwrap().parse::<Decimal>().unwrap(); // QUOTE amount
-        let price = trade["price"].as_str().unwrap().parse::<Decimal>().unwrap(); // QUOTE / BASE
-        let time = DateTime::from_timestamp(int_part(to_decimal(&trade["time"])), 0)
-            .unwrap()
-            .date_naive();
-        let trade_type = trade["type"].as_str().unwrap();
-
-        println!("### Trade pair: {pair}");
+        let (base, quote) = (trade.base.as_str(), trade.quote.as_str());
+        let vol = trade.volume; // BASE amount
+        let cost = trade.cost; // QUOTE amount
+        let fee = trade.fee; // QUOTE amount
+        let price = trade.price; // QUOTE / BASE
+        let time = options.day(trade.time);
+        let what = entry_name(&t!("trade"), &trade.ids, trade.time);
+
+        if trade_record_type(base, quote, trade.side).is_some_and(|t| !options.wants_record_type(t))
+        {
+            continue;
+        }
+
+        options.trace(format_args!("Trade pair: {base}/{quote}"));
+        if fee.is_zero() && trade.credit_fee_usd.is_zero() {
+            options.trace(format_args!("No fee given, taken as zero"));
+        }
+        // The fee paid with fee credits only adds to the fees: the amounts
+        // traded are whole
+        let credit_fee /* BRL */ = match options.fee_credits {
+            _ if trade.credit_fee_usd.is_zero() || is_fiat(base) => Decimal::ZERO,
+            FeeCredits::Value => {
+                checked::mul(trade.credit_fee_usd, resolver.rate("USD", time)?, &what)?
+            }
+            FeeCredits::Zero => Decimal::ZERO,
+        };
+        if !trade.credit_fee_usd.is_zero() {
+            options.trace(format_args!(
+                "Fee paid with fee credits: {} USD, taken as {credit_fee} BRL",
+                trade.credit_fee_usd
+            ));
+        }
 
         match (is_fiat(base), is_fiat(quote)) {
             // Crypto-Fiat trade
             (false, true) => {
                 // Calculate net amounts (after fees)
-                let operation_value = cost - fee; // QUOTE amount
-                let crypto_amount = vol - (fee / price); // BASE amount
-                let (_rate_date, brl_rate /* BRL / QUOTE */) = get_exchange_rate(time, quote)
-                    .unwrap_or_else(|e| {
-                        panic!(
-                            "Failed to get exchange rate for {} on {}: {}",
-                            quote, time, e
-                        )
-                    });
+                let (operation_value /* QUOTE */, crypto_amount /* BASE */) = match options.fees {
+                    FeePolicy::Net => (
+                        checked::sub(cost, fee, &what)?,
+                        checked::sub(vol, checked::div(fee, price, &what)?, &what)?,
+                    ),
+                    FeePolicy::Gross => (cost, vol),
+                };
+                let brl_rate /* BRL / QUOTE */ = resolver.rate(quote, time)?;
+                let brl_fee = checked::mul(fee, brl_rate, &what)?;
+                let brl_value = checked::mul(operation_value, brl_rate, &what)?;
 
-                println!(
-                    "### Original fee: {fee} {quote}, converted fee: {} BRL",
-                    fee * brl_rate
-                );
-                println!(
-                    "### Operation value: {operation_value} {quote}, converted value: {} BRL",
-                    operation_value * brl_rate
-                );
-
-                match trade_type {
-                    "buy" => {
+                options.trace(format_args!(
+                    "Original fee: {fee} {quote}, converted fee: {brl_fee} BRL"
+                ));
+                options.trace(format_args!(
+                    "Operation value: {operation_value} {quote}, converted value: {brl_value} BRL"
+                ));
+
+                let fees = checked::add(brl_fee, credit_fee, &what)?;
+                let exact = Exact {
+                    index: 0,
+                    value: brl_value,
+                    fees,
+                };
+                match trade.side {
+                    Side::Buy => {
                         let purchase = Transaction::Purchase(PurchaseTransaction {
                             base: TransactionBase {
                                 operation_date: time,
-                                operation_fees: Some(fee * brl_rate),
+                                operation_fees: Some(options.brl(fees)),
                                 crypto_symbol: base.to_string(),
                                 crypto_amount,
                             },
-                            operation_value: operation_value * brl_rate,
-                            buyer_exchange: kraken_exchange_info(),
+                            operation_value: options.brl(brl_value),
+                            buyer_exchange: exchange.clone(),
                         });
-                        transactions.push(purchase);
+                        processed.add_trade(options, purchase, exact, &mut exacts);
                     }
-                    "sell" => {
+                    Side::Sell => {
                         let sale = Transaction::Sale(SaleTransaction {
                             base: TransactionBase {
                                 operation_date: time,
-                                operation_fees: Some(fee * brl_rate),
+                                operation_fees: Some(options.brl(fees)),
                                 crypto_symbol: base.to_string(),
                                 crypto_amount,
                             },
-                            operation_value: operation_value * brl_rate,
-                            seller_exchange: kraken_exchange_info(),
+                            operation_value: options.brl(brl_value),
+                            seller_exchange: exchange.clone(),
                         });
-                        transactions.push(sale);
+                        processed.add_trade(options, sale, exact, &mut exacts);
                     }
-                    _ => panic!("Unknown trade type: {}", trade_type),
                 }
             }
             // Crypto-Crypto trade
             (false, false) => {
                 // Convert fee to BRL using the base currency rate
-                let (_rate_date, base_brl_rate) =
-                    get_exchange_rate(time, base).unwrap_or_else(|e| {
-                        panic!(
-                            "Failed to get exchange rate for {} on {}: {}",
-                            base, time, e
-                        )
-                    });
+                let base_brl_rate = resolver.rate(base, time)?;
+                let brl_fee = checked::mul(fee, base_brl_rate, &what)?;
 
-                let operation_fees = Some(fee * base_brl_rate);
-                println!("### Original fee: {fee} {quote}, converted fee: {operation_fees:?} BRL");
-                let exchange = kraken_exchange_info();
+                let operation_fees = Some(options.brl(checked::add(brl_fee, credit_fee, &what)?));
+                options.trace(format_args!(
+                    "Original fee: {fee} {quote}, converted fee: {operation_fees:?} BRL"
+                ));
+                let exchange = exchange.clone();
 
-                let swap = Transaction::Swap(if trade_type == "buy" {
-                    SwapTransaction {
+                let swap = Transaction::Swap(match trade.side {
+                    Side::Buy => SwapTransaction {
                         operation_date: time,
                         operation_fees,
                         received_crypto_symbol: base.to_string(),
@@ -200,9 +645,8 @@ pub fn process_kraken_data(
                         given_crypto_symbol: quote.to_string(),
                         given_crypto_amount: cost,
                         exchange,
-                    }
-                } else if trade_type == "sell" {
-                    SwapTransaction {
+                    },
+                    Side::Sell => SwapTransaction {
                         operation_date: time,
                         operation_fees,
                         received_crypto_symbol: quote.to_string(),
@@ -210,39 +654,186 @@ pub fn process_kraken_data(
                         given_crypto_symbol: base.to_string(),
                         given_crypto_amount: vol,
                         exchange,
-                    }
-                } else {
-                    panic!("Unknown trade type: {}", trade_type);
+                    },
                 });
-                transactions.push(swap);
+                processed.add(options, swap, checked::mul(vol, base_brl_rate, &what)?);
             }
             // Fiat-Crypto trade (should be handled by the other case)
             (true, false) => {
-                // This case should not happen as Kraken always puts the base currency first
-                panic!("Unexpected Fiat-Crypto trade pair: {}", pair);
+                // This case should not happen as exchanges always put the crypto first
+                return Err(Error::Validation(t!(
+                    "unexpected Fiat-Crypto trade pair: {}",
+                    format!("{}/{}", base, quote)
+                )));
             }
             // Fiat-Fiat trade (should be ignored)
             (true, true) => continue,
         }
     }
 
-    transactions.sort_unstable_by_key(|t| t.record_type().0);
-    transactions
+    // The income is valued on the day received, its cost for the gains (only
+    // non-fiat)
+    for income in income {
+        let asset = income.asset.as_str();
+        if !is_fiat(asset) {
+            let date = options.day(income.time);
+            let brl_rate = resolver.rate(asset, date)?;
+            let what = entry_name(&t!("income"), &income.ids, income.time);
+            let brl_value = checked::mul(income.amount, brl_rate, &what)?;
+            options.trace(format_args!(
+                "Income of {} {asset} ({:?}), worth {brl_value} BRL",
+                income.amount, income.kind
+            ));
+            processed.income.push(Income {
+                date,
+                kind: income.kind,
+                asset: asset.to_string(),
+                amount: income.amount,
+                value: options.brl(brl_value),
+            });
+        }
+    }
+
+    processed.residuals = residuals::settle(&mut processed.transactions, &exacts, options);
+    processed.amount_residuals = precision::settle(&mut processed.transactions, options)?;
+    processed
+        .transactions
+        .sort_unstable_by_key(|t| t.record_type().0);
+    Ok(processed)
 }
 
-pub fn generate_report(transactions: Vec<Transaction>, out_file: &str) -> std::io::Result<()> {
-    let mut file = BufWriter::new(File::create(out_file)?);
+pub fn generate_report(transactions: &[Transaction], out_file: &str, layout: Layout) -> Result<()> {
+    write_report(
+        transactions,
+        layout,
+        &mut BufWriter::new(File::create(out_file)?),
+    )
+}
 
+/// Writes the records of the transactions, in order, in the given layout
+pub fn write_report(
+    transactions: &[Transaction],
+    layout: Layout,
+    writer: &mut impl Write,
+) -> Result<()> {
     for transaction in transactions {
-        transaction.write_transaction(&mut file)?;
+        transaction.write_transaction(writer, layout)?;
     }
-
+    writer.flush()?;
     Ok(())
 }
 
-/// Get the integer part of a Decimal
-fn int_part(d: Decimal) -> i64 {
-    let d = d.trunc();
-    assert_eq!(d.scale(), 0, "Decimal must be an integer");
-    d.mantissa() as i64
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::{NormalizedDeposit, NormalizedTrade, NormalizedWithdrawal};
+    use resolve::Resolved;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_overflow_names_the_trade() {
+        let date = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        let activity = Activity {
+            trades: vec![NormalizedTrade {
+                base: "SHIB".to_string(),
+                quote: "EUR".to_string(),
+                side: Side::Sell,
+                volume: dec!(1000000000000000000000),
+                cost: dec!(100000000000000000000000),
+                fee: Decimal::ZERO,
+                credit_fee_usd: Decimal::ZERO,
+                price: dec!(100),
+                time: "2024-03-01T15:00:00Z".parse().unwrap(),
+                ids: vec!["TX1".to_string()],
+            }],
+            ..Activity::default()
+        };
+        let exchange = ExchangeInfo {
+            name: "Kraken".to_string(),
+            url: "https://www.kraken.com".to_string(),
+            country: "US".to_string(),
+        };
+        let mut resolved = Resolved::default();
+        resolved
+            .rates
+            .insert(("EUR".to_string(), date), dec!(5400000.123));
+        let result = convert_activity(
+            activity,
+            &exchange,
+            &ProcessOptions::default(),
+            &mut resolved,
+        );
+        assert!(matches!(result, Err(Error::Validation(message)) if message.contains("TX1")));
+    }
+
+    #[test]
+    fn test_min_value() {
+        let date = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        let withdrawal = |amount, id: &str| NormalizedWithdrawal {
+            asset: "BTC".to_string(),
+            amount,
+            fee: Decimal::ZERO,
+            time: "2024-03-01T15:00:00Z".parse().unwrap(),
+            ids: vec![id.to_string()],
+            destination: None,
+            network: None,
+        };
+        let deposit = |amount, id: &str| NormalizedDeposit {
+            asset: "BTC".to_string(),
+            amount,
+            fee: Decimal::ZERO,
+            time: "2024-03-01T15:00:00Z".parse().unwrap(),
+            ids: vec![id.to_string()],
+            origin_exchange: Some("Binance".to_string()),
+            origin_wallet: None,
+            network: None,
+        };
+        let activity = Activity {
+            deposits: vec![deposit(dec!(0.0001), "D1"), deposit(dec!(0.01), "D2")],
+            withdrawals: vec![withdrawal(dec!(0.0001), "W1"), withdrawal(dec!(0.01), "W2")],
+            ..Activity::default()
+        };
+        let exchange = ExchangeInfo {
+            name: "Kraken".to_string(),
+            url: "https://www.kraken.com".to_string(),
+            country: "US".to_string(),
+        };
+        let mut resolved = Resolved::default();
+        resolved
+            .rates
+            .insert(("BTC".to_string(), date), dec!(300000));
+        let options = ProcessOptions {
+            min_value: Some(dec!(50)),
+            ..ProcessOptions::default()
+        };
+        let processed = convert_activity(activity, &exchange, &options, &mut resolved).unwrap();
+
+        // R$ 3.000,00 is reported, R$ 30,00 is left out
+        let [
+            Transaction::TransferToExchange(deposit),
+            Transaction::WithdrawalFromExchange(reported),
+        ] = processed.transactions.as_slice()
+        else {
+            panic!(
+                "expected a deposit and a withdrawal, got {:?}",
+                processed.transactions
+            );
+        };
+        assert_eq!(reported.base.crypto_amount, dec!(0.01));
+        // Only the reported deposit keeps its ID for the registry
+        assert_eq!(deposit.deposit_id.as_deref(), Some("D2"));
+        let [skipped_deposit, skipped] = processed.below_min_value.as_slice() else {
+            panic!("expected two skipped, got {:?}", processed.below_min_value);
+        };
+        assert!(matches!(
+            &skipped_deposit.transaction,
+            Transaction::TransferToExchange(t) if t.base.crypto_amount == dec!(0.0001)
+        ));
+        assert!(matches!(
+            &skipped.transaction,
+            Transaction::WithdrawalFromExchange(t) if t.base.crypto_amount == dec!(0.0001)
+        ));
+        let total: Decimal = processed.below_min_value.iter().map(|s| s.brl_value).sum();
+        assert_eq!(total, dec!(60));
+    }
 }
@@ -0,0 +1,234 @@
+//! What the decimals of the crypto amounts in the report leave out of the
+//! amounts in the data. The layout writes them with a fixed number of
+//! decimals, 10 in most records, so an amount with more is rounded on the way
+//! and the report declares a slightly different quantity than the exchange
+//! recorded. [`Truncation`] says what is done about it.
+
+use std::fmt;
+
+use rust_decimal::Decimal;
+
+use super::ProcessOptions;
+use super::transactions::Transaction;
+use crate::error::{Error, Result};
+use crate::i18n::t;
+
+/// What is done with a crypto amount that has more decimals than its record
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Truncation {
+    /// It is rounded when written, with a warning giving the difference
+    #[default]
+    Warn,
+    /// The report isn't generated
+    Error,
+    /// It is rounded, and the difference is carried to the next amount of the
+    /// same asset in the same record type, so their total stays exact
+    Accumulate,
+}
+
+impl Truncation {
+    /// Every policy
+    pub const ALL: [Truncation; 3] = [Truncation::Warn, Truncation::Error, Truncation::Accumulate];
+
+    /// Parses a policy by its name, e.g. "accumulate", given as `what`
+    pub fn parse(what: &str, name: &str) -> Result<Self> {
+        Truncation::ALL
+            .into_iter()
+            .find(|truncation| truncation.to_string() == name)
+            .ok_or_else(|| {
+                let known = Truncation::ALL.map(|truncation| truncation.to_string());
+                Error::Config(t!(
+                    "invalid value for {}: {}",
+                    what,
+                    t!("{} (expected {})", name, known.join(", "))
+                ))
+            })
+    }
+}
+
+impl fmt::Display for Truncation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Truncation::Warn => write!(f, "warn"),
+            Truncation::Error => write!(f, "error"),
+            Truncation::Accumulate => write!(f, "accumulate"),
+        }
+    }
+}
+
+/// What is left of the rounding of the crypto amounts of an asset in a record
+/// type with [`Truncation::Accumulate`], after the last of them
+#[derive(Debug, Clone, PartialEq)]
+pub struct AmountResidual {
+    /// E.g. "0410" for the deposits
+    pub record_type: &'static str,
+    pub asset: String,
+    /// The exact total minus the total written, in the asset
+    pub amount: Decimal,
+}
+
+/// The crypto amounts of a transaction, with their assets
+fn amounts(transaction: &mut Transaction) -> Vec<(&str, &mut Decimal)> {
+    let base = match transaction {
+        Transaction::Swap(t) => {
+            return vec![
+                (&t.received_crypto_symbol, &mut t.received_crypto_amount),
+                (&t.given_crypto_symbol, &mut t.given_crypto_amount),
+            ];
+        }
+        Transaction::Purchase(t) => &mut t.base,
+        Transaction::Sale(t) => &mut t.base,
+        Transaction::TransferToExchange(t) => &mut t.base,
+        Transaction::WithdrawalFromExchange(t) => &mut t.base,
+        Transaction::CryptoPaymentReceiver(t) => &mut t.base,
+        Transaction::CryptoPaymentSender(t) => &mut t.base,
+    };
+    vec![(&base.crypto_symbol, &mut base.crypto_amount)]
+}
+
+/// Applies `options.truncation` to the crypto amounts with more decimals than
+/// `options.layout` writes, in the order of the transactions. Returns the
+/// residuals left with [`Truncation::Accumulate`], without the zero ones.
+pub(super) fn settle(
+    transactions: &mut [Transaction],
+    options: &ProcessOptions,
+) -> Result<Vec<AmountResidual>> {
+    let mut residuals: Vec<AmountResidual> = Vec::new();
+    for transaction in transactions {
+        let (record_type, date) = (transaction.record_type().0, transaction.date());
+        let precision = options.layout.amount_precision(record_type);
+        for (asset, amount) in amounts(transaction) {
+            if options.truncation == Truncation::Accumulate {
+                let residual = match residuals
+                    .iter()
+                    .position(|r| (r.record_type, r.asset.as_str()) == (record_type, asset))
+                {
+                    Some(index) => &mut residuals[index],
+                    None => {
+                        residuals.push(AmountResidual {
+                            record_type,
+                            asset: asset.to_string(),
+                            amount: Decimal::ZERO,
+                        });
+                        residuals.last_mut().unwrap()
+                    }
+                };
+                let exact = *amount + residual.amount;
+                *amount = exact.round_dp(precision);
+                residual.amount = exact - *amount;
+                continue;
+            }
+            let rounded = amount.round_dp(precision);
+            if rounded == *amount {
+                continue;
+            }
+            let message = t!(
+                "the amount {} {} of the {} record of {} has more than {} decimals, and is written as {} ({} off)",
+                amount,
+                asset,
+                record_type,
+                date,
+                precision,
+                rounded,
+                rounded - *amount
+            );
+            match options.truncation {
+                Truncation::Error => {
+                    return Err(Error::Validation(t!(
+                        "{} (an error because of --truncation error)",
+                        message
+                    )));
+                }
+                _ => options.warn(message)?,
+            }
+        }
+    }
+    residuals.retain(|r| !r.amount.is_zero());
+    Ok(residuals)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::transactions::{TransactionBase, TransferToExchangeTransaction};
+    use chrono::NaiveDate;
+    use rust_decimal_macros::dec;
+
+    fn deposits(amounts: &[Decimal]) -> Vec<Transaction> {
+        amounts
+            .iter()
+            .map(|&amount| {
+                Transaction::TransferToExchange(TransferToExchangeTransaction {
+                    base: TransactionBase {
+                        operation_date: NaiveDate::from_ymd_opt(2024, 3, 1).unwrap(),
+                        operation_fees: None,
+                        crypto_symbol: "ETH".to_string(),
+                        crypto_amount: amount,
+                    },
+                    origin_wallet: None,
+                    origin_exchange_name: None,
+                    deposit_id: None,
+                })
+            })
+            .collect()
+    }
+
+    fn amount(transaction: &Transaction) -> Decimal {
+        match transaction {
+            Transaction::TransferToExchange(t) => t.base.crypto_amount,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        for truncation in Truncation::ALL {
+            assert_eq!(
+                Truncation::parse("--truncation", &truncation.to_string()).unwrap(),
+                truncation
+            );
+        }
+        assert!(matches!(
+            Truncation::parse("--truncation", "round"),
+            Err(Error::Config(_))
+        ));
+    }
+
+    #[test]
+    fn test_settle() {
+        let exact = [dec!(0.123456789012), dec!(0.123456789012), dec!(1.5)];
+        let options = |truncation| ProcessOptions {
+            truncation,
+            ..ProcessOptions::default()
+        };
+
+        let mut transactions = deposits(&exact);
+        let residuals = settle(&mut transactions, &options(Truncation::Warn)).unwrap();
+        assert!(residuals.is_empty());
+        assert_eq!(amount(&transactions[0]), exact[0]);
+
+        let mut transactions = deposits(&exact);
+        let error = settle(&mut transactions, &options(Truncation::Error));
+        assert!(matches!(error, Err(Error::Validation(_))));
+        let mut transactions = deposits(&exact[2..]);
+        assert!(settle(&mut transactions, &options(Truncation::Error)).is_ok());
+
+        // The 0.0000000000012 left out of the first is carried to the second
+        let mut transactions = deposits(&exact);
+        let residuals = settle(&mut transactions, &options(Truncation::Accumulate)).unwrap();
+        let written: Vec<Decimal> = transactions.iter().map(amount).collect();
+        assert_eq!(written, [dec!(0.1234567890), dec!(0.1234567890), dec!(1.5)]);
+        assert_eq!(
+            residuals,
+            [AmountResidual {
+                record_type: "0410",
+                asset: "ETH".to_string(),
+                amount: dec!(0.000000000024),
+            }]
+        );
+        assert_eq!(
+            written.iter().sum::<Decimal>() + residuals[0].amount,
+            exact.iter().sum::<Decimal>()
+        );
+    }
+}
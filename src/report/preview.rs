@@ -0,0 +1,210 @@
+//! Listing of the records a run would generate, without rates or prompts.
+
+use chrono::NaiveDate;
+
+use super::{ProcessOptions, trade_record_type};
+use crate::error::{Error, Result};
+use crate::i18n::t;
+use crate::source::{Activity, Side, is_fiat};
+
+/// A record that would be generated from the activity, without its BRL values
+#[derive(Debug)]
+pub struct RecordPreview {
+    pub record_type: &'static str,
+    pub date: NaiveDate,
+    /// What the record is about: operation, assets and amounts
+    pub description: String,
+}
+
+/// Lists the records `process_activity` would generate, without looking up
+/// any exchange rate or asking the user anything.
+///
+/// Since BRL values are unknown, `ProcessOptions::min_value` is not applied.
+pub fn preview_activity(
+    activity: &Activity,
+    options: &ProcessOptions,
+) -> Result<Vec<RecordPreview>> {
+    let mut previews = Vec::new();
+    let included = |ids: &[String]| options.excluded_id(ids).is_none();
+
+    if options.wants_record_type("0410") {
+        for deposit in activity.deposits.iter().filter(|d| included(&d.ids)) {
+            if !is_fiat(&deposit.asset) {
+                previews.push(RecordPreview {
+                    record_type: "0410",
+                    date: options.day(deposit.time),
+                    description: t!("deposit of {} {}", deposit.amount, deposit.asset),
+                });
+            }
+        }
+    }
+
+    if options.wants_record_type("0510") {
+        for withdrawal in activity.withdrawals.iter().filter(|w| included(&w.ids)) {
+            if !is_fiat(&withdrawal.asset) {
+                previews.push(RecordPreview {
+                    record_type: "0510",
+                    date: options.day(withdrawal.time),
+                    description: t!("withdrawal of {} {}", withdrawal.amount, withdrawal.asset),
+                });
+            }
+        }
+    }
+
+    for trade in activity.trades.iter().filter(|t| included(&t.ids)) {
+        let (base, quote) = (&trade.base, &trade.quote);
+        if is_fiat(base) && is_fiat(quote) {
+            continue;
+        }
+        let record_type = trade_record_type(base, quote, trade.side).ok_or_else(|| {
+            Error::Validation(t!(
+                "unexpected Fiat-Crypto trade pair: {}",
+                format!("{}/{}", base, quote)
+            ))
+        })?;
+        if !options.wants_record_type(record_type) {
+            continue;
+        }
+
+        let (vol, cost) = (trade.volume, trade.cost);
+        let description = match (record_type, trade.side) {
+            ("0110", _) => t!("purchase of {} {} for {} {}", vol, base, cost, quote),
+            ("0120", _) => t!("sale of {} {} for {} {}", vol, base, cost, quote),
+            (_, Side::Buy) => t!("swap of {} {} for {} {}", cost, quote, vol, base),
+            (_, Side::Sell) => t!("swap of {} {} for {} {}", vol, base, cost, quote),
+        };
+        previews.push(RecordPreview {
+            record_type,
+            date: options.day(trade.time),
+            description,
+        });
+    }
+
+    previews.sort_by_key(|p| (p.record_type, p.date));
+    Ok(previews)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::{NormalizedDeposit, NormalizedTrade, NormalizedWithdrawal};
+    use chrono::{DateTime, Utc};
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    fn deposit(asset: &str, amount: Decimal, time: DateTime<Utc>) -> NormalizedDeposit {
+        NormalizedDeposit {
+            asset: asset.to_string(),
+            amount,
+            fee: Decimal::ZERO,
+            time,
+            ids: Vec::new(),
+            origin_exchange: None,
+            origin_wallet: None,
+            network: None,
+        }
+    }
+
+    fn trade(pair: (&str, &str), side: Side, volume: Decimal, cost: Decimal) -> NormalizedTrade {
+        NormalizedTrade {
+            base: pair.0.to_string(),
+            quote: pair.1.to_string(),
+            side,
+            volume,
+            cost,
+            fee: Decimal::ZERO,
+            credit_fee_usd: Decimal::ZERO,
+            price: cost / volume,
+            time: "2024-03-01T12:00:00Z".parse().unwrap(),
+            ids: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_preview() {
+        let (day1, day2) = (
+            "2024-03-01T12:00:00Z".parse().unwrap(),
+            "2024-03-02T12:00:00Z".parse().unwrap(),
+        );
+        let swap = NormalizedTrade {
+            time: day2,
+            ids: vec!["TZX2WP-XSEOP-CEWAIE".to_string()],
+            ..trade(("ETH", "BTC"), Side::Sell, dec!(1), dec!(0.05))
+        };
+        let activity = Activity {
+            deposits: vec![
+                deposit("BTC", dec!(0.5), day1),
+                deposit("EUR", dec!(1000), day1),
+            ],
+            withdrawals: vec![NormalizedWithdrawal {
+                asset: "ETH".to_string(),
+                amount: dec!(2),
+                fee: dec!(0.01),
+                time: day2,
+                ids: vec!["FTQcuak-V6Za8qrWnhzTx67yYHz8Tg".to_string()],
+                destination: None,
+                network: None,
+            }],
+            trades: vec![
+                trade(("BTC", "EUR"), Side::Buy, dec!(0.1), dec!(5000)),
+                swap,
+            ],
+            income: Vec::new(),
+            wallet_transfers: Vec::new(),
+        };
+
+        let previews = preview_activity(&activity, &ProcessOptions::default()).unwrap();
+        let lines: Vec<String> = previews
+            .iter()
+            .map(|p| format!("{} {} {}", p.record_type, p.date, p.description))
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "0110 2024-03-01 purchase of 0.1 BTC for 5000 EUR",
+                "0210 2024-03-02 swap of 1 ETH for 0.05 BTC",
+                "0410 2024-03-01 deposit of 0.5 BTC",
+                "0510 2024-03-02 withdrawal of 2 ETH",
+            ]
+        );
+
+        let only_transfers = ProcessOptions {
+            record_types: Some(vec!["0410".to_string()]),
+            ..Default::default()
+        };
+        let previews = preview_activity(&activity, &only_transfers).unwrap();
+        assert_eq!(previews.len(), 1);
+        assert_eq!(previews[0].record_type, "0410");
+
+        let excluding = ProcessOptions {
+            exclude_ids: ["TZX2WP-XSEOP-CEWAIE", "FTQcuak-V6Za8qrWnhzTx67yYHz8Tg"]
+                .map(String::from)
+                .into(),
+            ..Default::default()
+        };
+        let previews = preview_activity(&activity, &excluding).unwrap();
+        let record_types: Vec<_> = previews.iter().map(|p| p.record_type).collect();
+        assert_eq!(record_types, ["0110", "0410"]);
+    }
+
+    #[test]
+    fn test_day() {
+        // Late in the evening in Brasília is already the next day in UTC
+        let activity = Activity {
+            deposits: vec![deposit(
+                "BTC",
+                dec!(0.5),
+                "2024-03-01T01:30:00Z".parse().unwrap(),
+            )],
+            ..Activity::default()
+        };
+        let previews = preview_activity(&activity, &ProcessOptions::default()).unwrap();
+        assert_eq!(previews[0].date.to_string(), "2024-02-29");
+        let utc = ProcessOptions {
+            utc_offset: Some("+00:00".parse().unwrap()),
+            ..Default::default()
+        };
+        let previews = preview_activity(&activity, &utc).unwrap();
+        assert_eq!(previews[0].date.to_string(), "2024-03-01");
+    }
+}
@@ -0,0 +1,192 @@
+//! What the rounding of the BRL values to cents leaves out of the purchases
+//! and sales. The value of a trade is converted to BRL with its fee taken
+//! from it or not, and each record is rounded on its own, so the records of a
+//! month may add up to a few cents more or less than their exact total. The
+//! difference is tracked by record type, asset and month, and can be folded
+//! into the last record of each, so the totals match exactly.
+
+use chrono::Datelike;
+use rust_decimal::Decimal;
+
+use super::ProcessOptions;
+use super::transactions::Transaction;
+
+/// The exact BRL values of a record of the report, before rounding
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Exact {
+    /// Position of the record in the transactions
+    pub index: usize,
+    pub value: Decimal,
+    pub fees: Decimal,
+}
+
+/// The rounding residual of the purchases or sales of an asset in a month:
+/// their exact total minus the total of their rounded values
+#[derive(Debug, Clone, PartialEq)]
+pub struct Residual {
+    /// "0110" for purchases, "0120" for sales
+    pub record_type: &'static str,
+    pub asset: String,
+    pub year: i32,
+    pub month: u32,
+    /// Of the operation values, in BRL
+    pub value: Decimal,
+    /// Of the fees, in BRL
+    pub fees: Decimal,
+    /// Whether it was added to the last record, so nothing is left out
+    pub folded: bool,
+}
+
+/// The operation value and the fees of a purchase or sale
+fn values(transaction: &mut Transaction) -> Option<(&mut Decimal, &mut Option<Decimal>)> {
+    match transaction {
+        Transaction::Purchase(t) => Some((&mut t.operation_value, &mut t.base.operation_fees)),
+        Transaction::Sale(t) => Some((&mut t.operation_value, &mut t.base.operation_fees)),
+        _ => None,
+    }
+}
+
+/// Adds up the residuals of the records with exact values, in their order,
+/// and folds them into the last record of each group if
+/// `options.fold_residuals`. The groups without residual are left out.
+pub(super) fn settle(
+    transactions: &mut [Transaction],
+    exact: &[Exact],
+    options: &ProcessOptions,
+) -> Vec<Residual> {
+    // Each group with the position of its last record
+    let mut residuals: Vec<(Residual, usize)> = Vec::new();
+    for entry in exact {
+        let transaction = &mut transactions[entry.index];
+        let (record_type, date) = (transaction.record_type().0, transaction.date());
+        let asset = transaction.assets()[0].to_string();
+        let Some((value, fees)) = values(transaction) else {
+            continue;
+        };
+        let value_residual = entry.value - *value;
+        let fees_residual = entry.fees - fees.unwrap_or_default();
+
+        let key = (record_type, asset.as_str(), date.year(), date.month());
+        match residuals
+            .iter_mut()
+            .find(|(r, _)| (r.record_type, r.asset.as_str(), r.year, r.month) == key)
+        {
+            Some((residual, last)) => {
+                residual.value += value_residual;
+                residual.fees += fees_residual;
+                *last = entry.index;
+            }
+            None => residuals.push((
+                Residual {
+                    record_type,
+                    asset,
+                    year: date.year(),
+                    month: date.month(),
+                    value: value_residual,
+                    fees: fees_residual,
+                    folded: false,
+                },
+                entry.index,
+            )),
+        }
+    }
+
+    residuals.retain(|(r, _)| !r.value.is_zero() || !r.fees.is_zero());
+    if options.fold_residuals {
+        for (residual, last) in &mut residuals {
+            if let Some((value, fees)) = values(&mut transactions[*last]) {
+                *value = options.brl(*value + residual.value);
+                if let Some(fees) = fees {
+                    *fees = options.brl(*fees + residual.fees);
+                }
+                residual.folded = true;
+            }
+        }
+    }
+    residuals
+        .into_iter()
+        .map(|(residual, _)| residual)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::convert_activity;
+    use crate::report::resolve::Resolved;
+    use crate::report::transactions::ExchangeInfo;
+    use crate::source::{Activity, NormalizedTrade, Side};
+    use chrono::NaiveDate;
+    use rust_decimal_macros::dec;
+
+    fn purchases() -> Activity {
+        let trade = |day: u32| NormalizedTrade {
+            base: "BTC".to_string(),
+            quote: "USD".to_string(),
+            side: Side::Buy,
+            volume: dec!(0.00001),
+            cost: dec!(0.333),
+            fee: dec!(0),
+            credit_fee_usd: Decimal::ZERO,
+            price: dec!(33300),
+            time: format!("2024-03-{:02}T12:00:00Z", day).parse().unwrap(),
+            ids: Vec::new(),
+        };
+        Activity {
+            trades: vec![trade(1), trade(2), trade(3)],
+            ..Activity::default()
+        }
+    }
+
+    fn convert(fold_residuals: bool) -> (Vec<Decimal>, Vec<Residual>) {
+        let mut resolved = Resolved::default();
+        for day in 1..=3 {
+            let date = NaiveDate::from_ymd_opt(2024, 3, day).unwrap();
+            resolved.rates.insert(("USD".to_string(), date), dec!(5));
+        }
+        let exchange = ExchangeInfo {
+            name: "Kraken".to_string(),
+            url: String::new(),
+            country: "US".to_string(),
+        };
+        let options = ProcessOptions {
+            fold_residuals,
+            ..ProcessOptions::default()
+        };
+        let processed = convert_activity(purchases(), &exchange, &options, &mut resolved).unwrap();
+        let values = processed
+            .transactions
+            .iter()
+            .map(|t| match t {
+                Transaction::Purchase(p) => p.operation_value,
+                _ => panic!("expected a purchase"),
+            })
+            .collect();
+        (values, processed.residuals)
+    }
+
+    #[test]
+    fn test_residuals() {
+        // Each purchase is worth 1,665 BRL, rounded to 1,66
+        let (values, residuals) = convert(false);
+        assert_eq!(values, [dec!(1.66), dec!(1.66), dec!(1.66)]);
+        assert_eq!(
+            residuals,
+            [Residual {
+                record_type: "0110",
+                asset: "BTC".to_string(),
+                year: 2024,
+                month: 3,
+                value: dec!(0.015),
+                fees: dec!(0),
+                folded: false,
+            }]
+        );
+
+        // The last one takes the residual, so the total is the rounded 4,995
+        let (values, residuals) = convert(true);
+        assert_eq!(values, [dec!(1.66), dec!(1.66), dec!(1.68)]);
+        assert_eq!(values.iter().sum::<Decimal>(), dec!(5.00));
+        assert!(residuals[0].folded);
+    }
+}
@@ -0,0 +1,277 @@
+//! What the conversion of the activity needs from outside: the BRL rates and
+//! the answers of the user. The conversion only gets them through a
+//! [`Resolver`], so it does no I/O itself and runs the same anywhere; callers
+//! that can't block, like async servers, resolve the [`required_rates`] on
+//! their own and pass them in a [`Resolved`].
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+
+use super::transactions::ExchangeInfo;
+use super::{ProcessOptions, convert_activity, encoding};
+use crate::checkpoint::Checkpoint;
+use crate::error::{Error, Result};
+use crate::exchange_rate::{get_exchange_rate, get_exchange_rate_impl};
+use crate::i18n::t;
+use crate::prompt::Prompter;
+use crate::source::Activity;
+
+/// Provides the inputs of [`convert_activity`]
+pub trait Resolver {
+    /// BRL price of 1 `asset` on `date`
+    fn rate(&mut self, asset: &str, date: NaiveDate) -> Result<Decimal>;
+
+    /// Answer to a question about the activity, `None` if unknown. The answer
+    /// goes into the report.
+    fn answer(&mut self, question: &str) -> Result<Option<String>>;
+}
+
+/// Rates and answers resolved beforehand. A rate missing here is an error, an
+/// answer missing is unknown.
+#[derive(Debug, Default, Clone)]
+pub struct Resolved {
+    /// BRL price of 1 unit, keyed by asset and date
+    pub rates: BTreeMap<(String, NaiveDate), Decimal>,
+    pub answers: BTreeMap<String, Option<String>>,
+}
+
+impl Resolver for Resolved {
+    fn rate(&mut self, asset: &str, date: NaiveDate) -> Result<Decimal> {
+        self.rates
+            .get(&(asset.to_string(), date))
+            .copied()
+            .ok_or_else(|| Error::Rate {
+                asset: asset.to_string(),
+                date,
+                reason: t!("not resolved beforehand"),
+            })
+    }
+
+    fn answer(&mut self, question: &str) -> Result<Option<String>> {
+        Ok(self.answers.get(question).cloned().flatten())
+    }
+}
+
+/// Fetches the rates from the built-in providers, once each, and answers
+/// nothing
+#[derive(Debug, Default)]
+pub struct Providers {
+    rates: BTreeMap<(String, NaiveDate), Decimal>,
+}
+
+impl Providers {
+    /// Uses `rates`, e.g. those of a [`crate::bundle::Bundle`], before
+    /// fetching any
+    pub fn with_rates(rates: BTreeMap<(String, NaiveDate), Decimal>) -> Self {
+        Providers { rates }
+    }
+}
+
+impl Resolver for Providers {
+    fn rate(&mut self, asset: &str, date: NaiveDate) -> Result<Decimal> {
+        let key = (asset.to_string(), date);
+        if let Some(&rate) = self.rates.get(&key) {
+            return Ok(rate);
+        }
+        let (_, rate) = get_exchange_rate_impl(date, asset)?;
+        self.rates.insert(key, rate);
+        Ok(rate)
+    }
+
+    fn answer(&mut self, _: &str) -> Result<Option<String>> {
+        Ok(None)
+    }
+}
+
+/// The rates, by asset and date, [`convert_activity`] needs for `activity`
+pub fn required_rates(
+    activity: &Activity,
+    options: &ProcessOptions,
+) -> Result<BTreeSet<(String, NaiveDate)>> {
+    /// Records the rates asked for. Which rates are needed doesn't depend on
+    /// their values, so any value does.
+    struct Recorder(BTreeSet<(String, NaiveDate)>);
+
+    impl Resolver for Recorder {
+        fn rate(&mut self, asset: &str, date: NaiveDate) -> Result<Decimal> {
+            self.0.insert((asset.to_string(), date));
+            Ok(Decimal::ONE)
+        }
+
+        fn answer(&mut self, _: &str) -> Result<Option<String>> {
+            Ok(None)
+        }
+    }
+
+    let options = ProcessOptions {
+        debug: false,
+        ..options.clone()
+    };
+    let exchange = ExchangeInfo {
+        name: String::new(),
+        url: String::new(),
+        country: String::new(),
+    };
+    let mut recorder = Recorder(BTreeSet::new());
+    convert_activity(activity.clone(), &exchange, &options, &mut recorder)?;
+    Ok(recorder.0)
+}
+
+/// Fetches the rates from the providers and asks the user, reusing and saving
+/// what was resolved in the checkpoint
+pub(super) struct InteractiveResolver<'a> {
+    pub options: &'a ProcessOptions,
+    pub prompter: &'a mut Prompter,
+    pub checkpoint: &'a mut Checkpoint,
+}
+
+impl Resolver for InteractiveResolver<'_> {
+    fn rate(&mut self, asset: &str, date: NaiveDate) -> Result<Decimal> {
+        brl_rate(date, asset, self.options, self.prompter, self.checkpoint)
+    }
+
+    fn answer(&mut self, question: &str) -> Result<Option<String>> {
+        ask(question, self.options, self.prompter, self.checkpoint)
+    }
+}
+
+/// A rate from more days than this before the operation is warned about. Up to
+/// 4 days is expected for the BCB rates, e.g. over the Carnival holidays.
+const MAX_RATE_AGE_DAYS: i64 = 4;
+
+/// Get the BRL exchange rate of an asset on a given date.
+///
+/// Rates already resolved in the checkpoint are reused. If the rate can't be
+/// fetched, the user is asked to type it in, when possible.
+fn brl_rate(
+    date: NaiveDate,
+    asset: &str,
+    options: &ProcessOptions,
+    prompter: &mut Prompter,
+    checkpoint: &mut Checkpoint,
+) -> Result<Decimal> {
+    if let Some(rate) = checkpoint.rate(asset, date) {
+        return Ok(rate);
+    }
+
+    let rate = match get_exchange_rate(date, asset) {
+        Ok((rate_date, rate)) => {
+            // Before the rate is saved, so a resumed run warns again
+            if (date - rate_date).num_days() > MAX_RATE_AGE_DAYS {
+                options.warn(t!(
+                    "the rate of {} for {} is from {}, {} days before",
+                    asset,
+                    date,
+                    rate_date,
+                    (date - rate_date).num_days()
+                ))?;
+            }
+            rate
+        }
+        Err(e) => {
+            if prompter.is_interactive() {
+                println!("{}", e);
+            }
+            prompter
+                .ask_decimal(&t!(
+                    "Price in BRL of 1 {} on {} (empty to abort):",
+                    asset,
+                    date
+                ))?
+                .ok_or(e)?
+        }
+    };
+    checkpoint.set_rate(asset, date, rate)?;
+    Ok(rate)
+}
+
+/// Asks the user a question, unless it was already answered in the checkpoint.
+/// The answer goes into the report, so characters not allowed there are replaced.
+fn ask(
+    question: &str,
+    options: &ProcessOptions,
+    prompter: &mut Prompter,
+    checkpoint: &mut Checkpoint,
+) -> Result<Option<String>> {
+    if let Some(answer) = checkpoint.answer(question) {
+        return Ok(answer);
+    }
+    if !prompter.is_interactive() {
+        // Not an answer, so a later interactive run still asks
+        return Ok(None);
+    }
+    let mut answer = prompter.ask(question)?;
+    if let Some(sanitized) = answer.as_deref().and_then(encoding::sanitize) {
+        options.warn(t!(
+            "replaced the characters not allowed in the report: {}",
+            sanitized
+        ))?;
+        answer = Some(sanitized);
+    }
+    checkpoint.set_answer(question, answer.clone())?;
+    Ok(answer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::{NormalizedTrade, NormalizedWithdrawal, Side};
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_resolved() {
+        let time = "2024-03-01T12:00:00Z".parse().unwrap();
+        let activity = Activity {
+            withdrawals: vec![NormalizedWithdrawal {
+                asset: "BTC".to_string(),
+                amount: dec!(0.5),
+                fee: dec!(0.0001),
+                time,
+                ids: Vec::new(),
+                destination: None,
+                network: None,
+            }],
+            trades: vec![NormalizedTrade {
+                base: "BTC".to_string(),
+                quote: "EUR".to_string(),
+                side: Side::Buy,
+                volume: dec!(0.1),
+                cost: dec!(5000),
+                fee: dec!(8),
+                credit_fee_usd: Decimal::ZERO,
+                price: dec!(50000),
+                time,
+                ids: Vec::new(),
+            }],
+            ..Activity::default()
+        };
+        let options = ProcessOptions::default();
+        let date = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        let rates = required_rates(&activity, &options).unwrap();
+        assert_eq!(
+            rates
+                .iter()
+                .map(|(asset, _)| asset.as_str())
+                .collect::<Vec<_>>(),
+            ["BTC", "EUR"]
+        );
+
+        let mut resolved = Resolved::default();
+        let exchange = ExchangeInfo {
+            name: "Kraken".to_string(),
+            url: "https://www.kraken.com".to_string(),
+            country: "US".to_string(),
+        };
+        assert!(matches!(
+            convert_activity(activity.clone(), &exchange, &options, &mut resolved),
+            Err(Error::Rate { .. })
+        ));
+
+        resolved.rates = rates.into_iter().map(|key| (key, dec!(5))).collect();
+        let processed = convert_activity(activity, &exchange, &options, &mut resolved).unwrap();
+        assert_eq!(processed.transactions.len(), 2);
+        assert_eq!(resolved.rate("EUR", date).unwrap(), dec!(5));
+    }
+}
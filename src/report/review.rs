@@ -0,0 +1,312 @@
+//! Interactive review of the records before the report is written.
+
+use super::encoding;
+use super::transactions::Transaction;
+use crate::error::{Error, Result};
+use crate::i18n::t;
+use crate::prompt::Prompter;
+
+/// A command given at the review prompt
+#[derive(Debug, PartialEq)]
+enum ReviewCommand {
+    List,
+    /// Acts on a record; record numbers start at 1, as listed
+    Edit(usize, Edit),
+    Write,
+    Quit,
+}
+
+#[derive(Debug, PartialEq)]
+enum Edit {
+    Show,
+    /// Excludes the record, or includes it back if it was excluded
+    Toggle,
+    SetWallet(String),
+    SetExchange(String),
+    Annotate(String),
+}
+
+fn parse_command(line: &str) -> Option<ReviewCommand> {
+    let mut words = line.splitn(3, ' ').map(str::trim);
+    let first = words.next()?;
+    if let Ok(n) = first.parse() {
+        return Some(ReviewCommand::Edit(n, Edit::Show));
+    }
+    let n = words.next().and_then(|n| n.parse().ok());
+    let text = words.next().filter(|t| !t.is_empty()).map(str::to_string);
+    Some(match (first, n, text) {
+        ("l", None, None) => ReviewCommand::List,
+        ("x", Some(n), None) => ReviewCommand::Edit(n, Edit::Toggle),
+        ("o", Some(n), Some(wallet)) => ReviewCommand::Edit(n, Edit::SetWallet(wallet)),
+        ("e", Some(n), Some(exchange)) => ReviewCommand::Edit(n, Edit::SetExchange(exchange)),
+        ("n", Some(n), Some(note)) => ReviewCommand::Edit(n, Edit::Annotate(note)),
+        ("w", None, None) => ReviewCommand::Write,
+        ("q", None, None) => ReviewCommand::Quit,
+        _ => return None,
+    })
+}
+
+/// A processed transaction under review
+struct Entry {
+    transaction: Transaction,
+    excluded: bool,
+    note: Option<String>,
+}
+
+/// Transactions accepted in the review, to be written to the report
+pub struct Reviewed {
+    pub transactions: Vec<Transaction>,
+    /// Number of transactions left out by the user
+    pub excluded: usize,
+    /// Notes taken by the user, one line per annotated transaction
+    pub notes: Vec<String>,
+}
+
+/// One line description of a transaction, with its BRL values
+pub fn describe(transaction: &Transaction) -> String {
+    match transaction {
+        Transaction::Purchase(t) => t!(
+            "bought {} {} for {} BRL",
+            t.base.crypto_amount,
+            t.base.crypto_symbol,
+            t.operation_value.round_dp(2)
+        ),
+        Transaction::Sale(t) => t!(
+            "sold {} {} for {} BRL",
+            t.base.crypto_amount,
+            t.base.crypto_symbol,
+            t.operation_value.round_dp(2)
+        ),
+        Transaction::Swap(t) => t!(
+            "swap of {} {} for {} {}",
+            t.given_crypto_amount,
+            t.given_crypto_symbol,
+            t.received_crypto_amount,
+            t.received_crypto_symbol
+        ),
+        Transaction::TransferToExchange(t) => t!(
+            "deposit of {} {} (wallet: {}, exchange: {})",
+            t.base.crypto_amount,
+            t.base.crypto_symbol,
+            t.origin_wallet.as_deref().unwrap_or("-"),
+            t.origin_exchange_name.as_deref().unwrap_or("-")
+        ),
+        Transaction::WithdrawalFromExchange(t) => t!(
+            "withdrawal of {} {}",
+            t.base.crypto_amount,
+            t.base.crypto_symbol
+        ),
+        Transaction::CryptoPaymentReceiver(t) => t!(
+            "payment received of {} {}",
+            t.base.crypto_amount,
+            t.base.crypto_symbol
+        ),
+        Transaction::CryptoPaymentSender(t) => t!(
+            "payment made of {} {}",
+            t.base.crypto_amount,
+            t.base.crypto_symbol
+        ),
+    }
+}
+
+fn print_list(entries: &[Entry]) {
+    for (i, entry) in entries.iter().enumerate() {
+        println!(
+            "{:>4} {} {} {} {}{}",
+            i + 1,
+            if entry.excluded { 'x' } else { ' ' },
+            entry.transaction.record_type().0,
+            entry.transaction.date(),
+            describe(&entry.transaction),
+            entry
+                .note
+                .as_ref()
+                .map(|note| format!(" [{}]", note))
+                .unwrap_or_default()
+        );
+    }
+}
+
+fn print_help() {
+    println!("{}", t!("Commands:"));
+    for line in [
+        t!("  <n>              show the details of record n"),
+        t!("  x <n>            exclude record n, or include it back"),
+        t!("  o <n> <wallet>   set the origin wallet of deposit n"),
+        t!("  e <n> <exchange> set the origin exchange of deposit n"),
+        t!("  n <n> <note>     annotate record n"),
+        t!("  l                list the records again"),
+        t!("  w                write the report with the included records"),
+        t!("  q                quit without writing the report"),
+    ] {
+        println!("{}", line);
+    }
+}
+
+/// Applies an edit to record `n`, failing with a message for the user
+fn apply(entries: &mut [Entry], n: usize, edit: Edit) -> std::result::Result<(), String> {
+    let entry = n
+        .checked_sub(1)
+        .and_then(|i| entries.get_mut(i))
+        .ok_or_else(|| t!("no record number {}", n))?;
+
+    // These go into the report
+    if let Edit::SetWallet(text) | Edit::SetExchange(text) = &edit
+        && encoding::sanitize(text).is_some()
+    {
+        return Err(t!("the text can't contain '|' or line breaks"));
+    }
+
+    match (edit, &mut entry.transaction) {
+        (Edit::Show, transaction) => println!("{:#?}", transaction),
+        (Edit::Toggle, _) => entry.excluded = !entry.excluded,
+        (Edit::Annotate(note), _) => entry.note = Some(note),
+        (Edit::SetWallet(wallet), Transaction::TransferToExchange(deposit)) => {
+            deposit.origin_wallet = Some(wallet)
+        }
+        (Edit::SetExchange(exchange), Transaction::TransferToExchange(deposit)) => {
+            deposit.origin_exchange_name = Some(exchange)
+        }
+        (Edit::SetWallet(_) | Edit::SetExchange(_), _) => {
+            return Err(t!("record {} is not a deposit", n));
+        }
+    }
+    Ok(())
+}
+
+/// Lets the user inspect, exclude, annotate and edit the processed
+/// transactions before the report is written. Returns `None` if the user
+/// quits without writing the report.
+pub fn review(transactions: Vec<Transaction>, prompter: &mut Prompter) -> Result<Option<Reviewed>> {
+    if !prompter.is_interactive() {
+        return Err(Error::Config(t!("--review needs an interactive terminal")));
+    }
+
+    let mut entries: Vec<Entry> = transactions
+        .into_iter()
+        .map(|transaction| Entry {
+            transaction,
+            excluded: false,
+            note: None,
+        })
+        .collect();
+
+    print_list(&entries);
+    print_help();
+    loop {
+        let Some(line) = prompter.ask(&t!("review>"))? else {
+            if !prompter.is_interactive() {
+                // stdin was closed
+                return Ok(None);
+            }
+            print_help();
+            continue;
+        };
+        match parse_command(&line) {
+            Some(ReviewCommand::List) => print_list(&entries),
+            Some(ReviewCommand::Write) => break,
+            Some(ReviewCommand::Quit) => return Ok(None),
+            Some(ReviewCommand::Edit(n, edit)) => {
+                if let Err(msg) = apply(&mut entries, n, edit) {
+                    println!("{}", msg);
+                }
+            }
+            None => {
+                println!("{}", t!("Invalid command: {}", line));
+                print_help();
+            }
+        }
+    }
+
+    let mut reviewed = Reviewed {
+        transactions: Vec::new(),
+        excluded: 0,
+        notes: Vec::new(),
+    };
+    for entry in entries {
+        if let Some(note) = &entry.note {
+            reviewed.notes.push(format!(
+                "{} {} {}: {}",
+                entry.transaction.record_type().0,
+                entry.transaction.date(),
+                describe(&entry.transaction),
+                note
+            ));
+        }
+        if entry.excluded {
+            reviewed.excluded += 1;
+        } else {
+            reviewed.transactions.push(entry.transaction);
+        }
+    }
+    Ok(Some(reviewed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::transactions::{TransactionBase, TransferToExchangeTransaction};
+    use chrono::NaiveDate;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse_command("3"), Some(ReviewCommand::Edit(3, Edit::Show)));
+        assert_eq!(
+            parse_command("x 2"),
+            Some(ReviewCommand::Edit(2, Edit::Toggle))
+        );
+        assert_eq!(
+            parse_command("o 1 bc1q abc"),
+            Some(ReviewCommand::Edit(
+                1,
+                Edit::SetWallet("bc1q abc".to_string())
+            ))
+        );
+        assert_eq!(
+            parse_command("n 4 declared elsewhere"),
+            Some(ReviewCommand::Edit(
+                4,
+                Edit::Annotate("declared elsewhere".to_string())
+            ))
+        );
+        assert_eq!(parse_command("w"), Some(ReviewCommand::Write));
+        assert_eq!(parse_command("x"), None);
+        assert_eq!(parse_command("e 1"), None);
+        assert_eq!(parse_command("bogus"), None);
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut entries = vec![Entry {
+            transaction: Transaction::TransferToExchange(TransferToExchangeTransaction {
+                base: TransactionBase {
+                    operation_date: NaiveDate::from_ymd_opt(2024, 3, 1).unwrap(),
+                    operation_fees: None,
+                    crypto_symbol: "BTC".to_string(),
+                    crypto_amount: dec!(0.5),
+                },
+                origin_wallet: None,
+                origin_exchange_name: None,
+                deposit_id: None,
+            }),
+            excluded: false,
+            note: None,
+        }];
+
+        apply(&mut entries, 1, Edit::Toggle).unwrap();
+        assert!(entries[0].excluded);
+        apply(&mut entries, 1, Edit::SetWallet("bc1q".to_string())).unwrap();
+        apply(&mut entries, 1, Edit::SetExchange("Binance".to_string())).unwrap();
+        let Transaction::TransferToExchange(deposit) = &entries[0].transaction else {
+            panic!("not a deposit");
+        };
+        assert_eq!(deposit.origin_wallet.as_deref(), Some("bc1q"));
+        assert_eq!(deposit.origin_exchange_name.as_deref(), Some("Binance"));
+
+        assert!(apply(&mut entries, 1, Edit::SetWallet("a|b".to_string())).is_err());
+        assert!(apply(&mut entries, 1, Edit::SetWallet("a|b".to_string())).is_err());
+        assert!(apply(&mut entries, 0, Edit::Toggle).is_err());
+        assert!(apply(&mut entries, 2, Edit::Toggle).is_err());
+    }
+}
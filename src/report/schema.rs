@@ -0,0 +1,323 @@
+//! JSON Schemas (draft 2020-12) of the documents the crate reads and writes,
+//! so other tools can validate what they produce or consume: the interchange
+//! document of the transactions (see [`super::interchange`]) and the summary
+//! of a run, as served by `POST /reports`.
+//!
+//! They are written by hand, next to the types, and the tests validate what
+//! the types serialize to against them with a JSON Schema validator.
+
+use serde_json::{Value, json};
+
+use crate::error::{Error, Result};
+use crate::i18n::t;
+use crate::report::interchange::VERSION;
+
+/// A document with a schema
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Document {
+    /// The transactions, as written by `--json` and read by `encode`
+    Interchange,
+    /// [`super::summary::RunSummary`]
+    Summary,
+}
+
+impl Document {
+    /// Parses the name of a document, as given on the command line
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "interchange" => Ok(Document::Interchange),
+            "summary" => Ok(Document::Summary),
+            _ => Err(Error::Config(t!(
+                "unknown schema: {} (expected interchange or summary)",
+                name
+            ))),
+        }
+    }
+
+    pub fn schema(self) -> Value {
+        match self {
+            Document::Interchange => interchange(),
+            Document::Summary => summary(),
+        }
+    }
+}
+
+/// An object with exactly these properties, all required but `optional`
+fn object(properties: Value, optional: &[&str]) -> Value {
+    let required: Vec<&String> = properties
+        .as_object()
+        .expect("properties must be an object")
+        .keys()
+        .filter(|key| !optional.contains(&key.as_str()))
+        .collect();
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+        "additionalProperties": false,
+    })
+}
+
+/// A transaction of the given `type`, with the other properties
+fn transaction(kind: &str, properties: Value, optional: &[&str]) -> Value {
+    let mut properties = properties;
+    properties["type"] = json!({ "const": kind });
+    object(properties, optional)
+}
+
+fn interchange() -> Value {
+    let reference = |name: &str| json!({ "$ref": format!("#/$defs/{}", name) });
+    let with_base = |kind: &str, exchange: &str| {
+        transaction(
+            kind,
+            json!({ "base": reference("base"), exchange: reference("exchange") }),
+            &[],
+        )
+    };
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "kraken2rfb transactions",
+        "type": "object",
+        "properties": {
+            "version": { "const": VERSION },
+            "transactions": { "type": "array", "items": reference("transaction") },
+        },
+        "required": ["version", "transactions"],
+        "$defs": {
+            "date": { "type": "string", "pattern": "^[0-9]{4}-[0-9]{2}-[0-9]{2}$" },
+            "decimal": { "type": "string", "pattern": "^-?[0-9]+(\\.[0-9]+)?$" },
+            "optional_decimal": {
+                "anyOf": [reference("decimal"), { "type": "null" }]
+            },
+            "optional_string": { "type": ["string", "null"] },
+            "base": object(
+                json!({
+                    "operation_date": reference("date"),
+                    "operation_fees": reference("optional_decimal"),
+                    "crypto_symbol": { "type": "string" },
+                    "crypto_amount": reference("decimal"),
+                }),
+                &["operation_fees"],
+            ),
+            "exchange": object(
+                json!({
+                    "name": { "type": "string" },
+                    "url": { "type": "string" },
+                    "country": { "type": "string" },
+                }),
+                &[],
+            ),
+            "transaction": {
+                "oneOf": [
+                    transaction(
+                        "purchase",
+                        json!({
+                            "base": reference("base"),
+                            "operation_value": reference("decimal"),
+                            "buyer_exchange": reference("exchange"),
+                        }),
+                        &[],
+                    ),
+                    transaction(
+                        "sale",
+                        json!({
+                            "base": reference("base"),
+                            "operation_value": reference("decimal"),
+                            "seller_exchange": reference("exchange"),
+                        }),
+                        &[],
+                    ),
+                    transaction(
+                        "swap",
+                        json!({
+                            "operation_date": reference("date"),
+                            "operation_fees": reference("optional_decimal"),
+                            "received_crypto_symbol": { "type": "string" },
+                            "received_crypto_amount": reference("decimal"),
+                            "given_crypto_symbol": { "type": "string" },
+                            "given_crypto_amount": reference("decimal"),
+                            "exchange": reference("exchange"),
+                        }),
+                        &["operation_fees"],
+                    ),
+                    transaction(
+                        "transfer_to_exchange",
+                        json!({
+                            "base": reference("base"),
+                            "origin_wallet": reference("optional_string"),
+                            "origin_exchange_name": reference("optional_string"),
+                        }),
+                        &["origin_wallet", "origin_exchange_name"],
+                    ),
+                    with_base("withdrawal_from_exchange", "origin_exchange"),
+                    with_base("crypto_payment_receiver", "receiver_exchange"),
+                    with_base("crypto_payment_sender", "sender_exchange"),
+                ]
+            },
+        },
+    })
+}
+
+fn summary() -> Value {
+    let decimal = json!({ "type": "string", "pattern": "^-?[0-9]+(\\.[0-9]+)?$" });
+    let mut schema = object(
+        json!({
+            "record_counts": {
+                "type": "object",
+                "propertyNames": { "pattern": "^[0-9]{4}$" },
+                "additionalProperties": { "type": "integer", "minimum": 0 },
+            },
+            "total_bought": decimal,
+            "total_sold": decimal,
+            "total_fees": decimal,
+            "excluded_ids": { "type": "array", "items": { "type": "string" } },
+            "wallet_transfers": { "type": "array", "items": { "type": "string" } },
+            "transfer_networks": {
+                "type": "object",
+                "additionalProperties": { "type": "integer", "minimum": 0 },
+            },
+        }),
+        &[],
+    );
+    schema["$schema"] = json!("https://json-schema.org/draft/2020-12/schema");
+    schema["title"] = json!("kraken2rfb run summary");
+    schema
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::summary::RunSummary;
+    use crate::report::transactions::*;
+    use chrono::NaiveDate;
+    use jsonschema::Validator;
+    use rust_decimal_macros::dec;
+
+    fn validator(schema: &Value) -> Validator {
+        jsonschema::draft202012::new(schema).unwrap()
+    }
+
+    /// Fails with the errors of `value` against the schema, if any
+    fn assert_valid(validator: &Validator, value: &Value) {
+        let errors: Vec<String> = validator
+            .iter_errors(value)
+            .map(|e| e.to_string())
+            .collect();
+        assert!(errors.is_empty(), "{}: {:?}", value, errors);
+    }
+
+    fn transactions() -> Vec<Transaction> {
+        let base = TransactionBase {
+            operation_date: NaiveDate::from_ymd_opt(2024, 3, 1).unwrap(),
+            operation_fees: Some(dec!(1.5)),
+            crypto_symbol: "BTC".to_string(),
+            crypto_amount: dec!(0.5),
+        };
+        let exchange = ExchangeInfo {
+            name: "Kraken".to_string(),
+            url: "https://www.kraken.com".to_string(),
+            country: "US".to_string(),
+        };
+        vec![
+            Transaction::Purchase(PurchaseTransaction {
+                base: base.clone(),
+                operation_value: dec!(100),
+                buyer_exchange: exchange.clone(),
+            }),
+            Transaction::Sale(SaleTransaction {
+                base: base.clone(),
+                operation_value: dec!(100),
+                seller_exchange: exchange.clone(),
+            }),
+            Transaction::Swap(SwapTransaction {
+                operation_date: base.operation_date,
+                operation_fees: None,
+                received_crypto_symbol: "ETH".to_string(),
+                received_crypto_amount: dec!(2),
+                given_crypto_symbol: "BTC".to_string(),
+                given_crypto_amount: dec!(0.1),
+                exchange: exchange.clone(),
+            }),
+            Transaction::TransferToExchange(TransferToExchangeTransaction {
+                base: base.clone(),
+                origin_wallet: Some("bc1q".to_string()),
+                origin_exchange_name: None,
+                deposit_id: None,
+            }),
+            Transaction::WithdrawalFromExchange(WithdrawalFromExchangeTransaction {
+                base: base.clone(),
+                origin_exchange: exchange.clone(),
+            }),
+            Transaction::CryptoPaymentReceiver(CryptoPaymentReceiverTransaction {
+                base: base.clone(),
+                receiver_exchange: exchange.clone(),
+            }),
+            Transaction::CryptoPaymentSender(CryptoPaymentSenderTransaction {
+                base: TransactionBase {
+                    operation_fees: None,
+                    ..base
+                },
+                sender_exchange: exchange,
+            }),
+        ]
+    }
+
+    #[test]
+    fn test_interchange_schema() {
+        let transactions = transactions();
+        let root = Document::Interchange.schema();
+        let mut document = Vec::new();
+        crate::report::interchange::write(&transactions, &mut document).unwrap();
+        let document: Value = serde_json::from_slice(&document).unwrap();
+        assert_valid(&validator(&root), &document);
+
+        // Each variant matches the transaction of its type and no other
+        let variants = root["$defs"]["transaction"]["oneOf"].as_array().unwrap();
+        assert_eq!(variants.len(), transactions.len());
+        let serialized = document["transactions"].as_array().unwrap();
+        for i in 0..variants.len() {
+            let variant = validator(&json!({
+                "$defs": root["$defs"],
+                "$ref": format!("#/$defs/transaction/oneOf/{}", i),
+            }));
+            let matched: Vec<&Value> = serialized.iter().filter(|t| variant.is_valid(t)).collect();
+            assert_eq!(matched.len(), 1, "variant {} matches {:?}", i, matched);
+            assert_eq!(matched[0], &serialized[i]);
+        }
+
+        // What the types don't write is rejected
+        let invalid = |change: fn(&mut Value)| {
+            let mut document = document.clone();
+            change(&mut document);
+            validator(&root).is_valid(&document)
+        };
+        assert!(!invalid(|d| d["transactions"][0]["extra"] = json!(1)));
+        assert!(!invalid(
+            |d| d["transactions"][1]["operation_value"] = json!(100)
+        ));
+        assert!(!invalid(|d| {
+            d["transactions"][2]["exchange"]
+                .as_object_mut()
+                .unwrap()
+                .remove("country");
+        }));
+        assert!(!invalid(|d| d["transactions"][4]["type"] = json!("deposit")));
+        assert!(!invalid(|d| d["version"] = json!(VERSION + 1)));
+    }
+
+    #[test]
+    fn test_summary_schema() {
+        let validator = validator(&Document::Summary.schema());
+        assert_valid(
+            &validator,
+            &serde_json::to_value(RunSummary::default()).unwrap(),
+        );
+        let mut summary = RunSummary::from_transactions(&transactions()).unwrap();
+        summary.excluded_ids.push("TX1".to_string());
+        summary.transfer_networks.insert("Bitcoin".to_string(), 2);
+        let summary = serde_json::to_value(summary).unwrap();
+        assert!(summary["total_fees"].is_string());
+        assert_valid(&validator, &summary);
+        assert!(matches!(Document::parse("bogus"), Err(Error::Config(_))));
+    }
+}
@@ -0,0 +1,146 @@
+//! Writing of the report as it is converted, batch by batch, so the memory
+//! used stays the same however long the history is. Each record is encoded as
+//! soon as its transaction is converted, and kept in a temporary file of its
+//! record type until the end, since the report lists the records by type.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::error::Result;
+use crate::report::layout::Layout;
+use crate::report::transactions::Transaction;
+
+/// Encoded records, by record type, waiting to be written in order
+pub struct RecordSpool {
+    layout: Layout,
+    dir: PathBuf,
+    files: BTreeMap<&'static str, BufWriter<File>>,
+    counts: BTreeMap<&'static str, usize>,
+}
+
+impl RecordSpool {
+    /// Creates an empty spool, in its own directory under the temporary
+    /// directory of the system
+    pub fn new(layout: Layout) -> Result<Self> {
+        static SPOOLS: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "kraken2rfb-spool-{}-{}",
+            std::process::id(),
+            SPOOLS.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&dir)?;
+        Ok(RecordSpool {
+            layout,
+            dir,
+            files: BTreeMap::new(),
+            counts: BTreeMap::new(),
+        })
+    }
+
+    /// Encodes the record of a transaction
+    pub fn push(&mut self, transaction: &Transaction) -> Result<()> {
+        let record_type = transaction.record_type().0;
+        let file = match self.files.get_mut(record_type) {
+            Some(file) => file,
+            None => {
+                let file = File::create(self.dir.join(record_type))?;
+                self.files
+                    .entry(record_type)
+                    .or_insert(BufWriter::new(file))
+            }
+        };
+        transaction.write_transaction(file, self.layout)?;
+        *self.counts.entry(record_type).or_default() += 1;
+        Ok(())
+    }
+
+    /// How many records were encoded of each record type
+    pub fn counts(&self) -> &BTreeMap<&'static str, usize> {
+        &self.counts
+    }
+
+    /// Writes all the records, sorted by record type and otherwise in the
+    /// order they were pushed. It can be called once for each output.
+    pub fn write_to(&mut self, writer: &mut impl Write) -> Result<()> {
+        for (record_type, file) in &mut self.files {
+            file.flush()?;
+            let mut records = BufReader::new(File::open(self.dir.join(record_type))?);
+            io::copy(&mut records, writer)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl Drop for RecordSpool {
+    fn drop(&mut self) {
+        self.files.clear();
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::transactions::{
+        ExchangeInfo, PurchaseTransaction, TransactionBase, WithdrawalFromExchangeTransaction,
+    };
+    use crate::report::write_report;
+    use chrono::NaiveDate;
+    use rust_decimal_macros::dec;
+
+    fn base(day: u32) -> TransactionBase {
+        TransactionBase {
+            operation_date: NaiveDate::from_ymd_opt(2024, 3, day).unwrap(),
+            operation_fees: None,
+            crypto_symbol: "BTC".to_string(),
+            crypto_amount: dec!(0.1),
+        }
+    }
+
+    #[test]
+    fn test_spool() {
+        let exchange = ExchangeInfo {
+            name: "Kraken".to_string(),
+            url: "https://www.kraken.com".to_string(),
+            country: "US".to_string(),
+        };
+        let withdrawal = |day| {
+            Transaction::WithdrawalFromExchange(WithdrawalFromExchangeTransaction {
+                base: base(day),
+                origin_exchange: exchange.clone(),
+            })
+        };
+        let purchase = |day| {
+            Transaction::Purchase(PurchaseTransaction {
+                base: base(day),
+                operation_value: dec!(30000),
+                buyer_exchange: exchange.clone(),
+            })
+        };
+        let transactions = [withdrawal(1), purchase(2), withdrawal(3), purchase(4)];
+
+        let mut spool = RecordSpool::new(Layout::default()).unwrap();
+        for transaction in &transactions {
+            spool.push(transaction).unwrap();
+        }
+        assert_eq!(spool.counts(), &BTreeMap::from([("0110", 2), ("0510", 2)]));
+
+        let mut sorted = transactions.to_vec();
+        sorted.sort_by_key(|t| t.record_type().0);
+        let mut expected = Vec::new();
+        write_report(&sorted, Layout::default(), &mut expected).unwrap();
+        for _ in 0..2 {
+            let mut report = Vec::new();
+            spool.write_to(&mut report).unwrap();
+            assert_eq!(report, expected);
+        }
+
+        let dir = spool.dir.clone();
+        drop(spool);
+        assert!(!dir.exists());
+    }
+}
@@ -0,0 +1,125 @@
+//! Totals shown at the end of a run.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use super::transactions::Transaction;
+use crate::checked;
+use crate::error::Result;
+use crate::i18n::t;
+use crate::style;
+
+/// Totals of the records written to a report
+#[derive(Debug, Default, Serialize)]
+pub struct RunSummary {
+    /// Number of records per record type
+    pub record_counts: BTreeMap<&'static str, usize>,
+    /// Value of the purchases in BRL, excluding fees
+    pub total_bought: Decimal,
+    /// Value of the sales in BRL, excluding fees
+    pub total_sold: Decimal,
+    /// Fees of all operations in BRL
+    pub total_fees: Decimal,
+    /// IDs of the Kraken entries excluded from the report by the user
+    pub excluded_ids: Vec<String>,
+    /// IDs of the moves between the wallets of the account, left out of the
+    /// report since they are neither deposits nor withdrawals
+    pub wallet_transfers: Vec<String>,
+    /// Number of crypto deposits and withdrawals by the network they were
+    /// made on, e.g. "Bitcoin Lightning", for those the exchange names it
+    pub transfer_networks: BTreeMap<String, usize>,
+}
+
+impl RunSummary {
+    pub fn from_transactions(transactions: &[Transaction]) -> Result<Self> {
+        let mut summary = RunSummary::default();
+        for transaction in transactions {
+            let (record_type, _) = transaction.record_type();
+            *summary.record_counts.entry(record_type).or_default() += 1;
+            let what = t!(
+                "the totals, adding the {} record of {}",
+                record_type,
+                transaction.date()
+            );
+            match transaction {
+                Transaction::Purchase(t) => {
+                    summary.total_bought =
+                        checked::add(summary.total_bought, t.operation_value, &what)?
+                }
+                Transaction::Sale(t) => {
+                    summary.total_sold = checked::add(summary.total_sold, t.operation_value, &what)?
+                }
+                _ => {}
+            }
+            summary.total_fees = checked::add(
+                summary.total_fees,
+                transaction.fees().unwrap_or_default(),
+                &what,
+            )?;
+        }
+        Ok(summary)
+    }
+}
+
+impl fmt::Display for RunSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", t!("Summary:"))?;
+        if self.record_counts.is_empty() {
+            writeln!(f, "  {}", t!("no records"))?;
+        }
+        for (record_type, count) in &self.record_counts {
+            writeln!(f, "  {}", t!("{}: {} records", record_type, count))?;
+        }
+        writeln!(
+            f,
+            "  {}",
+            style::highlight(&t!("Total bought: {} BRL", self.total_bought.round_dp(2)))
+        )?;
+        writeln!(
+            f,
+            "  {}",
+            style::highlight(&t!("Total sold: {} BRL", self.total_sold.round_dp(2)))
+        )?;
+        write!(
+            f,
+            "  {}",
+            style::highlight(&t!("Total fees: {} BRL", self.total_fees.round_dp(2)))
+        )?;
+        if !self.excluded_ids.is_empty() {
+            write!(
+                f,
+                "\n  {}",
+                t!("Excluded by ID: {}", self.excluded_ids.join(", "))
+            )?;
+        }
+        if !self.wallet_transfers.is_empty() {
+            write!(
+                f,
+                "\n  {}",
+                t!(
+                    "Left out as moves between wallets: {}",
+                    self.wallet_transfers.join(", ")
+                )
+            )?;
+        }
+        if !self.transfer_networks.is_empty() {
+            let networks = self
+                .transfer_networks
+                .iter()
+                .map(|(network, count)| format!("{} ({})", network, count))
+                .collect::<Vec<_>>();
+            write!(
+                f,
+                "\n  {}",
+                t!(
+                    "Deposits and withdrawals by network: {}",
+                    networks.join(", ")
+                )
+            )?;
+        }
+        Ok(())
+    }
+}
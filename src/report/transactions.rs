@@ -1,23 +1,30 @@
-use crate::report::encoding::{Field, write_register_row};
+//! The transactions of the report and the records they are written as.
+
+use crate::error::Result;
+use crate::report::encoding::write_register_row;
+use crate::report::layout::Layout;
 use chrono::NaiveDate;
 use rust_decimal::Decimal;
-use std::io::{self, Write};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
 
 /// Common fields shared across all transaction records
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TransactionBase {
     /// Data da operação no formato DDMMAAAA
     pub operation_date: NaiveDate,
     /// Valor das taxas em reais, cobradas na operação
+    #[serde(default, with = "super::interchange::optional_decimal")]
     pub operation_fees: Option<Decimal>,
     /// Símbolo do criptoativo (ex: BTC, ETH)
     pub crypto_symbol: String,
     /// Quantidade de criptoativos
+    #[serde(with = "rust_decimal::serde::str")]
     pub crypto_amount: Decimal,
 }
 
 /// Common fields for exchange information
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ExchangeInfo {
     /// Nome da exchange domiciliada no exterior
     pub name: String,
@@ -27,96 +34,72 @@ pub struct ExchangeInfo {
     pub country: String,
 }
 
-impl ExchangeInfo {
-    /// Returns the exchange fields in the correct order
-    fn fields(&self) -> Vec<Field<'_>> {
-        vec![
-            Field::AlphaNumber { value: &self.name },
-            Field::AlphaNumber { value: &self.url },
-            Field::AlphaNumber {
-                value: &self.country,
-            },
-        ]
-    }
-}
-
-impl TransactionBase {
-    /// Returns the common fields for this transaction base in the correct order
-    fn common_fields<'a>(&'a self, record_code: &'a str) -> Vec<Field<'a>> {
-        vec![
-            Field::Date(self.operation_date),
-            Field::AlphaNumber { value: record_code },
-            self.operation_fees
-                .as_ref()
-                .map_or(Field::Empty, |fees| Field::DecimalNumber {
-                    value: fees,
-                    precision: 2,
-                }),
-            Field::AlphaNumber {
-                value: &self.crypto_symbol,
-            },
-            Field::DecimalNumber {
-                value: &self.crypto_amount,
-                precision: 10,
-            },
-        ]
-    }
-}
-
 /// Registro 0110: Registra as operações de compra
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PurchaseTransaction {
     /// Base fields common to all transactions
     pub base: TransactionBase,
     /// Valor da operação em reais, excluídas as taxas
+    #[serde(with = "rust_decimal::serde::str")]
     pub operation_value: Decimal,
     /// Informações da exchange do comprador
     pub buyer_exchange: ExchangeInfo,
 }
 
 /// Registro 0120: Registra as operações de venda
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SaleTransaction {
     /// Base fields common to all transactions
     pub base: TransactionBase,
     /// Valor da operação em reais, excluídas as taxas
+    #[serde(with = "rust_decimal::serde::str")]
     pub operation_value: Decimal,
     /// Informações da exchange do vendedor
     pub seller_exchange: ExchangeInfo,
 }
 
 /// Registro 0210: Registra as operações de permuta
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SwapTransaction {
     /// Data da operação no formato DDMMAAAA
     pub operation_date: NaiveDate,
     /// Valor das taxas em reais, cobradas na operação
+    #[serde(default, with = "super::interchange::optional_decimal")]
     pub operation_fees: Option<Decimal>,
     /// Símbolo do criptoativo recebido
     pub received_crypto_symbol: String,
     /// Quantidade de criptoativos recebidos
+    #[serde(with = "rust_decimal::serde::str")]
     pub received_crypto_amount: Decimal,
     /// Símbolo do criptoativo entregue
     pub given_crypto_symbol: String,
     /// Quantidade de criptoativos entregues
+    #[serde(with = "rust_decimal::serde::str")]
     pub given_crypto_amount: Decimal,
     /// Informações da exchange
     pub exchange: ExchangeInfo,
 }
 
 /// Registro 0410: Registra as operações de transferência de criptoativo para Exchange
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TransferToExchangeTransaction {
     /// Base fields common to all transactions
     pub base: TransactionBase,
     /// Código alfanumérico que representa a wallet do cliente na Exchange
+    #[serde(default)]
     pub origin_wallet: Option<String>,
     /// Nome da exchange estrangeira de origem do criptoativo
+    #[serde(default)]
     pub origin_exchange_name: Option<String>,
+    /// Exchange ID of the deposit, which isn't written to the report: the
+    /// registry records it once the record is, so the deposit isn't reported
+    /// again in a later period
+    #[serde(skip)]
+    pub deposit_id: Option<String>,
 }
 
 /// Registro 0510: Registra as operações de retirada de criptoativo da Exchange
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WithdrawalFromExchangeTransaction {
     /// Base fields common to all transactions
     pub base: TransactionBase,
@@ -125,7 +108,7 @@ pub struct WithdrawalFromExchangeTransaction {
 }
 
 /// Registro 0710: Registra as operações de dação de criptoativos em pagamento - Recebedor
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CryptoPaymentReceiverTransaction {
     /// Base fields common to all transactions
     pub base: TransactionBase,
@@ -134,7 +117,7 @@ pub struct CryptoPaymentReceiverTransaction {
 }
 
 /// Registro 0720: Registra as operações de dação de criptoativos em pagamento - Pagador
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CryptoPaymentSenderTransaction {
     /// Base fields common to all transactions
     pub base: TransactionBase,
@@ -142,15 +125,22 @@ pub struct CryptoPaymentSenderTransaction {
     pub sender_exchange: ExchangeInfo,
 }
 
-/// Enum representing all possible transaction types
-#[derive(Debug)]
+/// Record type codes of all transaction types, in report order
+pub const RECORD_TYPES: [&str; 7] = ["0110", "0120", "0210", "0410", "0510", "0710", "0720"];
+
+/// Enum representing all possible transaction types. In JSON, the variant is
+/// given by the `type` field, in snake case (see [`super::interchange`]).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum Transaction {
     Purchase(PurchaseTransaction),
     Sale(SaleTransaction),
     Swap(SwapTransaction),
     TransferToExchange(TransferToExchangeTransaction),
     WithdrawalFromExchange(WithdrawalFromExchangeTransaction),
+    #[allow(dead_code)] // Not produced from Kraken data yet
     CryptoPaymentReceiver(CryptoPaymentReceiverTransaction),
+    #[allow(dead_code)] // Not produced from Kraken data yet
     CryptoPaymentSender(CryptoPaymentSenderTransaction),
 }
 
@@ -168,124 +158,74 @@ impl Transaction {
         }
     }
 
-    /// Writes the transaction to the given writer in the report format
-    pub fn write_transaction<W: Write>(&self, writer: &mut W) -> io::Result<()> {
-        let (record_type, record_code) = self.record_type();
-        let fields = match self {
-            Transaction::Purchase(t) => {
-                let mut fields = vec![
-                    Field::AlphaNumber { value: record_type },
-                    Field::Date(t.base.operation_date),
-                    Field::AlphaNumber { value: record_code },
-                    Field::DecimalNumber {
-                        value: &t.operation_value,
-                        precision: 2,
-                    },
-                    t.base.operation_fees.as_ref().map_or(Field::Empty, |fees| {
-                        Field::DecimalNumber {
-                            value: fees,
-                            precision: 2,
-                        }
-                    }),
-                    Field::AlphaNumber {
-                        value: &t.base.crypto_symbol,
-                    },
-                    Field::DecimalNumber {
-                        value: &t.base.crypto_amount,
-                        precision: 10,
-                    },
-                ];
-                fields.extend(t.buyer_exchange.fields());
-                fields
-            }
-            Transaction::Sale(t) => {
-                let mut fields = vec![
-                    Field::AlphaNumber { value: record_type },
-                    Field::Date(t.base.operation_date),
-                    Field::AlphaNumber { value: record_code },
-                    Field::DecimalNumber {
-                        value: &t.operation_value,
-                        precision: 2,
-                    },
-                    t.base.operation_fees.as_ref().map_or(Field::Empty, |fees| {
-                        Field::DecimalNumber {
-                            value: fees,
-                            precision: 2,
-                        }
-                    }),
-                    Field::AlphaNumber {
-                        value: &t.base.crypto_symbol,
-                    },
-                    Field::DecimalNumber {
-                        value: &t.base.crypto_amount,
-                        precision: 12,
-                    },
-                ];
-                fields.extend(t.seller_exchange.fields());
-                fields
-            }
-            Transaction::Swap(t) => {
-                let mut fields = vec![
-                    Field::AlphaNumber { value: record_type },
-                    Field::Date(t.operation_date),
-                    Field::AlphaNumber { value: record_code },
-                    t.operation_fees
-                        .as_ref()
-                        .map_or(Field::Empty, |fees| Field::DecimalNumber {
-                            value: fees,
-                            precision: 2,
-                        }),
-                    Field::AlphaNumber {
-                        value: &t.received_crypto_symbol,
-                    },
-                    Field::DecimalNumber {
-                        value: &t.received_crypto_amount,
-                        precision: 10,
-                    },
-                    Field::AlphaNumber {
-                        value: &t.given_crypto_symbol,
-                    },
-                    Field::DecimalNumber {
-                        value: &t.given_crypto_amount,
-                        precision: 10,
-                    },
-                ];
-                fields.extend(t.exchange.fields());
-                fields
-            }
+    /// Date of the operation
+    pub fn date(&self) -> NaiveDate {
+        match self {
+            Transaction::Purchase(t) => t.base.operation_date,
+            Transaction::Sale(t) => t.base.operation_date,
+            Transaction::Swap(t) => t.operation_date,
+            Transaction::TransferToExchange(t) => t.base.operation_date,
+            Transaction::WithdrawalFromExchange(t) => t.base.operation_date,
+            Transaction::CryptoPaymentReceiver(t) => t.base.operation_date,
+            Transaction::CryptoPaymentSender(t) => t.base.operation_date,
+        }
+    }
+
+    /// Symbols of the crypto assets of the operation: the received one first
+    /// in a swap
+    pub fn assets(&self) -> Vec<&str> {
+        match self {
+            Transaction::Purchase(t) => vec![&t.base.crypto_symbol],
+            Transaction::Sale(t) => vec![&t.base.crypto_symbol],
+            Transaction::Swap(t) => vec![&t.received_crypto_symbol, &t.given_crypto_symbol],
+            Transaction::TransferToExchange(t) => vec![&t.base.crypto_symbol],
+            Transaction::WithdrawalFromExchange(t) => vec![&t.base.crypto_symbol],
+            Transaction::CryptoPaymentReceiver(t) => vec![&t.base.crypto_symbol],
+            Transaction::CryptoPaymentSender(t) => vec![&t.base.crypto_symbol],
+        }
+    }
+
+    /// How the operation changes the amount of each asset held on the
+    /// exchanges: positive for what comes in, negative for what goes out
+    pub fn holding_changes(&self) -> Vec<(&str, Decimal)> {
+        match self {
+            Transaction::Purchase(t) => vec![(&t.base.crypto_symbol, t.base.crypto_amount)],
+            Transaction::Sale(t) => vec![(&t.base.crypto_symbol, -t.base.crypto_amount)],
+            Transaction::Swap(t) => vec![
+                (&t.received_crypto_symbol, t.received_crypto_amount),
+                (&t.given_crypto_symbol, -t.given_crypto_amount),
+            ],
             Transaction::TransferToExchange(t) => {
-                let mut fields = vec![Field::AlphaNumber { value: record_type }];
-                fields.extend(t.base.common_fields(record_code));
-                fields.extend(vec![
-                    t.origin_wallet
-                        .as_ref()
-                        .map_or(Field::Empty, |w| Field::AlphaNumber { value: w }),
-                    t.origin_exchange_name
-                        .as_ref()
-                        .map_or(Field::Empty, |n| Field::AlphaNumber { value: n }),
-                ]);
-                fields
+                vec![(&t.base.crypto_symbol, t.base.crypto_amount)]
             }
             Transaction::WithdrawalFromExchange(t) => {
-                let mut fields = vec![Field::AlphaNumber { value: record_type }];
-                fields.extend(t.base.common_fields(record_code));
-                fields.extend(t.origin_exchange.fields());
-                fields
+                vec![(&t.base.crypto_symbol, -t.base.crypto_amount)]
             }
             Transaction::CryptoPaymentReceiver(t) => {
-                let mut fields = vec![Field::AlphaNumber { value: record_type }];
-                fields.extend(t.base.common_fields(record_code));
-                fields.extend(t.receiver_exchange.fields());
-                fields
+                vec![(&t.base.crypto_symbol, t.base.crypto_amount)]
             }
             Transaction::CryptoPaymentSender(t) => {
-                let mut fields = vec![Field::AlphaNumber { value: record_type }];
-                fields.extend(t.base.common_fields(record_code));
-                fields.extend(t.sender_exchange.fields());
-                fields
+                vec![(&t.base.crypto_symbol, -t.base.crypto_amount)]
             }
-        };
+        }
+    }
+
+    /// Fees of the operation, in BRL
+    pub fn fees(&self) -> Option<Decimal> {
+        match self {
+            Transaction::Purchase(t) => t.base.operation_fees,
+            Transaction::Sale(t) => t.base.operation_fees,
+            Transaction::Swap(t) => t.operation_fees,
+            Transaction::TransferToExchange(t) => t.base.operation_fees,
+            Transaction::WithdrawalFromExchange(t) => t.base.operation_fees,
+            Transaction::CryptoPaymentReceiver(t) => t.base.operation_fees,
+            Transaction::CryptoPaymentSender(t) => t.base.operation_fees,
+        }
+    }
 
-        write_register_row(writer, &fields)
+    /// Writes the transaction to the given writer in the report format, with
+    /// the fields of the given layout
+    pub fn write_transaction<W: Write>(&self, writer: &mut W, layout: Layout) -> Result<()> {
+        write_register_row(writer, &layout.fields(self))
     }
 }
@@ -0,0 +1,182 @@
+use std::fs;
+use std::path::PathBuf;
+
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::cli::{ExchangeName, ReportOptions, parse_record_types};
+use crate::run_report;
+use kraken2rfb::error::{Error, Result};
+use kraken2rfb::i18n::t;
+use kraken2rfb::period::Period;
+use kraken2rfb::redact::redact;
+use kraken2rfb::report::FeeCredits;
+use kraken2rfb::report::layout::Layout;
+use kraken2rfb::report::manifest::ReportManifest;
+use kraken2rfb::report::precision::Truncation;
+
+/// Body of `POST /reports`. The optional fields work like the command line
+/// options of the same name.
+#[derive(Debug, Deserialize)]
+struct ReportRequest {
+    year: i32,
+    month: u32,
+    /// Like `--exchange`, e.g. "binance"; Kraken by default
+    #[serde(default)]
+    exchange: Option<String>,
+    #[serde(default)]
+    min_value: Option<Decimal>,
+    /// Comma separated, like `--record-types`
+    #[serde(default)]
+    record_types: Option<String>,
+    /// Like `--layout`, e.g. "2019"
+    #[serde(default)]
+    layout: Option<String>,
+    #[serde(default)]
+    exclude_ids: Vec<String>,
+    #[serde(default)]
+    fail_on_warning: bool,
+}
+
+/// Serves report generation over HTTP until the process is killed.
+///
+/// `POST /reports` with a [`ReportRequest`] generates the report of the
+/// period, non-interactively, and responds with the report file contents and
+/// its summary. Requests are handled one at a time.
+pub fn serve(listen: &str, keyfile: Option<PathBuf>) -> Result<()> {
+    let server = Server::http(listen)
+        .map_err(|e| Error::Config(t!("failed to listen on {}: {}", listen, e)))?;
+    println!("{}", t!("Listening on http://{}", listen));
+
+    for request in server.incoming_requests() {
+        handle(request, &keyfile);
+    }
+    Ok(())
+}
+
+fn handle(mut request: Request, keyfile: &Option<PathBuf>) {
+    let (status, body) = match (request.method(), request.url()) {
+        (Method::Post, "/reports") => match generate(&mut request, keyfile) {
+            Ok(body) => (200, body),
+            Err(e) => (status_code(&e), json!({ "error": redact(&e.to_string()) })),
+        },
+        (_, "/reports") => (405, json!({ "error": t!("only POST is allowed") })),
+        _ => (404, json!({ "error": t!("not found") })),
+    };
+
+    let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+    let response = Response::from_string(body.to_string())
+        .with_status_code(status)
+        .with_header(content_type);
+    if let Err(e) = request.respond(response) {
+        eprintln!("{}", t!("Failed to send the response: {}", e));
+    }
+}
+
+/// HTTP status of a failed report generation
+fn status_code(error: &Error) -> u16 {
+    match error {
+        // Bad request, or the server's key file is missing or wrong
+        Error::Config(_) => 400,
+        Error::Kraken { .. } | Error::Exchange { .. } if error.is_auth_error() => 400,
+        Error::UnknownAsset(_) | Error::UnknownPair(_) | Error::Validation(_) => 422,
+        Error::Network(_) | Error::Kraken { .. } | Error::Exchange { .. } | Error::Rate { .. } => {
+            502
+        }
+        Error::Io(_) => 500,
+    }
+}
+
+fn generate(request: &mut Request, keyfile: &Option<PathBuf>) -> Result<Value> {
+    let mut body = String::new();
+    request.as_reader().read_to_string(&mut body)?;
+    let report_request: ReportRequest =
+        serde_json::from_str(&body).map_err(|e| Error::Config(t!("invalid request: {}", e)))?;
+
+    // The report is written to a file named after the period, so a request that
+    // fails midway resumes from its checkpoint when retried
+    let period = Period::month(report_request.year, report_request.month)?;
+    let report_file = std::env::temp_dir().join(format!("kraken2rfb-server-{}.txt", period));
+    // Left by a request that failed after writing it
+    let _ = fs::remove_file(&report_file);
+    let options = ReportOptions {
+        period,
+        report_file: report_file.to_string_lossy().into_owned(),
+        exchange: report_request
+            .exchange
+            .as_deref()
+            .map(ExchangeName::parse)
+            .transpose()?
+            .unwrap_or_default(),
+        non_interactive: true,
+        keyfile: keyfile.clone(),
+        input: None,
+        mapping: None,
+        wallet: None,
+        xpub: None,
+        eth_addresses: Vec::new(),
+        wallet_files: Vec::new(),
+        utc_offset: None,
+        min_value: report_request.min_value,
+        record_types: report_request
+            .record_types
+            .as_deref()
+            .map(parse_record_types)
+            .transpose()?,
+        layout: report_request
+            .layout
+            .as_deref()
+            .map(Layout::parse)
+            .transpose()?
+            .unwrap_or_default(),
+        json_file: None,
+        no_store: false,
+        no_archive: false,
+        force: false,
+        reconcile: false,
+        verify_withdrawals: false,
+        fold_residuals: false,
+        truncation: Truncation::default(),
+        fee_credits: FeeCredits::default(),
+        restart: false,
+        dry_run: false,
+        review: false,
+        stream: false,
+        exclude_ids: report_request.exclude_ids,
+        exclude_file: None,
+        debug: false,
+        fail_on_warning: report_request.fail_on_warning,
+    };
+
+    let summary =
+        run_report(options)?.expect("a report is always written without dry run or review");
+    let report = fs::read_to_string(&report_file)?;
+    fs::remove_file(&report_file)?;
+    fs::remove_file(ReportManifest::path_for(&report_file))?;
+    Ok(json!({ "summary": summary, "report": report }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_report_request() {
+        let request: ReportRequest = serde_json::from_str(r#"{"year": 2024, "month": 3}"#).unwrap();
+        assert_eq!((request.year, request.month), (2024, 3));
+        assert_eq!(request.min_value, None);
+        assert!(request.exclude_ids.is_empty());
+
+        let request: ReportRequest = serde_json::from_str(
+            r#"{"year": 2024, "month": 3, "min_value": 0.50, "record_types": "0110,0120"}"#,
+        )
+        .unwrap();
+        assert_eq!(request.min_value, Some(dec!(0.50)));
+        assert_eq!(request.record_types.as_deref(), Some("0110,0120"));
+
+        assert!(serde_json::from_str::<ReportRequest>(r#"{"year": 2024}"#).is_err());
+    }
+}
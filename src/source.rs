@@ -0,0 +1,684 @@
+//! Sources of exchange activity. Each source converts what its exchange
+//! returns into the typed entries of [`Activity`], so the report layer works
+//! the same for every exchange or file importer.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::OnceLock;
+
+use chrono::{DateTime, Datelike, Days, FixedOffset, Months, NaiveDate, TimeDelta, Utc};
+use phf::phf_set;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::env;
+use crate::error::{Error, Result};
+use crate::i18n::t;
+use crate::report::transactions::ExchangeInfo;
+
+/// Whether a trade bought or sold its base asset
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+/// A deposit of an asset to the exchange
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NormalizedDeposit {
+    /// Common symbol of the asset, e.g. "BTC"
+    pub asset: String,
+    pub amount: Decimal,
+    /// Charged by the exchange, in the asset
+    pub fee: Decimal,
+    pub time: DateTime<Utc>,
+    /// Exchange IDs of the entry, its own first, by which it can be excluded
+    pub ids: Vec<String>,
+    /// Name of the exchange the asset came from, when known, e.g. from a
+    /// withdrawal matched by [`crate::transfers::match_transfers`]
+    #[serde(default)]
+    pub origin_exchange: Option<String>,
+    /// Address of the wallet of the user the asset came from, when known,
+    /// e.g. from a transfer matched by [`crate::transfers::match_wallet`]
+    #[serde(default)]
+    pub origin_wallet: Option<String>,
+    /// Network or method the asset came by, when the exchange gives it, e.g.
+    /// "Bitcoin Lightning" or "Ethereum (ERC20)"
+    #[serde(default)]
+    pub network: Option<String>,
+}
+
+/// A withdrawal of an asset from the exchange
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NormalizedWithdrawal {
+    /// Common symbol of the asset, e.g. "BTC"
+    pub asset: String,
+    pub amount: Decimal,
+    /// Charged by the exchange, in the asset
+    pub fee: Decimal,
+    pub time: DateTime<Utc>,
+    /// Exchange IDs of the entry, its own first, by which it can be excluded
+    pub ids: Vec<String>,
+    /// Address the asset was sent to, when the exchange gives it
+    #[serde(default)]
+    pub destination: Option<String>,
+    /// Network or method the asset was sent by, when the exchange gives it
+    #[serde(default)]
+    pub network: Option<String>,
+}
+
+/// A trade of a base asset for a quote asset
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NormalizedTrade {
+    /// Common symbol of the base asset, e.g. "BTC" in BTC/EUR
+    pub base: String,
+    /// Common symbol of the quote asset, e.g. "EUR" in BTC/EUR
+    pub quote: String,
+    pub side: Side,
+    /// Amount of the base asset
+    pub volume: Decimal,
+    /// Amount of the quote asset
+    pub cost: Decimal,
+    /// Charged by the exchange, in the quote asset
+    pub fee: Decimal,
+    /// Charged in the fee credits of the exchange instead, e.g. Kraken's KFEE,
+    /// as their worth in US dollars
+    #[serde(default)]
+    pub credit_fee_usd: Decimal,
+    /// Quote asset per unit of the base asset
+    pub price: Decimal,
+    pub time: DateTime<Utc>,
+    /// Exchange IDs of the trade, its own first (e.g. Kraken's trade and then
+    /// order ID), by which it can be excluded
+    pub ids: Vec<String>,
+}
+
+/// What the exchange paid an income for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IncomeKind {
+    /// A reward for referring someone, or another credit of the exchange
+    Referral,
+    /// Part of the trading fees given back
+    FeeRebate,
+    /// A reward of staking, or of another product that earns on the funds
+    /// held, like Kraken Earn
+    Staking,
+}
+
+impl IncomeKind {
+    /// Parses the name of a kind, as given on the command line
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "referral" => Ok(IncomeKind::Referral),
+            "fee-rebate" => Ok(IncomeKind::FeeRebate),
+            "staking" => Ok(IncomeKind::Staking),
+            _ => Err(Error::Config(t!(
+                "unknown income kind: {} (expected {})",
+                name,
+                "referral, fee-rebate, staking"
+            ))),
+        }
+    }
+}
+
+/// An asset the exchange paid to the account, neither bought nor deposited
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NormalizedIncome {
+    pub kind: IncomeKind,
+    /// Common symbol of the asset, e.g. "BTC"
+    pub asset: String,
+    pub amount: Decimal,
+    pub time: DateTime<Utc>,
+    /// Exchange IDs of the entry, its own first, by which it can be excluded
+    pub ids: Vec<String>,
+}
+
+/// A move of an asset between the wallets of the account on the exchange,
+/// e.g. from spot to staking, which is neither a deposit nor a withdrawal
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WalletTransfer {
+    /// Common symbol of the asset, or the exchange's name for it if unknown
+    /// (e.g. the staked variants of Kraken, like "DOT.S")
+    pub asset: String,
+    /// Into the wallet the exchange lists it in if positive, out if negative
+    pub amount: Decimal,
+    pub time: DateTime<Utc>,
+    /// What the exchange calls the move, e.g. "spottostaking"
+    pub description: String,
+    /// Exchange IDs of the entry, its own first
+    pub ids: Vec<String>,
+}
+
+/// Deposits, withdrawals, trades and income of an account in a period, sorted
+/// by time, with the assets already converted to their common symbols
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Activity {
+    pub deposits: Vec<NormalizedDeposit>,
+    pub withdrawals: Vec<NormalizedWithdrawal>,
+    pub trades: Vec<NormalizedTrade>,
+    /// Only some sources know the income, the others leave it out
+    #[serde(default)]
+    pub income: Vec<NormalizedIncome>,
+    /// Moves between the wallets of the account, left out of the deposits
+    /// and withdrawals and only listed
+    #[serde(default)]
+    pub wallet_transfers: Vec<WalletTransfer>,
+}
+
+/// An ID found in more than one entry of an activity, with different data
+#[derive(Debug, Clone, PartialEq)]
+pub enum Conflict {
+    Deposit(NormalizedDeposit, NormalizedDeposit),
+    Withdrawal(NormalizedWithdrawal, NormalizedWithdrawal),
+    Trade(NormalizedTrade, NormalizedTrade),
+    Income(NormalizedIncome, NormalizedIncome),
+    WalletTransfer(WalletTransfer, WalletTransfer),
+}
+
+/// Removes the entries whose own exchange ID was already seen, adding a
+/// conflict if their data differs from the kept one's
+fn dedup_entries<T: Clone + PartialEq>(
+    entries: Vec<T>,
+    ids: fn(&T) -> &[String],
+    conflict: fn(T, T) -> Conflict,
+    conflicts: &mut Vec<Conflict>,
+) -> Vec<T> {
+    let mut kept: Vec<T> = Vec::with_capacity(entries.len());
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for entry in entries {
+        let Some(id) = ids(&entry).first() else {
+            kept.push(entry);
+            continue;
+        };
+        match seen.get(id) {
+            Some(&index) if kept[index] != entry => {
+                conflicts.push(conflict(kept[index].clone(), entry));
+            }
+            Some(_) => {}
+            None => {
+                seen.insert(id.clone(), kept.len());
+                kept.push(entry);
+            }
+        }
+    }
+    kept
+}
+
+impl Activity {
+    /// Removes the entries imported more than once, e.g. when a period is
+    /// imported from both the API and an exported file, by their own exchange
+    /// ID: the trade ID, or the reference ID of a transfer. The first entry
+    /// with an ID is kept; the repeated ones with different data are returned
+    /// as conflicts. Entries without ID are all kept.
+    pub fn dedup(self) -> (Activity, Vec<Conflict>) {
+        let mut conflicts = Vec::new();
+        let activity = Activity {
+            deposits: dedup_entries(self.deposits, |d| &d.ids, Conflict::Deposit, &mut conflicts),
+            withdrawals: dedup_entries(
+                self.withdrawals,
+                |w| &w.ids,
+                Conflict::Withdrawal,
+                &mut conflicts,
+            ),
+            trades: dedup_entries(self.trades, |t| &t.ids, Conflict::Trade, &mut conflicts),
+            income: dedup_entries(self.income, |i| &i.ids, Conflict::Income, &mut conflicts),
+            wallet_transfers: dedup_entries(
+                self.wallet_transfers,
+                |w| &w.ids,
+                Conflict::WalletTransfer,
+                &mut conflicts,
+            ),
+        };
+        (activity, conflicts)
+    }
+
+    /// Leaves out the entries already seen in an earlier batch of the same
+    /// account, by their first ID: those in `previous` or `seen`. The IDs
+    /// are all added to `seen`, so an entry repeated in every batch is left
+    /// out of each after the first. The entries without ID are all kept.
+    pub fn unseen(self, previous: &HashSet<String>, seen: &mut HashSet<String>) -> Activity {
+        fn unseen<T>(
+            entries: Vec<T>,
+            ids: fn(&T) -> &[String],
+            previous: &HashSet<String>,
+            seen: &mut HashSet<String>,
+        ) -> Vec<T> {
+            entries
+                .into_iter()
+                .filter(|entry| {
+                    ids(entry)
+                        .first()
+                        .is_none_or(|id| seen.insert(id.clone()) && !previous.contains(id))
+                })
+                .collect()
+        }
+        Activity {
+            deposits: unseen(self.deposits, |d| &d.ids, previous, seen),
+            withdrawals: unseen(self.withdrawals, |w| &w.ids, previous, seen),
+            trades: unseen(self.trades, |t| &t.ids, previous, seen),
+            income: unseen(self.income, |i| &i.ids, previous, seen),
+            wallet_transfers: unseen(self.wallet_transfers, |w| &w.ids, previous, seen),
+        }
+    }
+
+    /// Leaves out the entries outside `first_day` to `last_day`, inclusive, for
+    /// the sources that read a whole history at once, like an exported file
+    pub fn within(self, first_day: NaiveDate, last_day: NaiveDate) -> Activity {
+        self.within_days(first_day, last_day, FixedOffset::east_opt(0).unwrap())
+    }
+
+    /// Leaves out the entries whose day, at `offset` from UTC, is outside
+    /// `first_day` to `last_day`, inclusive
+    pub fn within_days(
+        self,
+        first_day: NaiveDate,
+        last_day: NaiveDate,
+        offset: FixedOffset,
+    ) -> Activity {
+        let within = |time: &DateTime<Utc>| {
+            (first_day..=last_day).contains(&time.with_timezone(&offset).date_naive())
+        };
+        Activity {
+            deposits: self
+                .deposits
+                .into_iter()
+                .filter(|d| within(&d.time))
+                .collect(),
+            withdrawals: self
+                .withdrawals
+                .into_iter()
+                .filter(|w| within(&w.time))
+                .collect(),
+            trades: self
+                .trades
+                .into_iter()
+                .filter(|t| within(&t.time))
+                .collect(),
+            income: self
+                .income
+                .into_iter()
+                .filter(|i| within(&i.time))
+                .collect(),
+            wallet_transfers: self
+                .wallet_transfers
+                .into_iter()
+                .filter(|w| within(&w.time))
+                .collect(),
+        }
+    }
+
+    /// Leaves only the entries with any of `ids` among their exchange IDs,
+    /// e.g. a trade by its trade or order ID
+    pub fn with_ids(self, ids: &[String]) -> Activity {
+        fn with_ids<T>(entries: Vec<T>, own: fn(&T) -> &[String], ids: &[String]) -> Vec<T> {
+            entries
+                .into_iter()
+                .filter(|entry| own(entry).iter().any(|id| ids.contains(id)))
+                .collect()
+        }
+        Activity {
+            deposits: with_ids(self.deposits, |d| &d.ids, ids),
+            withdrawals: with_ids(self.withdrawals, |w| &w.ids, ids),
+            trades: with_ids(self.trades, |t| &t.ids, ids),
+            income: with_ids(self.income, |i| &i.ids, ids),
+            wallet_transfers: with_ids(self.wallet_transfers, |w| &w.ids, ids),
+        }
+    }
+
+    /// Whether there is no entry at all
+    pub fn is_empty(&self) -> bool {
+        self.deposits.is_empty()
+            && self.withdrawals.is_empty()
+            && self.trades.is_empty()
+            && self.income.is_empty()
+            && self.wallet_transfers.is_empty()
+    }
+
+    /// Adds the entries of another activity of the same account, keeping
+    /// them sorted by time. Repeated entries are left for [`Activity::dedup`].
+    pub fn extend(&mut self, other: Activity) {
+        self.deposits.extend(other.deposits);
+        self.deposits.sort_by_key(|d| d.time);
+        self.withdrawals.extend(other.withdrawals);
+        self.withdrawals.sort_by_key(|w| w.time);
+        self.trades.extend(other.trades);
+        self.trades.sort_by_key(|t| t.time);
+        self.income.extend(other.income);
+        self.income.sort_by_key(|i| i.time);
+        self.wallet_transfers.extend(other.wallet_transfers);
+        self.wallet_transfers.sort_by_key(|w| w.time);
+    }
+}
+
+/// An exchange, or a file exported from one, the activity comes from
+pub trait ExchangeSource {
+    /// The exchange, as written in the records of the report
+    fn exchange(&self) -> ExchangeInfo;
+
+    /// Fetches the activity from `first_day` to `last_day`, inclusive
+    fn fetch(&self, first_day: NaiveDate, last_day: NaiveDate) -> Result<Activity>;
+
+    /// Fetches the same activity in batches, so it can be converted and
+    /// written without holding all of it. By default, each batch is a
+    /// calendar month; a source that pages through its history can return
+    /// the pages instead. An entry must not be in more than one batch.
+    fn fetch_batches<'s>(
+        &'s self,
+        first_day: NaiveDate,
+        last_day: NaiveDate,
+    ) -> Box<dyn Iterator<Item = Result<Activity>> + 's> {
+        Box::new(month_ranges(first_day, last_day).map(|(first, last)| self.fetch(first, last)))
+    }
+}
+
+impl<S: ExchangeSource + ?Sized> ExchangeSource for Box<S> {
+    fn exchange(&self) -> ExchangeInfo {
+        (**self).exchange()
+    }
+
+    fn fetch(&self, first_day: NaiveDate, last_day: NaiveDate) -> Result<Activity> {
+        (**self).fetch(first_day, last_day)
+    }
+
+    fn fetch_batches<'s>(
+        &'s self,
+        first_day: NaiveDate,
+        last_day: NaiveDate,
+    ) -> Box<dyn Iterator<Item = Result<Activity>> + 's> {
+        (**self).fetch_batches(first_day, last_day)
+    }
+}
+
+/// Fetches the activity of the days from `first_day` to `last_day`, at
+/// `offset` from UTC. The sources fetch by UTC days, so a day more is fetched
+/// on each side, and what falls outside the days is left out.
+pub fn fetch_days(
+    source: &dyn ExchangeSource,
+    first_day: NaiveDate,
+    last_day: NaiveDate,
+    offset: FixedOffset,
+) -> Result<Activity> {
+    let activity = source.fetch(
+        first_day.pred_opt().unwrap_or(first_day),
+        last_day.succ_opt().unwrap_or(last_day),
+    )?;
+    Ok(activity.within_days(first_day, last_day, offset))
+}
+
+/// The part of each calendar month from `first_day` to `last_day`, inclusive
+pub fn month_ranges(
+    first_day: NaiveDate,
+    last_day: NaiveDate,
+) -> impl Iterator<Item = (NaiveDate, NaiveDate)> {
+    let mut next = Some(first_day);
+    std::iter::from_fn(move || {
+        let first = next.filter(|&day| day <= last_day)?;
+        let next_month = first.with_day(1)?.checked_add_months(Months::new(1))?;
+        next = Some(next_month);
+        Some((first, next_month.pred_opt()?.min(last_day)))
+    })
+}
+
+/// Consecutive ranges of at most `days` days from `first_day` to `last_day`,
+/// inclusive
+pub fn day_ranges(
+    first_day: NaiveDate,
+    last_day: NaiveDate,
+    days: u64,
+) -> impl Iterator<Item = (NaiveDate, NaiveDate)> {
+    let mut next = Some(first_day);
+    std::iter::from_fn(move || {
+        let first = next.filter(|&day| day <= last_day)?;
+        let last = first.checked_add_days(Days::new(days - 1))?.min(last_day);
+        next = last.succ_opt();
+        Some((first, last))
+    })
+}
+
+/// Milliseconds since the UNIX epoch at the start of a day, as the exchange
+/// APIs take their times
+pub fn day_millis(date: NaiveDate) -> i64 {
+    date.and_hms_opt(0, 0, 0)
+        .unwrap()
+        .and_utc()
+        .timestamp_millis()
+}
+
+/// The first and last millisecond from the start of `first_day` to the end
+/// of `last_day`
+pub fn millis_range(first_day: NaiveDate, last_day: NaiveDate) -> (i64, i64) {
+    (
+        day_millis(first_day),
+        day_millis(last_day) + TimeDelta::days(1).num_milliseconds() - 1,
+    )
+}
+
+/// Ranges of at most `days` days covering `start..=end`, in milliseconds, for
+/// the APIs that limit the time range of a request
+pub fn millis_windows(start: i64, end: i64, days: i64) -> impl Iterator<Item = (i64, i64)> {
+    let step = TimeDelta::days(days).num_milliseconds();
+    (start..=end)
+        .step_by(step as usize)
+        .map(move |from| (from, (from + step - 1).min(end)))
+}
+
+static EXTRA_FIAT: OnceLock<HashSet<String>> = OnceLock::new();
+
+/// Adds the fiat currencies of `fiat` in the configuration file and of
+/// `KRAKEN2RFB_FIAT`, separated by commas, to the ones [`is_fiat`] knows, for
+/// those Kraken started taking after the release. Only the first call has
+/// any effect.
+pub fn init_fiat(configured: &[String]) -> Result<()> {
+    let listed = env::var("FIAT").unwrap_or_default();
+    let mut tickers = HashSet::new();
+    for ticker in configured
+        .iter()
+        .map(String::as_str)
+        .chain(listed.split(','))
+    {
+        let ticker = ticker.trim().to_uppercase();
+        if ticker.is_empty() {
+            continue;
+        }
+        if !ticker.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(Error::Config(t!("invalid fiat currency: {}", ticker)));
+        }
+        tickers.insert(ticker);
+    }
+    let _ = EXTRA_FIAT.set(tickers);
+    Ok(())
+}
+
+/// Whether an asset, given by its common symbol or Kraken name, is a fiat
+/// currency, one of the known ones or of [`init_fiat`]
+pub fn is_fiat(ticker: &str) -> bool {
+    static FIAT_CURRENCIES: phf::Set<&'static str> = phf_set! {
+        "USD", "ZUSD",
+        "EUR", "ZEUR",
+        "GBP", "ZGBP",
+        "JPY", "ZJPY",
+        "CAD", "ZCAD",
+        "AUD", "ZAUD",
+        "MXN", "ZMXN",
+        "CHF", "ZCHF",
+        "BRL", "ZBRL",
+        "ARS", "ZARS",
+        "AED", "ZAED",
+    };
+    let ticker = ticker.to_uppercase();
+    if FIAT_CURRENCIES.contains(ticker.as_str()) {
+        return true;
+    }
+    let Some(extra) = EXTRA_FIAT.get() else {
+        return false;
+    };
+    // Kraken names the fiat currencies like ZUSD, with the Z
+    extra.contains(&ticker)
+        || ticker
+            .strip_prefix('Z')
+            .is_some_and(|name| name.len() == 3 && extra.contains(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn deposit(id: &str, amount: Decimal) -> NormalizedDeposit {
+        NormalizedDeposit {
+            asset: "BTC".to_string(),
+            amount,
+            fee: Decimal::ZERO,
+            time: "2024-03-01T12:00:00Z".parse().unwrap(),
+            ids: [id]
+                .into_iter()
+                .filter(|id| !id.is_empty())
+                .map(String::from)
+                .collect(),
+            origin_exchange: None,
+            origin_wallet: None,
+            network: None,
+        }
+    }
+
+    #[test]
+    fn test_dedup() {
+        let mut activity = Activity {
+            deposits: vec![deposit("A", dec!(1)), deposit("", dec!(2))],
+            ..Activity::default()
+        };
+        activity.extend(Activity {
+            deposits: vec![
+                deposit("A", dec!(1)),
+                deposit("B", dec!(3)),
+                deposit("A", dec!(1.5)),
+                deposit("", dec!(2)),
+            ],
+            ..Activity::default()
+        });
+        let (activity, conflicts) = activity.dedup();
+        let amounts: Vec<_> = activity.deposits.iter().map(|d| d.amount).collect();
+        assert_eq!(amounts, [dec!(1), dec!(2), dec!(3), dec!(2)]);
+        assert_eq!(
+            conflicts,
+            [Conflict::Deposit(
+                deposit("A", dec!(1)),
+                deposit("A", dec!(1.5))
+            )]
+        );
+    }
+
+    #[test]
+    fn test_within_days() {
+        let day = |m, d| NaiveDate::from_ymd_opt(2024, m, d).unwrap();
+        let at = |time: &str| NormalizedDeposit {
+            time: time.parse().unwrap(),
+            ..deposit("", dec!(1))
+        };
+        let activity = Activity {
+            deposits: vec![
+                at("2024-03-01T02:00:00Z"),
+                at("2024-03-01T04:00:00Z"),
+                at("2024-04-01T02:00:00Z"),
+            ],
+            ..Activity::default()
+        };
+        let times = |activity: Activity| -> Vec<String> {
+            activity
+                .deposits
+                .iter()
+                .map(|d| d.time.format("%m-%dT%H").to_string())
+                .collect()
+        };
+        let brasilia = crate::period::BRASILIA;
+        assert_eq!(
+            times(
+                activity
+                    .clone()
+                    .within_days(day(3, 1), day(3, 31), brasilia)
+            ),
+            ["03-01T04", "04-01T02"]
+        );
+        assert_eq!(
+            times(activity.within(day(3, 1), day(3, 31))),
+            ["03-01T02", "03-01T04"]
+        );
+    }
+
+    #[test]
+    fn test_with_ids() {
+        let activity = Activity {
+            deposits: vec![deposit("A", dec!(1)), deposit("B", dec!(2))],
+            ..Activity::default()
+        };
+        let only = activity.clone().with_ids(&["B".to_string()]);
+        assert_eq!(only.deposits, [deposit("B", dec!(2))]);
+        assert!(!only.is_empty());
+        assert!(activity.with_ids(&["C".to_string()]).is_empty());
+    }
+
+    #[test]
+    fn test_month_ranges() {
+        let day = |m, d| NaiveDate::from_ymd_opt(2024, m, d).unwrap();
+        let ranges: Vec<_> = month_ranges(day(1, 15), day(3, 10)).collect();
+        assert_eq!(
+            ranges,
+            [
+                (day(1, 15), day(1, 31)),
+                (day(2, 1), day(2, 29)),
+                (day(3, 1), day(3, 10)),
+            ]
+        );
+        assert_eq!(month_ranges(day(3, 2), day(3, 1)).count(), 0);
+    }
+
+    #[test]
+    fn test_day_ranges() {
+        let day = |m, d| NaiveDate::from_ymd_opt(2024, m, d).unwrap();
+        let ranges: Vec<_> = day_ranges(day(2, 26), day(3, 10), 7).collect();
+        assert_eq!(ranges, [(day(2, 26), day(3, 3)), (day(3, 4), day(3, 10)),]);
+        assert_eq!(day_ranges(day(3, 1), day(3, 1), 7).count(), 1);
+        assert_eq!(day_ranges(day(3, 2), day(3, 1), 7).count(), 0);
+    }
+
+    #[test]
+    fn test_unseen() {
+        let batch = |ids: &[&str]| Activity {
+            deposits: ids.iter().map(|id| deposit(id, dec!(1))).collect(),
+            ..Activity::default()
+        };
+        let ids = |activity: Activity| -> Vec<String> {
+            activity
+                .deposits
+                .into_iter()
+                .map(|d| d.ids[0].clone())
+                .collect()
+        };
+        let previous = HashSet::from(["A".to_string()]);
+        let mut seen = HashSet::new();
+        assert_eq!(ids(batch(&["A", "B"]).unseen(&previous, &mut seen)), ["B"]);
+        assert_eq!(ids(batch(&["B", "C"]).unseen(&previous, &mut seen)), ["C"]);
+        assert_eq!(seen.len(), 3);
+    }
+
+    #[test]
+    fn test_millis_windows() {
+        let day = TimeDelta::days(1).num_milliseconds();
+        let ranges: Vec<_> = millis_windows(0, 45 * day - 1, 30).collect();
+        assert_eq!(ranges, [(0, 30 * day - 1), (30 * day, 45 * day - 1)]);
+        let march = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        let (start, end) = millis_range(march, NaiveDate::from_ymd_opt(2024, 3, 31).unwrap());
+        assert_eq!((start, end - start + 1), (1709251200000, 31 * day));
+    }
+
+    #[test]
+    fn test_init_fiat() {
+        assert!(is_fiat("zusd") && is_fiat("BRL"));
+        assert!(!is_fiat("BTC"));
+        assert!(init_fiat(&["PL N".to_string()]).is_err());
+        init_fiat(&[" czk ".to_string()]).unwrap();
+        assert!(is_fiat("CZK") && is_fiat("ZCZK"));
+        assert!(!is_fiat("XCZK") && !is_fiat("BTC"));
+    }
+}
@@ -0,0 +1,1131 @@
+//! Local store of every transaction reported so far, across periods and
+//! sources, so the history can be queried without fetching it again.
+//!
+//! The store is a JSON file, by default `transactions.json` in
+//! [`paths::data_dir`], holding the transactions in the format of
+//! [`report::interchange`](crate::report::interchange), each with the source
+//! it came from. Reporting a period again replaces what the store had of that
+//! source in the period. The [`Income`] received is kept apart, since it
+//! isn't a transaction of the report.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use chrono::{DateTime, Datelike, FixedOffset, Months, NaiveDate, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::checked;
+use crate::error::{Error, Result};
+use crate::gains::{MonthResult, carry_losses, realized_gains};
+use crate::i18n::t;
+use crate::income::Income;
+use crate::paths;
+use crate::period::{BRASILIA, Period};
+use crate::reconcile::{Discrepancy, reconcile};
+use crate::report::interchange;
+use crate::report::resolve::Resolver;
+use crate::report::transactions::Transaction;
+use crate::source::{Activity, is_fiat};
+use crate::tax;
+use crate::transfers::InternalTransfer;
+
+/// Name of the store file in the data directory
+const STORE_NAME: &str = "transactions.json";
+
+/// A transaction with where it came from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredTransaction {
+    /// Name of the exchange or importer, as in the records of the report
+    pub source: String,
+    pub transaction: Transaction,
+    /// Key shared by a withdrawal and the deposit it became on another
+    /// exchange of the user, see [`Store::link_transfers`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transfer: Option<u64>,
+}
+
+/// An income with where it came from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredIncome {
+    /// Name of the exchange or importer, as in the records of the report
+    pub source: String,
+    pub income: Income,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoreData {
+    /// The version of the interchange format the transactions are in
+    version: u32,
+    entries: Vec<StoredTransaction>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    income: Vec<StoredIncome>,
+    /// Losses carried from before the stored history, in BRL
+    #[serde(default, with = "rust_decimal::serde::str")]
+    opening_loss: Decimal,
+}
+
+/// Which stored transactions to select; every criterion left as `None` matches
+/// all of them
+#[derive(Debug, Default)]
+pub struct Query {
+    /// Only the transactions of this asset, on either side of a swap
+    pub asset: Option<String>,
+    /// Only records of these types (e.g. "0410")
+    pub record_types: Option<Vec<String>>,
+    /// Only the transactions within this period
+    pub period: Option<Period>,
+    /// Only the transactions of the source with this name
+    pub source: Option<String>,
+}
+
+impl Query {
+    pub fn matches(&self, entry: &StoredTransaction) -> bool {
+        let transaction = &entry.transaction;
+        self.asset.as_ref().is_none_or(|asset| {
+            transaction
+                .assets()
+                .iter()
+                .any(|a| a.eq_ignore_ascii_case(asset))
+        }) && self
+            .record_types
+            .as_ref()
+            .is_none_or(|types| types.iter().any(|t| t == transaction.record_type().0))
+            && self
+                .period
+                .is_none_or(|period| contains(period, transaction))
+            && self
+                .source
+                .as_ref()
+                .is_none_or(|source| source.eq_ignore_ascii_case(&entry.source))
+    }
+}
+
+fn contains(period: Period, transaction: &Transaction) -> bool {
+    (period.first_day()..=period.last_day()).contains(&transaction.date())
+}
+
+/// An asset held on a date, see [`Store::valued_holdings`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Holding {
+    pub asset: String,
+    pub amount: Decimal,
+    /// Value of the amount in BRL, at the rate of the date
+    pub value: Decimal,
+}
+
+/// The transaction store, loaded in memory
+pub struct Store {
+    path: PathBuf,
+    entries: Vec<StoredTransaction>,
+    income: Vec<StoredIncome>,
+    opening_loss: Decimal,
+}
+
+impl Store {
+    /// Path of the store, `$KRAKEN2RFB_STORE` if set, otherwise in the data
+    /// directory. `None` if there is no data directory.
+    pub fn default_path() -> Option<PathBuf> {
+        crate::env::var("STORE")
+            .map(PathBuf::from)
+            .or_else(|| paths::data_dir().map(|dir| dir.join(STORE_NAME)))
+    }
+
+    /// Opens the store at `path`, empty if it doesn't exist yet
+    pub fn open(path: PathBuf) -> Result<Self> {
+        let data = match fs::read_to_string(&path) {
+            Ok(content) => {
+                let data: StoreData = serde_json::from_str(&content).map_err(|e| {
+                    Error::Config(t!("corrupted store file {}: {}", path.display(), e))
+                })?;
+                if data.version != interchange::VERSION {
+                    return Err(Error::Config(t!(
+                        "the store {} is from another version of kraken2rfb",
+                        path.display()
+                    )));
+                }
+                data
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => StoreData {
+                version: interchange::VERSION,
+                entries: Vec::new(),
+                income: Vec::new(),
+                opening_loss: Decimal::ZERO,
+            },
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Store {
+            path,
+            entries: data.entries,
+            income: data.income,
+            opening_loss: data.opening_loss,
+        })
+    }
+
+    /// Replaces the transactions of `source` within `period` by `transactions`
+    pub fn replace_period(&mut self, source: &str, period: Period, transactions: &[Transaction]) {
+        self.entries
+            .retain(|entry| entry.source != source || !contains(period, &entry.transaction));
+        self.entries
+            .extend(transactions.iter().map(|transaction| StoredTransaction {
+                source: source.to_string(),
+                transaction: transaction.clone(),
+                transfer: None,
+            }));
+        self.entries
+            .sort_by_key(|entry| (entry.transaction.date(), entry.transaction.record_type().0));
+    }
+
+    /// Saves the transactions of an import file of `source`, replacing what
+    /// the store had of the source in each month they span, and links the
+    /// deposits naming the exchange they came from. Returns the first and
+    /// last day of the transactions, `None` if there are none.
+    pub fn import(
+        &mut self,
+        source: &str,
+        transactions: &[Transaction],
+    ) -> Result<Option<(NaiveDate, NaiveDate)>> {
+        let (Some(first), Some(last)) = (
+            transactions.iter().map(Transaction::date).min(),
+            transactions.iter().map(Transaction::date).max(),
+        ) else {
+            return Ok(None);
+        };
+        let mut month = first.with_day(1).expect("every month has a first day");
+        while month <= last {
+            let period = Period::month(month.year(), month.month())?;
+            let in_month: Vec<Transaction> = transactions
+                .iter()
+                .filter(|transaction| contains(period, transaction))
+                .cloned()
+                .collect();
+            self.replace_period(source, period, &in_month);
+            month = month + Months::new(1);
+        }
+        self.link_transfers(&[], BRASILIA);
+        Ok(Some((first, last)))
+    }
+
+    /// Saves the transactions and income of the report of `source` for
+    /// `period`, replacing what the store had of the source in the period,
+    /// and links its internal transfers, whose days are those at `offset`
+    pub fn save_report(
+        &mut self,
+        source: &str,
+        period: Period,
+        transactions: &[Transaction],
+        income: &[Income],
+        transfers: &[InternalTransfer],
+        offset: FixedOffset,
+    ) {
+        self.replace_period(source, period, transactions);
+        self.replace_period_income(source, period, income);
+        self.link_transfers(transfers, offset);
+    }
+
+    /// Links the withdrawals stored to the deposits they became on another
+    /// exchange of the user, for the deposits to take their cost in
+    /// [`Store::monthly_results`]: first the pairs of `transfers`, whose days
+    /// are those at `offset`, and then each deposit whose origin exchange is
+    /// a source of the store, to a withdrawal of the same asset from it, at
+    /// most a day apart and of at least the amount deposited, the closest in
+    /// amount and then in date. The links left with a single entry, after
+    /// the other was replaced, are dropped first.
+    pub fn link_transfers(&mut self, transfers: &[InternalTransfer], offset: FixedOffset) {
+        let mut counts: BTreeMap<u64, usize> = BTreeMap::new();
+        for key in self.entries.iter().filter_map(|entry| entry.transfer) {
+            *counts.entry(key).or_default() += 1;
+        }
+        for entry in &mut self.entries {
+            if entry.transfer.is_some_and(|key| counts[&key] < 2) {
+                entry.transfer = None;
+            }
+        }
+        let mut next_key = counts.keys().last().map_or(0, |key| key + 1);
+        let mut link = |entries: &mut [StoredTransaction], withdrawal: usize, deposit: usize| {
+            entries[withdrawal].transfer = Some(next_key);
+            entries[deposit].transfer = Some(next_key);
+            next_key += 1;
+        };
+
+        let day = |time: DateTime<Utc>| time.with_timezone(&offset).date_naive();
+        for transfer in transfers {
+            let find = |source: &str, withdrawal: bool, asset: &str, date, amount| {
+                self.entries.iter().position(|entry| {
+                    entry.transfer.is_none()
+                        && entry.source == source
+                        && transfer_of(&entry.transaction, withdrawal)
+                            == Some((asset, date, amount))
+                })
+            };
+            let (w, d) = (&transfer.withdrawal, &transfer.deposit);
+            if let Some(withdrawal) = find(&transfer.from, true, &w.asset, day(w.time), w.amount)
+                && let Some(deposit) = find(&transfer.to, false, &d.asset, day(d.time), d.amount)
+            {
+                link(&mut self.entries, withdrawal, deposit);
+            }
+        }
+
+        for deposit in 0..self.entries.len() {
+            let entry = &self.entries[deposit];
+            let (Transaction::TransferToExchange(transfer), None) =
+                (&entry.transaction, entry.transfer)
+            else {
+                continue;
+            };
+            let Some(origin) = &transfer.origin_exchange_name else {
+                continue;
+            };
+            let (asset, date, amount) = (
+                transfer.base.crypto_symbol.as_str(),
+                transfer.base.operation_date,
+                transfer.base.crypto_amount,
+            );
+            let withdrawal = self
+                .entries
+                .iter()
+                .enumerate()
+                .filter(|(_, entry)| {
+                    entry.transfer.is_none() && entry.source.eq_ignore_ascii_case(origin)
+                })
+                .filter_map(|(i, entry)| {
+                    let (a, d, w) = transfer_of(&entry.transaction, true)?;
+                    let days = (d - date).num_days().abs();
+                    (a == asset && days <= 1 && w >= amount).then_some((i, (w - amount, days)))
+                })
+                .min_by_key(|&(_, distance)| distance)
+                .map(|(i, _)| i);
+            if let Some(withdrawal) = withdrawal {
+                link(&mut self.entries, withdrawal, deposit);
+            }
+        }
+    }
+
+    /// Names the exchange each deposit of `activity`, on `exchange`, came
+    /// from, when a withdrawal stored from another source may have become
+    /// it: of the same asset, on the day of the deposit at `offset` or the
+    /// one before, and of at least the amount deposited, the closest in
+    /// amount and then in date, as [`Store::link_transfers`] links them
+    /// later. The withdrawals already linked and the deposits whose origin
+    /// is known are left alone. Returns the indices of the deposits named.
+    pub fn match_deposits(
+        &self,
+        exchange: &str,
+        activity: &mut Activity,
+        offset: FixedOffset,
+    ) -> Vec<usize> {
+        let mut used = Vec::new();
+        let mut matched = Vec::new();
+        for (i, deposit) in activity.deposits.iter_mut().enumerate() {
+            if deposit.origin_exchange.is_some() || deposit.origin_wallet.is_some() {
+                continue;
+            }
+            let date = deposit.time.with_timezone(&offset).date_naive();
+            let withdrawal = self
+                .entries
+                .iter()
+                .enumerate()
+                .filter(|(j, entry)| {
+                    entry.transfer.is_none()
+                        && !entry.source.eq_ignore_ascii_case(exchange)
+                        && !used.contains(j)
+                })
+                .filter_map(|(j, entry)| {
+                    let (a, d, w) = transfer_of(&entry.transaction, true)?;
+                    let days = (date - d).num_days();
+                    (a == deposit.asset && (0..=1).contains(&days) && w >= deposit.amount)
+                        .then_some((j, (w - deposit.amount, days)))
+                })
+                .min_by_key(|&(_, distance)| distance)
+                .map(|(j, _)| j);
+            if let Some(j) = withdrawal {
+                used.push(j);
+                deposit.origin_exchange = Some(self.entries[j].source.clone());
+                matched.push(i);
+            }
+        }
+        matched
+    }
+
+    /// Replaces the income of `source` within `period` by `income`
+    pub fn replace_period_income(&mut self, source: &str, period: Period, income: &[Income]) {
+        let within = |date: NaiveDate| (period.first_day()..=period.last_day()).contains(&date);
+        self.income
+            .retain(|entry| entry.source != source || !within(entry.income.date));
+        self.income.extend(income.iter().map(|income| StoredIncome {
+            source: source.to_string(),
+            income: income.clone(),
+        }));
+        self.income.sort_by_key(|entry| entry.income.date);
+    }
+
+    /// The stored income, of `year` only if given, by date
+    pub fn income(&self, year: Option<i32>) -> impl Iterator<Item = &StoredIncome> {
+        self.income
+            .iter()
+            .filter(move |entry| year.is_none_or(|year| entry.income.date.year() == year))
+    }
+
+    /// The stored transactions matching `query`, by date
+    pub fn query<'a>(&'a self, query: &'a Query) -> impl Iterator<Item = &'a StoredTransaction> {
+        self.entries.iter().filter(|entry| query.matches(entry))
+    }
+
+    /// The amount of each asset held on `date`, at the end of the day, by
+    /// the transactions matching `query` up to it. The assets whose amount
+    /// adds up to zero are left out.
+    pub fn holdings(&self, query: &Query, date: NaiveDate) -> Result<BTreeMap<String, Decimal>> {
+        let mut holdings: BTreeMap<String, Decimal> = BTreeMap::new();
+        for entry in self.query(query) {
+            if entry.transaction.date() > date {
+                break;
+            }
+            let what = t!(
+                "the holdings, adding the {} record of {}",
+                entry.transaction.record_type().0,
+                entry.transaction.date()
+            );
+            for (asset, change) in entry.transaction.holding_changes() {
+                let amount = holdings.entry(asset.to_string()).or_default();
+                *amount = checked::add(*amount, change, &what)?;
+            }
+        }
+        holdings.retain(|_, amount| !amount.is_zero());
+        if let Some(asset) = &query.asset {
+            // A swap also changes the asset it was swapped with
+            holdings.retain(|a, _| a.eq_ignore_ascii_case(asset));
+        }
+        Ok(holdings)
+    }
+
+    /// The BRL value of the sales of each month of `year`, across all
+    /// sources, keyed by month number. The months without sales are left out.
+    pub fn monthly_sales(&self, year: i32) -> Result<BTreeMap<u32, Decimal>> {
+        let mut sales: BTreeMap<u32, Decimal> = BTreeMap::new();
+        for entry in &self.entries {
+            if let Transaction::Sale(sale) = &entry.transaction
+                && sale.base.operation_date.year() == year
+            {
+                let date = sale.base.operation_date;
+                let what = t!("the sales, adding the {} record of {}", "0120", date);
+                let total = sales.entry(date.month()).or_default();
+                *total = checked::add(*total, sale.operation_value, &what)?;
+            }
+        }
+        Ok(sales)
+    }
+
+    /// The BRL value of the sales of a month, across all sources
+    pub fn month_sales(&self, year: i32, month: u32) -> Result<Decimal> {
+        Ok(self
+            .monthly_sales(year)?
+            .get(&month)
+            .copied()
+            .unwrap_or_default())
+    }
+
+    /// The months of `period` whose sales, across all sources, exceed the
+    /// monthly exemption, by their first day, with the sales
+    pub fn taxable_months(&self, period: Period) -> Result<Vec<(NaiveDate, Decimal)>> {
+        let mut months = Vec::new();
+        let mut month = period.first_day();
+        while month <= period.last_day() {
+            let sales = self.month_sales(month.year(), month.month())?;
+            if sales > tax::MONTHLY_EXEMPTION {
+                months.push((month, sales));
+            }
+            month = month + Months::new(1);
+        }
+        Ok(months)
+    }
+
+    /// The holdings on `date` of the transactions matching `query`, see
+    /// [`Store::holdings`], each with its BRL value on that date at the rate
+    /// of `resolver`, rounded to cents, and their total
+    pub fn valued_holdings(
+        &self,
+        query: &Query,
+        date: NaiveDate,
+        resolver: &mut (impl Resolver + ?Sized),
+    ) -> Result<(Vec<Holding>, Decimal)> {
+        let mut holdings = Vec::new();
+        let mut total = Decimal::ZERO;
+        for (asset, amount) in self.holdings(query, date)? {
+            let what = t!("the holdings of {} on {}", asset, date);
+            let rate = resolver.rate(&asset, date)?;
+            let value = checked::mul(amount, rate, &what)?.round_dp(2);
+            total = checked::add(total, value, &what)?;
+            holdings.push(Holding {
+                asset,
+                amount,
+                value,
+            });
+        }
+        Ok((holdings, total))
+    }
+
+    /// Compares the holdings of `source` on `date`, of `asset` only if given,
+    /// with its balances on the exchange at the end of that day. The store
+    /// has no fiat, so the fiat balances are left out.
+    pub fn reconcile_holdings(
+        &self,
+        source: &str,
+        asset: Option<&str>,
+        date: NaiveDate,
+        mut balances: BTreeMap<String, Decimal>,
+    ) -> Result<Vec<Discrepancy>> {
+        let query = Query {
+            asset: asset.map(String::from),
+            source: Some(source.to_string()),
+            ..Query::default()
+        };
+        let holdings = self.holdings(&query, date)?;
+        balances.retain(|a, _| !is_fiat(a) && asset.is_none_or(|asset| asset == a));
+        Ok(reconcile(&holdings, &balances))
+    }
+
+    /// Losses carried from before the stored history, to be taken from its
+    /// first gains
+    pub fn opening_loss(&self) -> Decimal {
+        self.opening_loss
+    }
+
+    pub fn set_opening_loss(&mut self, loss: Decimal) {
+        self.opening_loss = loss;
+    }
+
+    /// The tax of each month with sales, with the losses carried forward
+    /// across the whole stored history, see [`crate::gains`]
+    pub fn monthly_results(&self) -> Result<Vec<MonthResult>> {
+        let gains = realized_gains(
+            self.entries
+                .iter()
+                .map(|entry| (&entry.transaction, entry.transfer)),
+            self.income.iter().map(|entry| &entry.income),
+        )?;
+        carry_losses(&gains, self.opening_loss)
+    }
+
+    /// Writes the store back to its file
+    pub fn save(&self) -> Result<()> {
+        let data = StoreData {
+            version: interchange::VERSION,
+            entries: self.entries.clone(),
+            income: self.income.clone(),
+            opening_loss: self.opening_loss,
+        };
+        let content = serde_json::to_string(&data)
+            .map_err(|e| Error::Validation(t!("failed to serialize the store: {}", e)))?;
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // Write to a temporary file first, so a crash never leaves a truncated store
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, content)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+/// Columns of the CSV export, one row per stored transaction
+const CSV_HEADER: [&str; 14] = [
+    "date",
+    "record_type",
+    "type",
+    "asset",
+    "amount",
+    "counter_asset",
+    "counter_amount",
+    "brl_value",
+    "brl_fees",
+    "source",
+    "exchange",
+    "exchange_url",
+    "exchange_country",
+    "origin",
+];
+
+/// The asset, date and amount of a withdrawal, or of a deposit if not
+/// `withdrawal`; `None` for the other transactions
+fn transfer_of(transaction: &Transaction, withdrawal: bool) -> Option<(&str, NaiveDate, Decimal)> {
+    let base = match transaction {
+        Transaction::WithdrawalFromExchange(t) if withdrawal => &t.base,
+        Transaction::TransferToExchange(t) if !withdrawal => &t.base,
+        _ => return None,
+    };
+    Some((
+        base.crypto_symbol.as_str(),
+        base.operation_date,
+        base.crypto_amount,
+    ))
+}
+
+/// Quotes a CSV field if it has a separator, a quote or a line break
+fn csv_field(value: &str) -> Cow<'_, str> {
+    if value.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", value.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+/// The CSV row of a stored transaction, in the order of [`CSV_HEADER`]
+fn csv_row(entry: &StoredTransaction) -> Vec<String> {
+    let transaction = &entry.transaction;
+    let (kind, asset, amount, counter, value, exchange, origin) = match transaction {
+        Transaction::Purchase(t) => (
+            "purchase",
+            &t.base.crypto_symbol,
+            t.base.crypto_amount,
+            None,
+            Some(t.operation_value),
+            Some(&t.buyer_exchange),
+            None,
+        ),
+        Transaction::Sale(t) => (
+            "sale",
+            &t.base.crypto_symbol,
+            t.base.crypto_amount,
+            None,
+            Some(t.operation_value),
+            Some(&t.seller_exchange),
+            None,
+        ),
+        Transaction::Swap(t) => (
+            "swap",
+            &t.received_crypto_symbol,
+            t.received_crypto_amount,
+            Some((&t.given_crypto_symbol, t.given_crypto_amount)),
+            None,
+            Some(&t.exchange),
+            None,
+        ),
+        Transaction::TransferToExchange(t) => (
+            "transfer_to_exchange",
+            &t.base.crypto_symbol,
+            t.base.crypto_amount,
+            None,
+            None,
+            None,
+            t.origin_wallet.as_ref().or(t.origin_exchange_name.as_ref()),
+        ),
+        Transaction::WithdrawalFromExchange(t) => (
+            "withdrawal_from_exchange",
+            &t.base.crypto_symbol,
+            t.base.crypto_amount,
+            None,
+            None,
+            Some(&t.origin_exchange),
+            None,
+        ),
+        Transaction::CryptoPaymentReceiver(t) => (
+            "crypto_payment_receiver",
+            &t.base.crypto_symbol,
+            t.base.crypto_amount,
+            None,
+            None,
+            Some(&t.receiver_exchange),
+            None,
+        ),
+        Transaction::CryptoPaymentSender(t) => (
+            "crypto_payment_sender",
+            &t.base.crypto_symbol,
+            t.base.crypto_amount,
+            None,
+            None,
+            Some(&t.sender_exchange),
+            None,
+        ),
+    };
+    vec![
+        transaction.date().to_string(),
+        transaction.record_type().0.to_string(),
+        kind.to_string(),
+        asset.clone(),
+        amount.to_string(),
+        counter.map(|(asset, _)| asset.clone()).unwrap_or_default(),
+        counter
+            .map(|(_, amount)| amount.to_string())
+            .unwrap_or_default(),
+        value.map(|value| value.to_string()).unwrap_or_default(),
+        transaction
+            .fees()
+            .map(|fees| fees.to_string())
+            .unwrap_or_default(),
+        entry.source.clone(),
+        exchange.map(|e| e.name.clone()).unwrap_or_default(),
+        exchange.map(|e| e.url.clone()).unwrap_or_default(),
+        exchange.map(|e| e.country.clone()).unwrap_or_default(),
+        origin.cloned().unwrap_or_default(),
+    ]
+}
+
+/// Writes the stored transactions as CSV, with a header row, the decimals with
+/// a `.` separator and the dates as `YYYY-MM-DD`, for spreadsheets and
+/// dataframes
+pub fn write_csv<'a>(
+    entries: impl IntoIterator<Item = &'a StoredTransaction>,
+    writer: &mut impl Write,
+) -> Result<()> {
+    writeln!(writer, "{}", CSV_HEADER.join(","))?;
+    for entry in entries {
+        let row = csv_row(entry);
+        let fields: Vec<_> = row.iter().map(|field| csv_field(field)).collect();
+        writeln!(writer, "{}", fields.join(","))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::period::BRASILIA;
+    use crate::report::resolve::Resolved;
+    use crate::report::transactions::{
+        ExchangeInfo, PurchaseTransaction, SaleTransaction, SwapTransaction, TransactionBase,
+        TransferToExchangeTransaction, WithdrawalFromExchangeTransaction,
+    };
+    use crate::source::IncomeKind;
+    use crate::source::{NormalizedDeposit, NormalizedWithdrawal};
+    use rust_decimal_macros::dec;
+
+    fn withdrawal(day: u32, asset: &str) -> Transaction {
+        Transaction::WithdrawalFromExchange(WithdrawalFromExchangeTransaction {
+            base: TransactionBase {
+                operation_date: NaiveDate::from_ymd_opt(2024, 3, day).unwrap(),
+                operation_fees: Some(dec!(1.5)),
+                crypto_symbol: asset.to_string(),
+                crypto_amount: dec!(0.5),
+            },
+            origin_exchange: ExchangeInfo {
+                name: "Kraken".to_string(),
+                url: "https://www.kraken.com".to_string(),
+                country: "US".to_string(),
+            },
+        })
+    }
+
+    #[test]
+    fn test_store() {
+        let path =
+            std::env::temp_dir().join(format!("kraken2rfb-test-{}.store.json", std::process::id()));
+        let march = Period::month(2024, 3).unwrap();
+        let swap = Transaction::Swap(SwapTransaction {
+            operation_date: NaiveDate::from_ymd_opt(2024, 3, 2).unwrap(),
+            operation_fees: None,
+            received_crypto_symbol: "BTC".to_string(),
+            received_crypto_amount: dec!(0.05),
+            given_crypto_symbol: "ETH".to_string(),
+            given_crypto_amount: dec!(1),
+            exchange: ExchangeInfo {
+                name: "Kraken".to_string(),
+                url: String::new(),
+                country: "US".to_string(),
+            },
+        });
+
+        let mut store = Store::open(path.clone()).unwrap();
+        store.replace_period("Kraken", march, &[withdrawal(1, "BTC"), swap]);
+        store.replace_period("Other", march, &[withdrawal(3, "ETH")]);
+        store.save().unwrap();
+
+        let store = Store::open(path.clone()).unwrap();
+        let count = |query: Query| store.query(&query).count();
+        assert_eq!(count(Query::default()), 3);
+        let eth = || Query {
+            asset: Some("eth".to_string()),
+            ..Query::default()
+        };
+        assert_eq!(count(eth()), 2);
+        assert_eq!(
+            count(Query {
+                record_types: Some(vec!["0510".to_string()]),
+                ..eth()
+            }),
+            1
+        );
+        assert_eq!(
+            count(Query {
+                period: Some(Period::month(2024, 4).unwrap()),
+                ..Query::default()
+            }),
+            0
+        );
+
+        let day = |day| NaiveDate::from_ymd_opt(2024, 3, day).unwrap();
+        assert_eq!(
+            store.holdings(&Query::default(), day(2)).unwrap(),
+            BTreeMap::from([
+                ("BTC".to_string(), dec!(-0.45)),
+                ("ETH".to_string(), dec!(-1))
+            ])
+        );
+        assert_eq!(
+            store.holdings(&eth(), day(31)).unwrap(),
+            BTreeMap::from([("ETH".to_string(), dec!(-1.5))])
+        );
+
+        // Reporting the period again replaces only that source's transactions
+        let mut store = store;
+        store.replace_period("Kraken", march, &[withdrawal(5, "BTC")]);
+        let all = Query::default();
+        let sources: Vec<_> = store
+            .query(&all)
+            .map(|entry| (entry.source.as_str(), entry.transaction.date().to_string()))
+            .collect();
+        assert_eq!(
+            sources,
+            [
+                ("Other", "2024-03-03".to_string()),
+                ("Kraken", "2024-03-05".to_string())
+            ]
+        );
+
+        // The income is replaced the same way
+        let income = |day| Income {
+            date: NaiveDate::from_ymd_opt(2024, 3, day).unwrap(),
+            kind: IncomeKind::Referral,
+            asset: "BTC".to_string(),
+            amount: dec!(0.0001),
+            value: dec!(30),
+        };
+        store.replace_period_income("Kraken", march, &[income(4), income(9)]);
+        store.replace_period_income("Kraken", march, &[income(7)]);
+        let received: Vec<_> = store
+            .income(Some(2024))
+            .map(|e| e.income.date.day())
+            .collect();
+        assert_eq!(received, [7]);
+        assert_eq!(store.income(Some(2023)).count(), 0);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_monthly_sales() {
+        let sale = |month, value| {
+            Transaction::Sale(SaleTransaction {
+                base: TransactionBase {
+                    operation_date: NaiveDate::from_ymd_opt(2024, month, 10).unwrap(),
+                    operation_fees: None,
+                    crypto_symbol: "BTC".to_string(),
+                    crypto_amount: dec!(0.1),
+                },
+                operation_value: value,
+                seller_exchange: ExchangeInfo {
+                    name: "Kraken".to_string(),
+                    url: String::new(),
+                    country: "US".to_string(),
+                },
+            })
+        };
+        let mut store = Store {
+            path: PathBuf::new(),
+            entries: Vec::new(),
+            income: Vec::new(),
+            opening_loss: Decimal::ZERO,
+        };
+        store.replace_period(
+            "Kraken",
+            Period::quarter(2024, 1).unwrap(),
+            &[
+                sale(1, dec!(20000)),
+                sale(3, dec!(1000)),
+                withdrawal(5, "BTC"),
+            ],
+        );
+        store.replace_period(
+            "Other",
+            Period::month(2024, 1).unwrap(),
+            &[sale(1, dec!(16000.5))],
+        );
+        assert_eq!(
+            store.monthly_sales(2024).unwrap(),
+            BTreeMap::from([(1, dec!(36000.5)), (3, dec!(1000))])
+        );
+        assert!(store.monthly_sales(2023).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_link_transfers() {
+        let path =
+            std::env::temp_dir().join(format!("kraken2rfb-test-{}.links.json", std::process::id()));
+        let march = Period::month(2024, 3).unwrap();
+        let base = |day, amount| TransactionBase {
+            operation_date: NaiveDate::from_ymd_opt(2024, 3, day).unwrap(),
+            operation_fees: None,
+            crypto_symbol: "BTC".to_string(),
+            crypto_amount: amount,
+        };
+        let exchange = |name: &str| ExchangeInfo {
+            name: name.to_string(),
+            url: String::new(),
+            country: "US".to_string(),
+        };
+        let deposit = |origin: Option<&str>| {
+            Transaction::TransferToExchange(TransferToExchangeTransaction {
+                base: base(2, dec!(1)),
+                origin_wallet: None,
+                origin_exchange_name: origin.map(String::from),
+                deposit_id: None,
+            })
+        };
+        let sale = Transaction::Sale(SaleTransaction {
+            base: base(3, dec!(1)),
+            operation_value: dec!(310000),
+            seller_exchange: exchange("Kraken"),
+        });
+
+        // Bought on Binance, moved to Kraken and sold there
+        let mut store = Store::open(path.clone()).unwrap();
+        let binance = [
+            Transaction::Purchase(PurchaseTransaction {
+                base: base(1, dec!(1)),
+                operation_value: dec!(300000),
+                buyer_exchange: exchange("Binance"),
+            }),
+            Transaction::WithdrawalFromExchange(WithdrawalFromExchangeTransaction {
+                base: base(2, dec!(1)),
+                origin_exchange: exchange("Binance"),
+            }),
+        ];
+        store.replace_period("Binance", march, &binance);
+        store.replace_period("Kraken", march, &[deposit(None), sale.clone()]);
+        let gain = |store: &Store| store.monthly_results().unwrap()[0].gains.gain;
+        let links =
+            |store: &Store| -> Vec<_> { store.entries.iter().map(|e| e.transfer).collect() };
+        store.link_transfers(&[], BRASILIA);
+        assert_eq!(gain(&store), dec!(310000));
+
+        // Matched between the exchanges of a report, late in the evening
+        let time = "2024-03-03T01:00:00Z".parse().unwrap();
+        let transfer = InternalTransfer {
+            from: "Binance".to_string(),
+            to: "Kraken".to_string(),
+            withdrawal: NormalizedWithdrawal {
+                asset: "BTC".to_string(),
+                amount: dec!(1),
+                fee: dec!(0),
+                time,
+                ids: Vec::new(),
+                destination: None,
+                network: None,
+            },
+            deposit: NormalizedDeposit {
+                asset: "BTC".to_string(),
+                amount: dec!(1),
+                fee: dec!(0),
+                time,
+                ids: Vec::new(),
+                origin_exchange: Some("Binance".to_string()),
+                origin_wallet: None,
+                network: None,
+            },
+        };
+        store.link_transfers(&[transfer], BRASILIA);
+        assert_eq!(links(&store), [None, Some(0), Some(0), None]);
+        assert_eq!(gain(&store), dec!(10000));
+        store.save().unwrap();
+        let mut store = Store::open(path.clone()).unwrap();
+        assert_eq!(gain(&store), dec!(10000));
+
+        // Replacing the deposit drops the link, and the new deposit is linked
+        // by the exchange it names
+        store.replace_period("Kraken", march, &[deposit(Some("binance")), sale]);
+        store.link_transfers(&[], BRASILIA);
+        assert_eq!(links(&store), [None, Some(1), Some(1), None]);
+        assert_eq!(gain(&store), dec!(10000));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_write_csv() {
+        let entries = [
+            StoredTransaction {
+                source: "Kraken".to_string(),
+                transaction: withdrawal(1, "BTC"),
+                transfer: None,
+            },
+            StoredTransaction {
+                source: "Exported, \"old\"".to_string(),
+                transaction: withdrawal(2, "ETH"),
+                transfer: None,
+            },
+        ];
+        let mut csv = Vec::new();
+        write_csv(&entries, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines[0], CSV_HEADER.join(","));
+        assert_eq!(
+            lines[1],
+            "2024-03-01,0510,withdrawal_from_exchange,BTC,0.5,,,,1.5,Kraken,Kraken,https://www.kraken.com,US,"
+        );
+        assert!(lines[2].contains(",\"Exported, \"\"old\"\"\",Kraken,"));
+    }
+
+    #[test]
+    fn test_import_and_holdings() {
+        let date = |month, day| NaiveDate::from_ymd_opt(2024, month, day).unwrap();
+        let base = |month, day, amount| TransactionBase {
+            operation_date: date(month, day),
+            operation_fees: None,
+            crypto_symbol: "BTC".to_string(),
+            crypto_amount: amount,
+        };
+        let exchange = ExchangeInfo {
+            name: "Binance".to_string(),
+            url: String::new(),
+            country: "US".to_string(),
+        };
+        let purchase = |month, day, amount| {
+            Transaction::Purchase(PurchaseTransaction {
+                base: base(month, day, amount),
+                operation_value: dec!(300000) * amount,
+                buyer_exchange: exchange.clone(),
+            })
+        };
+        let sale = Transaction::Sale(SaleTransaction {
+            base: base(3, 20, dec!(0.2)),
+            operation_value: dec!(62000),
+            seller_exchange: exchange.clone(),
+        });
+        let mut store = Store {
+            path: PathBuf::new(),
+            entries: Vec::new(),
+            income: Vec::new(),
+            opening_loss: Decimal::ZERO,
+        };
+        assert_eq!(store.import("Binance", &[]).unwrap(), None);
+        store.replace_period(
+            "Binance",
+            Period::month(2024, 4).unwrap(),
+            &[purchase(4, 1, dec!(0.1))],
+        );
+        let imported = [purchase(2, 10, dec!(1)), purchase(3, 5, dec!(0.5)), sale];
+        assert_eq!(
+            store.import("Binance", &imported).unwrap(),
+            Some((date(2, 10), date(3, 20)))
+        );
+        // The months after the last transaction keep what they had
+        assert_eq!(store.query(&Query::default()).count(), 4);
+        store.import("Binance", &imported[..2]).unwrap();
+        assert_eq!(store.query(&Query::default()).count(), 3);
+        store.import("Binance", &imported).unwrap();
+
+        assert_eq!(store.month_sales(2024, 3).unwrap(), dec!(62000));
+        assert_eq!(store.month_sales(2024, 2).unwrap(), Decimal::ZERO);
+        assert_eq!(
+            store
+                .taxable_months(Period::quarter(2024, 1).unwrap())
+                .unwrap(),
+            [(date(3, 1), dec!(62000))]
+        );
+
+        let mut resolver = Resolved::default();
+        resolver
+            .rates
+            .insert(("BTC".to_string(), date(3, 31)), dec!(350000.004));
+        let (holdings, total) = store
+            .valued_holdings(&Query::default(), date(3, 31), &mut resolver)
+            .unwrap();
+        assert_eq!(
+            holdings,
+            [Holding {
+                asset: "BTC".to_string(),
+                amount: dec!(1.3),
+                value: dec!(455000.01),
+            }]
+        );
+        assert_eq!(total, dec!(455000.01));
+        // No rate for the day
+        assert!(
+            store
+                .valued_holdings(&Query::default(), date(3, 30), &mut resolver)
+                .is_err()
+        );
+
+        // The fiat and, if one is given, the other assets are left out
+        let balances = BTreeMap::from([
+            ("BTC".to_string(), dec!(1.25)),
+            ("ETH".to_string(), dec!(2)),
+            ("USD".to_string(), dec!(100)),
+        ]);
+        let discrepancies = |asset| {
+            store
+                .reconcile_holdings("Binance", asset, date(3, 31), balances.clone())
+                .unwrap()
+                .into_iter()
+                .map(|d| (d.asset, d.implied, d.actual))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            discrepancies(None),
+            [
+                ("BTC".to_string(), dec!(1.3), dec!(1.25)),
+                ("ETH".to_string(), Decimal::ZERO, dec!(2)),
+            ]
+        );
+        assert_eq!(
+            discrepancies(Some("BTC")),
+            [("BTC".to_string(), dec!(1.3), dec!(1.25))]
+        );
+    }
+
+    #[test]
+    fn test_match_deposits() {
+        let deposit = |amount, time: &str| NormalizedDeposit {
+            asset: "BTC".to_string(),
+            amount,
+            fee: Decimal::ZERO,
+            time: time.parse().unwrap(),
+            ids: Vec::new(),
+            origin_exchange: None,
+            origin_wallet: None,
+            network: None,
+        };
+        let mut store = Store {
+            path: PathBuf::new(),
+            entries: Vec::new(),
+            income: Vec::new(),
+            opening_loss: Decimal::ZERO,
+        };
+        let march = Period::month(2024, 3).unwrap();
+        // 0.5 BTC withdrawn on March 3 and 5, and ETH on March 1
+        store.replace_period(
+            "Binance",
+            march,
+            &[withdrawal(3, "BTC"), withdrawal(1, "ETH")],
+        );
+        store.replace_period("Kraken", march, &[withdrawal(5, "BTC")]);
+
+        let mut activity = Activity {
+            deposits: vec![
+                // On March 3 in Brasília, the withdrawal less its fee
+                deposit(dec!(0.4999), "2024-03-04T02:00:00Z"),
+                // The withdrawal is already taken
+                deposit(dec!(0.4999), "2024-03-04T01:00:00Z"),
+                // Only the withdrawal from the exchange itself is close enough
+                deposit(dec!(0.5), "2024-03-05T12:00:00Z"),
+                // More than withdrawn
+                deposit(dec!(0.6), "2024-03-02T12:00:00Z"),
+            ],
+            ..Activity::default()
+        };
+        assert_eq!(store.match_deposits("Kraken", &mut activity, BRASILIA), [0]);
+        let origins: Vec<_> = activity
+            .deposits
+            .iter()
+            .map(|d| d.origin_exchange.as_deref())
+            .collect();
+        assert_eq!(origins, [Some("Binance"), None, None, None]);
+
+        // Nor is the withdrawal of a deposit whose origin is known
+        activity.deposits[0].origin_exchange = None;
+        activity.deposits[0].origin_wallet = Some("bc1q".to_string());
+        assert_eq!(store.match_deposits("Kraken", &mut activity, BRASILIA), [1]);
+    }
+}
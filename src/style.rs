@@ -0,0 +1,47 @@
+//! Terminal colors. Colors are only used when the output goes to a terminal,
+//! and can be turned off with `--no-color` or the `NO_COLOR` environment
+//! variable (see <https://no-color.org>).
+
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static COLOR: AtomicBool = AtomicBool::new(false);
+
+/// Enables colors, unless disabled by `--no-color` or `NO_COLOR`
+pub fn init(no_color: bool) {
+    let disabled = no_color || std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    COLOR.store(!disabled, Ordering::Relaxed);
+}
+
+fn paint(text: &str, code: &str, is_terminal: bool) -> String {
+    if COLOR.load(Ordering::Relaxed) && is_terminal {
+        format!("\x1b[{}m{}\x1b[0m", code, text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Styles an error message printed to stderr, in red
+pub fn error(text: &str) -> String {
+    paint(text, "1;31", io::stderr().is_terminal())
+}
+
+/// Styles a warning printed to stderr, in yellow
+pub fn warning(text: &str) -> String {
+    paint(text, "33", io::stderr().is_terminal())
+}
+
+/// Styles a failure printed to stdout, in red
+pub fn failure(text: &str) -> String {
+    paint(text, "31", io::stdout().is_terminal())
+}
+
+/// Styles a success printed to stdout, in green
+pub fn success(text: &str) -> String {
+    paint(text, "32", io::stdout().is_terminal())
+}
+
+/// Highlights important values printed to stdout, like totals, in bold
+pub fn highlight(text: &str) -> String {
+    paint(text, "1", io::stdout().is_terminal())
+}
@@ -0,0 +1,169 @@
+//! Capital gains tax on crypto sales (Lei 8.981/1995 art. 21, with the rates
+//! of Lei 13.259/2016), used to estimate the DARF of a sale.
+
+use chrono::{Datelike, Months, NaiveDate, Weekday};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+use crate::checked;
+use crate::error::Result;
+
+/// Sales of up to this much BRL in a month are exempt from capital gains tax
+pub const MONTHLY_EXEMPTION: Decimal = dec!(35000);
+
+/// Revenue code of the DARF for capital gains
+pub const DARF_CODE: &str = "4600";
+
+/// Tax rate on each portion of the gain: the rate applies to the part of the
+/// gain up to the limit, above the previous limit
+const BRACKETS: [(Option<Decimal>, Decimal); 4] = [
+    (Some(dec!(5000000)), dec!(0.15)),
+    (Some(dec!(10000000)), dec!(0.175)),
+    (Some(dec!(30000000)), dec!(0.20)),
+    (None, dec!(0.225)),
+];
+
+/// Rounds a BRL value to cents, always showing two decimal places
+fn brl(value: Decimal) -> Decimal {
+    let mut value = value.round_dp(2);
+    value.rescale(2);
+    value
+}
+
+/// Tax due on a capital gain, in BRL; `what` names the sales it is of, for
+/// the errors
+pub fn capital_gains_tax(gain: Decimal, what: &str) -> Result<Decimal> {
+    let mut tax = Decimal::ZERO;
+    let mut lower = Decimal::ZERO;
+    for (upper, rate) in BRACKETS {
+        if gain <= lower {
+            break;
+        }
+        let portion = checked::sub(upper.map_or(gain, |upper| gain.min(upper)), lower, what)?;
+        tax = checked::add(tax, checked::mul(portion, rate, what)?, what)?;
+        lower = upper.unwrap_or(gain);
+    }
+    Ok(brl(tax))
+}
+
+/// Highest rate applied to a gain, i.e. its tax bracket
+pub fn marginal_rate(gain: Decimal) -> Decimal {
+    BRACKETS
+        .iter()
+        .find(|(upper, _)| upper.is_none_or(|upper| gain <= upper))
+        .map(|(_, rate)| *rate)
+        .expect("the last bracket has no upper limit")
+}
+
+/// Outcome of selling some amount of an asset
+#[derive(Debug, PartialEq)]
+pub struct SaleEstimate {
+    pub sale_value: Decimal,
+    pub cost: Decimal,
+    /// Negative for a loss
+    pub gain: Decimal,
+    /// Sales in the month, including this one
+    pub month_sales: Decimal,
+    pub exempt: bool,
+    /// Tax due on the DARF, zero if exempt or not profitable
+    pub tax: Decimal,
+}
+
+/// Estimates the tax of selling `amount` units at `price` BRL each, given the
+/// average cost of each unit and the BRL value of the other sales in the
+/// month. `what` names the sale, for the errors.
+pub fn estimate_sale(
+    amount: Decimal,
+    price: Decimal,
+    average_cost: Decimal,
+    other_month_sales: Decimal,
+    what: &str,
+) -> Result<SaleEstimate> {
+    let sale_value = brl(checked::mul(amount, price, what)?);
+    let cost = brl(checked::mul(amount, average_cost, what)?);
+    let gain = checked::sub(sale_value, cost, what)?;
+    let month_sales = brl(checked::add(other_month_sales, sale_value, what)?);
+    let exempt = month_sales <= MONTHLY_EXEMPTION;
+    let tax = if exempt || gain <= Decimal::ZERO {
+        brl(Decimal::ZERO)
+    } else {
+        capital_gains_tax(gain, what)?
+    };
+    Ok(SaleEstimate {
+        sale_value,
+        cost,
+        gain,
+        month_sales,
+        exempt,
+        tax,
+    })
+}
+
+/// Due date of the DARF of a sale: the last business day of the following
+/// month. Holidays are not taken into account.
+pub fn darf_due_date(sale_date: NaiveDate) -> NaiveDate {
+    let first_of_month = sale_date.with_day(1).unwrap();
+    let mut date = (first_of_month + Months::new(2)).pred_opt().unwrap();
+    while matches!(date.weekday(), Weekday::Sat | Weekday::Sun) {
+        date = date.pred_opt().unwrap();
+    }
+    date
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capital_gains_tax() {
+        assert_eq!(capital_gains_tax(dec!(0), "").unwrap(), dec!(0));
+        assert_eq!(capital_gains_tax(dec!(1000), "").unwrap(), dec!(150));
+        assert_eq!(capital_gains_tax(dec!(5000000), "").unwrap(), dec!(750000));
+        // 750k on the first 5M, plus 17.5% of the next 1M
+        assert_eq!(capital_gains_tax(dec!(6000000), "").unwrap(), dec!(925000));
+        // 750k + 875k + 4M + 22.5% of the 10M above 30M
+        assert_eq!(
+            capital_gains_tax(dec!(40000000), "").unwrap(),
+            dec!(7875000)
+        );
+
+        assert_eq!(marginal_rate(dec!(1000)), dec!(0.15));
+        assert_eq!(marginal_rate(dec!(6000000)), dec!(0.175));
+        assert_eq!(marginal_rate(dec!(40000000)), dec!(0.225));
+    }
+
+    #[test]
+    fn test_estimate_sale() {
+        // Exempt: the month's sales stay within R$ 35.000
+        let estimate = estimate_sale(dec!(0.1), dec!(300000), dec!(200000), dec!(0), "").unwrap();
+        assert_eq!(estimate.gain, dec!(10000));
+        assert!(estimate.exempt);
+        assert_eq!(estimate.tax, dec!(0));
+
+        // The same sale after others in the month is taxed
+        let estimate =
+            estimate_sale(dec!(0.1), dec!(300000), dec!(200000), dec!(10000), "").unwrap();
+        assert_eq!(estimate.month_sales, dec!(40000));
+        assert!(!estimate.exempt);
+        assert_eq!(estimate.tax, dec!(1500));
+
+        // No tax on losses
+        let estimate = estimate_sale(dec!(1), dec!(100000), dec!(200000), dec!(0), "").unwrap();
+        assert_eq!(estimate.gain, dec!(-100000));
+        assert_eq!(estimate.tax, dec!(0));
+
+        // A sale whose value doesn't fit fails instead of overflowing
+        let huge = dec!(100000000000000000000);
+        let error = estimate_sale(huge, huge, dec!(1), dec!(0), "the sale").unwrap_err();
+        assert!(error.to_string().contains("in the sale"), "{error}");
+    }
+
+    #[test]
+    fn test_darf_due_date() {
+        let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        assert_eq!(darf_due_date(date(2024, 3, 15)), date(2024, 4, 30));
+        // 2024-08-31 is a Saturday
+        assert_eq!(darf_due_date(date(2024, 7, 1)), date(2024, 8, 30));
+        assert_eq!(darf_due_date(date(2024, 12, 31)), date(2025, 1, 31));
+    }
+}
@@ -0,0 +1,436 @@
+//! Matching of the transfers between the exchanges of a report: a withdrawal
+//! from one exchange and the deposit of the same asset it became on another.
+//! The deposit is then known to come from that exchange, so its record names
+//! it without asking the user, and the pair is a movement of the user's own
+//! assets rather than a disposal.
+//!
+//! The transfers between an exchange and a self-custody wallet of the user,
+//! seen on the blockchain, are matched the same way by [`match_wallet`].
+
+use chrono::{DateTime, TimeDelta, Utc};
+use rust_decimal::Decimal;
+
+use crate::report::transactions::ExchangeInfo;
+use crate::source::{Activity, NormalizedDeposit, NormalizedWithdrawal};
+
+/// How long after the withdrawal the deposit may arrive, for the network to
+/// confirm the transfer
+const MAX_DELAY: TimeDelta = TimeDelta::hours(24);
+
+/// How long before the withdrawal the deposit may be recorded, since each
+/// exchange takes the time of a different step of the transfer
+const MAX_ADVANCE: TimeDelta = TimeDelta::hours(1);
+
+/// Chain of each word that may name it in a network or method, in the order
+/// they are looked for: a layer 2 or token standard is named along with the
+/// chain it runs next to, e.g. "Bitcoin Lightning" or "Tether USD (TRC20)"
+const CHAIN_WORDS: [(&str, &str); 16] = [
+    ("lightning", "lightning"),
+    ("arbitrum", "arbitrum"),
+    ("optimism", "optimism"),
+    ("polygon", "polygon"),
+    ("base", "base"),
+    ("bep20", "bsc"),
+    ("bsc", "bsc"),
+    ("trc20", "tron"),
+    ("tron", "tron"),
+    ("solana", "solana"),
+    ("erc20", "ethereum"),
+    ("classic", "ethereum classic"),
+    ("ether", "ethereum"),
+    ("ethereum", "ethereum"),
+    ("cash", "bitcoin cash"),
+    ("bitcoin", "bitcoin"),
+];
+
+/// The chain a network or method name of an exchange or wallet refers to, if
+/// it is a known one, e.g. "lightning" for "Bitcoin Lightning"
+fn chain(network: &str) -> Option<&'static str> {
+    let network = network.to_lowercase();
+    let words: Vec<&str> = network
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .collect();
+    CHAIN_WORDS
+        .iter()
+        .find(|(word, _)| words.contains(word))
+        .map(|&(_, chain)| chain)
+}
+
+/// Whether two transfers may have been made on the same network: unless both
+/// name a known chain, and they differ, e.g. a Lightning payment and an
+/// on-chain transaction
+fn same_network(a: Option<&str>, b: Option<&str>) -> bool {
+    match (a.and_then(chain), b.and_then(chain)) {
+        (Some(a), Some(b)) => a == b,
+        _ => true,
+    }
+}
+
+/// A withdrawal from one exchange matched to a deposit on another
+#[derive(Debug, Clone, PartialEq)]
+pub struct InternalTransfer {
+    /// Name of the exchange the asset was withdrawn from
+    pub from: String,
+    /// Name of the exchange the asset was deposited to
+    pub to: String,
+    pub withdrawal: NormalizedWithdrawal,
+    pub deposit: NormalizedDeposit,
+}
+
+/// Whether the deposit may be the withdrawal arriving on another exchange:
+/// the same asset, the amount withdrawn with or without the withdrawal fee
+/// (exchanges differ on whether it is included), close in time, and not on
+/// another network
+fn may_match(withdrawal: &NormalizedWithdrawal, deposit: &NormalizedDeposit) -> bool {
+    let delay = deposit.time - withdrawal.time;
+    withdrawal.asset == deposit.asset
+        && same_network(withdrawal.network.as_deref(), deposit.network.as_deref())
+        && (deposit.amount == withdrawal.amount - withdrawal.fee
+            || deposit.amount == withdrawal.amount)
+        && -MAX_ADVANCE <= delay
+        && delay <= MAX_DELAY
+}
+
+fn distance(a: DateTime<Utc>, b: DateTime<Utc>) -> TimeDelta {
+    (a - b).abs()
+}
+
+/// Matches the withdrawals of each exchange to the deposits of the others,
+/// each entry at most once and the closest deposit in time first. The
+/// matched deposits get the name of the exchange they came from in
+/// `origin_exchange`; those that already have one are left alone.
+pub fn match_transfers(activities: &mut [(ExchangeInfo, Activity)]) -> Vec<InternalTransfer> {
+    let mut withdrawals: Vec<(usize, &NormalizedWithdrawal)> = activities
+        .iter()
+        .enumerate()
+        .flat_map(|(i, (_, activity))| activity.withdrawals.iter().map(move |w| (i, w)))
+        .collect();
+    withdrawals.sort_by_key(|(_, w)| w.time);
+
+    // (withdrawal exchange, withdrawal, deposit exchange, deposit index)
+    let mut matches: Vec<(usize, NormalizedWithdrawal, usize, usize)> = Vec::new();
+    for (from, withdrawal) in withdrawals {
+        let deposit = activities
+            .iter()
+            .enumerate()
+            .filter(|&(to, _)| to != from)
+            .flat_map(|(to, (_, activity))| {
+                activity
+                    .deposits
+                    .iter()
+                    .enumerate()
+                    .map(move |(j, d)| (to, j, d))
+            })
+            .filter(|&(to, j, d)| {
+                d.origin_exchange.is_none()
+                    && may_match(withdrawal, d)
+                    && !matches.iter().any(|&(_, _, t, k)| (t, k) == (to, j))
+            })
+            .min_by_key(|(_, _, d)| distance(d.time, withdrawal.time));
+        if let Some((to, j, _)) = deposit {
+            matches.push((from, withdrawal.clone(), to, j));
+        }
+    }
+
+    matches
+        .into_iter()
+        .map(|(from, withdrawal, to, j)| {
+            let from = activities[from].0.name.clone();
+            let (exchange, activity) = &mut activities[to];
+            let deposit = &mut activity.deposits[j];
+            deposit.origin_exchange = Some(from.clone());
+            InternalTransfer {
+                from,
+                to: exchange.name.clone(),
+                withdrawal,
+                deposit: deposit.clone(),
+            }
+        })
+        .collect()
+}
+
+/// A transfer on a blockchain between a wallet of the user and an address
+/// outside it, as a block explorer or the wallet's own export sees it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainTransfer {
+    /// Common symbol of the asset, e.g. "BTC"
+    pub asset: String,
+    /// Amount that left or arrived, without the network fee
+    pub amount: Decimal,
+    /// Network fee the wallet paid for the transaction, in the asset; zero for
+    /// incoming transfers
+    pub fee: Decimal,
+    /// Time of the block of the transaction
+    pub time: DateTime<Utc>,
+    /// Hash of the transaction
+    pub txid: String,
+    /// Address of the wallet the asset left from or arrived at
+    pub address: String,
+    /// Whether the asset left the wallet, or else arrived at it
+    pub outgoing: bool,
+    /// Network of the transaction, e.g. "Bitcoin", when the source tells it
+    pub network: Option<String>,
+}
+
+/// A deposit or withdrawal of an exchange matched to a transfer of a wallet
+/// of the user: a deposit for an outgoing transfer, else a withdrawal
+#[derive(Debug, Clone, PartialEq)]
+pub struct WalletMatch {
+    /// IDs of the exchange entry
+    pub ids: Vec<String>,
+    pub transfer: ChainTransfer,
+}
+
+/// Whether the exchange entry may be the transfer: the same transaction when
+/// the exchange gives its hash, or else the same asset and network, one of
+/// the amounts, and close in time
+fn may_be_transfer(
+    transfer: &ChainTransfer,
+    asset: &str,
+    network: Option<&str>,
+    amounts: [Decimal; 2],
+    ids: &[String],
+    delay: TimeDelta,
+) -> bool {
+    if ids.iter().any(|id| id.eq_ignore_ascii_case(&transfer.txid)) {
+        return true;
+    }
+    transfer.asset == asset
+        && same_network(transfer.network.as_deref(), network)
+        && amounts.contains(&transfer.amount)
+        && -MAX_ADVANCE <= delay
+        && delay <= MAX_DELAY
+}
+
+/// Matches the deposits of an activity to the transfers out of the wallets
+/// of the user, and the withdrawals to the transfers into them, each transfer
+/// at most once and the closest in time first. The matched deposits get the
+/// wallet address they came from in `origin_wallet`; those that already have
+/// an origin are left alone. The withdrawals are only corroborated, as their
+/// record has no destination.
+pub fn match_wallet(activity: &mut Activity, transfers: &[ChainTransfer]) -> Vec<WalletMatch> {
+    let mut used = vec![false; transfers.len()];
+    let mut matches = Vec::new();
+    let mut closest =
+        |ids: &[String], time: DateTime<Utc>, fits: &dyn Fn(&ChainTransfer) -> bool| {
+            let (index, transfer) = transfers
+                .iter()
+                .enumerate()
+                .filter(|&(i, t)| !used[i] && fits(t))
+                .min_by_key(|(_, t)| distance(t.time, time))?;
+            used[index] = true;
+            matches.push(WalletMatch {
+                ids: ids.to_vec(),
+                transfer: transfer.clone(),
+            });
+            Some(transfer)
+        };
+
+    for deposit in &mut activity.deposits {
+        if deposit.origin_exchange.is_some() || deposit.origin_wallet.is_some() {
+            continue;
+        }
+        let amounts = [deposit.amount, deposit.amount + deposit.fee];
+        let fits = |t: &ChainTransfer| {
+            t.outgoing
+                && may_be_transfer(
+                    t,
+                    &deposit.asset,
+                    deposit.network.as_deref(),
+                    amounts,
+                    &deposit.ids,
+                    deposit.time - t.time,
+                )
+        };
+        if let Some(transfer) = closest(&deposit.ids, deposit.time, &fits) {
+            deposit.origin_wallet = Some(transfer.address.clone());
+        }
+    }
+    for withdrawal in &activity.withdrawals {
+        let amounts = [withdrawal.amount - withdrawal.fee, withdrawal.amount];
+        let fits = |t: &ChainTransfer| {
+            !t.outgoing
+                && may_be_transfer(
+                    t,
+                    &withdrawal.asset,
+                    withdrawal.network.as_deref(),
+                    amounts,
+                    &withdrawal.ids,
+                    t.time - withdrawal.time,
+                )
+        };
+        closest(&withdrawal.ids, withdrawal.time, &fits);
+    }
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    fn exchange(name: &str) -> ExchangeInfo {
+        ExchangeInfo {
+            name: name.to_string(),
+            url: String::new(),
+            country: "US".to_string(),
+        }
+    }
+
+    fn withdrawal(amount: Decimal, fee: Decimal, time: &str) -> NormalizedWithdrawal {
+        NormalizedWithdrawal {
+            asset: "BTC".to_string(),
+            amount,
+            fee,
+            time: time.parse().unwrap(),
+            ids: Vec::new(),
+            destination: None,
+            network: None,
+        }
+    }
+
+    fn deposit(amount: Decimal, time: &str) -> NormalizedDeposit {
+        NormalizedDeposit {
+            asset: "BTC".to_string(),
+            amount,
+            fee: Decimal::ZERO,
+            time: time.parse().unwrap(),
+            ids: Vec::new(),
+            origin_exchange: None,
+            origin_wallet: None,
+            network: None,
+        }
+    }
+
+    #[test]
+    fn test_match_transfers() {
+        let mut activities = [
+            (
+                exchange("Binance"),
+                Activity {
+                    withdrawals: vec![
+                        withdrawal(dec!(0.5), dec!(0.0005), "2024-03-01T12:00:00Z"),
+                        // Arrives too late to be the deposit of 0.8
+                        withdrawal(dec!(0.8), dec!(0), "2024-03-02T12:00:00Z"),
+                    ],
+                    ..Activity::default()
+                },
+            ),
+            (
+                exchange("Kraken"),
+                Activity {
+                    deposits: vec![
+                        deposit(dec!(0.4995), "2024-03-01T12:40:00Z"),
+                        // Same amount, but farther from the withdrawal
+                        deposit(dec!(0.4995), "2024-03-01T20:00:00Z"),
+                        deposit(dec!(0.8), "2024-03-04T12:00:00Z"),
+                    ],
+                    // Not matched to the deposits of its own exchange
+                    withdrawals: vec![withdrawal(dec!(0.8), dec!(0), "2024-03-04T11:00:00Z")],
+                    ..Activity::default()
+                },
+            ),
+        ];
+        let transfers = match_transfers(&mut activities);
+        assert_eq!(transfers.len(), 1);
+        assert_eq!(
+            (transfers[0].from.as_str(), transfers[0].to.as_str()),
+            ("Binance", "Kraken")
+        );
+        assert_eq!(transfers[0].withdrawal.amount, dec!(0.5));
+
+        let origins: Vec<_> = activities[1]
+            .1
+            .deposits
+            .iter()
+            .map(|d| d.origin_exchange.as_deref())
+            .collect();
+        assert_eq!(origins, [Some("Binance"), None, None]);
+        assert_eq!(transfers[0].deposit, activities[1].1.deposits[0]);
+    }
+
+    fn transfer(amount: Decimal, time: &str, outgoing: bool) -> ChainTransfer {
+        ChainTransfer {
+            asset: "BTC".to_string(),
+            amount,
+            fee: Decimal::ZERO,
+            time: time.parse().unwrap(),
+            txid: format!("tx{}", amount),
+            address: format!("bc1q{}", amount),
+            outgoing,
+            network: None,
+        }
+    }
+
+    #[test]
+    fn test_match_wallet() {
+        let mut known = deposit(dec!(0.3), "2024-03-05T12:00:00Z");
+        known.origin_exchange = Some("Binance".to_string());
+        let mut by_hash = deposit(dec!(0.2), "2024-03-09T12:00:00Z");
+        by_hash.ids = vec!["D1".to_string(), "TX0.7".to_string()];
+        let mut activity = Activity {
+            deposits: vec![
+                deposit(dec!(0.1), "2024-03-01T12:30:00Z"),
+                known,
+                by_hash,
+                // Arrived long after any transfer of the wallet
+                deposit(dec!(0.4), "2024-03-20T12:00:00Z"),
+            ],
+            withdrawals: vec![withdrawal(dec!(0.5), dec!(0.0005), "2024-03-02T12:00:00Z")],
+            ..Activity::default()
+        };
+        let transfers = [
+            transfer(dec!(0.1), "2024-03-01T12:00:00Z", true),
+            transfer(dec!(0.3), "2024-03-05T11:00:00Z", true),
+            transfer(dec!(0.7), "2024-03-01T00:00:00Z", true),
+            transfer(dec!(0.4), "2024-03-10T12:00:00Z", true),
+            // Into the wallet, so not a deposit
+            transfer(dec!(0.4), "2024-03-20T11:00:00Z", false),
+            transfer(dec!(0.4995), "2024-03-02T13:00:00Z", false),
+        ];
+        let matches = match_wallet(&mut activity, &transfers);
+
+        let origins: Vec<_> = activity
+            .deposits
+            .iter()
+            .map(|d| d.origin_wallet.as_deref())
+            .collect();
+        assert_eq!(origins, [Some("bc1q0.1"), None, Some("bc1q0.7"), None]);
+        let txids: Vec<_> = matches.iter().map(|m| m.transfer.txid.as_str()).collect();
+        assert_eq!(txids, ["tx0.1", "tx0.7", "tx0.4995"]);
+        assert!(!matches[2].transfer.outgoing);
+    }
+
+    #[test]
+    fn test_networks() {
+        assert_eq!(chain("Bitcoin Lightning"), Some("lightning"));
+        assert_eq!(chain("Ethereum (ERC20)"), Some("ethereum"));
+        assert_eq!(chain("Tether USD (TRC20)"), Some("tron"));
+        assert_eq!(chain("Ether"), Some("ethereum"));
+        assert_eq!(chain("Bitcoin Cash"), Some("bitcoin cash"));
+        assert_eq!(chain("Tether USD"), None);
+        assert!(same_network(Some("Bitcoin"), Some("BTC")));
+        assert!(same_network(None, Some("Bitcoin Lightning")));
+        assert!(!same_network(Some("Bitcoin"), Some("Bitcoin Lightning")));
+
+        // A Lightning payment is no on-chain transfer of the same amount
+        let mut lightning = deposit(dec!(0.1), "2024-03-01T12:30:00Z");
+        lightning.network = Some("Bitcoin Lightning".to_string());
+        let mut on_chain = transfer(dec!(0.1), "2024-03-01T12:00:00Z", true);
+        on_chain.network = Some("Bitcoin".to_string());
+        let mut activity = Activity {
+            deposits: vec![lightning.clone()],
+            ..Activity::default()
+        };
+        assert!(match_wallet(&mut activity, &[on_chain.clone()]).is_empty());
+        activity.deposits[0].network = Some("Bitcoin".to_string());
+        assert_eq!(match_wallet(&mut activity, &[on_chain]).len(), 1);
+
+        let mut withdrawal = withdrawal(dec!(0.1), dec!(0), "2024-03-01T12:00:00Z");
+        withdrawal.network = Some("Bitcoin".to_string());
+        assert!(!may_match(&withdrawal, &lightning));
+        withdrawal.network = Some("Lightning".to_string());
+        assert!(may_match(&withdrawal, &lightning));
+    }
+}
@@ -0,0 +1,115 @@
+use std::fs;
+use std::path::Path;
+
+use itertools::Itertools;
+use serde_json::Value;
+
+use kraken2rfb::error::{Error, Result};
+use kraken2rfb::i18n::t;
+use kraken2rfb::kraken::kraken_public_request;
+
+/// Kraken public endpoints whose responses are bundled, and the file each is saved to
+const DATA_FILES: [(&str, &str); 2] = [
+    ("Assets", "assets.json"),
+    ("AssetPairs", "kraken_pairs.json"),
+];
+
+/// Keys added, removed and changed between two "result" objects
+#[derive(Debug, Default, PartialEq)]
+struct Changes {
+    added: Vec<String>,
+    removed: Vec<String>,
+    changed: Vec<String>,
+}
+
+fn diff(old: &Value, new: &Value) -> Changes {
+    let empty = serde_json::Map::new();
+    let old = old["result"].as_object().unwrap_or(&empty);
+    let new = new["result"].as_object().unwrap_or(&empty);
+
+    let mut changes = Changes::default();
+    for (key, value) in new {
+        match old.get(key) {
+            None => changes.added.push(key.clone()),
+            Some(old_value) if old_value != value => changes.changed.push(key.clone()),
+            Some(_) => {}
+        }
+    }
+    changes.removed = old
+        .keys()
+        .filter(|key| !new.contains_key(*key))
+        .cloned()
+        .collect();
+    changes
+}
+
+/// Downloads fresh asset and pair metadata from Kraken into `dir`, replacing
+/// the bundled snapshots, and prints what changed. The new data is compiled in
+/// on the next build.
+pub fn update_data(dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    for (endpoint, file_name) in DATA_FILES {
+        let path = dir.join(file_name);
+        println!("{}", t!("Downloading {} into {}", endpoint, path.display()));
+        let new = kraken_public_request(endpoint)?;
+
+        let old = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or(Value::Null),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Value::Null,
+            Err(e) => return Err(e.into()),
+        };
+        let changes = diff(&old, &new);
+
+        let content = serde_json::to_string_pretty(&new)
+            .map_err(|e| Error::Validation(t!("failed to serialize {}: {}", endpoint, e)))?;
+        // Write to a temporary file first, so a failure never leaves a truncated file
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, content + "\n")?;
+        fs::rename(&tmp, &path)?;
+
+        if changes == Changes::default() {
+            println!("  {}", t!("no changes"));
+        }
+        for (what, keys) in [
+            (t!("added"), &changes.added),
+            (t!("removed"), &changes.removed),
+            (t!("changed"), &changes.changed),
+        ] {
+            if !keys.is_empty() {
+                println!("  {} {}: {}", keys.len(), what, keys.iter().join(", "));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_diff() {
+        let old = json!({"error": [], "result": {
+            "XXBT": {"altname": "XBT", "status": "enabled"},
+            "XETH": {"altname": "ETH", "status": "enabled"},
+            "LUNA": {"altname": "LUNA", "status": "enabled"},
+        }});
+        let new = json!({"error": [], "result": {
+            "XXBT": {"altname": "XBT", "status": "enabled"},
+            "XETH": {"altname": "ETH", "status": "deposit_only"},
+            "SOL": {"altname": "SOL", "status": "enabled"},
+        }});
+        assert_eq!(
+            diff(&old, &new),
+            Changes {
+                added: vec!["SOL".to_string()],
+                removed: vec!["LUNA".to_string()],
+                changed: vec!["XETH".to_string()],
+            }
+        );
+
+        // Everything is new when there was no previous file
+        assert_eq!(diff(&Value::Null, &new).added.len(), 3);
+    }
+}
@@ -0,0 +1,191 @@
+//! Verification of the crypto withdrawals of an exchange on public block
+//! explorers: the transaction whose hash the exchange gives must pay the
+//! amount withdrawn, with or without the withdrawal fee, to the destination
+//! address, if the exchange gives it too. A withdrawal that doesn't is
+//! flagged before it ends up in the report.
+//!
+//! The lookups on the explorers are done by the [`crate::bitcoin`] and
+//! [`crate::ethereum`] modules; this one only tells the transaction of a
+//! withdrawal and checks what it paid.
+
+use rust_decimal::Decimal;
+
+use crate::checked;
+use crate::i18n::t;
+use crate::source::NormalizedWithdrawal;
+
+/// What a transaction paid to an address, as a block explorer sees it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainPayment {
+    pub address: String,
+    pub amount: Decimal,
+}
+
+/// A blockchain whose transactions can be looked up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chain {
+    Bitcoin,
+    Ethereum,
+}
+
+/// What the explorer showed of a withdrawal
+#[derive(Debug, Clone, PartialEq)]
+pub enum Finding {
+    /// The transaction paid the withdrawal
+    Confirmed,
+    /// The explorer doesn't know the transaction
+    NotFound,
+    /// The transaction paid something else, as the message says
+    Mismatch(String),
+}
+
+/// The chain and hash of the transaction of a withdrawal, if it has one that
+/// can be looked up: a hash among its IDs, of the form of the chain of its
+/// asset. Only bitcoin and ether are, the tokens aren't.
+pub fn withdrawal_transaction(withdrawal: &NormalizedWithdrawal) -> Option<(Chain, String)> {
+    let is_hash = |hex: &str| hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit());
+    let chain = match withdrawal.asset.as_str() {
+        "BTC" => Chain::Bitcoin,
+        "ETH" => Chain::Ethereum,
+        _ => return None,
+    };
+    withdrawal.ids.iter().find_map(|id| {
+        let id = id.trim().to_lowercase();
+        let hex = match chain {
+            Chain::Bitcoin => id.as_str(),
+            Chain::Ethereum => id.strip_prefix("0x")?,
+        };
+        is_hash(hex).then_some((chain, id.clone()))
+    })
+}
+
+/// Checks the payments of the transaction of a withdrawal, `None` if the
+/// explorer doesn't know it. With no destination, any payment of the amount
+/// will do.
+pub fn check(withdrawal: &NormalizedWithdrawal, payments: Option<&[ChainPayment]>) -> Finding {
+    let Some(payments) = payments else {
+        return Finding::NotFound;
+    };
+    let amounts = [withdrawal.amount - withdrawal.fee, withdrawal.amount];
+    let Some(destination) = &withdrawal.destination else {
+        return if payments.iter().any(|p| amounts.contains(&p.amount)) {
+            Finding::Confirmed
+        } else {
+            Finding::Mismatch(t!(
+                "no output of the transaction pays {} {}",
+                amounts[0],
+                withdrawal.asset
+            ))
+        };
+    };
+    let paid: Vec<Decimal> = payments
+        .iter()
+        .filter(|p| p.address.eq_ignore_ascii_case(destination))
+        .map(|p| p.amount)
+        .collect();
+    if paid.is_empty() {
+        return Finding::Mismatch(t!("the transaction pays nothing to {}", destination));
+    }
+    let total = match checked::sum(
+        paid,
+        &t!("the outputs of the transaction to {}", destination),
+    ) {
+        Ok(total) => total,
+        Err(error) => return Finding::Mismatch(error.to_string()),
+    };
+    if amounts.contains(&total) {
+        Finding::Confirmed
+    } else {
+        Finding::Mismatch(t!(
+            "the transaction pays {} {} to {}, not {}",
+            total,
+            withdrawal.asset,
+            destination,
+            amounts[0]
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    const TXID: &str = "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+
+    fn withdrawal(asset: &str, destination: Option<&str>) -> NormalizedWithdrawal {
+        NormalizedWithdrawal {
+            asset: asset.to_string(),
+            amount: dec!(0.5),
+            fee: dec!(0.0005),
+            time: "2024-03-01T12:00:00Z".parse().unwrap(),
+            ids: vec![
+                "FTQcuak-V6Za8qrWnhzTx67yYHz8Tg".to_string(),
+                TXID.to_uppercase(),
+            ],
+            destination: destination.map(String::from),
+            network: None,
+        }
+    }
+
+    fn payment(address: &str, amount: Decimal) -> ChainPayment {
+        ChainPayment {
+            address: address.to_string(),
+            amount,
+        }
+    }
+
+    #[test]
+    fn test_withdrawal_transaction() {
+        assert_eq!(
+            withdrawal_transaction(&withdrawal("BTC", None)),
+            Some((Chain::Bitcoin, TXID.to_string()))
+        );
+        // An ether transaction hash has the 0x
+        assert_eq!(withdrawal_transaction(&withdrawal("ETH", None)), None);
+        let mut ether = withdrawal("ETH", None);
+        ether.ids[1] = format!("0x{}", TXID);
+        assert_eq!(
+            withdrawal_transaction(&ether),
+            Some((Chain::Ethereum, format!("0x{}", TXID)))
+        );
+        assert_eq!(withdrawal_transaction(&withdrawal("USDT", None)), None);
+    }
+
+    #[test]
+    fn test_check() {
+        let to_kraken = withdrawal("BTC", Some("bc1qdest"));
+        let payments = [
+            payment("bc1qdest", dec!(0.4995)),
+            payment("bc1qchange", dec!(3)),
+        ];
+        assert_eq!(check(&to_kraken, Some(&payments)), Finding::Confirmed);
+        assert_eq!(check(&to_kraken, None), Finding::NotFound);
+        // Another amount to the destination, or nothing to it
+        let other = [payment("bc1qdest", dec!(0.45))];
+        assert!(
+            matches!(check(&to_kraken, Some(&other)), Finding::Mismatch(m) if m.contains("0.45"))
+        );
+        let elsewhere = [payment("bc1qother", dec!(0.4995))];
+        assert!(matches!(
+            check(&to_kraken, Some(&elsewhere)),
+            Finding::Mismatch(_)
+        ));
+        // Without a destination, the amount is enough
+        let unknown = withdrawal("BTC", None);
+        assert_eq!(check(&unknown, Some(&elsewhere)), Finding::Confirmed);
+        assert!(matches!(
+            check(&unknown, Some(&other)),
+            Finding::Mismatch(_)
+        ));
+        // Outputs whose total overflows are a mismatch, not a panic
+        let huge = [
+            payment("bc1qdest", Decimal::MAX),
+            payment("bc1qdest", Decimal::MAX),
+        ];
+        assert!(matches!(
+            check(&to_kraken, Some(&huge)),
+            Finding::Mismatch(m) if m.contains("bc1qdest")
+        ));
+    }
+}
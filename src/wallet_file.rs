@@ -0,0 +1,440 @@
+//! Reader of the transaction exports of self-custody wallets, as the
+//! [`ChainTransfer`]s that [`crate::transfers::match_wallet`] matches to the
+//! deposits and withdrawals of an exchange, for the wallets a block explorer
+//! can't scan from a public key. The format is told by the columns of the
+//! file:
+//!
+//! - Ledger Live, the operation history exported in Settings → Accounts,
+//!   with an `IN` or `OUT` row per operation of each account. The amount of
+//!   an `OUT` includes its fee.
+//! - Trezor Suite, the transaction export of an account, with a `RECV` or
+//!   `SENT` row per output of each transaction, separated by `;`. The file
+//!   has no name for the account, so the wallet is named after the file.
+//! - Electrum, the history exported in Wallet → History → Export, with a row
+//!   per transaction of the wallet and its value, the change of the balance:
+//!   a sent one includes the fee. The timestamps are in the local time of the
+//!   computer it was exported on, as the wallet shows them. The wallet is
+//!   named after the file.
+//! - Exodus, the transaction export of Settings → Backup, with a `deposit` or
+//!   `withdrawal` row per transfer of each asset. The wallet is the portfolio,
+//!   or else named after the file; the exchanges inside the wallet are no
+//!   transfers.
+//!
+//! The other operations (fees alone, staking, transactions between the
+//! accounts of the wallet and failed ones) are no transfers.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use chrono::{DateTime, Local, NaiveDateTime, Utc};
+use rust_decimal::Decimal;
+
+use crate::csv::{Row, Table};
+use crate::error::{Error, Result};
+use crate::i18n::t;
+use crate::transfers::ChainTransfer;
+
+/// Columns of a Ledger Live export read by the reader
+const LEDGER_COLUMNS: [&str; 7] = [
+    "Operation Date",
+    "Currency Ticker",
+    "Operation Type",
+    "Operation Amount",
+    "Operation Fees",
+    "Operation Hash",
+    "Account Name",
+];
+
+/// Columns of a Trezor Suite export read by the reader
+const TREZOR_COLUMNS: [&str; 7] = [
+    "Timestamp",
+    "Type",
+    "Transaction ID",
+    "Fee",
+    "Fee unit",
+    "Amount",
+    "Amount unit",
+];
+
+/// Columns of an Electrum history export read by the reader
+const ELECTRUM_COLUMNS: [&str; 5] = [
+    "transaction_hash",
+    "confirmations",
+    "value",
+    "fee",
+    "timestamp",
+];
+
+/// Columns of an Exodus transaction export read by the reader
+const EXODUS_COLUMNS: [&str; 12] = [
+    "DATE",
+    "TYPE",
+    "FROMPORTFOLIO",
+    "TOPORTFOLIO",
+    "OUTAMOUNT",
+    "OUTCURRENCY",
+    "FEEAMOUNT",
+    "FEECURRENCY",
+    "OUTTXID",
+    "INAMOUNT",
+    "INCURRENCY",
+    "INTXID",
+];
+
+/// Reads the transfers of a wallet export, oldest first
+pub fn read(path: &Path) -> Result<Vec<ChainTransfer>> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| Error::Config(t!("failed to read {}: {}", path.display(), e)))?;
+    let name = path.display().to_string();
+    // The formats that name no account are named after the file
+    let wallet = path
+        .file_stem()
+        .map_or(name.clone(), |stem| stem.to_string_lossy().into_owned());
+    let is_format = |table: &Table, columns: &[&str]| columns.iter().all(|c| table.has_column(c));
+    let mut transfers = None;
+    for delimiter in [',', ';'] {
+        let table = Table::parse(&text, delimiter, &name)?;
+        transfers = if is_format(&table, &LEDGER_COLUMNS) {
+            Some(ledger(&table)?)
+        } else if is_format(&table, &TREZOR_COLUMNS) {
+            Some(trezor(&table, &wallet)?)
+        } else if is_format(&table, &ELECTRUM_COLUMNS) {
+            Some(electrum(&table, &wallet)?)
+        } else if is_format(&table, &EXODUS_COLUMNS) {
+            Some(exodus(&table, &wallet)?)
+        } else {
+            continue;
+        };
+        break;
+    }
+    let mut transfers = transfers
+        .ok_or_else(|| Error::Validation(t!("{} is not the export of a known wallet", name)))?;
+    transfers.sort_by(|a, b| (a.time, &a.txid).cmp(&(b.time, &b.txid)));
+    Ok(transfers)
+}
+
+fn decimal(table: &Table, row: &Row, column: &str) -> Result<Decimal> {
+    let text = table.get(row, column)?;
+    text.parse::<Decimal>()
+        .or_else(|_| Decimal::from_scientific(text))
+        .map(|value| value.abs())
+        .map_err(|e| {
+            table.error(
+                row,
+                t!(
+                    "invalid decimal \"{}\" in field \"{}\": {}",
+                    text,
+                    column,
+                    e
+                ),
+            )
+        })
+}
+
+/// The transfers of a Ledger Live export
+fn ledger(table: &Table) -> Result<Vec<ChainTransfer>> {
+    let mut transfers = Vec::new();
+    for row in &table.rows {
+        let outgoing = match table.get(row, "Operation Type")? {
+            "OUT" => true,
+            "IN" => false,
+            _ => continue,
+        };
+        if table.has_column("Status") && table.get(row, "Status")? == "Failed" {
+            continue;
+        }
+        let text = table.get(row, "Operation Date")?;
+        let time = DateTime::parse_from_rfc3339(text)
+            .map(|time| time.with_timezone(&Utc))
+            .map_err(|e| {
+                table.error(
+                    row,
+                    t!("invalid time \"{}\" in {}: {}", text, "Operation Date", e),
+                )
+            })?;
+        let amount = decimal(table, row, "Operation Amount")?;
+        let fee = if outgoing {
+            decimal(table, row, "Operation Fees")?
+        } else {
+            Decimal::ZERO
+        };
+        transfers.push(ChainTransfer {
+            asset: table.get(row, "Currency Ticker")?.to_uppercase(),
+            amount: amount - fee,
+            fee,
+            time,
+            txid: table.get(row, "Operation Hash")?.to_string(),
+            address: table.get(row, "Account Name")?.to_string(),
+            outgoing,
+            network: None,
+        });
+    }
+    Ok(transfers)
+}
+
+/// An asset and amount of a Trezor Suite export, given in satoshis when the
+/// suite shows bitcoin in them
+fn trezor_amount(table: &Table, row: &Row, amount: &str, unit: &str) -> Result<(String, Decimal)> {
+    let value = decimal(table, row, amount)?;
+    Ok(match table.get(row, unit)? {
+        unit if unit.eq_ignore_ascii_case("sat") => ("BTC".to_string(), value * Decimal::new(1, 8)),
+        unit => (unit.to_uppercase(), value),
+    })
+}
+
+/// The transfers of a Trezor Suite export of the wallet
+fn trezor(table: &Table, wallet: &str) -> Result<Vec<ChainTransfer>> {
+    let mut charged = HashSet::new();
+    let mut transfers = Vec::new();
+    for row in &table.rows {
+        let outgoing = match table.get(row, "Type")? {
+            "SENT" => true,
+            "RECV" => false,
+            _ => continue,
+        };
+        let text = table.get(row, "Timestamp")?;
+        let time = text
+            .parse()
+            .ok()
+            .and_then(|seconds| DateTime::from_timestamp(seconds, 0))
+            .ok_or_else(|| table.error(row, t!("invalid timestamp: {}", text)))?;
+        let txid = table.get(row, "Transaction ID")?;
+        let (asset, amount) = trezor_amount(table, row, "Amount", "Amount unit")?;
+        // The fee is repeated on each output of the transaction, and counts
+        // once, if it is in the asset sent
+        let mut fee = Decimal::ZERO;
+        if outgoing && charged.insert(txid) {
+            let (fee_asset, value) = trezor_amount(table, row, "Fee", "Fee unit")?;
+            if fee_asset == asset {
+                fee = value;
+            }
+        }
+        transfers.push(ChainTransfer {
+            asset,
+            amount,
+            fee,
+            time,
+            txid: txid.to_string(),
+            address: wallet.to_string(),
+            outgoing,
+            network: None,
+        });
+    }
+    Ok(transfers)
+}
+
+/// The transfers of an Electrum history export of the wallet, all of bitcoin
+fn electrum(table: &Table, wallet: &str) -> Result<Vec<ChainTransfer>> {
+    let mut transfers = Vec::new();
+    for row in &table.rows {
+        // Not yet in a block
+        if matches!(table.get(row, "confirmations")?, "" | "0") {
+            continue;
+        }
+        let value = table.get(row, "value")?;
+        let outgoing = value.starts_with('-');
+        let text = table.get(row, "timestamp")?;
+        let time = NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S")
+            .or_else(|_| NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M"))
+            .map_err(|e| {
+                table.error(
+                    row,
+                    t!("invalid time \"{}\" in {}: {}", text, "timestamp", e),
+                )
+            })?;
+        // The hour skipped when the clocks go forward is none, and the one
+        // repeated when they go back is taken the first time
+        let time = time
+            .and_local_timezone(Local)
+            .earliest()
+            .ok_or_else(|| table.error(row, t!("invalid timestamp: {}", text)))?;
+        let mut amount = decimal(table, row, "value")?;
+        let mut fee = Decimal::ZERO;
+        if outgoing && !table.get(row, "fee")?.is_empty() {
+            fee = decimal(table, row, "fee")?;
+            amount -= fee;
+        }
+        // A transaction between the addresses of the wallet only costs the fee
+        if amount <= Decimal::ZERO {
+            continue;
+        }
+        transfers.push(ChainTransfer {
+            asset: "BTC".to_string(),
+            amount,
+            fee,
+            time: time.with_timezone(&Utc),
+            txid: table.get(row, "transaction_hash")?.to_string(),
+            address: wallet.to_string(),
+            outgoing,
+            network: Some("Bitcoin".to_string()),
+        });
+    }
+    Ok(transfers)
+}
+
+/// Time of an Exodus export: ISO 8601 in the newer ones, and as JavaScript
+/// prints a date in the older, e.g. "Fri Mar 01 2024 09:00:00 GMT-0300
+/// (Brasilia Standard Time)"
+fn exodus_time(table: &Table, row: &Row) -> Result<DateTime<Utc>> {
+    let text = table.get(row, "DATE")?;
+    let javascript = text.split(" (").next().unwrap_or(text);
+    DateTime::parse_from_rfc3339(text)
+        .or_else(|_| DateTime::parse_from_str(javascript, "%a %b %d %Y %H:%M:%S GMT%z"))
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|e| table.error(row, t!("invalid time \"{}\" in {}: {}", text, "DATE", e)))
+}
+
+/// The transfers of an Exodus export
+fn exodus(table: &Table, wallet: &str) -> Result<Vec<ChainTransfer>> {
+    let mut transfers = Vec::new();
+    for row in &table.rows {
+        let (outgoing, side, portfolio) = match table.get(row, "TYPE")? {
+            "withdrawal" => (true, "OUT", "FROMPORTFOLIO"),
+            "deposit" => (false, "IN", "TOPORTFOLIO"),
+            _ => continue,
+        };
+        let asset = table.get(row, &format!("{}CURRENCY", side))?.to_uppercase();
+        let amount = decimal(table, row, &format!("{}AMOUNT", side))?;
+        let fee = if outgoing
+            && table.get(row, "FEECURRENCY")?.eq_ignore_ascii_case(&asset)
+            && !table.get(row, "FEEAMOUNT")?.is_empty()
+        {
+            decimal(table, row, "FEEAMOUNT")?
+        } else {
+            Decimal::ZERO
+        };
+        let portfolio = table.get(row, portfolio)?;
+        transfers.push(ChainTransfer {
+            asset,
+            amount,
+            fee,
+            time: exodus_time(table, row)?,
+            txid: table.get(row, &format!("{}TXID", side))?.to_string(),
+            address: if portfolio.is_empty() {
+                wallet
+            } else {
+                portfolio
+            }
+            .to_string(),
+            outgoing,
+            network: None,
+        });
+    }
+    Ok(transfers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_ledger() {
+        let text = "Operation Date,Status,Currency Ticker,Operation Type,Operation Amount,Operation Fees,Operation Hash,Account Name,Account xpub,Countervalue Ticker\n\
+            2024-03-01T12:00:00.000Z,Confirmed,BTC,IN,0.01,0.00002,aa,Bitcoin 1,xpub1,USD\n\
+            2024-03-02T12:00:00.000Z,Confirmed,BTC,OUT,0.00502,0.00002,bb,Bitcoin 1,xpub1,USD\n\
+            2024-03-03T12:00:00.000Z,Confirmed,ETH,FEES,0.001,0.001,cc,Ethereum 1,0x1,USD\n\
+            2024-03-04T12:00:00.000Z,Failed,ETH,OUT,1.001,0.001,dd,Ethereum 1,0x1,USD\n";
+        let table = Table::parse(text, ',', "ledgerlive.csv").unwrap();
+        let [received, sent] = ledger(&table).unwrap().try_into().unwrap();
+        assert_eq!(
+            (received.amount, received.fee, received.outgoing),
+            (dec!(0.01), dec!(0), false)
+        );
+        // The fee is taken out of the amount sent
+        assert_eq!(
+            (sent.amount, sent.fee, sent.outgoing),
+            (dec!(0.005), dec!(0.00002), true)
+        );
+        assert_eq!(
+            (sent.txid.as_str(), sent.address.as_str()),
+            ("bb", "Bitcoin 1")
+        );
+        assert_eq!(
+            sent.time,
+            "2024-03-02T12:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_trezor() {
+        let text = "Timestamp;Date;Time;Type;Transaction ID;Fee;Fee unit;Address;Label;Amount;Amount unit;Fiat (USD);Other\n\
+            1709294400;01/03/2024;12:00:00;RECV;aa;;;bc1qown;;1000000;sat;;\n\
+            1709380800;02/03/2024;12:00:00;SENT;bb;1410;sat;bc1qexchange;;500000;sat;;\n\
+            1709380800;02/03/2024;12:00:00;SENT;bb;1410;sat;bc1qother;;100000;sat;;\n\
+            1709467200;03/03/2024;12:00:00;SELF;cc;1000;sat;bc1qown;;0;sat;;\n";
+        let table = Table::parse(text, ';', "trezor.csv").unwrap();
+        let transfers = trezor(&table, "trezor").unwrap();
+        assert_eq!(transfers.len(), 3);
+        assert_eq!(
+            (transfers[0].asset.as_str(), transfers[0].amount),
+            ("BTC", dec!(0.01))
+        );
+        // The fee of the transaction goes with its first output only
+        assert_eq!(
+            (transfers[1].amount, transfers[1].fee, transfers[1].outgoing),
+            (dec!(0.005), dec!(0.0000141), true)
+        );
+        assert_eq!(transfers[2].fee, dec!(0));
+        assert_eq!(transfers[1].address, "trezor");
+        assert!(trezor(&Table::parse("Timestamp;Type;Transaction ID;Fee;Fee unit;Amount;Amount unit\n1;SENT;aa;x;BTC;1;BTC\n", ';', "trezor.csv").unwrap(), "trezor").is_err());
+    }
+
+    #[test]
+    fn test_electrum() {
+        let text = "transaction_hash,label,confirmations,value,fiat_value,fee,fiat_fee,timestamp\n\
+            aa,,120,0.01,,,,2024-03-01 12:00\n\
+            bb,to kraken,100,-0.00502,,0.00002,,2024-03-02 12:00:00\n\
+            cc,consolidation,90,-0.00001,,0.00001,,2024-03-03 12:00\n\
+            dd,,0,-0.1,,0.0001,,2024-03-04 12:00\n";
+        let table = Table::parse(text, ',', "electrum.csv").unwrap();
+        let [received, sent] = electrum(&table, "electrum").unwrap().try_into().unwrap();
+        assert_eq!(
+            (received.asset.as_str(), received.amount, received.outgoing),
+            ("BTC", dec!(0.01), false)
+        );
+        // The fee is taken out of the change of the balance
+        assert_eq!(
+            (sent.amount, sent.fee, sent.outgoing),
+            (dec!(0.005), dec!(0.00002), true)
+        );
+        let local = NaiveDateTime::parse_from_str("2024-03-02 12:00", "%Y-%m-%d %H:%M")
+            .unwrap()
+            .and_local_timezone(Local)
+            .unwrap();
+        assert_eq!(sent.time, local.with_timezone(&Utc));
+    }
+
+    #[test]
+    fn test_exodus() {
+        let text = "DATE,TYPE,FROMPORTFOLIO,TOPORTFOLIO,OUTAMOUNT,OUTCURRENCY,FEEAMOUNT,FEECURRENCY,TOADDRESS,OUTTXID,OUTTXURL,INAMOUNT,INCURRENCY,INTXID,INTXURL,ORDERID,PERSONALNOTE\n\
+            2024-03-01T12:00:00.000Z,deposit,,exodus_0,,,,,,,,0.5,ETH,0xaa,,,\n\
+            Sat Mar 02 2024 09:00:00 GMT-0300 (Brasilia Standard Time),withdrawal,exodus_0,,-0.2,ETH,-0.0005,ETH,0xkraken,0xbb,,,,,,,\n\
+            2024-03-03T12:00:00.000Z,exchange,exodus_0,exodus_0,-0.1,ETH,-0.001,ETH,,0xcc,,0.005,BTC,dd,,o1,\n\
+            2024-03-04T12:00:00.000Z,withdrawal,,,-100,USDT,-0.002,ETH,0xkraken,0xee,,,,,,,\n";
+        let table = Table::parse(text, ',', "exodus.csv").unwrap();
+        let [deposit, withdrawal, token] = exodus(&table, "exodus").unwrap().try_into().unwrap();
+        assert_eq!(
+            (
+                deposit.amount,
+                deposit.txid.as_str(),
+                deposit.address.as_str()
+            ),
+            (dec!(0.5), "0xaa", "exodus_0")
+        );
+        assert_eq!(
+            (withdrawal.amount, withdrawal.fee, withdrawal.outgoing),
+            (dec!(0.2), dec!(0.0005), true)
+        );
+        assert_eq!(
+            withdrawal.time,
+            "2024-03-02T12:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+        // The gas of a token is paid in ether, and the portfolio is unnamed
+        assert_eq!(
+            (token.asset.as_str(), token.fee, token.address.as_str()),
+            ("USDT", dec!(0), "exodus")
+        );
+    }
+}
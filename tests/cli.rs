@@ -0,0 +1,167 @@
+//! The binary run as the user runs it, against the recorded Kraken activity,
+//! with its data in a temporary directory
+
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// A path in the temporary directory, unique to this test process
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("kraken2rfb-cli-{}-{}", std::process::id(), name))
+}
+
+/// Runs the binary with `args`, its data and configuration in `dir` and the
+/// APIs at the mock server, failing the test if it fails
+fn run(dir: &Path, args: &[&str]) -> Output {
+    let url = common::mock_endpoints();
+    let output = Command::new(env!("CARGO_BIN_EXE_kraken2rfb"))
+        .args(args)
+        .current_dir(dir)
+        .env("HOME", dir)
+        .env("KRAKEN2RFB_DATA_DIR", dir.join("data"))
+        .env("KRAKEN2RFB_CONFIG_DIR", dir.join("config"))
+        .env("KRAKEN2RFB_CACHE_DIR", dir.join("cache"))
+        .env("KRAKEN2RFB_HTTP_CACHE", "no")
+        .env("KRAKEN2RFB_KRAKEN_URL", url)
+        .env("KRAKEN2RFB_BCB_URL", url)
+        .env("KRAKEN2RFB_COINGECKO_URL", url)
+        .env("KRAKEN2RFB_LANG", "en")
+        .env_remove("KRAKEN2RFB_PROFILE")
+        .env_remove("KRAKEN2RFB_STORE")
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{:?} failed:\n{}{}",
+        args,
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+#[test]
+fn test_report_matches_stored_withdrawal() {
+    let dir = temp_path("home");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(
+        dir.join("keys.json"),
+        r#"{"key": "test-key", "secret": "c2VjcmV0"}"#,
+    )
+    .unwrap();
+    // The Kraken deposit of 0.5 BTC on March 1 was withdrawn from Binance,
+    // with the network fee, the day before
+    fs::write(
+        dir.join("binance.json"),
+        r#"{
+          "version": 1,
+          "transactions": [
+            {
+              "type": "withdrawal_from_exchange",
+              "base": {
+                "operation_date": "2024-02-29",
+                "operation_fees": "150.00",
+                "crypto_symbol": "BTC",
+                "crypto_amount": "0.5005"
+              },
+              "origin_exchange": {
+                "name": "Binance",
+                "url": "https://www.binance.com",
+                "country": "KY"
+              }
+            }
+          ]
+        }"#,
+    )
+    .unwrap();
+
+    run(
+        &dir,
+        &[
+            "import",
+            "--format",
+            "json",
+            "binance.json",
+            "--source",
+            "Binance",
+        ],
+    );
+    let output = run(
+        &dir,
+        &[
+            "--period",
+            "2024-03",
+            "--keyfile",
+            "keys.json",
+            "--non-interactive",
+            "--no-archive",
+            "report.txt",
+        ],
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("came from Binance"), "{}", stdout);
+
+    let report = fs::read_to_string(dir.join("report.txt")).unwrap();
+    let deposits: Vec<&str> = report
+        .lines()
+        .filter(|line| line.starts_with("0410"))
+        .collect();
+    // The other deposit, late on March 31 in Brasília, matches no withdrawal
+    assert_eq!(
+        deposits,
+        [
+            "0410|01032024|IV|0,00|BTC|0,5000000000||Binance",
+            "0410|31032024|IV|0,00|BTC|0,2500000000||",
+        ]
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_streamed_report() {
+    let dir = temp_path("stream");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(
+        dir.join("koinly.csv"),
+        "Date (UTC),Type,Tag,Sending Wallet,Sent Amount,Sent Currency,Sent Cost Basis,Receiving Wallet,Received Amount,Received Currency,Received Cost Basis,Fee Amount,Fee Currency,TxHash\n\
+         2024-03-01 12:00:00 UTC,crypto_deposit,,,,,,Binance,0.5,BTC,,,,0xa1\n\
+         2024-03-01 15:00:00 UTC,crypto_withdrawal,,Binance,0.2,BTC,,,,,,0.0001,BTC,0xa2\n",
+    )
+    .unwrap();
+    let report = |file: &str, stream: bool| {
+        let mut args = vec![
+            "--exchange",
+            "koinly",
+            "--input",
+            "koinly.csv",
+            "--wallet",
+            "Binance",
+            "--period",
+            "2024-03",
+            "--non-interactive",
+            "--no-store",
+            "--no-archive",
+            file,
+        ];
+        if stream {
+            args.insert(0, "--stream");
+        }
+        let output = run(&dir, &args);
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    report("report.txt", false);
+    let stdout = report("streamed.txt", true);
+    assert!(stdout.contains("Records written:"), "{}", stdout);
+    let streamed = fs::read_to_string(dir.join("streamed.txt")).unwrap();
+    assert_eq!(streamed.lines().count(), 2);
+    assert_eq!(
+        streamed,
+        fs::read_to_string(dir.join("report.txt")).unwrap()
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,128 @@
+//! A local HTTP server replaying the responses recorded in `tests/fixtures`,
+//! in place of the Kraken, BCB and CoinGecko APIs.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::thread;
+
+use chrono::NaiveDate;
+use kraken2rfb::endpoints::{self, Endpoints};
+use kraken2rfb::http_cache;
+use serde_json::Value;
+use tiny_http::{Response, Server};
+
+fn fixture_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+/// Starts the server, once per test binary, and points every endpoint to it,
+/// with the HTTP cache off so the responses aren't kept in the user's cache.
+/// Returns the URL of the server, for the endpoints of a child process.
+pub fn mock_endpoints() -> &'static str {
+    static URL: OnceLock<String> = OnceLock::new();
+    URL.get_or_init(|| {
+        let server = Server::http("127.0.0.1:0").expect("failed to start the mock server");
+        let url = format!("http://{}", server.server_addr());
+        thread::spawn(move || {
+            for mut request in server.incoming_requests() {
+                let mut form = String::new();
+                let _ = request.as_reader().read_to_string(&mut form);
+                let (status, body) = respond(request.url(), &form);
+                let _ = request.respond(Response::from_string(body).with_status_code(status));
+            }
+        });
+        endpoints::set(Endpoints {
+            kraken: url.clone(),
+            bcb: url.clone(),
+            coingecko: url.clone(),
+            binance: url.clone(),
+            bitstamp: url.clone(),
+            bybit: url.clone(),
+            okx: url.clone(),
+            kucoin: url.clone(),
+            gemini: url.clone(),
+            gate: url.clone(),
+            esplora: url.clone(),
+            etherscan: url.clone(),
+        })
+        .expect("endpoints were used before the mock server started");
+        http_cache::set(None).expect("the HTTP cache was used before the mock server started");
+        url
+    })
+}
+
+/// The response to a request for `url`, with `form` the body of a private
+/// Kraken request
+fn respond(url: &str, form: &str) -> (u16, String) {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    fn find<'a>(params: &'a str, name: &str) -> &'a str {
+        params
+            .split('&')
+            .find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='))
+            .unwrap_or_default()
+    }
+    let param = |name: &str| find(query, name);
+    let not_found = || (404, r#"{"error": "not found"}"#.to_string());
+    let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+
+    match segments.as_slice() {
+        // The ledger entries of a type, e.g. Ledgers-credit.json, are
+        // recorded apart, and the other types have none
+        ["0", "private" | "public", endpoint] => {
+            read(&format!("kraken/{}-{}.json", endpoint, find(form, "type")))
+                .or_else(|| read(&format!("kraken/{}.json", endpoint)))
+                .map_or_else(not_found, |body| (200, page(&body, find(form, "ofs"))))
+        }
+        ["dados", "serie", series, "dados"] => {
+            let series = series.trim_start_matches("bcdata.sgs.");
+            let Some(body) = read(&format!("bcb/{}.json", series)) else {
+                return not_found();
+            };
+            // The fixtures have every recorded day, so only the requested ones are returned
+            let date = |text: &str| NaiveDate::parse_from_str(text, "%d/%m/%Y").unwrap();
+            let (first, last) = (date(param("dataInicial")), date(param("dataFinal")));
+            let values: Vec<Value> = serde_json::from_str(&body).unwrap();
+            let selected: Vec<&Value> = values
+                .iter()
+                .filter(|value| (first..=last).contains(&date(value["data"].as_str().unwrap())))
+                .collect();
+            (200, serde_json::to_string(&selected).unwrap())
+        }
+        ["api", "v3", "coins", id, "history"] => {
+            read(&format!("coingecko/{}/{}.json", id, param("date")))
+                .map_or_else(not_found, |body| (200, body))
+        }
+        _ => not_found(),
+    }
+}
+
+/// Entries of the trades and ledger responses in each page, fewer than the 50
+/// of Kraken so the recorded ones take more than one page
+const PAGE: usize = 1;
+
+/// The page of a Kraken trades or ledger response starting at the entry
+/// `ofs`, the latest first as Kraken sends them; the other responses whole
+fn page(body: &str, ofs: &str) -> String {
+    let mut json: Value = serde_json::from_str(body).unwrap();
+    let ofs: usize = ofs.parse().unwrap_or(0);
+    for list in ["trades", "ledger"] {
+        let entries = json
+            .get_mut("result")
+            .and_then(|result| result.get_mut(list))
+            .and_then(Value::as_object_mut);
+        if let Some(entries) = entries {
+            let mut sorted: Vec<(String, Value)> = std::mem::take(entries).into_iter().collect();
+            sorted.sort_by(|(_, a), (_, b)| {
+                let time = |entry: &Value| entry["time"].as_f64().unwrap_or_default();
+                time(b).total_cmp(&time(a))
+            });
+            entries.extend(sorted.into_iter().skip(ofs).take(PAGE));
+        }
+    }
+    json.to_string()
+}
+
+fn read(name: &str) -> Option<String> {
+    fs::read_to_string(fixture_dir().join(name)).ok()
+}
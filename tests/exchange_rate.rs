@@ -0,0 +1,61 @@
+//! Rates from the recorded BCB and CoinGecko responses
+
+mod common;
+
+use chrono::NaiveDate;
+use kraken2rfb::error::Error;
+use kraken2rfb::exchange_rate::{get_exchange_rate, get_exchange_rate_impl};
+use rust_decimal_macros::dec;
+
+fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(year, month, day).unwrap()
+}
+
+#[test]
+fn test_all_currencies() {
+    common::mock_endpoints();
+    for code in [
+        "USD", "EUR", "JPY", "GBP", "CHF", "DKK", "NOK", "SEK", "AUD", "CAD",
+    ] {
+        let (rate_date, rate) = get_exchange_rate_impl(date(2024, 3, 1), code)
+            .unwrap_or_else(|e| panic!("{}: {}", code, e));
+        assert_eq!(rate_date, date(2024, 3, 1), "{}", code);
+        assert!(rate > dec!(0), "{}", code);
+    }
+}
+
+#[test]
+fn test_non_bank_day() {
+    common::mock_endpoints();
+    // March 2, 2024 is a Saturday, so the rate is from the previous business day
+    let result = get_exchange_rate_impl(date(2024, 3, 2), "USD").unwrap();
+    assert_eq!(result, (date(2024, 3, 1), dec!(4.9693)));
+}
+
+#[test]
+fn test_crypto_rate_historical() {
+    common::mock_endpoints();
+    let (rate_date, rate) = get_exchange_rate_impl(date(2024, 3, 1), "BTC").unwrap();
+    assert_eq!(rate_date, date(2024, 3, 1));
+    assert_eq!(rate.round_dp(2), dec!(304123.45));
+}
+
+#[test]
+fn test_invalid_crypto_id() {
+    common::mock_endpoints();
+    let result = get_exchange_rate_impl(date(2024, 3, 1), "invalid-crypto-id-123456789");
+    let Err(Error::Rate { asset, reason, .. }) = result else {
+        panic!("expected a rate error, got {:?}", result);
+    };
+    assert_eq!(asset, "invalid-crypto-id-123456789");
+    assert!(reason.contains("Cryptocurrency ID not found"), "{}", reason);
+}
+
+#[test]
+fn test_unified_api() {
+    common::mock_endpoints();
+    let (_, usd) = get_exchange_rate(date(2024, 3, 1), "USD").unwrap();
+    assert_eq!(usd, dec!(4.9693));
+    let (_, eth) = get_exchange_rate(date(2024, 3, 2), "ETH").unwrap();
+    assert_eq!(eth.round_dp(2), dec!(16987.21));
+}
@@ -0,0 +1,99 @@
+//! The whole run, from the recorded Kraken activity to the report file
+
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+
+use chrono::NaiveDate;
+use kraken2rfb::checkpoint::Checkpoint;
+use kraken2rfb::kraken::{self, KrakenSource};
+use kraken2rfb::period::Period;
+use kraken2rfb::prompt::Prompter;
+use kraken2rfb::reconcile::{implied_balances, reconcile};
+use kraken2rfb::report::layout::Layout;
+use kraken2rfb::report::{ProcessOptions, generate_report, process_activity};
+use kraken2rfb::source::ExchangeSource;
+use rust_decimal_macros::dec;
+
+/// A path in the temporary directory, unique to this test process
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("kraken2rfb-{}-{}", std::process::id(), name))
+}
+
+#[test]
+fn test_report() {
+    common::mock_endpoints();
+    let keyfile = temp_path("keys.json");
+    fs::write(&keyfile, r#"{"key": "test-key", "secret": "c2VjcmV0"}"#).unwrap();
+    let report_file = temp_path("report.txt");
+    let period = Period::month(2024, 3).unwrap();
+
+    let source = KrakenSource::new(Some(keyfile.clone()));
+    let activity = source.fetch(period.first_day(), period.last_day()).unwrap();
+    // The deposit of April is outside the period
+    assert_eq!(activity.deposits.len(), 2);
+    // The trades come in a page each, and none is lost
+    assert_eq!(activity.trades.len(), 2);
+
+    let mut checkpoint = Checkpoint::restart(Checkpoint::path_for(&report_file), period).unwrap();
+    let processed = process_activity(
+        activity,
+        &source.exchange(),
+        &ProcessOptions::default(),
+        &mut Prompter::new(true),
+        &mut checkpoint,
+    )
+    .unwrap();
+    // The referral reward has no record, but is valued for the gains
+    let [income] = processed.income.as_slice() else {
+        panic!("expected 1 income entry, got {:?}", processed.income);
+    };
+    assert_eq!((income.asset.as_str(), income.value), ("BTC", dec!(30.41)));
+    generate_report(
+        &processed.transactions,
+        report_file.to_str().unwrap(),
+        Layout::default(),
+    )
+    .unwrap();
+    checkpoint.finish().unwrap();
+
+    let report = fs::read_to_string(&report_file).unwrap();
+    let expected = fs::read_to_string(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/report.txt"
+    ))
+    .unwrap();
+    assert_eq!(report, expected);
+
+    fs::remove_file(&keyfile).unwrap();
+    fs::remove_file(&report_file).unwrap();
+}
+
+#[test]
+fn test_reconcile() {
+    common::mock_endpoints();
+    let keyfile = temp_path("reconcile-keys.json");
+    fs::write(&keyfile, r#"{"key": "test-key", "secret": "c2VjcmV0"}"#).unwrap();
+
+    let source = KrakenSource::new(Some(keyfile.clone()));
+    let last_day = NaiveDate::from_ymd_opt(2024, 4, 30).unwrap();
+    let history = source.fetch(kraken::FIRST_DAY, last_day).unwrap();
+    let discrepancies = reconcile(&implied_balances(&history), &source.balances().unwrap());
+    // The recorded history lacks the deposits that paid for the trades and the
+    // withdrawal; only BTC adds up
+    let assets: Vec<_> = discrepancies
+        .iter()
+        .map(|d| (d.asset.as_str(), d.implied, d.actual))
+        .collect();
+    assert_eq!(
+        assets,
+        [
+            ("DOT.S", dec!(0), dec!(3)),
+            ("ETH", dec!(-3.01), dec!(0)),
+            ("EUR", dec!(-4008), dec!(0)),
+        ]
+    );
+
+    fs::remove_file(&keyfile).unwrap();
+}
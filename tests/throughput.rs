@@ -0,0 +1,44 @@
+//! The throughput target of `benches/throughput.rs`: encoding 100k records in
+//! under a second. Timing depends on the machine and on the build, so it is
+//! ignored by default; run it with
+//! `cargo test --release --test throughput -- --ignored`.
+
+#[path = "../benches/synthetic/mod.rs"]
+mod synthetic;
+
+use std::time::{Duration, Instant};
+
+use kraken2rfb::report::layout::Layout;
+use kraken2rfb::report::{ProcessOptions, convert_activity, write_report};
+
+#[test]
+#[ignore = "timing; run in release with --ignored"]
+fn test_encoding_target() {
+    let transactions = convert_activity(
+        synthetic::activity(),
+        &synthetic::exchange(),
+        &ProcessOptions::default(),
+        &mut synthetic::rates(),
+    )
+    .unwrap()
+    .transactions;
+    assert_eq!(transactions.len(), synthetic::TRADES);
+
+    // The fastest of a few runs, so a busy moment of the machine doesn't fail it
+    let mut report = Vec::with_capacity(transactions.len() * 100);
+    let fastest = (0..3)
+        .map(|_| {
+            report.clear();
+            let start = Instant::now();
+            write_report(&transactions, Layout::default(), &mut report).unwrap();
+            start.elapsed()
+        })
+        .min()
+        .unwrap();
+    assert!(
+        fastest < Duration::from_secs(1),
+        "encoding {} records took {:?}, more than the target of 1s",
+        synthetic::TRADES,
+        fastest
+    );
+}